@@ -4,14 +4,19 @@ extern crate log;
 extern crate structopt;
 
 use record_query as rq;
+use std::cmp;
+use std::collections;
 use std::env;
 use std::fs;
 use std::io;
 use std::io::prelude::*;
 use std::path;
+use std::process;
+use std::rc;
 use std::str;
+use std::time;
 
-#[derive(Debug, StructOpt)]
+#[derive(Clone, Debug, StructOpt)]
 #[structopt(
     name = "rq",
     version = record_query::VERSION,
@@ -42,12 +47,34 @@ pub struct Options {
     /// Input is an Apache Avro container file.
     #[structopt(short = "a", long = "input-avro")]
     pub flag_input_avro: bool,
+    /// Input is a series of bencode values, like a .torrent file.  Byte strings that aren't UTF-8
+    /// become bytes.
+    #[structopt(long = "input-bencode")]
+    pub flag_input_bencode: bool,
+    /// Input is a series of BSON documents, like a file written by mongodump.
+    #[structopt(long = "input-bson")]
+    pub flag_input_bson: bool,
     /// Input is a series of CBOR values.
     #[structopt(short = "c", long = "input-cbor")]
     pub flag_input_cbor: bool,
+    /// Input is a series of Amazon Ion values, in either the text or the binary encoding.
+    #[structopt(long = "input-ion")]
+    pub flag_input_ion: bool,
+    /// Input is a series of Universal Binary JSON (UBJSON) values.
+    #[structopt(long = "input-ubjson")]
+    pub flag_input_ubjson: bool,
+    /// Input is a series of EDN values, the data notation of Clojure.  Keywords, symbols, sets,
+    /// lists and tagged literals become maps like {"$keyword": "name"}, except that keywords
+    /// that are map keys become strings like ":name".
+    #[structopt(long = "input-edn")]
+    pub flag_input_edn: bool,
     /// Input is white-space separated JSON values (default).
     #[structopt(short = "j", long = "input-json")]
     pub flag_input_json: bool,
+    /// Input is JSON with comments (JSONC), like VS Code settings or tsconfig.json, which may
+    /// also have trailing commas.
+    #[structopt(long = "input-jsonc")]
+    pub flag_input_jsonc: bool,
     /// Input is CSV.
     #[structopt(short = "v", long = "input-csv")]
     pub flag_input_csv: bool,
@@ -56,6 +83,64 @@ pub struct Options {
     pub flag_input_message_pack: bool,
     #[structopt(short = "p", long = "input-protobuf")]
     pub flag_input_protobuf: Option<String>,
+    /// Render protobuf input according to the proto3 JSON mapping: lowerCamelCase field names,
+    /// 64-bit integers and bytes as strings, and maps as objects, as `protoc` and gRPC gateways
+    /// do.
+    #[structopt(long = "proto3-json", requires = "flag-input-protobuf")]
+    pub flag_proto3_json: bool,
+    /// Input is a protobuf message without a known schema, which is decoded into a map from
+    /// field numbers to values with guessed types, like `protoc --decode_raw` does.
+    #[structopt(long = "input-protobuf-raw", conflicts_with = "flag-input-protobuf")]
+    pub flag_input_protobuf_raw: bool,
+    /// Input is a protobuf message of the specified type in the text format, like the files
+    /// read by `protoc --encode`.  The schema is looked up like for --input-protobuf.
+    #[structopt(
+        long = "input-textproto",
+        value_name = "message",
+        conflicts_with = "flag-input-protobuf"
+    )]
+    pub flag_input_textproto: Option<String>,
+    /// Input is a FlatBuffer described by the specified binary schema, as made by `flatc
+    /// --binary --schema`.  Its root type is decoded, unless --flatbuffers-root names another
+    /// table.
+    #[structopt(
+        long = "input-flatbuffers",
+        value_name = "schema.bfbs",
+        parse(from_os_str)
+    )]
+    pub flag_input_flatbuffers: Option<path::PathBuf>,
+    /// The table of FlatBuffers input, like `example.Monster`.
+    #[structopt(
+        long = "flatbuffers-root",
+        value_name = "table",
+        requires = "flag-input-flatbuffers"
+    )]
+    pub flag_flatbuffers_root: Option<String>,
+    /// FlatBuffers input is a stream of buffers that are each prefixed with their size, as
+    /// written by `FinishSizePrefixed`.
+    #[structopt(
+        long = "flatbuffers-size-prefixed",
+        requires = "flag-input-flatbuffers"
+    )]
+    pub flag_flatbuffers_size_prefixed: bool,
+    /// Input is a series of Thrift structs of the specified type, described by --thrift-idl.
+    #[structopt(
+        long = "input-thrift",
+        value_name = "struct",
+        requires = "flag-thrift-idl"
+    )]
+    pub flag_input_thrift: Option<String>,
+    /// The Thrift IDL file that describes the structs of --input-thrift and --output-thrift.
+    /// Files that it includes are looked up relative to it.
+    #[structopt(long = "thrift-idl", value_name = "file.thrift", parse(from_os_str))]
+    pub flag_thrift_idl: Option<path::PathBuf>,
+    /// The protocol of Thrift structs: 'binary' or 'compact'.
+    #[structopt(
+        long = "thrift-protocol",
+        value_name = "protocol",
+        default_value = "binary"
+    )]
+    pub flag_thrift_protocol: rq::value::thrift::Protocol,
     /// Input is plain text.
     #[structopt(short = "r", long = "input-raw")]
     pub flag_input_raw: bool,
@@ -68,11 +153,420 @@ pub struct Options {
     /// Input is formatted as SMILE
     #[structopt(short = "s", long = "input-smile")]
     pub flag_input_smile: bool,
+    /// Input is a series of XML documents.
+    #[structopt(short = "x", long = "input-xml")]
+    pub flag_input_xml: bool,
+    /// Input is Java properties in XML, as stored by `Properties.storeToXML`.  Each document
+    /// becomes a map from keys to values.
+    #[structopt(long = "input-properties-xml")]
+    pub flag_input_properties_xml: bool,
+    /// Input is a log written by the XML layout of log4j or logback.  Each event becomes a flat
+    /// record with its time, level, logger, message, location and properties.
+    #[structopt(long = "input-log4j-xml")]
+    pub flag_input_log4j_xml: bool,
+    /// Input is an Apache Parquet file.
+    #[structopt(long = "input-parquet")]
+    pub flag_input_parquet: bool,
+    /// Input is an Excel workbook (.xlsx).  Each row becomes a map keyed by the first row of its
+    /// sheet.
+    #[structopt(long = "input-xlsx")]
+    pub flag_input_xlsx: bool,
+    /// The sheet of an Excel workbook to read, instead of all of them.
+    #[structopt(long = "xlsx-sheet", value_name = "name")]
+    pub flag_xlsx_sheet: Option<String>,
+    /// Input is a SQLite database.  Each row of its tables becomes a map keyed by the column
+    /// names.
+    #[structopt(long = "input-sqlite")]
+    pub flag_input_sqlite: bool,
+    /// The SQL query to run on a SQLite database, or the table to read, instead of reading all
+    /// tables.
+    #[structopt(long = "sqlite-query", value_name = "sql|table")]
+    pub flag_sqlite_query: Option<String>,
+    /// Input is the output of 'git log --format=raw', optionally with '--numstat'.  Each commit
+    /// becomes a map with its hashes, author, committer, message and changed files.
+    #[structopt(long = "input-git-log")]
+    pub flag_input_git_log: bool,
+    /// Input is the lockfile of a package manager: Cargo.lock, package-lock.json or yarn.lock.
+    /// Each locked package becomes a map with its name, version, source and checksum.
+    #[structopt(long = "input-lockfile")]
+    pub flag_input_lockfile: bool,
+    /// Input is a tar archive of a container image, either an OCI image layout or written by
+    /// 'docker save'.  Each image becomes a manifest record, a config record and a record for
+    /// each of its layers.
+    #[structopt(long = "input-oci-image")]
+    pub flag_input_oci_image: bool,
+    /// Input is a DNS zone file in the format of BIND.  Each resource record becomes a map with
+    /// its name, TTL, class, type and data.
+    #[structopt(long = "input-zone-file")]
+    pub flag_input_zone_file: bool,
+    /// Input is an OpenSSH known_hosts file.  Each key becomes a map with its hosts, type,
+    /// fingerprint, size and comment.
+    #[structopt(long = "input-known-hosts")]
+    pub flag_input_known_hosts: bool,
+    /// Input is an OpenSSH authorized_keys file.  Each key becomes a map with its options, type,
+    /// fingerprint, size and comment.
+    #[structopt(long = "input-authorized-keys")]
+    pub flag_input_authorized_keys: bool,
+    /// Input is HCL, like Terraform configuration and .tfvars files.  Each file becomes a map,
+    /// with blocks nested like in the HCL JSON syntax.
+    #[structopt(long = "input-hcl")]
+    pub flag_input_hcl: bool,
+    /// Input is X.509 certificates, in a PEM bundle or DER.  Each certificate becomes a map with
+    /// its subject, issuer, alternative names, validity dates, key and fingerprints.
+    #[structopt(long = "input-x509")]
+    pub flag_input_x509: bool,
+    /// Input is ASN.1 in DER or BER, like certificates, CRLs and keys, either raw or in PEM
+    /// blocks.  Each value becomes a map with its class, tag, type and decoded value.
+    #[structopt(long = "input-asn1")]
+    pub flag_input_asn1: bool,
+    /// Input is a RIFF file, like a WAV, AVI or WebP file.  Each top-level chunk becomes a map
+    /// with its id, offset, size and the chunks in it, and the headers of well-known chunks are
+    /// parsed.
+    #[structopt(long = "input-riff")]
+    pub flag_input_riff: bool,
+    /// Input is a Standard MIDI File.  Each chunk becomes a map with its id, offset and size,
+    /// and the header or a summary of the track.
+    #[structopt(long = "input-midi")]
+    pub flag_input_midi: bool,
+    /// Input is a JPEG, PNG, TIFF, WebP or HEIF image, which becomes a single map with its
+    /// format, size, the time it was taken, its GPS position, and its EXIF, XMP and text metadata.
+    #[structopt(long = "input-image")]
+    pub flag_input_image: bool,
+    /// Input is an MP3, FLAC, Ogg Vorbis or Opus file, which becomes a single map with its
+    /// format, its tags under common names like title and artist, and its embedded pictures.
+    #[structopt(long = "input-audio-tags")]
+    pub flag_input_audio_tags: bool,
+    /// Input is a PDF document, which becomes a single map with its version, page count, document
+    /// info like title and author, and its outline of bookmarks, but not its text.
+    #[structopt(long = "input-pdf")]
+    pub flag_input_pdf: bool,
+    /// Input is a Windows registry file exported by regedit (.reg) or a raw hive file, and each
+    /// key becomes a record with its path, values and value types.
+    #[structopt(long = "input-registry")]
+    pub flag_input_registry: bool,
+    /// Input is a Windows event log (.evtx), and each event becomes a record with its system
+    /// fields and its event data.
+    #[structopt(long = "input-evtx")]
+    pub flag_input_evtx: bool,
+    /// Input is an environment file (.env) with KEY=VALUE lines, which becomes a single map.
+    #[structopt(long = "input-dotenv")]
+    pub flag_input_dotenv: bool,
+    /// Input is a logfmt log with key=value pairs on each line, like the logs of Heroku.  Each
+    /// line becomes a flat map, where unquoted numbers and booleans are typed.
+    #[structopt(long = "input-logfmt")]
+    pub flag_input_logfmt: bool,
+    /// Input is a KDL document.  Each top-level node becomes a map with its name, args, props
+    /// and children, which are nodes in the same way.
+    #[structopt(long = "input-kdl")]
+    pub flag_input_kdl: bool,
+    /// Input is an Apache Arrow IPC file (Feather file) or stream.
+    #[structopt(long = "input-arrow")]
+    pub flag_input_arrow: bool,
+    /// The character encoding of text input, like 'utf-16le' or 'latin1'.  By default, text
+    /// input is UTF-8, unless it starts with a UTF-8 or UTF-16 byte order mark.
+    #[structopt(
+        long = "input-encoding",
+        value_name = "encoding",
+        parse(try_from_str = parse_encoding)
+    )]
+    pub flag_input_encoding: Option<&'static encoding_rs::Encoding>,
+    /// Input has the specified MIME type, like 'application/x-ndjson' or 'text/csv'.  This is
+    /// an alternative to the input format flags for wrappers that already know the content type.
+    #[structopt(
+        long = "input-mime",
+        value_name = "type",
+        parse(try_from_str = InputFormat::from_mime)
+    )]
+    pub flag_input_mime: Option<InputFormat>,
+    /// Input is a single record made from the environment variables, optionally only the ones
+    /// whose names start with a prefix (like '--input-env=APP_').  Stdin is not read.
+    #[structopt(long = "input-env", value_name = "prefix")]
+    pub flag_input_env: Option<Option<String>>,
+    /// Input is the history of the git repository in the specified directory (or the current
+    /// one), as if piped from 'git log --format=raw --numstat'.  Stdin is not read.
+    #[structopt(long = "git-log", value_name = "repository")]
+    pub flag_git_log: Option<Option<path::PathBuf>>,
+    /// Input is a manifest that lists files to read one after the other, one per line, each
+    /// with its own format named like the input flags without their '--input-' prefix, like
+    /// 'users.csv:csv' or 'dump.bin:message-pack'.  The records of all files are merged into
+    /// one stream.
+    #[structopt(long = "input-manifest")]
+    pub flag_input_manifest: bool,
+    /// Memory-map the input instead of reading it, when stdin is a regular file, and likewise the
+    /// files of --input-manifest and --merge and the output read by --skip-existing.  This
+    /// avoids copying large local files through read buffers.  The files must not be truncated
+    /// while rq is running.
+    #[structopt(long = "mmap")]
+    pub flag_mmap: bool,
+    /// Transpose each record: a sequence of maps becomes a map of sequences and vice versa, and
+    /// a sequence of sequences is transposed like a matrix.
+    #[structopt(long = "transpose")]
+    pub flag_transpose: bool,
+    /// Accept input that deviates from the strict syntax of its format: comments, trailing
+    /// commas, single-quoted strings and unquoted keys in JSON, and tabs in YAML indentation.
+    /// The fixes that were needed are reported.
+    #[structopt(long = "lenient", conflicts_with = "flag-strict")]
+    pub flag_lenient: bool,
+    /// Only accept input with the strict syntax of its format (the default).
+    #[structopt(long = "strict")]
+    pub flag_strict: bool,
+    /// Recognize JSON-RPC and MessagePack-RPC messages, and turn them into records with the
+    /// fields protocol, kind, id, method, and params or result and error.  JSON-RPC batches are
+    /// split into their messages, and responses get the method of their request.
+    #[structopt(long = "rpc")]
+    pub flag_rpc: bool,
+    /// Decode a field that holds a compressed document, given as the path and the encodings in
+    /// the order they were applied, like '.payload:gzip+base64'.  Encodings: base64, gzip,
+    /// snappy and lz4.  Decoded JSON replaces the field as a structured value.  Can be repeated.
+    #[structopt(long = "decompress-field", value_name = "field", number_of_values = 1)]
+    pub flag_decompress_field: Vec<rq::transform::decompress::Field>,
+    /// Unwrap AWS CloudTrail log files and CloudWatch Logs subscription messages (also as
+    /// received by Lambda), outputting their events instead.  CloudWatch Logs events get the log
+    /// group, stream and owner of their message, and messages that are JSON are parsed.
+    #[structopt(long = "aws-logs")]
+    pub flag_aws_logs: bool,
+    /// Split Kubernetes manifests into one record per resource, with its kind, name, namespace
+    /// and the resource itself.  List resources are split into their items.
+    #[structopt(long = "k8s-split")]
+    pub flag_k8s_split: bool,
+    /// Only keep the Kubernetes resources of this kind, like 'Deployment' or
+    /// 'apps/v1/Deployment'.  Can be repeated.
+    #[structopt(long = "k8s-kind", value_name = "kind", number_of_values = 1)]
+    pub flag_k8s_kind: Vec<rq::transform::kubernetes::Kind>,
+    /// Turn records made by --k8s-split back into resources, to write them as a manifest.
+    #[structopt(long = "k8s-join")]
+    pub flag_k8s_join: bool,
+    /// Output a record for each resource instance in Terraform state, as read from a state file
+    /// or `terraform show -json`, with its address, module, mode, type, name, index, provider and
+    /// attributes.  Optionally only resources matching filters, like 'type=aws_instance' or
+    /// 'module=module.vpc,type=aws_*'.
+    #[structopt(long = "tf-resources", value_name = "filters")]
+    pub flag_tf_resources: Option<Option<rq::transform::terraform::Filters>>,
+    /// Output a record for each entry of HTTP Archives (HAR files), with its page, start time,
+    /// method, URL, status, response type and size, the timings of each phase, and the request
+    /// and response.
+    #[structopt(long = "har")]
+    pub flag_har: bool,
+    /// Decode the bodies of the requests and responses of --har entries, from base64 if needed,
+    /// and parse the ones that are JSON.
+    #[structopt(long = "har-bodies", requires = "flag-har")]
+    pub flag_har_bodies: bool,
+    /// Merge the records of a file into each record, like 'values-prod.yaml:yaml', with the
+    /// format named like in an input manifest.  Maps are merged recursively and null values
+    /// remove entries.  Can be repeated, with later files merged on top.
+    #[structopt(
+        long = "merge",
+        value_name = "file:format",
+        number_of_values = 1,
+        parse(try_from_str = parse_file_format)
+    )]
+    pub flag_merge: Vec<(path::PathBuf, InputFormat)>,
+    /// How --merge merges sequences: 'deep' replaces them, while 'strategic' merges the items
+    /// of lists with a merge key and follows '$patch' directives, like Kubernetes strategic
+    /// merge patches.
+    #[structopt(
+        long = "merge-strategy",
+        value_name = "strategy",
+        default_value = "deep"
+    )]
+    pub flag_merge_strategy: rq::transform::merge::Strategy,
+    /// The field that identifies the items of a list in strategic merges, given with the field
+    /// names leading to the list, like 'sidecars=name' or '.spec.ports=port'.  Adds to the
+    /// merge keys of common Kubernetes lists, like 'containers=name'.  Can be repeated.
+    #[structopt(long = "merge-key", value_name = "path=key", number_of_values = 1)]
+    pub flag_merge_key: Vec<rq::transform::merge::MergeKey>,
+    /// Clean up all values in the records, recursively, with comma-separated rules like
+    /// 'trim-strings,empty-as-null,lowercase-keys'.  Rules: trim-strings, collapse-whitespace
+    /// (also trims), empty-as-null, lowercase-strings and lowercase-keys.
+    #[structopt(long = "normalize", value_name = "rules")]
+    pub flag_normalize: Option<rq::transform::normalize::Rules>,
+    /// Collect all records (which must be maps) into a single column-oriented record, like
+    /// '{"a": [1, 2]}'.
+    #[structopt(long = "to-columns", conflicts_with = "flag-from-columns")]
+    pub flag_to_columns: bool,
+    /// Split column-oriented records, like '{"a": [1, 2]}', into one record per row.
+    #[structopt(long = "from-columns")]
+    pub flag_from_columns: bool,
+    /// Drop records whose value at this path, like '.id', is the same as that of an earlier
+    /// record.  Records without the field are kept.
+    #[structopt(long = "dedup-key", value_name = "path")]
+    pub flag_dedup_key: Option<rq::value::path::Path>,
+    /// Output a copy of each record for every element of the sequence at this path, like
+    /// '.items', with the sequence replaced by the element.
+    #[structopt(long = "explode", value_name = "path")]
+    pub flag_explode: Option<rq::value::path::Path>,
+    /// Group consecutive records with the same value at this path into one record, collecting
+    /// them in the sequence given by --into (the reverse of --explode).
+    #[structopt(long = "nest-by", value_name = "path", requires = "flag-into")]
+    pub flag_nest_by: Option<rq::value::path::Path>,
+    /// The field to collect nested records in, for --nest-by.
+    #[structopt(long = "into", value_name = "path", requires = "flag-nest-by")]
+    pub flag_into: Option<rq::value::path::Path>,
+    /// With --nest-by, group all records with the same key instead of only consecutive ones.
+    /// The whole input is kept in memory.
+    #[structopt(long = "nest-all", requires = "flag-nest-by")]
+    pub flag_nest_all: bool,
+    /// Only keep records with a string value that matches this regex.
+    #[structopt(long = "grep", value_name = "regex")]
+    pub flag_grep: Option<regex::Regex>,
+    /// Only look for --grep matches within this path, like '.message'; can be given several
+    /// times.
+    #[structopt(
+        long = "grep-path",
+        value_name = "path",
+        number_of_values = 1,
+        requires = "flag-grep"
+    )]
+    pub flag_grep_path: Vec<rq::value::path::Path>,
+    /// Highlight the --grep matches in string values (only for readable JSON output).
+    #[structopt(long = "grep-highlight", requires = "flag-grep")]
+    pub flag_grep_highlight: bool,
+    /// Only keep records where the value at a path has one of the given types, like
+    /// '.id:string' or '.count:integer|null'; can be given several times.  Types: missing,
+    /// null, boolean, integer, float, number, string, bytes, array and object.
+    #[structopt(long = "where-type", value_name = "check", number_of_values = 1)]
+    pub flag_where_type: Vec<rq::transform::types::Check>,
+    /// Instead of the records, output a report for every record whose fields or their types
+    /// differ from the first record, with the missing, unexpected and changed fields.
+    #[structopt(long = "drift")]
+    pub flag_drift: bool,
+    /// Add the difference between a numeric field and its value in the previous record, like
+    /// '.bytes' (stored in '.bytes_delta') or '.bytes:.rate'.  It is null for the first record.
+    #[structopt(
+        long = "delta",
+        value_name = "spec",
+        number_of_values = 1,
+        parse(try_from_str = rq::transform::look_behind::Spec::delta)
+    )]
+    pub flag_delta: Vec<rq::transform::look_behind::Spec>,
+    /// Add the seconds since the previous record's timestamp (in seconds since the epoch, or an
+    /// RFC 3339 string), like '.time' (stored in '.elapsed') or '.time:.since_previous'.
+    #[structopt(
+        long = "elapsed",
+        value_name = "spec",
+        number_of_values = 1,
+        parse(try_from_str = rq::transform::look_behind::Spec::elapsed)
+    )]
+    pub flag_elapsed: Vec<rq::transform::look_behind::Spec>,
+    /// Add a session number that starts at 0 and increases whenever there is a gap of more than
+    /// the given time between consecutive timestamps, like '.time:30m' (stored in '.session') or
+    /// '.time:30m:.visit'.
+    #[structopt(
+        long = "session",
+        value_name = "spec",
+        number_of_values = 1,
+        parse(try_from_str = rq::transform::look_behind::Spec::session)
+    )]
+    pub flag_session: Vec<rq::transform::look_behind::Spec>,
+    /// Only output a uniformly random sample of this many records (in their original order).
+    /// The whole input is read, but only the sample is kept in memory.
+    #[structopt(long = "sample", value_name = "count")]
+    pub flag_sample: Option<usize>,
+    /// Replace fields with fake data, like 'name=person.name,contact.email=internet.email'.
+    /// The same original value is always replaced by the same fake data for the same --seed.
+    /// Kinds: person.first_name, person.last_name, person.name, internet.email,
+    /// internet.username, phone.number, address.city, company.name and lorem.word.
+    #[structopt(
+        long = "fake",
+        value_name = "fields",
+        number_of_values = 1,
+        parse(try_from_str = parse_fake_fields)
+    )]
+    pub flag_fake: Vec<Vec<rq::transform::fake::Field>>,
+    /// Output the records in a random order.  All records are kept in memory.
+    #[structopt(long = "shuffle")]
+    pub flag_shuffle: bool,
+    /// Output a histogram of a numeric field instead of the records: one record per bucket with
+    /// its bounds and count.  Buckets are given by their edges, like '.latency_ms:0,10,100,1000',
+    /// or by their width, like '.latency_ms:width=10'.
+    #[structopt(long = "histogram", value_name = "spec")]
+    pub flag_histogram: Option<rq::transform::histogram::Spec>,
+    /// Output the most frequent values of the field given by --by instead of the records, with
+    /// their counts.  Uses bounded memory, so counts can be overestimated by up to the reported
+    /// error when there are very many distinct values.
+    #[structopt(long = "top-k", value_name = "k", requires = "flag-by")]
+    pub flag_top_k: Option<usize>,
+    /// Output a summary of a numeric field instead of the records: its count, min, max, mean
+    /// and estimated percentiles, like '.latency_ms:p50,p95,p99' (defaults to p50, p90 and p99).
+    #[structopt(long = "quantiles", value_name = "spec")]
+    pub flag_quantiles: Option<rq::transform::quantiles::Spec>,
+    /// Output a pivot table instead of the records: one record per value of the rows field,
+    /// with a column per value of the columns field, like
+    /// 'rows=.region,columns=.month,values=sum(.revenue)'.  Values can be 'count' (the default)
+    /// or sum, min, max or mean of a field.
+    #[structopt(long = "pivot", value_name = "spec")]
+    pub flag_pivot: Option<rq::transform::pivot::Spec>,
+    /// The field to aggregate over, as a path like '.user_agent'.
+    #[structopt(long = "by", value_name = "path")]
+    pub flag_by: Option<rq::value::path::Path>,
+    /// The seed for random choices, like which records to sample or how to shuffle them.  The
+    /// same seed makes the same choices for the same input, which is useful for reproducible
+    /// test fixtures.
+    #[structopt(long = "seed")]
+    pub flag_seed: Option<u64>,
+    /// Describe the conversion pipeline before running it, and print how many records passed
+    /// through each stage and how long they took afterwards (to stderr).
+    #[structopt(long = "explain")]
+    pub flag_explain: bool,
+
+    /// Make the records conform to this Avro schema before they are written: missing fields
+    /// are filled in with their defaults from the schema, and fields that aren't in the schema
+    /// are dropped.  Only Avro schemas are supported, not JSON Schema or protobuf descriptors.
+    #[structopt(long = "conform-to", value_name = "schema", parse(from_os_str))]
+    pub flag_conform_to: Option<path::PathBuf>,
+    /// With --conform-to, accept values of enums that only differ in case from one of the
+    /// symbols.
+    #[structopt(long = "enum-ignore-case", requires = "flag-conform-to")]
+    pub flag_enum_ignore_case: bool,
+    /// With --conform-to, a YAML or JSON file that maps the name of each enum to a map from
+    /// values to the symbols that they stand for.
+    #[structopt(
+        long = "enum-aliases",
+        value_name = "file",
+        requires = "flag-conform-to",
+        parse(from_os_str)
+    )]
+    pub flag_enum_aliases: Option<path::PathBuf>,
+    /// The line ending to use in text output: 'lf' (the default) or 'crlf', for tools on
+    /// Windows.  Applies to all line breaks, including those within records.
+    #[structopt(long = "output-newline", value_name = "newline")]
+    pub flag_output_newline: Option<rq::output::Newline>,
+    /// Compress the output with 'gzip', 'snappy' (the Snappy framing format) or 'lz4' (the LZ4
+    /// frame format).  Compressed input in any of these is decompressed without being asked.
+    #[structopt(long = "compress", value_name = "codec")]
+    pub flag_compress: Option<rq::compression::Codec>,
+    /// The gzip compression level, from 0 (none) to 9 (best).  The Snappy and LZ4 encoders only
+    /// have one level.
+    #[structopt(
+        long = "compress-level",
+        value_name = "level",
+        requires = "flag-compress"
+    )]
+    pub flag_compress_level: Option<u32>,
 
     #[structopt(short = "A", long = "output-avro")]
     pub flag_output_avro: Option<String>,
+    /// Output a series of bencode values, with the keys of dictionaries sorted.
+    #[structopt(long = "output-bencode")]
+    pub flag_output_bencode: bool,
+    /// Output a series of BSON documents.
+    #[structopt(long = "output-bson")]
+    pub flag_output_bson: bool,
     #[structopt(short = "C", long = "output-cbor")]
     pub flag_output_cbor: bool,
+    /// Output a series of Amazon Ion values in the text encoding, one per line.
+    #[structopt(long = "output-ion")]
+    pub flag_output_ion: bool,
+    /// Output a series of Amazon Ion values in the binary encoding.
+    #[structopt(long = "output-ion-binary")]
+    pub flag_output_ion_binary: bool,
+    /// Output a series of Universal Binary JSON (UBJSON) values.
+    #[structopt(long = "output-ubjson")]
+    pub flag_output_ubjson: bool,
+    /// Output a series of EDN values, one per line.  Maps like {"$keyword": "name"} and keys
+    /// like ":name" are written as the EDN types that --input-edn reads them from.
+    #[structopt(long = "output-edn")]
+    pub flag_output_edn: bool,
     #[structopt(short = "J", long = "output-json")]
     pub flag_output_json: bool,
     #[structopt(short = "R", long = "output-raw")]
@@ -83,12 +577,190 @@ pub struct Options {
     pub flag_output_message_pack: bool,
     #[structopt(short = "P", long = "output-protobuf")]
     pub flag_output_protobuf: Option<String>,
+    /// Output a protobuf message of the specified type in the text format.  Fields may be named
+    /// like in the schema or in lowerCamelCase.  There can only be one record.
+    #[structopt(long = "output-textproto", value_name = "message")]
+    pub flag_output_textproto: Option<String>,
+    /// Output Thrift structs of the specified type, described by --thrift-idl.  Enums can be
+    /// given by name or by value.
+    #[structopt(
+        long = "output-thrift",
+        value_name = "struct",
+        requires = "flag-thrift-idl"
+    )]
+    pub flag_output_thrift: Option<String>,
     #[structopt(short = "T", long = "output-toml")]
     pub flag_output_toml: bool,
+    /// Output an environment file (.env) with a KEY=VALUE line for each entry of the records,
+    /// which must be maps of scalars.
+    #[structopt(long = "output-dotenv")]
+    pub flag_output_dotenv: bool,
+    /// Output a logfmt line of key=value pairs for each record, which must be maps of scalars.
+    /// Strings are quoted when they would be read back as something else.
+    #[structopt(long = "output-logfmt")]
+    pub flag_output_logfmt: bool,
+    /// Output a KDL document with a node for each record, which must be maps with a name, and
+    /// optionally args, props and children, like --input-kdl reads them.
+    #[structopt(long = "output-kdl")]
+    pub flag_output_kdl: bool,
     #[structopt(short = "Y", long = "output-yaml")]
     pub flag_output_yaml: bool,
     #[structopt(short = "S", long = "output-smile")]
     pub flag_output_smile: bool,
+    #[structopt(short = "X", long = "output-xml")]
+    pub flag_output_xml: bool,
+    /// Output an Apache Parquet file, with the schema inferred from the first records.
+    #[structopt(long = "output-parquet")]
+    pub flag_output_parquet: bool,
+    /// Output an Apache Arrow IPC file (Feather file), with the schema inferred from the first
+    /// records.
+    #[structopt(long = "output-arrow")]
+    pub flag_output_arrow: bool,
+    /// Insert the records into the specified table of the SQLite database at --output, creating
+    /// it with columns for the fields of the first record if it doesn't exist yet.
+    #[structopt(
+        long = "output-sqlite",
+        value_name = "table",
+        requires = "flag-output",
+        conflicts_with_all = &["flag-compress", "flag-output-rotate"]
+    )]
+    pub flag_output_sqlite: Option<String>,
+    /// Replace the tags of the MP3, FLAC, Ogg Vorbis or Opus file at --output with the tags of
+    /// the record, keeping its pictures and audio.  The record can be the map of tags itself,
+    /// or have them under `tags` like --input-audio-tags reads them.
+    #[structopt(
+        long = "output-audio-tags",
+        requires = "flag-output",
+        conflicts_with_all = &["flag-compress", "flag-output-rotate", "flag-append"]
+    )]
+    pub flag_output_audio_tags: bool,
+
+    /// How to output null values in TOML, which has no null type.  Can be one of 'omit'
+    /// (leave out the entry or element), 'empty-string' or 'error'.
+    #[structopt(long = "toml-nulls", default_value = "omit")]
+    pub flag_toml_nulls: rq::value::toml::NullPolicy,
+    /// Wrap records that the output format can't have at the top level, instead of failing.
+    /// TOML and XML output wrap other records in a table or root element with the specified
+    /// key, while CSV output writes maps as rows under a header with the keys of the first
+    /// map, and other records as single-field rows.
+    #[structopt(long = "wrap-scalar", value_name = "key")]
+    pub flag_wrap_scalar: Option<String>,
+    /// The character that separates fields in CSV input and output, like ';' or '\t'.
+    #[structopt(
+        long = "csv-delimiter",
+        value_name = "char",
+        parse(try_from_str = rq::value::csv::parse_char)
+    )]
+    pub flag_csv_delimiter: Option<u8>,
+    /// Use tabs to separate fields in CSV input and output (TSV), like --csv-delimiter '\t'.
+    #[structopt(long = "tsv", conflicts_with = "flag-csv-delimiter")]
+    pub flag_tsv: bool,
+    /// The character that quotes fields in CSV input and output, instead of '"'.
+    #[structopt(
+        long = "csv-quote",
+        value_name = "char",
+        parse(try_from_str = rq::value::csv::parse_char)
+    )]
+    pub flag_csv_quote: Option<u8>,
+    /// The character that escapes quotes in quoted CSV fields, like '\\'.  By default, quotes
+    /// are escaped by doubling them.
+    #[structopt(
+        long = "csv-escape",
+        value_name = "char",
+        parse(try_from_str = rq::value::csv::parse_char)
+    )]
+    pub flag_csv_escape: Option<u8>,
+    /// The character that ends CSV records, like ';'.  By default, any line break ends a record
+    /// in CSV input, and output uses '\n' (see --output-newline for CRLF).
+    #[structopt(
+        long = "csv-terminator",
+        value_name = "char",
+        parse(try_from_str = rq::value::csv::parse_char)
+    )]
+    pub flag_csv_terminator: Option<u8>,
+    /// The prefix of the keys that XML attributes are stored under, to tell them apart from
+    /// child elements.  XML output writes entries with this prefix as attributes.
+    #[structopt(
+        long = "xml-attribute-prefix",
+        value_name = "prefix",
+        default_value = "@"
+    )]
+    pub flag_xml_attribute_prefix: String,
+
+    /// Write output to the specified file instead of to stdout.
+    #[structopt(short = "o", long = "output", parse(from_os_str))]
+    pub flag_output: Option<path::PathBuf>,
+    /// Write each record to the file at a path computed from its fields, like
+    /// '{record.date}/{record.tenant}.json'.  Directories are created as needed.
+    #[structopt(
+        long = "output-path-template",
+        value_name = "template",
+        conflicts_with_all = &["flag-output", "flag-output-rotate"]
+    )]
+    pub flag_output_path_template: Option<rq::output::PathTemplate>,
+    /// The most files that --output-path-template keeps open at once.  Beyond that, the least
+    /// recently used file is closed, and reopened to append to it when it gets more records.
+    #[structopt(long = "max-open-files", value_name = "n", default_value = "256")]
+    pub flag_max_open_files: usize,
+    /// Randomly distribute the records over several output files, each getting the specified
+    /// share of the records, like '0.8:train.json,0.2:valid.json'.  Uses --seed.
+    #[structopt(
+        long = "split",
+        value_name = "outputs",
+        conflicts_with_all = &["flag-output", "flag-output-rotate", "flag-output-path-template"]
+    )]
+    pub flag_split: Option<rq::output::Split>,
+    /// Write the records that match a condition to a file of their own, in its own output
+    /// format, like '.level == "error" => json:errors.json'; can be given several times.  Each
+    /// record takes the first route that it matches, and the others go to the regular output.
+    /// Conditions: '*', '.path' (present and not null or false), '.path == value', '.path !=
+    /// value', '.path =~ regex' and '.path:type'.
+    #[structopt(long = "route", value_name = "route", number_of_values = 1)]
+    pub flag_route: Vec<rq::output::Route>,
+    /// Split the output into several numbered files, starting a new file once the current one
+    /// has reached the specified size (like 'size=500M') or record count (like 'count=1e6').
+    /// Each file is a complete document in the output format.
+    #[structopt(long = "output-rotate", value_name = "limit")]
+    pub flag_output_rotate: Option<rq::output::Rotation>,
+    /// Append to the output file instead of replacing it.  Only supported for output formats
+    /// that can be extended: JSON, CSV, raw, CBOR, MessagePack, BSON, Ion and Avro (which requires
+    /// the existing file to have the same schema).
+    #[structopt(long = "append")]
+    pub flag_append: bool,
+    /// When appending, also drop the records whose --dedup-key is already in the output file,
+    /// so that re-running an import doesn't write the same records again.
+    #[structopt(long = "skip-existing", requires_all = &["flag-dedup-key", "flag-append"])]
+    pub flag_skip_existing: bool,
+    /// Write directly to the output file.  By default, output is written to a temporary file
+    /// that is renamed once it is complete, so that other processes never see partial output.
+    /// Replaced files keep their permissions and owner, symbolic links are written through, and
+    /// files with other hard links are written in place.
+    #[structopt(long = "no-atomic")]
+    pub flag_no_atomic: bool,
+    /// The permissions of output files, in octal (like '0640'), which files that are replaced
+    /// or appended to get as well.  The umask still applies.  Only supported on Unix.
+    #[structopt(
+        long = "output-mode",
+        value_name = "mode",
+        parse(try_from_str = rq::output::parse_mode)
+    )]
+    pub flag_output_mode: Option<u32>,
+    /// The owner of output files, like chown takes it: 'user', 'user:group' or ':group', by name
+    /// or numeric ID.  Changing the user usually requires root.  Only supported on Unix.
+    #[structopt(
+        long = "output-owner",
+        value_name = "user:group",
+        parse(try_from_str = rq::output::parse_owner)
+    )]
+    pub flag_output_owner: Option<rq::output::Owner>,
+
+    /// Run an HTTP server on this address, like '127.0.0.1:8080', instead of converting stdin.
+    /// POST a body to '/convert?from=csv&to=json' to get it converted between the formats, which
+    /// are named like their flags without the '--input-' or '--output-' prefix.  If 'from' is
+    /// left out, it is picked by the Content-Type of the request.  Bodies of up to 64 MiB are
+    /// converted, a few requests at a time.
+    #[structopt(long = "serve", value_name = "addr")]
+    pub flag_serve: Option<String>,
 
     #[structopt(short = "l", long = "log")]
     pub flag_log: Option<String>,
@@ -98,8 +770,38 @@ pub struct Options {
     pub flag_trace: bool,
 }
 
-#[derive(Debug, StructOpt)]
+#[derive(Clone, Debug, StructOpt)]
 pub enum Subcmd {
+    /// Run a conversion described by a YAML pipeline file.  Each key in the file is the name of
+    /// a long flag (like 'input-csv' or 'output'), with 'true' for flags without a value, and
+    /// 'query' sets the query.
+    #[structopt(name = "run")]
+    Run {
+        #[structopt(parse(from_os_str))]
+        pipeline: path::PathBuf,
+    },
+    /// Print the JSON Pointers of all map keys and string values that match a regex, starting
+    /// with the number of the record they are in, like '/0/users/3/email'.
+    #[structopt(name = "find")]
+    Find {
+        pattern: String,
+        /// Only match map keys.
+        #[structopt(long = "keys", conflicts_with = "strings")]
+        keys: bool,
+        /// Only match string values.
+        #[structopt(long = "strings")]
+        strings: bool,
+        /// Also print the value at each path.
+        #[structopt(long = "with-values")]
+        with_values: bool,
+    },
+    /// Report which input formats a file plausibly is, with the parse error for each format
+    /// that it isn't.
+    #[structopt(name = "detect")]
+    Detect {
+        #[structopt(parse(from_os_str))]
+        file: path::PathBuf,
+    },
     #[structopt(name = "protobuf")]
     Protobuf {
         #[structopt(subcommand)]
@@ -107,7 +809,7 @@ pub enum Subcmd {
     },
 }
 
-#[derive(Debug, StructOpt)]
+#[derive(Clone, Debug, StructOpt)]
 pub enum ProtobufSubcmd {
     #[structopt(name = "add")]
     Add {
@@ -124,6 +826,86 @@ pub enum Format {
     Indented,
 }
 
+/// An input format, as selected by the input flags, `--input-mime` or the `from` parameter of
+/// `--serve`.  All input is opened by `open_source` from this.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum InputFormat {
+    Arrow,
+    Asn1,
+    AudioTags,
+    AuthorizedKeys,
+    Avro,
+    Bencode,
+    Bson,
+    Cbor,
+    Csv,
+    Dotenv,
+    Edn,
+    Evtx,
+    GitLog,
+    Hcl,
+    Image,
+    Ion,
+    Json,
+    Jsonc,
+    Kdl,
+    KnownHosts,
+    Lockfile,
+    Log4jXml,
+    Logfmt,
+    MessagePack,
+    Midi,
+    OciImage,
+    Parquet,
+    Pdf,
+    PropertiesXml,
+    ProtobufRaw,
+    Raw,
+    Registry,
+    Riff,
+    Smile,
+    Sqlite,
+    Toml,
+    Ubjson,
+    X509,
+    Xlsx,
+    Xml,
+    Yaml,
+    ZoneFile,
+}
+
+/// An output format, as selected by the output flags.  All output is opened by `open_sink` from
+/// this, and described by `describe_output`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum OutputFormat {
+    Arrow,
+    AudioTags,
+    Avro,
+    Bencode,
+    Bson,
+    Cbor,
+    Csv,
+    Dotenv,
+    Edn,
+    Ion,
+    IonBinary,
+    Json,
+    Kdl,
+    Logfmt,
+    MessagePack,
+    Parquet,
+    Protobuf,
+    Raw,
+    Smile,
+    Sqlite,
+    Textproto,
+    Thrift,
+    Toml,
+    Ubjson,
+    Xml,
+    Yaml,
+}
+
 fn main() {
     use structopt::StructOpt;
 
@@ -145,6 +927,9 @@ fn main() {
 }
 
 fn main_with_args(args: &Options) -> rq::error::Result<()> {
+    if let Some(ref addr) = args.flag_serve {
+        return serve(addr);
+    }
     match args.subcmd {
         Some(Subcmd::Protobuf { ref subcmd }) => match subcmd {
             ProtobufSubcmd::Add { schema, base } => {
@@ -155,516 +940,4745 @@ fn main_with_args(args: &Options) -> rq::error::Result<()> {
                 rq::proto_index::add_file(&paths, base, schema)
             }
         },
-        None => run(args),
-    }
-}
-
-fn run(args: &Options) -> rq::error::Result<()> {
-    let stdin = io::stdin();
-    let mut input = stdin.lock();
+        Some(Subcmd::Run { ref pipeline }) => {
+            use structopt::StructOpt;
 
-    if let Some(ref name) = args.flag_input_protobuf {
-        let paths = rq::config::Paths::new()?;
-        let proto_descriptors = load_descriptors(&paths)?;
-        let stream = protobuf::CodedInputStream::new(&mut input);
-        let source = rq::value::protobuf::source(&proto_descriptors, name, stream)?;
-        run_source(args, source)
-    } else if args.flag_input_avro {
-        let source = rq::value::avro::source(&mut input)?;
-        run_source(args, source)
-    } else if args.flag_input_cbor {
-        let source = rq::value::cbor::source(&mut input);
-        run_source(args, source)
-    } else if args.flag_input_message_pack {
-        let source = rq::value::messagepack::source(&mut input);
-        run_source(args, source)
-    } else if args.flag_input_toml {
-        let source = rq::value::toml::source(&mut input)?;
-        run_source(args, source)
-    } else if args.flag_input_yaml {
-        let source = rq::value::yaml::source(&mut input);
-        run_source(args, source)
-    } else if args.flag_input_smile {
-        let source = rq::value::smile::source(&mut input)?;
-        run_source(args, source)
-    } else if args.flag_input_raw {
-        let source = rq::value::raw::source(&mut input);
-        run_source(args, source)
-    } else if args.flag_input_csv {
-        if env::args().skip(1).any(|v| v == "-v") && !has_ran_cmd("help")? {
-            warn!("You started rq -v, which puts it in CSV input mode.");
-            warn!("It's now waiting for CSV input, which might not be what you wanted.");
-            warn!(
-                "Specify --input-csv explicitly or run rq --help once to suppress this \
-                 warning."
-            );
+            let mut contents = String::new();
+            fs::File::open(pipeline)?.read_to_string(&mut contents)?;
+            let pipeline_args =
+                Options::from_iter_safe(pipeline_args(&contents)?).map_err(|e| {
+                    // Leave out the usage instructions, which are about the command line
+                    let message = e.message.lines().next().unwrap_or_default();
+                    rq::error::Error::Message(format!("invalid pipeline: {}", message))
+                })?;
+            if pipeline_args.subcmd.is_some() {
+                return Err(rq::error::Error::Message(
+                    "pipelines can't run subcommands".to_owned(),
+                ));
+            }
+            run(&pipeline_args)
         }
-        let source = rq::value::csv::source(&mut input);
-        run_source(args, source)
-    } else {
-        if !args.flag_input_json && !has_ran_cmd("help")? {
-            warn!("You started rq without any input flags, which puts it in JSON input mode.");
-            warn!("It's now waiting for JSON input, which might not be what you wanted.");
-            warn!(
-                "Specify (-j|--input-json) explicitly or run rq --help once to suppress this \
-                 warning."
-            );
+        Some(Subcmd::Detect { ref file }) => {
+            let mut input = Vec::new();
+            fs::File::open(file)?.read_to_end(&mut input)?;
+            detect(&input)
         }
-        let source = rq::value::json::source(&mut input);
-        run_source(args, source)
+        Some(Subcmd::Find { .. }) | None => run(args),
     }
 }
 
-fn run_source<I>(args: &Options, source: I) -> rq::error::Result<()>
-where
-    I: rq::value::Source,
-{
-    let mut output = io::stdout();
-
-    let format = args.flag_format.unwrap_or_else(infer_format);
+/// Translates a pipeline file into the equivalent command line arguments.
+fn pipeline_args(contents: &str) -> rq::error::Result<Vec<String>> {
+    let pipeline: serde_yaml::Mapping = serde_yaml::from_str(contents)?;
+    let mut args = vec!["rq".to_owned()];
+    let mut query = None;
 
-    macro_rules! dispatch_format {
-        ($compact:expr, $readable:expr, $indented:expr) => {
-            match format {
-                Format::Compact => {
-                    let sink = $compact(&mut output);
-                    run_source_sink(source, sink)
-                }
-                Format::Readable => {
-                    let sink = $readable(&mut output);
-                    run_source_sink(source, sink)
-                }
-                Format::Indented => {
-                    let sink = $indented(&mut output);
-                    run_source_sink(source, sink)
-                }
+    for (key, value) in pipeline {
+        let key = match key {
+            serde_yaml::Value::String(key) => key,
+            other => {
+                return Err(rq::error::Error::Message(format!(
+                    "pipeline keys must be strings, got {:?}",
+                    other
+                )))
             }
         };
+        let values = match value {
+            serde_yaml::Value::Sequence(values) => values,
+            value => vec![value],
+        };
+        for value in values {
+            let value = match value {
+                serde_yaml::Value::Bool(true) => None,
+                serde_yaml::Value::Bool(false) => continue,
+                serde_yaml::Value::String(s) => Some(s),
+                serde_yaml::Value::Number(n) => Some(n.to_string()),
+                other => {
+                    return Err(rq::error::Error::Message(format!(
+                        "unsupported value for pipeline key {:?}: {:?}",
+                        key, other
+                    )))
+                }
+            };
+            if key == "query" {
+                query = value;
+            } else {
+                args.push(format!("--{}", key));
+                args.extend(value);
+            }
+        }
     }
 
-    if args.flag_output_protobuf.is_some() {
-        Err(rq::error::Error::unimplemented(
-            "protobuf serialization".to_owned(),
-        ))
-    } else if let Some(ref schema_filename) = args.flag_output_avro {
-        use std::str::FromStr;
-
-        let schema = read_avro_schema_from_file(path::Path::new(schema_filename))?;
-        let codec_string = if let Some(ref c) = args.flag_codec {
-            c.as_str()
-        } else {
-            "null"
-        };
-        let codec = if let Ok(v) = avro_rs::Codec::from_str(codec_string) {
-            v
-        } else {
-            return Err(rq::error::Error::Message(format!(
-                "illegal Avro codec: {}",
-                codec_string
-            )));
-        };
-        let sink = rq::value::avro::sink(&schema, &mut output, codec)?;
-        run_source_sink(source, sink)
-    } else if args.flag_output_cbor {
-        let sink = rq::value::cbor::sink(&mut output);
-        run_source_sink(source, sink)
-    } else if args.flag_output_message_pack {
-        let sink = rq::value::messagepack::sink(&mut output);
-        run_source_sink(source, sink)
-    } else if args.flag_output_toml {
-        // TODO: add TOML ugly printing eventually; now it's always "readable"
-        dispatch_format!(
-            rq::value::toml::sink,
-            rq::value::toml::sink,
-            rq::value::toml::sink
-        )
-    } else if args.flag_output_yaml {
-        // TODO: add YAML ugly printing eventually; now it's always "readable"
-        dispatch_format!(
-            rq::value::yaml::sink,
-            rq::value::yaml::sink,
-            rq::value::yaml::sink
-        )
-    } else if args.flag_output_smile {
-        let sink = rq::value::smile::sink(&mut output)?;
-        run_source_sink(source, sink)
-    } else if args.flag_output_raw {
-        let sink = rq::value::raw::sink(&mut output);
-        run_source_sink(source, sink)
-    } else if args.flag_output_csv {
-        let sink = rq::value::csv::sink(&mut output);
-        run_source_sink(source, sink)
-    } else {
-        dispatch_format!(
-            rq::value::json::sink_compact,
-            rq::value::json::sink_readable,
-            rq::value::json::sink_indented
-        )
+    // The query goes last, after a separator so that it can't be mistaken for a subcommand
+    if let Some(query) = query {
+        args.push("--".to_owned());
+        args.push(query);
     }
+    Ok(args)
 }
 
-fn read_avro_schema_from_file(path: &path::Path) -> rq::error::Result<avro_rs::Schema> {
-    let mut file = fs::File::open(path)?;
-    let mut buffer = String::new();
-    file.read_to_string(&mut buffer)?;
-    avro_rs::Schema::parse_str(&buffer)
-        .map_err(|e| rq::error::Error::Avro(rq::error::Avro::downcast(e)))
+/// The output formats that are selected by a flag without a value, named like their flags,
+/// with the MIME type of their output.  These are the formats that `--route` can write and that
+/// `--serve` can convert to; the others need a schema or write to a file themselves.
+const NAMED_OUTPUT_FORMATS: &[(&str, OutputFormat, &str)] = &[
+    (
+        "arrow",
+        OutputFormat::Arrow,
+        "application/vnd.apache.arrow.file",
+    ),
+    ("bencode", OutputFormat::Bencode, "application/x-bittorrent"),
+    ("bson", OutputFormat::Bson, "application/bson"),
+    ("cbor", OutputFormat::Cbor, "application/cbor"),
+    ("csv", OutputFormat::Csv, "text/csv"),
+    ("dotenv", OutputFormat::Dotenv, "text/plain"),
+    ("edn", OutputFormat::Edn, "application/edn"),
+    ("ion", OutputFormat::Ion, "application/ion"),
+    ("ion-binary", OutputFormat::IonBinary, "application/ion"),
+    ("json", OutputFormat::Json, "application/json"),
+    ("kdl", OutputFormat::Kdl, "text/plain"),
+    ("logfmt", OutputFormat::Logfmt, "text/plain"),
+    (
+        "message-pack",
+        OutputFormat::MessagePack,
+        "application/msgpack",
+    ),
+    (
+        "parquet",
+        OutputFormat::Parquet,
+        "application/vnd.apache.parquet",
+    ),
+    ("raw", OutputFormat::Raw, "text/plain"),
+    ("smile", OutputFormat::Smile, "application/x-jackson-smile"),
+    ("toml", OutputFormat::Toml, "application/toml"),
+    ("ubjson", OutputFormat::Ubjson, "application/ubjson"),
+    ("xml", OutputFormat::Xml, "application/xml"),
+    ("yaml", OutputFormat::Yaml, "application/yaml"),
+];
+
+/// How many requests `--serve` converts at the same time.
+const SERVE_WORKERS: usize = 4;
+/// The largest body that `--serve` converts, in bytes.
+const SERVE_MAX_BODY_SIZE: u64 = 64 << 20;
+
+/// Serves conversions over HTTP, several requests at a time, until the process is stopped.
+fn serve(addr: &str) -> rq::error::Result<()> {
+    use std::thread;
+
+    let server = tiny_http::Server::http(addr)
+        .map_err(|e| rq::error::Error::Message(format!("can't listen on {}: {}", addr, e)))?;
+    info!("Listening on {}", addr);
+
+    thread::scope(|scope| {
+        for _ in 0..SERVE_WORKERS {
+            scope.spawn(|| {
+                for mut request in server.incoming_requests() {
+                    let response = match serve_request(&mut request) {
+                        Ok((mime, output)) => {
+                            let header = tiny_http::Header::from_bytes("Content-Type", mime)
+                                .expect("MIME types are valid header values");
+                            tiny_http::Response::from_data(output).with_header(header)
+                        }
+                        Err((status, message)) => {
+                            debug!("{} {}: {}", request.method(), request.url(), message);
+                            tiny_http::Response::from_string(message + "\n")
+                                .with_status_code(status)
+                        }
+                    };
+                    if let Err(e) = request.respond(response) {
+                        warn!("Failed to send a response: {}", e);
+                    }
+                }
+            });
+        }
+    });
+    Ok(())
 }
 
-fn run_source_sink<I, O>(mut source: I, mut sink: O) -> rq::error::Result<()>
-where
-    I: rq::value::Source,
-    O: rq::value::Sink,
-{
-    while let Some(result) = rq::value::Source::read(&mut source)? {
-        sink.write(result)?;
+/// Handles a request to the `--serve` server, returning the MIME type and the converted body,
+/// or the status code and message of the error.
+fn serve_request(
+    request: &mut tiny_http::Request,
+) -> Result<(&'static str, Vec<u8>), (u16, String)> {
+    let url = request.url().to_owned();
+    let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+    if path != "/convert" {
+        return Err((404, format!("no such endpoint: {}", path)));
     }
-    Ok(())
+    if *request.method() != tiny_http::Method::Post {
+        return Err((405, "conversions must be POSTed".to_owned()));
+    }
+    let params: Vec<_> = query
+        .split('&')
+        .filter_map(|p| p.split_once('='))
+        .map(|(k, v)| (decode_query_component(k), decode_query_component(v)))
+        .collect();
+    let param = |name: &str| {
+        params
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+    };
+
+    let format = match param("from") {
+        Some(from) => InputFormat::from_name(from)
+            .ok_or_else(|| (400, format!("unsupported input format: {}", from)))?,
+        None => request
+            .headers()
+            .iter()
+            .find(|h| h.field.equiv("Content-Type"))
+            .ok_or_else(|| (400, "missing the from parameter".to_owned()))
+            .and_then(|h| {
+                InputFormat::from_mime(h.value.as_str()).map_err(|e| (400, e.to_string()))
+            })?,
+    };
+    let to = param("to").ok_or_else(|| (400, "missing the to parameter".to_owned()))?;
+    let &(to, _, mime) = NAMED_OUTPUT_FORMATS
+        .iter()
+        .find(|&&(name, _, _)| name == to)
+        .ok_or_else(|| (400, format!("unsupported output format: {}", to)))?;
+
+    let too_large = || {
+        (
+            413,
+            format!("the body is larger than {} bytes", SERVE_MAX_BODY_SIZE),
+        )
+    };
+    if request.body_length().unwrap_or(0) as u64 > SERVE_MAX_BODY_SIZE {
+        return Err(too_large());
+    }
+    let mut body = Vec::new();
+    request
+        .as_reader()
+        .take(SERVE_MAX_BODY_SIZE + 1)
+        .read_to_end(&mut body)
+        .map_err(|e| (400, format!("failed to read the body: {}", e)))?;
+    if body.len() as u64 > SERVE_MAX_BODY_SIZE {
+        return Err(too_large());
+    }
+    convert(format, to, &body)
+        .map(|output| (mime, output))
+        .map_err(|e| {
+            let chain: Vec<_> = <dyn failure::Fail>::iter_chain(&e)
+                .map(|e| e.to_string())
+                .collect();
+            (400, chain.join(": "))
+        })
 }
 
-fn load_descriptors(
-    paths: &rq::config::Paths,
-) -> rq::error::Result<serde_protobuf::descriptor::Descriptors> {
-    let descriptors_proto = rq::proto_index::compile_descriptor_set(paths)?;
-    Ok(serde_protobuf::descriptor::Descriptors::from_proto(
-        &descriptors_proto,
-    ))
+/// Decodes a key or value of a URL query, where `+` stands for a space.
+fn decode_query_component(s: &str) -> String {
+    percent_encoding::percent_decode_str(&s.replace('+', " "))
+        .decode_utf8_lossy()
+        .into_owned()
 }
 
-fn infer_format() -> Format {
-    if atty::is(atty::Stream::Stdout) {
-        Format::Readable
-    } else {
-        Format::Compact
+/// Converts the input to the output format named like its flag, with the default options.
+fn convert(format: InputFormat, to: &str, input: &[u8]) -> rq::error::Result<Vec<u8>> {
+    use structopt::StructOpt;
+
+    let args = Options::from_iter_safe(&["rq".to_owned(), format!("--output-{}", to)])
+        .map_err(|e| rq::error::Error::Message(e.message))?;
+    let mut output = Vec::new();
+    {
+        let mut sink = open_sink(&args, Format::Compact, None, None, Box::new(&mut output))?;
+        for (index, record) in read_all(format, input)?.into_iter().enumerate() {
+            write_record(&mut *sink, record, index as u64)?;
+        }
+        finish_sink(&mut *sink)?;
     }
+    Ok(output)
 }
 
-fn has_ran_cmd(cmd: &str) -> rq::error::Result<bool> {
-    let paths = match rq::config::Paths::new() {
-        Ok(paths) => paths,
-        Err(_) => return Ok(false),
-    };
-    paths
-        .find_config(&format!("{}{}", "has-ran-", cmd))
-        .map(|v| !v.is_empty())
-        .map_err(From::from)
-}
+fn run(args: &Options) -> rq::error::Result<()> {
+    if let Some(ref prefix) = args.flag_input_env {
+        let source = rq::value::env::source(prefix.as_deref());
+        return run_source(args, source, "environment variables");
+    }
 
-fn set_ran_cmd(cmd: &str) -> rq::error::Result<()> {
-    let paths = match rq::config::Paths::new() {
-        Ok(paths) => paths,
-        Err(_) => return Ok(()),
-    };
+    if let Some(ref repository) = args.flag_git_log {
+        return run_git_log(args, repository.as_deref());
+    }
 
-    let file = paths.preferred_config(format!("{}{}", "has-ran-", cmd));
+    if args.flag_input_manifest {
+        let mut contents = String::new();
+        io::stdin().read_to_string(&mut contents)?;
+        let files = parse_manifest(&contents)?;
+        let description = format!("{} files listed on stdin", files.len());
+        let source = ManifestSource {
+            args,
+            files: files.into(),
+            current: None,
+        };
+        return run_source(args, source, &description);
+    }
 
-    if let Some(parent) = file.parent() {
-        fs::create_dir_all(parent)?;
+    if args.flag_mmap {
+        if let Some((map, offset)) = map_stdin()? {
+            return run_compressed(args, &map[offset..], "stdin (memory-mapped)");
+        }
     }
 
-    fs::File::create(&file)?;
+    let stdin = io::stdin();
+    run_compressed(args, stdin.lock(), "stdin")
+}
 
+/// Reads the history of a repository from the output of `git log`, which runs alongside.
+fn run_git_log(args: &Options, repository: Option<&path::Path>) -> rq::error::Result<()> {
+    let mut command = process::Command::new("git");
+    if let Some(repository) = repository {
+        command.arg("-C").arg(repository);
+    }
+    command
+        .args(["log", "--format=raw", "--numstat"])
+        .stdout(process::Stdio::piped());
+    debug!("Running {:?}", command);
+    let mut child = command
+        .spawn()
+        .map_err(|e| rq::error::Error::Message(format!("could not run git: {}", e)))?;
+    let stdout = child.stdout.take().expect("stdout is piped");
+    let description = match repository {
+        Some(repository) => format!("git log of {}", repository.display()),
+        None => "git log".to_owned(),
+    };
+    let result = run_source(
+        args,
+        rq::value::git_log::source(io::BufReader::new(stdout)),
+        &description,
+    );
+    let status = child.wait()?;
+    result?;
+    if !status.success() {
+        return Err(rq::error::Error::Message(format!(
+            "git log failed ({})",
+            status
+        )));
+    }
     Ok(())
 }
 
-fn log_error(args: &Options, error: &rq::error::Error) {
-    use failure::Fail;
+/// Reads the input, decompressing it first if it is compressed.
+fn run_compressed<R>(args: &Options, input: R, origin: &str) -> rq::error::Result<()>
+where
+    R: io::BufRead,
+{
+    match rq::compression::decoder(input)? {
+        (Some(codec), input) => {
+            run_input(args, input, &format!("{} ({}-compressed)", origin, codec))
+        }
+        (None, input) => run_input(args, input, origin),
+    }
+}
 
-    let main_str = format!("{}", error);
-    let mut main_lines = main_str.lines();
-    error!("Encountered: {}", main_lines.next().unwrap());
-    for line in main_lines {
-        error!("  {}", line);
+/// Parses an input manifest into the files that it lists with their formats.  Blank lines and
+/// lines starting with `#` are skipped.
+fn parse_manifest(contents: &str) -> rq::error::Result<Vec<(path::PathBuf, InputFormat)>> {
+    let mut files = Vec::new();
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let file = parse_file_format(line).map_err(|e| {
+            rq::error::Error::Message(format!("manifest line {}: {}", number + 1, e))
+        })?;
+        files.push(file);
     }
-    for e in <dyn failure::Fail>::iter_causes(error) {
-        let sub_str = format!("{}", e);
-        let mut sub_lines = sub_str.lines();
-        error!("Caused by: {}", sub_lines.next().unwrap());
-        for line in sub_lines {
-            error!("  {}", line);
+    Ok(files)
+}
+
+/// Parses a file with its format, like 'users.csv:csv'.
+fn parse_file_format(s: &str) -> rq::error::Result<(path::PathBuf, InputFormat)> {
+    // The format comes last, so that paths may contain colons
+    let (file, format) = s
+        .rsplit_once(':')
+        .ok_or_else(|| rq::error::Error::Message(format!("expected 'file:format', got: {}", s)))?;
+    let format = InputFormat::from_name(format).ok_or_else(|| {
+        rq::error::Error::Message(format!("unsupported input format: {}", format))
+    })?;
+    Ok((path::PathBuf::from(file), format))
+}
+
+/// Reads the records of the files to merge into each record, in order.
+fn read_patches(args: &Options) -> rq::error::Result<Vec<rq::value::Value>> {
+    let mut patches = Vec::new();
+    for (file, format) in &args.flag_merge {
+        debug!(
+            "Reading patches from {} as {}",
+            file.display(),
+            format.name()
+        );
+        let input = input_file(
+            args,
+            fs::File::open(file).inspect_err(|_| {
+                error!("Failed to open {}", file.display());
+            })?,
+        )?;
+        let (_, input) = rq::compression::decoder(input)?;
+        let mut source = open_source(args, *format, input)?;
+        while let Some(patch) = source.read()? {
+            patches.push(patch);
         }
     }
+    Ok(patches)
+}
 
-    if args.flag_trace || env::var("RUST_BACKTRACE").as_ref().map(String::as_str) == Ok("1") {
-        error!("");
-        if let Some(backtrace) = error.backtrace() {
-            error!("Backtrace:");
-            for line in format!("{:?}", backtrace).lines() {
-                error!("  {}", line);
+/// Reads the files listed in an input manifest one after the other, each in its own format.
+struct ManifestSource<'a> {
+    args: &'a Options,
+    files: collections::VecDeque<(path::PathBuf, InputFormat)>,
+    current: Option<(path::PathBuf, Box<dyn rq::value::Source>)>,
+}
+
+impl rq::value::Source for ManifestSource<'_> {
+    fn read(&mut self) -> rq::error::Result<Option<rq::value::Value>> {
+        loop {
+            if let Some((ref file, ref mut source)) = self.current {
+                match source.read() {
+                    Ok(Some(record)) => return Ok(Some(record)),
+                    Ok(None) => self.current = None,
+                    Err(e) => {
+                        error!("Failed to read {}", file.display());
+                        return Err(e);
+                    }
+                }
+                continue;
+            }
+
+            match self.files.pop_front() {
+                Some((file, format)) => {
+                    debug!("Reading {} as {}", file.display(), format.name());
+                    let input = input_file(
+                        self.args,
+                        fs::File::open(&file).inspect_err(|_| {
+                            error!("Failed to open {}", file.display());
+                        })?,
+                    )?;
+                    let (_, input) = rq::compression::decoder(input)?;
+                    let source = open_source(self.args, format, input)?;
+                    self.current = Some((file, source));
+                }
+                None => return Ok(None),
             }
-        } else {
-            error!("(No backtrace available)");
         }
-    } else {
-        error!("(Re-run with --trace or RUST_BACKTRACE=1 for a backtrace)");
     }
 }
 
-fn setup_log(spec: Option<&str>, quiet: bool) {
-    let mut builder = env_logger::Builder::new();
-
-    if quiet {
-        builder.filter(None, log::LevelFilter::Off);
-    } else if let Some(s) = spec {
-        builder.parse_filters(s);
-    } else if let Ok(s) = env::var("RUST_LOG") {
-        builder.parse_filters(&s);
+/// Creates a source that reads the input in the format.  Text input is transcoded to UTF-8 and
+/// read leniently with `--lenient`.
+fn open_source<'a, R>(
+    args: &Options,
+    format: InputFormat,
+    input: R,
+) -> rq::error::Result<Box<dyn rq::value::Source + 'a>>
+where
+    R: io::BufRead + 'a,
+{
+    let input: Box<dyn io::BufRead + 'a> = if format.is_text() {
+        // Transcodes to UTF-8 if there is a byte order mark or an explicit encoding, and passes
+        // the input through unchanged otherwise
+        Box::new(io::BufReader::new(
+            encoding_rs_io::DecodeReaderBytesBuilder::new()
+                .encoding(args.flag_input_encoding)
+                .build(input),
+        ))
     } else {
-        builder.filter(None, log::LevelFilter::Info);
+        Box::new(input)
     };
-
-    builder.format(format_log_record);
-
-    builder.init();
+    let (input, format): (Box<dyn io::BufRead + 'a>, _) = match format {
+        InputFormat::Json | InputFormat::Jsonc if args.flag_lenient => (
+            Box::new(io::BufReader::new(rq::value::lenient::json(input))),
+            InputFormat::Json,
+        ),
+        InputFormat::Yaml if args.flag_lenient => {
+            (Box::new(rq::value::lenient::yaml(input)?), format)
+        }
+        _ => (input, format),
+    };
+    Ok(match format {
+        InputFormat::Arrow => Box::new(rq::value::arrow::source(input)?),
+        InputFormat::Asn1 => Box::new(rq::value::asn1::source(input)?),
+        InputFormat::AuthorizedKeys => Box::new(rq::value::ssh_keys::authorized_keys(input)),
+        InputFormat::Avro => Box::new(rq::value::avro::source(input)?),
+        InputFormat::Bencode => Box::new(rq::value::bencode::source(input)),
+        InputFormat::Bson => Box::new(rq::value::bson::source(input)),
+        InputFormat::Ubjson => Box::new(rq::value::ubjson::source(input)),
+        InputFormat::Cbor => Box::new(rq::value::cbor::source(input)),
+        InputFormat::Csv => Box::new(rq::value::csv::source_with(input, &csv_dialect(args))),
+        InputFormat::Dotenv => Box::new(rq::value::dotenv::source(input)?),
+        InputFormat::Edn => Box::new(rq::value::edn::source(input)?),
+        InputFormat::GitLog => Box::new(rq::value::git_log::source(input)),
+        InputFormat::Hcl => Box::new(rq::value::hcl::source(input)?),
+        InputFormat::Ion => Box::new(rq::value::ion::source(input)?),
+        InputFormat::Json => Box::new(rq::value::json::source(input)),
+        InputFormat::Jsonc => Box::new(rq::value::json::source(io::BufReader::new(
+            rq::value::lenient::jsonc(input),
+        ))),
+        InputFormat::Image => Box::new(rq::value::image::source(input)?),
+        InputFormat::AudioTags => Box::new(rq::value::audio_tags::source(input)?),
+        InputFormat::Pdf => Box::new(rq::value::pdf::source(input)?),
+        InputFormat::Registry => Box::new(rq::value::registry::source(input)?),
+        InputFormat::Evtx => Box::new(rq::value::evtx::source(input)?),
+        InputFormat::Kdl => Box::new(rq::value::kdl::source(input)?),
+        InputFormat::KnownHosts => Box::new(rq::value::ssh_keys::known_hosts(input)),
+        InputFormat::Lockfile => Box::new(rq::value::lockfile::source(input)?),
+        InputFormat::Logfmt => Box::new(rq::value::logfmt::source(input)),
+        InputFormat::MessagePack => Box::new(rq::value::messagepack::source(input)),
+        InputFormat::Midi => Box::new(rq::value::chunks::midi(input)),
+        InputFormat::OciImage => Box::new(rq::value::oci::source(input)?),
+        InputFormat::Parquet => Box::new(rq::value::parquet::source(input)?),
+        InputFormat::ProtobufRaw => Box::new(rq::value::protobuf_raw::source(input)?),
+        InputFormat::Riff => Box::new(rq::value::chunks::riff(input)),
+        InputFormat::Raw => Box::new(rq::value::raw::source(input)),
+        InputFormat::Smile => Box::new(rq::value::smile::source(input)?),
+        InputFormat::Sqlite => Box::new(rq::value::sqlite::source(
+            input,
+            args.flag_sqlite_query.as_deref(),
+        )?),
+        InputFormat::Toml => Box::new(rq::value::toml::source(input)?),
+        InputFormat::X509 => Box::new(rq::value::x509::source(input)?),
+        InputFormat::Xlsx => Box::new(rq::value::xlsx::source(
+            input,
+            args.flag_xlsx_sheet.as_deref(),
+        )?),
+        InputFormat::Xml => Box::new(rq::value::xml::source(
+            input,
+            args.flag_xml_attribute_prefix.clone(),
+        )),
+        InputFormat::PropertiesXml => Box::new(rq::value::java_xml::properties(input)),
+        InputFormat::Log4jXml => Box::new(rq::value::java_xml::log4j(input)),
+        InputFormat::Yaml => Box::new(rq::value::yaml::source(input)),
+        InputFormat::ZoneFile => Box::new(rq::value::zone_file::source(input)),
+    })
 }
 
-impl str::FromStr for Format {
-    type Err = failure::Error;
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "compact" => Ok(Self::Compact),
-            "readable" => Ok(Self::Readable),
-            "indented" => Ok(Self::Indented),
-            _ => Err(failure::err_msg(format!("unrecognized format: {}", s))),
+/// Opens an input file for reading, which is memory-mapped with --mmap if it's a regular file.
+fn input_file(args: &Options, file: fs::File) -> rq::error::Result<Box<dyn io::BufRead>> {
+    if args.flag_mmap {
+        if let Some(map) = map_file(&file)? {
+            return Ok(Box::new(io::Cursor::new(map)));
         }
     }
+    Ok(Box::new(io::BufReader::new(file)))
 }
 
-fn format_log_record(
-    formatter: &mut env_logger::fmt::Formatter,
-    record: &log::Record,
-) -> io::Result<()> {
-    use ansi_term::ANSIStrings;
-    use ansi_term::Colour;
-    use ansi_term::Style;
+/// Memory-maps a file if it is a regular file.
+fn map_file(file: &fs::File) -> rq::error::Result<Option<memmap2::Mmap>> {
+    if !file.metadata()?.is_file() {
+        warn!("Input is not a regular file and can't be memory-mapped; reading it normally");
+        return Ok(None);
+    }
+    // Safety: the map is only read from, and the file must not be truncated while rq runs; that
+    // caveat is documented for --mmap
+    let map = unsafe { memmap2::Mmap::map(file)? };
+    debug!("Memory-mapped {} bytes of input", map.len());
+    Ok(Some(map))
+}
 
-    if atty::is(atty::Stream::Stderr) {
-        let normal = Style::new();
-        let (front, back) = match record.level() {
-            log::Level::Error => (Colour::Red.normal(), Colour::Red.dimmed()),
-            log::Level::Warn => (Colour::Yellow.normal(), Colour::Yellow.dimmed()),
-            log::Level::Info => (Colour::Blue.normal(), Colour::Blue.dimmed()),
-            log::Level::Debug => (Colour::Purple.normal(), Colour::Purple.dimmed()),
-            log::Level::Trace => (Colour::White.dimmed(), Colour::Black.normal()),
-        };
+/// Memory-maps stdin if it is a regular file, returning the map and the current read offset.
+#[cfg(unix)]
+fn map_stdin() -> rq::error::Result<Option<(memmap2::Mmap, usize)>> {
+    use std::os::unix::io::AsFd;
 
-        let strings = &[
-            back.paint("["),
-            front.paint(format!("{}", record.level())),
-            back.paint("]"),
-            normal.paint(" "),
-            back.paint("["),
-            front.paint(record.module_path().unwrap_or("<unknown>")),
-            back.paint("]"),
-            normal.paint(" "),
-            front.paint(format!("{}", record.args())),
-        ];
+    let mut file = fs::File::from(io::stdin().as_fd().try_clone_to_owned()?);
+    let offset = file.stream_position()? as usize;
+    Ok(map_file(&file)?.map(|map| {
+        let offset = offset.min(map.len());
+        (map, offset)
+    }))
+}
 
-        writeln!(formatter, "{}", ANSIStrings(strings))
+#[cfg(not(unix))]
+fn map_stdin() -> rq::error::Result<Option<(memmap2::Mmap, usize)>> {
+    warn!("Memory-mapped input is not supported on this platform; reading it normally");
+    Ok(None)
+}
+
+fn run_input<R>(args: &Options, mut input: R, origin: &str) -> rq::error::Result<()>
+where
+    R: io::BufRead,
+{
+    if let Some(ref name) = args.flag_input_protobuf {
+        let paths = rq::config::Paths::new()?;
+        let proto_descriptors = load_descriptors(&paths)?;
+        let stream = protobuf::CodedInputStream::new(&mut input);
+        if args.flag_proto3_json {
+            let source = rq::value::protobuf::proto3_json_source(&proto_descriptors, name, stream)?;
+            let description = format!("protobuf message {} (proto3 JSON) from {}", name, origin);
+            return run_source(args, source, &description);
+        }
+        let source = rq::value::protobuf::source(&proto_descriptors, name, stream)?;
+        let description = format!("protobuf message {} from {}", name, origin);
+        return run_source(args, source, &description);
+    }
+    if let Some(ref name) = args.flag_input_textproto {
+        let paths = rq::config::Paths::new()?;
+        let proto_descriptors = load_descriptors(&paths)?;
+        let source = rq::value::textproto::source(&proto_descriptors, name, input)?;
+        let description = format!("protobuf text format message {} from {}", name, origin);
+        return run_source(args, source, &description);
+    }
+    if let Some(ref path) = args.flag_input_flatbuffers {
+        let schema = rq::value::flatbuffers::Schema::parse(&fs::read(path)?)?;
+        let source = rq::value::flatbuffers::source(
+            schema,
+            args.flag_flatbuffers_root.as_deref(),
+            input,
+            args.flag_flatbuffers_size_prefixed,
+        )?;
+        let description = format!("FlatBuffers from {}", origin);
+        return run_source(args, source, &description);
+    }
+    if let Some(ref name) = args.flag_input_thrift {
+        let idl = thrift_idl(args)?;
+        let source = rq::value::thrift::source(idl, name, args.flag_thrift_protocol, input)?;
+        let description = format!(
+            "Thrift struct {} ({} protocol) from {}",
+            name, args.flag_thrift_protocol, origin
+        );
+        return run_source(args, source, &description);
+    }
+
+    let format = input_format(args);
+    let description = format!("{} from {}", format.name(), origin);
+    if args.flag_input_encoding.is_some() && !format.is_text() {
+        warn!("--input-encoding has no effect on {} input", format.name());
+    }
+    if args.flag_lenient
+        && !matches!(
+            format,
+            InputFormat::Json | InputFormat::Jsonc | InputFormat::Yaml
+        )
+    {
+        warn!("--lenient has no effect on {} input", format.name());
+    }
+    run_format(args, input, format, &description)
+}
+
+/// How likely it is that some input is in a format.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+enum Confidence {
+    No,
+    Low,
+    Medium,
+    High,
+}
+
+/// Tries to read the input in every format, and prints how plausible each format is.
+fn detect(input: &[u8]) -> rq::error::Result<()> {
+    if input.is_empty() {
+        println!("The input is empty");
+        return Ok(());
+    }
+
+    for (format, confidence, details) in guess_formats(input) {
+        let confidence = format!("{:?}", confidence).to_lowercase();
+        println!("{:<12} {:<7} {}", format.name(), confidence, details);
+    }
+    Ok(())
+}
+
+/// Judges every format that the input could be in, the most plausible ones first.
+fn guess_formats(input: &[u8]) -> Vec<(InputFormat, Confidence, String)> {
+    let formats = [
+        InputFormat::Json,
+        InputFormat::Jsonc,
+        InputFormat::Yaml,
+        InputFormat::Toml,
+        InputFormat::Hcl,
+        InputFormat::Csv,
+        InputFormat::Avro,
+        InputFormat::Bson,
+        InputFormat::Ubjson,
+        InputFormat::Bencode,
+        InputFormat::Parquet,
+        InputFormat::Arrow,
+        InputFormat::Xlsx,
+        InputFormat::Sqlite,
+        InputFormat::Lockfile,
+        InputFormat::OciImage,
+        InputFormat::X509,
+        InputFormat::Asn1,
+        InputFormat::Riff,
+        InputFormat::Midi,
+        InputFormat::Image,
+        InputFormat::AudioTags,
+        InputFormat::Pdf,
+        InputFormat::Registry,
+        InputFormat::Evtx,
+        InputFormat::GitLog,
+        InputFormat::ZoneFile,
+        InputFormat::KnownHosts,
+        InputFormat::AuthorizedKeys,
+        InputFormat::Dotenv,
+        InputFormat::Logfmt,
+        InputFormat::Kdl,
+        InputFormat::Edn,
+        InputFormat::Cbor,
+        InputFormat::MessagePack,
+        InputFormat::Ion,
+        InputFormat::Smile,
+        InputFormat::PropertiesXml,
+        InputFormat::Log4jXml,
+        InputFormat::Xml,
+        InputFormat::ProtobufRaw,
+        InputFormat::Raw,
+    ];
+    let json_parses = read_all(InputFormat::Json, input).is_ok();
+    let mut results: Vec<_> = formats
+        .iter()
+        .map(|&format| match read_all(format, input) {
+            Ok(records) => {
+                let (confidence, details) = judge(format, input, &records, json_parses);
+                (format, confidence, details)
+            }
+            Err(e) => {
+                let chain: Vec<_> = <dyn failure::Fail>::iter_chain(&e)
+                    .map(|e| summarize_error(&e.to_string()))
+                    .collect();
+                (format, Confidence::No, chain.join(": "))
+            }
+        })
+        .collect();
+    results.sort_by_key(|&(_, confidence, _)| cmp::Reverse(confidence));
+    results
+}
+
+/// Puts an error message on a single line.  Some messages show the input around the error on
+/// later lines, which is left out, but the explanation at the end is kept.
+fn summarize_error(message: &str) -> String {
+    let mut lines = message.lines().map(str::trim).filter(|l| !l.is_empty());
+    let first = lines.next().unwrap_or_default();
+    match lines.next_back() {
+        Some(last) => format!("{} ({})", first, last),
+        None => first.to_owned(),
+    }
+}
+
+/// Reads all records of the input in the format, with the default options.
+fn read_all(format: InputFormat, input: &[u8]) -> rq::error::Result<Vec<rq::value::Value>> {
+    use structopt::StructOpt;
+
+    let args =
+        Options::from_iter_safe(&["rq"]).map_err(|e| rq::error::Error::Message(e.message))?;
+    let mut source = open_source(&args, format, input)?;
+    let mut records = Vec::new();
+    while let Some(record) = source.read()? {
+        records.push(record);
+    }
+    Ok(records)
+}
+
+/// Judges how plausible it is that the input is in a format that it could be read in.  Many
+/// formats accept almost anything, like any text being a single YAML string or any bytes being
+/// small MessagePack integers, so those get a low confidence.
+fn judge(
+    format: InputFormat,
+    input: &[u8],
+    records: &[rq::value::Value],
+    json_parses: bool,
+) -> (Confidence, String) {
+    use rq::value::Value;
+
+    let count = match records.len() {
+        1 => "1 record".to_owned(),
+        n => format!("{} records", n),
+    };
+    let is_text = str::from_utf8(input).is_ok();
+    if records.is_empty() {
+        return (Confidence::No, "no records found".to_owned());
+    }
+    match format {
+        InputFormat::Json
+        | InputFormat::Arrow
+        | InputFormat::Avro
+        | InputFormat::Bson
+        | InputFormat::Evtx
+        | InputFormat::GitLog
+        | InputFormat::Image
+        | InputFormat::Lockfile
+        | InputFormat::Log4jXml
+        | InputFormat::Midi
+        | InputFormat::OciImage
+        | InputFormat::Parquet
+        | InputFormat::Pdf
+        | InputFormat::PropertiesXml
+        | InputFormat::Registry
+        | InputFormat::Riff
+        | InputFormat::Smile
+        | InputFormat::Sqlite
+        | InputFormat::X509
+        | InputFormat::Xlsx
+        | InputFormat::Xml => (Confidence::High, count),
+        // MP3 files without tags are only recognized by the sync bits of their first frame
+        InputFormat::AudioTags => match records {
+            [record]
+                if record.get("format").and_then(Value::as_str) == Some("MP3")
+                    && record.get("tags") == Some(&Value::Map(Vec::new()))
+                    && record.get("pictures") == Some(&Value::Sequence(Vec::new())) =>
+            {
+                (Confidence::Low, "an MP3 file without tags".to_owned())
+            }
+            _ => (Confidence::High, count),
+        },
+        // Known key types have their size decoded from the key itself
+        InputFormat::KnownHosts | InputFormat::AuthorizedKeys => {
+            if records
+                .iter()
+                .all(|r| r.get("bits").is_some_and(|b| *b != Value::Unit))
+            {
+                (Confidence::High, format!("{} of known key types", count))
+            } else {
+                (Confidence::Low, format!("{} of unknown key types", count))
+            }
+        }
+        InputFormat::ZoneFile => {
+            let has_directives = str::from_utf8(input).is_ok_and(|text| {
+                text.lines()
+                    .any(|l| l.starts_with("$ORIGIN") || l.starts_with("$TTL"))
+            });
+            let has_soa = records
+                .iter()
+                .any(|r| r.get("type").and_then(Value::as_str) == Some("SOA"));
+            if has_directives || has_soa {
+                (Confidence::High, count)
+            } else {
+                (
+                    Confidence::Medium,
+                    format!("{} without an SOA record", count),
+                )
+            }
+        }
+        InputFormat::Yaml => match records {
+            [Value::String(_)] => (Confidence::Low, "a single string".to_owned()),
+            _ if json_parses => (Confidence::Medium, format!("{} (JSON is also YAML)", count)),
+            _ => (Confidence::High, count),
+        },
+        InputFormat::Jsonc if json_parses => (
+            Confidence::Medium,
+            format!("{} (JSON is also JSONC)", count),
+        ),
+        InputFormat::Jsonc => (Confidence::High, count),
+        InputFormat::Ion if !is_text => (Confidence::High, format!("{} of binary Ion", count)),
+        // Any words and numbers are valid Ion text and EDN, as a series of symbols and numbers,
+        // but actual data is in structures
+        InputFormat::Ion | InputFormat::Edn if !records.iter().all(is_structure) => {
+            (Confidence::Low, format!("{} of bare values", count))
+        }
+        InputFormat::Ion if json_parses => {
+            (Confidence::Medium, format!("{} (JSON is also Ion)", count))
+        }
+        InputFormat::Edn if json_parses => {
+            (Confidence::Medium, format!("{} (JSON is also EDN)", count))
+        }
+        InputFormat::Ion | InputFormat::Edn => (Confidence::High, count),
+        InputFormat::Toml => match records {
+            [Value::Map(entries)] if !entries.is_empty() => (Confidence::High, count),
+            _ => (Confidence::Low, "an empty table".to_owned()),
+        },
+        InputFormat::Hcl => match records {
+            [Value::Map(entries)] if !entries.is_empty() => (Confidence::High, count),
+            _ => (Confidence::Low, "an empty body".to_owned()),
+        },
+        // Environment variables are usually upper case, unlike the keys of other formats
+        InputFormat::Dotenv => match records {
+            [Value::Map(entries)]
+                if !entries.is_empty()
+                    && entries.iter().all(|(k, _)| {
+                        k.as_str().is_some_and(|k| {
+                            k.chars()
+                                .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_')
+                        })
+                    }) =>
+            {
+                (Confidence::High, format!("{} variables", entries.len()))
+            }
+            [Value::Map(entries)] if !entries.is_empty() => (
+                Confidence::Medium,
+                format!("{} variables, not all in upper case", entries.len()),
+            ),
+            _ => (Confidence::Low, "no variables".to_owned()),
+        },
+        // Any words are valid logfmt, as keys without values, and a single pair on each line is
+        // also a dotenv file
+        InputFormat::Logfmt if records.iter().all(|r| logfmt_pairs(r) > 0) => {
+            if records.iter().any(|r| logfmt_pairs(r) > 1) {
+                (Confidence::High, format!("{} of key=value pairs", count))
+            } else {
+                (
+                    Confidence::Medium,
+                    format!("{} of single key=value pairs", count),
+                )
+            }
+        }
+        InputFormat::Logfmt => (Confidence::Low, format!("{} of bare keys", count)),
+        // Lines of words are nodes with arguments, but children and properties are rare elsewhere
+        InputFormat::Kdl
+            if records
+                .iter()
+                .any(|r| r.get(".children").is_some() || r.get(".props").is_some()) =>
+        {
+            (Confidence::Medium, format!("{} of nodes", count))
+        }
+        InputFormat::Kdl => (Confidence::Low, format!("{} of bare nodes", count)),
+        InputFormat::Csv => {
+            let fields = match records.first() {
+                Some(Value::Sequence(fields)) => fields.len(),
+                _ => 0,
+            };
+            let details = format!("{} with {} fields", count, fields);
+            if records.len() > 1 && fields > 1 {
+                (Confidence::Medium, details)
+            } else {
+                (Confidence::Low, details)
+            }
+        }
+        InputFormat::Bencode
+        | InputFormat::Cbor
+        | InputFormat::MessagePack
+        | InputFormat::Ubjson
+            if is_text =>
+        {
+            (
+                Confidence::Low,
+                format!("{} (but the input is text)", count),
+            )
+        }
+        InputFormat::Bencode
+        | InputFormat::Cbor
+        | InputFormat::MessagePack
+        | InputFormat::Ubjson => (Confidence::Medium, count),
+        // Certificates and keys are also ASN.1, which is the more specific format to prefer
+        InputFormat::Asn1 => (Confidence::Medium, count),
+        // Text is rarely valid wire format, but short binary input often is by accident
+        InputFormat::ProtobufRaw if is_text => (
+            Confidence::Low,
+            format!("{} (but the input is text)", count),
+        ),
+        InputFormat::ProtobufRaw => (Confidence::Medium, count),
+        InputFormat::Raw => (Confidence::Low, format!("{} of text", count)),
+    }
+}
+
+/// Whether a top-level record of Ion text or EDN is a structure rather than a scalar.
+fn is_structure(record: &rq::value::Value) -> bool {
+    match record {
+        rq::value::Value::Sequence(_) => true,
+        rq::value::Value::Map(_) => !["$symbol", "$keyword", "$bigint", "$decimal", "$timestamp"]
+            .iter()
+            .any(|key| record.get(key).is_some()),
+        _ => false,
+    }
+}
+
+/// How many keys of a logfmt record have values.
+fn logfmt_pairs(record: &rq::value::Value) -> usize {
+    match record {
+        rq::value::Value::Map(entries) => entries
+            .iter()
+            .filter(|(_, v)| *v != rq::value::Value::Bool(true))
+            .count(),
+        _ => 0,
+    }
+}
+
+/// Reads the input in the format, and runs the conversion.
+fn run_format<R>(
+    args: &Options,
+    input: R,
+    format: InputFormat,
+    description: &str,
+) -> rq::error::Result<()>
+where
+    R: io::BufRead,
+{
+    if format == InputFormat::Csv && env::args().skip(1).any(|v| v == "-v") && !has_ran_cmd("help")?
+    {
+        warn!("You started rq -v, which puts it in CSV input mode.");
+        warn!("It's now waiting for CSV input, which might not be what you wanted.");
+        warn!(
+            "Specify --input-csv explicitly or run rq --help once to suppress this \
+             warning."
+        );
+    }
+    if format == InputFormat::Json
+        && !args.flag_input_json
+        && args.flag_input_mime.is_none()
+        && !has_ran_cmd("help")?
+    {
+        warn!("You started rq without any input flags, which puts it in JSON input mode.");
+        warn!("It's now waiting for JSON input, which might not be what you wanted.");
+        warn!(
+            "Specify (-j|--input-json) explicitly or run rq --help once to suppress this \
+             warning."
+        );
+    }
+    let source = open_source(args, format, input)?;
+    run_source(args, source, description)
+}
+
+/// The input format selected by the input flags, falling back to `--input-mime` and then JSON.
+fn input_format(args: &Options) -> InputFormat {
+    if args.flag_input_avro {
+        InputFormat::Avro
+    } else if args.flag_input_bencode {
+        InputFormat::Bencode
+    } else if args.flag_input_bson {
+        InputFormat::Bson
+    } else if args.flag_input_ubjson {
+        InputFormat::Ubjson
+    } else if args.flag_input_cbor {
+        InputFormat::Cbor
+    } else if args.flag_input_ion {
+        InputFormat::Ion
+    } else if args.flag_input_message_pack {
+        InputFormat::MessagePack
+    } else if args.flag_input_toml {
+        InputFormat::Toml
+    } else if args.flag_input_yaml {
+        InputFormat::Yaml
+    } else if args.flag_input_smile {
+        InputFormat::Smile
+    } else if args.flag_input_xml {
+        InputFormat::Xml
+    } else if args.flag_input_properties_xml {
+        InputFormat::PropertiesXml
+    } else if args.flag_input_log4j_xml {
+        InputFormat::Log4jXml
+    } else if args.flag_input_protobuf_raw {
+        InputFormat::ProtobufRaw
+    } else if args.flag_input_parquet {
+        InputFormat::Parquet
+    } else if args.flag_input_xlsx {
+        InputFormat::Xlsx
+    } else if args.flag_input_sqlite {
+        InputFormat::Sqlite
+    } else if args.flag_input_arrow {
+        InputFormat::Arrow
+    } else if args.flag_input_lockfile {
+        InputFormat::Lockfile
+    } else if args.flag_input_git_log {
+        InputFormat::GitLog
+    } else if args.flag_input_oci_image {
+        InputFormat::OciImage
+    } else if args.flag_input_zone_file {
+        InputFormat::ZoneFile
+    } else if args.flag_input_known_hosts {
+        InputFormat::KnownHosts
+    } else if args.flag_input_authorized_keys {
+        InputFormat::AuthorizedKeys
+    } else if args.flag_input_dotenv {
+        InputFormat::Dotenv
+    } else if args.flag_input_logfmt {
+        InputFormat::Logfmt
+    } else if args.flag_input_hcl {
+        InputFormat::Hcl
+    } else if args.flag_input_x509 {
+        InputFormat::X509
+    } else if args.flag_input_asn1 {
+        InputFormat::Asn1
+    } else if args.flag_input_kdl {
+        InputFormat::Kdl
+    } else if args.flag_input_riff {
+        InputFormat::Riff
+    } else if args.flag_input_midi {
+        InputFormat::Midi
+    } else if args.flag_input_edn {
+        InputFormat::Edn
+    } else if args.flag_input_image {
+        InputFormat::Image
+    } else if args.flag_input_audio_tags {
+        InputFormat::AudioTags
+    } else if args.flag_input_pdf {
+        InputFormat::Pdf
+    } else if args.flag_input_registry {
+        InputFormat::Registry
+    } else if args.flag_input_evtx {
+        InputFormat::Evtx
+    } else if args.flag_input_raw {
+        InputFormat::Raw
+    } else if args.flag_input_csv {
+        InputFormat::Csv
+    } else if args.flag_input_jsonc {
+        InputFormat::Jsonc
+    } else if args.flag_input_json {
+        InputFormat::Json
     } else {
-        writeln!(
-            formatter,
-            "[{}] [{}] {}",
-            record.level(),
-            record.module_path().unwrap_or("<unknown>"),
-            record.args()
+        args.flag_input_mime.unwrap_or(InputFormat::Json)
+    }
+}
+
+/// The output format selected by the output flags, falling back to JSON.
+fn output_format(args: &Options) -> OutputFormat {
+    if args.flag_output_protobuf.is_some() {
+        OutputFormat::Protobuf
+    } else if args.flag_output_textproto.is_some() {
+        OutputFormat::Textproto
+    } else if args.flag_output_thrift.is_some() {
+        OutputFormat::Thrift
+    } else if args.flag_output_sqlite.is_some() {
+        OutputFormat::Sqlite
+    } else if args.flag_output_audio_tags {
+        OutputFormat::AudioTags
+    } else if args.flag_output_avro.is_some() {
+        OutputFormat::Avro
+    } else if args.flag_output_bencode {
+        OutputFormat::Bencode
+    } else if args.flag_output_bson {
+        OutputFormat::Bson
+    } else if args.flag_output_cbor {
+        OutputFormat::Cbor
+    } else if args.flag_output_ion {
+        OutputFormat::Ion
+    } else if args.flag_output_ion_binary {
+        OutputFormat::IonBinary
+    } else if args.flag_output_edn {
+        OutputFormat::Edn
+    } else if args.flag_output_ubjson {
+        OutputFormat::Ubjson
+    } else if args.flag_output_message_pack {
+        OutputFormat::MessagePack
+    } else if args.flag_output_toml {
+        OutputFormat::Toml
+    } else if args.flag_output_dotenv {
+        OutputFormat::Dotenv
+    } else if args.flag_output_logfmt {
+        OutputFormat::Logfmt
+    } else if args.flag_output_kdl {
+        OutputFormat::Kdl
+    } else if args.flag_output_yaml {
+        OutputFormat::Yaml
+    } else if args.flag_output_smile {
+        OutputFormat::Smile
+    } else if args.flag_output_xml {
+        OutputFormat::Xml
+    } else if args.flag_output_parquet {
+        OutputFormat::Parquet
+    } else if args.flag_output_arrow {
+        OutputFormat::Arrow
+    } else if args.flag_output_raw {
+        OutputFormat::Raw
+    } else if args.flag_output_csv {
+        OutputFormat::Csv
+    } else {
+        OutputFormat::Json
+    }
+}
+
+/// Runs the conversion from the source, which reads from the described input.  With
+/// `--explain`, the pipeline is described before it runs, and statistics are printed after.
+fn run_source<'a, I>(args: &Options, source: I, input: &str) -> rq::error::Result<()>
+where
+    I: rq::value::Source + 'a,
+{
+    let seed = args.flag_seed.unwrap_or_else(|| {
+        let seed = rq::transform::random_seed();
+        debug!("Using random seed {}", seed);
+        seed
+    });
+
+    if let Some(Subcmd::Find {
+        ref pattern,
+        keys,
+        strings,
+        with_values,
+    }) = args.subcmd
+    {
+        let finder = rq::find::Finder::new(regex::Regex::new(pattern)?, !strings, !keys);
+        return find(transform(args, source, seed, None)?, &finder, with_values);
+    }
+
+    if !args.flag_explain {
+        return write_output(args, transform(args, source, seed, None)?, seed, None);
+    }
+
+    eprintln!("Pipeline:");
+    eprintln!("  source:     {}", input);
+
+    let read = rc::Rc::new(rq::stats::Stage::default());
+    let write = rc::Rc::new(rq::stats::Stage::default());
+    let start = time::Instant::now();
+    let mut stages = Vec::new();
+    let source = rq::stats::source(source, read.clone());
+    let source = transform(args, source, seed, Some(&mut stages))?;
+    eprintln!("  transforms: {}", describe_transforms(&stages));
+    eprintln!("  sink:       {}", describe_output(args));
+    let result = write_output(args, source, seed, Some(&write));
+
+    eprintln!("Statistics:");
+    eprintln!(
+        "  source:     {} records in {:?}",
+        read.records(),
+        read.elapsed()
+    );
+    // Reading from a stage also reads from the stages before it, whose time is taken out
+    let mut upstream = read.elapsed();
+    for (name, stage) in &stages {
+        eprintln!(
+            "  transform:  {}: {} records in {:?}",
+            name,
+            stage.records(),
+            stage.elapsed().saturating_sub(upstream)
+        );
+        upstream = stage.elapsed();
+    }
+    eprintln!(
+        "  sink:       {} records in {:?}",
+        write.records(),
+        write.elapsed()
+    );
+    eprintln!("  total:      {:?}", start.elapsed());
+    result
+}
+
+/// Prints the paths of all matches in the records, prefixed with the record number.
+fn find<I>(mut source: I, finder: &rq::find::Finder, with_values: bool) -> rq::error::Result<()>
+where
+    I: rq::value::Source,
+{
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    let mut index = 0usize;
+    while let Some(record) = source.read()? {
+        let mut base = rq::value::path::Path::root();
+        base.push(index.to_string());
+        for found in finder.find(&record, base) {
+            if with_values {
+                writeln!(stdout, "{}\t{}", found.path.to_pointer(), found.value)?;
+            } else {
+                writeln!(stdout, "{}", found.path.to_pointer())?;
+            }
+        }
+        index += 1;
+    }
+    Ok(())
+}
+
+/// Stacks the transform stages that were asked for on top of the source.  With `stages`, every
+/// stage counts its records and time in a `Stage` that is added to it along with the stage's
+/// name, in the order that they are applied.
+fn transform<'a, I>(
+    args: &Options,
+    source: I,
+    seed: u64,
+    mut stages: Option<&mut Vec<(String, rc::Rc<rq::stats::Stage>)>>,
+) -> rq::error::Result<Box<dyn rq::value::Source + 'a>>
+where
+    I: rq::value::Source + 'a,
+{
+    let mut stage = |name: String,
+                     source: Box<dyn rq::value::Source + 'a>|
+     -> Box<dyn rq::value::Source + 'a> {
+        match stages {
+            Some(ref mut stages) => {
+                let stage = rc::Rc::new(rq::stats::Stage::default());
+                stages.push((name, stage.clone()));
+                Box::new(rq::stats::source(source, stage))
+            }
+            None => source,
+        }
+    };
+    let mut source: Box<dyn rq::value::Source + 'a> = Box::new(source);
+    if !args.flag_decompress_field.is_empty() {
+        let fields = args.flag_decompress_field.clone();
+        let names: Vec<_> = fields.iter().map(ToString::to_string).collect();
+        source = stage(
+            format!("decompress {}", names.join(", ")),
+            Box::new(rq::transform::decompress::source(source, fields)),
+        );
+    }
+    if args.flag_aws_logs {
+        source = stage(
+            "unwrap AWS log envelopes".to_owned(),
+            Box::new(rq::transform::aws_logs::source(source)),
+        );
+    }
+    if args.flag_k8s_split || !args.flag_k8s_kind.is_empty() {
+        let kinds = args.flag_k8s_kind.clone();
+        let split = args.flag_k8s_split;
+        let action = if split { "split" } else { "read" };
+        let name = if kinds.is_empty() {
+            format!("{} Kubernetes resources", action)
+        } else {
+            let names: Vec<_> = kinds.iter().map(ToString::to_string).collect();
+            format!("{} Kubernetes {} resources", action, names.join(", "))
+        };
+        source = stage(
+            name,
+            Box::new(rq::transform::kubernetes::source(source, kinds, split)),
+        );
+    }
+    if args.flag_k8s_join {
+        source = stage(
+            "join Kubernetes resources".to_owned(),
+            Box::new(rq::transform::kubernetes::join(source)),
+        );
+    }
+    if let Some(ref filters) = args.flag_tf_resources {
+        let name = match filters {
+            Some(filters) => format!("list Terraform resources {}", filters),
+            None => "list Terraform resources".to_owned(),
+        };
+        let filters = filters.clone().unwrap_or_default();
+        source = stage(
+            name,
+            Box::new(rq::transform::terraform::source(source, filters)),
+        );
+    }
+    if args.flag_har {
+        let bodies = args.flag_har_bodies;
+        let name = if bodies {
+            "split HAR entries, decoding bodies"
+        } else {
+            "split HAR entries"
+        };
+        source = stage(
+            name.to_owned(),
+            Box::new(rq::transform::har::source(source, bodies)),
+        );
+    }
+    if !args.flag_merge.is_empty() {
+        let files: Vec<_> = args
+            .flag_merge
+            .iter()
+            .map(|(file, _)| file.display().to_string())
+            .collect();
+        let patches = read_patches(args)?;
+        let merger = rq::transform::merge::Merger::new(
+            args.flag_merge_strategy,
+            args.flag_merge_key.clone(),
+        );
+        source = stage(
+            format!("{} merge {}", args.flag_merge_strategy, files.join(", ")),
+            Box::new(rq::transform::merge::source(source, patches, merger)),
+        );
+    }
+    if args.flag_rpc {
+        source = stage(
+            "recognize RPC messages".to_owned(),
+            Box::new(rq::transform::rpc::source(source)),
+        );
+    }
+    if let Some(ref rules) = args.flag_normalize {
+        source = stage(
+            format!("normalize {}", rules),
+            Box::new(rq::transform::normalize::source(source, rules.clone())),
+        );
+    }
+    if args.flag_from_columns {
+        source = stage(
+            "split columns into records".to_owned(),
+            Box::new(rq::transform::transpose::from_columns(source)),
+        );
+    }
+    if args.flag_transpose {
+        source = stage(
+            "transpose".to_owned(),
+            Box::new(rq::transform::transpose::source(source)),
+        );
+    }
+    if let Some(ref path) = args.flag_explode {
+        source = stage(
+            format!("explode {}", path),
+            Box::new(rq::transform::explode::source(source, path.clone())),
+        );
+    }
+    if let (Some(key), Some(into)) = (&args.flag_nest_by, &args.flag_into) {
+        let grouping = if args.flag_nest_all {
+            "all"
+        } else {
+            "consecutive"
+        };
+        source = stage(
+            format!("nest {} records by {} into {}", grouping, key, into),
+            Box::new(rq::transform::nest::source(
+                source,
+                key.clone(),
+                into.clone(),
+                args.flag_nest_all,
+            )),
+        );
+    }
+    if let Some(ref regex) = args.flag_grep {
+        let paths = args.flag_grep_path.clone();
+        let name = if paths.is_empty() {
+            format!("grep {}", regex)
+        } else {
+            let names: Vec<_> = paths.iter().map(ToString::to_string).collect();
+            format!("grep {} in {}", regex, names.join(", "))
+        };
+        source = stage(
+            name,
+            Box::new(rq::transform::grep::source(source, regex.clone(), paths)),
+        );
+    }
+    if !args.flag_where_type.is_empty() {
+        let checks = args.flag_where_type.clone();
+        let names: Vec<_> = checks.iter().map(ToString::to_string).collect();
+        source = stage(
+            format!("where type {}", names.join(", ")),
+            Box::new(rq::transform::types::source(source, checks)),
+        );
+    }
+    if args.flag_drift {
+        source = stage(
+            "report structure drift".to_owned(),
+            Box::new(rq::transform::drift::source(source)),
+        );
+    }
+    let look_behind = look_behind_specs(args);
+    if !look_behind.is_empty() {
+        let names: Vec<_> = look_behind.iter().map(ToString::to_string).collect();
+        source = stage(
+            names.join(", "),
+            Box::new(rq::transform::look_behind::source(source, look_behind)),
+        );
+    }
+    if let Some(size) = args.flag_sample {
+        source = stage(
+            format!("sample of {} records (seed {})", size, seed),
+            Box::new(rq::transform::sample::source(source, size, seed)),
+        );
+    }
+    if !args.flag_fake.is_empty() {
+        let fields: Vec<_> = args.flag_fake.iter().flatten().cloned().collect();
+        let names: Vec<_> = fields.iter().map(ToString::to_string).collect();
+        source = stage(
+            format!("fake {} (seed {})", names.join(","), seed),
+            Box::new(rq::transform::fake::source(source, fields, seed)),
+        );
+    }
+    if args.flag_shuffle {
+        source = stage(
+            format!("shuffle (seed {})", seed),
+            Box::new(rq::transform::shuffle::source(source, seed)),
+        );
+    }
+    if let Some(ref spec) = args.flag_histogram {
+        source = stage(
+            format!("histogram of {}", spec),
+            Box::new(rq::transform::histogram::source(source, spec.clone())),
+        );
+    }
+    if let (Some(k), Some(path)) = (args.flag_top_k, &args.flag_by) {
+        source = stage(
+            format!("top {} values of {}", k, path),
+            Box::new(rq::transform::top_k::source(source, k, path.clone())),
+        );
+    }
+    if let Some(ref spec) = args.flag_quantiles {
+        source = stage(
+            format!("quantiles of {}", spec),
+            Box::new(rq::transform::quantiles::source(source, spec.clone())),
+        );
+    }
+    if let Some(ref spec) = args.flag_pivot {
+        source = stage(
+            format!("pivot {}", spec),
+            Box::new(rq::transform::pivot::source(source, spec.clone())),
+        );
+    }
+    if args.flag_to_columns {
+        source = stage(
+            "collect records into columns".to_owned(),
+            Box::new(rq::transform::transpose::to_columns(source)),
+        );
+    }
+    if let Some(ref path) = args.flag_conform_to {
+        let schema = read_avro_schema_from_file(path).inspect_err(|_| {
+            error!(
+                "--conform-to only supports Avro schemas, not JSON Schema or protobuf \
+                 descriptors; failed to read {}",
+                path.display()
+            );
+        })?;
+        let mut enums = rq::transform::conform::Enums {
+            ignore_case: args.flag_enum_ignore_case,
+            ..Default::default()
+        };
+        if let Some(ref path) = args.flag_enum_aliases {
+            enums.aliases = serde_yaml::from_reader(fs::File::open(path)?)?;
+        }
+        source = stage(
+            format!("conform to {}", path.display()),
+            Box::new(rq::transform::conform::source(source, schema, enums)),
+        );
+    }
+    if let Some(ref path) = args.flag_dedup_key {
+        let (name, existing) = if args.flag_skip_existing {
+            (
+                format!("drop duplicates and existing records by {}", path),
+                existing_keys(args, path)?,
+            )
+        } else {
+            (
+                format!("drop duplicates by {}", path),
+                collections::HashSet::new(),
+            )
+        };
+        source = stage(
+            name,
+            Box::new(rq::transform::dedup::source(source, path.clone(), existing)),
+        );
+    }
+    Ok(source)
+}
+
+/// The keys of the records that are already in the output file, for `--skip-existing`.
+fn existing_keys(
+    args: &Options,
+    path: &rq::value::path::Path,
+) -> rq::error::Result<collections::HashSet<String>> {
+    let output = args.flag_output.as_ref().ok_or_else(|| {
+        rq::error::Error::Message(
+            "--skip-existing requires an output file (see --output)".to_owned(),
+        )
+    })?;
+    let format = existing_output_format(args).ok_or_else(|| {
+        rq::error::Error::Message(format!(
+            "--skip-existing can't read back {} output",
+            describe_output(args)
+        ))
+    })?;
+    let file = match fs::File::open(output) {
+        Ok(file) => file,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(collections::HashSet::new()),
+        Err(e) => return Err(rq::error::Error::from(e)),
+    };
+    if file.metadata()?.len() == 0 {
+        return Ok(collections::HashSet::new());
+    }
+    let (_, input) = rq::compression::decoder(input_file(args, file)?)?;
+    let source = open_source(args, format, input)?;
+    let keys = rq::transform::dedup::keys(source, path)?;
+    debug!("Found {} existing keys in {}", keys.len(), output.display());
+    Ok(keys)
+}
+
+/// Whether the options select an output format.
+type IsSelected = fn(&Options) -> bool;
+
+/// The output formats that can be appended to, with their names, whether the options select
+/// them, and the input format that reads back what they write.  These are plain streams of
+/// records with no headers or trailers, and Avro, whose blocks are added to the existing file.
+const APPENDABLE_OUTPUT_FORMATS: &[(&str, IsSelected, Option<InputFormat>)] = &[
+    ("JSON", is_json_output, Some(InputFormat::Json)),
+    ("CSV", |args| args.flag_output_csv, Some(InputFormat::Csv)),
+    ("raw", |args| args.flag_output_raw, Some(InputFormat::Raw)),
+    (
+        "logfmt",
+        |args| args.flag_output_logfmt,
+        Some(InputFormat::Logfmt),
+    ),
+    (
+        "CBOR",
+        |args| args.flag_output_cbor,
+        Some(InputFormat::Cbor),
+    ),
+    (
+        "MessagePack",
+        |args| args.flag_output_message_pack,
+        Some(InputFormat::MessagePack),
+    ),
+    (
+        "BSON",
+        |args| args.flag_output_bson,
+        Some(InputFormat::Bson),
+    ),
+    (
+        "UBJSON",
+        |args| args.flag_output_ubjson,
+        Some(InputFormat::Ubjson),
+    ),
+    (
+        "bencode",
+        |args| args.flag_output_bencode,
+        Some(InputFormat::Bencode),
+    ),
+    ("EDN", |args| args.flag_output_edn, Some(InputFormat::Edn)),
+    (
+        "Ion",
+        |args| args.flag_output_ion || args.flag_output_ion_binary,
+        Some(InputFormat::Ion),
+    ),
+    // Thrift structs can't be read back without the IDL
+    ("Thrift", |args| args.flag_output_thrift.is_some(), None),
+    (
+        "Avro",
+        |args| args.flag_output_avro.is_some(),
+        Some(InputFormat::Avro),
+    ),
+];
+
+/// Whether the output is JSON, which is what's written when no other output format is selected.
+fn is_json_output(args: &Options) -> bool {
+    output_format(args) == OutputFormat::Json
+}
+
+fn is_appendable(args: &Options) -> bool {
+    APPENDABLE_OUTPUT_FORMATS
+        .iter()
+        .any(|&(_, selected, _)| selected(args))
+}
+
+/// The input format that reads back what the output format writes, for the formats that can
+/// be appended to.
+fn existing_output_format(args: &Options) -> Option<InputFormat> {
+    APPENDABLE_OUTPUT_FORMATS
+        .iter()
+        .find(|&&(_, selected, _)| selected(args))
+        .and_then(|&(_, _, format)| format)
+}
+
+/// The fields computed from the previous record, in the order they are added.
+fn look_behind_specs(args: &Options) -> Vec<rq::transform::look_behind::Spec> {
+    let mut specs = args.flag_delta.clone();
+    specs.extend(args.flag_elapsed.iter().cloned());
+    specs.extend(args.flag_session.iter().cloned());
+    specs
+}
+
+/// Describes the transform stages that `transform` added, in the order that it applies them.
+fn describe_transforms(stages: &[(String, rc::Rc<rq::stats::Stage>)]) -> String {
+    if stages.is_empty() {
+        "none".to_owned()
+    } else {
+        let names: Vec<_> = stages.iter().map(|(name, _)| name.as_str()).collect();
+        names.join(", ")
+    }
+}
+
+/// Describes where and how records will be written, for the output format that `open_sink`
+/// opens.
+fn describe_output(args: &Options) -> String {
+    let format = match output_format(args) {
+        OutputFormat::Protobuf => format!(
+            "protobuf message {}",
+            args.flag_output_protobuf.as_deref().unwrap_or_default()
+        ),
+        OutputFormat::Textproto => format!(
+            "protobuf text format message {}",
+            args.flag_output_textproto.as_deref().unwrap_or_default()
+        ),
+        OutputFormat::Thrift => format!(
+            "Thrift struct {} ({} protocol)",
+            args.flag_output_thrift.as_deref().unwrap_or_default(),
+            args.flag_thrift_protocol
+        ),
+        OutputFormat::Sqlite => format!(
+            "SQLite table {}",
+            args.flag_output_sqlite.as_deref().unwrap_or_default()
+        ),
+        OutputFormat::Avro => {
+            let codec = args.flag_codec.as_deref().unwrap_or("null");
+            format!("Avro ({} codec)", codec)
+        }
+        OutputFormat::Csv if csv_dialect(args).delimiter == b'\t' => "TSV".to_owned(),
+        OutputFormat::Json => {
+            let format = match (args.flag_format, &args.flag_output) {
+                (Some(format), _) => format,
+                (None, Some(_)) => Format::Compact,
+                (None, None) => infer_format(),
+            };
+            match format {
+                Format::Compact => "JSON (compact)".to_owned(),
+                Format::Readable => "JSON (readable)".to_owned(),
+                Format::Indented => "JSON (indented)".to_owned(),
+            }
+        }
+        format => format.name().to_owned(),
+    };
+
+    let format = match args.flag_output_newline {
+        Some(rq::output::Newline::Crlf) if is_text_output(args) => {
+            format!("{} with CRLF line endings", format)
+        }
+        _ => format,
+    };
+    let format = match args.flag_compress {
+        Some(codec) => match args.flag_compress_level {
+            Some(level) => format!("{}, {}-compressed at level {}", format, codec, level),
+            None => format!("{}, {}-compressed", format, codec),
+        },
+        None => format,
+    };
+    let routes: String = args
+        .flag_route
+        .iter()
+        .map(|route| format!(", routing {}", route))
+        .collect();
+    let destination = if let Some(ref path) = args.flag_output {
+        path.display().to_string()
+    } else if let Some(ref template) = args.flag_output_path_template {
+        format!("files at {}", template)
+    } else if let Some(ref split) = args.flag_split {
+        format!("split {}", split)
+    } else {
+        return format!("{} to stdout{}", format, routes);
+    };
+    let mut options = Vec::new();
+    if args.flag_append {
+        options.push("appending".to_owned());
+    } else if !args.flag_no_atomic
+        && args.flag_output_sqlite.is_none()
+        && !args.flag_output_audio_tags
+    {
+        options.push("atomic".to_owned());
+    }
+    if let Some(rotation) = args.flag_output_rotate {
+        options.push(format!("rotating at {}", rotation));
+    }
+    if options.is_empty() {
+        format!("{} to {}{}", format, destination, routes)
+    } else {
+        format!(
+            "{} to {} ({}){}",
+            format,
+            destination,
+            options.join(", "),
+            routes
         )
     }
-}
+}
+
+fn write_output<I>(
+    args: &Options,
+    source: I,
+    seed: u64,
+    stage: Option<&rc::Rc<rq::stats::Stage>>,
+) -> rq::error::Result<()>
+where
+    I: rq::value::Source,
+{
+    if args.flag_route.is_empty() {
+        return write_main_output(args, source, seed, stage);
+    }
+
+    let route_args = args
+        .flag_route
+        .iter()
+        .map(|route| route_options(args, route))
+        .collect::<rq::error::Result<Vec<_>>>()?;
+    let mut routes = Vec::new();
+    for (route, route_args) in args.flag_route.iter().zip(&route_args) {
+        if args.flag_append && !is_appendable(route_args) {
+            return Err(rq::error::Error::Message(format!(
+                "--append is not supported for the {} output of route {}",
+                route.format, route
+            )));
+        }
+        let output = OutputFiles {
+            args: route_args,
+            options: file_options(args),
+            format: args.flag_format.unwrap_or(Format::Compact),
+            avro_schema: None,
+            stage,
+        };
+        // Open all files up front, so that they exist even if they don't get any records
+        let (sink, pending, _) = output.open(&route.path)?;
+        routes.push(RouteOutput {
+            route,
+            sink,
+            pending,
+            records: 0,
+        });
+    }
+    let source = RoutedSource {
+        inner: source,
+        routes,
+    };
+    write_main_output(args, source, seed, stage)
+}
+
+/// The options for writing the output of a route, which are the same as for the regular output
+/// except for the output format.
+fn route_options(args: &Options, route: &rq::output::Route) -> rq::error::Result<Options> {
+    use structopt::StructOpt;
+
+    if !NAMED_OUTPUT_FORMATS
+        .iter()
+        .any(|&(name, _, _)| name == route.format)
+    {
+        let names: Vec<_> = NAMED_OUTPUT_FORMATS
+            .iter()
+            .map(|&(name, _, _)| name)
+            .collect();
+        return Err(rq::error::Error::Message(format!(
+            "unsupported output format {:?} for route {} (expected one of {})",
+            route.format,
+            route,
+            names.join(", ")
+        )));
+    }
+    let selected =
+        Options::from_iter_safe(&["rq".to_owned(), format!("--output-{}", route.format)])
+            .map_err(|e| rq::error::Error::Message(e.message))?;
+    // Take over every output format flag, so that only the route's format is selected
+    let mut options = args.clone();
+    options.flag_output_arrow = selected.flag_output_arrow;
+    options.flag_output_audio_tags = selected.flag_output_audio_tags;
+    options.flag_output_avro = selected.flag_output_avro;
+    options.flag_output_bencode = selected.flag_output_bencode;
+    options.flag_output_bson = selected.flag_output_bson;
+    options.flag_output_cbor = selected.flag_output_cbor;
+    options.flag_output_csv = selected.flag_output_csv;
+    options.flag_output_dotenv = selected.flag_output_dotenv;
+    options.flag_output_edn = selected.flag_output_edn;
+    options.flag_output_ion = selected.flag_output_ion;
+    options.flag_output_ion_binary = selected.flag_output_ion_binary;
+    options.flag_output_json = selected.flag_output_json;
+    options.flag_output_kdl = selected.flag_output_kdl;
+    options.flag_output_logfmt = selected.flag_output_logfmt;
+    options.flag_output_message_pack = selected.flag_output_message_pack;
+    options.flag_output_parquet = selected.flag_output_parquet;
+    options.flag_output_protobuf = selected.flag_output_protobuf;
+    options.flag_output_raw = selected.flag_output_raw;
+    options.flag_output_smile = selected.flag_output_smile;
+    options.flag_output_sqlite = selected.flag_output_sqlite;
+    options.flag_output_textproto = selected.flag_output_textproto;
+    options.flag_output_thrift = selected.flag_output_thrift;
+    options.flag_output_toml = selected.flag_output_toml;
+    options.flag_output_ubjson = selected.flag_output_ubjson;
+    options.flag_output_xml = selected.flag_output_xml;
+    options.flag_output_yaml = selected.flag_output_yaml;
+    Ok(options)
+}
+
+fn file_options(args: &Options) -> rq::output::FileOptions {
+    rq::output::FileOptions {
+        append: args.flag_append,
+        atomic: !args.flag_no_atomic,
+        mode: args.flag_output_mode,
+        owner: args.flag_output_owner,
+    }
+}
+
+/// An open output file of a route.
+struct RouteOutput<'a> {
+    route: &'a rq::output::Route,
+    sink: Box<dyn rq::value::Sink + 'a>,
+    pending: rq::output::Pending,
+    records: u64,
+}
+
+/// A source that writes the records that match a route to the route's output, and only yields
+/// the others.  The route outputs are finished once the input runs out.
+struct RoutedSource<'a, I> {
+    inner: I,
+    routes: Vec<RouteOutput<'a>>,
+}
+
+impl<I> rq::value::Source for RoutedSource<'_, I>
+where
+    I: rq::value::Source,
+{
+    fn read(&mut self) -> rq::error::Result<Option<rq::value::Value>> {
+        while let Some(record) = self.inner.read()? {
+            match self
+                .routes
+                .iter_mut()
+                .find(|output| output.route.condition.matches(&record))
+            {
+                Some(output) => {
+                    write_record(&mut *output.sink, record, output.records)?;
+                    output.records += 1;
+                }
+                None => return Ok(Some(record)),
+            }
+        }
+
+        for mut output in self.routes.drain(..) {
+            debug!(
+                "Routed {} records to {}",
+                output.records,
+                output.route.path.display()
+            );
+            finish_sink(&mut *output.sink)?;
+            drop(output.sink);
+            output.pending.commit()?;
+        }
+        Ok(None)
+    }
+
+    #[inline]
+    fn position(&self) -> Option<rq::value::Position> {
+        self.inner.position()
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, self.inner.size_hint().1)
+    }
+}
+
+/// Writes the records to stdout or to the output files, as selected by the output flags.
+fn write_main_output<I>(
+    args: &Options,
+    mut source: I,
+    seed: u64,
+    stage: Option<&rc::Rc<rq::stats::Stage>>,
+) -> rq::error::Result<()>
+where
+    I: rq::value::Source,
+{
+    let avro_schema = match args.flag_output_avro {
+        Some(ref schema_filename) => Some(read_avro_schema_from_file(path::Path::new(
+            schema_filename,
+        ))?),
+        None => None,
+    };
+
+    if args.flag_output.is_none()
+        && args.flag_output_path_template.is_none()
+        && args.flag_split.is_none()
+    {
+        if args.flag_output_rotate.is_some() {
+            return Err(rq::error::Error::Message(
+                "output rotation requires an output file (see --output)".to_owned(),
+            ));
+        }
+        let format = args.flag_format.unwrap_or_else(infer_format);
+        let sink = open_sink(
+            args,
+            format,
+            avro_schema.as_ref(),
+            None,
+            Box::new(io::stdout()),
+        )?;
+        let mut sink = timed(sink, stage);
+        let mut next = read_record(&mut source)?;
+        write_records(&mut source, &mut *sink, &mut next, &mut 0, |_| false)?;
+        return finish_sink(&mut *sink);
+    }
+
+    // Audio tags are written into the audio file itself, which is replaced as a whole
+    if let (true, Some(path)) = (args.flag_output_audio_tags, &args.flag_output) {
+        debug!("Writing tags to {:?}", path);
+        let sink: Box<dyn rq::value::Sink> = Box::new(rq::value::audio_tags::sink(path));
+        let mut sink = timed(sink, stage);
+        let mut next = read_record(&mut source)?;
+        write_records(&mut source, &mut *sink, &mut next, &mut 0, |_| false)?;
+        return finish_sink(&mut *sink);
+    }
+
+    // SQLite writes to the database file itself, and always adds to tables that exist already
+    if let (Some(table), Some(path)) = (&args.flag_output_sqlite, &args.flag_output) {
+        debug!("Writing output to table {} of {:?}", table, path);
+        let sink: Box<dyn rq::value::Sink> = Box::new(rq::value::sqlite::sink(path, table)?);
+        let mut sink = timed(sink, stage);
+        let mut next = read_record(&mut source)?;
+        write_records(&mut source, &mut *sink, &mut next, &mut 0, |_| false)?;
+        return finish_sink(&mut *sink);
+    }
+
+    if args.flag_append {
+        if args.flag_output_rotate.is_some() {
+            return Err(rq::error::Error::Message(
+                "--append can't be combined with --output-rotate".to_owned(),
+            ));
+        }
+        if !is_appendable(args) {
+            let names: Vec<_> = APPENDABLE_OUTPUT_FORMATS
+                .iter()
+                .map(|&(name, _, _)| name)
+                .collect();
+            let (last, names) = names.split_last().unwrap();
+            return Err(rq::error::Error::Message(format!(
+                "--append is only supported for {} and {} output",
+                names.join(", "),
+                last
+            )));
+        }
+    }
+
+    // Colors don't belong in files, so only use them if explicitly asked for
+    let format = args.flag_format.unwrap_or(Format::Compact);
+    let output = OutputFiles {
+        args,
+        options: file_options(args),
+        format,
+        avro_schema: avro_schema.as_ref(),
+        stage,
+    };
+
+    if let Some(ref output_path) = args.flag_output {
+        write_files(&output, source, output_path)
+    } else if let Some(ref template) = args.flag_output_path_template {
+        write_templated_files(&output, source, template)
+    } else if let Some(ref split) = args.flag_split {
+        write_split_files(&output, source, split, seed)
+    } else {
+        unreachable!()
+    }
+}
+
+/// Everything needed to open output files with sinks.
+struct OutputFiles<'a> {
+    args: &'a Options,
+    options: rq::output::FileOptions,
+    format: Format,
+    avro_schema: Option<&'a avro_rs::Schema>,
+    stage: Option<&'a rc::Rc<rq::stats::Stage>>,
+}
+
+impl<'a> OutputFiles<'a> {
+    /// Opens the output file at the path, along with a sink that writes to it.
+    fn open(
+        &self,
+        path: &path::Path,
+    ) -> rq::error::Result<(
+        Box<dyn rq::value::Sink + 'a>,
+        rq::output::Pending,
+        rq::output::ByteCount,
+    )> {
+        self.open_with(path, &self.options)
+    }
+
+    /// Opens the output file at the path like `open`, but in a different way.
+    fn open_with(
+        &self,
+        path: &path::Path,
+        options: &rq::output::FileOptions,
+    ) -> rq::error::Result<(
+        Box<dyn rq::value::Sink + 'a>,
+        rq::output::Pending,
+        rq::output::ByteCount,
+    )> {
+        debug!("Writing output to {:?}", path);
+        let avro_header = if options.append && self.avro_schema.is_some() {
+            read_existing_avro_header(path)?
+        } else {
+            None
+        };
+        let (file, pending) = rq::output::open(path, options)?;
+        let (file, bytes) = rq::output::counting(io::BufWriter::new(file));
+        let sink = open_sink(
+            self.args,
+            self.format,
+            self.avro_schema,
+            avro_header,
+            Box::new(file),
+        )?;
+        Ok((timed(sink, self.stage), pending, bytes))
+    }
+}
+
+/// Writes all records to the output file, or to numbered files when rotating.
+fn write_files<I>(
+    output: &OutputFiles,
+    mut source: I,
+    output_path: &path::Path,
+) -> rq::error::Result<()>
+where
+    I: rq::value::Source,
+{
+    let rotation = output.args.flag_output_rotate;
+    let mut next = read_record(&mut source)?;
+    let mut index = 0;
+    let mut written = 0;
+
+    loop {
+        let path = match rotation {
+            Some(_) => rq::output::numbered_path(output_path, index),
+            None => output_path.to_owned(),
+        };
+        let (mut sink, pending, bytes) = output.open(&path)?;
+        write_records(
+            &mut source,
+            &mut *sink,
+            &mut next,
+            &mut written,
+            |records| rotation.is_some_and(|r| r.is_full(records, bytes.get())),
+        )?;
+        finish_sink(&mut *sink)?;
+        drop(sink);
+        pending.commit()?;
+
+        if next.is_none() {
+            break;
+        }
+        index += 1;
+    }
+
+    Ok(())
+}
+
+/// Writes each record to one of the outputs of the split, picked at random.
+fn write_split_files<I>(
+    output: &OutputFiles,
+    mut source: I,
+    split: &rq::output::Split,
+    seed: u64,
+) -> rq::error::Result<()>
+where
+    I: rq::value::Source,
+{
+    // Open all files up front, so that they exist even if they don't get any records
+    let mut files = split
+        .paths()
+        .map(|path| {
+            let (sink, pending, _) = output.open(path)?;
+            Ok((sink, pending))
+        })
+        .collect::<rq::error::Result<Vec<_>>>()?;
+
+    let mut chooser = split.chooser(seed);
+    let mut records = 0;
+    while let Some(record) = read_record(&mut source)? {
+        write_record(&mut *files[chooser.choose()].0, record, records)?;
+        records += 1;
+    }
+
+    for (mut sink, pending) in files {
+        finish_sink(&mut *sink)?;
+        drop(sink);
+        pending.commit()?;
+    }
+    Ok(())
+}
+
+/// Writes each record to the file at the path that the template computes for it.  Files are
+/// kept open until the input runs out, and then all of them are finished, unless there are more
+/// than --max-open-files of them: then the least recently used file is finished early, and
+/// reopened to append to it if it gets more records.
+fn write_templated_files<I>(
+    output: &OutputFiles,
+    mut source: I,
+    template: &rq::output::PathTemplate,
+) -> rq::error::Result<()>
+where
+    I: rq::value::Source,
+{
+    let max_open = output.args.flag_max_open_files;
+    if max_open == 0 {
+        return Err(rq::error::Error::Message(
+            "--max-open-files must be at least 1".to_owned(),
+        ));
+    }
+    let reopen_options = rq::output::FileOptions {
+        append: true,
+        ..output.options.clone()
+    };
+
+    // The open files, with the number of the record that was last written to each
+    let mut files: collections::HashMap<
+        path::PathBuf,
+        (Box<dyn rq::value::Sink>, rq::output::Pending, u64),
+    > = collections::HashMap::new();
+    let mut opened = collections::HashSet::new();
+    let mut records = 0;
+    while let Some(record) = read_record(&mut source)? {
+        let path = template.render(&record)?;
+        if !files.contains_key(&path) {
+            if files.len() >= max_open {
+                if !is_appendable(output.args) {
+                    return Err(rq::error::Error::Message(format!(
+                        "--output-path-template needs more than {} open files (see \
+                         --max-open-files), but {} output can't be reopened to append to it",
+                        max_open,
+                        describe_output(output.args)
+                    )));
+                }
+                let least_recent = files
+                    .iter()
+                    .min_by_key(|&(_, &(_, _, used))| used)
+                    .map(|(path, _)| path.clone())
+                    .expect("there are open files");
+                debug!("Closing {:?} to stay within --max-open-files", least_recent);
+                let (mut sink, pending, _) = files.remove(&least_recent).unwrap();
+                finish_sink(&mut *sink)?;
+                drop(sink);
+                pending.commit()?;
+            }
+            let (sink, pending, _) = if opened.contains(&path) {
+                output.open_with(&path, &reopen_options)?
+            } else {
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                opened.insert(path.clone());
+                output.open(&path)?
+            };
+            files.insert(path.clone(), (sink, pending, records));
+        }
+        let (sink, _, used) = files.get_mut(&path).unwrap();
+        *used = records;
+        write_record(&mut **sink, record, records)?;
+        records += 1;
+    }
+
+    debug!("Finishing {} output files", files.len());
+    let mut files: Vec<_> = files.into_iter().collect();
+    files.sort_by(|(a, _), (b, _)| a.cmp(b));
+    for (_, (mut sink, pending, _)) in files {
+        finish_sink(&mut *sink)?;
+        drop(sink);
+        pending.commit()?;
+    }
+    Ok(())
+}
+
+/// Reads the header of the Avro file to append to, if there is one with any contents.
+fn read_existing_avro_header(
+    path: &path::Path,
+) -> rq::error::Result<Option<rq::value::avro::Header>> {
+    match fs::File::open(path) {
+        Ok(file) => {
+            if file.metadata()?.len() == 0 {
+                Ok(None)
+            } else {
+                let header = rq::value::avro::read_header(io::BufReader::new(file))?;
+                Ok(Some(header))
+            }
+        }
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(rq::error::Error::from(e)),
+    }
+}
+
+fn open_sink<'a>(
+    args: &Options,
+    format: Format,
+    avro_schema: Option<&'a avro_rs::Schema>,
+    avro_header: Option<rq::value::avro::Header>,
+    output: Box<dyn io::Write + 'a>,
+) -> rq::error::Result<Box<dyn rq::value::Sink + 'a>> {
+    // Line endings are converted before compressing, so the compressor wraps the output first
+    let output: Box<dyn io::Write + 'a> = match args.flag_compress {
+        Some(codec) => Box::new(rq::compression::encoder(
+            codec,
+            args.flag_compress_level,
+            output,
+        )?),
+        None => output,
+    };
+    let output: Box<dyn io::Write + 'a> = match args.flag_output_newline {
+        Some(rq::output::Newline::Crlf) if is_text_output(args) => {
+            Box::new(rq::output::crlf(output))
+        }
+        Some(rq::output::Newline::Crlf) => {
+            warn!("--output-newline has no effect on binary output formats");
+            output
+        }
+        _ => output,
+    };
+    match output_format(args) {
+        OutputFormat::Protobuf => Err(rq::error::Error::unimplemented(
+            "protobuf serialization".to_owned(),
+        )),
+        OutputFormat::Textproto => {
+            let paths = rq::config::Paths::new()?;
+            let descriptors = load_descriptors(&paths)?;
+            Ok(Box::new(rq::value::textproto::sink(
+                descriptors,
+                args.flag_output_textproto.as_deref().unwrap_or_default(),
+                output,
+            )?))
+        }
+        OutputFormat::Thrift => {
+            let idl = thrift_idl(args)?;
+            Ok(Box::new(rq::value::thrift::sink(
+                idl,
+                args.flag_output_thrift.as_deref().unwrap_or_default(),
+                args.flag_thrift_protocol,
+                output,
+            )?))
+        }
+        // These write to the output file themselves, see `write_main_output`
+        selected @ (OutputFormat::Sqlite | OutputFormat::AudioTags) => {
+            Err(rq::error::Error::Message(format!(
+                "{} output requires an output file (see --output)",
+                selected.name()
+            )))
+        }
+        OutputFormat::Avro => {
+            use std::str::FromStr;
+
+            let schema = avro_schema.ok_or_else(|| {
+                rq::error::Error::Message("Avro output requires a schema".to_owned())
+            })?;
+            let codec_string = if let Some(ref c) = args.flag_codec {
+                c.as_str()
+            } else {
+                "null"
+            };
+            let codec = if let Ok(v) = avro_rs::Codec::from_str(codec_string) {
+                v
+            } else {
+                return Err(rq::error::Error::Message(format!(
+                    "illegal Avro codec: {}",
+                    codec_string
+                )));
+            };
+            match avro_header {
+                Some(header) => Ok(Box::new(rq::value::avro::sink_append(
+                    schema, output, header,
+                )?)),
+                None => Ok(Box::new(rq::value::avro::sink(schema, output, codec)?)),
+            }
+        }
+        OutputFormat::Bencode => Ok(Box::new(rq::value::bencode::sink(output))),
+        OutputFormat::Bson => Ok(Box::new(rq::value::bson::sink(output))),
+        OutputFormat::Cbor => Ok(Box::new(rq::value::cbor::sink(output))),
+        OutputFormat::Ion => Ok(Box::new(rq::value::ion::sink(output))),
+        OutputFormat::IonBinary => Ok(Box::new(rq::value::ion::binary_sink(output))),
+        OutputFormat::Edn => Ok(Box::new(rq::value::edn::sink(output))),
+        OutputFormat::Ubjson => Ok(Box::new(rq::value::ubjson::sink(output))),
+        OutputFormat::MessagePack => Ok(Box::new(rq::value::messagepack::sink(output))),
+        // TODO: add TOML ugly printing eventually; now it's always "readable"
+        OutputFormat::Toml => Ok(Box::new(rq::value::toml::sink(
+            output,
+            args.flag_toml_nulls,
+            args.flag_wrap_scalar.clone(),
+        ))),
+        OutputFormat::Dotenv => Ok(Box::new(rq::value::dotenv::sink(output))),
+        OutputFormat::Logfmt => Ok(Box::new(rq::value::logfmt::sink(output))),
+        OutputFormat::Kdl => Ok(Box::new(rq::value::kdl::sink(output))),
+        // TODO: add YAML ugly printing eventually; now it's always "readable"
+        OutputFormat::Yaml => Ok(Box::new(rq::value::yaml::sink(output))),
+        OutputFormat::Smile => Ok(Box::new(rq::value::smile::sink(output)?)),
+        OutputFormat::Xml => Ok(Box::new(rq::value::xml::sink(
+            output,
+            args.flag_xml_attribute_prefix.clone(),
+            args.flag_wrap_scalar.clone(),
+        ))),
+        OutputFormat::Parquet => Ok(Box::new(rq::value::parquet::sink(output))),
+        OutputFormat::Arrow => Ok(Box::new(rq::value::arrow::sink(output))),
+        OutputFormat::Raw => Ok(Box::new(rq::value::raw::sink(output))),
+        OutputFormat::Csv => Ok(Box::new(rq::value::csv::sink_with(
+            output,
+            args.flag_wrap_scalar.is_some(),
+            &csv_dialect(args),
+        ))),
+        OutputFormat::Json => match format {
+            Format::Compact => Ok(Box::new(rq::value::json::sink_compact(output))),
+            Format::Readable => match args.flag_grep {
+                Some(ref regex) if args.flag_grep_highlight => Ok(Box::new(
+                    rq::value::json::sink_readable_highlighting(output, regex.clone()),
+                )),
+                _ => Ok(Box::new(rq::value::json::sink_readable(output))),
+            },
+            Format::Indented => Ok(Box::new(rq::value::json::sink_indented(output))),
+        },
+    }
+}
+
+/// Wraps the sink so that it updates the stage statistics, if there are any.
+fn timed<'a>(
+    sink: Box<dyn rq::value::Sink + 'a>,
+    stage: Option<&rc::Rc<rq::stats::Stage>>,
+) -> Box<dyn rq::value::Sink + 'a> {
+    match stage {
+        Some(stage) => Box::new(rq::stats::sink(sink, stage.clone())),
+        None => sink,
+    }
+}
+
+fn read_avro_schema_from_file(path: &path::Path) -> rq::error::Result<avro_rs::Schema> {
+    let mut file = fs::File::open(path)?;
+    let mut buffer = String::new();
+    file.read_to_string(&mut buffer)?;
+    avro_rs::Schema::parse_str(&buffer)
+        .map_err(|e| rq::error::Error::Avro(rq::error::Avro::downcast(e)))
+}
+
+fn read_record<I>(source: &mut I) -> rq::error::Result<Option<rq::value::Value>>
+where
+    I: rq::value::Source,
+{
+    match source.read() {
+        Ok(Some(record)) => {
+            trace!("Read record: {}", record.summary(120));
+            Ok(Some(record))
+        }
+        Ok(None) => {
+            if let Some(position) = source.position() {
+                debug!("Finished reading input at {}", position);
+            }
+            Ok(None)
+        }
+        Err(e) => {
+            if let Some(position) = source.position() {
+                error!("Failed to read input after {}", position);
+            }
+            Err(e)
+        }
+    }
+}
+
+/// Writes a record to the sink, reporting which record failed if it can't be written.  Sinks
+/// only write complete records, so the output is intact up to the failed record.
+fn write_record(
+    sink: &mut dyn rq::value::Sink,
+    record: rq::value::Value,
+    index: u64,
+) -> rq::error::Result<()> {
+    sink.write(record).inspect_err(|_| {
+        error!(
+            "Failed to write record {} (counting from 0) to the output; all earlier records were \
+             written completely",
+            index
+        )
+    })
+}
+
+/// Writes records to the sink, starting with the already read `next` record, until either the
+/// source runs out or `is_full` (given the number of records written to this sink) says to stop.
+/// The first record that didn't get written is left in `next`.  `written` counts the records
+/// written to all sinks so far, so that failures name the record of the whole output.
+fn write_records<I, F>(
+    source: &mut I,
+    sink: &mut dyn rq::value::Sink,
+    next: &mut Option<rq::value::Value>,
+    written: &mut u64,
+    mut is_full: F,
+) -> rq::error::Result<()>
+where
+    I: rq::value::Source,
+    F: FnMut(u64) -> bool,
+{
+    let mut records = 0;
+    while let Some(record) = next.take() {
+        write_record(sink, record, *written)?;
+        records += 1;
+        *written += 1;
+        *next = read_record(source)?;
+        if is_full(records) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn finish_sink(sink: &mut dyn rq::value::Sink) -> rq::error::Result<()> {
+    sink.finish()?;
+    sink.flush()?;
+    Ok(())
+}
+
+/// Reads the Thrift IDL of --thrift-idl, which the Thrift flags require.
+fn thrift_idl(args: &Options) -> rq::error::Result<rq::value::thrift::Idl> {
+    let path = args
+        .flag_thrift_idl
+        .as_ref()
+        .ok_or_else(|| rq::error::Error::Message("--thrift-idl is required".to_owned()))?;
+    rq::value::thrift::Idl::load(path)
+}
+
+fn load_descriptors(
+    paths: &rq::config::Paths,
+) -> rq::error::Result<serde_protobuf::descriptor::Descriptors> {
+    let descriptors_proto = rq::proto_index::compile_descriptor_set(paths)?;
+    Ok(serde_protobuf::descriptor::Descriptors::from_proto(
+        &descriptors_proto,
+    ))
+}
+
+fn infer_format() -> Format {
+    if atty::is(atty::Stream::Stdout) {
+        Format::Readable
+    } else {
+        Format::Compact
+    }
+}
+
+fn has_ran_cmd(cmd: &str) -> rq::error::Result<bool> {
+    let paths = match rq::config::Paths::new() {
+        Ok(paths) => paths,
+        Err(_) => return Ok(false),
+    };
+    paths
+        .find_config(&format!("{}{}", "has-ran-", cmd))
+        .map(|v| !v.is_empty())
+}
+
+fn set_ran_cmd(cmd: &str) -> rq::error::Result<()> {
+    let paths = match rq::config::Paths::new() {
+        Ok(paths) => paths,
+        Err(_) => return Ok(()),
+    };
+
+    let file = paths.preferred_config(format!("{}{}", "has-ran-", cmd));
+
+    if let Some(parent) = file.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    fs::File::create(&file)?;
+
+    Ok(())
+}
+
+fn log_error(args: &Options, error: &rq::error::Error) {
+    use failure::Fail;
+
+    let main_str = format!("{}", error);
+    let mut main_lines = main_str.lines();
+    error!("Encountered: {}", main_lines.next().unwrap());
+    for line in main_lines {
+        error!("  {}", line);
+    }
+    for e in <dyn failure::Fail>::iter_causes(error) {
+        let sub_str = format!("{}", e);
+        let mut sub_lines = sub_str.lines();
+        error!("Caused by: {}", sub_lines.next().unwrap());
+        for line in sub_lines {
+            error!("  {}", line);
+        }
+    }
+
+    if args.flag_trace || env::var("RUST_BACKTRACE").as_ref().map(String::as_str) == Ok("1") {
+        error!("");
+        if let Some(backtrace) = error.backtrace() {
+            error!("Backtrace:");
+            for line in format!("{:?}", backtrace).lines() {
+                error!("  {}", line);
+            }
+        } else {
+            error!("(No backtrace available)");
+        }
+    } else {
+        error!("(Re-run with --trace or RUST_BACKTRACE=1 for a backtrace)");
+    }
+}
+
+fn setup_log(spec: Option<&str>, quiet: bool) {
+    let mut builder = env_logger::Builder::new();
+
+    if quiet {
+        builder.filter(None, log::LevelFilter::Off);
+    } else if let Some(s) = spec {
+        builder.parse_filters(s);
+    } else if let Ok(s) = env::var("RUST_LOG") {
+        builder.parse_filters(&s);
+    } else {
+        builder.filter(None, log::LevelFilter::Info);
+    };
+
+    builder.format(format_log_record);
+
+    builder.init();
+}
+
+impl str::FromStr for Format {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "compact" => Ok(Self::Compact),
+            "readable" => Ok(Self::Readable),
+            "indented" => Ok(Self::Indented),
+            _ => Err(failure::err_msg(format!("unrecognized format: {}", s))),
+        }
+    }
+}
+
+/// Whether the output format is text, made up of lines.
+fn is_text_output(args: &Options) -> bool {
+    args.flag_output_protobuf.is_none()
+        && args.flag_output_avro.is_none()
+        && !args.flag_output_bencode
+        && !args.flag_output_bson
+        && !args.flag_output_cbor
+        && !args.flag_output_ion_binary
+        && !args.flag_output_message_pack
+        && !args.flag_output_smile
+        && !args.flag_output_ubjson
+        && !args.flag_output_parquet
+        && !args.flag_output_arrow
+        && args.flag_output_sqlite.is_none()
+        && !args.flag_output_audio_tags
+        && args.flag_output_thrift.is_none()
+}
+
+/// The CSV dialect selected by the CSV flags, for both input and output.
+fn csv_dialect(args: &Options) -> rq::value::csv::Dialect {
+    let default = rq::value::csv::Dialect::default();
+    let delimiter = if args.flag_tsv {
+        b'\t'
+    } else {
+        args.flag_csv_delimiter.unwrap_or(default.delimiter)
+    };
+    rq::value::csv::Dialect {
+        delimiter,
+        quote: args.flag_csv_quote.unwrap_or(default.quote),
+        escape: args.flag_csv_escape,
+        terminator: args.flag_csv_terminator,
+    }
+}
+
+fn parse_encoding(s: &str) -> rq::error::Result<&'static encoding_rs::Encoding> {
+    encoding_rs::Encoding::for_label(s.as_bytes())
+        .ok_or_else(|| rq::error::Error::Message(format!("unknown encoding: {}", s)))
+}
+
+fn parse_fake_fields(s: &str) -> rq::error::Result<Vec<rq::transform::fake::Field>> {
+    s.split(',').map(str::parse).collect()
+}
+
+impl InputFormat {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Arrow => "Arrow",
+            Self::Asn1 => "ASN.1",
+            Self::AuthorizedKeys => "authorized_keys",
+            Self::Avro => "Avro",
+            Self::Bencode => "bencode",
+            Self::Bson => "BSON",
+            Self::Cbor => "CBOR",
+            Self::Csv => "CSV",
+            Self::Dotenv => "dotenv",
+            Self::Edn => "EDN",
+            Self::GitLog => "git log",
+            Self::Hcl => "HCL",
+            Self::Image => "image",
+            Self::AudioTags => "audio tags",
+            Self::Pdf => "PDF",
+            Self::Registry => "registry",
+            Self::Evtx => "event log",
+            Self::Ion => "Ion",
+            Self::Json => "JSON",
+            Self::Jsonc => "JSONC",
+            Self::Kdl => "KDL",
+            Self::KnownHosts => "known_hosts",
+            Self::Lockfile => "lockfile",
+            Self::Logfmt => "logfmt",
+            Self::MessagePack => "MessagePack",
+            Self::Midi => "MIDI",
+            Self::OciImage => "OCI image",
+            Self::Parquet => "Parquet",
+            Self::X509 => "X.509",
+            Self::Xlsx => "Excel",
+            Self::ProtobufRaw => "raw protobuf",
+            Self::Raw => "raw text",
+            Self::Riff => "RIFF",
+            Self::Smile => "Smile",
+            Self::Sqlite => "SQLite",
+            Self::Toml => "TOML",
+            Self::Ubjson => "UBJSON",
+            Self::Xml => "XML",
+            Self::PropertiesXml => "Java props",
+            Self::Log4jXml => "log4j XML",
+            Self::Yaml => "YAML",
+            Self::ZoneFile => "zone file",
+        }
+    }
+
+    /// Whether the format is text, which can be transcoded from other encodings.
+    fn is_text(self) -> bool {
+        match self {
+            Self::AuthorizedKeys
+            | Self::Csv
+            | Self::Dotenv
+            | Self::Edn
+            | Self::GitLog
+            | Self::Hcl
+            | Self::Json
+            | Self::Jsonc
+            | Self::Kdl
+            | Self::KnownHosts
+            | Self::Lockfile
+            | Self::Log4jXml
+            | Self::Logfmt
+            | Self::PropertiesXml
+            | Self::Raw
+            | Self::Toml
+            | Self::Xml
+            | Self::Yaml
+            | Self::ZoneFile => true,
+            Self::Arrow
+            | Self::Asn1
+            | Self::AudioTags
+            | Self::Avro
+            | Self::Bencode
+            | Self::Bson
+            | Self::Cbor
+            | Self::Evtx
+            | Self::Image
+            | Self::Ion
+            | Self::MessagePack
+            | Self::Midi
+            | Self::OciImage
+            | Self::Parquet
+            | Self::Pdf
+            | Self::ProtobufRaw
+            | Self::Registry
+            | Self::Riff
+            | Self::Smile
+            | Self::Sqlite
+            | Self::Ubjson
+            | Self::X509
+            | Self::Xlsx => false,
+        }
+    }
+
+    /// Picks the input format named like its flag, without the `--input-` prefix.
+    fn from_name(s: &str) -> Option<Self> {
+        let format = match s {
+            "arrow" => Self::Arrow,
+            "asn1" => Self::Asn1,
+            "audio-tags" => Self::AudioTags,
+            "authorized-keys" => Self::AuthorizedKeys,
+            "avro" => Self::Avro,
+            "bencode" => Self::Bencode,
+            "bson" => Self::Bson,
+            "cbor" => Self::Cbor,
+            "csv" => Self::Csv,
+            "dotenv" => Self::Dotenv,
+            "edn" => Self::Edn,
+            "evtx" => Self::Evtx,
+            "git-log" => Self::GitLog,
+            "hcl" => Self::Hcl,
+            "image" => Self::Image,
+            "ion" => Self::Ion,
+            "json" => Self::Json,
+            "jsonc" => Self::Jsonc,
+            "kdl" => Self::Kdl,
+            "known-hosts" => Self::KnownHosts,
+            "lockfile" => Self::Lockfile,
+            "log4j-xml" => Self::Log4jXml,
+            "logfmt" => Self::Logfmt,
+            "message-pack" => Self::MessagePack,
+            "midi" => Self::Midi,
+            "oci-image" => Self::OciImage,
+            "parquet" => Self::Parquet,
+            "pdf" => Self::Pdf,
+            "properties-xml" => Self::PropertiesXml,
+            "protobuf-raw" => Self::ProtobufRaw,
+            "raw" => Self::Raw,
+            "registry" => Self::Registry,
+            "riff" => Self::Riff,
+            "smile" => Self::Smile,
+            "sqlite" => Self::Sqlite,
+            "toml" => Self::Toml,
+            "ubjson" => Self::Ubjson,
+            "x509" => Self::X509,
+            "xlsx" => Self::Xlsx,
+            "xml" => Self::Xml,
+            "yaml" => Self::Yaml,
+            "zone-file" => Self::ZoneFile,
+            _ => return None,
+        };
+        Some(format)
+    }
+
+    /// Picks the input format for a MIME type, ignoring any parameters like `charset`.
+    fn from_mime(s: &str) -> Result<Self, failure::Error> {
+        let essence = s
+            .split(';')
+            .next()
+            .unwrap_or("")
+            .trim()
+            .to_ascii_lowercase();
+        let format = match essence.as_str() {
+            "application/avro"
+            | "application/x-avro"
+            | "avro/binary"
+            | "application/vnd.apache.avro+binary" => Self::Avro,
+            "application/x-bittorrent" => Self::Bencode,
+            "application/bson" => Self::Bson,
+            "application/ubjson" => Self::Ubjson,
+            "application/cbor" => Self::Cbor,
+            "application/edn" => Self::Edn,
+            "application/ion" | "text/x-amzn-ion" | "application/x-amzn-ion" => Self::Ion,
+            "application/vnd.apache.arrow.file" | "application/vnd.apache.arrow.stream" => {
+                Self::Arrow
+            }
+            "application/vnd.apache.parquet" | "application/x-parquet" => Self::Parquet,
+            "text/csv" | "application/csv" => Self::Csv,
+            "application/json"
+            | "text/json"
+            | "application/x-ndjson"
+            | "application/ndjson"
+            | "application/jsonl"
+            | "application/x-jsonlines"
+            | "application/json-seq" => Self::Json,
+            "application/msgpack" | "application/x-msgpack" | "application/vnd.msgpack" => {
+                Self::MessagePack
+            }
+            "application/x-protobuf"
+            | "application/protobuf"
+            | "application/vnd.google.protobuf" => Self::ProtobufRaw,
+            "text/plain" => Self::Raw,
+            "application/x-jackson-smile" | "application/smile" => Self::Smile,
+            "application/vnd.sqlite3" | "application/x-sqlite3" => Self::Sqlite,
+            "application/toml" | "application/x-toml" | "text/toml" => Self::Toml,
+            "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet" => Self::Xlsx,
+            "application/xml" | "text/xml" => Self::Xml,
+            "application/yaml" | "application/x-yaml" | "text/yaml" | "text/x-yaml" => Self::Yaml,
+            "text/dns" => Self::ZoneFile,
+            "audio/wav" | "audio/wave" | "audio/x-wav" | "audio/vnd.wave" | "video/x-msvideo"
+            | "video/avi" | "image/webp" => Self::Riff,
+            "audio/midi" | "audio/x-midi" | "audio/mid" => Self::Midi,
+            "image/jpeg" | "image/png" | "image/tiff" | "image/heic" | "image/heif"
+            | "image/avif" => Self::Image,
+            "audio/mpeg" | "audio/mp3" | "audio/flac" | "audio/x-flac" | "audio/ogg"
+            | "audio/opus" | "audio/vorbis" => Self::AudioTags,
+            "application/pdf" | "application/x-pdf" => Self::Pdf,
+            "application/pkix-cert" | "application/x-x509-ca-cert" | "application/x-pem-file" => {
+                Self::X509
+            }
+            "application/pkix-crl" | "application/pkcs8" | "application/pkcs10" => Self::Asn1,
+            // Structured syntax suffixes, like `application/geo+json`
+            _ if essence.ends_with("+json") => Self::Json,
+            _ if essence.ends_with("+cbor") => Self::Cbor,
+            _ if essence.ends_with("+yaml") => Self::Yaml,
+            _ if essence.ends_with("+xml") => Self::Xml,
+            _ => return Err(failure::err_msg(format!("unsupported MIME type: {}", s))),
+        };
+        Ok(format)
+    }
+}
+
+impl OutputFormat {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Arrow => "Arrow",
+            Self::AudioTags => "audio tags",
+            Self::Avro => "Avro",
+            Self::Bencode => "bencode",
+            Self::Bson => "BSON",
+            Self::Cbor => "CBOR",
+            Self::Csv => "CSV",
+            Self::Dotenv => "dotenv",
+            Self::Edn => "EDN",
+            Self::Ion => "Ion text",
+            Self::IonBinary => "Ion binary",
+            Self::Json => "JSON",
+            Self::Kdl => "KDL",
+            Self::Logfmt => "logfmt",
+            Self::MessagePack => "MessagePack",
+            Self::Parquet => "Parquet",
+            Self::Protobuf => "protobuf",
+            Self::Raw => "raw text",
+            Self::Smile => "Smile",
+            Self::Sqlite => "SQLite",
+            Self::Textproto => "protobuf text format",
+            Self::Thrift => "Thrift",
+            Self::Toml => "TOML",
+            Self::Ubjson => "UBJSON",
+            Self::Xml => "XML",
+            Self::Yaml => "YAML",
+        }
+    }
+}
+
+fn format_log_record(
+    formatter: &mut env_logger::fmt::Formatter,
+    record: &log::Record,
+) -> io::Result<()> {
+    use ansi_term::ANSIStrings;
+    use ansi_term::Colour;
+    use ansi_term::Style;
+
+    if atty::is(atty::Stream::Stderr) {
+        let normal = Style::new();
+        let (front, back) = match record.level() {
+            log::Level::Error => (Colour::Red.normal(), Colour::Red.dimmed()),
+            log::Level::Warn => (Colour::Yellow.normal(), Colour::Yellow.dimmed()),
+            log::Level::Info => (Colour::Blue.normal(), Colour::Blue.dimmed()),
+            log::Level::Debug => (Colour::Purple.normal(), Colour::Purple.dimmed()),
+            log::Level::Trace => (Colour::White.dimmed(), Colour::Black.normal()),
+        };
+
+        let strings = &[
+            back.paint("["),
+            front.paint(format!("{}", record.level())),
+            back.paint("]"),
+            normal.paint(" "),
+            back.paint("["),
+            front.paint(record.module_path().unwrap_or("<unknown>")),
+            back.paint("]"),
+            normal.paint(" "),
+            front.paint(format!("{}", record.args())),
+        ];
+
+        writeln!(formatter, "{}", ANSIStrings(strings))
+    } else {
+        writeln!(
+            formatter,
+            "[{}] [{}] {}",
+            record.level(),
+            record.module_path().unwrap_or("<unknown>"),
+            record.args()
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse_args(args: &[&str]) -> Options {
+        use structopt::StructOpt;
+        let a = Options::from_iter_safe(args.iter()).unwrap();
+        println!("{:?}", a);
+        a
+    }
+
+    #[test]
+    fn test_docopt_kitchen_sink() {
+        let a = parse_args(&["rq", "-l", "info", "-jP", ".foo.Bar", "select x"]);
+        assert!(a.flag_input_json);
+        assert_eq!(a.flag_output_protobuf, Some(".foo.Bar".to_owned()));
+        assert_eq!(a.flag_log, Some("info".to_owned()));
+        assert_eq!(a.arg_query, Some("select x".to_owned()));
+    }
+
+    #[test]
+    fn test_docopt_no_args() {
+        parse_args(&["rq"]);
+    }
+
+    #[test]
+    #[cfg_attr(
+        all(target_arch = "x86", target_pointer_width = "32", target_env = "musl"),
+        ignore
+    )]
+    #[should_panic(expected = "Help")]
+    fn test_docopt_help() {
+        parse_args(&["rq", "--help"]);
+    }
+
+    #[test]
+    fn test_docopt_input_json() {
+        let a = parse_args(&["rq", "-j"]);
+        assert!(a.flag_input_json);
+    }
+
+    #[test]
+    fn test_docopt_input_json_long() {
+        let a = parse_args(&["rq", "--input-json"]);
+        assert!(a.flag_input_json);
+    }
+
+    #[test]
+    fn test_docopt_output_json() {
+        let a = parse_args(&["rq", "-J"]);
+        assert!(a.flag_output_json);
+    }
+
+    #[test]
+    fn test_docopt_output_json_long() {
+        let a = parse_args(&["rq", "--output-json"]);
+        assert!(a.flag_output_json);
+    }
+
+    #[test]
+    fn test_docopt_input_raw() {
+        let a = parse_args(&["rq", "-r"]);
+        assert!(a.flag_input_raw);
+    }
+
+    #[test]
+    fn test_docopt_input_raw_long() {
+        let a = parse_args(&["rq", "--input-raw"]);
+        assert!(a.flag_input_raw);
+    }
+
+    #[test]
+    fn test_docopt_output_raw() {
+        let a = parse_args(&["rq", "-R"]);
+        assert!(a.flag_output_raw);
+    }
+
+    #[test]
+    fn test_docopt_output_raw_long() {
+        let a = parse_args(&["rq", "--output-raw"]);
+        assert!(a.flag_output_raw);
+    }
+
+    #[test]
+    fn test_docopt_input_csv() {
+        let a = parse_args(&["rq", "-v"]);
+        assert!(a.flag_input_csv);
+    }
+
+    #[test]
+    fn test_docopt_input_csv_long() {
+        let a = parse_args(&["rq", "--input-csv"]);
+        assert!(a.flag_input_csv);
+    }
+
+    #[test]
+    fn test_docopt_output_csv() {
+        let a = parse_args(&["rq", "-V"]);
+        assert!(a.flag_output_csv);
+    }
+
+    #[test]
+    fn test_docopt_output_csv_long() {
+        let a = parse_args(&["rq", "--output-csv"]);
+        assert!(a.flag_output_csv);
+    }
+
+    #[test]
+    fn test_docopt_csv_dialect() {
+        use rq::value::csv::Dialect;
+        use structopt::StructOpt;
+
+        let a = parse_args(&["rq", "-vV", "--tsv"]);
+        assert_eq!(
+            csv_dialect(&a),
+            Dialect {
+                delimiter: b'\t',
+                ..Dialect::default()
+            }
+        );
+        assert_eq!(describe_output(&a), "TSV to stdout");
+
+        let a = parse_args(&[
+            "rq",
+            "--csv-delimiter",
+            ";",
+            "--csv-escape",
+            "\\",
+            "--csv-terminator",
+            "|",
+        ]);
+        assert_eq!(
+            csv_dialect(&a),
+            Dialect {
+                delimiter: b';',
+                quote: b'"',
+                escape: Some(b'\\'),
+                terminator: Some(b'|'),
+            }
+        );
+
+        assert!(Options::from_iter_safe(&["rq", "--tsv", "--csv-delimiter", ";"]).is_err());
+        assert!(Options::from_iter_safe(&["rq", "--csv-quote", "''"]).is_err());
+    }
+
+    #[test]
+    fn test_docopt_csv_wrap_scalar() {
+        let a = parse_args(&["rq", "-V", "--wrap-scalar", "value"]);
+        let mut sink = open_sink(&a, Format::Compact, None, None, Box::new(Vec::new())).unwrap();
+        assert!(sink.write(rq::value!({"a": 1})).is_ok());
+
+        let a = parse_args(&["rq", "-V"]);
+        let mut sink = open_sink(&a, Format::Compact, None, None, Box::new(Vec::new())).unwrap();
+        assert!(sink.write(rq::value!({"a": 1})).is_err());
+    }
+
+    #[test]
+    fn test_docopt_input_cbor() {
+        let a = parse_args(&["rq", "-c"]);
+        assert!(a.flag_input_cbor);
+    }
+
+    #[test]
+    fn test_docopt_input_cbor_long() {
+        let a = parse_args(&["rq", "--input-cbor"]);
+        assert!(a.flag_input_cbor);
+    }
+
+    #[test]
+    fn test_docopt_output_cbor() {
+        let a = parse_args(&["rq", "-C"]);
+        assert!(a.flag_output_cbor);
+    }
+
+    #[test]
+    fn test_docopt_output_cbor_long() {
+        let a = parse_args(&["rq", "--output-cbor"]);
+        assert!(a.flag_output_cbor);
+    }
+
+    #[test]
+    fn test_docopt_input_smile() {
+        let a = parse_args(&["rq", "-s"]);
+        assert!(a.flag_input_smile);
+    }
+
+    #[test]
+    fn test_docopt_input_smile_long() {
+        let a = parse_args(&["rq", "--input-smile"]);
+        assert!(a.flag_input_smile);
+    }
+
+    #[test]
+    fn test_docopt_output_smile() {
+        let a = parse_args(&["rq", "-S"]);
+        assert!(a.flag_output_smile);
+    }
+
+    #[test]
+    fn test_docopt_output_smile_long() {
+        let a = parse_args(&["rq", "--output-smile"]);
+        assert!(a.flag_output_smile);
+    }
+
+    #[test]
+    fn test_failed_record_leaves_no_partial_output() {
+        use rq::value::Value;
+
+        // Map keys have to be strings in both formats
+        let unrepresentable = Value::Map(vec![
+            (Value::from("a"), Value::from("x")),
+            (rq::value!({"b": "y"}), Value::from("y")),
+        ]);
+        for (flag, format) in &[("-J", InputFormat::Json), ("-S", InputFormat::Smile)] {
+            let a = parse_args(&["rq", flag]);
+            let mut output = Vec::new();
+            {
+                let mut sink =
+                    open_sink(&a, Format::Compact, None, None, Box::new(&mut output)).unwrap();
+                write_record(&mut *sink, rq::value!({"a": "x"}), 0).unwrap();
+                assert!(write_record(&mut *sink, unrepresentable.clone(), 1).is_err());
+                finish_sink(&mut *sink).unwrap();
+            }
+            assert_eq!(
+                read_all(*format, &output).unwrap(),
+                vec![rq::value!({"a": "x"})],
+                "{}",
+                flag
+            );
+        }
+
+        // Smile remembers the strings of the failed record, so it can't go on
+        let a = parse_args(&["rq", "-S"]);
+        let mut sink = open_sink(&a, Format::Compact, None, None, Box::new(Vec::new())).unwrap();
+        assert!(sink.write(unrepresentable).is_err());
+        assert!(sink.write(rq::value!({"a": "x"})).is_err());
+    }
+
+    #[test]
+    fn test_docopt_input_xml() {
+        let a = parse_args(&["rq", "-x"]);
+        assert!(a.flag_input_xml);
+        assert_eq!(a.flag_xml_attribute_prefix, "@");
+    }
+
+    #[test]
+    fn test_docopt_output_xml() {
+        let a = parse_args(&["rq", "-X", "--xml-attribute-prefix", "_"]);
+        assert!(a.flag_output_xml);
+        assert_eq!(a.flag_xml_attribute_prefix, "_");
+    }
+
+    #[test]
+    fn test_docopt_xlsx() {
+        let a = parse_args(&["rq", "--input-xlsx", "--xlsx-sheet", "People"]);
+        assert_eq!(input_format(&a), InputFormat::Xlsx);
+        assert_eq!(a.flag_xlsx_sheet, Some("People".to_owned()));
+    }
+
+    #[test]
+    fn test_docopt_lockfile() {
+        let a = parse_args(&["rq", "--input-lockfile"]);
+        assert_eq!(input_format(&a), InputFormat::Lockfile);
+    }
+
+    #[test]
+    fn test_docopt_oci_image() {
+        let a = parse_args(&["rq", "--input-oci-image"]);
+        assert_eq!(input_format(&a), InputFormat::OciImage);
+    }
+
+    #[test]
+    fn test_docopt_zone_file() {
+        let a = parse_args(&["rq", "--input-zone-file"]);
+        assert_eq!(input_format(&a), InputFormat::ZoneFile);
+        assert_eq!(
+            InputFormat::from_mime("text/dns").unwrap(),
+            InputFormat::ZoneFile
+        );
+    }
+
+    #[test]
+    fn test_docopt_ssh_keys() {
+        let a = parse_args(&["rq", "--input-known-hosts"]);
+        assert_eq!(input_format(&a), InputFormat::KnownHosts);
+        let a = parse_args(&["rq", "--input-authorized-keys"]);
+        assert_eq!(input_format(&a), InputFormat::AuthorizedKeys);
+    }
+
+    #[test]
+    fn test_docopt_dotenv() {
+        let a = parse_args(&["rq", "--input-dotenv", "--output-dotenv"]);
+        assert_eq!(input_format(&a), InputFormat::Dotenv);
+        assert!(a.flag_output_dotenv);
+        assert_eq!(describe_output(&a).split(' ').next(), Some("dotenv"));
+    }
+
+    #[test]
+    fn test_docopt_hcl() {
+        let a = parse_args(&["rq", "--input-hcl"]);
+        assert_eq!(input_format(&a), InputFormat::Hcl);
+    }
+
+    #[test]
+    fn test_docopt_x509() {
+        let a = parse_args(&["rq", "--input-x509"]);
+        assert_eq!(input_format(&a), InputFormat::X509);
+    }
+
+    #[test]
+    fn test_docopt_kdl() {
+        let a = parse_args(&["rq", "--input-kdl", "--output-kdl"]);
+        assert_eq!(input_format(&a), InputFormat::Kdl);
+        assert!(a.flag_output_kdl);
+        assert_eq!(describe_output(&a).split(' ').next(), Some("KDL"));
+    }
+
+    #[test]
+    fn test_docopt_riff_and_midi() {
+        let a = parse_args(&["rq", "--input-riff"]);
+        assert_eq!(input_format(&a), InputFormat::Riff);
+        let a = parse_args(&["rq", "--input-midi"]);
+        assert_eq!(input_format(&a), InputFormat::Midi);
+    }
+
+    #[test]
+    fn test_docopt_image() {
+        let a = parse_args(&["rq", "--input-image"]);
+        assert_eq!(input_format(&a), InputFormat::Image);
+    }
+
+    #[test]
+    fn test_docopt_audio_tags() {
+        let a = parse_args(&["rq", "--input-audio-tags"]);
+        assert_eq!(input_format(&a), InputFormat::AudioTags);
+        let a = parse_args(&["rq", "--output-audio-tags", "--output", "song.flac"]);
+        assert_eq!(describe_output(&a), "audio tags to song.flac");
+    }
+
+    #[test]
+    fn test_docopt_pdf() {
+        let a = parse_args(&["rq", "--input-pdf"]);
+        assert_eq!(input_format(&a), InputFormat::Pdf);
+    }
+
+    #[test]
+    fn test_docopt_ubjson() {
+        let a = parse_args(&["rq", "--input-ubjson", "--output-ubjson"]);
+        assert_eq!(input_format(&a), InputFormat::Ubjson);
+        assert_eq!(describe_output(&a), "UBJSON to stdout");
+    }
+
+    #[test]
+    fn test_docopt_registry() {
+        let a = parse_args(&["rq", "--input-registry"]);
+        assert_eq!(input_format(&a), InputFormat::Registry);
+    }
+
+    #[test]
+    fn test_docopt_bencode() {
+        let a = parse_args(&["rq", "--input-bencode", "--output-bencode"]);
+        assert_eq!(input_format(&a), InputFormat::Bencode);
+        assert_eq!(describe_output(&a), "bencode to stdout");
+    }
+
+    #[test]
+    fn test_docopt_evtx() {
+        let a = parse_args(&["rq", "--input-evtx"]);
+        assert_eq!(input_format(&a), InputFormat::Evtx);
+    }
+
+    #[test]
+    fn test_docopt_asn1() {
+        let a = parse_args(&["rq", "--input-asn1"]);
+        assert_eq!(input_format(&a), InputFormat::Asn1);
+    }
+
+    #[test]
+    fn test_docopt_java_xml() {
+        let a = parse_args(&["rq", "--input-properties-xml"]);
+        assert_eq!(input_format(&a), InputFormat::PropertiesXml);
+        let a = parse_args(&["rq", "--input-log4j-xml"]);
+        assert_eq!(input_format(&a), InputFormat::Log4jXml);
+    }
+
+    #[test]
+    fn test_docopt_logfmt() {
+        let a = parse_args(&["rq", "--input-logfmt", "--output-logfmt"]);
+        assert_eq!(input_format(&a), InputFormat::Logfmt);
+        assert!(a.flag_output_logfmt);
+        assert_eq!(describe_output(&a).split(' ').next(), Some("logfmt"));
+    }
+
+    #[test]
+    fn test_docopt_edn() {
+        let a = parse_args(&["rq", "--input-edn", "--output-edn"]);
+        assert_eq!(input_format(&a), InputFormat::Edn);
+        assert!(a.flag_output_edn);
+        assert_eq!(describe_output(&a).split(' ').next(), Some("EDN"));
+    }
+
+    #[test]
+    fn test_docopt_git_log() {
+        let a = parse_args(&["rq", "--input-git-log"]);
+        assert_eq!(input_format(&a), InputFormat::GitLog);
+        let a = parse_args(&["rq", "--git-log"]);
+        assert_eq!(a.flag_git_log, Some(None));
+        let a = parse_args(&["rq", "--git-log", "../repo"]);
+        assert_eq!(a.flag_git_log, Some(Some(path::PathBuf::from("../repo"))));
+    }
+
+    #[test]
+    fn test_docopt_sqlite() {
+        use structopt::StructOpt;
+
+        let a = parse_args(&["rq", "--output-sqlite", "people", "--output", "people.db"]);
+        assert_eq!(describe_output(&a), "SQLite table people to people.db");
+        let a = parse_args(&[
+            "rq",
+            "--input-sqlite",
+            "--sqlite-query",
+            "SELECT count(*) AS n FROM people",
+        ]);
+        assert_eq!(input_format(&a), InputFormat::Sqlite);
+        assert_eq!(
+            a.flag_sqlite_query,
+            Some("SELECT count(*) AS n FROM people".to_owned())
+        );
+        assert!(Options::from_iter_safe(&["rq", "--output-sqlite", "people"]).is_err());
+    }
+
+    #[test]
+    fn test_docopt_parquet() {
+        let a = parse_args(&["rq", "--input-parquet", "--output-parquet"]);
+        assert_eq!(input_format(&a), InputFormat::Parquet);
+        assert!(a.flag_output_parquet);
+    }
+
+    #[test]
+    fn test_docopt_arrow() {
+        let a = parse_args(&["rq", "--input-arrow", "--output-arrow"]);
+        assert_eq!(input_format(&a), InputFormat::Arrow);
+        assert!(a.flag_output_arrow);
+    }
+
+    #[test]
+    fn test_docopt_bson() {
+        let a = parse_args(&["rq", "--input-bson", "--output-bson"]);
+        assert_eq!(input_format(&a), InputFormat::Bson);
+        assert!(a.flag_output_bson);
+    }
+
+    #[test]
+    fn test_docopt_ion() {
+        let a = parse_args(&["rq", "--input-ion", "--output-ion-binary"]);
+        assert_eq!(input_format(&a), InputFormat::Ion);
+        assert!(a.flag_output_ion_binary);
+    }
+
+    #[test]
+    fn test_input_manifest() {
+        use rq::value::Source;
+
+        let a = parse_args(&["rq", "--input-manifest"]);
+        assert!(a.flag_input_manifest);
+
+        let dir = env::temp_dir().join(format!("rq-manifest-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let csv = dir.join("users.csv");
+        let yaml = dir.join("extra.yaml");
+        fs::write(&csv, "a,b\n").unwrap();
+        fs::write(&yaml, "c: 1\n").unwrap();
+
+        let manifest = format!(
+            "# users first\n{}:csv\n\n{}:yaml\n",
+            csv.display(),
+            yaml.display()
+        );
+        let files = parse_manifest(&manifest).unwrap();
+        assert_eq!(
+            files,
+            vec![(csv, InputFormat::Csv), (yaml, InputFormat::Yaml)]
+        );
+        let mut source = ManifestSource {
+            args: &a,
+            files: files.into(),
+            current: None,
+        };
+        assert_eq!(source.read().unwrap(), Some(rq::value!(["a", "b"])));
+        assert_eq!(source.read().unwrap(), Some(rq::value!({"c": 1})));
+        assert_eq!(source.read().unwrap(), None);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(parse_manifest("users.csv").is_err());
+        assert!(parse_manifest("users.csv:env").is_err());
+    }
+
+    #[test]
+    fn test_convert() {
+        let a = parse_args(&["rq", "--serve", "127.0.0.1:8080"]);
+        assert_eq!(a.flag_serve.as_deref(), Some("127.0.0.1:8080"));
+        assert_eq!(
+            InputFormat::from_name("message-pack"),
+            Some(InputFormat::MessagePack)
+        );
+        assert_eq!(InputFormat::from_name("env"), None);
+
+        let output = convert(InputFormat::Yaml, "json", b"a: [x]\nb: 1\n").unwrap();
+        assert_eq!(
+            str::from_utf8(&output).unwrap(),
+            "{\"a\":[\"x\"],\"b\":1}\n"
+        );
+        assert!(convert(InputFormat::Json, "csv", b"{").is_err());
+    }
+
+    #[test]
+    fn test_serve_request() {
+        use std::net;
+        use std::thread;
+
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let addr = server.server_addr().to_ip().unwrap();
+        let client = thread::spawn(move || {
+            for request in &[
+                "POST /convert?from=json&to=yaml HTTP/1.1\r\nContent-Length: 8\r\n\r\n{\"a\": 1}",
+                "POST /convert?to=json HTTP/1.1\r\nContent-Type: text/csv\r\n\
+                 Content-Length: 4\r\n\r\na,b\n",
+                "GET /convert?from=json&to=yaml HTTP/1.1\r\n\r\n",
+                "POST /convert?from=env&to=json HTTP/1.1\r\nContent-Length: 0\r\n\r\n",
+                "POST /convert?from=json&to=message%2Dpack HTTP/1.1\r\n\
+                 Content-Length: 1\r\n\r\n1",
+                "POST /convert?from=json&to=json HTTP/1.1\r\n\
+                 Content-Length: 100000000\r\n\r\n",
+            ] {
+                let mut stream = net::TcpStream::connect(addr).unwrap();
+                stream.write_all(request.as_bytes()).unwrap();
+                stream.shutdown(net::Shutdown::Write).unwrap();
+                // Wait for the response, so that the requests arrive in order
+                let mut status = [0; 12];
+                stream.read_exact(&mut status).unwrap();
+            }
+        });
+
+        let mut results = Vec::new();
+        for _ in 0..6 {
+            // Dropping the request answers it, with an empty response
+            let mut request = server.recv().unwrap();
+            results.push(serve_request(&mut request));
+        }
+        client.join().unwrap();
+        assert_eq!(results[0], Ok(("application/yaml", b"a: 1\n\n".to_vec())));
+        assert_eq!(
+            results[1],
+            Ok(("application/json", b"[\"a\",\"b\"]\n".to_vec()))
+        );
+        assert_eq!(results[2].as_ref().unwrap_err().0, 405);
+        assert_eq!(
+            results[3],
+            Err((400, "unsupported input format: env".to_owned()))
+        );
+        assert_eq!(results[4], Ok(("application/msgpack", vec![1])));
+        assert_eq!(results[5].as_ref().unwrap_err().0, 413);
+    }
+
+    #[test]
+    fn test_docopt_input_protobuf() {
+        let a = parse_args(&["rq", "-p", ".foo.Bar"]);
+        assert_eq!(a.flag_input_protobuf, Some(".foo.Bar".to_owned()));
+    }
+
+    #[test]
+    fn test_docopt_input_protobuf_long() {
+        let a = parse_args(&["rq", "--input-protobuf", ".foo.Bar"]);
+        assert_eq!(a.flag_input_protobuf, Some(".foo.Bar".to_owned()));
+    }
+
+    #[test]
+    fn test_docopt_proto3_json() {
+        use structopt::StructOpt;
+
+        let a = parse_args(&["rq", "-p", ".foo.Bar", "--proto3-json"]);
+        assert!(a.flag_proto3_json);
+        assert!(Options::from_iter_safe(&["rq", "--proto3-json"]).is_err());
+    }
+
+    #[test]
+    fn test_docopt_textproto() {
+        let a = parse_args(&["rq", "--input-textproto", ".test.Config", "-J"]);
+        assert_eq!(a.flag_input_textproto, Some(".test.Config".to_owned()));
+        let a = parse_args(&["rq", "--output-textproto", ".test.Config"]);
+        assert_eq!(
+            describe_output(&a),
+            "protobuf text format message .test.Config to stdout"
+        );
+    }
+
+    #[test]
+    fn test_docopt_flatbuffers() {
+        use structopt::StructOpt;
+
+        let a = parse_args(&["rq", "--input-flatbuffers", "monster.bfbs", "-J"]);
+        assert_eq!(
+            a.flag_input_flatbuffers,
+            Some(path::PathBuf::from("monster.bfbs"))
+        );
+        assert!(Options::from_iter_safe(&["rq", "--flatbuffers-root", "Monster"]).is_err());
+    }
+
+    #[test]
+    fn test_docopt_thrift() {
+        use rq::value::thrift::Protocol;
+        use structopt::StructOpt;
+
+        let a = parse_args(&["rq", "--input-thrift", "User", "--thrift-idl", "u.thrift"]);
+        assert_eq!(a.flag_input_thrift, Some("User".to_owned()));
+        assert_eq!(a.flag_thrift_protocol, Protocol::Binary);
+        assert!(Options::from_iter_safe(&["rq", "--input-thrift", "User"]).is_err());
+        let a = parse_args(&[
+            "rq",
+            "--output-thrift",
+            "User",
+            "--thrift-idl",
+            "u.thrift",
+            "--thrift-protocol",
+            "compact",
+        ]);
+        assert_eq!(
+            describe_output(&a),
+            "Thrift struct User (compact protocol) to stdout"
+        );
+    }
+
+    #[test]
+    fn test_docopt_protobuf_raw() {
+        let a = parse_args(&["rq", "--input-protobuf-raw"]);
+        assert_eq!(input_format(&a), InputFormat::ProtobufRaw);
+    }
+
+    #[test]
+    fn test_docopt_output_protobuf() {
+        let a = parse_args(&["rq", "-P", ".foo.Bar"]);
+        assert_eq!(a.flag_output_protobuf, Some(".foo.Bar".to_owned()));
+    }
+
+    #[test]
+    fn test_docopt_output_protobuf_long() {
+        let a = parse_args(&["rq", "--output-protobuf", ".foo.Bar"]);
+        assert_eq!(a.flag_output_protobuf, Some(".foo.Bar".to_owned()));
+    }
+
+    #[test]
+    fn test_docopt_protobuf_add_schema() {
+        let a = parse_args(&["rq", "-l", "info", "protobuf", "add", "schema.proto"]);
+        assert_eq!(a.flag_log, Some("info".to_owned()));
+        assert_eq!(
+            Some(path::PathBuf::from("schema.proto")),
+            match a.subcmd {
+                Some(Subcmd::Protobuf { subcmd }) => match subcmd {
+                    ProtobufSubcmd::Add { schema, .. } => Some(schema),
+                },
+                _ => None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_docopt_toml_nulls_default() {
+        let a = parse_args(&["rq", "-T"]);
+        assert_eq!(a.flag_toml_nulls, rq::value::toml::NullPolicy::Omit);
+    }
+
+    #[test]
+    fn test_docopt_toml_nulls() {
+        let a = parse_args(&["rq", "-T", "--toml-nulls", "empty-string"]);
+        assert_eq!(a.flag_toml_nulls, rq::value::toml::NullPolicy::EmptyString);
+    }
+
+    #[test]
+    fn test_docopt_wrap_scalar() {
+        let a = parse_args(&["rq", "-T", "--wrap-scalar", "value"]);
+        assert_eq!(a.flag_wrap_scalar, Some("value".to_owned()));
+    }
+
+    #[test]
+    fn test_docopt_output_file() {
+        let a = parse_args(&["rq", "-o", "out.json"]);
+        assert_eq!(a.flag_output, Some(path::PathBuf::from("out.json")));
+    }
+
+    #[test]
+    fn test_docopt_output_rotate_size() {
+        let a = parse_args(&["rq", "-o", "out.avro", "--output-rotate", "size=500M"]);
+        assert_eq!(
+            a.flag_output_rotate,
+            Some(rq::output::Rotation::Size(500 * 1024 * 1024))
+        );
+    }
+
+    #[test]
+    fn test_docopt_output_rotate_count() {
+        let a = parse_args(&["rq", "-o", "out.avro", "--output-rotate", "count=1e6"]);
+        assert_eq!(
+            a.flag_output_rotate,
+            Some(rq::output::Rotation::Count(1_000_000))
+        );
+    }
+
+    #[test]
+    fn test_docopt_append() {
+        let a = parse_args(&["rq", "-o", "out.json", "--append"]);
+        assert!(a.flag_append);
+        assert_eq!(existing_output_format(&a), Some(InputFormat::Json));
+
+        let a = parse_args(&["rq", "--output-logfmt"]);
+        assert_eq!(existing_output_format(&a), Some(InputFormat::Logfmt));
+        let a = parse_args(&["rq", "--output-thrift", "User", "--thrift-idl", "u.thrift"]);
+        assert!(is_appendable(&a));
+        assert_eq!(existing_output_format(&a), None);
+        for flags in &[
+            &["-T"][..],
+            &["--output-sqlite", "t", "-o", "a.db"],
+            &["-Y"],
+        ] {
+            let a = parse_args(&[&["rq"][..], flags].concat());
+            assert!(!is_appendable(&a));
+            assert_eq!(existing_output_format(&a), None);
+        }
+    }
+
+    #[test]
+    fn test_skip_existing() {
+        use structopt::StructOpt;
+
+        let dir = env::temp_dir().join(format!("rq-skip-existing-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let output = dir.join("out.json");
+        fs::write(&output, "{\"id\":1}\n{\"id\":\"2\"}\n").unwrap();
+
+        let a = parse_args(&[
+            "rq",
+            "-o",
+            output.to_str().unwrap(),
+            "--append",
+            "--dedup-key",
+            ".id",
+            "--skip-existing",
+        ]);
+        let input = r#"{"id": 2} {"id": 3} {"id": 1} {"id": 3} {"name": "x"}"#;
+        let source = transform(&a, rq::value::json::source(input.as_bytes()), 0, None).unwrap();
+        write_output(&a, source, 0, None).unwrap();
+        assert_eq!(
+            fs::read_to_string(&output).unwrap(),
+            "{\"id\":1}\n{\"id\":\"2\"}\n{\"id\":3}\n{\"name\":\"x\"}\n"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(Options::from_iter_safe(&["rq", "--skip-existing", "--append"]).is_err());
+    }
+
+    #[test]
+    fn test_docopt_no_atomic() {
+        let a = parse_args(&["rq", "-o", "out.json", "--no-atomic"]);
+        assert!(a.flag_no_atomic);
+    }
+
+    #[test]
+    fn test_docopt_input_mime() {
+        let a = parse_args(&["rq", "--input-mime", "application/x-ndjson"]);
+        assert_eq!(a.flag_input_mime, Some(InputFormat::Json));
+    }
+
+    #[test]
+    fn test_input_format_from_mime() {
+        assert_eq!(
+            InputFormat::from_mime("text/CSV; charset=utf-8").unwrap(),
+            InputFormat::Csv
+        );
+        assert_eq!(
+            InputFormat::from_mime("application/geo+json").unwrap(),
+            InputFormat::Json
+        );
+        assert!(InputFormat::from_mime("image/gif").is_err());
+    }
+
+    #[test]
+    fn test_output_format() {
+        let a = parse_args(&["rq", "--output-edn"]);
+        assert_eq!(output_format(&a), OutputFormat::Edn);
+        assert_eq!(describe_output(&a), "EDN to stdout");
+        let a = parse_args(&["rq", "-J", "--format", "indented"]);
+        assert_eq!(output_format(&a), OutputFormat::Json);
+        assert_eq!(describe_output(&a), "JSON (indented) to stdout");
+
+        // SQLite and audio tags output are written to the file itself, not through a stream
+        let a = parse_args(&["rq", "--output-sqlite", "t", "--output", "db.sqlite"]);
+        assert_eq!(output_format(&a), OutputFormat::Sqlite);
+        assert!(open_sink(&a, Format::Compact, None, None, Box::new(io::sink())).is_err());
+    }
+
+    #[test]
+    fn test_docopt_mmap() {
+        let a = parse_args(&["rq", "-c", "--mmap"]);
+        assert!(a.flag_mmap);
+    }
+
+    #[test]
+    fn test_mmap_files() {
+        let dir = env::temp_dir().join(format!("rq-mmap-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("in.json");
+        fs::write(&path, r#"{"a": 1} {"a": 2}"#).unwrap();
+
+        let a = parse_args(&["rq", "--mmap"]);
+        let input = input_file(&a, fs::File::open(&path).unwrap()).unwrap();
+        let mut source = open_source(&a, InputFormat::Json, input).unwrap();
+        assert_eq!(source.read().unwrap(), Some(rq::value!({"a": 1})));
+        assert_eq!(source.read().unwrap(), Some(rq::value!({"a": 2})));
+        assert_eq!(source.read().unwrap(), None);
+        assert!(map_file(&fs::File::open(&path).unwrap()).unwrap().is_some());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_docopt_explain() {
+        let a = parse_args(&["rq", "--explain"]);
+        assert!(a.flag_explain);
+    }
+
+    #[test]
+    fn test_explain_stages() {
+        let a = parse_args(&[
+            "rq",
+            "--grep",
+            "a",
+            "--delta",
+            ".n",
+            "--elapsed",
+            ".t",
+            "--sample",
+            "10",
+            "--shuffle",
+        ]);
+        let mut stages = Vec::new();
+        let input = r#"{"x": "a", "n": 1} {"x": "b", "n": 2} {"x": "a", "n": 3}"#;
+        let mut source = transform(
+            &a,
+            rq::value::json::source(input.as_bytes()),
+            0,
+            Some(&mut stages),
+        )
+        .unwrap();
+        while source.read().unwrap().is_some() {}
+        assert_eq!(
+            describe_transforms(&stages),
+            "grep a, delta of .n into .n_delta, time elapsed at .t into .elapsed, sample of 10 \
+             records (seed 0), shuffle (seed 0)"
+        );
+        let records: Vec<_> = stages.iter().map(|(_, stage)| stage.records()).collect();
+        assert_eq!(records, vec![2, 2, 2, 2]);
+    }
+
+    #[test]
+    fn test_docopt_run() {
+        let a = parse_args(&["rq", "run", "pipeline.yaml"]);
+        match a.subcmd {
+            Some(Subcmd::Run { pipeline }) => {
+                assert_eq!(pipeline, path::PathBuf::from("pipeline.yaml"))
+            }
+            other => panic!("unexpected subcommand: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_pipeline_args() {
+        let args = pipeline_args(
+            "input-csv: true\n\
+             output-json: false\n\
+             output: out.json\n\
+             output-rotate: count=10\n\
+             query: select x\n",
+        )
+        .unwrap();
+        assert_eq!(
+            args,
+            vec![
+                "rq",
+                "--input-csv",
+                "--output",
+                "out.json",
+                "--output-rotate",
+                "count=10",
+                "--",
+                "select x"
+            ]
+        );
+        let a = parse_args(&args.iter().map(String::as_str).collect::<Vec<_>>());
+        assert!(a.flag_input_csv);
+        assert_eq!(a.arg_query, Some("select x".to_owned()));
+    }
+
+    #[test]
+    fn test_docopt_input_env() {
+        let a = parse_args(&["rq", "--input-env"]);
+        assert_eq!(a.flag_input_env, Some(None));
+        let a = parse_args(&["rq", "--input-env=APP_", "-Y"]);
+        assert_eq!(a.flag_input_env, Some(Some("APP_".to_owned())));
+    }
+
+    #[test]
+    fn test_docopt_output_path_template() {
+        use structopt::StructOpt;
+        let a = parse_args(&["rq", "--output-path-template", "{record.day}/out.json"]);
+        assert!(a.flag_output_path_template.is_some());
+        assert!(Options::from_iter_safe(&[
+            "rq",
+            "--output-path-template",
+            "{record.day}.json",
+            "-o",
+            "out.json"
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn test_output_path_template() {
+        let template: rq::output::PathTemplate = "{record.date}/{record.tenant.id}-{{x}}.json"
+            .parse()
+            .unwrap();
+        let record = rq::value!({"date": "2024-01-02", "tenant": {"id": 7}});
+        assert_eq!(
+            template.render(&record).unwrap(),
+            path::PathBuf::from("2024-01-02/7-{x}.json")
+        );
+        assert!(template
+            .render(&rq::value!({"date": "..", "tenant": {"id": 7}}))
+            .is_err());
+        assert!(template
+            .render(&rq::value!({"date": "2024-01-02"}))
+            .is_err());
+        assert!("{date}.json".parse::<rq::output::PathTemplate>().is_err());
+    }
+
+    #[test]
+    fn test_max_open_files() {
+        let dir = env::temp_dir().join(format!("rq-max-open-files-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let template = format!("{}/{{record.k}}.json", dir.display());
+        let input = r#"{"k": "a"} {"k": "b"} {"k": "a"} {"k": "c"} {"k": "b"} {"k": "a"}"#;
+
+        let a = parse_args(&[
+            "rq",
+            "--output-path-template",
+            &template,
+            "--max-open-files",
+            "2",
+        ]);
+        write_output(&a, rq::value::json::source(input.as_bytes()), 0, None).unwrap();
+        let read = |name: &str| fs::read_to_string(dir.join(name)).unwrap().lines().count();
+        assert_eq!((read("a.json"), read("b.json"), read("c.json")), (3, 2, 1));
+
+        // Files that can't be appended to can't be closed early
+        let a = parse_args(&[
+            "rq",
+            "-Y",
+            "--output-path-template",
+            &template,
+            "--max-open-files",
+            "2",
+        ]);
+        assert!(write_output(&a, rq::value::json::source(input.as_bytes()), 0, None).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_docopt_sample() {
+        let a = parse_args(&["rq", "--sample", "100", "--seed", "42"]);
+        assert_eq!(a.flag_sample, Some(100));
+        assert_eq!(a.flag_seed, Some(42));
+    }
+
+    #[test]
+    fn test_docopt_shuffle() {
+        let a = parse_args(&["rq", "--shuffle", "--seed", "1"]);
+        assert!(a.flag_shuffle);
+    }
+
+    #[test]
+    fn test_docopt_split() {
+        let a = parse_args(&["rq", "--split", "0.8:train.json,0.2:valid.json"]);
+        let split = a.flag_split.unwrap();
+        assert_eq!(
+            split.paths().collect::<Vec<_>>(),
+            vec![path::Path::new("train.json"), path::Path::new("valid.json")]
+        );
+        assert!("1:only.json".parse::<rq::output::Split>().is_err());
+        assert!("0:a.json,1:b.json".parse::<rq::output::Split>().is_err());
+    }
+
+    #[test]
+    fn test_route() {
+        let condition = |s: &str| s.parse::<rq::output::Condition>().unwrap();
+        let record = rq::value!({"level": "error", "code": 500, "ok": false, "msg": "timeout"});
+        assert!(condition("*").matches(&record));
+        assert!(condition(".level").matches(&record));
+        assert!(!condition(".ok").matches(&record));
+        assert!(condition(r#".level == "error""#).matches(&record));
+        assert!(condition(".level == error").matches(&record));
+        assert!(condition(".code == 500.0").matches(&record));
+        assert!(condition(".level != info").matches(&record));
+        assert!(condition(".msg =~ ^time").matches(&record));
+        assert!(condition(".code:integer").matches(&record));
+        assert!(!condition(".missing == 1").matches(&record));
+        assert!("=> json:a.json".parse::<rq::output::Route>().is_err());
+        assert!(".a == 1 => json".parse::<rq::output::Route>().is_err());
+
+        let dir = env::temp_dir().join(format!("rq-route-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let errors = dir.join("errors.json");
+        let metrics = dir.join("metrics.yaml");
+        let rest = dir.join("rest.json");
+        let a = parse_args(&[
+            "rq",
+            "--route",
+            &format!(".level == error => json:{}", errors.display()),
+            "--route",
+            &format!(".value:number => yaml:{}", metrics.display()),
+            "--output",
+            rest.to_str().unwrap(),
+        ]);
+        assert_eq!(a.flag_route.len(), 2);
+        let input = r#"{"level": "error"} {"value": 1} {"level": "info"} {"value": 2}"#;
+        write_output(&a, rq::value::json::source(input.as_bytes()), 0, None).unwrap();
+        assert_eq!(
+            fs::read_to_string(&errors).unwrap(),
+            "{\"level\":\"error\"}\n"
+        );
+        assert_eq!(
+            fs::read_to_string(&metrics).unwrap(),
+            "value: 1\n\n---\nvalue: 2\n\n"
+        );
+        assert_eq!(fs::read_to_string(&rest).unwrap(), "{\"level\":\"info\"}\n");
+        fs::remove_dir_all(&dir).unwrap();
+
+        let a = parse_args(&["rq", "--route", "* => avro:all.avro"]);
+        assert!(route_options(&a, &a.flag_route[0]).is_err());
+        for &(name, format, _) in NAMED_OUTPUT_FORMATS {
+            let route = format!("* => {}:out", name);
+            let a = parse_args(&["rq", "--output-avro", "schema.avsc", "--route", &route]);
+            let options = route_options(&a, &a.flag_route[0]).unwrap();
+            assert_eq!(output_format(&options), format);
+        }
+    }
+
+    #[test]
+    fn test_docopt_fake() {
+        let a = parse_args(&[
+            "rq",
+            "--fake",
+            "name=person.name,email=internet.email",
+            "--fake",
+            "city=address.city",
+        ]);
+        let fields: Vec<_> = a
+            .flag_fake
+            .iter()
+            .flatten()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            fields,
+            vec![
+                ".name=person.name",
+                ".email=internet.email",
+                ".city=address.city"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_docopt_histogram() {
+        let a = parse_args(&["rq", "--histogram", ".latency:0,10,100"]);
+        assert_eq!(
+            a.flag_histogram.map(|h| h.to_string()),
+            Some(".latency:0,10,100".to_owned())
+        );
+        assert!(".latency:10,0"
+            .parse::<rq::transform::histogram::Spec>()
+            .is_err());
+        assert!(".latency:width=0"
+            .parse::<rq::transform::histogram::Spec>()
+            .is_err());
+    }
+
+    #[test]
+    fn test_docopt_top_k() {
+        use structopt::StructOpt;
+
+        let a = parse_args(&["rq", "--top-k", "20", "--by", ".user_agent"]);
+        assert_eq!(a.flag_top_k, Some(20));
+        assert_eq!(
+            a.flag_by.map(|p| p.to_string()),
+            Some(".user_agent".to_owned())
+        );
+        assert!(Options::from_iter_safe(&["rq", "--top-k", "20"]).is_err());
+    }
 
-#[cfg(test)]
-mod test {
-    use super::*;
+    #[test]
+    fn test_docopt_quantiles() {
+        let a = parse_args(&["rq", "--quantiles", ".latency_ms:p50,p99.9"]);
+        assert_eq!(
+            a.flag_quantiles.map(|q| q.to_string()),
+            Some(".latency_ms:p50,p99.9".to_owned())
+        );
+        let a = parse_args(&["rq", "--quantiles", ".latency_ms"]);
+        assert_eq!(
+            a.flag_quantiles.map(|q| q.to_string()),
+            Some(".latency_ms:p50,p90,p99".to_owned())
+        );
+        assert!(".x:median"
+            .parse::<rq::transform::quantiles::Spec>()
+            .is_err());
+    }
 
-    fn parse_args(args: &[&str]) -> Options {
-        use structopt::StructOpt;
-        let a = Options::from_iter_safe(args.iter()).unwrap();
-        println!("{:?}", a);
-        a
+    #[test]
+    fn test_docopt_pivot() {
+        let a = parse_args(&[
+            "rq",
+            "--pivot",
+            "rows=.region,columns=.month,values=sum(.revenue)",
+        ]);
+        assert_eq!(
+            a.flag_pivot.map(|p| p.to_string()),
+            Some("rows=.region,columns=.month,values=sum(.revenue)".to_owned())
+        );
+        assert!("rows=.region"
+            .parse::<rq::transform::pivot::Spec>()
+            .is_err());
+        assert!("rows=.a,columns=.b,values=median(.c)"
+            .parse::<rq::transform::pivot::Spec>()
+            .is_err());
     }
 
     #[test]
-    fn test_docopt_kitchen_sink() {
-        let a = parse_args(&["rq", "-l", "info", "-jP", ".foo.Bar", "select x"]);
-        assert!(a.flag_input_json);
-        assert_eq!(a.flag_output_protobuf, Some(".foo.Bar".to_owned()));
-        assert_eq!(a.flag_log, Some("info".to_owned()));
-        assert_eq!(a.arg_query, Some("select x".to_owned()));
+    fn test_docopt_transpose() {
+        let a = parse_args(&["rq", "--transpose", "--to-columns"]);
+        assert!(a.flag_transpose);
+        assert!(a.flag_to_columns);
+        let a = parse_args(&["rq", "--from-columns"]);
+        assert!(a.flag_from_columns);
     }
 
     #[test]
-    fn test_docopt_no_args() {
-        parse_args(&["rq"]);
+    fn test_docopt_explode() {
+        let a = parse_args(&["rq", "--explode", ".items"]);
+        assert_eq!(
+            a.flag_explode.map(|p| p.to_string()),
+            Some(".items".to_owned())
+        );
     }
 
     #[test]
-    #[cfg_attr(
-        all(target_arch = "x86", target_pointer_width = "32", target_env = "musl"),
-        ignore
-    )]
-    #[should_panic(expected = "Help")]
-    fn test_docopt_help() {
-        parse_args(&["rq", "--help"]);
+    fn test_docopt_nest() {
+        use structopt::StructOpt;
+
+        let a = parse_args(&["rq", "--nest-by", ".order_id", "--into", "items"]);
+        assert_eq!(
+            a.flag_nest_by.map(|p| p.to_string()),
+            Some(".order_id".to_owned())
+        );
+        assert_eq!(
+            a.flag_into.map(|p| p.to_string()),
+            Some(".items".to_owned())
+        );
+        assert!(!a.flag_nest_all);
+        assert!(Options::from_iter_safe(&["rq", "--nest-by", ".order_id"]).is_err());
     }
 
     #[test]
-    fn test_docopt_input_json() {
-        let a = parse_args(&["rq", "-j"]);
-        assert!(a.flag_input_json);
+    fn test_docopt_look_behind() {
+        use structopt::StructOpt;
+
+        let a = parse_args(&[
+            "rq",
+            "--delta",
+            ".stats.bytes",
+            "--elapsed",
+            ".time",
+            "--session",
+            ".time:30m:.visit",
+        ]);
+        let specs: Vec<_> = look_behind_specs(&a)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            specs,
+            vec![
+                "delta of .stats.bytes into .stats.bytes_delta",
+                "time elapsed at .time into .elapsed",
+                "sessions split by 1800s gaps in .time into .visit",
+            ]
+        );
+        assert!(Options::from_iter_safe(&["rq", "--session", ".time"]).is_err());
+        assert!(Options::from_iter_safe(&["rq", "--delta", "."]).is_err());
     }
 
     #[test]
-    fn test_docopt_input_json_long() {
-        let a = parse_args(&["rq", "--input-json"]);
-        assert!(a.flag_input_json);
+    fn test_docopt_find() {
+        let a = parse_args(&["rq", "-y", "find", "^e?mail$", "--keys", "--with-values"]);
+        match a.subcmd {
+            Some(Subcmd::Find {
+                pattern,
+                keys,
+                strings,
+                with_values,
+            }) => {
+                assert_eq!(pattern, "^e?mail$");
+                assert!(keys);
+                assert!(!strings);
+                assert!(with_values);
+            }
+            other => panic!("expected find, got {:?}", other),
+        }
+        assert!(a.flag_input_yaml);
     }
 
     #[test]
-    fn test_docopt_output_json() {
-        let a = parse_args(&["rq", "-J"]);
-        assert!(a.flag_output_json);
+    fn test_find() {
+        let value =
+            rq::value!({"user": {"name": "jo", "e/mail": "jo@example.com"}, "tags": ["mail"]});
+        let finder = rq::find::Finder::new(regex::Regex::new("mail").unwrap(), true, true);
+        let found: Vec<_> = finder
+            .find(&value, rq::value::path::Path::root())
+            .iter()
+            .map(|f| f.path.to_pointer())
+            .collect();
+        assert_eq!(found, vec!["/tags/0", "/user/e~1mail"]);
     }
 
     #[test]
-    fn test_docopt_output_json_long() {
-        let a = parse_args(&["rq", "--output-json"]);
-        assert!(a.flag_output_json);
+    fn test_docopt_grep() {
+        use structopt::StructOpt;
+
+        let a = parse_args(&["rq", "--grep", "time(d )?out", "--grep-path", ".msg"]);
+        assert_eq!(
+            a.flag_grep.map(|r| r.to_string()),
+            Some("time(d )?out".to_owned())
+        );
+        assert_eq!(
+            a.flag_grep_path
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            vec![".msg"]
+        );
+        assert!(!a.flag_grep_highlight);
+        assert!(Options::from_iter_safe(&["rq", "--grep", "("]).is_err());
+        assert!(Options::from_iter_safe(&["rq", "--grep-highlight"]).is_err());
     }
 
     #[test]
-    fn test_docopt_input_raw() {
-        let a = parse_args(&["rq", "-r"]);
-        assert!(a.flag_input_raw);
+    fn test_docopt_where_type() {
+        use structopt::StructOpt;
+
+        let a = parse_args(&[
+            "rq",
+            "--where-type",
+            ".id:string",
+            "--where-type",
+            ".n:int|null",
+            "--drift",
+        ]);
+        let checks: Vec<_> = a.flag_where_type.iter().map(ToString::to_string).collect();
+        assert_eq!(checks, vec![".id:string", ".n:integer|null"]);
+        assert!(a.flag_drift);
+        assert!(a.flag_where_type[1].matches(&rq::value!({"n": null})));
+        assert!(!a.flag_where_type[1].matches(&rq::value!({"n": 1.5})));
+        assert!(Options::from_iter_safe(&["rq", "--where-type", ".id:text"]).is_err());
     }
 
     #[test]
-    fn test_docopt_input_raw_long() {
-        let a = parse_args(&["rq", "--input-raw"]);
-        assert!(a.flag_input_raw);
+    fn test_docopt_pointer() {
+        let a = parse_args(&["rq", "--explode", "/order/line.items", "--by", "/a~1b/~0c"]);
+        assert_eq!(
+            a.flag_explode.map(|p| p.segments().to_vec()),
+            Some(vec!["order".to_owned(), "line.items".to_owned()])
+        );
+        assert_eq!(
+            a.flag_by.map(|p| p.to_pointer()),
+            Some("/a~1b/~0c".to_owned())
+        );
     }
 
     #[test]
-    fn test_docopt_output_raw() {
-        let a = parse_args(&["rq", "-R"]);
-        assert!(a.flag_output_raw);
+    fn test_docopt_normalize() {
+        use structopt::StructOpt;
+
+        let a = parse_args(&[
+            "rq",
+            "--normalize",
+            "empty-as-null,trim-strings,lowercase-keys",
+        ]);
+        let rules = a.flag_normalize.unwrap();
+        assert_eq!(
+            rules.to_string(),
+            "trim-strings,empty-as-null,lowercase-keys"
+        );
+        assert_eq!(
+            rules.apply(rq::value!({"Name": " jo ", "Tags": ["  ", "A"]})),
+            rq::value!({"name": "jo", "tags": [null, "A"]})
+        );
+        assert!(Options::from_iter_safe(&["rq", "--normalize", "trim"]).is_err());
     }
 
     #[test]
-    fn test_docopt_output_raw_long() {
-        let a = parse_args(&["rq", "--output-raw"]);
-        assert!(a.flag_output_raw);
+    fn test_docopt_lenient() {
+        use structopt::StructOpt;
+
+        assert!(parse_args(&["rq", "--lenient"]).flag_lenient);
+        assert!(!parse_args(&["rq", "--strict"]).flag_lenient);
+        assert!(Options::from_iter_safe(&["rq", "--lenient", "--strict"]).is_err());
     }
 
     #[test]
-    fn test_docopt_input_csv() {
-        let a = parse_args(&["rq", "-v"]);
-        assert!(a.flag_input_csv);
+    fn test_docopt_jsonc() {
+        let a = parse_args(&["rq", "--input-jsonc"]);
+        assert_eq!(input_format(&a), InputFormat::Jsonc);
     }
 
     #[test]
-    fn test_docopt_input_csv_long() {
-        let a = parse_args(&["rq", "--input-csv"]);
-        assert!(a.flag_input_csv);
+    fn test_compress() {
+        use std::io::Read;
+
+        for codec in &["gzip", "snappy", "lz4"] {
+            let a = parse_args(&["rq", "--compress", codec, "--output-newline", "crlf"]);
+            let mut output = Vec::new();
+            {
+                let mut sink =
+                    open_sink(&a, Format::Compact, None, None, Box::new(&mut output)).unwrap();
+                sink.write(rq::value!({"a": 1})).unwrap();
+                sink.write(rq::value!([true])).unwrap();
+                finish_sink(&mut *sink).unwrap();
+            }
+            assert_eq!(
+                rq::compression::Codec::detect(&output),
+                Some(codec.parse().unwrap())
+            );
+
+            let (detected, mut input) = rq::compression::decoder(&output[..]).unwrap();
+            assert_eq!(detected, Some(codec.parse().unwrap()));
+            let mut text = String::new();
+            input.read_to_string(&mut text).unwrap();
+            assert_eq!(text, "{\"a\":1}\r\n[true]\r\n");
+        }
     }
 
     #[test]
-    fn test_docopt_output_csv() {
-        let a = parse_args(&["rq", "-V"]);
-        assert!(a.flag_output_csv);
+    fn test_decompress_field() {
+        use base64::Engine;
+        use std::io::Write;
+
+        let mut compressed = Vec::new();
+        {
+            let codec = rq::compression::Codec::Gzip;
+            let mut encoder =
+                rq::compression::encoder(codec, None, Box::new(&mut compressed)).unwrap();
+            encoder
+                .write_all(br#"{"logEvents": [{"id": "1"}]}"#)
+                .unwrap();
+            encoder.finish().unwrap();
+        }
+        let payload = base64::engine::general_purpose::STANDARD.encode(&compressed);
+
+        let a = parse_args(&["rq", "--decompress-field", ".data:gzip+base64"]);
+        let input = format!(r#"{{"data": "{}"}} {{"other": 1}}"#, payload);
+        let mut source = transform(&a, rq::value::json::source(input.as_bytes()), 0, None).unwrap();
+        assert_eq!(
+            source.read().unwrap(),
+            Some(rq::value!({"data": {"logEvents": [{"id": "1"}]}}))
+        );
+        assert_eq!(source.read().unwrap(), Some(rq::value!({"other": 1})));
+
+        let a = parse_args(&["rq", "--decompress-field", ".data:base64"]);
+        let input = r#"{"data": "aGk="} {"data": "!"}"#;
+        let mut source = transform(&a, rq::value::json::source(input.as_bytes()), 0, None).unwrap();
+        assert_eq!(source.read().unwrap(), Some(rq::value!({"data": "hi"})));
+        assert!(source.read().is_err());
+
+        assert!("data:zip"
+            .parse::<rq::transform::decompress::Field>()
+            .is_err());
     }
 
     #[test]
-    fn test_docopt_output_csv_long() {
-        let a = parse_args(&["rq", "--output-csv"]);
-        assert!(a.flag_output_csv);
+    fn test_aws_logs() {
+        let a = parse_args(&["rq", "--aws-logs"]);
+        let input = r#"
+            {"Records": [{"eventName": "A"}, {"eventName": "B"}]}
+            {"messageType": "CONTROL_MESSAGE", "logEvents": []}{"messageType": "DATA_MESSAGE",
+             "logGroup": "g", "logStream": "s", "owner": "1", "subscriptionFilters": ["f"],
+             "logEvents": [{"id": "1", "message": "{\"x\": 1}"}, {"id": "2", "message": "hi"}]}
+            {"Records": "not a trail"}
+        "#;
+        let mut source = transform(&a, rq::value::json::source(input.as_bytes()), 0, None).unwrap();
+        let mut records = Vec::new();
+        while let Some(record) = source.read().unwrap() {
+            records.push(record);
+        }
+        // The context of CloudWatch Logs events comes first
+        let records: Vec<String> = records.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            records,
+            vec![
+                r#"{"eventName": "A"}"#,
+                r#"{"eventName": "B"}"#,
+                r#"{"logGroup": "g", "logStream": "s", "owner": "1", "id": "1", "message": {"x": 1}}"#,
+                r#"{"logGroup": "g", "logStream": "s", "owner": "1", "id": "2", "message": "hi"}"#,
+                r#"{"Records": "not a trail"}"#,
+            ]
+        );
     }
 
     #[test]
-    fn test_docopt_input_cbor() {
-        let a = parse_args(&["rq", "-c"]);
-        assert!(a.flag_input_cbor);
+    fn test_kubernetes() {
+        use rq::value::Sink;
+
+        let manifest = "\
+apiVersion: v1
+kind: Namespace
+metadata: {name: web}
+---
+apiVersion: apps/v1
+kind: Deployment
+metadata: {name: api, namespace: web}
+---
+---
+apiVersion: v1
+kind: List
+items:
+- {apiVersion: v1, kind: Service, metadata: {name: api, namespace: web}}
+- {apiVersion: v1, kind: ConfigMap, metadata: {name: cfg, namespace: web}}
+";
+        let read = |args: &[&str]| {
+            let a = parse_args(args);
+            let source = rq::value::yaml::source(manifest.as_bytes());
+            let mut source = transform(&a, source, 0, None).unwrap();
+            let mut records = Vec::new();
+            while let Some(record) = source.read().unwrap() {
+                records.push(record);
+            }
+            records
+        };
+
+        let records = read(&["rq", "--k8s-split"]);
+        let names: Vec<_> = records
+            .iter()
+            .map(|r| {
+                let field = |path| r.get(path).cloned().unwrap_or(rq::value::Value::Unit);
+                (field(".kind"), field(".name"), field(".namespace"))
+            })
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                ("Namespace".into(), "web".into(), rq::value::Value::Unit),
+                ("Deployment".into(), "api".into(), "web".into()),
+                ("Service".into(), "api".into(), "web".into()),
+                ("ConfigMap".into(), "cfg".into(), "web".into()),
+            ]
+        );
+
+        let records = read(&[
+            "rq",
+            "--k8s-kind",
+            "apps/v1/deployment",
+            "--k8s-kind",
+            "v1/Service",
+        ]);
+        let kinds: Vec<_> = records.iter().map(|r| r.get(".kind").cloned()).collect();
+        assert_eq!(
+            kinds,
+            vec![Some("Deployment".into()), Some("Service".into())]
+        );
+        assert!(read(&["rq", "--k8s-kind", "batch/Deployment"]).is_empty());
+
+        // Joining restores the resources, which YAML output writes as a multi-document stream
+        let a = parse_args(&[
+            "rq",
+            "--k8s-split",
+            "--k8s-kind",
+            "ConfigMap",
+            "--k8s-kind",
+            "Namespace",
+            "--k8s-join",
+        ]);
+        let mut source =
+            transform(&a, rq::value::yaml::source(manifest.as_bytes()), 0, None).unwrap();
+        let mut output = Vec::new();
+        {
+            let mut sink = rq::value::yaml::sink(&mut output);
+            while let Some(record) = source.read().unwrap() {
+                sink.write(record).unwrap();
+            }
+        }
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(
+            output,
+            "apiVersion: v1\nkind: Namespace\nmetadata:\n  name: web\n\n---\n\
+             apiVersion: v1\nkind: ConfigMap\nmetadata:\n  name: cfg\n  namespace: web\n\n"
+        );
+
+        assert!("apps//Deployment"
+            .parse::<rq::transform::kubernetes::Kind>()
+            .is_err());
     }
 
     #[test]
-    fn test_docopt_input_cbor_long() {
-        let a = parse_args(&["rq", "--input-cbor"]);
-        assert!(a.flag_input_cbor);
+    fn test_terraform() {
+        let state = r#"{
+            "version": 4,
+            "terraform_version": "1.9.0",
+            "resources": [
+                {
+                    "mode": "managed",
+                    "type": "aws_instance",
+                    "name": "web",
+                    "provider": "provider[\"registry.terraform.io/hashicorp/aws\"]",
+                    "instances": [
+                        {"index_key": 0, "attributes": {"id": "i-1"}},
+                        {"index_key": 1, "attributes": {"id": "i-2"}}
+                    ]
+                },
+                {
+                    "module": "module.vpc",
+                    "mode": "data",
+                    "type": "aws_subnet",
+                    "name": "private",
+                    "provider": "provider[\"registry.terraform.io/hashicorp/aws\"].west",
+                    "instances": [{"index_key": "a", "attributes": {"id": "subnet-1"}}]
+                }
+            ]
+        }"#;
+        let show = r#"{"format_version": "1.0", "values": {"root_module": {
+            "resources": [{"address": "aws_s3_bucket.logs", "mode": "managed",
+                "type": "aws_s3_bucket", "name": "logs",
+                "provider_name": "registry.terraform.io/hashicorp/aws",
+                "values": {"bucket": "logs"}}],
+            "child_modules": [{"address": "module.db", "resources": [
+                {"address": "module.db.aws_db_instance.main", "mode": "managed",
+                 "type": "aws_db_instance", "name": "main",
+                 "provider_name": "registry.terraform.io/hashicorp/aws", "values": {}}]}]
+        }}}"#;
+        let read = |args: &[&str], input: &str| {
+            let a = parse_args(args);
+            let source = rq::value::json::source(input.as_bytes());
+            let mut source = transform(&a, source, 0, None).unwrap();
+            let mut records = Vec::new();
+            while let Some(record) = source.read().unwrap() {
+                records.push(record);
+            }
+            records
+        };
+
+        let records = read(&["rq", "--tf-resources"], state);
+        assert_eq!(records.len(), 3);
+        assert_eq!(
+            records[2].to_string(),
+            r#"{"address": "module.vpc.data.aws_subnet.private[\"a\"]", "module": "module.vpc", "mode": "data", "type": "aws_subnet", "name": "private", "index": "a", "provider": "registry.terraform.io/hashicorp/aws.west", "attributes": {"id": "subnet-1"}}"#
+        );
+        let addresses = |records: Vec<rq::value::Value>| -> Vec<_> {
+            records
+                .iter()
+                .map(|r| r.get(".address").unwrap().to_string())
+                .collect()
+        };
+        assert_eq!(
+            addresses(read(&["rq", "--tf-resources", "type=aws_instance"], state)),
+            vec![r#""aws_instance.web[0]""#, r#""aws_instance.web[1]""#]
+        );
+        assert_eq!(
+            addresses(read(
+                &["rq", "--tf-resources", "mode=managed,type=aws_*"],
+                show
+            )),
+            vec![
+                r#""aws_s3_bucket.logs""#,
+                r#""module.db.aws_db_instance.main""#
+            ]
+        );
+        assert!(read(&["rq", "--tf-resources", "module=module.vpc"], show).is_empty());
+
+        let a = parse_args(&["rq", "--tf-resources", "type=aws_*"]);
+        let mut stages = Vec::new();
+        transform(&a, rq::value::json::source(&b""[..]), 0, Some(&mut stages)).unwrap();
+        assert_eq!(
+            describe_transforms(&stages),
+            "list Terraform resources type=aws_*"
+        );
+        assert!("kind=x"
+            .parse::<rq::transform::terraform::Filters>()
+            .is_err());
+        assert!("type".parse::<rq::transform::terraform::Filters>().is_err());
     }
 
     #[test]
-    fn test_docopt_output_cbor() {
-        let a = parse_args(&["rq", "-C"]);
-        assert!(a.flag_output_cbor);
+    fn test_har() {
+        use structopt::StructOpt;
+
+        let input = r#"
+            {"log": {"version": "1.2", "entries": [{"pageref": "p", "time": 20.5,
+             "startedDateTime": "2024-04-30T12:40:00.123Z", "serverIPAddress": "192.0.2.1",
+             "request": {"method": "POST", "url": "https://x/", "postData": {"text": "{\"q\": 1}"}},
+             "response": {"status": 200, "content": {"size": 11, "mimeType": "application/json",
+              "text": "eyJvayI6dHJ1ZX0=", "encoding": "base64"}},
+             "timings": {"blocked": -1, "dns": -1, "connect": 10, "send": 0.5, "wait": 5, "receive": 5}}]}}
+            {"log": "not an archive"}
+        "#;
+        let a = parse_args(&["rq", "--har", "--har-bodies"]);
+        let mut source = transform(&a, rq::value::json::source(input.as_bytes()), 0, None).unwrap();
+        let mut records = Vec::new();
+        while let Some(record) = source.read().unwrap() {
+            records.push(record.to_string());
+        }
+        assert_eq!(
+            records,
+            vec![
+                r#"{"page": "p", "started": "2024-04-30T12:40:00.123Z", "time": 20.5, "method": "POST", "url": "https://x/", "status": 200, "mime_type": "application/json", "size": 11, "server_ip": "192.0.2.1", "blocked": null, "dns": null, "connect": 10, "ssl": null, "send": 0.5, "wait": 5, "receive": 5, "request": {"method": "POST", "url": "https://x/", "postData": {"text": {"q": 1}}}, "response": {"status": 200, "content": {"size": 11, "mimeType": "application/json", "text": {"ok": true}}}}"#,
+                r#"{"log": "not an archive"}"#,
+            ]
+        );
+        assert!(Options::from_iter_safe(&["rq", "--har-bodies"]).is_err());
     }
 
     #[test]
-    fn test_docopt_output_cbor_long() {
-        let a = parse_args(&["rq", "--output-cbor"]);
-        assert!(a.flag_output_cbor);
+    fn test_merge() {
+        let dir = env::temp_dir().join(format!("rq-merge-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let patch = dir.join("patch.yaml");
+        fs::write(
+            &patch,
+            "\
+spec:
+  replicas: 3
+  paused: null
+  template:
+    spec:
+      containers:
+      - name: app
+        image: app:2
+        ports: [{containerPort: 8080, name: http}]
+      - name: sidecar
+        $patch: delete
+      - name: metrics
+        image: exporter:1
+",
+        )
+        .unwrap();
+        let deployment = r#"{"spec": {"replicas": 1, "paused": false, "template": {"spec":
+            {"containers": [
+                {"name": "app", "image": "app:1", "ports": [{"containerPort": 8080}]},
+                {"name": "sidecar", "image": "proxy:1"}
+            ]}}}}"#;
+        let read = |args: &[&str]| {
+            let a = parse_args(args);
+            let source = rq::value::json::source(deployment.as_bytes());
+            let mut source = transform(&a, source, 0, None).unwrap();
+            let record = source.read().unwrap().unwrap();
+            assert_eq!(source.read().unwrap(), None);
+            record.get(".spec").unwrap().to_string()
+        };
+        let merge = format!("{}:yaml", patch.display());
+
+        // A deep merge replaces the containers, keeping the delete directive as is
+        assert_eq!(
+            read(&["rq", "--merge", &merge]),
+            r#"{"replicas": 3, "template": {"spec": {"containers": [{"name": "app", "image": "app:2", "ports": [{"containerPort": 8080, "name": "http"}]}, {"name": "sidecar", "$patch": "delete"}, {"name": "metrics", "image": "exporter:1"}]}}}"#
+        );
+        assert_eq!(
+            read(&["rq", "--merge", &merge, "--merge-strategy", "strategic"]),
+            r#"{"replicas": 3, "template": {"spec": {"containers": [{"name": "app", "image": "app:2", "ports": [{"containerPort": 8080, "name": "http"}]}, {"name": "metrics", "image": "exporter:1"}]}}}"#
+        );
+
+        // A replace marker replaces the whole list, and merge keys can be added
+        fs::write(
+            &patch,
+            "spec: {template: {spec: {containers: [{$patch: replace}, {name: only}]}}}",
+        )
+        .unwrap();
+        assert_eq!(
+            read(&["rq", "--merge", &merge, "--merge-strategy", "strategic"]),
+            r#"{"replicas": 1, "paused": false, "template": {"spec": {"containers": [{"name": "only"}]}}}"#
+        );
+        fs::write(
+            &patch,
+            "spec: {template: {spec: {containers: [{image: proxy:2}]}}}",
+        )
+        .unwrap();
+        assert_eq!(
+            read(&[
+                "rq",
+                "--merge",
+                &merge,
+                "--merge-strategy",
+                "strategic",
+                "--merge-key",
+                ".spec.template.spec.containers=image",
+            ]),
+            r#"{"replicas": 1, "paused": false, "template": {"spec": {"containers": [{"name": "app", "image": "app:1", "ports": [{"containerPort": 8080}]}, {"name": "sidecar", "image": "proxy:1"}, {"image": "proxy:2"}]}}}"#
+        );
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!("containers"
+            .parse::<rq::transform::merge::MergeKey>()
+            .is_err());
+        assert!("theirs".parse::<rq::transform::merge::Strategy>().is_err());
     }
 
     #[test]
-    fn test_docopt_input_smile() {
-        let a = parse_args(&["rq", "-s"]);
-        assert!(a.flag_input_smile);
+    fn test_compress_gzip_members() {
+        use std::io::Read;
+
+        let dir = env::temp_dir().join(format!("rq-gzip-members-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let output = dir.join("out.json.gz");
+        let path = output.to_str().unwrap();
+
+        // Each append adds a gzip member, like rotated logs that are concatenated
+        for (level, input) in &[("1", "{\"a\": 1}"), ("9", "{\"b\": 2}")] {
+            let a = parse_args(&[
+                "rq",
+                "-o",
+                path,
+                "--append",
+                "--compress",
+                "gzip",
+                "--compress-level",
+                level,
+            ]);
+            write_output(&a, rq::value::json::source(input.as_bytes()), 0, None).unwrap();
+        }
+        let file = fs::read(&output).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let (_, mut input) = rq::compression::decoder(&file[..]).unwrap();
+        let mut text = String::new();
+        input.read_to_string(&mut text).unwrap();
+        assert_eq!(text, "{\"a\":1}\n{\"b\":2}\n");
+
+        let a = parse_args(&["rq", "--compress", "gzip", "--compress-level", "10"]);
+        assert!(open_sink(&a, Format::Compact, None, None, Box::new(io::sink())).is_err());
     }
 
     #[test]
-    fn test_docopt_input_smile_long() {
-        let a = parse_args(&["rq", "--input-smile"]);
-        assert!(a.flag_input_smile);
+    fn test_docopt_input_encoding() {
+        use structopt::StructOpt;
+
+        let a = parse_args(&["rq", "--input-encoding", "latin1"]);
+        assert_eq!(a.flag_input_encoding, Some(encoding_rs::WINDOWS_1252));
+        let a = parse_args(&["rq", "--input-encoding", "UTF-16LE"]);
+        assert_eq!(a.flag_input_encoding, Some(encoding_rs::UTF_16LE));
+        assert!(Options::from_iter_safe(&["rq", "--input-encoding", "klingon"]).is_err());
     }
 
     #[test]
-    fn test_docopt_output_smile() {
-        let a = parse_args(&["rq", "-S"]);
-        assert!(a.flag_output_smile);
+    fn test_docopt_output_newline() {
+        use structopt::StructOpt;
+
+        let a = parse_args(&["rq", "--output-newline", "crlf"]);
+        assert_eq!(a.flag_output_newline, Some(rq::output::Newline::Crlf));
+        assert!(Options::from_iter_safe(&["rq", "--output-newline", "cr"]).is_err());
+
+        let mut output = Vec::new();
+        let mut w = rq::output::crlf(&mut output);
+        w.write_all(b"a\nb\r").unwrap();
+        w.write_all(b"\nc\r\n\n").unwrap();
+        assert_eq!(output, b"a\r\nb\r\nc\r\n\r\n");
     }
 
     #[test]
-    fn test_docopt_output_smile_long() {
-        let a = parse_args(&["rq", "--output-smile"]);
-        assert!(a.flag_output_smile);
+    fn test_docopt_detect() {
+        let a = parse_args(&["rq", "detect", "data.bin"]);
+        match a.subcmd {
+            Some(Subcmd::Detect { file }) => assert_eq!(file, path::PathBuf::from("data.bin")),
+            other => panic!("expected detect, got {:?}", other),
+        }
     }
 
     #[test]
-    fn test_docopt_input_protobuf() {
-        let a = parse_args(&["rq", "-p", ".foo.Bar"]);
-        assert_eq!(a.flag_input_protobuf, Some(".foo.Bar".to_owned()));
+    fn test_judge() {
+        let input = b"a,b\n1,2\n";
+        let records = read_all(InputFormat::Csv, input).unwrap();
+        assert_eq!(
+            judge(InputFormat::Csv, input, &records, false),
+            (Confidence::Medium, "2 records with 2 fields".to_owned())
+        );
+        assert!(read_all(InputFormat::Json, input).is_err());
+        let records = read_all(InputFormat::Yaml, input).unwrap();
+        assert_eq!(
+            judge(InputFormat::Yaml, input, &records, false).0,
+            Confidence::Low
+        );
     }
 
     #[test]
-    fn test_docopt_input_protobuf_long() {
-        let a = parse_args(&["rq", "--input-protobuf", ".foo.Bar"]);
-        assert_eq!(a.flag_input_protobuf, Some(".foo.Bar".to_owned()));
+    fn test_guess_formats() {
+        /// The most plausible format, which must be more plausible than all others.
+        fn top_guess(input: &str) -> (InputFormat, Confidence) {
+            let guesses = guess_formats(input.as_bytes());
+            let (format, confidence, _) = guesses[0];
+            let ties: Vec<_> = guesses[1..]
+                .iter()
+                .filter(|&&(_, c, _)| c == confidence)
+                .map(|(format, _, details)| (format.name(), details))
+                .collect();
+            assert!(ties.is_empty(), "{} ties with {:?}", format.name(), ties);
+            (format, confidence)
+        }
+
+        assert_eq!(
+            top_guess("{\"a\": 1, \"b\": [1, 2]}\n"),
+            (InputFormat::Json, Confidence::High)
+        );
+        assert_eq!(
+            top_guess("a,b\n1,2\n"),
+            (InputFormat::Csv, Confidence::Medium)
+        );
+        assert_eq!(
+            top_guess(
+                "[package]\nname = \"rq\"\nkeywords = [\"cli\"]\n\n[dependencies]\nlog = \"0.4\"\n"
+            ),
+            (InputFormat::Toml, Confidence::High)
+        );
+        assert_eq!(
+            top_guess("{:name \"rq\" :tags #{:cli}}\n"),
+            (InputFormat::Edn, Confidence::High)
+        );
+        assert_eq!(
+            top_guess("at=info method=GET path=/\n"),
+            (InputFormat::Logfmt, Confidence::High)
+        );
+        assert_eq!(
+            top_guess("PORT=8080\nDATABASE_URL=postgres://localhost\n"),
+            (InputFormat::Dotenv, Confidence::High)
+        );
+        assert_eq!(
+            top_guess("$ORIGIN example.com.\n@ 3600 IN A 192.0.2.1\n"),
+            (InputFormat::ZoneFile, Confidence::High)
+        );
     }
 
     #[test]
-    fn test_docopt_output_protobuf() {
-        let a = parse_args(&["rq", "-P", ".foo.Bar"]);
-        assert_eq!(a.flag_output_protobuf, Some(".foo.Bar".to_owned()));
+    fn test_docopt_conform_to() {
+        let a = parse_args(&["rq", "--conform-to", "schema.avsc", "-A", "schema.avsc"]);
+        assert_eq!(a.flag_conform_to, Some(path::PathBuf::from("schema.avsc")));
     }
 
     #[test]
-    fn test_docopt_output_protobuf_long() {
-        let a = parse_args(&["rq", "--output-protobuf", ".foo.Bar"]);
-        assert_eq!(a.flag_output_protobuf, Some(".foo.Bar".to_owned()));
+    fn test_docopt_enums() {
+        use structopt::StructOpt;
+
+        let a = parse_args(&["rq", "--conform-to", "s.avsc", "--enum-ignore-case"]);
+        assert!(a.flag_enum_ignore_case);
+        assert!(Options::from_iter_safe(&["rq", "--enum-aliases", "aliases.yaml"]).is_err());
     }
 
     #[test]
-    fn test_docopt_protobuf_add_schema() {
-        let a = parse_args(&["rq", "-l", "info", "protobuf", "add", "schema.proto"]);
-        assert_eq!(a.flag_log, Some("info".to_owned()));
-        assert_eq!(
-            Some(path::PathBuf::from("schema.proto")),
-            match a.subcmd {
-                Some(Subcmd::Protobuf { subcmd }) => match subcmd {
-                    ProtobufSubcmd::Add { schema, .. } => Some(schema),
-                },
-                _ => None,
-            }
-        );
+    fn test_docopt_rpc() {
+        let a = parse_args(&["rq", "--rpc"]);
+        assert!(a.flag_rpc);
+    }
+
+    #[test]
+    fn test_docopt_output_mode() {
+        let a = parse_args(&["rq", "-o", "out.json", "--output-mode", "0640"]);
+        assert_eq!(a.flag_output_mode, Some(0o640));
     }
 
     #[test]
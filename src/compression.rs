@@ -0,0 +1,252 @@
+//! Compressed input and output streams.
+//!
+//! Compressed input is recognized by the magic bytes of its format and decompressed
+//! transparently, while output is only compressed when a codec is asked for.
+
+use crate::error;
+
+use std::fmt;
+use std::io;
+use std::io::BufRead;
+use std::io::Read;
+use std::io::Write;
+use std::str;
+
+/// A compression format.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Codec {
+    Gzip,
+    /// The Snappy framing format, as written by `snzip` and used by Hadoop and Kafka tools.
+    Snappy,
+    /// The LZ4 frame format, as written by the `lz4` command-line tool.
+    Lz4,
+}
+
+const GZIP_MAGIC: &[u8] = b"\x1f\x8b\x08";
+const SNAPPY_MAGIC: &[u8] = b"\xff\x06\x00\x00sNaPpY";
+const LZ4_MAGIC: &[u8] = b"\x04\x22\x4d\x18";
+
+/// A writer that compresses everything written to it.
+///
+/// The trailer of the compression format is written when the encoder is dropped.  Call `finish`
+/// before that to find out whether it could be written.
+pub struct Encoder<'a>(Inner<'a>);
+
+enum Inner<'a> {
+    Gzip(flate2::write::GzEncoder<Box<dyn Write + 'a>>),
+    Snappy(Box<snap::write::FrameEncoder<Box<dyn Write + 'a>>>),
+    Lz4(lz4_flex::frame::FrameEncoder<Box<dyn Write + 'a>>),
+    Finished,
+}
+
+impl Codec {
+    /// The codec whose magic bytes start the input, if any.
+    pub fn detect(input: &[u8]) -> Option<Self> {
+        if input.starts_with(GZIP_MAGIC) {
+            Some(Self::Gzip)
+        } else if input.starts_with(SNAPPY_MAGIC) {
+            Some(Self::Snappy)
+        } else if input.starts_with(LZ4_MAGIC) {
+            Some(Self::Lz4)
+        } else {
+            None
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Gzip => "gzip",
+            Self::Snappy => "snappy",
+            Self::Lz4 => "lz4",
+        }
+    }
+}
+
+/// Wraps the input in a decoder if it starts with the magic bytes of a compression format, and
+/// returns the codec that was detected.
+pub fn decoder<'a, R>(mut input: R) -> io::Result<(Option<Codec>, Box<dyn BufRead + 'a>)>
+where
+    R: BufRead + 'a,
+{
+    let codec = Codec::detect(input.fill_buf()?);
+    let input: Box<dyn BufRead + 'a> = match codec {
+        None => Box::new(input),
+        Some(codec) => Box::new(io::BufReader::new(reader(codec, input))),
+    };
+    if let Some(codec) = codec {
+        debug!("Decompressing {} input", codec);
+    }
+    Ok((codec, input))
+}
+
+/// Decompresses a buffer that was compressed with the codec.
+pub fn decompress(codec: Codec, input: &[u8]) -> io::Result<Vec<u8>> {
+    let mut output = Vec::new();
+    reader(codec, input).read_to_end(&mut output)?;
+    Ok(output)
+}
+
+fn reader<'a, R>(codec: Codec, input: R) -> Box<dyn Read + 'a>
+where
+    R: BufRead + 'a,
+{
+    match codec {
+        // Concatenated members are valid gzip, as written by log rotation that appends to files
+        Codec::Gzip => Box::new(flate2::bufread::MultiGzDecoder::new(input)),
+        Codec::Snappy => Box::new(snap::read::FrameDecoder::new(input)),
+        Codec::Lz4 => Box::new(lz4_flex::frame::FrameDecoder::new(input)),
+    }
+}
+
+/// Creates a writer that compresses its output with the codec.  The level only applies to gzip,
+/// from 0 (no compression) to 9 (best compression), since the Snappy and LZ4 encoders have just
+/// one level.
+pub fn encoder<'a>(
+    codec: Codec,
+    level: Option<u32>,
+    output: Box<dyn Write + 'a>,
+) -> error::Result<Encoder<'a>> {
+    match (codec, level) {
+        (Codec::Gzip, Some(level)) if level > 9 => {
+            return Err(error::Error::Message(format!(
+                "illegal gzip compression level {} (expected 0 to 9)",
+                level
+            )));
+        }
+        (Codec::Snappy, Some(_)) | (Codec::Lz4, Some(_)) => {
+            warn!("The compression level has no effect on {} output", codec);
+        }
+        _ => (),
+    }
+    Ok(Encoder(match codec {
+        Codec::Gzip => Inner::Gzip(flate2::write::GzEncoder::new(
+            output,
+            level.map_or_else(flate2::Compression::default, flate2::Compression::new),
+        )),
+        Codec::Snappy => Inner::Snappy(Box::new(snap::write::FrameEncoder::new(output))),
+        Codec::Lz4 => Inner::Lz4(lz4_flex::frame::FrameEncoder::new(output)),
+    }))
+}
+
+impl Encoder<'_> {
+    /// Writes the trailer of the compression format, after which nothing more can be written.
+    pub fn finish(&mut self) -> io::Result<()> {
+        match std::mem::replace(&mut self.0, Inner::Finished) {
+            Inner::Gzip(encoder) => encoder.finish()?.flush(),
+            Inner::Snappy(encoder) => encoder
+                .into_inner()
+                .map_err(|e| io::Error::new(e.error().kind(), e.error().to_string()))?
+                .flush(),
+            Inner::Lz4(encoder) => encoder.finish().map_err(io::Error::other)?.flush(),
+            Inner::Finished => Ok(()),
+        }
+    }
+
+    fn writer(&mut self) -> io::Result<&mut dyn Write> {
+        match self.0 {
+            Inner::Gzip(ref mut encoder) => Ok(encoder),
+            Inner::Snappy(ref mut encoder) => Ok(&mut **encoder),
+            Inner::Lz4(ref mut encoder) => Ok(encoder),
+            Inner::Finished => Err(io::Error::other("compressed output is already finished")),
+        }
+    }
+}
+
+impl Write for Encoder<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer()?.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer()?.flush()
+    }
+}
+
+impl Drop for Encoder<'_> {
+    fn drop(&mut self) {
+        if let Err(e) = self.finish() {
+            error!("Failed to finish compressed output: {}", e);
+        }
+    }
+}
+
+impl fmt::Debug for Encoder<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let codec = match self.0 {
+            Inner::Gzip(_) => "gzip",
+            Inner::Snappy(_) => "snappy",
+            Inner::Lz4(_) => "lz4",
+            Inner::Finished => "finished",
+        };
+        f.debug_tuple("Encoder").field(&codec).finish()
+    }
+}
+
+impl fmt::Display for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl str::FromStr for Codec {
+    type Err = error::Error;
+
+    fn from_str(s: &str) -> error::Result<Self> {
+        match s {
+            "gzip" | "gz" => Ok(Self::Gzip),
+            "snappy" => Ok(Self::Snappy),
+            "lz4" => Ok(Self::Lz4),
+            _ => Err(error::Error::Message(format!(
+                "unknown compression codec {:?} (expected gzip, snappy or lz4)",
+                s
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn compress(codec: Codec, level: Option<u32>, input: &[u8]) -> Vec<u8> {
+        let mut output = Vec::new();
+        {
+            let mut encoder = encoder(codec, level, Box::new(&mut output)).unwrap();
+            encoder.write_all(input).unwrap();
+            encoder.finish().unwrap();
+        }
+        output
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for codec in &[Codec::Gzip, Codec::Snappy, Codec::Lz4] {
+            let output = compress(*codec, None, b"{\"a\":1}\n");
+            assert_eq!(Codec::detect(&output), Some(*codec));
+            assert_eq!(codec.name().parse::<Codec>().unwrap(), *codec);
+
+            let (detected, mut input) = decoder(&output[..]).unwrap();
+            assert_eq!(detected, Some(*codec));
+            let mut text = String::new();
+            input.read_to_string(&mut text).unwrap();
+            assert_eq!(text, "{\"a\":1}\n");
+        }
+
+        let (detected, _) = decoder(&b"{\"a\": 1}"[..]).unwrap();
+        assert_eq!(detected, None);
+        assert!("zip".parse::<Codec>().is_err());
+    }
+
+    #[test]
+    fn test_gzip_members() {
+        // Appending to a file adds a gzip member, like rotated logs that are concatenated
+        let mut file = compress(Codec::Gzip, Some(1), b"{\"a\":1}\n");
+        file.extend(compress(Codec::Gzip, Some(9), b"{\"b\":2}\n"));
+        assert_eq!(
+            decompress(Codec::Gzip, &file).unwrap(),
+            b"{\"a\":1}\n{\"b\":2}\n"
+        );
+
+        assert!(encoder(Codec::Gzip, Some(10), Box::new(io::sink())).is_err());
+    }
+}
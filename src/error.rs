@@ -3,6 +3,7 @@
 use csv;
 use glob;
 use protobuf;
+use regex;
 use rmpv;
 use serde_cbor;
 use serde_hjson;
@@ -56,6 +57,20 @@ pub enum Error {
     Csv(#[cause] csv::Error),
     #[fail(display = "MessagePack decode error")]
     MessagePackDecode(#[cause] rmpv::decode::Error),
+    #[fail(display = "regex error")]
+    Regex(#[cause] regex::Error),
+    #[fail(display = "XML error")]
+    Xml(#[cause] quick_xml::Error),
+    #[fail(display = "Parquet error")]
+    Parquet(#[cause] parquet::errors::ParquetError),
+    #[fail(display = "Arrow error")]
+    Arrow(#[cause] arrow_schema::ArrowError),
+    #[fail(display = "Excel error")]
+    Xlsx(#[cause] calamine::XlsxError),
+    #[fail(display = "SQLite error")]
+    Sqlite(#[cause] rusqlite::Error),
+    #[fail(display = "HCL error")]
+    Hcl(#[cause] hcl::Error),
     #[fail(display = "unimplemented: {}", msg)]
     Unimplemented { msg: String },
     #[fail(display = "illegal state: {}", msg)]
@@ -147,3 +162,10 @@ gen_from!(glob::GlobError, Glob);
 gen_from!(glob::PatternError, GlobPattern);
 gen_from!(csv::Error, Csv);
 gen_from!(rmpv::decode::Error, MessagePackDecode);
+gen_from!(quick_xml::Error, Xml);
+gen_from!(parquet::errors::ParquetError, Parquet);
+gen_from!(arrow_schema::ArrowError, Arrow);
+gen_from!(calamine::XlsxError, Xlsx);
+gen_from!(rusqlite::Error, Sqlite);
+gen_from!(hcl::Error, Hcl);
+gen_from!(regex::Error, Regex);
@@ -0,0 +1,80 @@
+//! Searching values for keys and strings that match a pattern.
+
+use crate::value;
+
+/// Finds the locations of map keys and string values that match a regex.
+#[derive(Debug)]
+pub struct Finder {
+    regex: regex::Regex,
+    keys: bool,
+    strings: bool,
+}
+
+/// A location where the pattern matched.
+#[derive(Debug, PartialEq)]
+pub struct Found<'a> {
+    /// The path to the value; for a matching key, the path to the value stored under it.
+    pub path: value::path::Path,
+    pub value: &'a value::Value,
+}
+
+impl Finder {
+    /// Creates a finder that matches map keys and/or string values.  Keys that aren't strings,
+    /// like integer keys, are matched in their textual form.
+    pub fn new(regex: regex::Regex, keys: bool, strings: bool) -> Self {
+        Self {
+            regex,
+            keys,
+            strings,
+        }
+    }
+
+    /// Returns all matches within the value, in document order, with paths relative to `base`.
+    pub fn find<'a>(&self, value: &'a value::Value, base: value::path::Path) -> Vec<Found<'a>> {
+        let mut found = Vec::new();
+        self.search(value, base, &mut found);
+        found
+    }
+
+    fn search<'a>(
+        &self,
+        value: &'a value::Value,
+        path: value::path::Path,
+        found: &mut Vec<Found<'a>>,
+    ) {
+        match *value {
+            value::Value::String(ref s) => {
+                // A key with a matching value is only reported once
+                let reported = found.last().is_some_and(|f| f.path == path);
+                if self.strings && !reported && self.regex.is_match(s) {
+                    found.push(Found { path, value });
+                }
+            }
+            value::Value::Sequence(ref elements) => {
+                for (i, element) in elements.iter().enumerate() {
+                    let mut child = path.clone();
+                    child.push(i.to_string());
+                    self.search(element, child, found);
+                }
+            }
+            value::Value::Map(ref entries) => {
+                for (key, entry) in entries {
+                    let key = match key.as_str() {
+                        Some(key) => key.to_owned(),
+                        None => key.to_string(),
+                    };
+                    let mut child = path.clone();
+                    child.push(key.as_str());
+                    if self.keys && self.regex.is_match(&key) {
+                        found.push(Found {
+                            path: child.clone(),
+                            value: entry,
+                        });
+                    }
+                    self.search(entry, child, found);
+                }
+            }
+            _ => (),
+        }
+    }
+}
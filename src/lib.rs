@@ -17,11 +17,19 @@ extern crate failure;
 #[macro_use]
 extern crate log;
 
+pub mod compression;
 pub mod config;
 pub mod error;
+pub mod find;
+pub mod output;
 pub mod proto_index;
+pub mod stats;
+pub mod transform;
 pub mod value;
 
+#[doc(hidden)]
+pub use serde_json as __serde_json;
+
 pub const VERSION: &str = env!("VERGEN_GIT_SEMVER");
 
 #[doc(hidden)]
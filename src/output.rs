@@ -0,0 +1,1038 @@
+//! Support for writing records to output files rather than to stdout.
+
+use crate::error;
+use crate::transform;
+use crate::value;
+
+use std::cell;
+use std::ffi;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path;
+use std::process;
+use std::rc;
+use std::str;
+
+/// When to move on to the next output file.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Rotation {
+    /// Start a new file once the current one has grown to at least this many bytes.
+    Size(u64),
+    /// Start a new file once this many records have been written to the current one.
+    Count(u64),
+}
+
+/// The line ending to use in text output.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Newline {
+    Lf,
+    Crlf,
+}
+
+/// A template for output file paths, with placeholders like `{record.date}` that are replaced
+/// with fields of each record.  Literal braces are written as `{{` and `}}`.
+#[derive(Clone, Debug)]
+pub struct PathTemplate(Vec<TemplatePart>);
+
+#[derive(Clone, Debug)]
+enum TemplatePart {
+    Literal(String),
+    Field(value::path::Path),
+}
+
+/// A random split of the records over several output files, each getting a share of the records.
+#[derive(Clone, Debug)]
+pub struct Split(Vec<(f64, path::PathBuf)>);
+
+/// Picks an output of a `Split` for each record.
+#[derive(Debug)]
+pub struct SplitChooser {
+    cumulative: Vec<f64>,
+    rng: transform::Rng,
+}
+
+/// An output file for the records that match a condition, in its own output format.
+#[derive(Clone, Debug)]
+pub struct Route {
+    pub condition: Condition,
+    /// The output format, named like its flag without the `--output-` prefix, like `csv`.
+    pub format: String,
+    pub path: path::PathBuf,
+}
+
+/// A condition on a record, which decides whether it takes a route.
+#[derive(Clone, Debug)]
+pub enum Condition {
+    /// Matches every record, written as `*`.
+    Always,
+    /// The value at the path exists and isn't null or false, like `.error`.
+    Present(value::path::Path),
+    /// The value at the path equals a literal, like `.level == "error"`.  Numbers are compared
+    /// by value, so `1` equals `1.0`.
+    Equals(value::path::Path, value::Value),
+    /// The value at the path doesn't equal a literal, like `.level != debug`.
+    NotEquals(value::path::Path, value::Value),
+    /// The value at the path is a string that matches a regex, like `.message =~ ^timeout`.
+    Matches(value::path::Path, regex::Regex),
+    /// The value at the path has one of some types, like `.duration:number`.
+    Type(transform::types::Check),
+}
+
+/// A writer that turns every line feed into a carriage return and line feed, unless it already
+/// is preceded by a carriage return.
+#[derive(Debug)]
+pub struct Crlf<W>(W, bool);
+
+/// How to open output files.
+#[derive(Clone, Debug, Default)]
+pub struct FileOptions {
+    /// Append to an existing file instead of replacing it.
+    pub append: bool,
+    /// Write to a temporary file that is renamed to the final path when it is complete, so that
+    /// nobody gets to see a half-written file.  Doesn't apply when appending.
+    pub atomic: bool,
+    /// The permission bits for output files (on Unix), like `0o640`, which files that are
+    /// replaced or appended to get as well.  The process umask is still applied on top of this.
+    pub mode: Option<u32>,
+    /// The owner to give output files (on Unix).
+    pub owner: Option<Owner>,
+}
+
+/// The owner and group of a file, by their numeric IDs.  Whichever is `None` is left as is.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Owner {
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+}
+
+/// An output file that has been opened, but that might not be at its final path yet.
+///
+/// The file must be committed once it has been completely written.  If it is dropped before
+/// that, any temporary file is removed again.
+#[derive(Debug)]
+pub struct Pending {
+    temp_path: Option<path::PathBuf>,
+    path: path::PathBuf,
+}
+
+/// A writer that keeps track of how many bytes have been written through it.
+///
+/// The count is shared with the `ByteCount` handle that is returned on creation, so that it can
+/// be inspected while a sink owns the writer.
+#[derive(Debug)]
+pub struct Counting<W>(W, rc::Rc<cell::Cell<u64>>);
+
+#[derive(Clone, Debug, Default)]
+pub struct ByteCount(rc::Rc<cell::Cell<u64>>);
+
+impl Rotation {
+    /// Whether a file that has received `records` records and `bytes` bytes is full.
+    ///
+    /// The byte count is only approximate, since sinks might buffer some of their output; files
+    /// can end up somewhat larger than the configured size.
+    pub fn is_full(self, records: u64, bytes: u64) -> bool {
+        match self {
+            Self::Size(size) => bytes >= size,
+            Self::Count(count) => records >= count,
+        }
+    }
+}
+
+/// The path of the output file with the specified index, when rotating output files.
+///
+/// The index is inserted before the file extension, so that `out.avro` becomes `out.00000.avro`,
+/// `out.00001.avro`, and so on.
+pub fn numbered_path(path: &path::Path, index: usize) -> path::PathBuf {
+    let stem = path.file_stem().unwrap_or_else(|| ffi::OsStr::new(""));
+    let mut file_name = stem.to_os_string();
+    file_name.push(format!(".{:05}", index));
+    if let Some(extension) = path.extension() {
+        file_name.push(".");
+        file_name.push(extension);
+    }
+    path.with_file_name(file_name)
+}
+
+impl PathTemplate {
+    /// The path that the record should be written to.
+    ///
+    /// Field values must be scalars, and can't contain path separators.  The path after the
+    /// directory that the template starts with must only have plain components, not `.` or `..`
+    /// or a root; this keeps records from writing outside of that directory, even when the values
+    /// of adjacent fields add up to something like `..`.
+    pub fn render(&self, record: &value::Value) -> error::Result<path::PathBuf> {
+        let mut result = String::new();
+        // Where the directory that the template starts with ends
+        let mut base_len = None;
+        for part in &self.0 {
+            match *part {
+                TemplatePart::Literal(ref literal) => result.push_str(literal),
+                TemplatePart::Field(ref field) => {
+                    if base_len.is_none() {
+                        base_len = Some(result.rfind(path::is_separator).map_or(0, |i| i + 1));
+                    }
+                    let component = match field.get(record) {
+                        Some(value::Value::String(s)) => s.clone(),
+                        Some(value::Value::Char(c)) => c.to_string(),
+                        Some(v @ value::Value::Unit)
+                        | Some(v @ value::Value::Bytes(_))
+                        | Some(v @ value::Value::Sequence(_))
+                        | Some(v @ value::Value::Map(_)) => {
+                            return Err(error::Error::Message(format!(
+                                "field {} can't be used in an output path: {}",
+                                field,
+                                v.summary(value::ERROR_SUMMARY_LEN)
+                            )))
+                        }
+                        Some(v) => v.to_string(),
+                        None => {
+                            return Err(error::Error::Message(format!(
+                                "record has no field {} for the output path",
+                                field
+                            )))
+                        }
+                    };
+                    if component.is_empty() || component.contains(['/', '\\', '\0']) {
+                        return Err(error::Error::Message(format!(
+                            "field {} is not a valid path component: {:?}",
+                            field, component
+                        )));
+                    }
+                    result.push_str(&component);
+                }
+            }
+        }
+        if let Some(base_len) = base_len {
+            let rendered = &result[base_len..];
+            let plain = path::Path::new(rendered)
+                .components()
+                .all(|c| matches!(c, path::Component::Normal(_)));
+            if !plain {
+                return Err(error::Error::Message(format!(
+                    "the output path {:?} leaves the directory of the template",
+                    result
+                )));
+            }
+        }
+        Ok(path::PathBuf::from(result))
+    }
+}
+
+impl Split {
+    /// The paths of the output files, in the order that they were specified.
+    pub fn paths(&self) -> impl Iterator<Item = &path::Path> {
+        self.0.iter().map(|(_, path)| path.as_path())
+    }
+
+    /// Creates a chooser of outputs.  The same seed always makes the same choices.
+    pub fn chooser(&self, seed: u64) -> SplitChooser {
+        let total: f64 = self.0.iter().map(|&(weight, _)| weight).sum();
+        let mut sum = 0.0;
+        let cumulative = self
+            .0
+            .iter()
+            .map(|&(weight, _)| {
+                sum += weight / total;
+                sum
+            })
+            .collect();
+        SplitChooser {
+            cumulative,
+            rng: transform::Rng::for_stream(seed, transform::SPLIT),
+        }
+    }
+}
+
+impl SplitChooser {
+    /// The index of the output that the next record should go to.
+    pub fn choose(&mut self) -> usize {
+        let x = self.rng.next_f64();
+        self.cumulative
+            .iter()
+            .position(|&c| x < c)
+            .unwrap_or(self.cumulative.len() - 1)
+    }
+}
+
+impl Condition {
+    pub fn matches(&self, record: &value::Value) -> bool {
+        match *self {
+            Self::Always => true,
+            Self::Present(ref path) => !matches!(
+                path.get(record),
+                None | Some(value::Value::Unit) | Some(value::Value::Bool(false))
+            ),
+            Self::Equals(ref path, ref literal) => {
+                path.get(record).is_some_and(|v| values_equal(v, literal))
+            }
+            Self::NotEquals(ref path, ref literal) => {
+                !path.get(record).is_some_and(|v| values_equal(v, literal))
+            }
+            Self::Matches(ref path, ref regex) => path
+                .get(record)
+                .and_then(value::Value::as_str)
+                .is_some_and(|s| regex.is_match(s)),
+            Self::Type(ref check) => check.matches(record),
+        }
+    }
+}
+
+fn values_equal(a: &value::Value, b: &value::Value) -> bool {
+    match (a.as_str(), b.as_str()) {
+        (Some(a), Some(b)) => a == b,
+        _ => match (a.as_f64(), b.as_f64()) {
+            (Some(a), Some(b)) => a == b,
+            _ => a == b,
+        },
+    }
+}
+
+/// Opens an output file for writing.
+pub fn open(path: &path::Path, options: &FileOptions) -> io::Result<(fs::File, Pending)> {
+    let mut open_options = fs::OpenOptions::new();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        if let Some(mode) = options.mode {
+            open_options.mode(mode);
+        }
+    }
+
+    if options.append {
+        let file = open_options.append(true).create(true).open(path)?;
+        set_mode_and_owner(&file, options)?;
+        let pending = Pending {
+            temp_path: None,
+            path: path.to_owned(),
+        };
+        return Ok((file, pending));
+    }
+    let target = if options.atomic {
+        replace_target(path)?
+    } else {
+        None
+    };
+    if let Some((path, existing)) = target {
+        // The temporary file must be in the same directory, since renames can't cross file
+        // systems
+        let mut temp_name = ffi::OsString::from(".");
+        temp_name.push(
+            path.file_name()
+                .unwrap_or_else(|| ffi::OsStr::new("output")),
+        );
+        temp_name.push(format!(".{}.tmp", process::id()));
+        let temp_path = path.with_file_name(temp_name);
+
+        let file = open_options.write(true).create_new(true).open(&temp_path)?;
+        let pending = Pending {
+            temp_path: Some(temp_path),
+            path,
+        };
+        if let Some(existing) = existing {
+            copy_metadata(&file, &existing)?;
+        }
+        set_mode_and_owner(&file, options)?;
+        Ok((file, pending))
+    } else {
+        let file = open_options
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        set_mode_and_owner(&file, options)?;
+        let pending = Pending {
+            temp_path: None,
+            path: path.to_owned(),
+        };
+        Ok((file, pending))
+    }
+}
+
+/// The file that an atomic write should replace, which is the target of a symbolic link, and
+/// its metadata if it exists already.  Files with other hard links can't be replaced without
+/// breaking the links, and neither can the targets of dangling symbolic links be found, so
+/// these are written in place instead.
+fn replace_target(path: &path::Path) -> io::Result<Option<(path::PathBuf, Option<fs::Metadata>)>> {
+    let path = match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_symlink() => match fs::canonicalize(path) {
+            Ok(target) => target,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        },
+        Ok(_) => path.to_owned(),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+            return Ok(Some((path.to_owned(), None)))
+        }
+        Err(e) => return Err(e),
+    };
+    let metadata = fs::metadata(&path)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        if metadata.nlink() > 1 {
+            debug!("Writing {:?} in place, since it has other hard links", path);
+            return Ok(None);
+        }
+    }
+    Ok(Some((path, Some(metadata))))
+}
+
+/// Gives a file that replaces another one the permissions and, as far as allowed, the owner of
+/// the file that it replaces.
+fn copy_metadata(file: &fs::File, existing: &fs::Metadata) -> io::Result<()> {
+    // The owner comes first, since changing it can clear the setuid and setgid bits
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        // Only root can give files away, but owners can still change the group to one of theirs
+        if std::os::unix::fs::fchown(file, Some(existing.uid()), Some(existing.gid())).is_err() {
+            let _ = std::os::unix::fs::fchown(file, None, Some(existing.gid()));
+        }
+    }
+    file.set_permissions(existing.permissions())
+}
+
+/// Gives an output file the permissions and owner of the options, which only apply when a file
+/// is created otherwise.
+fn set_mode_and_owner(file: &fs::File, options: &FileOptions) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if let Some(owner) = options.owner {
+            std::os::unix::fs::fchown(file, owner.uid, owner.gid)?;
+        }
+        if let Some(mode) = options.mode {
+            file.set_permissions(fs::Permissions::from_mode(mode & !umask()))?;
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        if options.mode.is_some() || options.owner.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "output file permissions and owners are only supported on Unix",
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// The umask of the process, which can only be read by setting it.
+#[cfg(unix)]
+#[allow(clippy::useless_conversion)] // mode_t is only u16 on some systems
+fn umask() -> u32 {
+    use nix::sys::stat;
+    let umask = stat::umask(stat::Mode::empty());
+    stat::umask(umask);
+    u32::from(umask.bits())
+}
+
+/// Parses file permission bits written in octal, like `0640`.
+pub fn parse_mode(s: &str) -> error::Result<u32> {
+    match u32::from_str_radix(s, 8) {
+        Ok(mode) if mode <= 0o7777 => Ok(mode),
+        _ => Err(error::Error::Message(format!(
+            "invalid file mode (expected octal permission bits like 0640): {}",
+            s
+        ))),
+    }
+}
+
+/// Parses an owner like chown does, as `user`, `user:group` or `:group`, where users and groups
+/// are names or numeric IDs.
+pub fn parse_owner(s: &str) -> error::Result<Owner> {
+    let (user, group) = match s.split_once(':') {
+        Some((user, group)) => (user, Some(group)),
+        None => (s, None),
+    };
+    let invalid = |what: &str| {
+        error::Error::Message(format!("invalid output owner (unknown {}): {}", what, s))
+    };
+    let uid = match user {
+        "" => None,
+        user => Some(match user.parse() {
+            Ok(uid) => uid,
+            Err(_) => lookup_user(user).ok_or_else(|| invalid("user"))?,
+        }),
+    };
+    let gid = match group {
+        None | Some("") => None,
+        Some(group) => Some(match group.parse() {
+            Ok(gid) => gid,
+            Err(_) => lookup_group(group).ok_or_else(|| invalid("group"))?,
+        }),
+    };
+    if uid.is_none() && gid.is_none() {
+        return Err(error::Error::Message(format!(
+            "invalid output owner (expected user, user:group or :group): {}",
+            s
+        )));
+    }
+    Ok(Owner { uid, gid })
+}
+
+#[cfg(unix)]
+fn lookup_user(name: &str) -> Option<u32> {
+    let user = nix::unistd::User::from_name(name).ok()??;
+    Some(user.uid.as_raw())
+}
+
+#[cfg(unix)]
+fn lookup_group(name: &str) -> Option<u32> {
+    let group = nix::unistd::Group::from_name(name).ok()??;
+    Some(group.gid.as_raw())
+}
+
+#[cfg(not(unix))]
+fn lookup_user(_: &str) -> Option<u32> {
+    None
+}
+
+#[cfg(not(unix))]
+fn lookup_group(_: &str) -> Option<u32> {
+    None
+}
+
+impl Pending {
+    /// Moves the file to its final path.  All writers for the file should be flushed first.
+    pub fn commit(mut self) -> io::Result<()> {
+        if let Some(temp_path) = self.temp_path.take() {
+            trace!("Renaming {:?} to {:?}", temp_path, self.path);
+            if let Err(e) = fs::rename(&temp_path, &self.path) {
+                let _ = fs::remove_file(&temp_path);
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        if let Some(ref temp_path) = self.temp_path {
+            debug!("Removing incomplete output file {:?}", temp_path);
+            let _ = fs::remove_file(temp_path);
+        }
+    }
+}
+
+pub fn counting<W>(w: W) -> (Counting<W>, ByteCount)
+where
+    W: io::Write,
+{
+    let count = rc::Rc::new(cell::Cell::new(0));
+    (Counting(w, count.clone()), ByteCount(count))
+}
+
+pub fn crlf<W>(w: W) -> Crlf<W>
+where
+    W: io::Write,
+{
+    Crlf(w, false)
+}
+
+impl ByteCount {
+    pub fn get(&self) -> u64 {
+        self.0.get()
+    }
+}
+
+impl<W> io::Write for Counting<W>
+where
+    W: io::Write,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.0.write(buf)?;
+        self.1.set(self.1.get() + n as u64);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl<W> io::Write for Crlf<W>
+where
+    W: io::Write,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut start = 0;
+        for (i, &b) in buf.iter().enumerate() {
+            let after_cr = if i == 0 { self.1 } else { buf[i - 1] == b'\r' };
+            if b == b'\n' && !after_cr {
+                self.0.write_all(&buf[start..i])?;
+                self.0.write_all(b"\r")?;
+                start = i;
+            }
+        }
+        self.0.write_all(&buf[start..])?;
+        if let Some(&last) = buf.last() {
+            self.1 = last == b'\r';
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl fmt::Display for Newline {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Self::Lf => f.write_str("lf"),
+            Self::Crlf => f.write_str("crlf"),
+        }
+    }
+}
+
+impl str::FromStr for Newline {
+    type Err = error::Error;
+
+    fn from_str(s: &str) -> error::Result<Self> {
+        match s {
+            "lf" => Ok(Self::Lf),
+            "crlf" => Ok(Self::Crlf),
+            _ => Err(error::Error::Message(format!(
+                "invalid newline {:?} (expected lf or crlf)",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for Rotation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Self::Size(size) => write!(f, "size={}", size),
+            Self::Count(count) => write!(f, "count={}", count),
+        }
+    }
+}
+
+impl str::FromStr for Rotation {
+    type Err = error::Error;
+
+    /// Parses rotation specs like `size=500M` or `count=1e6`.
+    fn from_str(s: &str) -> error::Result<Self> {
+        let invalid = || error::Error::Message(format!("invalid output rotation: {}", s));
+
+        match s.split_once('=') {
+            Some(("size", size)) => {
+                let (digits, multiplier) = match size.char_indices().last() {
+                    Some((i, 'K')) | Some((i, 'k')) => (&size[..i], 1 << 10),
+                    Some((i, 'M')) | Some((i, 'm')) => (&size[..i], 1 << 20),
+                    Some((i, 'G')) | Some((i, 'g')) => (&size[..i], 1 << 30),
+                    Some((i, 'T')) | Some((i, 't')) => (&size[..i], 1 << 40),
+                    _ => (size, 1),
+                };
+                match digits
+                    .parse::<u64>()
+                    .ok()
+                    .and_then(|v| v.checked_mul(multiplier))
+                {
+                    Some(size) if size > 0 => Ok(Self::Size(size)),
+                    _ => Err(invalid()),
+                }
+            }
+            Some(("count", count)) => {
+                // Allow scientific notation like `1e6`, as long as the result is a whole number
+                let count = count.parse::<f64>().map_err(|_| invalid())?;
+                if count >= 1.0 && count.fract() == 0.0 && count <= u64::MAX as f64 {
+                    Ok(Self::Count(count as u64))
+                } else {
+                    Err(invalid())
+                }
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for Split {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, (weight, path)) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, "{}:{}", weight, path.display())?;
+        }
+        Ok(())
+    }
+}
+
+impl str::FromStr for Split {
+    type Err = error::Error;
+
+    /// Parses splits like `0.8:train.json,0.2:valid.json`.  The shares don't have to add up to
+    /// 1, since they are relative to each other.
+    fn from_str(s: &str) -> error::Result<Self> {
+        let invalid = || error::Error::Message(format!("invalid output split: {}", s));
+
+        let targets = s
+            .split(',')
+            .map(|target| {
+                let (weight, path) = target.split_once(':').ok_or_else(invalid)?;
+                match weight.parse::<f64>() {
+                    Ok(weight) if weight > 0.0 && weight.is_finite() && !path.is_empty() => {
+                        Ok((weight, path::PathBuf::from(path)))
+                    }
+                    _ => Err(invalid()),
+                }
+            })
+            .collect::<error::Result<Vec<_>>>()?;
+        if targets.len() < 2 {
+            return Err(error::Error::Message(format!(
+                "an output split needs at least two outputs: {}",
+                s
+            )));
+        }
+        Ok(Self(targets))
+    }
+}
+
+impl fmt::Display for Route {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} => {}:{}",
+            self.condition,
+            self.format,
+            self.path.display()
+        )
+    }
+}
+
+impl str::FromStr for Route {
+    type Err = error::Error;
+
+    /// Parses routes like `.level == "error" => json:errors.json`.
+    fn from_str(s: &str) -> error::Result<Self> {
+        let (condition, target) = s.rsplit_once("=>").ok_or_else(|| {
+            error::Error::Message(format!(
+                "invalid route (expected condition => format:path): {}",
+                s
+            ))
+        })?;
+        let (format, path) = match target.trim().split_once(':') {
+            Some((format, path)) if !format.is_empty() && !path.is_empty() => (format, path),
+            _ => {
+                return Err(error::Error::Message(format!(
+                    "invalid route target (expected format:path): {}",
+                    target.trim()
+                )))
+            }
+        };
+        Ok(Self {
+            condition: condition.trim().parse()?,
+            format: format.to_owned(),
+            path: path::PathBuf::from(path),
+        })
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Self::Always => write!(f, "*"),
+            Self::Present(ref path) => write!(f, "{}", path),
+            Self::Equals(ref path, ref literal) => write!(f, "{} == {}", path, literal),
+            Self::NotEquals(ref path, ref literal) => write!(f, "{} != {}", path, literal),
+            Self::Matches(ref path, ref regex) => write!(f, "{} =~ {}", path, regex),
+            Self::Type(ref check) => write!(f, "{}", check),
+        }
+    }
+}
+
+impl str::FromStr for Condition {
+    type Err = error::Error;
+
+    /// Parses conditions like `*`, `.error`, `.level == "error"`, `.level != debug`,
+    /// `.message =~ ^timeout` or `.duration:number`.  Literals are read as JSON, and anything
+    /// that isn't valid JSON is a string.
+    fn from_str(s: &str) -> error::Result<Self> {
+        let s = s.trim();
+        if s == "*" {
+            return Ok(Self::Always);
+        }
+        // The operator that comes first separates the path from the operand, which can contain
+        // other operators
+        let operator = ["==", "!=", "=~"]
+            .iter()
+            .filter_map(|&operator| s.find(operator).map(|i| (i, operator)))
+            .min();
+        if let Some((i, operator)) = operator {
+            let path = value::path::Path::from(s[..i].trim());
+            let operand = s[i + operator.len()..].trim();
+            if operator == "=~" {
+                let regex = regex::Regex::new(operand).map_err(|e| {
+                    error::Error::Message(format!("invalid regex in condition {}: {}", s, e))
+                })?;
+                return Ok(Self::Matches(path, regex));
+            }
+            let literal = serde_json::from_str::<serde_json::Value>(operand)
+                .map(value::Value::from)
+                .unwrap_or_else(|_| value::Value::String(operand.to_owned()));
+            return Ok(if operator == "==" {
+                Self::Equals(path, literal)
+            } else {
+                Self::NotEquals(path, literal)
+            });
+        }
+        if s.contains(':') {
+            return s.parse().map(Self::Type);
+        }
+        if s.is_empty() {
+            return Err(error::Error::Message("empty condition".to_owned()));
+        }
+        Ok(Self::Present(value::path::Path::from(s)))
+    }
+}
+
+impl fmt::Display for PathTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for part in &self.0 {
+            match *part {
+                TemplatePart::Literal(ref literal) => {
+                    write!(f, "{}", literal.replace('{', "{{").replace('}', "}}"))?
+                }
+                TemplatePart::Field(ref field) if field.is_root() => write!(f, "{{record}}")?,
+                TemplatePart::Field(ref field) => write!(f, "{{record{}}}", field)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl str::FromStr for PathTemplate {
+    type Err = error::Error;
+
+    /// Parses templates like `{record.date}/{record.tenant}.ndjson`.
+    fn from_str(s: &str) -> error::Result<Self> {
+        let invalid =
+            |msg: &str| error::Error::Message(format!("invalid output path template: {}", msg));
+
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let rest = chars.as_str();
+                    let end = rest.find('}').ok_or_else(|| invalid("unterminated '{'"))?;
+                    let field = match rest[..end].strip_prefix("record") {
+                        Some(field) if field.is_empty() || field.starts_with('.') => field,
+                        _ => return Err(invalid("placeholders must look like {record.field}")),
+                    };
+                    if !literal.is_empty() {
+                        parts.push(TemplatePart::Literal(literal.split_off(0)));
+                    }
+                    parts.push(TemplatePart::Field(value::path::Path::from(field)));
+                    chars = rest[end + 1..].chars();
+                }
+                '}' => return Err(invalid("unmatched '}'")),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(TemplatePart::Literal(literal));
+        }
+        Ok(Self(parts))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::env;
+    use std::io::Write;
+
+    fn temp_dir(name: &str) -> path::PathBuf {
+        let dir = env::temp_dir().join(format!("rq-output-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn replace(path: &path::Path, contents: &str) {
+        let options = FileOptions {
+            atomic: true,
+            ..FileOptions::default()
+        };
+        let (mut file, pending) = open(path, &options).unwrap();
+        file.write_all(contents.as_bytes()).unwrap();
+        drop(file);
+        pending.commit().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_atomic_keeps_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = temp_dir("permissions");
+        let path = dir.join("out.json");
+        fs::write(&path, "old").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+        replace(&path, "new");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o7777, 0o600);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_atomic_keeps_owner() {
+        use std::os::unix::fs::MetadataExt;
+
+        let dir = temp_dir("owner");
+        let path = dir.join("out.json");
+        fs::write(&path, "old").unwrap();
+        // Only root can give the file to someone else, otherwise it stays with us
+        let _ = std::os::unix::fs::chown(&path, Some(4321), Some(4321));
+        let before = fs::metadata(&path).unwrap();
+        replace(&path, "new");
+        let after = fs::metadata(&path).unwrap();
+        assert_eq!((after.uid(), after.gid()), (before.uid(), before.gid()));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_atomic_through_symlink() {
+        let dir = temp_dir("symlink");
+        let target = dir.join("target.json");
+        let link = dir.join("link.json");
+        fs::write(&target, "old").unwrap();
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+        replace(&link, "new");
+        assert!(fs::symlink_metadata(&link)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert_eq!(fs::read_to_string(&target).unwrap(), "new");
+
+        // Dangling links are written through, creating their target
+        fs::remove_file(&target).unwrap();
+        replace(&link, "created");
+        assert_eq!(fs::read_to_string(&target).unwrap(), "created");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_atomic_keeps_hard_links() {
+        let dir = temp_dir("hard-link");
+        let path = dir.join("out.json");
+        let other = dir.join("other.json");
+        fs::write(&path, "old").unwrap();
+        fs::hard_link(&path, &other).unwrap();
+        replace(&path, "new");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert_eq!(fs::read_to_string(&other).unwrap(), "new");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_mode_for_existing_files() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = temp_dir("mode");
+        let path = dir.join("out.json");
+        let modes = [(true, false), (false, true), (false, false)];
+        for &(atomic, append) in &modes {
+            fs::write(&path, "old").unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+            let options = FileOptions {
+                append,
+                atomic,
+                mode: Some(0o600),
+                owner: None,
+            };
+            let (file, pending) = open(&path, &options).unwrap();
+            drop(file);
+            pending.commit().unwrap();
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(
+                mode & 0o7777,
+                0o600,
+                "atomic: {}, append: {}",
+                atomic,
+                append
+            );
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_render_stays_in_directory() {
+        let record = crate::value!({"a": ".", "b": ".", "c": "x", "n": 1});
+        let render = |template: &str| template.parse::<PathTemplate>().unwrap().render(&record);
+
+        assert_eq!(
+            render("out/{record.c}{record.n}/{record.c}.json").unwrap(),
+            path::PathBuf::from("out/x1/x.json")
+        );
+        assert_eq!(
+            render("/tmp/../out/{record.c}.json").unwrap(),
+            path::PathBuf::from("/tmp/../out/x.json")
+        );
+        assert_eq!(
+            render("out/{record.a}json").unwrap(),
+            path::PathBuf::from("out/.json")
+        );
+        // Adjacent fields that add up to `..`, and literals that do with a field
+        for template in &[
+            "{record.a}{record.b}/x.json",
+            "out/{record.a}{record.b}/x.json",
+            "out/{record.a}./x.json",
+            "out/{record.c}/../../x.json",
+            "out/{record.a}",
+        ] {
+            assert!(render(template).is_err(), "{}", template);
+        }
+        assert!(render("out/{record.missing}.json").is_err());
+    }
+
+    #[test]
+    fn test_condition_operators() {
+        let condition = |s: &str| s.parse::<Condition>().unwrap();
+        let record = crate::value!({"x": "a==b", "a": "x==y", "b": "c!=d"});
+        match condition(".x =~ a==b") {
+            Condition::Matches(_, regex) => assert_eq!(regex.as_str(), "a==b"),
+            other => panic!("expected a regex match, got {:?}", other),
+        }
+        assert!(condition(".x =~ a==b").matches(&record));
+        match condition(r#".a != "x==y""#) {
+            Condition::NotEquals(_, v) => assert_eq!(v, crate::value!("x==y")),
+            other => panic!("expected an inequality, got {:?}", other),
+        }
+        assert!(!condition(r#".a != "x==y""#).matches(&record));
+        assert!(condition(".b == c!=d").matches(&record));
+    }
+
+    #[test]
+    fn test_parse_owner() {
+        let owner = |uid, gid| Owner { uid, gid };
+        assert_eq!(parse_owner("1000").unwrap(), owner(Some(1000), None));
+        assert_eq!(parse_owner("1000:50").unwrap(), owner(Some(1000), Some(50)));
+        assert_eq!(parse_owner(":50").unwrap(), owner(None, Some(50)));
+        #[cfg(unix)]
+        assert_eq!(parse_owner("root:root").unwrap(), owner(Some(0), Some(0)));
+        assert!(parse_owner(":").is_err());
+        assert!(parse_owner("no-such-user-here").is_err());
+    }
+}
@@ -77,12 +77,12 @@ where
     P: AsRef<path::Path>,
 {
     if cache.exists() {
-        let cache_metadata = fs::metadata(&cache)?;
+        let cache_metadata = fs::metadata(cache)?;
         let cache_mtime = cache_metadata.modified()?;
         let mut max_proto_mtime = std::time::SystemTime::UNIX_EPOCH;
 
         for proto_file in proto_files.iter() {
-            let proto_metadata = fs::metadata(&proto_file)?;
+            let proto_metadata = fs::metadata(proto_file)?;
             let proto_mtime = proto_metadata.modified()?;
             max_proto_mtime = cmp::max(max_proto_mtime, proto_mtime);
         }
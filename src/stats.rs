@@ -0,0 +1,116 @@
+//! Per-stage statistics about a conversion, used to explain where time is spent.
+
+use crate::error;
+use crate::value;
+
+use std::cell;
+use std::rc;
+use std::time;
+
+/// Counters for one stage of the pipeline.
+///
+/// Stages are shared through `Rc`s, so that they can be inspected after the source or sink that
+/// updates them has been dropped.
+#[derive(Debug, Default)]
+pub struct Stage {
+    records: cell::Cell<u64>,
+    elapsed: cell::Cell<time::Duration>,
+}
+
+/// A source that records how many records it reads, and how long reading them takes.
+#[derive(Debug)]
+pub struct Source<S>(S, rc::Rc<Stage>);
+
+/// A sink that records how many records it writes, and how long writing them takes.
+#[derive(Debug)]
+pub struct Sink<S>(S, rc::Rc<Stage>);
+
+pub fn source<S>(source: S, stage: rc::Rc<Stage>) -> Source<S>
+where
+    S: value::Source,
+{
+    Source(source, stage)
+}
+
+pub fn sink<S>(sink: S, stage: rc::Rc<Stage>) -> Sink<S>
+where
+    S: value::Sink,
+{
+    Sink(sink, stage)
+}
+
+impl Stage {
+    /// The number of records that passed through the stage.
+    pub fn records(&self) -> u64 {
+        self.records.get()
+    }
+
+    /// The total time spent in the stage.
+    pub fn elapsed(&self) -> time::Duration {
+        self.elapsed.get()
+    }
+
+    fn time<F, A>(&self, f: F) -> A
+    where
+        F: FnOnce() -> A,
+    {
+        let start = time::Instant::now();
+        let result = f();
+        self.elapsed.set(self.elapsed.get() + start.elapsed());
+        result
+    }
+
+    fn count(&self) {
+        self.records.set(self.records.get() + 1);
+    }
+}
+
+impl<S> value::Source for Source<S>
+where
+    S: value::Source,
+{
+    #[inline]
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        let inner = &mut self.0;
+        let result = self.1.time(|| inner.read());
+        if let Ok(Some(_)) = result {
+            self.1.count();
+        }
+        result
+    }
+
+    #[inline]
+    fn position(&self) -> Option<value::Position> {
+        self.0.position()
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<S> value::Sink for Sink<S>
+where
+    S: value::Sink,
+{
+    #[inline]
+    fn write(&mut self, v: value::Value) -> error::Result<()> {
+        let inner = &mut self.0;
+        self.1.time(|| inner.write(v))?;
+        self.1.count();
+        Ok(())
+    }
+
+    #[inline]
+    fn flush(&mut self) -> error::Result<()> {
+        let inner = &mut self.0;
+        self.1.time(|| inner.flush())
+    }
+
+    #[inline]
+    fn finish(&mut self) -> error::Result<()> {
+        let inner = &mut self.0;
+        self.1.time(|| inner.finish())
+    }
+}
@@ -0,0 +1,214 @@
+//! Unwrapping the envelopes of AWS CloudTrail and CloudWatch Logs exports.
+
+use std::collections;
+
+use crate::compression;
+use crate::error;
+use crate::transform::decompress;
+use crate::value;
+
+/// A source that replaces AWS log envelopes from another source with the events in them.
+///
+/// CloudTrail log files are maps with the events under `Records`.  CloudWatch Logs subscription
+/// messages, as delivered by Firehose, have the events under `logEvents`; each of them becomes a
+/// record with the `logGroup`, `logStream` and `owner` of the message, and a `message` that holds
+/// JSON is parsed.  Messages that Lambda receives, with the gzipped and base64-encoded message
+/// under `awslogs.data`, are decoded first.  Control messages are dropped, and records that
+/// aren't envelopes are passed through as is.
+#[derive(Debug)]
+pub struct Source<S> {
+    inner: S,
+    pending: collections::VecDeque<value::Value>,
+}
+
+pub fn source<S>(inner: S) -> Source<S>
+where
+    S: value::Source,
+{
+    Source {
+        inner,
+        pending: collections::VecDeque::new(),
+    }
+}
+
+impl<S> value::Source for Source<S>
+where
+    S: value::Source,
+{
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        loop {
+            if let Some(record) = self.pending.pop_front() {
+                return Ok(Some(record));
+            }
+            match self.inner.read()? {
+                Some(record) => self.unwrap(record)?,
+                None => return Ok(None),
+            }
+        }
+    }
+
+    #[inline]
+    fn position(&self) -> Option<value::Position> {
+        self.inner.position()
+    }
+}
+
+impl<S> Source<S> {
+    /// Queues the events of an envelope, or the record itself if it isn't one.
+    fn unwrap(&mut self, mut record: value::Value) -> error::Result<()> {
+        if let Some(value::Value::String(_)) = record.get(".awslogs.data") {
+            let data = decompress::Field {
+                path: value::path::Path::from(".awslogs.data"),
+                encodings: vec![
+                    decompress::Encoding::Compressed(compression::Codec::Gzip),
+                    decompress::Encoding::Base64,
+                ],
+            };
+            data.decode(&mut record)?;
+            let message = match record {
+                value::Value::Map(entries) => match take(entries, "awslogs") {
+                    Some(value::Value::Map(awslogs)) => take(awslogs, "data"),
+                    _ => None,
+                },
+                _ => None,
+            };
+            return match message {
+                Some(message) => self.unwrap(message),
+                None => Ok(()),
+            };
+        }
+
+        let entries = match record {
+            value::Value::Map(entries) => entries,
+            record => {
+                self.pending.push_back(record);
+                return Ok(());
+            }
+        };
+        let is_trail = matches!(field(&entries, "Records"), Some(value::Value::Sequence(_)));
+        let message_type = field(&entries, "messageType").and_then(value::Value::as_str);
+        let has_events = matches!(
+            field(&entries, "logEvents"),
+            Some(value::Value::Sequence(_))
+        );
+
+        if is_trail {
+            if let Some(value::Value::Sequence(events)) = take(entries, "Records") {
+                self.pending.extend(events);
+            }
+        } else if message_type == Some("CONTROL_MESSAGE") {
+            debug!("Dropping a CloudWatch Logs control message");
+        } else if message_type.is_some() && has_events {
+            let context: Vec<_> = ["logGroup", "logStream", "owner"]
+                .iter()
+                .filter_map(|name| field(&entries, name).map(|v| ((*name).into(), v.clone())))
+                .collect();
+            if let Some(value::Value::Sequence(events)) = take(entries, "logEvents") {
+                for event in events {
+                    self.pending.push_back(log_event(&context, event));
+                }
+            }
+        } else {
+            self.pending.push_back(value::Value::Map(entries));
+        }
+        Ok(())
+    }
+}
+
+/// A CloudWatch Logs event with the context of its subscription message, and its own message
+/// parsed if it is a JSON document.
+fn log_event(context: &[(value::Value, value::Value)], event: value::Value) -> value::Value {
+    let entries = match event {
+        value::Value::Map(entries) => entries,
+        event => return event,
+    };
+    let mut record = context.to_vec();
+    for (key, v) in entries {
+        let v = match v {
+            value::Value::String(ref message) if key.as_str() == Some("message") => {
+                match serde_json::from_str(message) {
+                    Ok(v @ value::Value::Map(_)) | Ok(v @ value::Value::Sequence(_)) => v,
+                    _ => v,
+                }
+            }
+            v => v,
+        };
+        record.push((key, v));
+    }
+    value::Value::Map(record)
+}
+
+fn field<'a>(entries: &'a [(value::Value, value::Value)], name: &str) -> Option<&'a value::Value> {
+    entries
+        .iter()
+        .find(|(k, _)| k.as_str() == Some(name))
+        .map(|(_, v)| v)
+}
+
+fn take(entries: Vec<(value::Value, value::Value)>, name: &str) -> Option<value::Value> {
+    entries
+        .into_iter()
+        .find(|(k, _)| k.as_str() == Some(name))
+        .map(|(_, v)| v)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+
+    use crate::transform::test_util;
+    use crate::value::Source as _;
+
+    fn unwrap(input: &str) -> Vec<String> {
+        let records = test_util::read_all(value::json::source(input.as_bytes()));
+        test_util::read_text(source(test_util::records(records)))
+    }
+
+    #[test]
+    fn test_envelopes() {
+        let input = r#"
+            {"Records": [{"eventName": "A"}, {"eventName": "B"}]}
+            {"messageType": "CONTROL_MESSAGE", "logEvents": []}{"messageType": "DATA_MESSAGE",
+             "logGroup": "g", "logStream": "s", "owner": "1", "subscriptionFilters": ["f"],
+             "logEvents": [{"id": "1", "message": "{\"x\": 1}"}, {"id": "2", "message": "hi"}]}
+            {"Records": "not a trail"}
+        "#;
+        // The context of CloudWatch Logs events comes first
+        assert_eq!(
+            unwrap(input),
+            vec![
+                r#"{"eventName": "A"}"#,
+                r#"{"eventName": "B"}"#,
+                r#"{"logGroup": "g", "logStream": "s", "owner": "1", "id": "1", "message": {"x": 1}}"#,
+                r#"{"logGroup": "g", "logStream": "s", "owner": "1", "id": "2", "message": "hi"}"#,
+                r#"{"Records": "not a trail"}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lambda() {
+        use base64::Engine;
+
+        let message = br#"{"messageType": "DATA_MESSAGE", "logGroup": "g",
+            "logEvents": [{"id": "1", "message": "hi"}]}"#;
+        let mut data = Vec::new();
+        {
+            let mut encoder =
+                compression::encoder(compression::Codec::Gzip, None, Box::new(&mut data)).unwrap();
+            encoder.write_all(message).unwrap();
+            encoder.finish().unwrap();
+        }
+        let data = base64::engine::general_purpose::STANDARD.encode(data);
+        assert_eq!(
+            unwrap(&format!(r#"{{"awslogs": {{"data": "{}"}}}}"#, data)),
+            vec![r#"{"logGroup": "g", "id": "1", "message": "hi"}"#]
+        );
+        assert!(
+            source(test_util::records(vec![value!({"awslogs": {"data": "!"}})]))
+                .read()
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,395 @@
+//! Coercing records into the shape of an Avro schema.
+
+use crate::error;
+use crate::value;
+use std::collections;
+use std::fmt;
+
+/// A source that makes the records of another source conform to an Avro schema: fields that
+/// are missing are filled in with their defaults from the schema, and fields that the schema
+/// doesn't have are dropped.  Values of enums that aren't symbols of the enum are reported,
+/// and coerced into symbols according to the `Enums` rules.
+///
+/// Values are otherwise left as they are, so records that still don't match the schema, like
+/// ones with a missing field without a default, are reported by the sink.
+#[derive(Debug)]
+pub struct Source<S> {
+    inner: S,
+    schema: avro_rs::Schema,
+    enums: Enums,
+    index: usize,
+}
+
+/// How to coerce values of enums that aren't symbols of the enum.
+#[derive(Clone, Debug, Default)]
+pub struct Enums {
+    /// Whether values match symbols that only differ in case.
+    pub ignore_case: bool,
+    /// For each enum, by its name or full name, values that stand for one of its symbols.
+    pub aliases: collections::HashMap<String, collections::HashMap<String, String>>,
+}
+
+/// A value of an enum that isn't one of its symbols, even after coercion.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Violation {
+    pub path: value::path::Path,
+    pub value: value::Value,
+    pub name: String,
+}
+
+pub fn source<S>(inner: S, schema: avro_rs::Schema, enums: Enums) -> Source<S>
+where
+    S: value::Source,
+{
+    Source {
+        inner,
+        schema,
+        enums,
+        index: 0,
+    }
+}
+
+impl<S> value::Source for Source<S>
+where
+    S: value::Source,
+{
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        match self.inner.read()? {
+            Some(record) => {
+                let (record, violations) = conform(record, &self.schema, &self.enums);
+                for violation in violations {
+                    warn!("Record {} (counting from 0): {}", self.index, violation);
+                }
+                self.index += 1;
+                Ok(Some(record))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+impl Enums {
+    /// Finds the symbol of an enum that a value stands for.
+    fn coerce<'a>(
+        &'a self,
+        name: &avro_rs::schema::Name,
+        symbols: &'a [String],
+        value: &str,
+    ) -> Option<&'a str> {
+        if let Some(symbol) = symbols.iter().find(|symbol| *symbol == value) {
+            return Some(symbol);
+        }
+        let aliases = self
+            .aliases
+            .get(&name.name)
+            .or_else(|| self.aliases.get(&name.fullname(None)));
+        if let Some(symbol) = aliases.and_then(|aliases| aliases.get(value)) {
+            return symbols.iter().find(|s| *s == symbol).map(String::as_str);
+        }
+        if self.ignore_case {
+            return symbols
+                .iter()
+                .find(|symbol| symbol.eq_ignore_ascii_case(value))
+                .map(String::as_str);
+        }
+        None
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} at {} isn't a symbol of the enum {}",
+            self.value.summary(40),
+            self.path,
+            self.name
+        )
+    }
+}
+
+/// Makes the value conform to the schema, as far as filling in defaults, dropping unknown
+/// fields and coercing enum values goes.  Returns the enum values that couldn't be coerced.
+pub fn conform(
+    value: value::Value,
+    schema: &avro_rs::Schema,
+    enums: &Enums,
+) -> (value::Value, Vec<Violation>) {
+    let mut violations = Vec::new();
+    let value = conform_at(
+        value,
+        schema,
+        enums,
+        &value::path::Path::root(),
+        &mut violations,
+    );
+    (value, violations)
+}
+
+fn conform_at(
+    value: value::Value,
+    schema: &avro_rs::Schema,
+    enums: &Enums,
+    path: &value::path::Path,
+    violations: &mut Vec<Violation>,
+) -> value::Value {
+    use avro_rs::Schema;
+
+    let child = |segment: &str| {
+        let mut child = path.clone();
+        child.push(segment);
+        child
+    };
+
+    match (value, schema) {
+        (value::Value::Map(entries), Schema::Record { fields, .. }) => {
+            let mut entries: Vec<_> = entries.into_iter().map(Some).collect();
+            let mut conformed = Vec::with_capacity(fields.len());
+            for field in fields {
+                let existing = entries.iter_mut().find(|entry| {
+                    entry
+                        .as_ref()
+                        .is_some_and(|(k, _)| value::path::key_matches(k, &field.name))
+                });
+                match existing.and_then(Option::take) {
+                    Some((key, value)) => {
+                        let path = child(&field.name);
+                        let value = conform_at(value, &field.schema, enums, &path, violations);
+                        conformed.push((key, value));
+                    }
+                    None => {
+                        if let Some(ref default) = field.default {
+                            let value = default_value(default, &field.schema);
+                            conformed.push((value::Value::String(field.name.clone()), value));
+                        }
+                    }
+                }
+            }
+            for (key, _) in entries.into_iter().flatten() {
+                trace!("Dropping field {} that isn't in the schema", key);
+            }
+            value::Value::Map(conformed)
+        }
+        (value::Value::Map(entries), Schema::Map(values)) => value::Value::Map(
+            entries
+                .into_iter()
+                .map(|(k, v)| {
+                    let path = match k.as_str() {
+                        Some(key) => child(key),
+                        None => child(&k.to_string()),
+                    };
+                    let v = conform_at(v, values, enums, &path, violations);
+                    (k, v)
+                })
+                .collect(),
+        ),
+        (value::Value::Sequence(elements), Schema::Array(items)) => value::Value::Sequence(
+            elements
+                .into_iter()
+                .enumerate()
+                .map(|(i, element)| {
+                    conform_at(element, items, enums, &child(&i.to_string()), violations)
+                })
+                .collect(),
+        ),
+        (value::Value::String(s), Schema::Enum { name, symbols, .. }) => {
+            match enums.coerce(name, symbols, &s) {
+                Some(symbol) => value::Value::String(symbol.to_owned()),
+                None => {
+                    let value = value::Value::String(s);
+                    violations.push(Violation {
+                        path: path.clone(),
+                        value: value.clone(),
+                        name: name.name.clone(),
+                    });
+                    value
+                }
+            }
+        }
+        (value::Value::Unit, Schema::Union(_)) => value::Value::Unit,
+        (value, Schema::Union(union)) => match union_variant(&value, union.variants()) {
+            Some(variant) => conform_at(value, variant, enums, path, violations),
+            None => value,
+        },
+        (value, _) => value,
+    }
+}
+
+/// Picks the variant of a union that a value is meant to be, if there's a single candidate.
+fn union_variant<'a>(
+    value: &value::Value,
+    variants: &'a [avro_rs::Schema],
+) -> Option<&'a avro_rs::Schema> {
+    use avro_rs::Schema;
+
+    let mut candidates = variants.iter().filter(|variant| {
+        matches!(
+            (value, variant),
+            (value::Value::Map(_), Schema::Record { .. })
+                | (value::Value::Map(_), Schema::Map(_))
+                | (value::Value::Sequence(_), Schema::Array(_))
+                | (value::Value::String(_), Schema::Enum { .. })
+                | (value::Value::String(_), Schema::String)
+        )
+    });
+    match (candidates.next(), candidates.next()) {
+        (Some(variant), None) => Some(variant),
+        _ => None,
+    }
+}
+
+/// Converts a default value from a schema, which is given in JSON, for a field of the schema.
+fn default_value(default: &serde_json::Value, schema: &avro_rs::Schema) -> value::Value {
+    use avro_rs::Schema;
+
+    match (default, schema) {
+        // Defaults of unions are for their first variant
+        (default, Schema::Union(union)) => match union.variants().first() {
+            Some(variant) => default_value(default, variant),
+            None => value::Value::from(default.clone()),
+        },
+        // Bytes are given as strings with a character for each byte
+        (serde_json::Value::String(s), Schema::Bytes)
+        | (serde_json::Value::String(s), Schema::Fixed { .. }) => {
+            value::Value::Bytes(s.chars().map(|c| c as u8).collect())
+        }
+        (serde_json::Value::Object(_), Schema::Record { .. }) => {
+            conform(
+                value::Value::from(default.clone()),
+                schema,
+                &Enums::default(),
+            )
+            .0
+        }
+        (default, _) => value::Value::from(default.clone()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::transform::test_util;
+
+    fn schema() -> avro_rs::Schema {
+        avro_rs::Schema::parse_str(
+            r#"{"type": "record", "name": "r", "fields": [
+                {"name": "id", "type": "long"},
+                {"name": "owner", "type": ["null", {"type": "record", "name": "o", "fields": [
+                    {"name": "name", "type": "string", "default": "nobody"}
+                ]}], "default": null},
+                {"name": "tags", "type": {"type": "array", "items": "string"}, "default": []},
+                {"name": "key", "type": "bytes", "default": "ÿ"}
+            ]}"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_conform() {
+        let record = value!({"id": 1, "extra": true, "owner": {"team": "x"}});
+        let (record, violations) = conform(record, &schema(), &Enums::default());
+        assert_eq!(
+            record,
+            value::Value::Map(vec![
+                (value!("id"), value!(1)),
+                (value!("owner"), value!({"name": "nobody"})),
+                (value!("tags"), value!([])),
+                (value!("key"), value::Value::Bytes(vec![0xff])),
+            ])
+        );
+        assert!(violations.is_empty());
+
+        // Null stays null for an optional record, and a missing field without a default stays
+        // missing
+        let (record, _) = conform(value!({"owner": null}), &schema(), &Enums::default());
+        assert_eq!(
+            record,
+            value::Value::Map(vec![
+                (value!("owner"), value!(null)),
+                (value!("tags"), value!([])),
+                (value!("key"), value::Value::Bytes(vec![0xff])),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_source() {
+        let records = test_util::records(vec![value!({"id": 1, "extra": 2}), value!({"id": 2})]);
+        let conformed = test_util::read_all(source(records, schema(), Enums::default()));
+        assert_eq!(conformed.len(), 2);
+        assert_eq!(conformed[0].get("id"), Some(&value!(1)));
+        assert_eq!(conformed[0].get("extra"), None);
+        assert_eq!(conformed[1].get("tags"), Some(&value!([])));
+    }
+
+    #[test]
+    fn test_enums() {
+        let schema = avro_rs::Schema::parse_str(
+            r#"{"type": "record", "name": "r", "namespace": "n", "fields": [
+                {"name": "colors", "type": {"type": "array", "items":
+                    {"type": "enum", "name": "Color", "symbols": ["RED", "GREEN"]}}}
+            ]}"#,
+        )
+        .unwrap();
+        let record = value!({"colors": ["RED", "green", "crimson", "blue"]});
+
+        let (conformed, violations) = conform(record.clone(), &schema, &Enums::default());
+        assert_eq!(conformed, record);
+        assert_eq!(violations.len(), 3);
+        assert_eq!(violations[0].path.to_pointer(), "/colors/1");
+        assert_eq!(
+            violations[0].to_string(),
+            "\"green\" at .colors.1 isn't a symbol of the enum Color"
+        );
+
+        let mut enums = Enums {
+            ignore_case: true,
+            ..Enums::default()
+        };
+        enums.aliases.insert(
+            "Color".to_owned(),
+            vec![("crimson".to_owned(), "RED".to_owned())]
+                .into_iter()
+                .collect(),
+        );
+        let (conformed, violations) = conform(record, &schema, &enums);
+        assert_eq!(
+            conformed,
+            value!({"colors": ["RED", "GREEN", "RED", "blue"]})
+        );
+        assert_eq!(
+            violations,
+            vec![Violation {
+                path: value::path::Path::from(".colors.3"),
+                value: value!("blue"),
+                name: "Color".to_owned(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_coerce() {
+        let name = avro_rs::schema::Name {
+            name: "Color".to_owned(),
+            namespace: Some("n".to_owned()),
+            aliases: None,
+        };
+        let symbols = vec!["RED".to_owned(), "GREEN".to_owned()];
+        let mut enums = Enums::default();
+        // Aliases may be given by the full name, and must name a symbol
+        enums.aliases.insert(
+            "n.Color".to_owned(),
+            vec![
+                ("crimson".to_owned(), "RED".to_owned()),
+                ("teal".to_owned(), "CYAN".to_owned()),
+            ]
+            .into_iter()
+            .collect(),
+        );
+        assert_eq!(enums.coerce(&name, &symbols, "GREEN"), Some("GREEN"));
+        assert_eq!(enums.coerce(&name, &symbols, "crimson"), Some("RED"));
+        assert_eq!(enums.coerce(&name, &symbols, "teal"), None);
+        assert_eq!(enums.coerce(&name, &symbols, "green"), None);
+    }
+}
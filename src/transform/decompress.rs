@@ -0,0 +1,245 @@
+//! Decoding fields that hold compressed, encoded documents, like the payloads of CloudWatch Logs
+//! subscriptions.
+
+use std::fmt;
+use std::str;
+
+use base64::Engine;
+
+use crate::compression;
+use crate::error;
+use crate::value;
+
+/// A field to decode, and the encodings that were applied to it, in the order they were applied.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Field {
+    pub path: value::path::Path,
+    pub encodings: Vec<Encoding>,
+}
+
+/// One layer of encoding of a field.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Encoding {
+    Base64,
+    Compressed(compression::Codec),
+}
+
+/// A source that decodes fields of the records of another source.  Decoded JSON documents
+/// replace the field as structured values; other text becomes a string and the rest bytes.
+#[derive(Debug)]
+pub struct Source<S> {
+    inner: S,
+    fields: Vec<Field>,
+}
+
+pub fn source<S>(inner: S, fields: Vec<Field>) -> Source<S>
+where
+    S: value::Source,
+{
+    Source { inner, fields }
+}
+
+impl Field {
+    /// Decodes the field of a record in place.  Records without the field are left alone.
+    pub fn decode(&self, record: &mut value::Value) -> error::Result<()> {
+        let field = match self.path.get_mut(record) {
+            Some(field) => field,
+            None => return Ok(()),
+        };
+        let mut data = match std::mem::replace(field, value::Value::Unit) {
+            value::Value::String(s) => s.into_bytes(),
+            value::Value::Bytes(b) => b,
+            value::Value::Unit => return Ok(()),
+            other => {
+                *field = other;
+                return Err(error::Error::Message(format!(
+                    "can't decode {} as {}: it is neither a string nor bytes",
+                    self.path,
+                    self.encodings_name()
+                )));
+            }
+        };
+        for encoding in self.encodings.iter().rev() {
+            data = encoding.decode(&data).map_err(|e| {
+                error::Error::Message(format!(
+                    "failed to decode {} as {}: {}",
+                    self.path,
+                    self.encodings_name(),
+                    e
+                ))
+            })?;
+        }
+        *field = match serde_json::from_slice(&data) {
+            Ok(document) => document,
+            Err(_) => match String::from_utf8(data) {
+                Ok(text) => value::Value::String(text),
+                Err(e) => value::Value::Bytes(e.into_bytes()),
+            },
+        };
+        Ok(())
+    }
+
+    fn encodings_name(&self) -> String {
+        self.encodings
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join("+")
+    }
+}
+
+impl Encoding {
+    fn decode(self, data: &[u8]) -> Result<Vec<u8>, String> {
+        match self {
+            // Exports tend to wrap long base64 lines
+            Self::Base64 => {
+                let data: Vec<u8> = data
+                    .iter()
+                    .copied()
+                    .filter(|b| !b.is_ascii_whitespace())
+                    .collect();
+                base64::engine::general_purpose::STANDARD
+                    .decode(data)
+                    .map_err(|e| e.to_string())
+            }
+            Self::Compressed(codec) => {
+                compression::decompress(codec, data).map_err(|e| e.to_string())
+            }
+        }
+    }
+}
+
+impl<S> value::Source for Source<S>
+where
+    S: value::Source,
+{
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        let mut record = match self.inner.read()? {
+            Some(record) => record,
+            None => return Ok(None),
+        };
+        for field in &self.fields {
+            field.decode(&mut record)?;
+        }
+        Ok(Some(record))
+    }
+
+    #[inline]
+    fn position(&self) -> Option<value::Position> {
+        self.inner.position()
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.path, self.encodings_name())
+    }
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Self::Base64 => f.write_str("base64"),
+            Self::Compressed(codec) => write!(f, "{}", codec),
+        }
+    }
+}
+
+impl str::FromStr for Field {
+    type Err = error::Error;
+
+    /// Parses specs like `.payload:gzip+base64`, for a field that was gzipped and then base64
+    /// encoded.
+    fn from_str(s: &str) -> error::Result<Self> {
+        let (path, encodings) = s.rsplit_once(':').ok_or_else(|| {
+            error::Error::Message(format!(
+                "field to decompress should be like '.payload:gzip+base64', got: {}",
+                s
+            ))
+        })?;
+        let encodings = encodings
+            .split('+')
+            .map(|encoding| match encoding {
+                "base64" => Ok(Encoding::Base64),
+                codec => codec.parse().map(Encoding::Compressed),
+            })
+            .collect::<error::Result<Vec<_>>>()?;
+        Ok(Self {
+            path: value::path::Path::from(path),
+            encodings,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+
+    use crate::transform::test_util;
+    use crate::value::Source as _;
+
+    #[test]
+    fn test_gzip_base64() {
+        let mut compressed = Vec::new();
+        {
+            let codec = compression::Codec::Gzip;
+            let mut encoder = compression::encoder(codec, None, Box::new(&mut compressed)).unwrap();
+            encoder
+                .write_all(br#"{"logEvents": [{"id": "1"}]}"#)
+                .unwrap();
+            encoder.finish().unwrap();
+        }
+        let payload = base64::engine::general_purpose::STANDARD.encode(&compressed);
+
+        let field = ".data:gzip+base64".parse().unwrap();
+        let records = test_util::records(vec![
+            value!({ "data": payload }),
+            value!({"other": 1}),
+            value!({"data": null}),
+        ]);
+        assert_eq!(
+            test_util::read_all(source(records, vec![field])),
+            vec![
+                value!({"data": {"logEvents": [{"id": "1"}]}}),
+                value!({"other": 1}),
+                value!({"data": null}),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_base64() {
+        let field: Field = ".data:base64".parse().unwrap();
+        assert_eq!(field.to_string(), ".data:base64");
+        let records = test_util::records(vec![
+            value!({"data": "aGk="}),
+            value!({"data": "//79"}),
+            value!({"data": "!"}),
+            value!({"data": 1}),
+        ]);
+        let mut reader = source(records, vec![field]);
+        assert_eq!(reader.read().unwrap(), Some(value!({"data": "hi"})));
+        // Data that isn't text stays bytes
+        assert_eq!(
+            reader.read().unwrap(),
+            Some(value::Value::Map(vec![(
+                "data".into(),
+                value::Value::Bytes(vec![0xff, 0xfe, 0xfd])
+            )]))
+        );
+        assert!(reader.read().is_err());
+        assert!(reader.read().is_err());
+    }
+
+    #[test]
+    fn test_parse() {
+        assert!("data:zip".parse::<Field>().is_err());
+        assert!("data".parse::<Field>().is_err());
+    }
+}
@@ -0,0 +1,130 @@
+//! Dropping records whose key has been seen before.
+
+use std::collections;
+
+use crate::error;
+use crate::value;
+
+/// A source that only yields the records of another source whose key, the value at a path,
+/// hasn't been seen yet.  Records without the key are passed through, since they can't be told
+/// apart.
+#[derive(Debug)]
+pub struct Source<S> {
+    inner: S,
+    path: value::path::Path,
+    seen: collections::HashSet<String>,
+    skipped: u64,
+}
+
+/// Creates a source that drops records with duplicate keys, counting the keys in `seen` as
+/// already seen, like the keys of the records that an earlier run wrote.
+pub fn source<S>(inner: S, path: value::path::Path, seen: collections::HashSet<String>) -> Source<S>
+where
+    S: value::Source,
+{
+    Source {
+        inner,
+        path,
+        seen,
+        skipped: 0,
+    }
+}
+
+/// The key of a record, as it is compared.  Strings and other values are compared by their text,
+/// so that keys from CSV, where everything is a string, match those from other formats.
+pub fn key(record: &value::Value, path: &value::path::Path) -> Option<String> {
+    path.get(record).map(|key| match *key {
+        value::Value::String(ref s) => s.clone(),
+        ref key => key.to_string(),
+    })
+}
+
+/// Collects the keys of all records of a source, like an existing output file.
+pub fn keys<S>(
+    mut source: S,
+    path: &value::path::Path,
+) -> error::Result<collections::HashSet<String>>
+where
+    S: value::Source,
+{
+    let mut keys = collections::HashSet::new();
+    while let Some(record) = source.read()? {
+        keys.extend(key(&record, path));
+    }
+    Ok(keys)
+}
+
+impl<S> value::Source for Source<S>
+where
+    S: value::Source,
+{
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        while let Some(record) = self.inner.read()? {
+            let is_new = match key(&record, &self.path) {
+                Some(key) => self.seen.insert(key),
+                None => true,
+            };
+            if is_new {
+                return Ok(Some(record));
+            }
+            self.skipped += 1;
+        }
+        if self.skipped > 0 {
+            info!("Skipped {} records with duplicate keys", self.skipped);
+            self.skipped = 0;
+        }
+        Ok(None)
+    }
+
+    #[inline]
+    fn position(&self) -> Option<value::Position> {
+        self.inner.position()
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, self.inner.size_hint().1)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::transform::test_util;
+
+    #[test]
+    fn test_dedup() {
+        let path = value::path::Path::from(".id");
+        let records = test_util::records(vec![
+            value!({"id": 2}),
+            value!({"id": 3}),
+            value!({"id": 1}),
+            value!({"id": 3}),
+            value!({"name": "x"}),
+            value!({"name": "x"}),
+        ]);
+        // Keys are compared by their text, so the string "2" counts as seen
+        let existing = test_util::records(vec![value!({"id": 1}), value!({"id": "2"})]);
+        let seen = keys(existing, &path).unwrap();
+        assert_eq!(
+            test_util::read_all(source(records, path, seen)),
+            vec![
+                value!({"id": 3}),
+                value!({"name": "x"}),
+                value!({"name": "x"}),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_key() {
+        let path = value::path::Path::from(".id");
+        assert_eq!(key(&value!({"id": "a"}), &path), Some("a".to_owned()));
+        assert_eq!(
+            key(&value!({"id": [1, 2]}), &path),
+            Some("[1, 2]".to_owned())
+        );
+        assert_eq!(key(&value!({"name": "a"}), &path), None);
+    }
+}
@@ -0,0 +1,195 @@
+//! Detecting records whose structure differs from the first record.
+
+use std::collections;
+
+use crate::error;
+use crate::transform::types;
+use crate::value;
+
+/// A source that infers the structure of the first record of another source, and yields a
+/// report for every later record that deviates from it, like
+/// `{"record": 3, "missing": [".id"], "unexpected": [], "changed": []}`.
+///
+/// The structure is the type of every field, following nested maps (but not sequences).
+/// Integers and floats are both considered numbers, so that `1` and `1.5` are not reported.
+#[derive(Debug)]
+pub struct Source<S> {
+    inner: S,
+    expected: Option<Structure>,
+    index: u64,
+}
+
+type Structure = collections::BTreeMap<value::path::Path, types::Type>;
+
+pub fn source<S>(inner: S) -> Source<S>
+where
+    S: value::Source,
+{
+    Source {
+        inner,
+        expected: None,
+        index: 0,
+    }
+}
+
+impl<S> value::Source for Source<S>
+where
+    S: value::Source,
+{
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        while let Some(record) = self.inner.read()? {
+            let index = self.index;
+            self.index += 1;
+
+            let mut actual = Structure::new();
+            infer(&record, value::path::Path::root(), &mut actual);
+            let expected = match self.expected {
+                Some(ref expected) => expected,
+                None => {
+                    self.expected = Some(actual);
+                    continue;
+                }
+            };
+            if let Some(report) = compare(index, expected, &actual) {
+                return Ok(Some(report));
+            }
+        }
+        Ok(None)
+    }
+}
+
+fn infer(value: &value::Value, path: value::path::Path, structure: &mut Structure) {
+    let t = match types::Type::of(Some(value)) {
+        types::Type::Integer | types::Type::Float => types::Type::Number,
+        t => t,
+    };
+    if let value::Value::Map(ref entries) = *value {
+        for (key, entry) in entries {
+            let mut child = path.clone();
+            match key.as_str() {
+                Some(key) => child.push(key),
+                None => child.push(key.to_string()),
+            }
+            infer(entry, child, structure);
+        }
+    }
+    structure.insert(path, t);
+}
+
+fn compare(index: u64, expected: &Structure, actual: &Structure) -> Option<value::Value> {
+    let path = |p: &value::path::Path| value::Value::String(p.to_string());
+    // Differences within a field that is already reported, like the fields of a missing map,
+    // are left out
+    let parent_same = |p: &value::path::Path| match p.segments().split_last() {
+        Some((_, parents)) => {
+            let mut parent = value::path::Path::root();
+            for segment in parents {
+                parent.push(segment.as_str());
+            }
+            expected.get(&parent) == actual.get(&parent)
+        }
+        None => true,
+    };
+    let missing: Vec<_> = expected
+        .keys()
+        .filter(|p| !actual.contains_key(p) && parent_same(p))
+        .map(path)
+        .collect();
+    let unexpected: Vec<_> = actual
+        .keys()
+        .filter(|p| !expected.contains_key(p) && parent_same(p))
+        .map(path)
+        .collect();
+    let changed: Vec<_> = expected
+        .iter()
+        .filter(|(p, _)| parent_same(p))
+        .filter_map(|(p, &e)| match actual.get(p) {
+            Some(&a) if a != e => Some(value::Value::Map(vec![
+                (value::Value::from("path"), path(p)),
+                (value::Value::from("expected"), value::Value::from(e.name())),
+                (value::Value::from("actual"), value::Value::from(a.name())),
+            ])),
+            _ => None,
+        })
+        .collect();
+
+    if missing.is_empty() && unexpected.is_empty() && changed.is_empty() {
+        return None;
+    }
+    Some(value::Value::Map(vec![
+        (value::Value::from("record"), value::Value::U64(index)),
+        (
+            value::Value::from("missing"),
+            value::Value::Sequence(missing),
+        ),
+        (
+            value::Value::from("unexpected"),
+            value::Value::Sequence(unexpected),
+        ),
+        (
+            value::Value::from("changed"),
+            value::Value::Sequence(changed),
+        ),
+    ]))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::transform::test_util;
+
+    fn drift(records: Vec<value::Value>) -> Vec<String> {
+        test_util::read_text(source(test_util::records(records)))
+    }
+
+    #[test]
+    fn test_drift() {
+        let records = vec![
+            value!({"id": 1, "user": {"name": "a"}}),
+            value!({"id": 2, "user": {"name": "b"}}),
+            value!({"id": "3", "user": {"name": "c"}, "extra": true}),
+            value!({"user": {}}),
+        ];
+        assert_eq!(
+            drift(records),
+            vec![
+                r#"{"record": 2, "missing": [], "unexpected": [".extra"], "changed": [{"path": ".id", "expected": "number", "actual": "string"}]}"#,
+                r#"{"record": 3, "missing": [".id", ".user.name"], "unexpected": [], "changed": []}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_numbers() {
+        // Integers and floats are the same kind of number
+        let records = vec![value!({"n": 1}), value!({"n": 1.5}), value!({"n": null})];
+        assert_eq!(
+            drift(records),
+            vec![
+                r#"{"record": 2, "missing": [], "unexpected": [], "changed": [{"path": ".n", "expected": "number", "actual": "null"}]}"#
+            ]
+        );
+    }
+
+    #[test]
+    fn test_changed_parent() {
+        // The fields of a map that became something else aren't reported one by one
+        let records = vec![
+            value!({"user": {"name": "a", "id": 1}}),
+            value!({"user": "a"}),
+        ];
+        assert_eq!(
+            drift(records),
+            vec![
+                r#"{"record": 1, "missing": [], "unexpected": [], "changed": [{"path": ".user", "expected": "object", "actual": "string"}]}"#
+            ]
+        );
+    }
+
+    #[test]
+    fn test_empty() {
+        assert_eq!(drift(vec![]), Vec::<String>::new());
+        assert_eq!(drift(vec![value!({"a": 1})]), Vec::<String>::new());
+    }
+}
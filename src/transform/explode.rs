@@ -0,0 +1,143 @@
+//! Flattening nested sequences into one record per element.
+
+use std::collections;
+
+use crate::error;
+use crate::value;
+
+/// A source that yields a copy of each record from another source for every element of a
+/// sequence field, with the sequence replaced by the element.
+///
+/// Records with an empty sequence are kept, with the field set to null, so that no parent record
+/// is lost.  Records where the field is missing or isn't a sequence are passed through as is.
+#[derive(Debug)]
+pub struct Source<S> {
+    inner: S,
+    path: value::path::Path,
+    pending: collections::VecDeque<value::Value>,
+}
+
+pub fn source<S>(inner: S, path: value::path::Path) -> Source<S>
+where
+    S: value::Source,
+{
+    Source {
+        inner,
+        path,
+        pending: collections::VecDeque::new(),
+    }
+}
+
+impl<S> value::Source for Source<S>
+where
+    S: value::Source,
+{
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        if let Some(record) = self.pending.pop_front() {
+            return Ok(Some(record));
+        }
+
+        let mut record = match self.inner.read()? {
+            Some(record) => record,
+            None => return Ok(None),
+        };
+        let elements = match self.path.get_mut(&mut record) {
+            Some(field @ value::Value::Sequence(_)) => {
+                match std::mem::replace(field, value::Value::Unit) {
+                    value::Value::Sequence(elements) => elements,
+                    _ => unreachable!(),
+                }
+            }
+            _ => return Ok(Some(record)),
+        };
+
+        for element in elements {
+            let mut child = record.clone();
+            if let Some(field) = self.path.get_mut(&mut child) {
+                *field = element;
+            }
+            self.pending.push_back(child);
+        }
+        // The field was set to null, which is what an empty sequence explodes to
+        Ok(Some(self.pending.pop_front().unwrap_or(record)))
+    }
+
+    #[inline]
+    fn position(&self) -> Option<value::Position> {
+        self.inner.position()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::transform::test_util;
+
+    fn explode(path: &str, records: Vec<value::Value>) -> Vec<String> {
+        let records = test_util::records(records);
+        test_util::read_text(source(records, path.parse().unwrap()))
+    }
+
+    #[test]
+    fn test_explode() {
+        let records = vec![
+            value!({"id": 1, "items": ["a", "b"]}),
+            value!({"id": 2, "items": [{"sku": 3}]}),
+        ];
+        assert_eq!(
+            explode(".items", records),
+            vec![
+                r#"{"id": 1, "items": "a"}"#,
+                r#"{"id": 1, "items": "b"}"#,
+                r#"{"id": 2, "items": {"sku": 3}}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_nested_path() {
+        let records = vec![value!({"order": {"id": 1, "items": [1, 2]}})];
+        assert_eq!(
+            explode(".order.items", records),
+            vec![
+                r#"{"order": {"id": 1, "items": 1}}"#,
+                r#"{"order": {"id": 1, "items": 2}}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_empty_sequence() {
+        let records = vec![
+            value!({"id": 1, "items": []}),
+            value!({"id": 2, "items": [5]}),
+        ];
+        assert_eq!(
+            explode(".items", records),
+            vec![r#"{"id": 1, "items": null}"#, r#"{"id": 2, "items": 5}"#]
+        );
+    }
+
+    #[test]
+    fn test_passed_through() {
+        let records = vec![
+            value!({"id": 1}),
+            value!({"id": 2, "items": "a"}),
+            value!({"id": 3, "items": {"a": 1}}),
+        ];
+        assert_eq!(
+            explode(".items", records),
+            vec![
+                r#"{"id": 1}"#,
+                r#"{"id": 2, "items": "a"}"#,
+                r#"{"id": 3, "items": {"a": 1}}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_empty() {
+        assert_eq!(explode(".items", vec![]), Vec::<String>::new());
+    }
+}
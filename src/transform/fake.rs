@@ -0,0 +1,325 @@
+//! Replacing fields with made-up data, to anonymize records.
+
+use std::fmt;
+use std::str;
+
+use crate::error;
+use crate::transform::rng;
+use crate::value;
+
+/// A source that replaces the configured fields of each record from another source with fake
+/// data.
+#[derive(Debug)]
+pub struct Source<S> {
+    inner: S,
+    fields: Vec<Field>,
+    seed: u64,
+}
+
+/// A field to replace, and the kind of fake data to replace it with.
+#[derive(Clone, Debug)]
+pub struct Field {
+    path: value::path::Path,
+    kind: Kind,
+}
+
+/// The kinds of fake data that can be generated.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Kind {
+    FirstName,
+    LastName,
+    Name,
+    Email,
+    Username,
+    Phone,
+    City,
+    Company,
+    Word,
+}
+
+const FIRST_NAMES: &[&str] = &[
+    "Alex", "Amara", "Ben", "Carla", "Chen", "Dana", "Elif", "Emil", "Fatima", "Felix", "Grace",
+    "Hugo", "Ines", "Ivan", "Jana", "Jonas", "Kai", "Lena", "Luis", "Maya", "Milan", "Nora",
+    "Omar", "Priya", "Quinn", "Rosa", "Sami", "Sofia", "Tariq", "Uma", "Victor", "Yuki",
+];
+
+const LAST_NAMES: &[&str] = &[
+    "Adler", "Bauer", "Castillo", "Dubois", "Eriksson", "Fischer", "Garcia", "Hansen", "Ito",
+    "Jensen", "Kowalski", "Larsen", "Moreau", "Nakamura", "Okafor", "Petrov", "Quint", "Rossi",
+    "Silva", "Tanaka", "Umar", "Varga", "Weber", "Xu", "Yilmaz", "Zimmer",
+];
+
+const CITIES: &[&str] = &[
+    "Ashford",
+    "Brookvale",
+    "Cedar Falls",
+    "Dunmore",
+    "Eastwick",
+    "Fairhaven",
+    "Glenrock",
+    "Harborview",
+    "Ironbridge",
+    "Juniper Bay",
+    "Kingsport",
+    "Lakeside",
+    "Millbrook",
+    "Northgate",
+    "Oakridge",
+    "Pinecrest",
+    "Riverton",
+    "Stonehill",
+    "Westfield",
+    "Willowdale",
+];
+
+const COMPANY_WORDS: &[&str] = &[
+    "Acme",
+    "Apex",
+    "Blue",
+    "Bright",
+    "Cobalt",
+    "Delta",
+    "Evergreen",
+    "Falcon",
+    "Granite",
+    "Harbor",
+    "Horizon",
+    "Nimbus",
+    "Nova",
+    "Orbit",
+    "Pioneer",
+    "Summit",
+    "Vertex",
+    "Zenith",
+];
+
+const COMPANY_SUFFIXES: &[&str] = &["Inc.", "LLC", "Group", "Labs", "Systems", "Partners"];
+
+const WORDS: &[&str] = &[
+    "alpha", "amber", "breeze", "canyon", "cloud", "coral", "dawn", "ember", "fern", "frost",
+    "glade", "harbor", "island", "jade", "lumen", "meadow", "nectar", "opal", "pebble", "quartz",
+    "river", "sage", "tide", "umber", "velvet", "willow",
+];
+
+// Reserved for documentation and examples, so fake addresses never reach real people
+const EMAIL_DOMAINS: &[&str] = &["example.com", "example.net", "example.org"];
+
+/// Creates a source that replaces `fields` in every record of `inner`.  The fake data is derived
+/// from the seed and the original value, so the same value is always replaced by the same fake
+/// data for the same seed, keeping relations between records intact.
+pub fn source<S>(inner: S, fields: Vec<Field>, seed: u64) -> Source<S>
+where
+    S: value::Source,
+{
+    Source {
+        inner,
+        fields,
+        seed,
+    }
+}
+
+impl<S> value::Source for Source<S>
+where
+    S: value::Source,
+{
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        let mut record = match self.inner.read()? {
+            Some(record) => record,
+            None => return Ok(None),
+        };
+        for field in &self.fields {
+            if let Some(v) = field.path.get_mut(&mut record) {
+                // Keep nulls, so that it's still visible which values were missing
+                if !v.is_unit() {
+                    let mut rng = rng::Rng::new(self.seed ^ fingerprint(field.kind, v));
+                    *v = value::Value::String(field.kind.generate(&mut rng));
+                }
+            }
+        }
+        Ok(Some(record))
+    }
+
+    #[inline]
+    fn position(&self) -> Option<value::Position> {
+        self.inner.position()
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+/// A hash of the original value that is stable across runs and platforms (unlike the hashers in
+/// the standard library), so that fake data is reproducible.
+fn fingerprint(kind: Kind, v: &value::Value) -> u64 {
+    // FNV-1a
+    let mut hash = 0xcbf2_9ce4_8422_2325u64;
+    for byte in kind
+        .to_string()
+        .bytes()
+        .chain([0])
+        .chain(v.to_string().bytes())
+    {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+fn pick(rng: &mut rng::Rng, words: &[&'static str]) -> &'static str {
+    words[rng.below(words.len() as u64) as usize]
+}
+
+impl Kind {
+    fn generate(self, rng: &mut rng::Rng) -> String {
+        match self {
+            Self::FirstName => pick(rng, FIRST_NAMES).to_owned(),
+            Self::LastName => pick(rng, LAST_NAMES).to_owned(),
+            Self::Name => format!("{} {}", pick(rng, FIRST_NAMES), pick(rng, LAST_NAMES)),
+            Self::Email => format!(
+                "{}@{}",
+                Self::Username.generate(rng),
+                pick(rng, EMAIL_DOMAINS)
+            ),
+            Self::Username => format!(
+                "{}.{}{}",
+                pick(rng, FIRST_NAMES).to_lowercase(),
+                pick(rng, LAST_NAMES).to_lowercase(),
+                rng.below(1000)
+            ),
+            // The 555-01xx numbers are reserved for fictional use
+            Self::Phone => format!("+1-{:03}-555-01{:02}", 200 + rng.below(800), rng.below(100)),
+            Self::City => pick(rng, CITIES).to_owned(),
+            Self::Company => format!(
+                "{} {}",
+                pick(rng, COMPANY_WORDS),
+                pick(rng, COMPANY_SUFFIXES)
+            ),
+            Self::Word => pick(rng, WORDS).to_owned(),
+        }
+    }
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Self::FirstName => "person.first_name",
+            Self::LastName => "person.last_name",
+            Self::Name => "person.name",
+            Self::Email => "internet.email",
+            Self::Username => "internet.username",
+            Self::Phone => "phone.number",
+            Self::City => "address.city",
+            Self::Company => "company.name",
+            Self::Word => "lorem.word",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl str::FromStr for Kind {
+    type Err = error::Error;
+
+    fn from_str(s: &str) -> error::Result<Self> {
+        match s {
+            "person.first_name" => Ok(Self::FirstName),
+            "person.last_name" => Ok(Self::LastName),
+            "person.name" => Ok(Self::Name),
+            "internet.email" => Ok(Self::Email),
+            "internet.username" => Ok(Self::Username),
+            "phone.number" => Ok(Self::Phone),
+            "address.city" => Ok(Self::City),
+            "company.name" => Ok(Self::Company),
+            "lorem.word" => Ok(Self::Word),
+            _ => Err(error::Error::Message(format!(
+                "unknown kind of fake data: {}",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}={}", self.path, self.kind)
+    }
+}
+
+impl str::FromStr for Field {
+    type Err = error::Error;
+
+    /// Parses specs like `user.email=internet.email`.
+    fn from_str(s: &str) -> error::Result<Self> {
+        match s.split_once('=') {
+            Some((path, kind)) if !path.is_empty() => Ok(Self {
+                path: value::path::Path::from(path),
+                kind: kind.parse()?,
+            }),
+            _ => Err(error::Error::Message(format!(
+                "invalid fake field (expected path=kind): {}",
+                s
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::transform::test_util;
+
+    fn fake(fields: &str, seed: u64, records: Vec<value::Value>) -> Vec<value::Value> {
+        let fields = fields.split(',').map(|f| f.parse().unwrap()).collect();
+        test_util::read_all(source(test_util::records(records), fields, seed))
+    }
+
+    #[test]
+    fn test_deterministic() {
+        let records = vec![
+            value!({"email": "jo@corp.com", "n": 1}),
+            value!({"email": "al@corp.com", "n": 2}),
+            value!({"email": "jo@corp.com", "n": 3}),
+        ];
+        let faked = fake("email=internet.email", 7, records.clone());
+        // The same value becomes the same fake data, and other fields are kept
+        assert_eq!(faked[0].get(".email"), faked[2].get(".email"));
+        assert_ne!(faked[0].get(".email"), faked[1].get(".email"));
+        assert_eq!(faked[1].get(".n"), Some(&value!(2)));
+        // And so it does in every run with the same seed, but not with another one
+        assert_eq!(fake("email=internet.email", 7, records.clone()), faked);
+        assert_ne!(fake("email=internet.email", 8, records), faked);
+    }
+
+    #[test]
+    fn test_kinds() {
+        let record = value!({"email": 42, "phone": "x", "name": "Jo", "id": "Jo"});
+        let faked = fake(
+            "email=internet.email,phone=phone.number,name=person.name,id=person.first_name",
+            1,
+            vec![record],
+        );
+        // Values of any type become strings of the kind of fake data
+        let field = |path: &str| faked[0].get(path).and_then(value::Value::as_str).unwrap();
+        let (user, domain) = field(".email").split_once('@').unwrap();
+        assert!(!user.is_empty() && EMAIL_DOMAINS.contains(&domain));
+        assert!(field(".phone").starts_with("+1-") && field(".phone").contains("-555-01"));
+        assert_eq!(field(".name").split(' ').count(), 2);
+        assert!(FIRST_NAMES.contains(&field(".id")));
+    }
+
+    #[test]
+    fn test_missing_and_null() {
+        let records = vec![value!({"city": null}), value!({"other": 1}), value!([1])];
+        assert_eq!(fake("city=address.city", 1, records.clone()), records);
+    }
+
+    #[test]
+    fn test_parse() {
+        assert!("email=internet.email".parse::<Field>().is_ok());
+        assert!("email".parse::<Field>().is_err());
+        assert!("=internet.email".parse::<Field>().is_err());
+        assert!("email=internet.nope".parse::<Field>().is_err());
+    }
+}
@@ -0,0 +1,128 @@
+//! Keeping only records with strings that match a pattern.
+
+use crate::error;
+use crate::value;
+
+/// A source that only yields the records of another source that contain a string matching a
+/// regex, either anywhere in the record or within some of its fields.
+#[derive(Debug)]
+pub struct Source<S> {
+    inner: S,
+    regex: regex::Regex,
+    paths: Vec<value::path::Path>,
+}
+
+/// Creates a source that keeps records with a matching string within any of `paths`, or
+/// anywhere in the record if `paths` is empty.
+pub fn source<S>(inner: S, regex: regex::Regex, paths: Vec<value::path::Path>) -> Source<S>
+where
+    S: value::Source,
+{
+    Source {
+        inner,
+        regex,
+        paths,
+    }
+}
+
+impl<S> Source<S>
+where
+    S: value::Source,
+{
+    fn matches(&self, record: &value::Value) -> bool {
+        if self.paths.is_empty() {
+            self.any_string_matches(record)
+        } else {
+            self.paths
+                .iter()
+                .filter_map(|path| path.get(record))
+                .any(|value| self.any_string_matches(value))
+        }
+    }
+
+    fn any_string_matches(&self, value: &value::Value) -> bool {
+        match *value {
+            value::Value::String(ref s) => self.regex.is_match(s),
+            value::Value::Sequence(ref elements) => {
+                elements.iter().any(|e| self.any_string_matches(e))
+            }
+            value::Value::Map(ref entries) => {
+                entries.iter().any(|(_, v)| self.any_string_matches(v))
+            }
+            _ => false,
+        }
+    }
+}
+
+impl<S> value::Source for Source<S>
+where
+    S: value::Source,
+{
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        while let Some(record) = self.inner.read()? {
+            if self.matches(&record) {
+                return Ok(Some(record));
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::transform::test_util;
+
+    fn grep(regex: &str, paths: &[&str], records: Vec<value::Value>) -> Vec<String> {
+        let regex = regex::Regex::new(regex).unwrap();
+        let paths = paths.iter().map(|p| p.parse().unwrap()).collect();
+        test_util::read_text(source(test_util::records(records), regex, paths))
+    }
+
+    fn requests() -> Vec<value::Value> {
+        vec![
+            value!({"path": "/login", "ua": "curl/8.0", "tags": []}),
+            value!({"path": "/", "ua": "firefox", "tags": ["bot", {"via": "curl"}]}),
+            value!({"path": "/curl", "ua": "wget"}),
+            value!({"path": "/", "ua": "wget", "curl": 1}),
+        ]
+    }
+
+    #[test]
+    fn test_anywhere() {
+        // Nested strings match, but keys and other values don't
+        assert_eq!(
+            grep("curl", &[], requests()),
+            vec![
+                r#"{"path": "/login", "tags": [], "ua": "curl/8.0"}"#,
+                r#"{"path": "/", "tags": ["bot", {"via": "curl"}], "ua": "firefox"}"#,
+                r#"{"path": "/curl", "ua": "wget"}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_paths() {
+        assert_eq!(
+            grep("^curl", &[".ua", ".tags"], requests()),
+            vec![
+                r#"{"path": "/login", "tags": [], "ua": "curl/8.0"}"#,
+                r#"{"path": "/", "tags": ["bot", {"via": "curl"}], "ua": "firefox"}"#,
+            ]
+        );
+        // Records without any of the paths don't match
+        assert_eq!(grep(".", &[".missing"], requests()), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_non_strings() {
+        let records = vec![value!({"n": 42}), value!({"n": "42"}), value!({"n": true})];
+        assert_eq!(grep("42|true", &[], records), vec![r#"{"n": "42"}"#]);
+    }
+
+    #[test]
+    fn test_empty() {
+        assert_eq!(grep("curl", &[], vec![]), Vec::<String>::new());
+    }
+}
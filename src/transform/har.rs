@@ -0,0 +1,180 @@
+//! Splitting HTTP Archives (HAR files), as saved by the developer tools of browsers, into their
+//! requests.
+
+use std::collections;
+
+use crate::error;
+use crate::transform::decompress;
+use crate::value;
+
+/// The phases of the timings of an entry, in the order they happen.
+const TIMINGS: &[&str] = &[
+    "blocked", "dns", "connect", "ssl", "send", "wait", "receive",
+];
+
+/// A source that yields a record for each entry of the HTTP Archives from another source.
+///
+/// Each record has the `page` of the entry, the time it `started`, its total `time` in
+/// milliseconds, the `method`, `url` and `status`, the `mime_type` and `size` of the response
+/// content, the `server_ip`, the timings of each phase (`blocked`, `dns`, `connect`, `ssl`,
+/// `send`, `wait` and `receive`, which are null when they don't apply), and the whole `request`
+/// and `response`.  With `bodies`, the text of request and response bodies is decoded like with
+/// `--decompress-field`: base64 content is decoded, and JSON becomes structured values.  Records
+/// that aren't HTTP Archives are passed through as is.
+#[derive(Debug)]
+pub struct Source<S> {
+    inner: S,
+    bodies: bool,
+    pending: collections::VecDeque<value::Value>,
+}
+
+pub fn source<S>(inner: S, bodies: bool) -> Source<S>
+where
+    S: value::Source,
+{
+    Source {
+        inner,
+        bodies,
+        pending: collections::VecDeque::new(),
+    }
+}
+
+impl<S> value::Source for Source<S>
+where
+    S: value::Source,
+{
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        loop {
+            if let Some(record) = self.pending.pop_front() {
+                return Ok(Some(record));
+            }
+            let mut record = match self.inner.read()? {
+                Some(record) => record,
+                None => return Ok(None),
+            };
+            let entries = match record.get_mut(".log.entries") {
+                Some(entries @ value::Value::Sequence(_)) => {
+                    std::mem::replace(entries, value::Value::Unit)
+                }
+                _ => {
+                    self.pending.push_back(record);
+                    continue;
+                }
+            };
+            if let value::Value::Sequence(entries) = entries {
+                for entry in entries {
+                    let record = self.entry(entry)?;
+                    self.pending.push_back(record);
+                }
+            }
+        }
+    }
+
+    #[inline]
+    fn position(&self) -> Option<value::Position> {
+        self.inner.position()
+    }
+}
+
+impl<S> Source<S> {
+    fn entry(&self, mut entry: value::Value) -> error::Result<value::Value> {
+        if self.bodies {
+            let base64 = entry
+                .get(".response.content.encoding")
+                .and_then(value::Value::as_str)
+                == Some("base64");
+            let bodies = [
+                (".request.postData.text", false),
+                (".response.content.text", base64),
+            ];
+            for &(path, base64) in &bodies {
+                let encodings = if base64 {
+                    vec![decompress::Encoding::Base64]
+                } else {
+                    Vec::new()
+                };
+                decompress::Field {
+                    path: value::path::Path::from(path),
+                    encodings,
+                }
+                .decode(&mut entry)?;
+            }
+            // The content isn't base64 anymore
+            if let Some(value::Value::Map(content)) = entry.get_mut(".response.content") {
+                content.retain(|(key, _)| key.as_str() != Some("encoding") || !base64);
+            }
+        }
+
+        let field = |path: &str| entry.get(path).cloned().unwrap_or(value::Value::Unit);
+        let mut record = vec![
+            ("page".into(), field(".pageref")),
+            ("started".into(), field(".startedDateTime")),
+            ("time".into(), field(".time")),
+            ("method".into(), field(".request.method")),
+            ("url".into(), field(".request.url")),
+            ("status".into(), field(".response.status")),
+            ("mime_type".into(), field(".response.content.mimeType")),
+            ("size".into(), field(".response.content.size")),
+            ("server_ip".into(), field(".serverIPAddress")),
+        ];
+        for &phase in TIMINGS {
+            // Phases that don't apply are -1
+            let timing = match entry.get(&format!(".timings.{}", phase)) {
+                Some(timing) if timing.as_f64() == Some(-1.0) => value::Value::Unit,
+                Some(timing) => timing.clone(),
+                None => value::Value::Unit,
+            };
+            record.push((phase.into(), timing));
+        }
+        if let value::Value::Map(entries) = entry {
+            for (key, v) in entries {
+                if matches!(key.as_str(), Some("request" | "response")) {
+                    record.push((key, v));
+                }
+            }
+        }
+        Ok(value::Value::Map(record))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::transform::test_util;
+
+    fn archive() -> test_util::Records {
+        let input = r#"
+            {"log": {"version": "1.2", "entries": [{"pageref": "p", "time": 20.5,
+             "startedDateTime": "2024-04-30T12:40:00.123Z", "serverIPAddress": "192.0.2.1",
+             "request": {"method": "POST", "url": "https://x/", "postData": {"text": "{\"q\": 1}"}},
+             "response": {"status": 200, "content": {"size": 11, "mimeType": "application/json",
+              "text": "eyJvayI6dHJ1ZX0=", "encoding": "base64"}},
+             "timings": {"blocked": -1, "dns": -1, "connect": 10, "send": 0.5, "wait": 5, "receive": 5}}]}}
+            {"log": "not an archive"}
+        "#;
+        test_util::records(test_util::read_all(value::json::source(input.as_bytes())))
+    }
+
+    #[test]
+    fn test_entries() {
+        assert_eq!(
+            test_util::read_text(source(archive(), false)),
+            vec![
+                r#"{"page": "p", "started": "2024-04-30T12:40:00.123Z", "time": 20.5, "method": "POST", "url": "https://x/", "status": 200, "mime_type": "application/json", "size": 11, "server_ip": "192.0.2.1", "blocked": null, "dns": null, "connect": 10, "ssl": null, "send": 0.5, "wait": 5, "receive": 5, "request": {"method": "POST", "url": "https://x/", "postData": {"text": "{\"q\": 1}"}}, "response": {"status": 200, "content": {"size": 11, "mimeType": "application/json", "text": "eyJvayI6dHJ1ZX0=", "encoding": "base64"}}}"#,
+                r#"{"log": "not an archive"}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bodies() {
+        assert_eq!(
+            test_util::read_text(source(archive(), true)),
+            vec![
+                r#"{"page": "p", "started": "2024-04-30T12:40:00.123Z", "time": 20.5, "method": "POST", "url": "https://x/", "status": 200, "mime_type": "application/json", "size": 11, "server_ip": "192.0.2.1", "blocked": null, "dns": null, "connect": 10, "ssl": null, "send": 0.5, "wait": 5, "receive": 5, "request": {"method": "POST", "url": "https://x/", "postData": {"text": {"q": 1}}}, "response": {"status": 200, "content": {"size": 11, "mimeType": "application/json", "text": {"ok": true}}}}"#,
+                r#"{"log": "not an archive"}"#,
+            ]
+        );
+    }
+}
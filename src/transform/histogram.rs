@@ -0,0 +1,269 @@
+//! Counting how the values of a numeric field are distributed over buckets.
+
+use std::collections;
+use std::fmt;
+use std::str;
+use std::vec;
+
+use crate::error;
+use crate::transform;
+use crate::value;
+
+/// A source that reads all records from another source, and yields one record per bucket of the
+/// histogram of a numeric field, like `{"from": 10, "to": 20, "count": 42}`.
+#[derive(Debug)]
+pub struct Source<S> {
+    inner: S,
+    spec: Spec,
+    buckets: Option<vec::IntoIter<value::Value>>,
+}
+
+/// Which field to make a histogram of, and how to bucket its values.
+#[derive(Clone, Debug)]
+pub struct Spec {
+    path: value::path::Path,
+    buckets: Buckets,
+}
+
+#[derive(Clone, Debug)]
+enum Buckets {
+    /// Buckets between consecutive edges, in ascending order.
+    Edges(Vec<f64>),
+    /// Buckets of this width, aligned at multiples of it.
+    Width(f64),
+}
+
+pub fn source<S>(inner: S, spec: Spec) -> Source<S>
+where
+    S: value::Source,
+{
+    Source {
+        inner,
+        spec,
+        buckets: None,
+    }
+}
+
+impl<S> Source<S>
+where
+    S: value::Source,
+{
+    fn collect_buckets(&mut self) -> error::Result<Vec<value::Value>> {
+        let mut counts = collections::BTreeMap::<i64, u64>::new();
+        let mut skipped = 0u64;
+        while let Some(record) = self.inner.read()? {
+            match self.spec.path.get(&record).and_then(value::Value::as_f64) {
+                Some(v) if !v.is_nan() => *counts.entry(self.spec.bucket(v)).or_default() += 1,
+                _ => skipped += 1,
+            }
+        }
+        if skipped > 0 {
+            warn!(
+                "Skipped {} records without a number at {} for the histogram",
+                skipped, self.spec.path
+            );
+        }
+
+        let records = match self.spec.buckets {
+            Buckets::Edges(ref edges) => {
+                // Bucket -1 is below the first edge, and bucket edges.len() - 1 is above the last
+                let first = if counts.contains_key(&-1) { -1 } else { 0 };
+                let last = if counts.contains_key(&(edges.len() as i64 - 1)) {
+                    edges.len() as i64 - 1
+                } else {
+                    edges.len() as i64 - 2
+                };
+                (first..=last)
+                    .map(|i| {
+                        let from = if i >= 0 {
+                            Some(edges[i as usize])
+                        } else {
+                            None
+                        };
+                        let to = edges.get((i + 1) as usize).cloned();
+                        bucket_record(from, to, counts.get(&i).cloned().unwrap_or(0))
+                    })
+                    .collect()
+            }
+            Buckets::Width(width) => match (counts.keys().next(), counts.keys().last()) {
+                (Some(&first), Some(&last)) => (first..=last)
+                    .map(|i| {
+                        let count = counts.get(&i).cloned().unwrap_or(0);
+                        bucket_record(Some(i as f64 * width), Some((i + 1) as f64 * width), count)
+                    })
+                    .collect(),
+                _ => Vec::new(),
+            },
+        };
+        Ok(records)
+    }
+}
+
+fn bucket_record(from: Option<f64>, to: Option<f64>, count: u64) -> value::Value {
+    let bound = |b: Option<f64>| b.map_or(value::Value::Unit, transform::number);
+    value::Value::Map(vec![
+        (value::Value::from("from"), bound(from)),
+        (value::Value::from("to"), bound(to)),
+        (value::Value::from("count"), value::Value::U64(count)),
+    ])
+}
+
+impl Spec {
+    /// The index of the bucket that the value belongs to.
+    fn bucket(&self, v: f64) -> i64 {
+        match self.buckets {
+            Buckets::Edges(ref edges) => {
+                edges.iter().take_while(|&&edge| edge <= v).count() as i64 - 1
+            }
+            Buckets::Width(width) => (v / width).floor() as i64,
+        }
+    }
+}
+
+impl<S> value::Source for Source<S>
+where
+    S: value::Source,
+{
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        if self.buckets.is_none() {
+            self.buckets = Some(self.collect_buckets()?.into_iter());
+        }
+        Ok(self.buckets.as_mut().and_then(Iterator::next))
+    }
+}
+
+impl fmt::Display for Spec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.buckets {
+            Buckets::Edges(ref edges) => {
+                let edges: Vec<_> = edges.iter().map(ToString::to_string).collect();
+                write!(f, "{}:{}", self.path, edges.join(","))
+            }
+            Buckets::Width(width) => write!(f, "{}:width={}", self.path, width),
+        }
+    }
+}
+
+impl str::FromStr for Spec {
+    type Err = error::Error;
+
+    /// Parses specs like `.latency_ms:0,10,100,1000` (bucket edges) or `.latency_ms:width=10`.
+    fn from_str(s: &str) -> error::Result<Self> {
+        let invalid = || error::Error::Message(format!("invalid histogram: {}", s));
+
+        let (path, buckets) = s.rsplit_once(':').ok_or_else(invalid)?;
+        let buckets = match buckets.strip_prefix("width=") {
+            Some(width) => match width.parse::<f64>() {
+                Ok(width) if width > 0.0 && width.is_finite() => Buckets::Width(width),
+                _ => return Err(invalid()),
+            },
+            None => {
+                let edges = buckets
+                    .split(',')
+                    .map(|edge| edge.parse::<f64>().map_err(|_| invalid()))
+                    .collect::<error::Result<Vec<_>>>()?;
+                let ascending = edges.windows(2).all(|w| w[0] < w[1]);
+                if edges.len() < 2 || !ascending || edges.iter().any(|e| !e.is_finite()) {
+                    return Err(error::Error::Message(format!(
+                        "histogram edges must be at least two ascending numbers: {}",
+                        s
+                    )));
+                }
+                Buckets::Edges(edges)
+            }
+        };
+        Ok(Self {
+            path: value::path::Path::from(path),
+            buckets,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::transform::test_util;
+
+    fn histogram(spec: &str, records: Vec<value::Value>) -> Vec<String> {
+        let records = test_util::records(records);
+        test_util::read_text(source(records, spec.parse().unwrap()))
+    }
+
+    #[test]
+    fn test_edges() {
+        let records = vec![
+            value!({"ms": 5}),
+            // Values on an edge belong to the bucket that starts there
+            value!({"ms": 10}),
+            value!({"ms": 99.5}),
+            value!({"ms": 100}),
+        ];
+        assert_eq!(
+            histogram(".ms:0,10,100,1000", records),
+            vec![
+                r#"{"from": 0, "to": 10, "count": 1}"#,
+                r#"{"from": 10, "to": 100, "count": 2}"#,
+                r#"{"from": 100, "to": 1000, "count": 1}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_outside_edges() {
+        let records = vec![value!({"ms": -1}), value!({"ms": 5}), value!({"ms": 10})];
+        assert_eq!(
+            histogram(".ms:0,10", records),
+            vec![
+                r#"{"from": null, "to": 0, "count": 1}"#,
+                r#"{"from": 0, "to": 10, "count": 1}"#,
+                r#"{"from": 10, "to": null, "count": 1}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_width() {
+        let records = vec![value!({"ms": 3}), value!({"ms": 27}), value!({"ms": 29.9})];
+        assert_eq!(
+            histogram(".ms:width=10", records),
+            vec![
+                r#"{"from": 0, "to": 10, "count": 1}"#,
+                // Empty buckets in between are there as well
+                r#"{"from": 10, "to": 20, "count": 0}"#,
+                r#"{"from": 20, "to": 30, "count": 2}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_skipped_values() {
+        let records = vec![
+            value!({"ms": "5"}),
+            value!({"other": 5}),
+            value!({"ms": null}),
+            value!({"ms": 5}),
+        ];
+        assert_eq!(
+            histogram(".ms:width=10", records),
+            vec![r#"{"from": 0, "to": 10, "count": 1}"#]
+        );
+    }
+
+    #[test]
+    fn test_empty() {
+        assert_eq!(histogram(".ms:width=10", vec![]), Vec::<String>::new());
+        assert_eq!(
+            histogram(".ms:0,10", vec![]),
+            vec![r#"{"from": 0, "to": 10, "count": 0}"#]
+        );
+    }
+
+    #[test]
+    fn test_parse() {
+        assert!(".ms:10,0".parse::<Spec>().is_err());
+        assert!(".ms:10".parse::<Spec>().is_err());
+        assert!(".ms:width=0".parse::<Spec>().is_err());
+        assert_eq!(".ms:0,10".parse::<Spec>().unwrap().to_string(), ".ms:0,10");
+    }
+}
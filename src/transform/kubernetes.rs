@@ -0,0 +1,311 @@
+//! Splitting, filtering and joining Kubernetes manifests.
+
+use std::collections;
+use std::fmt;
+use std::str;
+
+use crate::error;
+use crate::value;
+
+/// A group, version and kind (GVK) pattern that selects resources, like `Deployment`,
+/// `apps/Deployment`, `v1/ConfigMap` or `apps/v1/Deployment`.  Kinds are compared ignoring case.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Kind {
+    /// The group or version before the kind, for patterns with two parts.
+    qualifier: Option<String>,
+    group: Option<String>,
+    version: Option<String>,
+    kind: String,
+}
+
+/// A source that yields the resources in the manifests of another source.
+///
+/// `List` resources are split into their items and empty documents are dropped.  With kinds,
+/// only resources that match one of them are kept.  When splitting, each resource is wrapped in
+/// a record with its `kind`, `name` and `namespace` (null for cluster-wide resources), and the
+/// resource itself under `resource`.
+#[derive(Debug)]
+pub struct Source<S> {
+    inner: S,
+    kinds: Vec<Kind>,
+    split: bool,
+    pending: collections::VecDeque<value::Value>,
+}
+
+/// A source that unwraps the resources from records made by splitting, so that they can be
+/// written as a manifest again.  Other records are passed through.
+#[derive(Debug)]
+pub struct Join<S>(S);
+
+pub fn source<S>(inner: S, kinds: Vec<Kind>, split: bool) -> Source<S>
+where
+    S: value::Source,
+{
+    Source {
+        inner,
+        kinds,
+        split,
+        pending: collections::VecDeque::new(),
+    }
+}
+
+pub fn join<S>(inner: S) -> Join<S>
+where
+    S: value::Source,
+{
+    Join(inner)
+}
+
+impl Kind {
+    /// Whether the resource has a matching `apiVersion` and `kind`.
+    pub fn matches(&self, resource: &value::Value) -> bool {
+        let kind = match resource.get(".kind").and_then(value::Value::as_str) {
+            Some(kind) => kind,
+            None => return false,
+        };
+        if !kind.eq_ignore_ascii_case(&self.kind) {
+            return false;
+        }
+        let api_version = resource
+            .get(".apiVersion")
+            .and_then(value::Value::as_str)
+            .unwrap_or("");
+        // The core group has no name, like in `v1`
+        let (group, version) = match api_version.rsplit_once('/') {
+            Some((group, version)) => (group, version),
+            None => ("", api_version),
+        };
+        if let Some(ref qualifier) = self.qualifier {
+            return qualifier == group || qualifier == version;
+        }
+        self.group.as_ref().is_none_or(|g| g == group)
+            && self.version.as_ref().is_none_or(|v| v == version)
+    }
+}
+
+impl<S> value::Source for Source<S>
+where
+    S: value::Source,
+{
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        loop {
+            let resource = match self.pending.pop_front() {
+                Some(resource) => resource,
+                None => match self.inner.read()? {
+                    Some(resource) => resource,
+                    None => return Ok(None),
+                },
+            };
+            if resource == value::Value::Unit {
+                continue;
+            }
+            if is_list(&resource) {
+                if let Some(value::Value::Sequence(items)) = resource.get(".items") {
+                    // Keep the order of the items, ahead of anything queued after the list
+                    for item in items.iter().rev() {
+                        self.pending.push_front(item.clone());
+                    }
+                }
+                continue;
+            }
+            if !self.kinds.is_empty() && !self.kinds.iter().any(|k| k.matches(&resource)) {
+                continue;
+            }
+            return Ok(Some(if self.split { wrap(resource) } else { resource }));
+        }
+    }
+
+    #[inline]
+    fn position(&self) -> Option<value::Position> {
+        self.inner.position()
+    }
+}
+
+impl<S> value::Source for Join<S>
+where
+    S: value::Source,
+{
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        Ok(self.0.read()?.map(|record| match record {
+            value::Value::Map(entries) if is_wrapper(&entries) => entries
+                .into_iter()
+                .find(|(k, _)| k.as_str() == Some("resource"))
+                .map(|(_, v)| v)
+                .unwrap_or(value::Value::Unit),
+            record => record,
+        }))
+    }
+
+    #[inline]
+    fn position(&self) -> Option<value::Position> {
+        self.0.position()
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+/// Whether the resource is a `List`, like `kubectl get -o yaml` outputs, or a typed list like
+/// `ConfigMapList`.
+fn is_list(resource: &value::Value) -> bool {
+    let is_list_kind = resource
+        .get(".kind")
+        .and_then(value::Value::as_str)
+        .is_some_and(|kind| kind.ends_with("List"));
+    is_list_kind && matches!(resource.get(".items"), Some(value::Value::Sequence(_)))
+}
+
+fn wrap(resource: value::Value) -> value::Value {
+    let field = |path: &str| resource.get(path).cloned().unwrap_or(value::Value::Unit);
+    value::Value::Map(vec![
+        ("kind".into(), field(".kind")),
+        ("name".into(), field(".metadata.name")),
+        ("namespace".into(), field(".metadata.namespace")),
+        ("resource".into(), resource),
+    ])
+}
+
+fn is_wrapper(entries: &[(value::Value, value::Value)]) -> bool {
+    let keys: Vec<_> = entries.iter().map(|(k, _)| k.as_str()).collect();
+    keys.contains(&Some("resource")) && keys.contains(&Some("kind"))
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for part in [&self.qualifier, &self.group, &self.version]
+            .iter()
+            .copied()
+            .flatten()
+        {
+            write!(f, "{}/", part)?;
+        }
+        f.write_str(&self.kind)
+    }
+}
+
+impl str::FromStr for Kind {
+    type Err = error::Error;
+
+    fn from_str(s: &str) -> error::Result<Self> {
+        let parts: Vec<_> = s.split('/').collect();
+        if parts.iter().any(|part| part.is_empty()) {
+            return Err(error::Error::Message(format!(
+                "Kubernetes kinds should be like 'Deployment' or 'apps/v1/Deployment', got: {}",
+                s
+            )));
+        }
+        let owned = |i: usize| Some(parts[i].to_owned());
+        let (qualifier, group, version) = match parts.len() {
+            1 => (None, None, None),
+            2 => (owned(0), None, None),
+            3 => (None, owned(0), owned(1)),
+            _ => {
+                return Err(error::Error::Message(format!(
+                    "Kubernetes kinds have at most a group, version and kind, got: {}",
+                    s
+                )))
+            }
+        };
+        Ok(Self {
+            qualifier,
+            group,
+            version,
+            kind: parts[parts.len() - 1].to_owned(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::transform::test_util;
+
+    fn manifest() -> test_util::Records {
+        test_util::records(vec![
+            value!({"apiVersion": "v1", "kind": "Namespace", "metadata": {"name": "web"}}),
+            value!({
+                "apiVersion": "apps/v1",
+                "kind": "Deployment",
+                "metadata": {"name": "api", "namespace": "web"}
+            }),
+            value!(null),
+            value!({
+                "apiVersion": "v1",
+                "kind": "List",
+                "items": [
+                    {"apiVersion": "v1", "kind": "Service", "metadata": {"name": "api", "namespace": "web"}},
+                    {"apiVersion": "v1", "kind": "ConfigMap", "metadata": {"name": "cfg", "namespace": "web"}}
+                ]
+            }),
+        ])
+    }
+
+    fn kinds(kinds: &[&str]) -> Vec<String> {
+        let kinds = kinds.iter().map(|k| k.parse().unwrap()).collect();
+        test_util::read_all(source(manifest(), kinds, false))
+            .iter()
+            .map(|r| r.get(".kind").unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_split() {
+        let records = test_util::read_all(source(manifest(), Vec::new(), true));
+        let names: Vec<_> = records
+            .iter()
+            .map(|r| {
+                let field = |path| r.get(path).unwrap().to_string();
+                format!(
+                    "{} {} {}",
+                    field(".kind"),
+                    field(".name"),
+                    field(".namespace")
+                )
+            })
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                r#""Namespace" "web" null"#,
+                r#""Deployment" "api" "web""#,
+                r#""Service" "api" "web""#,
+                r#""ConfigMap" "cfg" "web""#,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_kinds() {
+        assert_eq!(
+            kinds(&["apps/v1/deployment", "v1/Service"]),
+            vec![r#""Deployment""#, r#""Service""#]
+        );
+        assert_eq!(kinds(&["apps/Deployment"]), vec![r#""Deployment""#]);
+        assert_eq!(kinds(&["v1/Deployment"]), vec![r#""Deployment""#]);
+        assert!(kinds(&["batch/Deployment"]).is_empty());
+
+        assert!("apps//Deployment".parse::<Kind>().is_err());
+    }
+
+    #[test]
+    fn test_join() {
+        let kinds = vec!["ConfigMap".parse().unwrap(), "Namespace".parse().unwrap()];
+        let resources = test_util::read_all(join(source(manifest(), kinds, true)));
+        assert_eq!(
+            resources,
+            vec![
+                value!({"apiVersion": "v1", "kind": "Namespace", "metadata": {"name": "web"}}),
+                value!({"apiVersion": "v1", "kind": "ConfigMap", "metadata": {"name": "cfg", "namespace": "web"}}),
+            ]
+        );
+        // Other records are passed through
+        let records = test_util::records(vec![value!({"kind": "Pod"})]);
+        assert_eq!(
+            test_util::read_all(join(records)),
+            vec![value!({"kind": "Pod"})]
+        );
+    }
+}
@@ -0,0 +1,369 @@
+//! Computations that compare each record with the one before it.
+
+use std::fmt;
+use std::str;
+
+use crate::error;
+use crate::transform;
+use crate::value;
+
+/// A source that adds fields computed from the previous record to each record of another
+/// source, like the change of a counter or the time since the previous event.
+#[derive(Debug)]
+pub struct Source<S> {
+    inner: S,
+    specs: Vec<Spec>,
+    /// The previous value of each spec's field, or `None` if no record had it yet.
+    previous: Vec<Option<f64>>,
+    /// The current session number of each spec (only used by sessions).
+    sessions: Vec<u64>,
+}
+
+/// A field to add to every record, and how to compute it from the previous record.
+#[derive(Clone, Debug)]
+pub struct Spec {
+    kind: Kind,
+    path: value::path::Path,
+    into: value::path::Path,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Kind {
+    /// The difference between the number at the path and the previous one.
+    Delta,
+    /// The seconds between the timestamp at the path and the previous one.
+    Elapsed,
+    /// A session number that increases whenever this many seconds passed since the previous
+    /// timestamp.
+    Session(f64),
+}
+
+pub fn source<S>(inner: S, specs: Vec<Spec>) -> Source<S>
+where
+    S: value::Source,
+{
+    let previous = vec![None; specs.len()];
+    let sessions = vec![0; specs.len()];
+    Source {
+        inner,
+        specs,
+        previous,
+        sessions,
+    }
+}
+
+impl<S> value::Source for Source<S>
+where
+    S: value::Source,
+{
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        let mut record = match self.inner.read()? {
+            Some(record) => record,
+            None => return Ok(None),
+        };
+
+        for (i, spec) in self.specs.iter().enumerate() {
+            let current = spec.path.get(&record).and_then(|v| match spec.kind {
+                Kind::Delta => v.as_f64(),
+                Kind::Elapsed | Kind::Session(_) => timestamp(v),
+            });
+            let previous = self.previous[i];
+            let result = match spec.kind {
+                Kind::Delta | Kind::Elapsed => match (previous, current) {
+                    (Some(previous), Some(current)) => transform::number(current - previous),
+                    _ => value::Value::Unit,
+                },
+                Kind::Session(gap) => {
+                    if let (Some(previous), Some(current)) = (previous, current) {
+                        if current - previous > gap {
+                            self.sessions[i] += 1;
+                        }
+                    }
+                    value::Value::U64(self.sessions[i])
+                }
+            };
+            // Records without the field don't reset the comparison
+            if current.is_some() {
+                self.previous[i] = current;
+            }
+            if !spec.into.set(&mut record, result) {
+                warn!("Couldn't add {} to a record", spec.into);
+            }
+        }
+
+        Ok(Some(record))
+    }
+}
+
+/// Reads a timestamp as seconds since the Unix epoch, either from a number of seconds or from an
+/// RFC 3339 string like `2024-01-01T12:00:00.5Z`.
+fn timestamp(value: &value::Value) -> Option<f64> {
+    match value.as_str() {
+        Some(s) => parse_rfc3339(s),
+        None => value.as_f64(),
+    }
+}
+
+pub(crate) fn parse_rfc3339(s: &str) -> Option<f64> {
+    fn digits(s: &str, range: std::ops::Range<usize>) -> Option<i64> {
+        let digits = s.get(range)?;
+        if digits.bytes().all(|b| b.is_ascii_digit()) {
+            digits.parse().ok()
+        } else {
+            None
+        }
+    }
+
+    let b = s.as_bytes();
+    if b.len() < 20 || b[4] != b'-' || b[7] != b'-' || b[13] != b':' || b[16] != b':' {
+        return None;
+    }
+    if !matches!(b[10], b'T' | b't' | b' ') {
+        return None;
+    }
+    let (year, month, day) = (digits(s, 0..4)?, digits(s, 5..7)?, digits(s, 8..10)?);
+    let (hour, minute, second) = (digits(s, 11..13)?, digits(s, 14..16)?, digits(s, 17..19)?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 {
+        return None;
+    }
+
+    let mut rest = &s[19..];
+    let mut fraction = 0.0;
+    if let Some(after_dot) = rest.strip_prefix('.') {
+        let len = after_dot.bytes().take_while(u8::is_ascii_digit).count();
+        if len == 0 {
+            return None;
+        }
+        fraction = format!("0.{}", &after_dot[..len]).parse().ok()?;
+        rest = &after_dot[len..];
+    }
+    let offset = match rest {
+        "Z" | "z" => 0,
+        _ => {
+            let sign = match rest.as_bytes().first()? {
+                b'+' => 1,
+                b'-' => -1,
+                _ => return None,
+            };
+            if rest.len() != 6 || rest.as_bytes()[3] != b':' {
+                return None;
+            }
+            sign * (digits(rest, 1..3)? * 3600 + digits(rest, 4..6)? * 60)
+        }
+    };
+
+    let days = days_from_civil(year, month, day);
+    let seconds = days * 86400 + hour * 3600 + minute * 60 + second - offset;
+    Some(seconds as f64 + fraction)
+}
+
+/// The number of days since 1970-01-01 of a date in the proleptic Gregorian calendar.
+pub(crate) fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+impl Spec {
+    /// Parses a `--delta` spec like `.bytes` or `.bytes:.bytes_per_interval`.  The result is
+    /// stored next to the field with a `_delta` suffix by default.
+    pub fn delta(s: &str) -> error::Result<Self> {
+        let (path, into) = split_into(s);
+        let path = value::path::Path::from(path);
+        let into = match into {
+            Some(into) => value::path::Path::from(into),
+            None => {
+                let (last, parents) = path.segments().split_last().ok_or_else(|| {
+                    error::Error::Message("the delta of the whole record needs a target".to_owned())
+                })?;
+                let mut into = value::path::Path::root();
+                for segment in parents {
+                    into.push(segment.as_str());
+                }
+                into.push(format!("{}_delta", last));
+                into
+            }
+        };
+        Ok(Self {
+            kind: Kind::Delta,
+            path,
+            into,
+        })
+    }
+
+    /// Parses an `--elapsed` spec like `.timestamp` or `.timestamp:.since_previous`.  The result
+    /// is stored in `.elapsed` by default.
+    pub fn elapsed(s: &str) -> error::Result<Self> {
+        let (path, into) = split_into(s);
+        Ok(Self {
+            kind: Kind::Elapsed,
+            path: value::path::Path::from(path),
+            into: value::path::Path::from(into.unwrap_or("elapsed")),
+        })
+    }
+
+    /// Parses a `--session` spec like `.timestamp:30m` or `.timestamp:30m:.visit`, with the gap
+    /// in seconds or with an `s`, `m`, `h` or `d` suffix.  The result is stored in `.session` by
+    /// default.
+    pub fn session(s: &str) -> error::Result<Self> {
+        let invalid = || {
+            error::Error::Message(format!(
+                "invalid session (expected path:gap or path:gap:into): {}",
+                s
+            ))
+        };
+        let mut parts = s.splitn(3, ':');
+        let path = parts.next().ok_or_else(invalid)?;
+        let gap = parse_duration(parts.next().ok_or_else(invalid)?).ok_or_else(invalid)?;
+        Ok(Self {
+            kind: Kind::Session(gap),
+            path: value::path::Path::from(path),
+            into: value::path::Path::from(parts.next().unwrap_or("session")),
+        })
+    }
+}
+
+fn split_into(s: &str) -> (&str, Option<&str>) {
+    match s.split_once(':') {
+        Some((path, into)) => (path, Some(into)),
+        None => (s, None),
+    }
+}
+
+fn parse_duration(s: &str) -> Option<f64> {
+    let (number, unit) = match s.char_indices().last()? {
+        (i, 's') => (&s[..i], 1.0),
+        (i, 'm') => (&s[..i], 60.0),
+        (i, 'h') => (&s[..i], 3600.0),
+        (i, 'd') => (&s[..i], 86400.0),
+        _ => (s, 1.0),
+    };
+    match number.parse::<f64>() {
+        Ok(n) if n >= 0.0 && n.is_finite() => Some(n * unit),
+        _ => None,
+    }
+}
+
+impl fmt::Display for Spec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            Kind::Delta => write!(f, "delta of {} into {}", self.path, self.into),
+            Kind::Elapsed => write!(f, "time elapsed at {} into {}", self.path, self.into),
+            Kind::Session(gap) => write!(
+                f,
+                "sessions split by {}s gaps in {} into {}",
+                gap, self.path, self.into
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::transform::test_util;
+
+    fn look_behind(specs: Vec<Spec>, records: Vec<value::Value>) -> Vec<String> {
+        test_util::read_text(source(test_util::records(records), specs))
+    }
+
+    #[test]
+    fn test_delta() {
+        let records = vec![
+            value!({"bytes": 100}),
+            value!({"bytes": 150}),
+            value!({"bytes": 120.5}),
+        ];
+        assert_eq!(
+            look_behind(vec![Spec::delta(".bytes").unwrap()], records),
+            vec![
+                r#"{"bytes": 100, "bytes_delta": null}"#,
+                r#"{"bytes": 150, "bytes_delta": 50}"#,
+                r#"{"bytes": 120.5, "bytes_delta": -29.5}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_missing_and_non_numeric() {
+        // Records without a number get null, and don't reset the comparison
+        let records = vec![
+            value!({"n": 1}),
+            value!({"other": 5}),
+            value!({"n": "7"}),
+            value!({"n": 4}),
+        ];
+        assert_eq!(
+            look_behind(vec![Spec::delta(".n:.d").unwrap()], records),
+            vec![
+                r#"{"n": 1, "d": null}"#,
+                r#"{"other": 5, "d": null}"#,
+                r#"{"n": "7", "d": null}"#,
+                r#"{"n": 4, "d": 3}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_elapsed() {
+        let records = vec![
+            value!({"t": "2024-01-01T12:00:00Z"}),
+            value!({"t": "2024-01-01T13:00:01.5+01:00"}),
+            value!({"t": 1_704_110_460}),
+        ];
+        assert_eq!(
+            look_behind(vec![Spec::elapsed(".t").unwrap()], records),
+            vec![
+                r#"{"t": "2024-01-01T12:00:00Z", "elapsed": null}"#,
+                r#"{"t": "2024-01-01T13:00:01.5+01:00", "elapsed": 1.5}"#,
+                r#"{"t": 1704110460, "elapsed": 58.5}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_session() {
+        let records = vec![
+            value!({"t": 0}),
+            value!({"t": 60}),
+            // Exactly the gap doesn't start a new session
+            value!({"t": 120}),
+            value!({"t": 121}),
+            value!({"t": 200}),
+        ];
+        assert_eq!(
+            look_behind(vec![Spec::session(".t:1m").unwrap()], records),
+            vec![
+                r#"{"t": 0, "session": 0}"#,
+                r#"{"t": 60, "session": 0}"#,
+                r#"{"t": 120, "session": 0}"#,
+                r#"{"t": 121, "session": 0}"#,
+                r#"{"t": 200, "session": 1}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_empty() {
+        let specs = vec![Spec::delta(".n").unwrap()];
+        assert_eq!(look_behind(specs, vec![]), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_parse() {
+        assert!(Spec::delta(".").is_err());
+        assert!(Spec::session(".t").is_err());
+        assert!(Spec::session(".t:-1m").is_err());
+        assert_eq!(
+            Spec::delta(".a.b").unwrap().to_string(),
+            "delta of .a.b into .a.b_delta"
+        );
+        assert_eq!(parse_rfc3339("1970-01-01T00:00:00Z"), Some(0.0));
+        assert_eq!(parse_rfc3339("2024-13-01T00:00:00Z"), None);
+        assert_eq!(parse_rfc3339("2024-01-01"), None);
+    }
+}
@@ -0,0 +1,450 @@
+//! Merging patches into records, like layered Helm values or Kubernetes strategic merge patches.
+
+use std::fmt;
+use std::str;
+
+use crate::error;
+use crate::value;
+
+/// How a patch is merged into a record.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Strategy {
+    /// Maps are merged recursively, and everything else, including sequences, is replaced by
+    /// the patch.  Null values remove entries, like in Helm values.
+    Deep,
+    /// Like `Deep`, but sequences of maps that have a merge key are merged item by item, like in
+    /// Kubernetes strategic merge patches.  Maps with a `$patch` directive of `replace` or
+    /// `delete` replace or delete the map or list item that they are merged into, and a
+    /// `{"$patch": "replace"}` item replaces the whole sequence with the other items.
+    Strategic,
+}
+
+/// The field that identifies the items of the sequences at a path, like `containers=name`.
+///
+/// Paths are the field names leading to the sequence, separated by dots and ignoring any
+/// sequences along the way, like `containers.ports` for the ports of every container.  They
+/// match any sequence whose path ends like that, unless they start with a dot, in which case
+/// they match from the root of the record, like `.spec.ports`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MergeKey {
+    anchored: bool,
+    path: Vec<String>,
+    key: String,
+}
+
+/// Merges patches into records with a strategy.
+#[derive(Clone, Debug)]
+pub struct Merger {
+    strategy: Strategy,
+    keys: Vec<MergeKey>,
+}
+
+/// A source that merges patches into each record of another source, one after the other.
+/// Records that a patch deletes are dropped.
+#[derive(Debug)]
+pub struct Source<S> {
+    inner: S,
+    patches: Vec<value::Value>,
+    merger: Merger,
+}
+
+pub fn source<S>(inner: S, patches: Vec<value::Value>, merger: Merger) -> Source<S>
+where
+    S: value::Source,
+{
+    Source {
+        inner,
+        patches,
+        merger,
+    }
+}
+
+/// The merge keys of the Kubernetes lists that are most often patched.
+const KUBERNETES_KEYS: &[(&str, &str)] = &[
+    ("containers", "name"),
+    ("initContainers", "name"),
+    ("ephemeralContainers", "name"),
+    ("containers.ports", "containerPort"),
+    ("initContainers.ports", "containerPort"),
+    ("env", "name"),
+    ("volumeMounts", "mountPath"),
+    ("volumeDevices", "devicePath"),
+    ("volumes", "name"),
+    ("imagePullSecrets", "name"),
+    ("hostAliases", "ip"),
+    (".spec.ports", "port"),
+];
+
+impl Merger {
+    /// Creates a merger.  Strategic merges use the merge keys, which take precedence over the
+    /// merge keys of common Kubernetes lists, like `containers=name` and `env=name`.
+    pub fn new(strategy: Strategy, mut keys: Vec<MergeKey>) -> Self {
+        if strategy == Strategy::Strategic {
+            keys.extend(
+                KUBERNETES_KEYS
+                    .iter()
+                    .map(|&(path, key)| MergeKey::new(path, key)),
+            );
+        }
+        Merger { strategy, keys }
+    }
+
+    /// Merges the patch into the record, which is `None` if the patch deletes it.
+    pub fn merge(
+        &self,
+        record: value::Value,
+        patch: &value::Value,
+    ) -> error::Result<Option<value::Value>> {
+        self.merge_at(&mut Vec::new(), record, patch)
+    }
+
+    fn merge_at(
+        &self,
+        path: &mut Vec<String>,
+        target: value::Value,
+        patch: &value::Value,
+    ) -> error::Result<Option<value::Value>> {
+        match *patch {
+            value::Value::Map(ref entries) => {
+                match self.directive(entries)? {
+                    Some(Directive::Delete) => return Ok(None),
+                    Some(Directive::Replace) => {
+                        // Merge into nothing, so that nested directives and nulls are dropped
+                        return self.merge_at(
+                            path,
+                            value::Value::Unit,
+                            &without_directive(entries),
+                        );
+                    }
+                    None => (),
+                }
+                let mut merged = match target {
+                    value::Value::Map(merged) => merged,
+                    _ => Vec::new(),
+                };
+                for (key, v) in entries {
+                    if self.strategy == Strategy::Strategic && key.as_str() == Some("$patch") {
+                        continue;
+                    }
+                    let position = merged.iter().position(|(k, _)| k == key);
+                    let old = match position {
+                        Some(i) => merged.remove(i).1,
+                        None => value::Value::Unit,
+                    };
+                    if *v == value::Value::Unit {
+                        continue;
+                    }
+                    path.push(key.as_str().map_or_else(|| key.to_string(), str::to_owned));
+                    let new = self.merge_at(path, old, v)?;
+                    path.pop();
+                    if let Some(new) = new {
+                        match position {
+                            Some(i) => merged.insert(i, (key.clone(), new)),
+                            None => merged.push((key.clone(), new)),
+                        }
+                    }
+                }
+                Ok(Some(value::Value::Map(merged)))
+            }
+            value::Value::Sequence(ref items) if self.strategy == Strategy::Strategic => {
+                let key = self.key_at(path);
+                let replace = items.iter().any(is_replace_marker);
+                let mut merged = match (key, target) {
+                    (Some(_), value::Value::Sequence(merged)) if !replace => merged,
+                    _ => Vec::new(),
+                };
+                for item in items.iter().filter(|item| !is_replace_marker(item)) {
+                    let position = key.and_then(|key| {
+                        let identity = field(item, key)?;
+                        merged
+                            .iter()
+                            .position(|old| field(old, key) == Some(identity))
+                    });
+                    let (old, i) = match position {
+                        Some(i) => (merged.remove(i), i),
+                        None => (value::Value::Unit, merged.len()),
+                    };
+                    if let Some(new) = self.merge_at(path, old, item)? {
+                        merged.insert(i, new);
+                    }
+                }
+                Ok(Some(value::Value::Sequence(merged)))
+            }
+            ref patch => Ok(Some(patch.clone())),
+        }
+    }
+
+    fn directive(
+        &self,
+        entries: &[(value::Value, value::Value)],
+    ) -> error::Result<Option<Directive>> {
+        if self.strategy != Strategy::Strategic {
+            return Ok(None);
+        }
+        let directive = entries
+            .iter()
+            .find(|(k, _)| k.as_str() == Some("$patch"))
+            .map(|(_, v)| v);
+        match directive {
+            None => Ok(None),
+            Some(directive) => match directive.as_str() {
+                Some("merge") => Ok(None),
+                Some("replace") => Ok(Some(Directive::Replace)),
+                Some("delete") => Ok(Some(Directive::Delete)),
+                _ => Err(error::Error::Message(format!(
+                    "$patch should be 'merge', 'replace' or 'delete', got: {}",
+                    directive
+                ))),
+            },
+        }
+    }
+
+    /// The merge key of the sequences at the path, if there is one.
+    fn key_at(&self, path: &[String]) -> Option<&str> {
+        self.keys
+            .iter()
+            .find(|key| key.matches(path))
+            .map(|key| key.key.as_str())
+    }
+}
+
+enum Directive {
+    Replace,
+    Delete,
+}
+
+impl MergeKey {
+    fn new(path: &str, key: &str) -> Self {
+        MergeKey {
+            anchored: path.starts_with('.'),
+            path: path
+                .split('.')
+                .filter(|s| !s.is_empty())
+                .map(str::to_owned)
+                .collect(),
+            key: key.to_owned(),
+        }
+    }
+
+    fn matches(&self, path: &[String]) -> bool {
+        if self.anchored {
+            path == &self.path[..]
+        } else {
+            path.ends_with(&self.path)
+        }
+    }
+}
+
+impl<S> value::Source for Source<S>
+where
+    S: value::Source,
+{
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        'records: while let Some(mut record) = self.inner.read()? {
+            for patch in &self.patches {
+                record = match self.merger.merge(record, patch)? {
+                    Some(record) => record,
+                    None => continue 'records,
+                };
+            }
+            return Ok(Some(record));
+        }
+        Ok(None)
+    }
+
+    #[inline]
+    fn position(&self) -> Option<value::Position> {
+        self.inner.position()
+    }
+}
+
+fn field<'a>(item: &'a value::Value, key: &str) -> Option<&'a value::Value> {
+    match *item {
+        value::Value::Map(ref entries) => entries
+            .iter()
+            .find(|(k, _)| k.as_str() == Some(key))
+            .map(|(_, v)| v),
+        _ => None,
+    }
+}
+
+/// Whether the item is `{"$patch": "replace"}`, which replaces the sequence that it is in.
+fn is_replace_marker(item: &value::Value) -> bool {
+    match *item {
+        value::Value::Map(ref entries) => {
+            entries.len() == 1
+                && field(item, "$patch").and_then(value::Value::as_str) == Some("replace")
+        }
+        _ => false,
+    }
+}
+
+fn without_directive(entries: &[(value::Value, value::Value)]) -> value::Value {
+    value::Value::Map(
+        entries
+            .iter()
+            .filter(|(k, _)| k.as_str() != Some("$patch"))
+            .cloned()
+            .collect(),
+    )
+}
+
+impl fmt::Display for Strategy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Strategy::Deep => "deep",
+            Strategy::Strategic => "strategic",
+        })
+    }
+}
+
+impl str::FromStr for Strategy {
+    type Err = error::Error;
+
+    fn from_str(s: &str) -> error::Result<Self> {
+        match s {
+            "deep" => Ok(Strategy::Deep),
+            "strategic" => Ok(Strategy::Strategic),
+            _ => Err(error::Error::Message(format!(
+                "merge strategies are 'deep' and 'strategic', got: {}",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for MergeKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.anchored {
+            f.write_str(".")?;
+        }
+        write!(f, "{}={}", self.path.join("."), self.key)
+    }
+}
+
+impl str::FromStr for MergeKey {
+    type Err = error::Error;
+
+    fn from_str(s: &str) -> error::Result<Self> {
+        match s.split_once('=') {
+            Some((path, key)) if !path.trim_matches('.').is_empty() && !key.is_empty() => {
+                Ok(MergeKey::new(path, key))
+            }
+            _ => Err(error::Error::Message(format!(
+                "merge keys should be like 'containers=name' or '.spec.ports=port', got: {}",
+                s
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::transform::test_util;
+    use crate::value::Source as _;
+
+    /// Parses JSON, which keeps the order of the fields unlike `value!`.
+    fn json(input: &str) -> value::Value {
+        value::json::source(input.as_bytes())
+            .read()
+            .unwrap()
+            .unwrap()
+    }
+
+    fn deployment() -> value::Value {
+        json(
+            r#"{"spec": {"replicas": 1, "paused": false, "template": {"spec": {"containers": [
+                {"name": "app", "image": "app:1", "ports": [{"containerPort": 8080}]},
+                {"name": "sidecar", "image": "proxy:1"}
+            ]}}}}"#,
+        )
+    }
+
+    fn patch() -> value::Value {
+        json(
+            r#"{"spec": {"replicas": 3, "paused": null, "template": {"spec": {"containers": [
+                {"name": "app", "image": "app:2", "ports": [{"containerPort": 8080, "name": "http"}]},
+                {"name": "sidecar", "$patch": "delete"},
+                {"name": "metrics", "image": "exporter:1"}
+            ]}}}}"#,
+        )
+    }
+
+    fn merge(strategy: Strategy, keys: &[&str], patch: &value::Value) -> String {
+        let keys = keys.iter().map(|k| k.parse().unwrap()).collect();
+        Merger::new(strategy, keys)
+            .merge(deployment(), patch)
+            .unwrap()
+            .unwrap()
+            .get(".spec")
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn test_deep() {
+        // Sequences are replaced, keeping the delete directive as is
+        assert_eq!(
+            merge(Strategy::Deep, &[], &patch()),
+            r#"{"replicas": 3, "template": {"spec": {"containers": [{"name": "app", "image": "app:2", "ports": [{"containerPort": 8080, "name": "http"}]}, {"name": "sidecar", "$patch": "delete"}, {"name": "metrics", "image": "exporter:1"}]}}}"#
+        );
+    }
+
+    #[test]
+    fn test_strategic() {
+        assert_eq!(
+            merge(Strategy::Strategic, &[], &patch()),
+            r#"{"replicas": 3, "template": {"spec": {"containers": [{"name": "app", "image": "app:2", "ports": [{"containerPort": 8080, "name": "http"}]}, {"name": "metrics", "image": "exporter:1"}]}}}"#
+        );
+
+        // A replace marker replaces the whole sequence
+        let replace = json(
+            r#"{"spec": {"template": {"spec": {"containers": [{"$patch": "replace"}, {"name": "only"}]}}}}"#,
+        );
+        assert_eq!(
+            merge(Strategy::Strategic, &[], &replace),
+            r#"{"replicas": 1, "paused": false, "template": {"spec": {"containers": [{"name": "only"}]}}}"#
+        );
+
+        let invalid = json(r#"{"spec": {"$patch": "keep"}}"#);
+        assert!(Merger::new(Strategy::Strategic, Vec::new())
+            .merge(deployment(), &invalid)
+            .is_err());
+    }
+
+    #[test]
+    fn test_merge_keys() {
+        let add =
+            json(r#"{"spec": {"template": {"spec": {"containers": [{"image": "proxy:2"}]}}}}"#);
+        assert_eq!(
+            merge(
+                Strategy::Strategic,
+                &[".spec.template.spec.containers=image"],
+                &add
+            ),
+            r#"{"replicas": 1, "paused": false, "template": {"spec": {"containers": [{"name": "app", "image": "app:1", "ports": [{"containerPort": 8080}]}, {"name": "sidecar", "image": "proxy:1"}, {"image": "proxy:2"}]}}}"#
+        );
+
+        assert!("containers".parse::<MergeKey>().is_err());
+        assert!("theirs".parse::<Strategy>().is_err());
+    }
+
+    #[test]
+    fn test_source() {
+        // Records that a patch deletes are dropped, and the patches apply in order
+        let patches = vec![json(r#"{"b": 2}"#), json(r#"{"b": 3, "$patch": "delete"}"#)];
+        let records = test_util::records(vec![value!({"a": 1}), value!({"a": 2})]);
+        let merger = Merger::new(Strategy::Strategic, Vec::new());
+        assert!(test_util::read_all(source(records, patches, merger)).is_empty());
+
+        let patches = vec![json(r#"{"b": 2}"#), json(r#"{"b": null, "c": 3}"#)];
+        let records = test_util::records(vec![value!({"a": 1}), value!({"a": 2})]);
+        let merger = Merger::new(Strategy::Deep, Vec::new());
+        assert_eq!(
+            test_util::read_text(source(records, patches, merger)),
+            vec![r#"{"a": 1, "c": 3}"#, r#"{"a": 2, "c": 3}"#]
+        );
+    }
+}
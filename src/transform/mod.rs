@@ -0,0 +1,95 @@
+//! Stages that transform the stream of records on its way from the source to the sink.
+//!
+//! Each stage wraps a `value::Source`, so that stages can be stacked in front of any input.
+
+pub mod aws_logs;
+pub mod conform;
+pub mod decompress;
+pub mod dedup;
+pub mod drift;
+pub mod explode;
+pub mod fake;
+pub mod grep;
+pub mod har;
+pub mod histogram;
+pub mod kubernetes;
+pub mod look_behind;
+pub mod merge;
+pub mod nest;
+pub mod normalize;
+pub mod pivot;
+pub mod quantiles;
+mod rng;
+pub mod rpc;
+pub mod sample;
+pub mod shuffle;
+pub mod terraform;
+pub mod top_k;
+pub mod transpose;
+pub mod types;
+
+pub use self::rng::random_seed;
+pub(crate) use self::rng::Rng;
+pub(crate) use self::rng::SPLIT;
+
+use crate::value;
+
+/// A number as a value, using an integer when it is a whole number so that it looks natural in
+/// the output.
+pub(crate) fn number(v: f64) -> value::Value {
+    if v.fract() == 0.0 && v.abs() < 2f64.powi(53) {
+        value::Value::I64(v as i64)
+    } else {
+        value::Value::from_f64(v)
+    }
+}
+
+/// Helpers for the tests of the stages.
+#[cfg(test)]
+pub(crate) mod test_util {
+    use std::collections;
+
+    use crate::error;
+    use crate::value;
+
+    /// A source of some records, which knows how many there are.
+    #[derive(Debug)]
+    pub(crate) struct Records(collections::VecDeque<value::Value>);
+
+    pub(crate) fn records<I>(records: I) -> Records
+    where
+        I: IntoIterator<Item = value::Value>,
+    {
+        Records(records.into_iter().collect())
+    }
+
+    /// Reads all records of a source.
+    pub(crate) fn read_all<S>(mut source: S) -> Vec<value::Value>
+    where
+        S: value::Source,
+    {
+        let mut records = Vec::new();
+        while let Some(record) = source.read().unwrap() {
+            records.push(record);
+        }
+        records
+    }
+
+    /// Reads all records of a source, as text that is easier to compare.
+    pub(crate) fn read_text<S>(source: S) -> Vec<String>
+    where
+        S: value::Source,
+    {
+        read_all(source).iter().map(ToString::to_string).collect()
+    }
+
+    impl value::Source for Records {
+        fn read(&mut self) -> error::Result<Option<value::Value>> {
+            Ok(self.0.pop_front())
+        }
+
+        fn size_hint(&self) -> (usize, Option<usize>) {
+            (self.0.len(), Some(self.0.len()))
+        }
+    }
+}
@@ -0,0 +1,255 @@
+//! Grouping records under a parent record, the reverse of exploding them.
+
+use std::collections;
+use std::vec;
+
+use crate::error;
+use crate::value;
+
+/// A source that groups the records of another source by a key field, and yields one parent
+/// record per group with the grouped values in a sequence field.
+///
+/// For records that have the `into` field, its value is moved into the sequence and the rest of
+/// the first record of the group becomes the parent, which reverses exploding the field.  Other
+/// records are moved into the sequence as a whole (without the key), and the parent only has the
+/// key, which rebuilds documents from flat rows.
+#[derive(Debug)]
+pub struct Source<S> {
+    inner: S,
+    key: value::path::Path,
+    into: value::path::Path,
+    mode: Mode,
+}
+
+#[derive(Debug)]
+enum Mode {
+    /// Groups consecutive records with the same key; the next record is kept for the next group.
+    Consecutive(Option<value::Value>),
+    /// Groups all records with the same key, buffering the whole input first.
+    All(Option<vec::IntoIter<value::Value>>),
+}
+
+#[derive(Debug)]
+struct Group {
+    parent: value::Value,
+    children: Vec<value::Value>,
+}
+
+/// Creates a source that nests records by `key` into the `into` field.  If `all` is set, records
+/// with the same key are grouped wherever they are in the input; otherwise only consecutive ones
+/// are, which works in constant memory for sorted input.
+pub fn source<S>(inner: S, key: value::path::Path, into: value::path::Path, all: bool) -> Source<S>
+where
+    S: value::Source,
+{
+    let mode = if all {
+        Mode::All(None)
+    } else {
+        Mode::Consecutive(None)
+    };
+    Source {
+        inner,
+        key,
+        into,
+        mode,
+    }
+}
+
+impl<S> Source<S>
+where
+    S: value::Source,
+{
+    fn key_of(&self, record: &value::Value) -> value::Value {
+        self.key.get(record).cloned().unwrap_or(value::Value::Unit)
+    }
+
+    fn start_group(&self, mut record: value::Value) -> Group {
+        let child = self.into.remove(&mut record);
+        match child {
+            Some(child) => Group {
+                parent: record,
+                children: vec![child],
+            },
+            None => {
+                let mut parent = value::Value::Map(Vec::new());
+                if let Some(key) = self.key.remove(&mut record) {
+                    self.key.set(&mut parent, key);
+                }
+                Group {
+                    parent,
+                    children: vec![record],
+                }
+            }
+        }
+    }
+
+    fn add_to_group(&self, group: &mut Group, mut record: value::Value) {
+        match self.into.remove(&mut record) {
+            Some(child) => group.children.push(child),
+            None => {
+                self.key.remove(&mut record);
+                group.children.push(record);
+            }
+        }
+    }
+
+    fn finish_group(&self, group: Group) -> value::Value {
+        let mut parent = group.parent;
+        if !self
+            .into
+            .set(&mut parent, value::Value::Sequence(group.children))
+        {
+            warn!("Couldn't add {} to a nested record", self.into);
+        }
+        parent
+    }
+
+    fn read_consecutive(
+        &mut self,
+        next: Option<value::Value>,
+    ) -> error::Result<Option<value::Value>> {
+        let first = match next {
+            Some(record) => record,
+            None => match self.inner.read()? {
+                Some(record) => record,
+                None => return Ok(None),
+            },
+        };
+        let key = self.key_of(&first);
+        let mut group = self.start_group(first);
+        while let Some(record) = self.inner.read()? {
+            if self.key_of(&record) == key {
+                self.add_to_group(&mut group, record);
+            } else {
+                self.mode = Mode::Consecutive(Some(record));
+                break;
+            }
+        }
+        Ok(Some(self.finish_group(group)))
+    }
+
+    fn collect_groups(&mut self) -> error::Result<Vec<value::Value>> {
+        let mut index = collections::HashMap::new();
+        let mut groups: Vec<Group> = Vec::new();
+        while let Some(record) = self.inner.read()? {
+            let key = self.key_of(&record);
+            match index.get(&key) {
+                Some(&i) => self.add_to_group(&mut groups[i], record),
+                None => {
+                    index.insert(key, groups.len());
+                    groups.push(self.start_group(record));
+                }
+            }
+        }
+        Ok(groups.into_iter().map(|g| self.finish_group(g)).collect())
+    }
+}
+
+impl<S> value::Source for Source<S>
+where
+    S: value::Source,
+{
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        match self.mode {
+            Mode::Consecutive(ref mut next) => {
+                let next = next.take();
+                self.read_consecutive(next)
+            }
+            Mode::All(Some(ref mut groups)) => Ok(groups.next()),
+            Mode::All(None) => {
+                let mut groups = self.collect_groups()?.into_iter();
+                let first = groups.next();
+                self.mode = Mode::All(Some(groups));
+                Ok(first)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::transform::test_util;
+
+    fn nest(key: &str, into: &str, all: bool, records: Vec<value::Value>) -> Vec<String> {
+        let records = test_util::records(records);
+        let source = source(records, key.parse().unwrap(), into.parse().unwrap(), all);
+        test_util::read_text(source)
+    }
+
+    #[test]
+    fn test_reverse_explode() {
+        let records = vec![
+            value!({"id": 1, "name": "x", "items": "a"}),
+            value!({"id": 1, "name": "x", "items": "b"}),
+            value!({"id": 2, "name": "y", "items": "c"}),
+        ];
+        assert_eq!(
+            nest(".id", ".items", false, records),
+            vec![
+                r#"{"id": 1, "name": "x", "items": ["a", "b"]}"#,
+                r#"{"id": 2, "name": "y", "items": ["c"]}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_flat_rows() {
+        let records = vec![
+            value!({"order": 1, "sku": "a", "qty": 2}),
+            value!({"order": 1, "sku": "b", "qty": 1}),
+        ];
+        assert_eq!(
+            nest(".order", ".lines", false, records),
+            vec![r#"{"order": 1, "lines": [{"qty": 2, "sku": "a"}, {"qty": 1, "sku": "b"}]}"#]
+        );
+    }
+
+    #[test]
+    fn test_consecutive_and_all() {
+        let records = || {
+            vec![
+                value!({"id": 1, "v": 1}),
+                value!({"id": 2, "v": 2}),
+                value!({"id": 1, "v": 3}),
+            ]
+        };
+        assert_eq!(
+            nest(".id", ".v", false, records()),
+            vec![
+                r#"{"id": 1, "v": [1]}"#,
+                r#"{"id": 2, "v": [2]}"#,
+                r#"{"id": 1, "v": [3]}"#,
+            ]
+        );
+        // Groups are in the order their keys were first seen
+        assert_eq!(
+            nest(".id", ".v", true, records()),
+            vec![r#"{"id": 1, "v": [1, 3]}"#, r#"{"id": 2, "v": [2]}"#]
+        );
+    }
+
+    #[test]
+    fn test_missing_key() {
+        // Records without the key are grouped together, under a parent without the key
+        let records = vec![
+            value!({"sku": "a"}),
+            value!({"id": 1, "sku": "b"}),
+            value!({"sku": "c"}),
+        ];
+        assert_eq!(
+            nest(".id", ".lines", true, records),
+            vec![
+                r#"{"lines": [{"sku": "a"}, {"sku": "c"}]}"#,
+                r#"{"id": 1, "lines": [{"sku": "b"}]}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_empty() {
+        assert_eq!(nest(".id", ".v", false, vec![]), Vec::<String>::new());
+        assert_eq!(nest(".id", ".v", true, vec![]), Vec::<String>::new());
+    }
+}
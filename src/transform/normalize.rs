@@ -0,0 +1,182 @@
+//! Cleaning up messy values, like strings with stray whitespace.
+
+use std::fmt;
+use std::str;
+
+use crate::error;
+use crate::value;
+
+/// A source that normalizes every value in the records of another source, recursively.
+#[derive(Debug)]
+pub struct Source<S> {
+    inner: S,
+    rules: Rules,
+}
+
+/// Which normalizations to apply, like `trim-strings,empty-as-null`.
+#[derive(Clone, Debug, Default)]
+pub struct Rules {
+    trim_strings: bool,
+    collapse_whitespace: bool,
+    empty_as_null: bool,
+    lowercase_strings: bool,
+    lowercase_keys: bool,
+}
+
+const RULE_NAMES: &[&str] = &[
+    "trim-strings",
+    "collapse-whitespace",
+    "empty-as-null",
+    "lowercase-strings",
+    "lowercase-keys",
+];
+
+pub fn source<S>(inner: S, rules: Rules) -> Source<S>
+where
+    S: value::Source,
+{
+    Source { inner, rules }
+}
+
+impl<S> value::Source for Source<S>
+where
+    S: value::Source,
+{
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        Ok(self.inner.read()?.map(|record| self.rules.apply(record)))
+    }
+}
+
+impl Rules {
+    /// Normalizes the value and everything nested in it.  Strings are trimmed and collapsed
+    /// before they are checked for being empty.
+    pub fn apply(&self, value: value::Value) -> value::Value {
+        match value {
+            value::Value::String(s) => self.string(s),
+            value::Value::Sequence(elements) => {
+                value::Value::Sequence(elements.into_iter().map(|e| self.apply(e)).collect())
+            }
+            value::Value::Map(entries) => value::Value::Map(
+                entries
+                    .into_iter()
+                    .map(|(k, v)| (self.key(k), self.apply(v)))
+                    .collect(),
+            ),
+            value => value,
+        }
+    }
+
+    fn string(&self, mut s: String) -> value::Value {
+        if self.collapse_whitespace {
+            s = s.split_whitespace().collect::<Vec<_>>().join(" ");
+        } else if self.trim_strings {
+            s = s.trim().to_owned();
+        }
+        if self.lowercase_strings {
+            s = s.to_lowercase();
+        }
+        if self.empty_as_null && s.is_empty() {
+            value::Value::Unit
+        } else {
+            value::Value::String(s)
+        }
+    }
+
+    fn key(&self, key: value::Value) -> value::Value {
+        match key {
+            value::Value::String(s) if self.lowercase_keys => {
+                value::Value::String(s.to_lowercase())
+            }
+            key => key,
+        }
+    }
+
+    fn enabled(&self) -> [bool; 5] {
+        [
+            self.trim_strings,
+            self.collapse_whitespace,
+            self.empty_as_null,
+            self.lowercase_strings,
+            self.lowercase_keys,
+        ]
+    }
+}
+
+impl fmt::Display for Rules {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let names: Vec<_> = RULE_NAMES
+            .iter()
+            .zip(self.enabled().iter())
+            .filter(|(_, &enabled)| enabled)
+            .map(|(name, _)| *name)
+            .collect();
+        f.write_str(&names.join(","))
+    }
+}
+
+impl str::FromStr for Rules {
+    type Err = error::Error;
+
+    /// Parses comma-separated rule names, like `trim-strings,empty-as-null,lowercase-keys`.
+    fn from_str(s: &str) -> error::Result<Self> {
+        let mut rules = Self::default();
+        for name in s.split(',') {
+            match name {
+                "trim-strings" => rules.trim_strings = true,
+                "collapse-whitespace" => rules.collapse_whitespace = true,
+                "empty-as-null" => rules.empty_as_null = true,
+                "lowercase-strings" => rules.lowercase_strings = true,
+                "lowercase-keys" => rules.lowercase_keys = true,
+                _ => {
+                    return Err(error::Error::Message(format!(
+                        "unknown normalization {:?} (expected {})",
+                        name,
+                        RULE_NAMES.join(", ")
+                    )))
+                }
+            }
+        }
+        Ok(rules)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::transform::test_util;
+
+    fn normalize(rules: &str, records: Vec<value::Value>) -> Vec<value::Value> {
+        let rules = rules.parse().unwrap();
+        test_util::read_all(source(test_util::records(records), rules))
+    }
+
+    #[test]
+    fn test_strings() {
+        let records = vec![value!({"a": "  Hello \t World ", "b": ["  ", " X "], "c": 1})];
+        assert_eq!(
+            normalize("trim-strings,empty-as-null", records.clone()),
+            vec![value!({"a": "Hello \t World", "b": [null, "X"], "c": 1})]
+        );
+        assert_eq!(
+            normalize("collapse-whitespace,lowercase-strings", records),
+            vec![value!({"a": "hello world", "b": ["", "x"], "c": 1})]
+        );
+    }
+
+    #[test]
+    fn test_keys() {
+        assert_eq!(
+            normalize("lowercase-keys", vec![value!({"Name": {"First": " Jo "}})]),
+            vec![value!({"name": {"first": " Jo "}})]
+        );
+    }
+
+    #[test]
+    fn test_rules() {
+        let rules: Rules = "lowercase-keys,trim-strings".parse().unwrap();
+        assert_eq!(rules.to_string(), "trim-strings,lowercase-keys");
+        assert!("trim".parse::<Rules>().is_err());
+        assert!("".parse::<Rules>().is_err());
+    }
+}
@@ -0,0 +1,349 @@
+//! Turning the values of one field into columns, aggregating another field into the cells.
+
+use std::collections;
+use std::fmt;
+use std::str;
+use std::vec;
+
+use crate::error;
+use crate::transform;
+use crate::value;
+
+/// A source that reads all records from another source, and yields a wide table: one record per
+/// distinct value of the rows field, with a column for each distinct value of the columns field.
+#[derive(Debug)]
+pub struct Source<S> {
+    inner: S,
+    spec: Spec,
+    table: Option<vec::IntoIter<value::Value>>,
+}
+
+/// How to pivot the records.
+#[derive(Clone, Debug)]
+pub struct Spec {
+    rows: value::path::Path,
+    columns: value::path::Path,
+    aggregation: Aggregation,
+}
+
+/// How to combine the records that end up in the same cell.
+#[derive(Clone, Debug)]
+pub enum Aggregation {
+    Count,
+    Sum(value::path::Path),
+    Min(value::path::Path),
+    Max(value::path::Path),
+    Mean(value::path::Path),
+}
+
+/// The aggregate of a cell so far.
+#[derive(Clone, Copy, Debug, Default)]
+struct Cell {
+    count: u64,
+    sum: f64,
+    min: f64,
+    max: f64,
+}
+
+pub fn source<S>(inner: S, spec: Spec) -> Source<S>
+where
+    S: value::Source,
+{
+    Source {
+        inner,
+        spec,
+        table: None,
+    }
+}
+
+impl<S> Source<S>
+where
+    S: value::Source,
+{
+    fn collect_table(&mut self) -> error::Result<Vec<value::Value>> {
+        let mut cells =
+            collections::BTreeMap::<value::Value, collections::BTreeMap<_, Cell>>::new();
+        let mut columns = collections::BTreeSet::new();
+        let mut skipped = 0u64;
+
+        while let Some(record) = self.inner.read()? {
+            let row = self.spec.rows.get(&record);
+            let column = self.spec.columns.get(&record);
+            let measure = match self.spec.aggregation.path() {
+                Some(path) => path.get(&record).and_then(value::Value::as_f64),
+                None => Some(0.0),
+            };
+            match (row, column, measure) {
+                (Some(row), Some(column), Some(measure)) => {
+                    let column = column_name(column);
+                    cells
+                        .entry(row.clone())
+                        .or_default()
+                        .entry(column.clone())
+                        .or_default()
+                        .add(measure);
+                    columns.insert(column);
+                }
+                _ => skipped += 1,
+            }
+        }
+        if skipped > 0 {
+            warn!(
+                "Skipped {} records that were missing fields for the pivot",
+                skipped
+            );
+        }
+
+        let row_name = self
+            .spec
+            .rows
+            .segments()
+            .last()
+            .cloned()
+            .unwrap_or_else(|| "row".to_owned());
+        Ok(cells
+            .into_iter()
+            .map(|(row, row_cells)| {
+                let mut entries = vec![(value::Value::String(row_name.clone()), row)];
+                for column in &columns {
+                    let cell = row_cells
+                        .get(column)
+                        .map_or(value::Value::Unit, |c| self.spec.aggregation.result(c));
+                    entries.push((value::Value::String(column.clone()), cell));
+                }
+                value::Value::Map(entries)
+            })
+            .collect())
+    }
+}
+
+/// The name of the column for a value of the columns field.
+fn column_name(v: &value::Value) -> String {
+    match *v {
+        value::Value::String(ref s) => s.clone(),
+        value::Value::Char(c) => c.to_string(),
+        ref other => other.to_string(),
+    }
+}
+
+impl Cell {
+    fn add(&mut self, v: f64) {
+        if self.count == 0 {
+            self.min = v;
+            self.max = v;
+        } else {
+            self.min = self.min.min(v);
+            self.max = self.max.max(v);
+        }
+        self.count += 1;
+        self.sum += v;
+    }
+}
+
+impl Aggregation {
+    fn path(&self) -> Option<&value::path::Path> {
+        match *self {
+            Self::Count => None,
+            Self::Sum(ref p) | Self::Min(ref p) | Self::Max(ref p) | Self::Mean(ref p) => Some(p),
+        }
+    }
+
+    fn result(&self, cell: &Cell) -> value::Value {
+        match *self {
+            Self::Count => value::Value::U64(cell.count),
+            Self::Sum(_) => transform::number(cell.sum),
+            Self::Min(_) => transform::number(cell.min),
+            Self::Max(_) => transform::number(cell.max),
+            Self::Mean(_) => transform::number(cell.sum / cell.count as f64),
+        }
+    }
+}
+
+impl<S> value::Source for Source<S>
+where
+    S: value::Source,
+{
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        if self.table.is_none() {
+            self.table = Some(self.collect_table()?.into_iter());
+        }
+        Ok(self.table.as_mut().and_then(Iterator::next))
+    }
+}
+
+impl fmt::Display for Aggregation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Self::Count => write!(f, "count"),
+            Self::Sum(ref p) => write!(f, "sum({})", p),
+            Self::Min(ref p) => write!(f, "min({})", p),
+            Self::Max(ref p) => write!(f, "max({})", p),
+            Self::Mean(ref p) => write!(f, "mean({})", p),
+        }
+    }
+}
+
+impl str::FromStr for Aggregation {
+    type Err = error::Error;
+
+    /// Parses `count`, or one of `sum`, `min`, `max` and `mean` applied to a path, like
+    /// `sum(.revenue)`.
+    fn from_str(s: &str) -> error::Result<Self> {
+        if s == "count" {
+            return Ok(Self::Count);
+        }
+        let invalid = || error::Error::Message(format!("invalid aggregation: {}", s));
+        let (function, rest) = s.split_once('(').ok_or_else(invalid)?;
+        let path = value::path::Path::from(rest.strip_suffix(')').ok_or_else(invalid)?);
+        match function {
+            "sum" => Ok(Self::Sum(path)),
+            "min" => Ok(Self::Min(path)),
+            "max" => Ok(Self::Max(path)),
+            "mean" => Ok(Self::Mean(path)),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for Spec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "rows={},columns={},values={}",
+            self.rows, self.columns, self.aggregation
+        )
+    }
+}
+
+impl str::FromStr for Spec {
+    type Err = error::Error;
+
+    /// Parses specs like `rows=.region,columns=.month,values=sum(.revenue)`.  The values default
+    /// to `count`.
+    fn from_str(s: &str) -> error::Result<Self> {
+        let invalid = |msg: &str| error::Error::Message(format!("invalid pivot: {}: {}", msg, s));
+
+        let mut rows = None;
+        let mut columns = None;
+        let mut aggregation = Aggregation::Count;
+        for part in s.split(',') {
+            match part.split_once('=') {
+                Some(("rows", path)) => rows = Some(value::path::Path::from(path)),
+                Some(("columns", path)) => columns = Some(value::path::Path::from(path)),
+                Some(("values", values)) => aggregation = values.parse()?,
+                _ => return Err(invalid("expected rows=, columns= or values=")),
+            }
+        }
+        Ok(Self {
+            rows: rows.ok_or_else(|| invalid("missing rows="))?,
+            columns: columns.ok_or_else(|| invalid("missing columns="))?,
+            aggregation,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::transform::test_util;
+
+    fn pivot(spec: &str, records: Vec<value::Value>) -> Vec<String> {
+        let records = test_util::records(records);
+        test_util::read_text(source(records, spec.parse().unwrap()))
+    }
+
+    fn sales() -> Vec<value::Value> {
+        vec![
+            value!({"region": "south", "month": "feb", "revenue": 5}),
+            value!({"region": "north", "month": "jan", "revenue": 10}),
+            value!({"region": "north", "month": "jan", "revenue": 20}),
+            value!({"region": "north", "month": "feb", "revenue": 1.5}),
+        ]
+    }
+
+    #[test]
+    fn test_count() {
+        assert_eq!(
+            pivot("rows=.region,columns=.month", sales()),
+            vec![
+                r#"{"region": "north", "feb": 1, "jan": 2}"#,
+                // Cells without records are null
+                r#"{"region": "south", "feb": 1, "jan": null}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_aggregations() {
+        let spec = "rows=.region,columns=.month,values=";
+        assert_eq!(
+            pivot(&format!("{}sum(.revenue)", spec), sales()),
+            vec![
+                r#"{"region": "north", "feb": 1.5, "jan": 30}"#,
+                r#"{"region": "south", "feb": 5, "jan": null}"#,
+            ]
+        );
+        assert_eq!(
+            pivot(&format!("{}min(.revenue)", spec), sales())[0],
+            r#"{"region": "north", "feb": 1.5, "jan": 10}"#
+        );
+        assert_eq!(
+            pivot(&format!("{}max(.revenue)", spec), sales())[0],
+            r#"{"region": "north", "feb": 1.5, "jan": 20}"#
+        );
+        assert_eq!(
+            pivot(&format!("{}mean(.revenue)", spec), sales())[0],
+            r#"{"region": "north", "feb": 1.5, "jan": 15}"#
+        );
+    }
+
+    #[test]
+    fn test_column_names() {
+        let records = vec![
+            value!({"id": 1, "status": 200}),
+            value!({"id": 1, "status": true}),
+        ];
+        assert_eq!(
+            pivot("rows=.id,columns=.status", records),
+            vec![r#"{"id": 1, "200": 1, "true": 1}"#]
+        );
+    }
+
+    #[test]
+    fn test_skipped_records() {
+        let records = vec![
+            value!({"month": "jan", "revenue": 1}),
+            value!({"region": "north", "revenue": 1}),
+            value!({"region": "north", "month": "jan"}),
+            value!({"region": "north", "month": "jan", "revenue": "1"}),
+            value!({"region": "north", "month": "jan", "revenue": 2}),
+        ];
+        assert_eq!(
+            pivot("rows=.region,columns=.month,values=sum(.revenue)", records),
+            vec![r#"{"region": "north", "jan": 2}"#]
+        );
+    }
+
+    #[test]
+    fn test_empty() {
+        assert_eq!(
+            pivot("rows=.region,columns=.month", vec![]),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn test_parse() {
+        assert!("rows=.a".parse::<Spec>().is_err());
+        assert!("columns=.a".parse::<Spec>().is_err());
+        assert!("rows=.a,columns=.b,values=median(.c)"
+            .parse::<Spec>()
+            .is_err());
+        assert!("rows=.a,columns=.b,values=sum.c".parse::<Spec>().is_err());
+        assert_eq!(
+            "rows=.a,columns=.b".parse::<Spec>().unwrap().to_string(),
+            "rows=.a,columns=.b,values=count"
+        );
+    }
+}
@@ -0,0 +1,341 @@
+//! Estimating quantiles of a numeric field in bounded memory, using a t-digest.
+
+use std::f64::consts;
+use std::fmt;
+use std::str;
+
+use crate::error;
+use crate::transform;
+use crate::value;
+
+/// Controls the accuracy of the digest; higher values keep more (and smaller) centroids.
+const COMPRESSION: f64 = 1000.0;
+/// How many values to collect before merging them into the centroids.
+const BUFFER_SIZE: usize = 10_000;
+
+/// A source that reads all records from another source, and yields a single record summarizing
+/// a numeric field, like `{"count": 1000, "min": 1, "max": 930, "mean": 48.2, "p50": 31,
+/// "p99": 802.5}`.
+#[derive(Debug)]
+pub struct Source<S> {
+    inner: S,
+    spec: Spec,
+    done: bool,
+}
+
+/// Which field to summarize, and which quantiles to estimate.
+#[derive(Clone, Debug)]
+pub struct Spec {
+    path: value::path::Path,
+    /// The names of the quantiles (like `p99`) and the quantiles themselves (like 0.99).
+    quantiles: Vec<(String, f64)>,
+}
+
+/// A merging t-digest (Dunning & Ertl, "Computing extremely accurate quantiles using
+/// t-digests").  Centroids are small near the tails, so extreme quantiles stay accurate.
+#[derive(Debug, Default)]
+struct Digest {
+    /// Centroids as (mean, weight), ordered by mean.
+    centroids: Vec<(f64, f64)>,
+    buffer: Vec<f64>,
+    count: u64,
+    sum: f64,
+    min: f64,
+    max: f64,
+}
+
+pub fn source<S>(inner: S, spec: Spec) -> Source<S>
+where
+    S: value::Source,
+{
+    Source {
+        inner,
+        spec,
+        done: false,
+    }
+}
+
+impl<S> Source<S>
+where
+    S: value::Source,
+{
+    fn summarize(&mut self) -> error::Result<value::Value> {
+        let mut digest = Digest::default();
+        let mut skipped = 0u64;
+        while let Some(record) = self.inner.read()? {
+            match self.spec.path.get(&record).and_then(value::Value::as_f64) {
+                Some(v) if v.is_finite() => digest.add(v),
+                _ => skipped += 1,
+            }
+        }
+        if skipped > 0 {
+            warn!(
+                "Skipped {} records without a number at {} for the quantiles",
+                skipped, self.spec.path
+            );
+        }
+        digest.merge();
+
+        let stat = |v: f64| {
+            if digest.count > 0 {
+                transform::number(v)
+            } else {
+                value::Value::Unit
+            }
+        };
+        let mut summary = vec![
+            (value::Value::from("count"), value::Value::U64(digest.count)),
+            (value::Value::from("min"), stat(digest.min)),
+            (value::Value::from("max"), stat(digest.max)),
+            (
+                value::Value::from("mean"),
+                stat(digest.sum / digest.count as f64),
+            ),
+        ];
+        for &(ref name, q) in &self.spec.quantiles {
+            summary.push((value::Value::from(name.as_str()), stat(digest.quantile(q))));
+        }
+        Ok(value::Value::Map(summary))
+    }
+}
+
+impl Digest {
+    fn add(&mut self, v: f64) {
+        if self.count == 0 {
+            self.min = v;
+            self.max = v;
+        } else {
+            self.min = self.min.min(v);
+            self.max = self.max.max(v);
+        }
+        self.count += 1;
+        self.sum += v;
+        self.buffer.push(v);
+        if self.buffer.len() >= BUFFER_SIZE {
+            self.merge();
+        }
+    }
+
+    /// Merges the buffered values into the centroids.
+    fn merge(&mut self) {
+        if self.buffer.is_empty() {
+            return;
+        }
+        let mut all: Vec<(f64, f64)> = self.centroids.drain(..).collect();
+        all.extend(self.buffer.drain(..).map(|v| (v, 1.0)));
+        all.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let total: f64 = all.iter().map(|c| c.1).sum();
+        let mut merged = Vec::new();
+        let mut current = all[0];
+        let mut weight_before = 0.0;
+        let mut limit = total * q_limit(0.0);
+        for &next in &all[1..] {
+            if weight_before + current.1 + next.1 <= limit {
+                // Merge into the current centroid, keeping the weighted mean
+                let weight = current.1 + next.1;
+                current.0 += (next.0 - current.0) * next.1 / weight;
+                current.1 = weight;
+            } else {
+                weight_before += current.1;
+                limit = total * q_limit(weight_before / total);
+                merged.push(current);
+                current = next;
+            }
+        }
+        merged.push(current);
+        self.centroids = merged;
+    }
+
+    fn quantile(&self, q: f64) -> f64 {
+        if self.centroids.len() == 1 {
+            return self.centroids[0].0;
+        }
+        let total: f64 = self.centroids.iter().map(|c| c.1).sum();
+        let target = q * total;
+
+        // Each centroid's mean is assumed to be at the middle of its weight; interpolate between
+        // those points, and towards min and max at the ends
+        let mut previous = (self.min, 0.0);
+        let mut cumulative = 0.0;
+        for &(mean, weight) in &self.centroids {
+            let center = (mean, cumulative + weight / 2.0);
+            if target < center.1 {
+                return interpolate(previous, center, target);
+            }
+            previous = center;
+            cumulative += weight;
+        }
+        interpolate(previous, (self.max, total), target)
+    }
+}
+
+/// The quantile up to which a centroid starting at quantile `q` can grow, using the k1 scale
+/// function, which makes centroids smaller near the tails.
+fn q_limit(q: f64) -> f64 {
+    let k = COMPRESSION / (2.0 * consts::PI) * (2.0 * q - 1.0).asin();
+    let k = k + 1.0;
+    ((k * 2.0 * consts::PI / COMPRESSION)
+        .min(consts::FRAC_PI_2)
+        .sin()
+        + 1.0)
+        / 2.0
+}
+
+fn interpolate((v0, w0): (f64, f64), (v1, w1): (f64, f64), target: f64) -> f64 {
+    if w1 <= w0 {
+        v1
+    } else {
+        v0 + (v1 - v0) * ((target - w0) / (w1 - w0)).clamp(0.0, 1.0)
+    }
+}
+
+impl<S> value::Source for Source<S>
+where
+    S: value::Source,
+{
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        if self.done {
+            return Ok(None);
+        }
+        self.done = true;
+        self.summarize().map(Some)
+    }
+}
+
+impl fmt::Display for Spec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let names: Vec<_> = self.quantiles.iter().map(|q| q.0.as_str()).collect();
+        write!(f, "{}:{}", self.path, names.join(","))
+    }
+}
+
+impl str::FromStr for Spec {
+    type Err = error::Error;
+
+    /// Parses specs like `.latency_ms:p50,p95,p99.9`.  Without a list of quantiles, the median,
+    /// 90th and 99th percentiles are estimated.
+    fn from_str(s: &str) -> error::Result<Self> {
+        let (path, quantiles) = s.rsplit_once(':').unwrap_or((s, "p50,p90,p99"));
+        let quantiles = quantiles
+            .split(',')
+            .map(|name| {
+                let percentile = name.strip_prefix('p').and_then(|p| p.parse::<f64>().ok());
+                match percentile {
+                    Some(p) if (0.0..=100.0).contains(&p) => Ok((name.to_owned(), p / 100.0)),
+                    _ => Err(error::Error::Message(format!(
+                        "invalid quantile (expected a percentile like p99): {}",
+                        name
+                    ))),
+                }
+            })
+            .collect::<error::Result<Vec<_>>>()?;
+        Ok(Self {
+            path: value::path::Path::from(path),
+            quantiles,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::transform::test_util;
+
+    fn quantiles(spec: &str, records: Vec<value::Value>) -> Vec<String> {
+        let records = test_util::records(records);
+        test_util::read_text(source(records, spec.parse().unwrap()))
+    }
+
+    #[test]
+    fn test_small() {
+        let records = (1..=4).map(|ms| value!({ "ms": ms })).collect();
+        assert_eq!(
+            quantiles(".ms:p0,p50,p100", records),
+            vec![
+                r#"{"count": 4, "min": 1, "max": 4, "mean": 2.5, "p0": 1, "p50": 2.5, "p100": 4}"#
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ties() {
+        let records = vec![
+            value!({"ms": 7}),
+            value!({"ms": 7}),
+            value!({"ms": 7}),
+            value!({"ms": 7}),
+        ];
+        assert_eq!(
+            quantiles(".ms:p50,p99", records),
+            vec![r#"{"count": 4, "min": 7, "max": 7, "mean": 7, "p50": 7, "p99": 7}"#]
+        );
+    }
+
+    #[test]
+    fn test_large() {
+        // Enough values to be merged into centroids several times
+        let records = (0..100_000)
+            .map(|i| value!({ "ms": ((i * 7919) % 100_000) }))
+            .collect();
+        let summary = test_util::read_all(source(
+            test_util::records::<Vec<_>>(records),
+            ".ms:p1,p50,p99".parse().unwrap(),
+        ))
+        .remove(0);
+        assert_eq!(
+            summary.get(".count").and_then(value::Value::as_f64),
+            Some(100_000.0)
+        );
+        for &(name, expected) in &[("p1", 1_000.0), ("p50", 50_000.0), ("p99", 99_000.0)] {
+            let actual = summary
+                .get(&format!(".{}", name))
+                .and_then(value::Value::as_f64);
+            let actual = actual.unwrap();
+            assert!(
+                (actual - expected).abs() < 100.0,
+                "{} was {}, expected about {}",
+                name,
+                actual,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_skipped_values() {
+        let records = vec![
+            value!({"ms": "5"}),
+            value!({"other": 5}),
+            value!({"ms": null}),
+            value!({"ms": 5}),
+        ];
+        assert_eq!(
+            quantiles(".ms:p50", records),
+            vec![r#"{"count": 1, "min": 5, "max": 5, "mean": 5, "p50": 5}"#]
+        );
+    }
+
+    #[test]
+    fn test_empty() {
+        assert_eq!(
+            quantiles(".ms:p50", vec![]),
+            vec![r#"{"count": 0, "min": null, "max": null, "mean": null, "p50": null}"#]
+        );
+    }
+
+    #[test]
+    fn test_parse() {
+        assert!(".ms:median".parse::<Spec>().is_err());
+        assert!(".ms:p101".parse::<Spec>().is_err());
+        assert_eq!(
+            ".ms".parse::<Spec>().unwrap().to_string(),
+            ".ms:p50,p90,p99"
+        );
+        assert_eq!(
+            ".ms:p99.9".parse::<Spec>().unwrap().to_string(),
+            ".ms:p99.9"
+        );
+    }
+}
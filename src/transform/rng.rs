@@ -0,0 +1,58 @@
+//! A small seedable random number generator, so that randomized stages are reproducible.
+
+// Streams for the stages that need random numbers
+pub(crate) const SAMPLE: u64 = 1;
+pub(crate) const SHUFFLE: u64 = 2;
+pub(crate) const SPLIT: u64 = 3;
+
+/// A SplitMix64 generator.  It is not cryptographically secure, but it is fast, has good
+/// statistical properties, and produces the same sequence for a seed on every platform.
+#[derive(Clone, Debug)]
+pub(crate) struct Rng(u64);
+
+/// A seed that is different for every invocation, for when no seed was specified.
+pub fn random_seed() -> u64 {
+    use std::collections::hash_map;
+    use std::hash::BuildHasher;
+
+    // `RandomState` is seeded randomly, which is good enough for this purpose
+    hash_map::RandomState::new().hash_one(0u64)
+}
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    /// A generator for one of several stages that share a seed.  Each stream gets a differently
+    /// scrambled seed, so that stages don't make correlated choices.
+    pub(crate) fn for_stream(seed: u64, stream: u64) -> Self {
+        let mut scrambler = Self(stream);
+        Self(seed ^ scrambler.next_u64())
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A uniformly distributed number in `0.0..1.0`.
+    pub(crate) fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// A uniformly distributed number in `0..n`, which must not be empty.
+    pub(crate) fn below(&mut self, n: u64) -> u64 {
+        // Reject the values that would make the lower numbers more likely
+        let zone = u64::MAX - u64::MAX % n;
+        loop {
+            let v = self.next_u64();
+            if v < zone {
+                return v % n;
+            }
+        }
+    }
+}
@@ -0,0 +1,253 @@
+//! Recognizing JSON-RPC and MessagePack-RPC messages in captured traffic.
+
+use std::collections;
+
+use crate::error;
+use crate::value;
+
+/// A source that turns the RPC messages from another source into records with the same
+/// top-level fields, whatever the protocol: `protocol`, `kind` (`request`, `notification` or
+/// `response`), `id`, `method`, and `params` for requests or `result` and `error` for
+/// responses.
+///
+/// JSON-RPC messages are maps, and batches of them are split into one record per message.
+/// MessagePack-RPC messages are sequences like `[0, id, method, params]`.  Responses get the
+/// method of the request with the same id, if it came earlier.  Records that aren't RPC messages
+/// are passed through as is.
+#[derive(Debug)]
+pub struct Source<S> {
+    inner: S,
+    pending: collections::VecDeque<value::Value>,
+    /// The methods of requests by id, until their response is seen.
+    methods: collections::HashMap<value::Value, value::Value>,
+}
+
+pub fn source<S>(inner: S) -> Source<S>
+where
+    S: value::Source,
+{
+    Source {
+        inner,
+        pending: collections::VecDeque::new(),
+        methods: collections::HashMap::new(),
+    }
+}
+
+/// An RPC message, taken apart.
+#[derive(Debug, Default)]
+struct Message {
+    protocol: &'static str,
+    id: Option<value::Value>,
+    method: Option<value::Value>,
+    params: Option<value::Value>,
+    result: Option<value::Value>,
+    error: Option<value::Value>,
+}
+
+impl<S> value::Source for Source<S>
+where
+    S: value::Source,
+{
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        loop {
+            let record = match self.pending.pop_front() {
+                Some(record) => record,
+                None => match self.inner.read()? {
+                    Some(value::Value::Sequence(batch)) if is_json_rpc_batch(&batch) => {
+                        self.pending.extend(batch);
+                        continue;
+                    }
+                    Some(record) => record,
+                    None => return Ok(None),
+                },
+            };
+            return Ok(Some(match parse(&record) {
+                Some(message) => self.describe(message),
+                None => record,
+            }));
+        }
+    }
+
+    #[inline]
+    fn position(&self) -> Option<value::Position> {
+        self.inner.position()
+    }
+}
+
+impl<S> Source<S> {
+    fn describe(&mut self, mut message: Message) -> value::Value {
+        let kind = if message.method.is_none() {
+            if let Some(ref id) = message.id {
+                message.method = self.methods.remove(id);
+            }
+            "response"
+        } else if let Some(ref id) = message.id {
+            if let Some(ref method) = message.method {
+                self.methods.insert(id.clone(), method.clone());
+            }
+            "request"
+        } else {
+            "notification"
+        };
+
+        let mut entries = vec![
+            ("protocol".into(), message.protocol.into()),
+            ("kind".into(), kind.into()),
+        ];
+        let fields = vec![
+            ("id", message.id),
+            ("method", message.method),
+            ("params", message.params),
+            ("result", message.result),
+            ("error", message.error),
+        ];
+        for (name, v) in fields {
+            if let Some(v) = v {
+                entries.push((name.into(), v));
+            }
+        }
+        value::Value::Map(entries)
+    }
+}
+
+fn is_json_rpc_batch(batch: &[value::Value]) -> bool {
+    !batch.is_empty()
+        && batch.iter().all(|message| {
+            matches!(
+                parse(message),
+                Some(Message {
+                    protocol: "JSON-RPC",
+                    ..
+                })
+            )
+        })
+}
+
+fn parse(record: &value::Value) -> Option<Message> {
+    match *record {
+        value::Value::Map(ref entries) => parse_json_rpc(entries),
+        value::Value::Sequence(ref elements) => parse_message_pack_rpc(elements),
+        _ => None,
+    }
+}
+
+fn parse_json_rpc(entries: &[(value::Value, value::Value)]) -> Option<Message> {
+    let field = |name: &str| {
+        entries
+            .iter()
+            .find(|(k, _)| k.as_str() == Some(name))
+            .map(|(_, v)| v.clone())
+    };
+    // Version 1.0 messages have no version field, but always have an id
+    let versioned = field("jsonrpc").is_some();
+    let id = field("id");
+    if !versioned && id.is_none() {
+        return None;
+    }
+
+    let message = Message {
+        protocol: "JSON-RPC",
+        id,
+        method: field("method").filter(|m| m.as_str().is_some()),
+        params: field("params"),
+        result: field("result"),
+        error: field("error"),
+    };
+    if message.method.is_some() || message.result.is_some() || message.error.is_some() {
+        Some(message)
+    } else {
+        None
+    }
+}
+
+fn parse_message_pack_rpc(elements: &[value::Value]) -> Option<Message> {
+    let kind = elements.first().and_then(value::Value::as_i64)?;
+    let message = Message {
+        protocol: "MessagePack-RPC",
+        ..Message::default()
+    };
+    match (kind, elements) {
+        (0, [_, id, method, params]) if method.as_str().is_some() => Some(Message {
+            id: Some(id.clone()),
+            method: Some(method.clone()),
+            params: Some(params.clone()),
+            ..message
+        }),
+        // Either the error or the result is nil
+        (1, [_, id, error, _]) if *error != value::Value::Unit => Some(Message {
+            id: Some(id.clone()),
+            error: Some(error.clone()),
+            ..message
+        }),
+        (1, [_, id, _, result]) => Some(Message {
+            id: Some(id.clone()),
+            result: Some(result.clone()),
+            ..message
+        }),
+        (2, [_, method, params]) if method.as_str().is_some() => Some(Message {
+            method: Some(method.clone()),
+            params: Some(params.clone()),
+            ..message
+        }),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::transform::test_util;
+
+    fn messages(input: &str) -> Vec<String> {
+        let records = test_util::read_all(value::json::source(input.as_bytes()));
+        test_util::read_text(source(test_util::records(records)))
+    }
+
+    #[test]
+    fn test_json_rpc() {
+        let input = r#"
+            [{"jsonrpc": "2.0", "method": "sum", "params": [1, 2], "id": 1},
+             {"jsonrpc": "2.0", "method": "ping"}]
+            {"jsonrpc": "2.0", "result": 3, "id": 1}
+            {"jsonrpc": "2.0", "error": {"code": -32601}, "id": 2}
+            {"id": 3, "method": "echo", "params": ["hi"]}
+            {"unrelated": true}
+            [{"jsonrpc": "2.0", "method": "ping"}, {"unrelated": true}]
+        "#;
+        assert_eq!(
+            messages(input),
+            vec![
+                r#"{"protocol": "JSON-RPC", "kind": "request", "id": 1, "method": "sum", "params": [1, 2]}"#,
+                r#"{"protocol": "JSON-RPC", "kind": "notification", "method": "ping"}"#,
+                r#"{"protocol": "JSON-RPC", "kind": "response", "id": 1, "method": "sum", "result": 3}"#,
+                r#"{"protocol": "JSON-RPC", "kind": "response", "id": 2, "error": {"code": -32601}}"#,
+                r#"{"protocol": "JSON-RPC", "kind": "request", "id": 3, "method": "echo", "params": ["hi"]}"#,
+                r#"{"unrelated": true}"#,
+                // Batches are only split if all of their elements are messages
+                r#"[{"jsonrpc": "2.0", "method": "ping"}, {"unrelated": true}]"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_message_pack_rpc() {
+        let input = r#"
+            [0, 7, "add", [1]]
+            [1, 7, "overflow", null]
+            [1, 8, null, 2]
+            [2, "log", ["started"]]
+            [3, "unknown"]
+        "#;
+        assert_eq!(
+            messages(input),
+            vec![
+                r#"{"protocol": "MessagePack-RPC", "kind": "request", "id": 7, "method": "add", "params": [1]}"#,
+                r#"{"protocol": "MessagePack-RPC", "kind": "response", "id": 7, "method": "add", "error": "overflow"}"#,
+                r#"{"protocol": "MessagePack-RPC", "kind": "response", "id": 8, "result": 2}"#,
+                r#"{"protocol": "MessagePack-RPC", "kind": "notification", "method": "log", "params": ["started"]}"#,
+                r#"[3, "unknown"]"#,
+            ]
+        );
+    }
+}
@@ -0,0 +1,143 @@
+//! Picking a uniformly random sample of a fixed number of records.
+
+use std::vec;
+
+use crate::error;
+use crate::transform::rng;
+use crate::value;
+
+/// A source that yields a random sample of the records of another source.
+///
+/// The whole input is read before the first record is returned, but only the sample is kept in
+/// memory.  The sampled records are returned in their original order.
+#[derive(Debug)]
+pub struct Source<S> {
+    inner: S,
+    size: usize,
+    rng: rng::Rng,
+    sample: Option<vec::IntoIter<value::Value>>,
+}
+
+/// Creates a source that samples exactly `size` records (or all of them, if there are fewer) from
+/// `inner`.  The same seed always picks the same records from the same input.
+pub fn source<S>(inner: S, size: usize, seed: u64) -> Source<S>
+where
+    S: value::Source,
+{
+    Source {
+        inner,
+        size,
+        rng: rng::Rng::for_stream(seed, rng::SAMPLE),
+        sample: None,
+    }
+}
+
+impl<S> Source<S>
+where
+    S: value::Source,
+{
+    /// Reservoir sampling ("algorithm R"): the n:th record replaces a random element of the
+    /// reservoir with probability size/n.
+    fn collect_sample(&mut self) -> error::Result<Vec<value::Value>> {
+        let mut reservoir = Vec::with_capacity(self.size);
+        let mut seen = 0u64;
+        while let Some(record) = self.inner.read()? {
+            if reservoir.len() < self.size {
+                reservoir.push((seen, record));
+            } else {
+                let i = self.rng.below(seen + 1) as usize;
+                if i < self.size {
+                    reservoir[i] = (seen, record);
+                }
+            }
+            seen += 1;
+        }
+        debug!("Sampled {} of {} records", reservoir.len(), seen);
+
+        reservoir.sort_by_key(|&(index, _)| index);
+        Ok(reservoir.into_iter().map(|(_, record)| record).collect())
+    }
+}
+
+impl<S> value::Source for Source<S>
+where
+    S: value::Source,
+{
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        if self.sample.is_none() {
+            self.sample = Some(self.collect_sample()?.into_iter());
+        }
+        Ok(self.sample.as_mut().and_then(Iterator::next))
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self.sample {
+            Some(ref sample) => sample.size_hint(),
+            None => {
+                let (lower, upper) = self.inner.size_hint();
+                (
+                    lower.min(self.size),
+                    Some(upper.map_or(self.size, |u| u.min(self.size))),
+                )
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::transform::test_util;
+    use crate::value::Source as _;
+
+    fn numbers(n: i64) -> test_util::Records {
+        test_util::records((0..n).map(value::Value::I64))
+    }
+
+    fn sample(n: i64, size: usize, seed: u64) -> Vec<i64> {
+        test_util::read_all(source(numbers(n), size, seed))
+            .iter()
+            .map(|v| v.as_i64().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_seeded() {
+        assert_eq!(sample(100, 10, 7), sample(100, 10, 7));
+        assert_ne!(sample(100, 10, 7), sample(100, 10, 8));
+    }
+
+    #[test]
+    fn test_fewer_records_than_size() {
+        assert_eq!(sample(5, 10, 1), vec![0, 1, 2, 3, 4]);
+        assert_eq!(sample(5, 5, 1), vec![0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_more_records_than_size() {
+        let picked = sample(1000, 10, 3);
+        assert_eq!(picked.len(), 10);
+        // In their original order, without duplicates
+        assert!(picked.windows(2).all(|w| w[0] < w[1]));
+        assert!(picked.iter().all(|&n| (0..1000).contains(&n)));
+        // Not just the first records
+        assert_ne!(picked, (0..10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_empty() {
+        assert_eq!(sample(0, 10, 1), Vec::<i64>::new());
+        assert_eq!(sample(10, 0, 1), Vec::<i64>::new());
+    }
+
+    #[test]
+    fn test_size_hint() {
+        assert_eq!(source(numbers(100), 10, 1).size_hint(), (10, Some(10)));
+        assert_eq!(source(numbers(5), 10, 1).size_hint(), (5, Some(5)));
+        let mut sampled = source(numbers(100), 10, 1);
+        sampled.read().unwrap();
+        assert_eq!(sampled.size_hint(), (9, Some(9)));
+    }
+}
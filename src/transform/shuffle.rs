@@ -0,0 +1,105 @@
+//! Putting records in a random order.
+
+use std::vec;
+
+use crate::error;
+use crate::transform::rng;
+use crate::value;
+
+/// A source that yields the records of another source in a random order.
+///
+/// All records are buffered in memory before the first one is returned.
+#[derive(Debug)]
+pub struct Source<S> {
+    inner: S,
+    rng: rng::Rng,
+    records: Option<vec::IntoIter<value::Value>>,
+}
+
+/// Creates a source that shuffles the records of `inner`.  The same seed always produces the
+/// same order for the same input.
+pub fn source<S>(inner: S, seed: u64) -> Source<S>
+where
+    S: value::Source,
+{
+    Source {
+        inner,
+        rng: rng::Rng::for_stream(seed, rng::SHUFFLE),
+        records: None,
+    }
+}
+
+impl<S> Source<S>
+where
+    S: value::Source,
+{
+    fn collect_shuffled(&mut self) -> error::Result<Vec<value::Value>> {
+        let mut records = Vec::with_capacity(self.inner.size_hint().0);
+        while let Some(record) = self.inner.read()? {
+            records.push(record);
+        }
+        debug!("Shuffling {} records", records.len());
+
+        // Fisher-Yates
+        for i in (1..records.len()).rev() {
+            let j = self.rng.below(i as u64 + 1) as usize;
+            records.swap(i, j);
+        }
+        Ok(records)
+    }
+}
+
+impl<S> value::Source for Source<S>
+where
+    S: value::Source,
+{
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        if self.records.is_none() {
+            self.records = Some(self.collect_shuffled()?.into_iter());
+        }
+        Ok(self.records.as_mut().and_then(Iterator::next))
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self.records {
+            Some(ref records) => records.size_hint(),
+            None => self.inner.size_hint(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::transform::test_util;
+
+    fn shuffle(n: i64, seed: u64) -> Vec<i64> {
+        let records = test_util::records((0..n).map(value::Value::I64));
+        test_util::read_all(source(records, seed))
+            .iter()
+            .map(|v| v.as_i64().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_seeded() {
+        assert_eq!(shuffle(100, 7), shuffle(100, 7));
+        assert_ne!(shuffle(100, 7), shuffle(100, 8));
+    }
+
+    #[test]
+    fn test_permutation() {
+        let mut shuffled = shuffle(100, 1);
+        assert_ne!(shuffled, (0..100).collect::<Vec<_>>());
+        shuffled.sort_unstable();
+        assert_eq!(shuffled, (0..100).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_small() {
+        assert_eq!(shuffle(0, 1), Vec::<i64>::new());
+        assert_eq!(shuffle(1, 1), vec![0]);
+    }
+}
@@ -0,0 +1,418 @@
+//! Listing the resources in Terraform state.
+
+use std::collections;
+use std::fmt;
+use std::str;
+
+use crate::error;
+use crate::value;
+
+/// Conditions on resources, like `type=aws_instance,module=module.vpc`, that must all hold.
+/// Values are glob patterns, like `type=aws_*`.
+#[derive(Clone, Debug, Default)]
+pub struct Filters(Vec<(Field, glob::Pattern)>);
+
+/// A field of a resource that can be filtered on.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Field {
+    Address,
+    Mode,
+    Module,
+    Name,
+    Provider,
+    Type,
+}
+
+/// A source that yields a record for each resource instance in Terraform state from another
+/// source, with its `address`, `module` (null in the root module), `mode` (`managed` or
+/// `data`), `type`, `name`, `index` (null unless it uses `count` or `for_each`), `provider` and
+/// `attributes`.
+///
+/// Both state files (`terraform.tfstate`) and the output of `terraform show -json` are read.
+/// Records that are neither are passed through as is.
+#[derive(Debug)]
+pub struct Source<S> {
+    inner: S,
+    filters: Filters,
+    pending: collections::VecDeque<value::Value>,
+}
+
+pub fn source<S>(inner: S, filters: Filters) -> Source<S>
+where
+    S: value::Source,
+{
+    Source {
+        inner,
+        filters,
+        pending: collections::VecDeque::new(),
+    }
+}
+
+impl<S> value::Source for Source<S>
+where
+    S: value::Source,
+{
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        loop {
+            if let Some(record) = self.pending.pop_front() {
+                return Ok(Some(record));
+            }
+            let record = match self.inner.read()? {
+                Some(record) => record,
+                None => return Ok(None),
+            };
+            let instances =
+                if let Some(value::Value::Sequence(resources)) = record.get(".resources") {
+                    state_instances(&record, resources)?
+                } else if let Some(module) = record.get(".values.root_module") {
+                    let mut instances = Vec::new();
+                    show_instances(module, &mut instances);
+                    instances
+                } else {
+                    vec![record]
+                };
+            let filters = &self.filters;
+            self.pending.extend(
+                instances
+                    .into_iter()
+                    .filter(|instance| filters.matches(instance)),
+            );
+        }
+    }
+
+    #[inline]
+    fn position(&self) -> Option<value::Position> {
+        self.inner.position()
+    }
+}
+
+impl Filters {
+    /// Whether the resource record matches all conditions.  Records that aren't resources
+    /// only match without any.
+    pub fn matches(&self, record: &value::Value) -> bool {
+        self.0.iter().all(|(field, pattern)| {
+            let path = match field {
+                Field::Address => ".address",
+                Field::Mode => ".mode",
+                Field::Module => ".module",
+                Field::Name => ".name",
+                Field::Provider => ".provider",
+                Field::Type => ".type",
+            };
+            record
+                .get(path)
+                .and_then(value::Value::as_str)
+                .is_some_and(|v| pattern.matches(v))
+        })
+    }
+}
+
+/// The instances of the resources of a state file, which has them nested in the resources.
+fn state_instances(
+    state: &value::Value,
+    resources: &[value::Value],
+) -> error::Result<Vec<value::Value>> {
+    if let Some(version) = state.get(".version").and_then(value::Value::as_f64) {
+        if version < 4.0 {
+            return Err(error::Error::Format {
+                msg: format!(
+                    "Terraform state version {} is not supported, only version 4 (Terraform \
+                     0.12 and later)",
+                    version
+                ),
+            });
+        }
+    }
+    let mut instances = Vec::new();
+    for resource in resources {
+        let field = |path: &str| resource.get(path).cloned().unwrap_or(value::Value::Unit);
+        let provider = resource
+            .get(".provider")
+            .and_then(value::Value::as_str)
+            .map_or(value::Value::Unit, |provider| {
+                provider_name(provider).into()
+            });
+        let items = match resource.get(".instances") {
+            Some(value::Value::Sequence(items)) => &items[..],
+            _ => &[],
+        };
+        for instance in items {
+            let index = instance
+                .get(".index_key")
+                .cloned()
+                .unwrap_or(value::Value::Unit);
+            let (module, mode, kind, name) = (
+                field(".module"),
+                field(".mode"),
+                field(".type"),
+                field(".name"),
+            );
+            instances.push(value::Value::Map(vec![
+                (
+                    "address".into(),
+                    address(&module, &mode, &kind, &name, &index).into(),
+                ),
+                ("module".into(), module),
+                ("mode".into(), mode),
+                ("type".into(), kind),
+                ("name".into(), name),
+                ("index".into(), index),
+                ("provider".into(), provider.clone()),
+                (
+                    "attributes".into(),
+                    instance
+                        .get(".attributes")
+                        .cloned()
+                        .unwrap_or(value::Value::Unit),
+                ),
+            ]));
+        }
+    }
+    Ok(instances)
+}
+
+/// The instances of the resources of a module in `terraform show -json` output, which has an
+/// entry for each instance, and of its child modules.
+fn show_instances(module: &value::Value, instances: &mut Vec<value::Value>) {
+    if let Some(value::Value::Sequence(resources)) = module.get(".resources") {
+        for resource in resources {
+            let field = |path: &str| resource.get(path).cloned().unwrap_or(value::Value::Unit);
+            instances.push(value::Value::Map(vec![
+                ("address".into(), field(".address")),
+                (
+                    "module".into(),
+                    module
+                        .get(".address")
+                        .cloned()
+                        .unwrap_or(value::Value::Unit),
+                ),
+                ("mode".into(), field(".mode")),
+                ("type".into(), field(".type")),
+                ("name".into(), field(".name")),
+                ("index".into(), field(".index")),
+                ("provider".into(), field(".provider_name")),
+                ("attributes".into(), field(".values")),
+            ]));
+        }
+    }
+    if let Some(value::Value::Sequence(children)) = module.get(".child_modules") {
+        for child in children {
+            show_instances(child, instances);
+        }
+    }
+}
+
+/// The address of a resource instance, like `module.vpc.aws_subnet.private["a"]`.
+fn address(
+    module: &value::Value,
+    mode: &value::Value,
+    kind: &value::Value,
+    name: &value::Value,
+    index: &value::Value,
+) -> String {
+    let mut address = String::new();
+    if let Some(module) = module.as_str() {
+        address.push_str(module);
+        address.push('.');
+    }
+    if mode.as_str() == Some("data") {
+        address.push_str("data.");
+    }
+    address.push_str(kind.as_str().unwrap_or_default());
+    address.push('.');
+    address.push_str(name.as_str().unwrap_or_default());
+    // Keys of `for_each` are quoted, like in Terraform
+    if *index != value::Value::Unit {
+        address.push_str(&format!("[{}]", index));
+    }
+    address
+}
+
+/// The name of a provider in state, like `registry.terraform.io/hashicorp/aws` for
+/// `provider["registry.terraform.io/hashicorp/aws"]`, keeping any alias, like `.west`.
+fn provider_name(provider: &str) -> String {
+    match provider
+        .strip_prefix("provider[\"")
+        .and_then(|rest| rest.split_once("\"]"))
+    {
+        Some((name, alias)) => format!("{}{}", name, alias),
+        None => provider.to_owned(),
+    }
+}
+
+impl fmt::Display for Filters {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, (field, pattern)) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            write!(f, "{}={}", field, pattern)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Field::Address => "address",
+            Field::Mode => "mode",
+            Field::Module => "module",
+            Field::Name => "name",
+            Field::Provider => "provider",
+            Field::Type => "type",
+        })
+    }
+}
+
+impl str::FromStr for Filters {
+    type Err = error::Error;
+
+    fn from_str(s: &str) -> error::Result<Self> {
+        let mut filters = Vec::new();
+        for condition in s.split(',').filter(|c| !c.is_empty()) {
+            let (field, pattern) = condition.split_once('=').ok_or_else(|| {
+                error::Error::Message(format!(
+                    "resource filters should be like 'type=aws_instance', got: {}",
+                    condition
+                ))
+            })?;
+            let field = match field {
+                "address" => Field::Address,
+                "mode" => Field::Mode,
+                "module" => Field::Module,
+                "name" => Field::Name,
+                "provider" => Field::Provider,
+                "type" => Field::Type,
+                _ => {
+                    return Err(error::Error::Message(format!(
+                        "resources can be filtered by address, mode, module, name, provider or \
+                         type, got: {}",
+                        field
+                    )))
+                }
+            };
+            filters.push((field, glob::Pattern::new(pattern)?));
+        }
+        Ok(Filters(filters))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::transform::test_util;
+    use crate::value::Source as _;
+
+    /// Parses JSON, which keeps the order of the fields unlike `value!`.
+    fn json(input: &str) -> value::Value {
+        value::json::source(input.as_bytes())
+            .read()
+            .unwrap()
+            .unwrap()
+    }
+
+    fn state() -> value::Value {
+        json(
+            r#"{
+            "version": 4,
+            "terraform_version": "1.9.0",
+            "resources": [
+                {
+                    "mode": "managed",
+                    "type": "aws_instance",
+                    "name": "web",
+                    "provider": "provider[\"registry.terraform.io/hashicorp/aws\"]",
+                    "instances": [
+                        {"index_key": 0, "attributes": {"id": "i-1"}},
+                        {"index_key": 1, "attributes": {"id": "i-2"}}
+                    ]
+                },
+                {
+                    "module": "module.vpc",
+                    "mode": "data",
+                    "type": "aws_subnet",
+                    "name": "private",
+                    "provider": "provider[\"registry.terraform.io/hashicorp/aws\"].west",
+                    "instances": [{"index_key": "a", "attributes": {"id": "subnet-1"}}]
+                }
+            ]
+        }"#,
+        )
+    }
+
+    fn show() -> value::Value {
+        json(
+            r#"{"format_version": "1.0", "values": {"root_module": {
+                "resources": [{"address": "aws_s3_bucket.logs", "mode": "managed",
+                    "type": "aws_s3_bucket", "name": "logs",
+                    "provider_name": "registry.terraform.io/hashicorp/aws",
+                    "values": {"bucket": "logs"}}],
+                "child_modules": [{"address": "module.db", "resources": [
+                    {"address": "module.db.aws_db_instance.main", "mode": "managed",
+                     "type": "aws_db_instance", "name": "main",
+                     "provider_name": "registry.terraform.io/hashicorp/aws", "values": {}}]}]
+            }}}"#,
+        )
+    }
+
+    fn resources(filters: &str, record: value::Value) -> Vec<value::Value> {
+        let records = test_util::records(vec![record]);
+        test_util::read_all(source(records, filters.parse().unwrap()))
+    }
+
+    fn addresses(filters: &str, record: value::Value) -> Vec<String> {
+        resources(filters, record)
+            .iter()
+            .map(|r| r.get(".address").unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_state() {
+        let records = resources("", state());
+        assert_eq!(records.len(), 3);
+        assert_eq!(
+            records[2].to_string(),
+            r#"{"address": "module.vpc.data.aws_subnet.private[\"a\"]", "module": "module.vpc", "mode": "data", "type": "aws_subnet", "name": "private", "index": "a", "provider": "registry.terraform.io/hashicorp/aws.west", "attributes": {"id": "subnet-1"}}"#
+        );
+        assert_eq!(
+            addresses("type=aws_instance", state()),
+            vec![r#""aws_instance.web[0]""#, r#""aws_instance.web[1]""#]
+        );
+
+        let old = json(r#"{"version": 3, "resources": []}"#);
+        let mut reader = source(test_util::records(vec![old]), Filters::default());
+        assert!(reader.read().is_err());
+    }
+
+    #[test]
+    fn test_show() {
+        assert_eq!(
+            addresses("mode=managed,type=aws_*", show()),
+            vec![
+                r#""aws_s3_bucket.logs""#,
+                r#""module.db.aws_db_instance.main""#
+            ]
+        );
+        assert!(resources("module=module.vpc", show()).is_empty());
+    }
+
+    #[test]
+    fn test_filters() {
+        // Other records are passed through, unless there are filters
+        assert_eq!(resources("", value!({"a": 1})), vec![value!({"a": 1})]);
+        assert!(resources("type=*", value!({"a": 1})).is_empty());
+
+        assert_eq!(
+            "type=aws_*,module=module.vpc"
+                .parse::<Filters>()
+                .unwrap()
+                .to_string(),
+            "type=aws_*,module=module.vpc"
+        );
+        assert!("kind=x".parse::<Filters>().is_err());
+        assert!("type".parse::<Filters>().is_err());
+    }
+}
@@ -0,0 +1,255 @@
+//! Finding the most frequent values of a field, in bounded memory.
+
+use std::collections;
+use std::vec;
+
+use crate::error;
+use crate::value;
+
+/// How many counters to keep per requested value.  More counters make the ranking more accurate
+/// for streams with many distinct values, at the cost of memory.
+const COUNTERS_PER_VALUE: usize = 10;
+const MIN_COUNTERS: usize = 1000;
+
+/// A source that reads all records from another source, and yields the most frequent values of a
+/// field, like `{"value": "curl/8.0", "count": 42, "error": 0}`, most frequent first.
+///
+/// Counts are approximate when there are more distinct values than counters: a value's count can
+/// be overestimated by at most `error`.
+#[derive(Debug)]
+pub struct Source<S> {
+    inner: S,
+    k: usize,
+    path: value::path::Path,
+    ranking: Option<vec::IntoIter<value::Value>>,
+}
+
+/// The state of the space-saving algorithm.  Counters are kept ordered by count, so that the
+/// smallest one can be found quickly when a new value has to replace it.
+#[derive(Debug)]
+struct Counters {
+    capacity: usize,
+    index: collections::HashMap<value::Value, usize>,
+    entries: Vec<Entry>,
+    by_count: collections::BTreeSet<(u64, usize)>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    value: value::Value,
+    count: u64,
+    error: u64,
+}
+
+pub fn source<S>(inner: S, k: usize, path: value::path::Path) -> Source<S>
+where
+    S: value::Source,
+{
+    Source {
+        inner,
+        k,
+        path,
+        ranking: None,
+    }
+}
+
+impl<S> Source<S>
+where
+    S: value::Source,
+{
+    fn collect_ranking(&mut self) -> error::Result<Vec<value::Value>> {
+        let mut counters = Counters::new((self.k * COUNTERS_PER_VALUE).max(MIN_COUNTERS));
+        let mut skipped = 0u64;
+        while let Some(mut record) = self.inner.read()? {
+            match self.path.get_mut(&mut record) {
+                Some(v) => counters.add(std::mem::replace(v, value::Value::Unit)),
+                None => skipped += 1,
+            }
+        }
+        if skipped > 0 {
+            warn!(
+                "Skipped {} records without {} for the top values",
+                skipped, self.path
+            );
+        }
+
+        let mut entries = counters.entries;
+        entries.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.error.cmp(&b.error)));
+        entries.truncate(self.k);
+        Ok(entries
+            .into_iter()
+            .map(|entry| {
+                value::Value::Map(vec![
+                    (value::Value::from("value"), entry.value),
+                    (value::Value::from("count"), value::Value::U64(entry.count)),
+                    (value::Value::from("error"), value::Value::U64(entry.error)),
+                ])
+            })
+            .collect())
+    }
+}
+
+impl Counters {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            index: collections::HashMap::new(),
+            entries: Vec::new(),
+            by_count: collections::BTreeSet::new(),
+        }
+    }
+
+    fn add(&mut self, v: value::Value) {
+        if let Some(&i) = self.index.get(&v) {
+            self.increment(i);
+        } else if self.entries.len() < self.capacity {
+            let i = self.entries.len();
+            self.entries.push(Entry {
+                value: v.clone(),
+                count: 1,
+                error: 0,
+            });
+            self.index.insert(v, i);
+            self.by_count.insert((1, i));
+        } else {
+            // Replace the least frequent value, assuming the new one might have occurred that
+            // often too without being counted
+            let &(min, i) = self.by_count.iter().next().expect("no counters");
+            let entry = &mut self.entries[i];
+            self.index.remove(&entry.value);
+            entry.value = v.clone();
+            entry.error = min;
+            self.index.insert(v, i);
+            self.increment(i);
+        }
+    }
+
+    fn increment(&mut self, i: usize) {
+        let entry = &mut self.entries[i];
+        self.by_count.remove(&(entry.count, i));
+        entry.count += 1;
+        self.by_count.insert((entry.count, i));
+    }
+}
+
+impl<S> value::Source for Source<S>
+where
+    S: value::Source,
+{
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        if self.ranking.is_none() {
+            self.ranking = Some(self.collect_ranking()?.into_iter());
+        }
+        Ok(self.ranking.as_mut().and_then(Iterator::next))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::transform::test_util;
+
+    fn top_k(k: usize, path: &str, records: Vec<value::Value>) -> Vec<String> {
+        let records = test_util::records(records);
+        test_util::read_text(source(records, k, path.parse().unwrap()))
+    }
+
+    #[test]
+    fn test_ranking() {
+        let records = vec![
+            value!({"ua": "curl"}),
+            value!({"ua": "wget"}),
+            value!({"ua": "curl"}),
+            value!({"ua": "firefox"}),
+            value!({"ua": "curl"}),
+            value!({"ua": "wget"}),
+        ];
+        assert_eq!(
+            top_k(2, ".ua", records),
+            vec![
+                r#"{"value": "curl", "count": 3, "error": 0}"#,
+                r#"{"value": "wget", "count": 2, "error": 0}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ties() {
+        // Values that occur equally often keep the order they were first seen in
+        let records = vec![
+            value!({"ua": "wget"}),
+            value!({"ua": "curl"}),
+            value!({"ua": "firefox"}),
+            value!({"ua": "curl"}),
+            value!({"ua": "wget"}),
+        ];
+        assert_eq!(
+            top_k(3, ".ua", records),
+            vec![
+                r#"{"value": "wget", "count": 2, "error": 0}"#,
+                r#"{"value": "curl", "count": 2, "error": 0}"#,
+                r#"{"value": "firefox", "count": 1, "error": 0}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_missing_path() {
+        let records = vec![
+            value!({"ua": "curl"}),
+            value!({"other": "curl"}),
+            value!({"ua": null}),
+            value!({"ua": null}),
+        ];
+        assert_eq!(
+            top_k(5, ".ua", records),
+            vec![
+                r#"{"value": null, "count": 2, "error": 0}"#,
+                r#"{"value": "curl", "count": 1, "error": 0}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_value_types() {
+        // Values of any type can be counted, and values of different types are distinct
+        let records = vec![
+            value!({"id": 5}),
+            value!({"id": "5"}),
+            value!({"id": [1, 2]}),
+            value!({"id": [1, 2]}),
+        ];
+        assert_eq!(
+            top_k(5, ".id", records),
+            vec![
+                r#"{"value": [1, 2], "count": 2, "error": 0}"#,
+                r#"{"value": 5, "count": 1, "error": 0}"#,
+                r#"{"value": "5", "count": 1, "error": 0}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_empty() {
+        assert_eq!(top_k(5, ".ua", vec![]), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_evicted_counters() {
+        let mut counters = Counters::new(2);
+        for v in &["a", "a", "a", "b", "c", "c"] {
+            counters.add(value::Value::from(*v));
+        }
+        let entries = counters
+            .entries
+            .iter()
+            .map(|e| (e.value.to_string(), e.count, e.error))
+            .collect::<Vec<_>>();
+        // "c" replaced "b", and inherited its count as the possible error
+        assert_eq!(
+            entries,
+            vec![("\"a\"".to_owned(), 3, 0), ("\"c\"".to_owned(), 3, 1)]
+        );
+    }
+}
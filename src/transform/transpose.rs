@@ -0,0 +1,282 @@
+//! Converting between row-oriented (`[{"a": 1}, {"a": 2}]`) and column-oriented
+//! (`{"a": [1, 2]}`) data.
+
+use std::collections;
+use std::vec;
+
+use crate::error;
+use crate::value;
+
+/// A source that transposes each record of another source; see `transpose`.
+#[derive(Debug)]
+pub struct Source<S>(S);
+
+/// A source that collects all records of another source (which should be maps) into a single
+/// column-oriented record.
+#[derive(Debug)]
+pub struct ToColumns<S> {
+    inner: S,
+    done: bool,
+}
+
+/// A source that splits each column-oriented record of another source into one record per row.
+#[derive(Debug)]
+pub struct FromColumns<S> {
+    inner: S,
+    rows: vec::IntoIter<value::Value>,
+}
+
+pub fn source<S>(inner: S) -> Source<S>
+where
+    S: value::Source,
+{
+    Source(inner)
+}
+
+pub fn to_columns<S>(inner: S) -> ToColumns<S>
+where
+    S: value::Source,
+{
+    ToColumns { inner, done: false }
+}
+
+pub fn from_columns<S>(inner: S) -> FromColumns<S>
+where
+    S: value::Source,
+{
+    FromColumns {
+        inner,
+        rows: Vec::new().into_iter(),
+    }
+}
+
+/// Transposes a value:
+///
+/// * a sequence of maps becomes a map of sequences (rows to columns),
+/// * a map of sequences becomes a sequence of maps (columns to rows), and
+/// * a sequence of sequences is transposed like a matrix.
+///
+/// Missing fields and elements of shorter sequences are filled in with nulls.
+pub fn transpose(v: value::Value) -> error::Result<value::Value> {
+    match v {
+        value::Value::Sequence(rows) if rows.iter().all(|r| r.as_map().is_some()) => {
+            Ok(rows_to_columns(rows))
+        }
+        value::Value::Sequence(rows) if rows.iter().all(|r| r.as_sequence().is_some()) => {
+            let rows: Vec<Vec<value::Value>> = rows
+                .into_iter()
+                .map(|r| match r {
+                    value::Value::Sequence(r) => r,
+                    _ => unreachable!(),
+                })
+                .collect();
+            let width = rows.iter().map(Vec::len).max().unwrap_or(0);
+            let mut columns = vec![Vec::with_capacity(rows.len()); width];
+            for row in rows {
+                let len = row.len();
+                for (column, cell) in columns.iter_mut().zip(row) {
+                    column.push(cell);
+                }
+                for column in &mut columns[len..] {
+                    column.push(value::Value::Unit);
+                }
+            }
+            Ok(value::Value::Sequence(
+                columns.into_iter().map(value::Value::Sequence).collect(),
+            ))
+        }
+        value::Value::Map(columns) if columns.iter().all(|(_, c)| c.as_sequence().is_some()) => {
+            Ok(value::Value::Sequence(columns_to_rows(columns)))
+        }
+        other => Err(error::Error::Message(format!(
+            "only sequences of maps, maps of sequences and sequences of sequences can be \
+             transposed, got: {}",
+            other.summary(value::ERROR_SUMMARY_LEN)
+        ))),
+    }
+}
+
+fn rows_to_columns(rows: Vec<value::Value>) -> value::Value {
+    let mut names = Vec::new();
+    let mut index = collections::HashMap::new();
+    let mut columns: Vec<Vec<value::Value>> = Vec::new();
+
+    for (i, row) in rows.into_iter().enumerate() {
+        let fields = match row {
+            value::Value::Map(fields) => fields,
+            _ => unreachable!(),
+        };
+        for (name, v) in fields {
+            let c = *index.entry(name.clone()).or_insert_with(|| {
+                names.push(name);
+                // Rows before this one didn't have the field
+                columns.push(vec![value::Value::Unit; i]);
+                columns.len() - 1
+            });
+            columns[c].push(v);
+        }
+        for column in &mut columns {
+            if column.len() <= i {
+                column.push(value::Value::Unit);
+            }
+        }
+    }
+
+    value::Value::Map(
+        names
+            .into_iter()
+            .zip(columns.into_iter().map(value::Value::Sequence))
+            .collect(),
+    )
+}
+
+fn columns_to_rows(columns: Vec<(value::Value, value::Value)>) -> Vec<value::Value> {
+    let height = columns
+        .iter()
+        .filter_map(|(_, c)| c.as_sequence())
+        .map(<[value::Value]>::len)
+        .max()
+        .unwrap_or(0);
+    let mut rows = vec![Vec::with_capacity(columns.len()); height];
+    for (name, column) in columns {
+        let mut cells = match column {
+            value::Value::Sequence(cells) => cells.into_iter(),
+            _ => unreachable!(),
+        };
+        for row in &mut rows {
+            row.push((name.clone(), cells.next().unwrap_or(value::Value::Unit)));
+        }
+    }
+    rows.into_iter().map(value::Value::Map).collect()
+}
+
+impl<S> value::Source for Source<S>
+where
+    S: value::Source,
+{
+    #[inline]
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        match self.0.read()? {
+            Some(record) => transpose(record).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    #[inline]
+    fn position(&self) -> Option<value::Position> {
+        self.0.position()
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<S> value::Source for ToColumns<S>
+where
+    S: value::Source,
+{
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        if self.done {
+            return Ok(None);
+        }
+        self.done = true;
+
+        let mut rows = Vec::new();
+        while let Some(record) = self.inner.read()? {
+            if record.as_map().is_none() {
+                return Err(error::Error::Message(format!(
+                    "only map records can be collected into columns, got: {}",
+                    record.summary(value::ERROR_SUMMARY_LEN)
+                )));
+            }
+            rows.push(record);
+        }
+        Ok(Some(rows_to_columns(rows)))
+    }
+}
+
+impl<S> value::Source for FromColumns<S>
+where
+    S: value::Source,
+{
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        loop {
+            if let Some(row) = self.rows.next() {
+                return Ok(Some(row));
+            }
+            match self.inner.read()? {
+                Some(value::Value::Map(columns))
+                    if columns.iter().all(|(_, c)| c.as_sequence().is_some()) =>
+                {
+                    self.rows = columns_to_rows(columns).into_iter();
+                }
+                Some(other) => {
+                    return Err(error::Error::Message(format!(
+                        "only maps of sequences can be split into rows, got: {}",
+                        other.summary(value::ERROR_SUMMARY_LEN)
+                    )))
+                }
+                None => return Ok(None),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::transform::test_util;
+    use crate::value::Source as _;
+
+    #[test]
+    fn test_transpose() {
+        assert_eq!(
+            transpose(value!([{"a": 1}, {"a": 2, "b": 3}])).unwrap(),
+            value!({"a": [1, 2], "b": [null, 3]})
+        );
+        assert_eq!(
+            transpose(value!({"a": [1, 2], "b": [3]})).unwrap(),
+            value!([{"a": 1, "b": 3}, {"a": 2, "b": null}])
+        );
+        assert_eq!(
+            transpose(value!([[1, 2], [3, 4]])).unwrap(),
+            value!([[1, 3], [2, 4]])
+        );
+        assert!(transpose(value!("a")).is_err());
+    }
+
+    #[test]
+    fn test_to_columns() {
+        let rows = test_util::records(vec![value!({"a": 1}), value!({"b": 2}), value!({"a": 3})]);
+        assert_eq!(
+            test_util::read_all(to_columns(rows)),
+            vec![value!({"a": [1, null, 3], "b": [null, 2, null]})]
+        );
+
+        let mut columns = to_columns(test_util::records(vec![value!({"a": 1}), value!(2)]));
+        assert!(columns.read().is_err());
+    }
+
+    #[test]
+    fn test_from_columns() {
+        let columns = test_util::records(vec![
+            value!({"a": [1, 2], "b": [3]}),
+            value!({}),
+            value!({"a": [4]}),
+        ]);
+        assert_eq!(
+            test_util::read_all(from_columns(columns)),
+            vec![
+                value!({"a": 1, "b": 3}),
+                value!({"a": 2, "b": null}),
+                value!({"a": 4}),
+            ]
+        );
+
+        let mut rows = from_columns(test_util::records(vec![value!([1])]));
+        assert!(rows.read().is_err());
+    }
+}
@@ -0,0 +1,239 @@
+//! Filtering records by the types of their fields.
+
+use std::fmt;
+use std::str;
+
+use crate::error;
+use crate::value;
+
+/// A source that only yields the records of another source where every type check passes.
+#[derive(Debug)]
+pub struct Source<S> {
+    inner: S,
+    checks: Vec<Check>,
+}
+
+/// A check that the value at a path has one of some types, like `.id:string|integer`.
+#[derive(Clone, Debug)]
+pub struct Check {
+    path: value::path::Path,
+    types: Vec<Type>,
+}
+
+/// The type of a value, as far as filtering is concerned.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Type {
+    /// The path doesn't exist in the record.
+    Missing,
+    Null,
+    Boolean,
+    Integer,
+    Float,
+    /// Either an integer or a float.
+    Number,
+    /// A string or a char.
+    String,
+    Bytes,
+    Array,
+    Object,
+}
+
+pub fn source<S>(inner: S, checks: Vec<Check>) -> Source<S>
+where
+    S: value::Source,
+{
+    Source { inner, checks }
+}
+
+impl<S> value::Source for Source<S>
+where
+    S: value::Source,
+{
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        while let Some(record) = self.inner.read()? {
+            if self.checks.iter().all(|check| check.matches(&record)) {
+                return Ok(Some(record));
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl Check {
+    pub fn matches(&self, record: &value::Value) -> bool {
+        let actual = Type::of(self.path.get(record));
+        self.types.iter().any(|t| t.includes(actual))
+    }
+}
+
+impl Type {
+    /// The most specific type of a value; `None` means that it is missing.
+    pub fn of(value: Option<&value::Value>) -> Self {
+        match value {
+            None => Self::Missing,
+            Some(value::Value::Unit) => Self::Null,
+            Some(value::Value::Bool(_)) => Self::Boolean,
+            Some(value::Value::I8(_))
+            | Some(value::Value::I16(_))
+            | Some(value::Value::I32(_))
+            | Some(value::Value::I64(_))
+            | Some(value::Value::U8(_))
+            | Some(value::Value::U16(_))
+            | Some(value::Value::U32(_))
+            | Some(value::Value::U64(_)) => Self::Integer,
+            Some(value::Value::F32(_)) | Some(value::Value::F64(_)) => Self::Float,
+            Some(value::Value::Char(_)) | Some(value::Value::String(_)) => Self::String,
+            Some(value::Value::Bytes(_)) => Self::Bytes,
+            Some(value::Value::Sequence(_)) => Self::Array,
+            Some(value::Value::Map(_)) => Self::Object,
+        }
+    }
+
+    /// Whether a value of the `actual` type (as returned by `of`) is of this type.
+    pub fn includes(self, actual: Self) -> bool {
+        match self {
+            Self::Number => actual == Self::Integer || actual == Self::Float,
+            _ => self == actual,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Missing => "missing",
+            Self::Null => "null",
+            Self::Boolean => "boolean",
+            Self::Integer => "integer",
+            Self::Float => "float",
+            Self::Number => "number",
+            Self::String => "string",
+            Self::Bytes => "bytes",
+            Self::Array => "array",
+            Self::Object => "object",
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl str::FromStr for Type {
+    type Err = error::Error;
+
+    fn from_str(s: &str) -> error::Result<Self> {
+        match s {
+            "missing" => Ok(Self::Missing),
+            "null" => Ok(Self::Null),
+            "boolean" | "bool" => Ok(Self::Boolean),
+            "integer" | "int" => Ok(Self::Integer),
+            "float" => Ok(Self::Float),
+            "number" => Ok(Self::Number),
+            "string" => Ok(Self::String),
+            "bytes" => Ok(Self::Bytes),
+            "array" | "sequence" => Ok(Self::Array),
+            "object" | "map" => Ok(Self::Object),
+            _ => Err(error::Error::Message(format!(
+                "unknown type {:?} (expected missing, null, boolean, integer, float, number, \
+                 string, bytes, array or object)",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let types: Vec<_> = self.types.iter().map(|t| t.name()).collect();
+        write!(f, "{}:{}", self.path, types.join("|"))
+    }
+}
+
+impl str::FromStr for Check {
+    type Err = error::Error;
+
+    /// Parses checks like `.id:string` or `.count:integer|null`.
+    fn from_str(s: &str) -> error::Result<Self> {
+        let (path, types) = s.rsplit_once(':').ok_or_else(|| {
+            error::Error::Message(format!("invalid type check (expected path:type): {}", s))
+        })?;
+        Ok(Self {
+            path: value::path::Path::from(path),
+            types: types
+                .split('|')
+                .map(str::parse)
+                .collect::<error::Result<_>>()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::transform::test_util;
+
+    fn where_type(checks: &[&str], records: Vec<value::Value>) -> Vec<String> {
+        let checks = checks.iter().map(|c| c.parse().unwrap()).collect();
+        test_util::read_text(source(test_util::records(records), checks))
+    }
+
+    fn records() -> Vec<value::Value> {
+        vec![
+            value!({"id": 1}),
+            value!({"id": 1.5}),
+            value!({"id": "1"}),
+            value!({"id": null}),
+            value!({"other": 1}),
+        ]
+    }
+
+    #[test]
+    fn test_types() {
+        assert_eq!(
+            where_type(&[".id:integer"], records()),
+            vec![r#"{"id": 1}"#]
+        );
+        assert_eq!(
+            where_type(&[".id:number"], records()),
+            vec![r#"{"id": 1}"#, r#"{"id": 1.5}"#]
+        );
+        assert_eq!(
+            where_type(&[".id:string|null"], records()),
+            vec![r#"{"id": "1"}"#, r#"{"id": null}"#]
+        );
+        assert_eq!(
+            where_type(&[".id:missing"], records()),
+            vec![r#"{"other": 1}"#]
+        );
+    }
+
+    #[test]
+    fn test_all_checks() {
+        let records = vec![
+            value!({"id": 1, "tags": []}),
+            value!({"id": 1, "tags": {}}),
+            value!({"id": "1", "tags": []}),
+        ];
+        assert_eq!(
+            where_type(&[".id:int", ".tags:array"], records),
+            vec![r#"{"id": 1, "tags": []}"#]
+        );
+    }
+
+    #[test]
+    fn test_empty() {
+        assert_eq!(where_type(&[".id:string"], vec![]), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_parse() {
+        assert!(".id".parse::<Check>().is_err());
+        assert!(".id:text".parse::<Check>().is_err());
+        assert_eq!(
+            ".id:int|map".parse::<Check>().unwrap().to_string(),
+            ".id:integer|object"
+        );
+    }
+}
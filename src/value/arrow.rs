@@ -0,0 +1,283 @@
+//! Apache Arrow IPC files (also known as Feather files) and streams.
+//!
+//! The source reads the rows of all record batches as maps, from either the file or the stream
+//! format.  The sink buffers records into batches, infers the schema from the first batch, and
+//! writes the file format.  Records after the first batch must fit that schema; fields that it
+//! doesn't have are left out.
+
+use std::collections;
+use std::fmt;
+use std::io;
+use std::sync;
+
+use crate::error;
+use crate::value;
+
+/// How many records are buffered into a batch.  The schema is inferred from the first batch.
+pub(crate) const BATCH_SIZE: usize = 8192;
+
+/// The magic bytes at the start of the file format; streams start with a schema message.
+const FILE_MAGIC: &[u8] = b"ARROW1";
+/// The marker before the length of each message of a stream.
+const CONTINUATION_MARKER: [u8; 4] = [0xff; 4];
+
+type Batches = Box<dyn Iterator<Item = Result<arrow_array::RecordBatch, arrow_schema::ArrowError>>>;
+
+pub struct Source {
+    batches: Batches,
+    pending: collections::VecDeque<value::Value>,
+}
+
+pub struct Sink<W>
+where
+    W: io::Write,
+{
+    batcher: Batcher,
+    output: Option<W>,
+    writer: Option<arrow_ipc::writer::FileWriter<W>>,
+}
+
+/// Buffers records into record batches, with the schema inferred from the first batch.
+pub(crate) struct Batcher {
+    pending: Vec<serde_json::Value>,
+    decoder: Option<(arrow_schema::SchemaRef, arrow_json::reader::Decoder)>,
+}
+
+/// Creates an Arrow source.  The file format can only be read from the end, so the whole input
+/// is read up front.
+pub fn source<R>(mut r: R) -> error::Result<Source>
+where
+    R: io::Read,
+{
+    let mut input = Vec::new();
+    r.read_to_end(&mut input)?;
+    let input = io::Cursor::new(input);
+    let batches: Batches = if input.get_ref().starts_with(FILE_MAGIC) {
+        Box::new(arrow_ipc::reader::FileReader::try_new(input, None)?)
+    } else {
+        check_stream_start(input.get_ref())?;
+        Box::new(arrow_ipc::reader::StreamReader::try_new(input, None)?)
+    };
+    Ok(Source {
+        batches,
+        pending: collections::VecDeque::new(),
+    })
+}
+
+/// Checks that a stream starts with a message of a plausible length, since the stream reader
+/// trusts the length and panics on other input.
+fn check_stream_start(input: &[u8]) -> error::Result<()> {
+    if !input.starts_with(&CONTINUATION_MARKER) || input.len() < 8 {
+        return Err(error::Error::Format {
+            msg: "not an Arrow file or stream".to_owned(),
+        });
+    }
+    let len = i32::from_le_bytes([input[4], input[5], input[6], input[7]]);
+    if len <= 0 || len as usize > input.len() - 8 {
+        return Err(error::Error::Format {
+            msg: format!("invalid Arrow stream: bad length {} of the schema", len),
+        });
+    }
+    Ok(())
+}
+
+#[inline]
+pub fn sink<W>(w: W) -> Sink<W>
+where
+    W: io::Write,
+{
+    Sink {
+        batcher: Batcher::new(),
+        output: Some(w),
+        writer: None,
+    }
+}
+
+impl value::Source for Source {
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        while self.pending.is_empty() {
+            match self.batches.next() {
+                Some(batch) => self.pending.extend(rows(&batch?)?),
+                None => return Ok(None),
+            }
+        }
+        Ok(self.pending.pop_front())
+    }
+}
+
+/// Converts the rows of a batch into maps.  This goes through JSON, which Arrow knows how to
+/// write every type as.
+fn rows(batch: &arrow_array::RecordBatch) -> error::Result<Vec<value::Value>> {
+    let mut writer = arrow_json::WriterBuilder::new()
+        .with_explicit_nulls(true)
+        .build::<_, arrow_json::writer::LineDelimited>(Vec::new());
+    writer.write(batch)?;
+    writer.finish()?;
+    let json = writer.into_inner();
+
+    let mut source = value::json::source(&json[..]);
+    let mut rows = Vec::with_capacity(batch.num_rows());
+    while let Some(row) = value::Source::read(&mut source)? {
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+impl Batcher {
+    pub(crate) fn new() -> Self {
+        Self {
+            pending: Vec::new(),
+            decoder: None,
+        }
+    }
+
+    /// Adds a record, returning whether a batch is full.
+    pub(crate) fn push(&mut self, value: value::Value, format: &str) -> error::Result<bool> {
+        if !matches!(value, value::Value::Map(_)) {
+            return Err(error::Error::Format {
+                msg: format!(
+                    "{} can only output maps, got: {}",
+                    format,
+                    value.summary(value::ERROR_SUMMARY_LEN)
+                ),
+            });
+        }
+        self.pending.push(serde_json::to_value(&value)?);
+        Ok(self.pending.len() >= BATCH_SIZE)
+    }
+
+    /// Turns the buffered records into a batch, if there are any.
+    pub(crate) fn batch(
+        &mut self,
+    ) -> error::Result<Option<(arrow_schema::SchemaRef, arrow_array::RecordBatch)>> {
+        if self.pending.is_empty() {
+            return Ok(None);
+        }
+        if self.decoder.is_none() {
+            let schema = arrow_json::reader::infer_json_schema_from_iterator(
+                self.pending.iter().map(|v| Ok(v.clone())),
+            )?;
+            let schema = sync::Arc::new(schema);
+            let decoder = arrow_json::ReaderBuilder::new(schema.clone())
+                .with_batch_size(BATCH_SIZE)
+                .build_decoder()?;
+            self.decoder = Some((schema, decoder));
+        }
+
+        match self.decoder {
+            Some((ref schema, ref mut decoder)) => {
+                decoder.serialize(&self.pending)?;
+                self.pending.clear();
+                Ok(decoder.flush()?.map(|batch| (schema.clone(), batch)))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+impl<W> Sink<W>
+where
+    W: io::Write,
+{
+    fn write_batch(&mut self) -> error::Result<()> {
+        if let Some((schema, batch)) = self.batcher.batch()? {
+            if let Some(output) = self.output.take() {
+                self.writer = Some(arrow_ipc::writer::FileWriter::try_new(output, &schema)?);
+            }
+            if let Some(ref mut writer) = self.writer {
+                writer.write(&batch)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<W> value::Sink for Sink<W>
+where
+    W: io::Write,
+{
+    fn write(&mut self, value: value::Value) -> error::Result<()> {
+        if self.batcher.push(value, "Arrow")? {
+            self.write_batch()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> error::Result<()> {
+        if let Some(ref mut writer) = self.writer {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> error::Result<()> {
+        self.write_batch()?;
+        if let Some(ref mut writer) = self.writer {
+            writer.finish()?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ArrowSource").finish()
+    }
+}
+
+impl<W> fmt::Debug for Sink<W>
+where
+    W: io::Write,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ArrowSink").finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::value::Sink as _;
+    use crate::value::Source as _;
+
+    #[test]
+    fn test_round_trip() {
+        let records = vec![
+            value!({"id": 1, "name": "a", "scores": [1.5, 2.0]}),
+            value!({"id": 2, "name": null, "scores": []}),
+        ];
+        let mut output = Vec::new();
+        {
+            let mut writer = sink(&mut output);
+            for record in records.clone() {
+                writer.write(record).unwrap();
+            }
+            writer.finish().unwrap();
+        }
+        let mut reader = source(&output[..]).unwrap();
+        for record in records {
+            let read = reader.read().unwrap().unwrap();
+            assert_eq!(read.to_string(), record.to_string());
+        }
+        assert_eq!(reader.read().unwrap(), None);
+    }
+
+    #[test]
+    fn test_not_arrow() {
+        for input in &[
+            &b""[..],
+            b"{\"a\": 1}\n",
+            b"\xff\xff\xff\xff",
+            b"\xff\xff\xff\xff\xff\xff\xff\xff\x10\0\0\0",
+            b"\xff\xff\xff\xff\x10\0\0\0\0\0",
+            b"\x10\0\0\0\xff\xff\xff\xff\0\0\0\0\0\0\0\0\0\0\0\0",
+        ] {
+            match source(*input) {
+                Err(error::Error::Format { .. }) => (),
+                Err(e) => panic!("expected a format error for {:?}, got {}", input, e),
+                Ok(_) => panic!("expected an error for {:?}", input),
+            }
+        }
+    }
+}
@@ -0,0 +1,482 @@
+//! ASN.1 values in DER or BER, like certificates, CRLs and PKCS#8 or PKCS#12 files, either raw
+//! or in PEM blocks of any kind.
+//!
+//! Each top-level value, or the value of each PEM block, becomes a record.  Every value is a map
+//! with its `class` (`universal`, `application`, `context` or `private`), its `tag` number, the
+//! `type` of universal values like `SEQUENCE` or `INTEGER` (null for the other classes) and its
+//! `value`.  Constructed values have the sequence of the values in them, and primitive values are
+//! decoded by their type: integers become numbers (or bytes when they don't fit in 64 bits),
+//! object identifiers dotted strings with their `name` when it's well-known, strings strings and
+//! times RFC 3339.  Octet and bit strings are bytes, and when their contents are themselves a
+//! DER sequence or set, like the extensions of a certificate, it is decoded into `encapsulated`.
+//! Bit strings also have their number of `unused_bits`.
+
+use std::borrow;
+use std::collections;
+use std::io;
+use std::str;
+
+use crate::error;
+use crate::value;
+
+/// How deeply values are nested at most.
+const MAX_DEPTH: usize = 128;
+
+/// The names of the universal types, by their tag number.
+const TYPES: &[&str] = &[
+    "END-OF-CONTENTS",
+    "BOOLEAN",
+    "INTEGER",
+    "BIT STRING",
+    "OCTET STRING",
+    "NULL",
+    "OBJECT IDENTIFIER",
+    "ObjectDescriptor",
+    "EXTERNAL",
+    "REAL",
+    "ENUMERATED",
+    "EMBEDDED PDV",
+    "UTF8String",
+    "RELATIVE-OID",
+    "TIME",
+    "",
+    "SEQUENCE",
+    "SET",
+    "NumericString",
+    "PrintableString",
+    "T61String",
+    "VideotexString",
+    "IA5String",
+    "UTCTime",
+    "GeneralizedTime",
+    "GraphicString",
+    "VisibleString",
+    "GeneralString",
+    "UniversalString",
+    "CHARACTER STRING",
+    "BMPString",
+];
+
+const CLASSES: [&str; 4] = ["universal", "application", "context", "private"];
+
+const BOOLEAN: u32 = 1;
+const INTEGER: u32 = 2;
+const BIT_STRING: u32 = 3;
+const OCTET_STRING: u32 = 4;
+const NULL: u32 = 5;
+const OBJECT_IDENTIFIER: u32 = 6;
+const ENUMERATED: u32 = 10;
+const RELATIVE_OID: u32 = 13;
+const T61_STRING: u32 = 20;
+const UTC_TIME: u32 = 23;
+const GENERALIZED_TIME: u32 = 24;
+const UNIVERSAL_STRING: u32 = 28;
+const BMP_STRING: u32 = 30;
+
+#[derive(Debug)]
+pub struct Source(collections::VecDeque<value::Value>);
+
+/// Creates a source for the ASN.1 values in the input, which is read in full.
+pub fn source<R>(mut r: R) -> error::Result<Source>
+where
+    R: io::Read,
+{
+    let mut input = Vec::new();
+    r.read_to_end(&mut input)?;
+
+    let mut values = collections::VecDeque::new();
+    if input.trim_ascii_start().starts_with(b"-----BEGIN") {
+        for pem in x509_parser::pem::Pem::iter_from_buffer(&input) {
+            let pem = pem.map_err(|e| format_error(&format!("invalid PEM: {}", e)))?;
+            values.extend(decode(&pem.contents).map_err(|e| {
+                format_error(&format!("invalid ASN.1 in the {} block: {}", pem.label, e))
+            })?);
+        }
+    } else {
+        values.extend(decode(&input).map_err(|e| format_error(&format!("invalid ASN.1: {}", e)))?);
+    }
+    Ok(Source(values))
+}
+
+impl value::Source for Source {
+    #[inline]
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        Ok(self.0.pop_front())
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.0.len(), Some(self.0.len()))
+    }
+}
+
+/// Decodes all values one after another.
+fn decode(input: &[u8]) -> Result<Vec<value::Value>, String> {
+    let mut parser = Parser { input, position: 0 };
+    let mut values = Vec::new();
+    while parser.position < input.len() {
+        let element = parser
+            .element(0)
+            .map_err(|msg| format!("{} at byte {}", msg, parser.position))?;
+        values.push(element_value(element));
+    }
+    Ok(values)
+}
+
+/// A value as it is encoded, before it is decoded by its type.
+struct Element<'a> {
+    class: u8,
+    tag: u32,
+    contents: Contents<'a>,
+}
+
+enum Contents<'a> {
+    Primitive(&'a [u8]),
+    Constructed(Vec<Element<'a>>),
+}
+
+struct Parser<'a> {
+    input: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn byte(&mut self) -> Result<u8, String> {
+        let b = *self.input.get(self.position).ok_or("input ended")?;
+        self.position += 1;
+        Ok(b)
+    }
+
+    fn element(&mut self, depth: usize) -> Result<Element<'a>, String> {
+        if depth >= MAX_DEPTH {
+            return Err("values are nested too deeply".to_owned());
+        }
+        let identifier = self.byte()?;
+        let class = identifier >> 6;
+        let constructed = identifier & 0x20 != 0;
+        let mut tag = u32::from(identifier & 0x1f);
+        // Tags from 31 on follow in base 128
+        if tag == 0x1f {
+            tag = 0;
+            loop {
+                let b = self.byte()?;
+                if tag > u32::MAX >> 7 {
+                    return Err("the tag is too large".to_owned());
+                }
+                tag = tag << 7 | u32::from(b & 0x7f);
+                if b & 0x80 == 0 {
+                    break;
+                }
+            }
+        }
+
+        let first = self.byte()?;
+        let len = match first {
+            0x80 if constructed => None,
+            0x80 => return Err("a primitive value has an indefinite length".to_owned()),
+            0x81..=0x88 => {
+                let mut len = 0_u64;
+                for _ in 0..first & 0x7f {
+                    len = len << 8 | u64::from(self.byte()?);
+                }
+                Some(len)
+            }
+            0x00..=0x7f => Some(u64::from(first)),
+            _ => return Err("the length is too large".to_owned()),
+        };
+
+        let contents = match len {
+            Some(len) => {
+                let remaining = (self.input.len() - self.position) as u64;
+                if len > remaining {
+                    return Err(format!("the length {} is beyond the end of the input", len));
+                }
+                let end = self.position + len as usize;
+                if constructed {
+                    let mut parser = Parser {
+                        input: &self.input[..end],
+                        position: self.position,
+                    };
+                    let mut elements = Vec::new();
+                    while parser.position < end {
+                        match parser.element(depth + 1) {
+                            Ok(element) => elements.push(element),
+                            Err(e) => {
+                                self.position = parser.position;
+                                return Err(e);
+                            }
+                        }
+                    }
+                    self.position = end;
+                    Contents::Constructed(elements)
+                } else {
+                    let contents = &self.input[self.position..end];
+                    self.position = end;
+                    Contents::Primitive(contents)
+                }
+            }
+            None => {
+                // Values of indefinite length end with two zero bytes
+                let mut elements = Vec::new();
+                while !self.input[self.position..].starts_with(&[0, 0]) {
+                    elements.push(self.element(depth + 1)?);
+                }
+                self.position += 2;
+                Contents::Constructed(elements)
+            }
+        };
+        Ok(Element {
+            class,
+            tag,
+            contents,
+        })
+    }
+}
+
+fn element_value(element: Element) -> value::Value {
+    let universal = element.class == 0;
+    let kind = match TYPES.get(element.tag as usize) {
+        Some(&kind) if universal && !kind.is_empty() => kind.into(),
+        _ => value::Value::Unit,
+    };
+    let mut entries = vec![
+        ("class".into(), CLASSES[usize::from(element.class)].into()),
+        ("tag".into(), value::Value::U32(element.tag)),
+        ("type".into(), kind),
+    ];
+
+    let contents = match element.contents {
+        Contents::Constructed(elements) => {
+            // Strings of BER can be split into segments, and are joined here
+            if !universal || !is_string(element.tag) {
+                let elements = elements.into_iter().map(element_value).collect();
+                entries.push(("value".into(), value::Value::Sequence(elements)));
+                return value::Value::Map(entries);
+            }
+            let mut joined = Vec::new();
+            join_segments(elements, &mut joined);
+            borrow::Cow::Owned(joined)
+        }
+        Contents::Primitive(contents) => borrow::Cow::Borrowed(contents),
+    };
+
+    if !universal {
+        entries.push(("value".into(), value::Value::Bytes(contents.into_owned())));
+        return value::Value::Map(entries);
+    }
+    let v = match element.tag {
+        BOOLEAN => match *contents {
+            [b] => value::Value::Bool(b != 0),
+            _ => value::Value::Bytes(contents.into_owned()),
+        },
+        INTEGER | ENUMERATED => integer(&contents),
+        BIT_STRING => match contents.split_first() {
+            Some((&unused, bits)) => {
+                entries.push(("unused_bits".into(), value::Value::U8(unused)));
+                if unused == 0 {
+                    encapsulated(bits, &mut entries);
+                }
+                value::Value::Bytes(bits.to_vec())
+            }
+            None => value::Value::Bytes(Vec::new()),
+        },
+        OCTET_STRING => {
+            encapsulated(&contents, &mut entries);
+            value::Value::Bytes(contents.into_owned())
+        }
+        NULL => value::Value::Unit,
+        OBJECT_IDENTIFIER => {
+            let oid = x509_parser::der_parser::oid::Oid::new(borrow::Cow::Borrowed(&contents));
+            if let Ok(name) =
+                x509_parser::objects::oid2sn(&oid, x509_parser::objects::oid_registry())
+            {
+                entries.push(("name".into(), name.into()));
+            }
+            oid.to_id_string().into()
+        }
+        RELATIVE_OID => {
+            x509_parser::der_parser::oid::Oid::new_relative(borrow::Cow::Borrowed(&contents))
+                .to_id_string()
+                .into()
+        }
+        UTC_TIME | GENERALIZED_TIME => match str::from_utf8(&contents) {
+            Ok(text) => time(text, element.tag == UTC_TIME)
+                .unwrap_or_else(|| text.to_owned())
+                .into(),
+            Err(_) => value::Value::Bytes(contents.into_owned()),
+        },
+        T61_STRING => encoding_rs::WINDOWS_1252
+            .decode(&contents)
+            .0
+            .into_owned()
+            .into(),
+        BMP_STRING => {
+            let units: Vec<u16> = contents
+                .chunks_exact(2)
+                .map(|c| u16::from_be_bytes([c[0], c[1]]))
+                .collect();
+            String::from_utf16_lossy(&units).into()
+        }
+        UNIVERSAL_STRING => contents
+            .chunks_exact(4)
+            .map(|c| {
+                char::from_u32(u32::from_be_bytes([c[0], c[1], c[2], c[3]])).unwrap_or('\u{fffd}')
+            })
+            .collect::<String>()
+            .into(),
+        tag if is_string(tag) => match String::from_utf8(contents.into_owned()) {
+            Ok(s) => s.into(),
+            Err(e) => value::Value::Bytes(e.into_bytes()),
+        },
+        _ => value::Value::Bytes(contents.into_owned()),
+    };
+    entries.push(("value".into(), v));
+    value::Value::Map(entries)
+}
+
+/// Whether a universal type is a string of bytes or characters, which BER can split.
+fn is_string(tag: u32) -> bool {
+    matches!(tag, BIT_STRING | OCTET_STRING | 7 | 12 | 18..=30)
+}
+
+fn join_segments(elements: Vec<Element>, joined: &mut Vec<u8>) {
+    for element in elements {
+        match element.contents {
+            Contents::Primitive(contents) => joined.extend_from_slice(contents),
+            Contents::Constructed(elements) => join_segments(elements, joined),
+        }
+    }
+}
+
+/// Decodes the contents of an octet or bit string as a sequence or set of DER, if they are one.
+fn encapsulated(contents: &[u8], entries: &mut Vec<(value::Value, value::Value)>) {
+    if !matches!(contents.first(), Some(0x30 | 0x31)) {
+        return;
+    }
+    let mut parser = Parser {
+        input: contents,
+        position: 0,
+    };
+    if let Ok(element) = parser.element(0) {
+        if parser.position == contents.len() {
+            entries.push(("encapsulated".into(), element_value(element)));
+        }
+    }
+}
+
+/// A big-endian two's complement integer, which is bytes when it doesn't fit in 64 bits.
+fn integer(contents: &[u8]) -> value::Value {
+    match contents {
+        [] => value::Value::Bytes(Vec::new()),
+        [first, ..] if contents.len() <= 8 => {
+            let fill = if first & 0x80 != 0 { 0xff } else { 0 };
+            let mut bytes = [fill; 8];
+            bytes[8 - contents.len()..].copy_from_slice(contents);
+            value::Value::I64(i64::from_be_bytes(bytes))
+        }
+        [0, rest @ ..] if rest.len() == 8 => {
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(rest);
+            value::Value::U64(u64::from_be_bytes(bytes))
+        }
+        _ => value::Value::Bytes(contents.to_vec()),
+    }
+}
+
+/// Converts a time like `250131235959Z` (UTCTime, where years before 50 are in the 2000s) or
+/// `20250131235959.5Z` (GeneralizedTime) to RFC 3339.  Local times without a zone are kept so.
+fn time(text: &str, utc_time: bool) -> Option<String> {
+    let (year, rest) = if utc_time {
+        let year: u32 = text.get(..2)?.parse().ok()?;
+        let century = if year < 50 { 2000 } else { 1900 };
+        (century + year, &text[2..])
+    } else {
+        (text.get(..4)?.parse().ok()?, &text[4..])
+    };
+    let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+    if digits != 8 && digits != 10 {
+        return None;
+    }
+    let field = |start: usize| rest.get(start..start + 2).filter(|_| start + 2 <= digits);
+    let mut time = format!(
+        "{:04}-{}-{}T{}:{}:{}",
+        year,
+        field(0)?,
+        field(2)?,
+        field(4)?,
+        field(6)?,
+        field(8).unwrap_or("00")
+    );
+    let mut rest = &rest[digits..];
+    if let Some(fraction) = rest.strip_prefix(['.', ',']) {
+        let len = fraction.bytes().take_while(u8::is_ascii_digit).count();
+        if len == 0 || utc_time {
+            return None;
+        }
+        time.push('.');
+        time.push_str(&fraction[..len]);
+        rest = &fraction[len..];
+    }
+    match rest.as_bytes() {
+        [] => (),
+        [b'Z'] => time.push('Z'),
+        [sign @ (b'+' | b'-'), offset @ ..]
+            if offset.len() == 4 && offset.iter().all(u8::is_ascii_digit) =>
+        {
+            time.push(char::from(*sign));
+            time.push_str(&rest[1..3]);
+            time.push(':');
+            time.push_str(&rest[3..5]);
+        }
+        _ => return None,
+    }
+    Some(time)
+}
+
+fn format_error(msg: &str) -> error::Error {
+    error::Error::Format {
+        msg: msg.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::value::Source as _;
+
+    fn read(input: &[u8]) -> error::Result<Vec<value::Value>> {
+        let mut reader = source(input)?;
+        let mut records = Vec::new();
+        while let Some(record) = reader.read()? {
+            records.push(record);
+        }
+        Ok(records)
+    }
+
+    #[test]
+    fn test_der() {
+        let der = b"\x30\x26\x06\x03\x55\x04\x03\x13\x02rq\x17\x0d250131235959Z\x03\x05\x00\x30\x02\x05\x00\x80\x01\xff\x02\x02\xff\x85";
+        let records = read(der).unwrap();
+        assert_eq!(
+            records.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            vec![
+                r#"{"class": "universal", "tag": 16, "type": "SEQUENCE", "value": [{"class": "universal", "tag": 6, "type": "OBJECT IDENTIFIER", "name": "commonName", "value": "2.5.4.3"}, {"class": "universal", "tag": 19, "type": "PrintableString", "value": "rq"}, {"class": "universal", "tag": 23, "type": "UTCTime", "value": "2025-01-31T23:59:59Z"}, {"class": "universal", "tag": 3, "type": "BIT STRING", "unused_bits": 0, "encapsulated": {"class": "universal", "tag": 16, "type": "SEQUENCE", "value": [{"class": "universal", "tag": 5, "type": "NULL", "value": null}]}, "value": 0x30020500}, {"class": "context", "tag": 0, "type": null, "value": 0xff}, {"class": "universal", "tag": 2, "type": "INTEGER", "value": -123}]}"#
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ber() {
+        // BER with an indefinite length and a string in segments, in PEM
+        let pem = "-----BEGIN DATA-----\nJIAEAmFiBAFjAAABAQA=\n-----END DATA-----\n";
+        let records = read(pem.as_bytes()).unwrap();
+        assert_eq!(
+            records.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            vec![
+                r#"{"class": "universal", "tag": 4, "type": "OCTET STRING", "value": 0x616263}"#,
+                r#"{"class": "universal", "tag": 1, "type": "BOOLEAN", "value": false}"#,
+            ]
+        );
+        assert!(read(b"\x30\x05\x02\x01").is_err());
+    }
+}
@@ -0,0 +1,883 @@
+//! The tags of audio files: ID3 tags of MP3 files, and Vorbis comments of FLAC, Ogg Vorbis and
+//! Opus files.
+//!
+//! Each file becomes a record with its `format`, its `tags` and the `pictures` that are embedded
+//! in it.  Common tags have the same names in all formats, like `title`, `artist`, `album`,
+//! `album_artist`, `track`, `disc`, `date` and `genre`.  Other tags keep their name in the file,
+//! like `REPLAYGAIN_TRACK_GAIN` for Vorbis comments and user-defined ID3 frames, or the frame id
+//! like `TMOO` for other ID3 text frames.  Tag values are strings, or sequences of strings for
+//! tags with several values.
+//!
+//! The sink changes the tags of an audio file in place: the tags of the record replace all tags
+//! of the file, while pictures and the audio itself are kept.
+
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path;
+
+use base64::Engine;
+use id3::TagLike;
+
+use crate::error;
+use crate::output;
+use crate::value;
+
+/// The names of common tags, with their ID3 frame and their Vorbis comment field.
+const COMMON_TAGS: &[(&str, &str, &str)] = &[
+    ("title", "TIT2", "TITLE"),
+    ("artist", "TPE1", "ARTIST"),
+    ("album", "TALB", "ALBUM"),
+    ("album_artist", "TPE2", "ALBUMARTIST"),
+    ("composer", "TCOM", "COMPOSER"),
+    ("genre", "TCON", "GENRE"),
+    ("date", "TDRC", "DATE"),
+    ("track", "TRCK", "TRACKNUMBER"),
+    ("disc", "TPOS", "DISCNUMBER"),
+    ("bpm", "TBPM", "BPM"),
+    ("isrc", "TSRC", "ISRC"),
+    ("copyright", "TCOP", "COPYRIGHT"),
+    ("encoder", "TSSE", "ENCODER"),
+    ("comment", "COMM", "COMMENT"),
+    ("lyrics", "USLT", "LYRICS"),
+];
+
+/// The Vorbis comment field with an embedded picture, as a base64 FLAC picture block.
+const PICTURE_FIELD: &str = "METADATA_BLOCK_PICTURE";
+
+/// The names of picture types, which ID3 and FLAC share.
+const PICTURE_TYPES: &[&str] = &[
+    "Other",
+    "File icon",
+    "Other file icon",
+    "Front cover",
+    "Back cover",
+    "Leaflet page",
+    "Media",
+    "Lead artist",
+    "Artist",
+    "Conductor",
+    "Band",
+    "Composer",
+    "Lyricist",
+    "Recording location",
+    "During recording",
+    "During performance",
+    "Screen capture",
+    "Bright colored fish",
+    "Illustration",
+    "Band logotype",
+    "Publisher logotype",
+];
+
+#[derive(Debug)]
+pub struct Source(Option<value::Value>);
+
+#[derive(Debug)]
+pub struct Sink {
+    path: path::PathBuf,
+    written: bool,
+}
+
+/// The tags of a file, in order, with all values of each tag.
+type Tags = Vec<(String, Vec<String>)>;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Container {
+    Mp3,
+    Flac,
+    Ogg,
+}
+
+/// Creates a source for the tags of an audio file, which is read in full from the input.
+pub fn source<R>(mut r: R) -> error::Result<Source>
+where
+    R: io::Read,
+{
+    let mut input = Vec::new();
+    r.read_to_end(&mut input)?;
+    Ok(Source(Some(read(&input)?)))
+}
+
+/// Creates a sink that replaces the tags of the audio file at the path.
+#[inline]
+pub fn sink(path: &path::Path) -> Sink {
+    Sink {
+        path: path.to_owned(),
+        written: false,
+    }
+}
+
+impl value::Source for Source {
+    #[inline]
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        Ok(self.0.take())
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = if self.0.is_some() { 1 } else { 0 };
+        (remaining, Some(remaining))
+    }
+}
+
+impl value::Sink for Sink {
+    fn write(&mut self, v: value::Value) -> error::Result<()> {
+        if self.written {
+            return Err(format_error(
+                "the tags of an audio file can only be written from a single record",
+            ));
+        }
+        self.written = true;
+        let tags = record_tags(v)?;
+
+        let input = fs::read(&self.path)?;
+        let (container, start) = container(&input)?;
+        let output = match container {
+            Container::Mp3 => write_id3(&input, start, &tags)?,
+            Container::Flac => write_flac(&input, start, &tags)?,
+            Container::Ogg => write_ogg(&input, start, &tags)?,
+        };
+
+        // The file is replaced as a whole, keeping its permissions
+        let mut options = output::FileOptions {
+            atomic: true,
+            ..output::FileOptions::default()
+        };
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            options.mode = Some(fs::metadata(&self.path)?.permissions().mode() & 0o7777);
+        }
+        let (mut file, pending) = output::open(&self.path, &options)?;
+        file.write_all(&output)?;
+        file.sync_all()?;
+        pending.commit()?;
+        Ok(())
+    }
+}
+
+/// Finds the container of the audio, and where it starts after any ID3 tag in front of it.
+fn container(input: &[u8]) -> error::Result<(Container, usize)> {
+    let start = id3_length(input);
+    let audio = &input[start..];
+    if audio.starts_with(b"fLaC") {
+        Ok((Container::Flac, start))
+    } else if audio.starts_with(b"OggS") {
+        Ok((Container::Ogg, start))
+    } else if start > 0
+        || (audio.len() >= 2 && audio[0] == 0xFF && audio[1] & 0xE0 == 0xE0)
+        || (input.len() >= 128 && input[input.len() - 128..].starts_with(b"TAG"))
+    {
+        Ok((Container::Mp3, start))
+    } else {
+        Err(format_error(
+            "the input is not an MP3, FLAC, Ogg Vorbis or Opus file",
+        ))
+    }
+}
+
+/// The length of the ID3v2 tag at the start of the input, if there is one.
+fn id3_length(input: &[u8]) -> usize {
+    if input.len() < 10 || !input.starts_with(b"ID3") {
+        return 0;
+    }
+    // The size doesn't include the header, or the footer that some tags have
+    let size = input[6..10]
+        .iter()
+        .fold(0, |size, &b| size << 7 | usize::from(b & 0x7F));
+    let footer = if input[5] & 0x10 != 0 { 10 } else { 0 };
+    (10 + size + footer).min(input.len())
+}
+
+fn read(input: &[u8]) -> error::Result<value::Value> {
+    let (container, start) = container(input)?;
+    let mut pictures = Vec::new();
+    let (format, tags) = match container {
+        Container::Mp3 => {
+            let tag = match id3::v1v2::read_from(io::Cursor::new(input)) {
+                Ok(tag) => Some(tag),
+                Err(id3::Error {
+                    kind: id3::ErrorKind::NoTag,
+                    ..
+                }) => None,
+                Err(e) => return Err(format_error(&format!("invalid ID3 tag: {}", e))),
+            };
+            let tags = tag.map_or_else(Vec::new, |tag| read_id3(&tag, &mut pictures));
+            ("MP3", tags)
+        }
+        Container::Flac => {
+            let mut tags = Vec::new();
+            for (kind, data) in flac_blocks(&input[start..])?.1 {
+                match kind {
+                    4 => tags = vorbis_comments(data)?.1,
+                    6 => pictures.extend(picture(data)),
+                    _ => (),
+                }
+            }
+            ("FLAC", tags)
+        }
+        Container::Ogg => {
+            let pages = ogg_pages(&input[start..])?;
+            let (codec, packets, _) = ogg_headers(&pages)?;
+            let comments = &packets[1][codec.comment_prefix().len()..];
+            (codec.name(), vorbis_comments(comments)?.1)
+        }
+    };
+
+    // Pictures in Vorbis comments are base64 FLAC picture blocks
+    let mut entries: Vec<(value::Value, value::Value)> = Vec::new();
+    for (name, values) in tags {
+        if name.eq_ignore_ascii_case(PICTURE_FIELD) {
+            for v in values {
+                if let Ok(data) = base64::engine::general_purpose::STANDARD.decode(v.trim()) {
+                    pictures.extend(picture(&data));
+                }
+            }
+            continue;
+        }
+        let v = if values.len() == 1 {
+            values.into_iter().next().unwrap().into()
+        } else {
+            value::Value::Sequence(values.into_iter().map(value::Value::from).collect())
+        };
+        entries.push((name.into(), v));
+    }
+    Ok(value::Value::Map(vec![
+        ("format".into(), format.into()),
+        ("tags".into(), value::Value::Map(entries)),
+        ("pictures".into(), value::Value::Sequence(pictures)),
+    ]))
+}
+
+/// Adds a value to a tag, after any values it has already.
+fn add_tag(tags: &mut Tags, name: &str, v: String) {
+    match tags.iter_mut().find(|(n, _)| n == name) {
+        Some((_, values)) => values.push(v),
+        None => tags.push((name.to_owned(), vec![v])),
+    }
+}
+
+/// The tags to write from a record: its `tags`, like the source reads them, or else the record
+/// itself.
+fn record_tags(v: value::Value) -> error::Result<Tags> {
+    let mut entries = match v {
+        value::Value::Map(entries) => entries,
+        v => {
+            return Err(format_error(&format!(
+                "audio tags can only be written from maps, got: {}",
+                v.summary(value::ERROR_SUMMARY_LEN)
+            )))
+        }
+    };
+    if let Some(i) = entries
+        .iter()
+        .position(|(k, v)| k.as_str() == Some("tags") && v.as_map().is_some())
+    {
+        if let value::Value::Map(tags) = entries.swap_remove(i).1 {
+            entries = tags;
+        }
+    }
+
+    let mut tags = Vec::new();
+    for (k, v) in entries {
+        let name = match k {
+            value::Value::String(name) => name,
+            k => k.to_string(),
+        };
+        let values = match v {
+            value::Value::Sequence(values) => values,
+            v => vec![v],
+        };
+        for v in values {
+            match v {
+                value::Value::Unit => (),
+                value::Value::String(s) => add_tag(&mut tags, &name, s),
+                value::Value::Map(_) | value::Value::Sequence(_) | value::Value::Bytes(_) => {
+                    return Err(format_error(&format!(
+                        "audio tags can only be scalars, but {} is: {}",
+                        name,
+                        v.summary(value::ERROR_SUMMARY_LEN)
+                    )))
+                }
+                v => add_tag(&mut tags, &name, v.to_string()),
+            }
+        }
+    }
+    Ok(tags)
+}
+
+/// Reads the text frames, comments and lyrics of an ID3 tag.
+fn read_id3(tag: &id3::Tag, pictures: &mut Vec<value::Value>) -> Tags {
+    let mut tags = Vec::new();
+    for frame in tag.frames() {
+        match *frame.content() {
+            id3::Content::Text(ref text) => {
+                let name = match frame.id() {
+                    // ID3v2.3 has the year in its own frame
+                    "TYER" => "date",
+                    id => COMMON_TAGS
+                        .iter()
+                        .find(|(_, frame, _)| *frame == id)
+                        .map_or(id, |(name, _, _)| name),
+                };
+                for v in text.split('\0') {
+                    add_tag(&mut tags, name, v.to_owned());
+                }
+            }
+            id3::Content::ExtendedText(ref text) => {
+                for v in text.value.split('\0') {
+                    add_tag(&mut tags, &text.description, v.to_owned());
+                }
+            }
+            id3::Content::Comment(ref comment) if comment.description.is_empty() => {
+                add_tag(&mut tags, "comment", comment.text.clone());
+            }
+            id3::Content::Lyrics(ref lyrics) => {
+                add_tag(&mut tags, "lyrics", lyrics.text.clone());
+            }
+            id3::Content::Picture(ref p) => pictures.push(picture_value(
+                u8::from(p.picture_type).into(),
+                &p.mime_type,
+                &p.description,
+                p.data.len(),
+            )),
+            _ => (),
+        }
+    }
+    tags
+}
+
+/// Writes the ID3 tag of an MP3 file with the tags, keeping other frames like pictures.
+fn write_id3(input: &[u8], start: usize, tags: &Tags) -> error::Result<Vec<u8>> {
+    let old = match id3::Tag::read_from2(io::Cursor::new(input)) {
+        Ok(tag) => tag,
+        Err(id3::Error {
+            kind: id3::ErrorKind::NoTag,
+            ..
+        }) => id3::Tag::new(),
+        Err(e) => return Err(format_error(&format!("invalid ID3 tag: {}", e))),
+    };
+    let mut tag = id3::Tag::with_version(id3::Version::Id3v24);
+    for frame in old.frames() {
+        let replaced = match *frame.content() {
+            id3::Content::Comment(ref comment) => comment.description.is_empty(),
+            id3::Content::Text(_) | id3::Content::ExtendedText(_) | id3::Content::Lyrics(_) => true,
+            _ => false,
+        };
+        if !replaced {
+            tag.add_frame(frame.clone());
+        }
+    }
+
+    for (name, values) in tags {
+        let frame = COMMON_TAGS
+            .iter()
+            .find(|(common, _, _)| common == name)
+            .map(|(_, frame, _)| *frame);
+        let (id, content) = match frame {
+            Some("COMM") => (
+                "COMM",
+                id3::Content::Comment(id3::frame::Comment {
+                    lang: "eng".to_owned(),
+                    description: String::new(),
+                    text: values.join("\n"),
+                }),
+            ),
+            Some("USLT") => (
+                "USLT",
+                id3::Content::Lyrics(id3::frame::Lyrics {
+                    lang: "eng".to_owned(),
+                    description: String::new(),
+                    text: values.join("\n"),
+                }),
+            ),
+            Some(id) => (id, id3::Content::Text(values.join("\0"))),
+            // Names of text frames, like TMOO, or else user-defined text
+            None if name.len() == 4
+                && name.starts_with('T')
+                && name != "TXXX"
+                && name
+                    .bytes()
+                    .all(|b| b.is_ascii_uppercase() || b.is_ascii_digit()) =>
+            {
+                (name.as_str(), id3::Content::Text(values.join("\0")))
+            }
+            None => (
+                "TXXX",
+                id3::Content::ExtendedText(id3::frame::ExtendedText {
+                    description: name.clone(),
+                    value: values.join("\0"),
+                }),
+            ),
+        };
+        tag.add_frame(id3::Frame::with_content(id, content));
+    }
+
+    let mut output = Vec::with_capacity(input.len());
+    tag.write_to(&mut output, id3::Version::Id3v24)
+        .map_err(|e| format_error(&format!("can't write the ID3 tag: {}", e)))?;
+    output.extend_from_slice(&input[start..]);
+    Ok(output)
+}
+
+/// A metadata block of a FLAC stream, with its type.
+type FlacBlock<'a> = (u8, &'a [u8]);
+
+/// The metadata blocks of a FLAC stream, and where the audio frames start.
+fn flac_blocks(audio: &[u8]) -> error::Result<(usize, Vec<FlacBlock<'_>>)> {
+    let mut blocks = Vec::new();
+    let mut i = 4;
+    loop {
+        if i + 4 > audio.len() {
+            return Err(format_error(&format!(
+                "FLAC metadata block at byte {} is truncated",
+                i
+            )));
+        }
+        let header = audio[i];
+        let length = u32::from_be_bytes([0, audio[i + 1], audio[i + 2], audio[i + 3]]) as usize;
+        let end = i + 4 + length;
+        if end > audio.len() {
+            return Err(format_error(&format!(
+                "FLAC metadata block at byte {} is truncated",
+                i
+            )));
+        }
+        blocks.push((header & 0x7F, &audio[i + 4..end]));
+        i = end;
+        if header & 0x80 != 0 {
+            return Ok((i, blocks));
+        }
+    }
+}
+
+/// Writes the Vorbis comment block of a FLAC file with the tags, keeping the other blocks.
+fn write_flac(input: &[u8], start: usize, tags: &Tags) -> error::Result<Vec<u8>> {
+    let audio = &input[start..];
+    let (end, blocks) = flac_blocks(audio)?;
+    let (mut vendor, mut pictures) = (String::new(), Vec::new());
+    let mut kept = Vec::new();
+    for (kind, data) in blocks {
+        if kind == 4 {
+            let (v, old_tags) = vorbis_comments(data)?;
+            vendor = v;
+            pictures = picture_fields(old_tags);
+        } else {
+            kept.push((kind, data.to_vec()));
+        }
+    }
+    let mut blocks = kept;
+    // The stream info must stay the first block
+    let comments = write_vorbis_comments(&vendor, tags, &pictures);
+    blocks.insert(1.min(blocks.len()), (4, comments));
+
+    let mut output = Vec::with_capacity(input.len());
+    output.extend_from_slice(&input[..start + 4]);
+    let last = blocks.len() - 1;
+    for (i, (kind, data)) in blocks.iter().enumerate() {
+        if data.len() >= 1 << 24 {
+            return Err(format_error("the FLAC metadata block is too large"));
+        }
+        let length = (data.len() as u32).to_be_bytes();
+        output.push(if i == last { kind | 0x80 } else { *kind });
+        output.extend_from_slice(&length[1..]);
+        output.extend_from_slice(data);
+    }
+    output.extend_from_slice(&audio[end..]);
+    Ok(output)
+}
+
+/// Reads a Vorbis comment list into its vendor and its tags.
+fn vorbis_comments(mut data: &[u8]) -> error::Result<(String, Tags)> {
+    let truncated = || format_error("the Vorbis comments are truncated");
+    let string = |data: &mut &[u8]| {
+        let length = take(data, 4).ok_or_else(truncated)?;
+        let length = u32::from_le_bytes([length[0], length[1], length[2], length[3]]);
+        let bytes = take(data, length as usize).ok_or_else(truncated)?;
+        Ok::<_, error::Error>(String::from_utf8_lossy(bytes).into_owned())
+    };
+    let vendor = string(&mut data)?;
+    let count = take(&mut data, 4).ok_or_else(truncated)?;
+    let count = u32::from_le_bytes([count[0], count[1], count[2], count[3]]);
+    let mut tags = Vec::new();
+    for _ in 0..count {
+        let comment = string(&mut data)?;
+        // Field names are case-insensitive, and common ones are named like in ID3 tags
+        let (field, v) = comment.split_once('=').unwrap_or((&comment, ""));
+        let name = match COMMON_TAGS
+            .iter()
+            .find(|(_, _, f)| f.eq_ignore_ascii_case(field))
+        {
+            Some((name, _, _)) => name,
+            None if field.eq_ignore_ascii_case("DESCRIPTION") => "comment",
+            None => field,
+        };
+        add_tag(&mut tags, name, v.to_owned());
+    }
+    Ok((vendor, tags))
+}
+
+/// Writes a Vorbis comment list with the tags, followed by the pictures that were in the
+/// comments before.
+fn write_vorbis_comments(vendor: &str, tags: &Tags, pictures: &[String]) -> Vec<u8> {
+    let mut comments = Vec::new();
+    for (name, values) in tags {
+        let field = COMMON_TAGS
+            .iter()
+            .find(|(common, _, _)| common == name)
+            .map_or_else(|| name.to_ascii_uppercase(), |(_, _, f)| (*f).to_owned());
+        for v in values {
+            comments.push(format!("{}={}", field, v));
+        }
+    }
+    comments.extend(pictures.iter().map(|p| format!("{}={}", PICTURE_FIELD, p)));
+
+    let mut data = (vendor.len() as u32).to_le_bytes().to_vec();
+    data.extend_from_slice(vendor.as_bytes());
+    data.extend_from_slice(&(comments.len() as u32).to_le_bytes());
+    for comment in comments {
+        data.extend_from_slice(&(comment.len() as u32).to_le_bytes());
+        data.extend_from_slice(comment.as_bytes());
+    }
+    data
+}
+
+/// The pictures in Vorbis comments, which are kept when the tags are replaced.
+fn picture_fields(tags: Tags) -> Vec<String> {
+    tags.into_iter()
+        .filter(|(name, _)| name.eq_ignore_ascii_case(PICTURE_FIELD))
+        .flat_map(|(_, values)| values)
+        .collect()
+}
+
+/// Describes a FLAC picture block.
+fn picture(mut data: &[u8]) -> Option<value::Value> {
+    let number =
+        |data: &mut &[u8]| take(data, 4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]));
+    let string = |data: &mut &[u8]| {
+        let length = number(data)?;
+        take(data, length as usize).map(|b| String::from_utf8_lossy(b).into_owned())
+    };
+    let kind = number(&mut data)?;
+    let mime = string(&mut data)?;
+    let description = string(&mut data)?;
+    // The width, height, color depth and number of colors
+    take(&mut data, 16)?;
+    let size = number(&mut data)?;
+    Some(picture_value(kind, &mime, &description, size as usize))
+}
+
+/// Takes bytes from the start of the data.
+fn take<'a>(data: &mut &'a [u8], length: usize) -> Option<&'a [u8]> {
+    if data.len() < length {
+        return None;
+    }
+    let (bytes, rest) = data.split_at(length);
+    *data = rest;
+    Some(bytes)
+}
+
+fn picture_value(kind: u32, mime: &str, description: &str, size: usize) -> value::Value {
+    let kind = PICTURE_TYPES
+        .get(kind as usize)
+        .map_or_else(|| value::Value::U32(kind), |&name| value::Value::from(name));
+    value::Value::Map(vec![
+        ("type".into(), kind),
+        ("mime".into(), mime.into()),
+        ("description".into(), description.into()),
+        ("size".into(), value::Value::U64(size as u64)),
+    ])
+}
+
+/// The codecs of Ogg files that have Vorbis comments.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Codec {
+    Vorbis,
+    Opus,
+}
+
+impl Codec {
+    fn name(self) -> &'static str {
+        match self {
+            Codec::Vorbis => "Ogg Vorbis",
+            Codec::Opus => "Opus",
+        }
+    }
+
+    /// What the packet with the comments starts with.
+    fn comment_prefix(self) -> &'static [u8] {
+        match self {
+            Codec::Vorbis => b"\x03vorbis",
+            Codec::Opus => b"OpusTags",
+        }
+    }
+
+    /// The number of header packets, which come before the audio on their own pages.
+    fn header_packets(self) -> usize {
+        match self {
+            Codec::Vorbis => 3,
+            Codec::Opus => 2,
+        }
+    }
+}
+
+/// A page of an Ogg file, with its segment table and the data of the segments.
+#[derive(Debug)]
+struct Page<'a> {
+    bytes: &'a [u8],
+    serial: u32,
+    sequence: u32,
+    segments: &'a [u8],
+    data: &'a [u8],
+}
+
+fn ogg_pages(input: &[u8]) -> error::Result<Vec<Page<'_>>> {
+    let mut pages = Vec::new();
+    let mut i = 0;
+    while i < input.len() {
+        let truncated = || format_error(&format!("Ogg page at byte {} is truncated", i));
+        let header = input.get(i..i + 27).ok_or_else(truncated)?;
+        if !header.starts_with(b"OggS") {
+            return Err(format_error(&format!("invalid Ogg page at byte {}", i)));
+        }
+        let count = usize::from(header[26]);
+        let segments = input.get(i + 27..i + 27 + count).ok_or_else(truncated)?;
+        let start = i + 27 + count;
+        let end = start + segments.iter().map(|&s| usize::from(s)).sum::<usize>();
+        let data = input.get(start..end).ok_or_else(truncated)?;
+        pages.push(Page {
+            bytes: &input[i..end],
+            serial: u32::from_le_bytes([header[14], header[15], header[16], header[17]]),
+            sequence: u32::from_le_bytes([header[18], header[19], header[20], header[21]]),
+            segments,
+            data,
+        });
+        i = end;
+    }
+    Ok(pages)
+}
+
+/// Reads the header packets of the first stream of an Ogg file, and the index of the page that
+/// the last one ends on.
+fn ogg_headers(pages: &[Page]) -> error::Result<(Codec, Vec<Vec<u8>>, usize)> {
+    let first = pages
+        .first()
+        .ok_or_else(|| format_error("the Ogg file has no pages"))?;
+    let codec = if first.data.starts_with(b"\x01vorbis") {
+        Codec::Vorbis
+    } else if first.data.starts_with(b"OpusHead") {
+        Codec::Opus
+    } else {
+        return Err(format_error("the Ogg file is neither Vorbis nor Opus"));
+    };
+
+    let mut packets = Vec::new();
+    let mut packet = Vec::new();
+    for (index, page) in pages.iter().enumerate() {
+        if page.serial != first.serial {
+            continue;
+        }
+        let mut offset = 0;
+        for (i, &size) in page.segments.iter().enumerate() {
+            let size = usize::from(size);
+            packet.extend_from_slice(&page.data[offset..offset + size]);
+            offset += size;
+            // Packets end with a segment that is shorter than the longest one
+            if size < 255 {
+                packets.push(std::mem::take(&mut packet));
+                if packets.len() == codec.header_packets() {
+                    if i + 1 != page.segments.len() {
+                        return Err(format_error(
+                            "the audio of the Ogg file starts on a page with its headers",
+                        ));
+                    }
+                    if !packets[1].starts_with(codec.comment_prefix()) {
+                        return Err(format_error("the Ogg file has no comment header"));
+                    }
+                    return Ok((codec, packets, index));
+                }
+            }
+        }
+    }
+    Err(format_error("the headers of the Ogg file are truncated"))
+}
+
+/// Writes the comment header of an Ogg file with the tags.  The pages of the headers after the
+/// first one are laid out again, and the later pages of the stream are renumbered.
+fn write_ogg(input: &[u8], start: usize, tags: &Tags) -> error::Result<Vec<u8>> {
+    let pages = ogg_pages(&input[start..])?;
+    let (codec, mut packets, last) = ogg_headers(&pages)?;
+    let serial = pages[0].serial;
+
+    let prefix = codec.comment_prefix();
+    let (vendor, old_tags) = vorbis_comments(&packets[1][prefix.len()..])?;
+    let mut comment = prefix.to_vec();
+    comment.extend(write_vorbis_comments(
+        &vendor,
+        tags,
+        &picture_fields(old_tags),
+    ));
+    // Vorbis ends the header with a framing bit
+    if codec == Codec::Vorbis {
+        comment.push(1);
+    }
+    packets[1] = comment;
+
+    let header_pages = pages[1..=last]
+        .iter()
+        .filter(|page| page.serial == serial)
+        .count();
+    let new_pages = ogg_header_pages(&packets[1..], serial);
+    let shift = new_pages.len() as i64 - header_pages as i64;
+
+    let mut output = Vec::with_capacity(input.len());
+    output.extend_from_slice(&input[..start]);
+    output.extend_from_slice(pages[0].bytes);
+    for page in &pages[1..=last] {
+        if page.serial != serial {
+            output.extend_from_slice(page.bytes);
+        }
+    }
+    for page in &new_pages {
+        output.extend_from_slice(page);
+    }
+    for page in &pages[last + 1..] {
+        if page.serial == serial && shift != 0 {
+            let mut bytes = page.bytes.to_vec();
+            let sequence = (i64::from(page.sequence) + shift) as u32;
+            bytes[18..22].copy_from_slice(&sequence.to_le_bytes());
+            set_ogg_crc(&mut bytes);
+            output.extend_from_slice(&bytes);
+        } else {
+            output.extend_from_slice(page.bytes);
+        }
+    }
+    Ok(output)
+}
+
+/// Lays out packets on pages that start with the sequence number 1, after the first page.
+fn ogg_header_pages(packets: &[Vec<u8>], serial: u32) -> Vec<Vec<u8>> {
+    // The segments of all packets, with whether they end a packet
+    let mut segments = Vec::new();
+    for packet in packets {
+        let mut rest = packet.as_slice();
+        while rest.len() >= 255 {
+            segments.push((&rest[..255], false));
+            rest = &rest[255..];
+        }
+        segments.push((rest, true));
+    }
+
+    let mut pages = Vec::new();
+    let mut continued = false;
+    for (sequence, chunk) in (1u32..).zip(segments.chunks(255)) {
+        let ends_packet = chunk.iter().any(|&(_, end)| end);
+        let mut page = b"OggS\0".to_vec();
+        page.push(if continued { 1 } else { 0 });
+        // Header pages have no granule position, unless a packet ends on them
+        let granule: u64 = if ends_packet { 0 } else { u64::MAX };
+        page.extend_from_slice(&granule.to_le_bytes());
+        page.extend_from_slice(&serial.to_le_bytes());
+        page.extend_from_slice(&sequence.to_le_bytes());
+        page.extend_from_slice(&[0; 4]);
+        page.push(chunk.len() as u8);
+        page.extend(chunk.iter().map(|(data, _)| data.len() as u8));
+        for (data, _) in chunk {
+            page.extend_from_slice(data);
+        }
+        set_ogg_crc(&mut page);
+        pages.push(page);
+        continued = chunk.last().is_some_and(|&(_, end)| !end);
+    }
+    pages
+}
+
+/// Sets the checksum of an Ogg page, which is a CRC-32 without reflection.
+fn set_ogg_crc(page: &mut [u8]) {
+    page[22..26].copy_from_slice(&[0; 4]);
+    let mut crc = 0u32;
+    for &b in page.iter() {
+        crc ^= u32::from(b) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                crc << 1 ^ 0x04C1_1DB7
+            } else {
+                crc << 1
+            };
+        }
+    }
+    page[22..26].copy_from_slice(&crc.to_le_bytes());
+}
+
+fn format_error(msg: &str) -> error::Error {
+    error::Error::Format {
+        msg: msg.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+
+    use crate::value::Sink as _;
+    use crate::value::Source as _;
+
+    fn read_all(input: &[u8]) -> error::Result<Vec<value::Value>> {
+        let mut reader = source(input)?;
+        let mut records = Vec::new();
+        while let Some(record) = reader.read()? {
+            records.push(record);
+        }
+        Ok(records)
+    }
+
+    /// A FLAC file with the stream info, the comments and a frame header.
+    fn flac() -> Vec<u8> {
+        let mut flac = b"fLaC\0\0\0\x22".to_vec();
+        flac.extend_from_slice(&[0; 34]);
+        flac.extend_from_slice(b"\x84\0\0\x27\x04\0\0\0test\x02\0\0\0\x0a\0\0\0TITLE=Song");
+        flac.extend_from_slice(b"\x09\0\0\0artist=Jo\xff\xf8\x69\x08");
+        flac
+    }
+
+    #[test]
+    fn test_flac() {
+        let flac = flac();
+        let records = read_all(&flac).unwrap();
+        assert_eq!(
+            records.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            vec![
+                r#"{"format": "FLAC", "tags": {"title": "Song", "artist": "Jo"}, "pictures": []}"#
+            ]
+        );
+        assert!(read_all(&flac[..50]).is_err());
+        assert!(read_all(&b"RIFF\0\0\0\0WAVE"[..]).is_err());
+    }
+
+    #[test]
+    fn test_sink() {
+        let dir = env::temp_dir().join(format!("rq-audio-tags-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let output = dir.join("song.flac");
+        fs::write(&output, flac()).unwrap();
+        let input = r#"{"title": "Intro", "artist": ["Jo", "Al"], "track": 1}"#;
+        let record = value::json::source(input.as_bytes())
+            .read()
+            .unwrap()
+            .unwrap();
+        let mut writer = sink(&output);
+        writer.write(record.clone()).unwrap();
+        assert!(writer.write(record).is_err());
+
+        let written = fs::read(&output).unwrap();
+        assert!(written.ends_with(b"\xff\xf8\x69\x08"));
+        let records = read_all(&written).unwrap();
+        assert_eq!(
+            records[0].get(".tags").unwrap().to_string(),
+            r#"{"title": "Intro", "artist": ["Jo", "Al"], "track": "1"}"#
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
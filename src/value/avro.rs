@@ -1,6 +1,7 @@
 use crate::error;
 use crate::value;
 use avro_rs;
+use serde_json;
 use std;
 use std::fmt;
 use std::io;
@@ -9,9 +10,31 @@ pub struct Source<'a, R>(avro_rs::Reader<'a, R>)
 where
     R: io::Read;
 
-pub struct Sink<'a, W>(avro_rs::Writer<'a, W>)
+/// Writes records as data blocks of an object container file.
+pub struct Sink<'a, W>
 where
-    W: io::Write;
+    W: io::Write,
+{
+    schema: &'a avro_rs::Schema,
+    writer: W,
+    codec: avro_rs::Codec,
+    marker: [u8; 16],
+    has_header: bool,
+    buffer: Vec<u8>,
+    num_values: usize,
+}
+
+/// The parts of an object container file header that are needed to append to the file.
+#[derive(Debug)]
+pub struct Header {
+    schema: avro_rs::Schema,
+    codec: avro_rs::Codec,
+    marker: [u8; 16],
+}
+
+const MAGIC: &[u8] = b"Obj\x01";
+// Same block size as what `avro_rs::Writer` uses
+const SYNC_INTERVAL: usize = 16_000;
 
 #[inline]
 pub fn source<'a, R>(r: R) -> error::Result<Source<'a, R>>
@@ -24,11 +47,227 @@ where
 }
 
 #[inline]
-pub fn sink<W>(schema: &avro_rs::Schema, w: W, codec: avro_rs::Codec) -> error::Result<Sink<W>>
+pub fn sink<W>(schema: &avro_rs::Schema, w: W, codec: avro_rs::Codec) -> error::Result<Sink<'_, W>>
+where
+    W: io::Write,
+{
+    Ok(Sink {
+        schema,
+        writer: w,
+        codec,
+        marker: random_marker(),
+        has_header: false,
+        buffer: Vec::with_capacity(SYNC_INTERVAL),
+        num_values: 0,
+    })
+}
+
+/// Creates a sink that appends to an object container file with the specified header, which
+/// should have been read from the file using `read_header`.  The file must use the same schema.
+#[inline]
+pub fn sink_append<W>(schema: &avro_rs::Schema, w: W, header: Header) -> error::Result<Sink<'_, W>>
+where
+    W: io::Write,
+{
+    if header.schema != *schema {
+        return Err(error::Error::Avro(error::Avro::Custom {
+            message: "cannot append to a file that was written with a different schema".to_owned(),
+        }));
+    }
+    Ok(Sink {
+        schema,
+        writer: w,
+        codec: header.codec,
+        marker: header.marker,
+        has_header: true,
+        buffer: Vec::with_capacity(SYNC_INTERVAL),
+        num_values: 0,
+    })
+}
+
+/// Generates a sync marker, which only needs to be unlikely to occur in the data.
+fn random_marker() -> [u8; 16] {
+    use std::collections::hash_map;
+    use std::hash::BuildHasher;
+
+    // `RandomState` is seeded randomly, which is good enough for this purpose
+    let mut marker = [0; 16];
+    for (i, chunk) in marker.chunks_mut(8).enumerate() {
+        let random = hash_map::RandomState::new().hash_one(i);
+        chunk.copy_from_slice(&random.to_le_bytes());
+    }
+    marker
+}
+
+/// Reads the header of an existing object container file.
+pub fn read_header<R>(mut r: R) -> error::Result<Header>
+where
+    R: io::Read,
+{
+    use std::str::FromStr;
+
+    let invalid = |message: &str| {
+        error::Error::Avro(error::Avro::Custom {
+            message: format!("invalid object container file header: {}", message),
+        })
+    };
+
+    let mut magic = [0; 4];
+    r.read_exact(&mut magic)?;
+    if magic != MAGIC {
+        return Err(invalid("bad magic bytes"));
+    }
+
+    let mut schema = None;
+    let mut codec = avro_rs::Codec::Null;
+    loop {
+        let mut count = read_long(&mut r)?;
+        if count == 0 {
+            break;
+        } else if count < 0 {
+            // A negative count is followed by the size of the block in bytes
+            read_long(&mut r)?;
+            count = -count;
+        }
+        for _ in 0..count {
+            let key = read_bytes(&mut r)?;
+            let value = read_bytes(&mut r)?;
+            match key.as_slice() {
+                b"avro.schema" => {
+                    let json = String::from_utf8(value)?;
+                    schema = Some(
+                        avro_rs::Schema::parse_str(&json)
+                            .map_err(|e| error::Error::Avro(error::Avro::downcast(e)))?,
+                    );
+                }
+                b"avro.codec" => {
+                    let name = String::from_utf8(value)?;
+                    codec = avro_rs::Codec::from_str(&name)
+                        .map_err(|e| error::Error::Avro(error::Avro::Decode(e)))?;
+                }
+                _ => (),
+            }
+        }
+    }
+
+    let mut marker = [0; 16];
+    r.read_exact(&mut marker)?;
+
+    Ok(Header {
+        schema: schema.ok_or_else(|| invalid("missing schema"))?,
+        codec,
+        marker,
+    })
+}
+
+fn read_long<R>(r: &mut R) -> error::Result<i64>
+where
+    R: io::Read,
+{
+    let mut result: u64 = 0;
+    let mut shift = 0;
+    loop {
+        let mut byte = [0];
+        r.read_exact(&mut byte)?;
+        if shift >= 64 {
+            return Err(error::Error::Avro(error::Avro::Custom {
+                message: "variable-length integer is too long".to_owned(),
+            }));
+        }
+        result |= u64::from(byte[0] & 0x7f) << shift;
+        shift += 7;
+        if byte[0] & 0x80 == 0 {
+            break;
+        }
+    }
+    Ok((result >> 1) as i64 ^ -((result & 1) as i64))
+}
+
+fn read_bytes<R>(r: &mut R) -> error::Result<Vec<u8>>
+where
+    R: io::Read,
+{
+    use std::io::Read;
+
+    let len = read_long(r)?;
+    if len < 0 {
+        return Err(error::Error::Avro(error::Avro::Custom {
+            message: "negative length in header".to_owned(),
+        }));
+    }
+    let mut bytes = Vec::new();
+    r.by_ref().take(len as u64).read_to_end(&mut bytes)?;
+    if bytes.len() as i64 != len {
+        return Err(error::Error::from(io::Error::from(
+            io::ErrorKind::UnexpectedEof,
+        )));
+    }
+    Ok(bytes)
+}
+
+fn write_long(buffer: &mut Vec<u8>, v: i64) {
+    let mut zigzag = ((v << 1) ^ (v >> 63)) as u64;
+    while zigzag >= 0x80 {
+        buffer.push((zigzag as u8) | 0x80);
+        zigzag >>= 7;
+    }
+    buffer.push(zigzag as u8);
+}
+
+impl<'a, W> Sink<'a, W>
 where
     W: io::Write,
 {
-    Ok(Sink(avro_rs::Writer::with_codec(schema, w, codec)))
+    fn write_header(&mut self) -> error::Result<()> {
+        let schema = serde_json::to_string(self.schema)?;
+        let codec: &[u8] = match self.codec {
+            avro_rs::Codec::Null => b"null",
+            avro_rs::Codec::Deflate => b"deflate",
+            avro_rs::Codec::Snappy => b"snappy",
+        };
+
+        let mut header = MAGIC.to_vec();
+        write_long(&mut header, 2);
+        for (key, value) in &[
+            (&b"avro.schema"[..], schema.as_bytes()),
+            (b"avro.codec", codec),
+        ] {
+            write_long(&mut header, key.len() as i64);
+            header.extend_from_slice(key);
+            write_long(&mut header, value.len() as i64);
+            header.extend_from_slice(value);
+        }
+        write_long(&mut header, 0);
+        header.extend_from_slice(&self.marker);
+
+        self.writer.write_all(&header)?;
+        self.has_header = true;
+        Ok(())
+    }
+
+    fn write_block(&mut self) -> error::Result<()> {
+        if !self.has_header {
+            self.write_header()?;
+        }
+        if self.num_values == 0 {
+            return Ok(());
+        }
+
+        self.codec
+            .compress(&mut self.buffer)
+            .map_err(|e| error::Error::Avro(error::Avro::downcast(e)))?;
+
+        let mut block = Vec::with_capacity(20);
+        write_long(&mut block, self.num_values as i64);
+        write_long(&mut block, self.buffer.len() as i64);
+        self.writer.write_all(&block)?;
+        self.writer.write_all(&self.buffer)?;
+        self.writer.write_all(&self.marker)?;
+
+        self.buffer.clear();
+        self.num_values = 0;
+        Ok(())
+    }
 }
 
 impl<'a, R> value::Source for Source<'a, R>
@@ -77,11 +316,29 @@ where
 {
     #[inline]
     fn write(&mut self, value: value::Value) -> error::Result<()> {
-        self.0
-            .append(value_to_avro(value)?)
+        let datum = avro_rs::to_avro_datum(self.schema, value_to_avro(value)?)
             .map_err(|e| error::Error::Avro(error::Avro::downcast(e)))?;
+        self.buffer.extend_from_slice(&datum);
+        self.num_values += 1;
+
+        if self.buffer.len() >= SYNC_INTERVAL {
+            self.write_block()?;
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn flush(&mut self) -> error::Result<()> {
+        self.write_block()?;
+        self.writer.flush()?;
         Ok(())
     }
+
+    #[inline]
+    fn finish(&mut self) -> error::Result<()> {
+        // Closes the last data block, and makes sure that even an empty file gets a header
+        self.write_block()
+    }
 }
 
 fn value_to_avro(value: value::Value) -> error::Result<avro_rs::types::Value> {
@@ -140,7 +397,10 @@ fn value_to_string(value: value::Value) -> error::Result<String> {
         value::Value::Char(v) => Ok(format!("{}", v)),
         value::Value::String(v) => Ok(v),
         x => Err(error::Error::Format {
-            msg: format!("Avro can only output string keys, got: {:?}", x),
+            msg: format!(
+                "Avro can only output string keys, got: {}",
+                x.summary(value::ERROR_SUMMARY_LEN)
+            ),
         }),
     }
 }
@@ -162,15 +422,3 @@ where
         f.debug_struct("AvroSink").finish()
     }
 }
-
-impl<'a, W> Drop for Sink<'a, W>
-where
-    W: io::Write,
-{
-    fn drop(&mut self) {
-        match self.0.flush() {
-            Ok(_) => (),
-            Err(error) => panic!("{}", error),
-        }
-    }
-}
@@ -0,0 +1,342 @@
+//! Bencode, the encoding of BitTorrent's `.torrent` files and tracker responses.
+//!
+//! Each top-level value is a record.  Byte strings that are valid UTF-8 become strings and the
+//! others bytes, like the `pieces` of a torrent, so that writing a torrent back keeps its info
+//! hash.  The sink writes the keys of dictionaries sorted as bencode requires, booleans as 0 or
+//! 1, and keys that aren't strings as their text, like `1`.  Bencode has no null or floats.
+
+use std::cmp;
+use std::io;
+
+use crate::error;
+use crate::value;
+
+/// How much of a byte string is allocated up front, which keeps a bogus length from allocating
+/// more than the input can fill.
+const MAX_PREALLOCATED: usize = 1 << 16;
+/// How deeply lists and dictionaries are nested at most.
+const MAX_DEPTH: usize = 128;
+
+#[derive(Debug)]
+pub struct Source<R>
+where
+    R: io::Read,
+{
+    input: io::BufReader<R>,
+    position: u64,
+}
+
+/// A bencode sink.  Each value is serialized into a buffer first, so that a record that fails to
+/// serialize leaves nothing behind in the output.
+#[derive(Debug)]
+pub struct Sink<W>(W, Vec<u8>)
+where
+    W: io::Write;
+
+#[inline]
+pub fn source<R>(r: R) -> Source<R>
+where
+    R: io::Read,
+{
+    Source {
+        input: io::BufReader::new(r),
+        position: 0,
+    }
+}
+
+#[inline]
+pub fn sink<W>(w: W) -> Sink<W>
+where
+    W: io::Write,
+{
+    Sink(w, Vec::new())
+}
+
+impl<R> value::Source for Source<R>
+where
+    R: io::Read,
+{
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        // Files often end with a newline after the value
+        loop {
+            match self.peek()? {
+                None => return Ok(None),
+                Some(b) if b.is_ascii_whitespace() => self.skip(),
+                Some(_) => break,
+            }
+        }
+        let start = self.position;
+        let v = self.value(0).map_err(|e| match e {
+            error::Error::Format { msg } => error::Error::Format {
+                msg: format!(
+                    "invalid bencode at byte {} of a value starting at byte {}: {}",
+                    self.position, start, msg
+                ),
+            },
+            e => e,
+        })?;
+        Ok(Some(v))
+    }
+}
+
+impl<R> Source<R>
+where
+    R: io::Read,
+{
+    fn peek(&mut self) -> error::Result<Option<u8>> {
+        use std::io::BufRead;
+
+        Ok(self.input.fill_buf()?.first().copied())
+    }
+
+    fn skip(&mut self) {
+        use std::io::BufRead;
+
+        self.input.consume(1);
+        self.position += 1;
+    }
+
+    fn byte(&mut self) -> error::Result<u8> {
+        let b = self.peek()?.ok_or_else(|| format_error("input ended"))?;
+        self.skip();
+        Ok(b)
+    }
+
+    fn value(&mut self, depth: usize) -> error::Result<value::Value> {
+        if depth >= MAX_DEPTH {
+            return Err(format_error(&format!(
+                "values are nested more than {} levels deep",
+                MAX_DEPTH
+            )));
+        }
+        match self.peek()?.ok_or_else(|| format_error("input ended"))? {
+            b'i' => {
+                self.skip();
+                let digits = self.digits(b'e')?;
+                if let Ok(v) = digits.parse() {
+                    Ok(value::Value::U64(v))
+                } else if let Ok(v) = digits.parse() {
+                    Ok(value::Value::I64(v))
+                } else {
+                    Err(format_error(&format!("invalid integer {:?}", digits)))
+                }
+            }
+            b'l' => {
+                self.skip();
+                let mut elements = Vec::new();
+                while self.peek()? != Some(b'e') {
+                    elements.push(self.value(depth + 1)?);
+                }
+                self.skip();
+                Ok(value::Value::Sequence(elements))
+            }
+            b'd' => {
+                self.skip();
+                let mut entries = Vec::new();
+                while self.peek()? != Some(b'e') {
+                    let key = match self.peek()? {
+                        Some(b'0'..=b'9') => self.string()?,
+                        None => return Err(format_error("input ended")),
+                        _ => return Err(format_error("dictionary keys must be strings")),
+                    };
+                    entries.push((key, self.value(depth + 1)?));
+                }
+                self.skip();
+                Ok(value::Value::Map(entries))
+            }
+            b'0'..=b'9' => self.string(),
+            b => Err(format_error(&format!("unexpected byte 0x{:02x}", b))),
+        }
+    }
+
+    /// Reads a byte string, which is its length and a colon and then its bytes.
+    fn string(&mut self) -> error::Result<value::Value> {
+        use std::io::Read;
+
+        let digits = self.digits(b':')?;
+        let len: u64 = digits
+            .parse()
+            .map_err(|_| format_error(&format!("invalid length {:?}", digits)))?;
+        let mut bytes = Vec::with_capacity(cmp::min(len, MAX_PREALLOCATED as u64) as usize);
+        (&mut self.input).take(len).read_to_end(&mut bytes)?;
+        self.position += bytes.len() as u64;
+        if bytes.len() as u64 != len {
+            return Err(format_error("input ended"));
+        }
+        Ok(match String::from_utf8(bytes) {
+            Ok(s) => value::Value::String(s),
+            Err(e) => value::Value::Bytes(e.into_bytes()),
+        })
+    }
+
+    /// Reads the digits of a number, with an optional minus sign, up to the terminator.
+    fn digits(&mut self, terminator: u8) -> error::Result<String> {
+        let mut digits = String::new();
+        loop {
+            match self.byte()? {
+                b if b == terminator => return Ok(digits),
+                b @ (b'0'..=b'9' | b'-') => digits.push(char::from(b)),
+                b => {
+                    return Err(format_error(&format!(
+                        "unexpected byte 0x{:02x} in a number",
+                        b
+                    )))
+                }
+            }
+        }
+    }
+}
+
+impl<W> value::Sink for Sink<W>
+where
+    W: io::Write,
+{
+    fn write(&mut self, v: value::Value) -> error::Result<()> {
+        self.1.clear();
+        encode(&mut self.1, v)?;
+        self.0.write_all(&self.1)?;
+        Ok(())
+    }
+
+    #[inline]
+    fn flush(&mut self) -> error::Result<()> {
+        self.0.flush()?;
+        Ok(())
+    }
+}
+
+fn encode(out: &mut Vec<u8>, v: value::Value) -> error::Result<()> {
+    match v {
+        value::Value::Bool(b) => out.extend_from_slice(if b { b"i1e" } else { b"i0e" }),
+        value::Value::I8(v) => integer(out, v),
+        value::Value::I16(v) => integer(out, v),
+        value::Value::I32(v) => integer(out, v),
+        value::Value::I64(v) => integer(out, v),
+        value::Value::U8(v) => integer(out, v),
+        value::Value::U16(v) => integer(out, v),
+        value::Value::U32(v) => integer(out, v),
+        value::Value::U64(v) => integer(out, v),
+        value::Value::Char(c) => string(out, c.encode_utf8(&mut [0; 4]).as_bytes()),
+        value::Value::String(s) => string(out, s.as_bytes()),
+        value::Value::Bytes(bytes) => string(out, &bytes),
+        value::Value::Sequence(elements) => {
+            out.push(b'l');
+            for element in elements {
+                encode(out, element)?;
+            }
+            out.push(b'e');
+        }
+        value::Value::Map(entries) => {
+            let mut entries: Vec<_> = entries
+                .into_iter()
+                .map(|(key, v)| {
+                    let key = match key {
+                        value::Value::String(s) => s.into_bytes(),
+                        value::Value::Bytes(bytes) => bytes,
+                        key => key.into_key_string().into_bytes(),
+                    };
+                    (key, v)
+                })
+                .collect();
+            entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+            out.push(b'd');
+            for (key, v) in entries {
+                string(out, &key);
+                encode(out, v)?;
+            }
+            out.push(b'e');
+        }
+        v @ (value::Value::Unit | value::Value::F32(_) | value::Value::F64(_)) => {
+            return Err(format_error(&format!(
+                "bencode has no null or floats, got: {}",
+                v.summary(value::ERROR_SUMMARY_LEN)
+            )))
+        }
+    }
+    Ok(())
+}
+
+fn integer<N>(out: &mut Vec<u8>, v: N)
+where
+    N: ToString,
+{
+    out.push(b'i');
+    out.extend_from_slice(v.to_string().as_bytes());
+    out.push(b'e');
+}
+
+fn string(out: &mut Vec<u8>, bytes: &[u8]) {
+    out.extend_from_slice(bytes.len().to_string().as_bytes());
+    out.push(b':');
+    out.extend_from_slice(bytes);
+}
+
+fn format_error(msg: &str) -> error::Error {
+    error::Error::Format {
+        msg: msg.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::value::Sink as _;
+    use crate::value::Source as _;
+
+    fn read(input: &[u8]) -> error::Result<Vec<value::Value>> {
+        let mut reader = source(input);
+        let mut records = Vec::new();
+        while let Some(record) = reader.read()? {
+            records.push(record);
+        }
+        Ok(records)
+    }
+
+    #[test]
+    fn test_depth() {
+        let nested = |depth: usize| format!("{}{}", "l".repeat(depth), "e".repeat(depth));
+        assert!(read(nested(128).as_bytes()).is_ok());
+        match read(nested(200_000).as_bytes()) {
+            Err(error::Error::Format { msg }) => assert!(msg.contains("nested"), "{}", msg),
+            other => panic!(
+                "expected an error for deeply nested bencode, got {:?}",
+                other
+            ),
+        }
+        assert!(read("d1:a".repeat(200_000).as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let torrent = b"d8:announce3:udp4:infod6:lengthi-1e6:pieces2:\xff\0ee\n";
+        let records = read(torrent).unwrap();
+        assert_eq!(
+            records.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            vec![r#"{"announce": "udp", "info": {"length": -1, "pieces": 0xff00}}"#]
+        );
+        assert!(read(b"d1:ai1e").is_err());
+        assert!(read(b"di1e1:ae").is_err());
+
+        let mut output = Vec::new();
+        {
+            let mut writer = sink(&mut output);
+            for record in records {
+                writer.write(record).unwrap();
+            }
+            writer.write(value!({"z": true, "a": ["x", 2]})).unwrap();
+            // Keys that aren't strings are written without quotes
+            writer
+                .write(value::Value::Map(vec![(
+                    value::Value::Char('k'),
+                    value!(1),
+                )]))
+                .unwrap();
+            assert!(writer.write(value!([1.5])).is_err());
+        }
+        assert_eq!(
+            output,
+            &b"d8:announce3:udp4:infod6:lengthi-1e6:pieces2:\xff\0eed1:al1:xi2ee1:zi1eed1:ki1ee"[..]
+        );
+    }
+}
@@ -0,0 +1,839 @@
+//! BSON documents, as written by `mongodump` and MongoDB change streams.
+//!
+//! Each document is a record.  Types that have no direct equivalent are represented like
+//! MongoDB's relaxed Extended JSON does, for example an ObjectId as `{"$oid": "..."}` and a
+//! DateTime as `{"$date": "2024-01-01T00:00:00Z"}`.  Binary data with the generic subtype
+//! becomes bytes, while other subtypes (like UUIDs) become `{"$binary": {"base64": "...",
+//! "subType": "04"}}`.  The sink turns such maps back into the BSON types.
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::io;
+use std::str;
+
+use base64::Engine;
+
+use crate::error;
+use crate::value;
+
+const DOUBLE: u8 = 0x01;
+const STRING: u8 = 0x02;
+const DOCUMENT: u8 = 0x03;
+const ARRAY: u8 = 0x04;
+const BINARY: u8 = 0x05;
+const UNDEFINED: u8 = 0x06;
+const OBJECT_ID: u8 = 0x07;
+const BOOLEAN: u8 = 0x08;
+const DATE_TIME: u8 = 0x09;
+const NULL: u8 = 0x0a;
+const REGEX: u8 = 0x0b;
+const DB_POINTER: u8 = 0x0c;
+const CODE: u8 = 0x0d;
+const SYMBOL: u8 = 0x0e;
+const CODE_WITH_SCOPE: u8 = 0x0f;
+const INT32: u8 = 0x10;
+const TIMESTAMP: u8 = 0x11;
+const INT64: u8 = 0x12;
+const DECIMAL128: u8 = 0x13;
+const MIN_KEY: u8 = 0xff;
+const MAX_KEY: u8 = 0x7f;
+
+/// The binary subtype of plain bytes.
+const GENERIC: u8 = 0x00;
+
+/// How deeply documents and arrays are nested at most.
+const MAX_DEPTH: usize = 128;
+
+pub struct Source<R>(R, Vec<u8>)
+where
+    R: io::Read;
+
+/// A BSON sink.  Each document is serialized into a buffer first, so that a record that fails to
+/// serialize leaves nothing behind in the output.
+pub struct Sink<W>(W, Vec<u8>)
+where
+    W: io::Write;
+
+#[inline]
+pub fn source<R>(r: R) -> Source<R>
+where
+    R: io::Read,
+{
+    Source(r, Vec::new())
+}
+
+#[inline]
+pub fn sink<W>(w: W) -> Sink<W>
+where
+    W: io::Write,
+{
+    Sink(w, Vec::new())
+}
+
+impl<R> value::Source for Source<R>
+where
+    R: io::Read,
+{
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        use std::io::Read;
+
+        // The input may only end before the length of a document
+        let mut len = [0; 4];
+        let mut read = 0;
+        while read < len.len() {
+            match self.0.read(&mut len[read..]) {
+                Ok(0) if read == 0 => return Ok(None),
+                Ok(0) => return Err(format_error("input ended in the length of a document")),
+                Ok(n) => read += n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => return Err(error::Error::from(e)),
+            }
+        }
+        let len = i32::from_le_bytes(len);
+        if len < 5 {
+            return Err(format_error("invalid document length"));
+        }
+
+        self.1.clear();
+        self.1.extend_from_slice(&len.to_le_bytes());
+        (&mut self.0)
+            .take(len as u64 - 4)
+            .read_to_end(&mut self.1)?;
+        if self.1.len() != len as usize {
+            return Err(format_error("input ended in a document"));
+        }
+
+        let mut decoder = Decoder {
+            input: &self.1,
+            position: 0,
+            depth: 0,
+        };
+        match decoder.document() {
+            Ok(document) => Ok(Some(document)),
+            Err(msg) => Err(error::Error::Format {
+                msg: format!(
+                    "invalid BSON at byte {} of a document: {}",
+                    decoder.position, msg
+                ),
+            }),
+        }
+    }
+}
+
+fn format_error(msg: &str) -> error::Error {
+    error::Error::Format {
+        msg: format!("invalid BSON: {}", msg),
+    }
+}
+
+struct Decoder<'a> {
+    input: &'a [u8],
+    position: usize,
+    /// How many documents the decoder is inside of.
+    depth: usize,
+}
+
+impl<'a> Decoder<'a> {
+    /// Decodes a document, or an array if the keys are left out.
+    fn elements(&mut self) -> Result<Vec<(String, value::Value)>, &'static str> {
+        if self.depth >= MAX_DEPTH {
+            return Err("documents are nested too deeply");
+        }
+        let len = self.i32()?;
+        let end = usize::try_from(len)
+            .ok()
+            .and_then(|len| (self.position - 4).checked_add(len))
+            .filter(|&end| end <= self.input.len())
+            .ok_or("invalid document length")?;
+
+        let mut elements = Vec::new();
+        self.depth += 1;
+        loop {
+            let element_type = self.byte()?;
+            if element_type == 0 {
+                break;
+            }
+            let key = self.cstring()?;
+            elements.push((key, self.element(element_type)?));
+        }
+        self.depth -= 1;
+        if self.position != end {
+            return Err("document length doesn't match its contents");
+        }
+        Ok(elements)
+    }
+
+    fn document(&mut self) -> Result<value::Value, &'static str> {
+        Ok(value::Value::Map(
+            self.elements()?
+                .into_iter()
+                .map(|(k, v)| (value::Value::String(k), v))
+                .collect(),
+        ))
+    }
+
+    fn element(&mut self, element_type: u8) -> Result<value::Value, &'static str> {
+        let v = match element_type {
+            DOUBLE => value::Value::from_f64(f64::from_le_bytes(self.fixed()?)),
+            STRING => value::Value::String(self.string()?),
+            DOCUMENT => self.document()?,
+            ARRAY => value::Value::Sequence(self.elements()?.into_iter().map(|(_, v)| v).collect()),
+            BINARY => {
+                let len = usize::try_from(self.i32()?).map_err(|_| "invalid binary length")?;
+                let subtype = self.byte()?;
+                let mut bytes = self.take(len)?;
+                // The old binary subtype repeats the length
+                if subtype == 0x02 && bytes.len() >= 4 {
+                    bytes = &bytes[4..];
+                }
+                if subtype == GENERIC {
+                    value::Value::Bytes(bytes.to_vec())
+                } else {
+                    extended(
+                        "$binary",
+                        map(vec![
+                            (
+                                "base64",
+                                base64::engine::general_purpose::STANDARD
+                                    .encode(bytes)
+                                    .into(),
+                            ),
+                            ("subType", format!("{:02x}", subtype).into()),
+                        ]),
+                    )
+                }
+            }
+            UNDEFINED => value::Value::Unit,
+            OBJECT_ID => extended("$oid", hex(self.take(12)?).into()),
+            BOOLEAN => match self.byte()? {
+                0 => value::Value::Bool(false),
+                1 => value::Value::Bool(true),
+                _ => return Err("invalid boolean"),
+            },
+            DATE_TIME => extended("$date", date_time(i64::from_le_bytes(self.fixed()?))),
+            NULL => value::Value::Unit,
+            REGEX => {
+                let pattern = self.cstring()?;
+                let options = self.cstring()?;
+                extended(
+                    "$regularExpression",
+                    map(vec![
+                        ("pattern", pattern.into()),
+                        ("options", options.into()),
+                    ]),
+                )
+            }
+            DB_POINTER => {
+                let namespace = self.string()?;
+                let id = hex(self.take(12)?);
+                extended(
+                    "$dbPointer",
+                    map(vec![
+                        ("$ref", namespace.into()),
+                        ("$id", extended("$oid", id.into())),
+                    ]),
+                )
+            }
+            CODE => extended("$code", self.string()?.into()),
+            SYMBOL => extended("$symbol", self.string()?.into()),
+            CODE_WITH_SCOPE => {
+                self.i32()?;
+                let code = self.string()?;
+                let scope = self.document()?;
+                map(vec![("$code", code.into()), ("$scope", scope)])
+            }
+            INT32 => value::Value::I32(self.i32()?),
+            TIMESTAMP => {
+                let increment = u32::from_le_bytes(self.fixed()?);
+                let time = u32::from_le_bytes(self.fixed()?);
+                extended(
+                    "$timestamp",
+                    map(vec![
+                        ("t", value::Value::U32(time)),
+                        ("i", value::Value::U32(increment)),
+                    ]),
+                )
+            }
+            INT64 => value::Value::I64(i64::from_le_bytes(self.fixed()?)),
+            DECIMAL128 => extended(
+                "$numberDecimal",
+                decimal128_to_string(u128::from_le_bytes(self.fixed()?)).into(),
+            ),
+            MIN_KEY => extended("$minKey", value::Value::I32(1)),
+            MAX_KEY => extended("$maxKey", value::Value::I32(1)),
+            _ => return Err("unknown element type"),
+        };
+        Ok(v)
+    }
+
+    fn string(&mut self) -> Result<String, &'static str> {
+        let len = usize::try_from(self.i32()?).map_err(|_| "invalid string length")?;
+        match self.take(len)? {
+            [s @ .., 0] => str::from_utf8(s)
+                .map(str::to_owned)
+                .map_err(|_| "string is not UTF-8"),
+            _ => Err("string is not terminated"),
+        }
+    }
+
+    fn cstring(&mut self) -> Result<String, &'static str> {
+        let rest = &self.input[self.position..];
+        let len = rest
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or("input ended in a key")?;
+        let s = str::from_utf8(&rest[..len]).map_err(|_| "key is not UTF-8")?;
+        self.position += len + 1;
+        Ok(s.to_owned())
+    }
+
+    fn byte(&mut self) -> Result<u8, &'static str> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn i32(&mut self) -> Result<i32, &'static str> {
+        Ok(i32::from_le_bytes(self.fixed()?))
+    }
+
+    fn fixed<const N: usize>(&mut self) -> Result<[u8; N], &'static str> {
+        let mut result = [0; N];
+        result.copy_from_slice(self.take(N)?);
+        Ok(result)
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], &'static str> {
+        let end = self
+            .position
+            .checked_add(len)
+            .filter(|&end| end <= self.input.len())
+            .ok_or("input ended in an element")?;
+        let bytes = &self.input[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+}
+
+fn map(entries: Vec<(&str, value::Value)>) -> value::Value {
+    value::Value::Map(entries.into_iter().map(|(k, v)| (k.into(), v)).collect())
+}
+
+fn extended(key: &str, v: value::Value) -> value::Value {
+    map(vec![(key, v)])
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Formats milliseconds since the Unix epoch like relaxed Extended JSON does: as an RFC 3339
+/// string for the years 1970 to 9999, and as `{"$numberLong": "..."}` otherwise.
+fn date_time(millis: i64) -> value::Value {
+    const MAX: i64 = 253_402_300_799_999; // 9999-12-31T23:59:59.999Z
+    if !(0..=MAX).contains(&millis) {
+        return extended("$numberLong", millis.to_string().into());
+    }
+
+    let (days, millis) = (millis / 86_400_000, millis % 86_400_000);
+    let (year, month, day) = civil_from_days(days);
+    let (seconds, millis) = (millis / 1000, millis % 1000);
+    let fraction = if millis == 0 {
+        String::new()
+    } else {
+        format!(".{:03}", millis)
+    };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}{}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60,
+        fraction
+    )
+    .into()
+}
+
+/// The date in the proleptic Gregorian calendar of a number of days since 1970-01-01.
+pub(crate) fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+const DECIMAL128_BIAS: i32 = 6176;
+const DECIMAL128_MAX_EXPONENT: i32 = 6111;
+const DECIMAL128_MAX_COEFFICIENT: u128 = 9_999_999_999_999_999_999_999_999_999_999;
+
+/// Formats an IEEE 754 decimal128 number the way the BSON specification describes.
+fn decimal128_to_string(bits: u128) -> String {
+    let sign = if bits >> 127 == 1 { "-" } else { "" };
+    let (exponent, coefficient) = if (bits >> 125) & 0b11 == 0b11 {
+        match (bits >> 122) & 0b11111 {
+            0b11111 => return "NaN".to_owned(),
+            0b11110 => return format!("{}Infinity", sign),
+            // The coefficient would be too large, so it counts as zero
+            _ => ((bits >> 111) & 0x3fff, 0),
+        }
+    } else {
+        ((bits >> 113) & 0x3fff, bits & ((1 << 113) - 1))
+    };
+    let exponent = exponent as i32 - DECIMAL128_BIAS;
+    let coefficient = if coefficient > DECIMAL128_MAX_COEFFICIENT {
+        0
+    } else {
+        coefficient
+    };
+
+    let digits = coefficient.to_string();
+    let adjusted = exponent + digits.len() as i32 - 1;
+    if exponent <= 0 && adjusted >= -6 {
+        let point = digits.len() as i32 + exponent;
+        if exponent == 0 {
+            format!("{}{}", sign, digits)
+        } else if point <= 0 {
+            format!("{}0.{}{}", sign, "0".repeat(-point as usize), digits)
+        } else {
+            let (whole, fraction) = digits.split_at(point as usize);
+            format!("{}{}.{}", sign, whole, fraction)
+        }
+    } else {
+        let (first, rest) = digits.split_at(1);
+        let rest = if rest.is_empty() {
+            String::new()
+        } else {
+            format!(".{}", rest)
+        };
+        format!("{}{}{}E{:+}", sign, first, rest, adjusted)
+    }
+}
+
+/// Parses a decimal number into an IEEE 754 decimal128 number, without rounding.
+fn decimal128_from_str(s: &str) -> Option<u128> {
+    let (negative, unsigned) = match s.as_bytes().first()? {
+        b'-' => (true, &s[1..]),
+        b'+' => (false, &s[1..]),
+        _ => (false, s),
+    };
+    let sign = u128::from(negative) << 127;
+    let lower = unsigned.to_ascii_lowercase();
+    match lower.as_str() {
+        "nan" => return Some(0x7c << 120),
+        "inf" | "infinity" => return Some(sign | 0x78 << 120),
+        _ => (),
+    }
+
+    let (mantissa, exponent) = match lower.split_once('e') {
+        Some((mantissa, exponent)) => (mantissa, exponent.parse::<i32>().ok()?),
+        None => (lower.as_str(), 0),
+    };
+    let (whole, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    if whole.is_empty() && fraction.is_empty()
+        || !whole
+            .bytes()
+            .chain(fraction.bytes())
+            .all(|b| b.is_ascii_digit())
+    {
+        return None;
+    }
+    let digits = format!("{}{}", whole, fraction);
+    let mut digits = digits.trim_start_matches('0');
+    let mut exponent = exponent.checked_sub(fraction.len() as i32)?;
+    while digits.len() > 34 && digits.ends_with('0') {
+        digits = &digits[..digits.len() - 1];
+        exponent = exponent.checked_add(1)?;
+    }
+    if digits.len() > 34 || !(-DECIMAL128_BIAS..=DECIMAL128_MAX_EXPONENT).contains(&exponent) {
+        return None;
+    }
+    let coefficient = if digits.is_empty() {
+        0
+    } else {
+        digits.parse::<u128>().ok()?
+    };
+    Some(sign | ((exponent + DECIMAL128_BIAS) as u128) << 113 | coefficient)
+}
+
+/// Encodes records into a buffer.
+struct Encoder<'a>(&'a mut Vec<u8>);
+
+impl<'a> Encoder<'a> {
+    fn document<I>(&mut self, elements: I) -> error::Result<()>
+    where
+        I: IntoIterator<Item = (String, value::Value)>,
+    {
+        let start = self.0.len();
+        self.0.extend_from_slice(&[0; 4]);
+        for (key, v) in elements {
+            self.element(&key, v)?;
+        }
+        self.0.push(0);
+        let len = i32::try_from(self.0.len() - start).map_err(|_| error::Error::Format {
+            msg: "BSON documents can't be larger than 2 GiB".to_owned(),
+        })?;
+        self.0[start..start + 4].copy_from_slice(&len.to_le_bytes());
+        Ok(())
+    }
+
+    fn element(&mut self, key: &str, v: value::Value) -> error::Result<()> {
+        let type_position = self.0.len();
+        self.0.push(0);
+        self.cstring(key)?;
+        let element_type = match v {
+            value::Value::Unit => NULL,
+            value::Value::Bool(v) => {
+                self.0.push(u8::from(v));
+                BOOLEAN
+            }
+            value::Value::I8(v) => self.int32(i32::from(v)),
+            value::Value::I16(v) => self.int32(i32::from(v)),
+            value::Value::I32(v) => self.int32(v),
+            value::Value::U8(v) => self.int32(i32::from(v)),
+            value::Value::U16(v) => self.int32(i32::from(v)),
+            value::Value::U32(v) => self.int64(i64::from(v)),
+            value::Value::I64(v) => self.int64(v),
+            value::Value::U64(v) => match i64::try_from(v) {
+                Ok(v) => self.int64(v),
+                Err(_) => {
+                    return Err(error::Error::Format {
+                        msg: format!("BSON can't represent the integer {}", v),
+                    })
+                }
+            },
+            value::Value::F32(v) => self.double(f64::from(v.into_inner())),
+            value::Value::F64(v) => self.double(v.into_inner()),
+            value::Value::Char(v) => self.string(&v.to_string()),
+            value::Value::String(ref v) => self.string(v),
+            value::Value::Bytes(ref bytes) => self.binary(GENERIC, bytes)?,
+            value::Value::Sequence(values) => {
+                self.document(
+                    values
+                        .into_iter()
+                        .enumerate()
+                        .map(|(i, v)| (i.to_string(), v)),
+                )?;
+                ARRAY
+            }
+            value::Value::Map(entries) => match self.extended(&entries)? {
+                Some(element_type) => element_type,
+                None => {
+                    self.document(entries.into_iter().map(|(k, v)| (k.into_key_string(), v)))?;
+                    DOCUMENT
+                }
+            },
+        };
+        self.0[type_position] = element_type;
+        Ok(())
+    }
+
+    /// Writes the BSON value that a map like `{"$oid": "..."}` stands for, if it is one, and
+    /// returns its element type.
+    fn extended(&mut self, entries: &[(value::Value, value::Value)]) -> error::Result<Option<u8>> {
+        let field = |v: &value::Value, name: &str| v.get(name).cloned();
+        let element_type = match entries {
+            [(k, v)] => match (k.as_str(), v) {
+                (Some("$oid"), value::Value::String(id)) => match parse_hex(id) {
+                    Some(bytes) if bytes.len() == 12 => {
+                        self.0.extend_from_slice(&bytes);
+                        OBJECT_ID
+                    }
+                    _ => return Ok(None),
+                },
+                (Some("$date"), v) => match parse_date_time(v) {
+                    Some(millis) => {
+                        self.0.extend_from_slice(&millis.to_le_bytes());
+                        DATE_TIME
+                    }
+                    None => return Ok(None),
+                },
+                (Some("$binary"), v) => {
+                    let bytes = field(v, "base64").and_then(|b| {
+                        base64::engine::general_purpose::STANDARD
+                            .decode(b.as_str()?)
+                            .ok()
+                    });
+                    let subtype =
+                        field(v, "subType").and_then(|s| u8::from_str_radix(s.as_str()?, 16).ok());
+                    match (bytes, subtype) {
+                        (Some(bytes), Some(subtype)) => self.binary(subtype, &bytes)?,
+                        _ => return Ok(None),
+                    }
+                }
+                (Some("$timestamp"), v) => {
+                    let part = |name| {
+                        field(v, name)
+                            .and_then(|n| n.as_u64())
+                            .and_then(|n| u32::try_from(n).ok())
+                    };
+                    match (part("t"), part("i")) {
+                        (Some(time), Some(increment)) => {
+                            self.0.extend_from_slice(&increment.to_le_bytes());
+                            self.0.extend_from_slice(&time.to_le_bytes());
+                            TIMESTAMP
+                        }
+                        _ => return Ok(None),
+                    }
+                }
+                (Some("$regularExpression"), v) => {
+                    let part = |name| field(v, name).and_then(|s| s.as_str().map(str::to_owned));
+                    match (part("pattern"), part("options")) {
+                        (Some(pattern), Some(options)) => {
+                            self.cstring(&pattern)?;
+                            self.cstring(&options)?;
+                            REGEX
+                        }
+                        _ => return Ok(None),
+                    }
+                }
+                (Some("$numberDecimal"), value::Value::String(s)) => match decimal128_from_str(s) {
+                    Some(bits) => {
+                        self.0.extend_from_slice(&bits.to_le_bytes());
+                        DECIMAL128
+                    }
+                    None => return Ok(None),
+                },
+                (Some("$numberLong"), value::Value::String(s)) => match s.parse() {
+                    Ok(n) => self.int64(n),
+                    Err(_) => return Ok(None),
+                },
+                (Some("$numberInt"), value::Value::String(s)) => match s.parse() {
+                    Ok(n) => self.int32(n),
+                    Err(_) => return Ok(None),
+                },
+                (Some("$numberDouble"), value::Value::String(s)) => match s.parse() {
+                    Ok(n) => self.double(n),
+                    Err(_) => return Ok(None),
+                },
+                (Some("$symbol"), value::Value::String(s)) => {
+                    self.string(s);
+                    SYMBOL
+                }
+                (Some("$code"), value::Value::String(s)) => {
+                    self.string(s);
+                    CODE
+                }
+                (Some("$dbPointer"), v) => {
+                    let namespace = field(v, "$ref").and_then(|s| s.as_str().map(str::to_owned));
+                    let id = field(v, "$id.$oid").and_then(|s| parse_hex(s.as_str()?));
+                    match (namespace, id) {
+                        (Some(namespace), Some(id)) if id.len() == 12 => {
+                            self.string(&namespace);
+                            self.0.extend_from_slice(&id);
+                            DB_POINTER
+                        }
+                        _ => return Ok(None),
+                    }
+                }
+                (Some("$minKey"), _) => MIN_KEY,
+                (Some("$maxKey"), _) => MAX_KEY,
+                (Some("$undefined"), value::Value::Bool(true)) => UNDEFINED,
+                _ => return Ok(None),
+            },
+            [(k1, value::Value::String(code)), (k2, value::Value::Map(scope))]
+                if k1.as_str() == Some("$code") && k2.as_str() == Some("$scope") =>
+            {
+                let start = self.0.len();
+                self.0.extend_from_slice(&[0; 4]);
+                self.string(code);
+                self.document(
+                    scope
+                        .iter()
+                        .map(|(k, v)| (k.clone().into_key_string(), v.clone())),
+                )?;
+                let len = (self.0.len() - start) as i32;
+                self.0[start..start + 4].copy_from_slice(&len.to_le_bytes());
+                CODE_WITH_SCOPE
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(element_type))
+    }
+
+    fn int32(&mut self, v: i32) -> u8 {
+        self.0.extend_from_slice(&v.to_le_bytes());
+        INT32
+    }
+
+    fn int64(&mut self, v: i64) -> u8 {
+        self.0.extend_from_slice(&v.to_le_bytes());
+        INT64
+    }
+
+    fn double(&mut self, v: f64) -> u8 {
+        self.0.extend_from_slice(&v.to_le_bytes());
+        DOUBLE
+    }
+
+    fn string(&mut self, s: &str) -> u8 {
+        self.0
+            .extend_from_slice(&(s.len() as i32 + 1).to_le_bytes());
+        self.0.extend_from_slice(s.as_bytes());
+        self.0.push(0);
+        STRING
+    }
+
+    fn binary(&mut self, subtype: u8, bytes: &[u8]) -> error::Result<u8> {
+        let len = i32::try_from(bytes.len()).map_err(|_| error::Error::Format {
+            msg: "BSON binary data can't be larger than 2 GiB".to_owned(),
+        })?;
+        self.0.extend_from_slice(&len.to_le_bytes());
+        self.0.push(subtype);
+        self.0.extend_from_slice(bytes);
+        Ok(BINARY)
+    }
+
+    fn cstring(&mut self, s: &str) -> error::Result<()> {
+        if s.contains('\0') {
+            return Err(error::Error::Format {
+                msg: format!("BSON keys and patterns can't contain NUL: {:?}", s),
+            });
+        }
+        self.0.extend_from_slice(s.as_bytes());
+        self.0.push(0);
+        Ok(())
+    }
+}
+
+fn parse_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) || !s.is_ascii() {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok())
+        .collect()
+}
+
+/// Reads milliseconds since the Unix epoch from the value of `$date`, which is an RFC 3339
+/// string, `{"$numberLong": "..."}`, or a number in the legacy format.
+fn parse_date_time(v: &value::Value) -> Option<i64> {
+    match v {
+        value::Value::String(s) => crate::transform::look_behind::parse_rfc3339(s)
+            .map(|seconds| (seconds * 1000.0).round() as i64),
+        value::Value::Map(_) => v.get("$numberLong")?.as_str()?.parse().ok(),
+        v => v.as_i64(),
+    }
+}
+
+impl<W> value::Sink for Sink<W>
+where
+    W: io::Write,
+{
+    fn write(&mut self, v: value::Value) -> error::Result<()> {
+        let entries = match v {
+            value::Value::Map(entries) => entries,
+            v => {
+                return Err(error::Error::Format {
+                    msg: format!(
+                        "BSON can only output maps, got: {}",
+                        v.summary(value::ERROR_SUMMARY_LEN)
+                    ),
+                })
+            }
+        };
+        self.1.clear();
+        Encoder(&mut self.1)
+            .document(entries.into_iter().map(|(k, v)| (k.into_key_string(), v)))?;
+        self.0.write_all(&self.1)?;
+        Ok(())
+    }
+
+    #[inline]
+    fn flush(&mut self) -> error::Result<()> {
+        self.0.flush()?;
+        Ok(())
+    }
+}
+
+impl<R> fmt::Debug for Source<R>
+where
+    R: io::Read,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BsonSource").finish()
+    }
+}
+
+impl<W> fmt::Debug for Sink<W>
+where
+    W: io::Write,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BsonSink").finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::value::Sink as _;
+    use crate::value::Source as _;
+
+    /// Documents nested in each other under the key `a`.
+    fn nested(depth: usize) -> Vec<u8> {
+        let mut document = Vec::new();
+        for level in (1..depth).rev() {
+            // Each level adds its length, its element's type and key, and its terminator
+            document.extend_from_slice(&((5 + 8 * level) as i32).to_le_bytes());
+            document.extend_from_slice(&[DOCUMENT, b'a', 0]);
+        }
+        document.extend_from_slice(&5i32.to_le_bytes());
+        document.resize(document.len() + depth, 0);
+        document
+    }
+
+    #[test]
+    fn test_depth() {
+        assert!(source(&nested(128)[..]).read().is_ok());
+        for depth in &[129, 200_000] {
+            match source(&nested(*depth)[..]).read() {
+                Err(error::Error::Format { msg }) => assert!(msg.contains("nested"), "{}", msg),
+                other => panic!("expected an error for deeply nested BSON, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let hello = b"\x16\x00\x00\x00\x02hello\x00\x06\x00\x00\x00world\x00\x00";
+        let mut reader = source(&hello[..]);
+        assert_eq!(reader.read().unwrap(), Some(value!({"hello": "world"})));
+        assert_eq!(reader.read().unwrap(), None);
+
+        let records = vec![
+            value!({
+                "_id": {"$oid": "65a1b2c3d4e5f60718293a4b"},
+                "created": {"$date": "2024-01-12T09:30:00.250Z"},
+                "key": {"$binary": {"base64": "AAAAAAAAAAAAAAAAAAAAAA==", "subType": "04"}},
+                "price": {"$numberDecimal": "1.50"},
+                "tiny": {"$numberDecimal": "1E-40"},
+                "tags": ["a", null, 2.5],
+            }),
+            value::Value::Map(vec![("avatar".into(), value::Value::Bytes(vec![1, 2]))]),
+        ];
+        let mut output = Vec::new();
+        {
+            let mut writer = sink(&mut output);
+            for record in records.clone() {
+                writer.write(record).unwrap();
+            }
+            assert!(writer.write(value!([1])).is_err());
+        }
+        let mut reader = source(&output[..]);
+        for record in records {
+            let read = reader.read().unwrap().unwrap();
+            assert_eq!(read.to_string(), record.to_string());
+        }
+        assert_eq!(reader.read().unwrap(), None);
+        assert!(source(&output[..10]).read().is_err());
+    }
+}
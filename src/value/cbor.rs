@@ -10,7 +10,9 @@ pub struct Source<R>(serde_cbor::de::Deserializer<serde_cbor::de::IoRead<R>>)
 where
     R: io::Read;
 
-pub struct Sink<W>(serde_cbor::ser::Serializer<serde_cbor::ser::IoWrite<W>>)
+/// A CBOR sink.  Each record is serialized into a buffer first, so that a record that fails to
+/// serialize leaves nothing behind in the output.
+pub struct Sink<W>(W, Vec<u8>)
 where
     W: io::Write;
 
@@ -29,9 +31,7 @@ pub fn sink<W>(w: W) -> Sink<W>
 where
     W: io::Write,
 {
-    Sink(serde_cbor::ser::Serializer::new(
-        serde_cbor::ser::IoWrite::new(w),
-    ))
+    Sink(w, Vec::new())
 }
 
 impl<R> value::Source for Source<R>
@@ -56,7 +56,20 @@ where
 {
     #[inline]
     fn write(&mut self, v: value::Value) -> error::Result<()> {
-        serde::Serialize::serialize(&v, &mut self.0).map_err(From::from)
+        self.1.clear();
+        {
+            let mut serializer =
+                serde_cbor::ser::Serializer::new(serde_cbor::ser::IoWrite::new(&mut self.1));
+            serde::Serialize::serialize(&v, &mut serializer)?;
+        }
+        self.0.write_all(&self.1)?;
+        Ok(())
+    }
+
+    #[inline]
+    fn flush(&mut self) -> error::Result<()> {
+        self.0.flush()?;
+        Ok(())
     }
 }
 
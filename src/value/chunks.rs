@@ -0,0 +1,783 @@
+//! Chunk-structured binary files: RIFF files like WAV, AVI and WebP, and Standard MIDI Files.
+//!
+//! Each top-level chunk becomes a record with its four-character `id`, the `offset` of the chunk
+//! in the file and the `size` of its data.  `RIFF` and `LIST` chunks also have their `form`, like
+//! `WAVE` or `INFO`, and the `chunks` in them, and chunks of well-known types have a `header`
+//! with their parsed contents:
+//!
+//! * `fmt ` chunks of WAV files and `strf` chunks of AVI audio streams have the sample format,
+//!   channels, sample rate and so on, and `data` chunks of WAV files have the number of `frames`
+//!   and the `seconds` they last.
+//! * `avih`, `strh` and `strf` chunks of AVI files have the main and stream headers.
+//! * Chunks of `INFO` lists have their `text`, like the title in `INAM`.
+//! * `MThd` chunks of MIDI files have the format, number of tracks and timing, and `MTrk` chunks
+//!   have the name, tempos, time and key signatures, markers, channels and notes of the track.
+//!
+//! The contents of other chunks, like samples, are skipped without being kept in memory, and the
+//! chunks in `movi` lists, which are the frames of AVI files, are not listed.
+
+use std::convert::TryFrom;
+use std::io;
+
+use crate::error;
+use crate::value;
+
+/// Chunks that are larger than this are not headers, and their contents aren't parsed.
+const MAX_HEADER_SIZE: u64 = 1 << 20;
+
+/// The size of RIFF chunks whose size is unknown or in a `ds64` chunk.
+const UNKNOWN_SIZE: u64 = 0xffff_ffff;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Kind {
+    Riff,
+    Midi,
+}
+
+#[derive(Debug)]
+pub struct Source<R> {
+    r: R,
+    kind: Kind,
+    offset: u64,
+    records: u64,
+    /// Whether sizes are big-endian, as they are in RIFX and MIDI files.
+    big_endian: bool,
+    /// The RIFF and data sizes of the `ds64` chunk of RF64 files.
+    ds64: Option<(u64, u64)>,
+    /// The block size and sample rate of the last WAV format.
+    wave_format: Option<(u64, u64)>,
+    /// The type of the last AVI stream header, like `vids` or `auds`.
+    stream_type: Option<String>,
+}
+
+/// Creates a source for the chunks of a RIFF file, like a WAV or AVI file.
+pub fn riff<R>(r: R) -> Source<R>
+where
+    R: io::Read,
+{
+    source(r, Kind::Riff)
+}
+
+/// Creates a source for the chunks of a Standard MIDI File.
+pub fn midi<R>(r: R) -> Source<R>
+where
+    R: io::Read,
+{
+    source(r, Kind::Midi)
+}
+
+fn source<R>(r: R, kind: Kind) -> Source<R>
+where
+    R: io::Read,
+{
+    Source {
+        r,
+        kind,
+        offset: 0,
+        records: 0,
+        big_endian: kind == Kind::Midi,
+        ds64: None,
+        wave_format: None,
+        stream_type: None,
+    }
+}
+
+impl<R> value::Source for Source<R>
+where
+    R: io::Read,
+{
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        let offset = self.offset;
+        let (id, size) = match self.chunk_header()? {
+            Some(header) => header,
+            None if self.records == 0 => return Err(self.error("the input is empty")),
+            None => return Ok(None),
+        };
+        if self.records == 0 {
+            match (self.kind, id.as_str()) {
+                (Kind::Riff, "RIFF") | (Kind::Riff, "RF64") | (Kind::Midi, "MThd") => (),
+                (Kind::Riff, "RIFX") => self.big_endian = true,
+                (Kind::Riff, _) => return Err(self.error("not a RIFF file")),
+                (Kind::Midi, _) => return Err(self.error("not a MIDI file")),
+            }
+        }
+        let size = self.decode_size(&id, size);
+        let (record, _) = self.chunk(id, size, offset, "")?;
+        self.records += 1;
+        Ok(Some(record))
+    }
+
+    #[inline]
+    fn position(&self) -> Option<value::Position> {
+        Some(value::Position {
+            record: self.records,
+            byte: Some(self.offset),
+            line: None,
+        })
+    }
+}
+
+impl<R> Source<R>
+where
+    R: io::Read,
+{
+    /// Reads the id and raw size of a chunk, unless the input ends before it.
+    fn chunk_header(&mut self) -> error::Result<Option<(String, [u8; 4])>> {
+        let mut header = [0; 8];
+        let mut read = 0;
+        while read < header.len() {
+            match self.r.read(&mut header[read..]) {
+                Ok(0) => break,
+                Ok(n) => read += n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => return Err(e.into()),
+            }
+        }
+        self.offset += read as u64;
+        match read {
+            0 => Ok(None),
+            8 => Ok(Some((
+                String::from_utf8_lossy(&header[..4]).into_owned(),
+                [header[4], header[5], header[6], header[7]],
+            ))),
+            _ => Err(self.error("truncated chunk header")),
+        }
+    }
+
+    /// The size of a chunk, or `None` if it is unknown and the chunk goes on to the end of its
+    /// parent.
+    fn decode_size(&self, id: &str, size: [u8; 4]) -> Option<u64> {
+        let size = u64::from(if self.big_endian {
+            u32::from_be_bytes(size)
+        } else {
+            u32::from_le_bytes(size)
+        });
+        if self.kind == Kind::Midi || size != UNKNOWN_SIZE {
+            return Some(size);
+        }
+        match (id, self.ds64) {
+            ("RF64", Some((riff_size, _))) => Some(riff_size),
+            ("data", Some((_, data_size))) => Some(data_size),
+            _ => None,
+        }
+    }
+
+    /// Reads the rest of a chunk after its header, returning the record and whether the input
+    /// ended in it.
+    fn chunk(
+        &mut self,
+        id: String,
+        size: Option<u64>,
+        offset: u64,
+        parent: &str,
+    ) -> error::Result<(value::Value, bool)> {
+        let is_list = self.kind == Kind::Riff && ["RIFF", "RIFX", "RF64", "LIST"].contains(&&*id);
+        let mut entries = vec![
+            ("id".into(), value::Value::String(id.clone())),
+            ("offset".into(), value::Value::U64(offset)),
+            (
+                "size".into(),
+                size.map_or(value::Value::Unit, value::Value::U64),
+            ),
+        ];
+        let mut at_end = false;
+
+        if is_list {
+            let form = String::from_utf8_lossy(&self.contents(4)?).into_owned();
+            entries.push(("form".into(), value::Value::String(form.clone())));
+            let mut remaining = size.map(|size| size.saturating_sub(4));
+            if form == "movi" {
+                at_end = self.skip(remaining)?;
+            } else {
+                let mut chunks = Vec::new();
+                while remaining != Some(0) && !at_end {
+                    let offset = self.offset;
+                    let (id, size) = match self.chunk_header()? {
+                        Some(header) => header,
+                        None if remaining.is_none() => break,
+                        None => return Err(self.error("truncated chunk")),
+                    };
+                    let size = self.decode_size(&id, size);
+                    let (chunk, end) = self.chunk(id, size, offset, &form)?;
+                    chunks.push(chunk);
+                    at_end = end;
+                    remaining = match (remaining, size) {
+                        (Some(remaining), Some(size)) => {
+                            let used = 8 + size + (size & 1);
+                            if used > remaining + (size & 1) {
+                                return Err(self.error("a chunk is larger than its list"));
+                            }
+                            Some(remaining.saturating_sub(used))
+                        }
+                        _ => None,
+                    };
+                }
+                entries.push(("chunks".into(), value::Value::Sequence(chunks)));
+            }
+            // The size of RF64 files is only known from their ds64 chunk
+            if size.is_none() {
+                if let (true, Some((riff_size, _))) = (id == "RF64", self.ds64) {
+                    entries[2].1 = value::Value::U64(riff_size);
+                }
+            }
+        } else {
+            let header = match size {
+                Some(size) if size <= MAX_HEADER_SIZE && self.has_header(&id, parent) => {
+                    let contents = self.contents(size)?;
+                    self.header(&id, parent, &contents)?
+                }
+                _ => {
+                    let header = if self.kind == Kind::Riff && id == "data" && parent == "WAVE" {
+                        self.data_header(size)
+                    } else {
+                        None
+                    };
+                    at_end = self.skip(size)?;
+                    header
+                }
+            };
+            if let Some(header) = header {
+                entries.push(("header".into(), header));
+            }
+        }
+
+        // RIFF chunks are padded to an even size, but the padding is often missing at the end
+        if let (Kind::Riff, Some(size), false) = (self.kind, size, at_end) {
+            if size & 1 == 1 {
+                at_end = self.skip(Some(1))?;
+            }
+        }
+        Ok((value::Value::Map(entries), at_end))
+    }
+
+    fn has_header(&self, id: &str, parent: &str) -> bool {
+        match self.kind {
+            Kind::Riff => {
+                ["fmt ", "fact", "ds64", "avih", "strh", "strf"].contains(&id) || parent == "INFO"
+            }
+            Kind::Midi => id == "MThd" || id == "MTrk",
+        }
+    }
+
+    fn header(
+        &mut self,
+        id: &str,
+        parent: &str,
+        contents: &[u8],
+    ) -> error::Result<Option<value::Value>> {
+        let mut fields = Fields {
+            data: contents,
+            big_endian: self.big_endian,
+        };
+        let header = match id {
+            "MThd" if self.kind == Kind::Midi => midi_header(&mut fields),
+            "MTrk" if self.kind == Kind::Midi => return self.midi_track(contents).map(Some),
+            _ if parent == "INFO" => {
+                let text = String::from_utf8_lossy(contents);
+                Some(value::Value::Map(vec![(
+                    "text".into(),
+                    text.trim_end_matches('\0').into(),
+                )]))
+            }
+            "fmt " => wave_format(&mut fields).map(|(header, block_align, sample_rate)| {
+                self.wave_format = Some((block_align, sample_rate));
+                header
+            }),
+            "fact" => fields
+                .u32()
+                .map(|n| value::Value::Map(vec![("sample_length".into(), n.into())])),
+            "ds64" => ds64(&mut fields).map(|(header, riff_size, data_size)| {
+                self.ds64 = Some((riff_size, data_size));
+                header
+            }),
+            "avih" => avi_header(&mut fields),
+            "strh" => stream_header(&mut fields).map(|(header, kind)| {
+                self.stream_type = Some(kind);
+                header
+            }),
+            "strf" => match self.stream_type.as_deref() {
+                Some("vids") => bitmap_info(&mut fields),
+                Some("auds") => wave_format(&mut fields).map(|(header, _, _)| header),
+                _ => return Ok(None),
+            },
+            _ => return Ok(None),
+        };
+        match header {
+            Some(header) => Ok(Some(header)),
+            None => Err(self.error(&format!("the {} chunk is too short", id.trim_end()))),
+        }
+    }
+
+    /// The length of the samples of a WAV file, from the format before them.
+    fn data_header(&self, size: Option<u64>) -> Option<value::Value> {
+        let (block_align, sample_rate) = self.wave_format?;
+        if block_align == 0 || sample_rate == 0 {
+            return None;
+        }
+        let frames = size? / block_align;
+        Some(value::Value::Map(vec![
+            ("frames".into(), value::Value::U64(frames)),
+            (
+                "seconds".into(),
+                value::Value::from_f64(frames as f64 / sample_rate as f64),
+            ),
+        ]))
+    }
+
+    /// Summarizes the events of a MIDI track.
+    fn midi_track(&self, data: &[u8]) -> error::Result<value::Value> {
+        let truncated = || self.error("truncated MIDI event");
+        let mut pos = 0;
+        let mut tick = 0u64;
+        let mut running_status = None;
+        let mut events = 0u64;
+        let mut notes = 0u64;
+        let mut channels = [false; 16];
+        let mut texts = Vec::new();
+        let mut markers = Vec::new();
+        let mut tempos = Vec::new();
+        let mut time_signatures = Vec::new();
+        let mut key_signatures = Vec::new();
+        let mut name = value::Value::Unit;
+        let mut instrument = value::Value::Unit;
+        let mut copyright = value::Value::Unit;
+
+        while pos < data.len() {
+            tick += variable_length(data, &mut pos).ok_or_else(truncated)?;
+            let status = match *data.get(pos).ok_or_else(truncated)? {
+                status if status & 0x80 != 0 => {
+                    pos += 1;
+                    status
+                }
+                // Running status repeats the status of the last channel message
+                _ => running_status.ok_or_else(|| self.error("MIDI data without a status"))?,
+            };
+            events += 1;
+            match status {
+                0xff => {
+                    running_status = None;
+                    let kind = *data.get(pos).ok_or_else(truncated)?;
+                    pos += 1;
+                    let len = variable_length(data, &mut pos).ok_or_else(truncated)? as usize;
+                    let contents = data.get(pos..pos + len).ok_or_else(truncated)?;
+                    pos += len;
+                    let text =
+                        || value::Value::from(String::from_utf8_lossy(contents).into_owned());
+                    let at_tick = |key: &str, v: value::Value| {
+                        value::Value::Map(vec![
+                            ("tick".into(), value::Value::U64(tick)),
+                            (key.into(), v),
+                        ])
+                    };
+                    match (kind, contents) {
+                        (0x01, _) => texts.push(text()),
+                        (0x02, _) if copyright.is_unit() => copyright = text(),
+                        (0x03, _) if name.is_unit() => name = text(),
+                        (0x04, _) if instrument.is_unit() => instrument = text(),
+                        (0x06, _) => markers.push(at_tick("text", text())),
+                        (0x2f, _) => break,
+                        (0x51, &[a, b, c]) => {
+                            let micros = u32::from_be_bytes([0, a, b, c]);
+                            if micros > 0 {
+                                let bpm = 60_000_000.0 / f64::from(micros);
+                                tempos.push(at_tick("bpm", value::Value::from_f64(bpm)));
+                            }
+                        }
+                        (0x58, &[numerator, denominator, ..]) if denominator < 32 => {
+                            let signature = format!("{}/{}", numerator, 1u32 << denominator);
+                            time_signatures.push(at_tick("signature", signature.into()));
+                        }
+                        (0x59, &[sharps, minor]) => {
+                            if let Some(key) = key_name(sharps as i8, minor) {
+                                key_signatures.push(at_tick("key", key.into()));
+                            }
+                        }
+                        _ => (),
+                    }
+                }
+                0xf0 | 0xf7 => {
+                    running_status = None;
+                    let len = variable_length(data, &mut pos).ok_or_else(truncated)? as usize;
+                    pos += len;
+                }
+                0x80..=0xef => {
+                    running_status = Some(status);
+                    let len = match status & 0xf0 {
+                        0xc0 | 0xd0 => 1,
+                        _ => 2,
+                    };
+                    let message = data.get(pos..pos + len).ok_or_else(truncated)?;
+                    pos += len;
+                    channels[usize::from(status & 0x0f)] = true;
+                    // A note on with no velocity is a note off
+                    if status & 0xf0 == 0x90 && message[1] > 0 {
+                        notes += 1;
+                    }
+                }
+                status => {
+                    return Err(self.error(&format!("invalid MIDI status 0x{:02x}", status)));
+                }
+            }
+        }
+
+        Ok(value::Value::Map(vec![
+            ("name".into(), name),
+            ("instrument".into(), instrument),
+            ("copyright".into(), copyright),
+            ("texts".into(), value::Value::Sequence(texts)),
+            ("markers".into(), value::Value::Sequence(markers)),
+            ("tempos".into(), value::Value::Sequence(tempos)),
+            (
+                "time_signatures".into(),
+                value::Value::Sequence(time_signatures),
+            ),
+            (
+                "key_signatures".into(),
+                value::Value::Sequence(key_signatures),
+            ),
+            (
+                "channels".into(),
+                value::Value::Sequence(
+                    (1..=16u64)
+                        .filter(|&c| channels[c as usize - 1])
+                        .map(value::Value::U64)
+                        .collect(),
+                ),
+            ),
+            ("events".into(), value::Value::U64(events)),
+            ("notes".into(), value::Value::U64(notes)),
+            ("ticks".into(), value::Value::U64(tick)),
+        ]))
+    }
+
+    fn contents(&mut self, size: u64) -> error::Result<Vec<u8>> {
+        let mut contents = Vec::with_capacity(size as usize);
+        io::Read::read_to_end(&mut io::Read::take(&mut self.r, size), &mut contents)?;
+        self.offset += contents.len() as u64;
+        if (contents.len() as u64) < size {
+            return Err(self.error("truncated chunk"));
+        }
+        Ok(contents)
+    }
+
+    /// Skips the contents of a chunk, or the rest of the input if its size is unknown, and
+    /// returns whether the input ended.
+    fn skip(&mut self, size: Option<u64>) -> error::Result<bool> {
+        let skipped = match size {
+            Some(size) => io::copy(&mut io::Read::take(&mut self.r, size), &mut io::sink())?,
+            None => io::copy(&mut self.r, &mut io::sink())?,
+        };
+        self.offset += skipped;
+        match size {
+            // Only the padding byte may be missing at the end
+            Some(1) if skipped == 0 => Ok(true),
+            Some(size) if skipped < size => Err(self.error("truncated chunk")),
+            Some(_) => Ok(false),
+            None => Ok(true),
+        }
+    }
+
+    fn error(&self, msg: &str) -> error::Error {
+        error::Error::Format {
+            msg: format!("byte {}: {}", self.offset, msg),
+        }
+    }
+}
+
+/// Reads the little-endian or big-endian fields of a chunk.
+struct Fields<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+}
+
+impl Fields<'_> {
+    fn bytes<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let bytes = self.data.get(..N)?;
+        self.data = &self.data[N..];
+        let mut array = [0; N];
+        array.copy_from_slice(bytes);
+        Some(array)
+    }
+
+    fn u16(&mut self) -> Option<u64> {
+        let bytes = self.bytes()?;
+        Some(u64::from(if self.big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        }))
+    }
+
+    fn u32(&mut self) -> Option<u64> {
+        let bytes = self.bytes()?;
+        Some(u64::from(if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        }))
+    }
+
+    fn i32(&mut self) -> Option<i64> {
+        Some(i64::from(self.u32()? as u32 as i32))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        let bytes = self.bytes()?;
+        Some(if self.big_endian {
+            u64::from_be_bytes(bytes)
+        } else {
+            u64::from_le_bytes(bytes)
+        })
+    }
+
+    fn fourcc(&mut self) -> Option<String> {
+        Some(String::from_utf8_lossy(&self.bytes::<4>()?).into_owned())
+    }
+}
+
+/// Parses a WAVEFORMATEX structure, returning it with its block size and sample rate.
+fn wave_format(fields: &mut Fields) -> Option<(value::Value, u64, u64)> {
+    let tag = fields.u16()?;
+    let channels = fields.u16()?;
+    let sample_rate = fields.u32()?;
+    let byte_rate = fields.u32()?;
+    let block_align = fields.u16()?;
+    let mut entries = vec![
+        ("format".into(), format_name(tag)),
+        ("channels".into(), channels.into()),
+        ("sample_rate".into(), sample_rate.into()),
+        ("byte_rate".into(), byte_rate.into()),
+        ("block_align".into(), block_align.into()),
+        (
+            "bits_per_sample".into(),
+            fields.u16().map_or(value::Value::Unit, value::Value::U64),
+        ),
+    ];
+    // The extensible format has the actual format in a GUID that starts with its tag
+    if tag == 0xfffe && fields.u16().is_some_and(|size| size >= 22) {
+        entries.push(("valid_bits_per_sample".into(), fields.u16()?.into()));
+        entries.push(("channel_mask".into(), fields.u32()?.into()));
+        entries.push(("subformat".into(), format_name(fields.u16()?)));
+    }
+    Some((value::Value::Map(entries), block_align, sample_rate))
+}
+
+fn format_name(tag: u64) -> value::Value {
+    match tag {
+        0x0001 => "PCM".into(),
+        0x0002 => "ADPCM".into(),
+        0x0003 => "IEEE float".into(),
+        0x0006 => "A-law".into(),
+        0x0007 => "mu-law".into(),
+        0x0011 => "IMA ADPCM".into(),
+        0x0055 => "MP3".into(),
+        0x00ff => "AAC".into(),
+        0xfffe => "extensible".into(),
+        tag => value::Value::U64(tag),
+    }
+}
+
+/// Parses the ds64 chunk of an RF64 file, returning it with its RIFF and data sizes.
+fn ds64(fields: &mut Fields) -> Option<(value::Value, u64, u64)> {
+    let riff_size = fields.u64()?;
+    let data_size = fields.u64()?;
+    let sample_count = fields.u64()?;
+    let header = value::Value::Map(vec![
+        ("riff_size".into(), riff_size.into()),
+        ("data_size".into(), data_size.into()),
+        ("sample_count".into(), sample_count.into()),
+    ]);
+    Some((header, riff_size, data_size))
+}
+
+fn avi_header(fields: &mut Fields) -> Option<value::Value> {
+    let names = [
+        "microseconds_per_frame",
+        "max_bytes_per_second",
+        "padding_granularity",
+        "flags",
+        "total_frames",
+        "initial_frames",
+        "streams",
+        "suggested_buffer_size",
+        "width",
+        "height",
+    ];
+    let mut entries = Vec::with_capacity(names.len());
+    for name in &names {
+        entries.push(((*name).into(), fields.u32()?.into()));
+    }
+    Some(value::Value::Map(entries))
+}
+
+/// Parses an AVI stream header, returning it with the stream type.
+fn stream_header(fields: &mut Fields) -> Option<(value::Value, String)> {
+    let kind = fields.fourcc()?;
+    let handler = fields.fourcc()?;
+    let flags = fields.u32()?;
+    let priority = fields.u16()?;
+    let language = fields.u16()?;
+    let initial_frames = fields.u32()?;
+    let scale = fields.u32()?;
+    let rate = fields.u32()?;
+    let start = fields.u32()?;
+    let length = fields.u32()?;
+    let suggested_buffer_size = fields.u32()?;
+    let quality = fields.u32()?;
+    let sample_size = fields.u32()?;
+    // The rate is in frames or samples per `scale` seconds
+    let (per_second, seconds) = if scale > 0 && rate > 0 {
+        let per_second = rate as f64 / scale as f64;
+        (
+            value::Value::from_f64(per_second),
+            value::Value::from_f64(length as f64 / per_second),
+        )
+    } else {
+        (value::Value::Unit, value::Value::Unit)
+    };
+    let header = value::Value::Map(vec![
+        ("type".into(), kind.clone().into()),
+        ("handler".into(), handler.into()),
+        ("flags".into(), flags.into()),
+        ("priority".into(), priority.into()),
+        ("language".into(), language.into()),
+        ("initial_frames".into(), initial_frames.into()),
+        ("scale".into(), scale.into()),
+        ("rate".into(), rate.into()),
+        ("start".into(), start.into()),
+        ("length".into(), length.into()),
+        ("suggested_buffer_size".into(), suggested_buffer_size.into()),
+        ("quality".into(), quality.into()),
+        ("sample_size".into(), sample_size.into()),
+        ("per_second".into(), per_second),
+        ("seconds".into(), seconds),
+    ]);
+    Some((header, kind))
+}
+
+/// Parses the BITMAPINFOHEADER of an AVI video stream.
+fn bitmap_info(fields: &mut Fields) -> Option<value::Value> {
+    fields.u32()?;
+    let width = fields.i32()?;
+    let height = fields.i32()?;
+    fields.u16()?;
+    let bit_count = fields.u16()?;
+    let compression = fields.bytes::<4>()?;
+    // Uncompressed bitmaps have a number rather than a four-character code
+    let compression = match compression {
+        [0, 0, 0, 0] => "RGB".into(),
+        code => String::from_utf8_lossy(&code).into_owned(),
+    };
+    Some(value::Value::Map(vec![
+        ("width".into(), width.into()),
+        ("height".into(), height.into()),
+        ("bit_count".into(), bit_count.into()),
+        ("compression".into(), compression.into()),
+    ]))
+}
+
+fn midi_header(fields: &mut Fields) -> Option<value::Value> {
+    let format = fields.u16()?;
+    let tracks = fields.u16()?;
+    let division = fields.u16()?;
+    // Timing is either in ticks per quarter note, or in SMPTE frames per second and ticks per
+    // frame
+    let (ticks_per_quarter_note, frames_per_second, ticks_per_frame) = if division & 0x8000 == 0 {
+        (division.into(), value::Value::Unit, value::Value::Unit)
+    } else {
+        let frames = -i64::from((division >> 8) as u8 as i8);
+        (value::Value::Unit, frames.into(), (division & 0xff).into())
+    };
+    Some(value::Value::Map(vec![
+        ("format".into(), format.into()),
+        ("tracks".into(), tracks.into()),
+        ("ticks_per_quarter_note".into(), ticks_per_quarter_note),
+        ("frames_per_second".into(), frames_per_second),
+        ("ticks_per_frame".into(), ticks_per_frame),
+    ]))
+}
+
+/// Reads a variable-length quantity of MIDI, with 7 bits in each byte.
+fn variable_length(data: &[u8], pos: &mut usize) -> Option<u64> {
+    let mut n = 0u64;
+    for _ in 0..4 {
+        let byte = *data.get(*pos)?;
+        *pos += 1;
+        n = (n << 7) | u64::from(byte & 0x7f);
+        if byte & 0x80 == 0 {
+            return Some(n);
+        }
+    }
+    None
+}
+
+/// The name of a key signature from its number of sharps (or flats, if negative).
+fn key_name(sharps: i8, minor: u8) -> Option<String> {
+    const MAJOR: [&str; 15] = [
+        "Cb", "Gb", "Db", "Ab", "Eb", "Bb", "F", "C", "G", "D", "A", "E", "B", "F#", "C#",
+    ];
+    const MINOR: [&str; 15] = [
+        "Ab", "Eb", "Bb", "F", "C", "G", "D", "A", "E", "B", "F#", "C#", "G#", "D#", "A#",
+    ];
+    let index = usize::try_from(i16::from(sharps) + 7).ok()?;
+    match minor {
+        0 => Some(format!("{} major", MAJOR.get(index)?)),
+        1 => Some(format!("{} minor", MINOR.get(index)?)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::value::Source as _;
+
+    fn read<R>(mut reader: Source<R>) -> error::Result<Vec<value::Value>>
+    where
+        R: io::Read,
+    {
+        let mut records = Vec::new();
+        while let Some(record) = reader.read()? {
+            records.push(record);
+        }
+        Ok(records)
+    }
+
+    #[test]
+    fn test_riff() {
+        // One second of 16-bit stereo at 100 Hz, and a title with an odd size
+        let mut wav = b"RIFF\0\0\0\0WAVEfmt \x10\0\0\0".to_vec();
+        wav.extend_from_slice(&[1, 0, 2, 0, 100, 0, 0, 0, 144, 1, 0, 0, 4, 0, 16, 0]);
+        wav.extend_from_slice(b"data\x90\x01\0\0");
+        wav.extend_from_slice(&[0; 400]);
+        wav.extend_from_slice(b"LIST\x12\0\0\0INFOINAM\x05\0\0\0Song\0\0");
+        let size = (wav.len() as u32 - 8).to_le_bytes();
+        wav[4..8].copy_from_slice(&size);
+        let records = read(riff(&wav[..])).unwrap();
+        assert_eq!(
+            records.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            vec![
+                r#"{"id": "RIFF", "offset": 0, "size": 462, "form": "WAVE", "chunks": [{"id": "fmt ", "offset": 12, "size": 16, "header": {"format": "PCM", "channels": 2, "sample_rate": 100, "byte_rate": 400, "block_align": 4, "bits_per_sample": 16}}, {"id": "data", "offset": 36, "size": 400, "header": {"frames": 100, "seconds": 1}}, {"id": "LIST", "offset": 444, "size": 18, "form": "INFO", "chunks": [{"id": "INAM", "offset": 456, "size": 5, "header": {"text": "Song"}}]}]}"#
+            ]
+        );
+        assert!(read(riff(&wav[..100])).is_err());
+        assert!(read(riff(&b"OggS\0\0\0\0"[..])).is_err());
+    }
+
+    #[test]
+    fn test_midi() {
+        let mut file = b"MThd\0\0\0\x06\0\0\0\x01\x01\xe0MTrk\0\0\0\x24".to_vec();
+        file.extend_from_slice(b"\0\xff\x03\x04Lead\0\xff\x51\x03\x07\xa1\x20\0\xff\x59\x02\x02\0");
+        // A note on and off, the off with running status, and a program change
+        file.extend_from_slice(b"\0\x91\x3c\x40\x83\x60\x3c\0\0\xc1\x05\0\xff\x2f\0");
+        let records = read(midi(&file[..])).unwrap();
+        assert_eq!(
+            records.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            vec![
+                r#"{"id": "MThd", "offset": 0, "size": 6, "header": {"format": 0, "tracks": 1, "ticks_per_quarter_note": 480, "frames_per_second": null, "ticks_per_frame": null}}"#,
+                r#"{"id": "MTrk", "offset": 14, "size": 36, "header": {"name": "Lead", "instrument": null, "copyright": null, "texts": [], "markers": [], "tempos": [{"tick": 0, "bpm": 120}], "time_signatures": [], "key_signatures": [{"tick": 0, "key": "D major"}], "channels": [2], "events": 7, "notes": 1, "ticks": 480}}"#,
+            ]
+        );
+        assert!(read(midi(&file[..30])).is_err());
+    }
+}
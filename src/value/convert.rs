@@ -0,0 +1,170 @@
+//! Conversions between `Value` and other types, mostly for the benefit of library users.
+
+use crate::error;
+use crate::value::Value;
+use serde_json;
+use std::convert;
+
+macro_rules! gen_from {
+    ($t:ty, $i:ident) => {
+        impl From<$t> for Value {
+            fn from(v: $t) -> Self {
+                Self::$i(v)
+            }
+        }
+    };
+}
+
+gen_from!(bool, Bool);
+gen_from!(i8, I8);
+gen_from!(i16, I16);
+gen_from!(i32, I32);
+gen_from!(i64, I64);
+gen_from!(u8, U8);
+gen_from!(u16, U16);
+gen_from!(u32, U32);
+gen_from!(u64, U64);
+gen_from!(char, Char);
+gen_from!(String, String);
+gen_from!(Vec<u8>, Bytes);
+gen_from!(Vec<Value>, Sequence);
+gen_from!(Vec<(Value, Value)>, Map);
+
+impl From<()> for Value {
+    fn from(_: ()) -> Self {
+        Self::Unit
+    }
+}
+
+impl From<f32> for Value {
+    fn from(v: f32) -> Self {
+        Self::from_f32(v)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(v: f64) -> Self {
+        Self::from_f64(v)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(v: &str) -> Self {
+        Self::String(v.to_owned())
+    }
+}
+
+impl<A> From<Option<A>> for Value
+where
+    A: Into<Value>,
+{
+    fn from(v: Option<A>) -> Self {
+        v.map_or(Self::Unit, Into::into)
+    }
+}
+
+impl From<serde_json::Value> for Value {
+    fn from(v: serde_json::Value) -> Self {
+        match v {
+            serde_json::Value::Null => Self::Unit,
+            serde_json::Value::Bool(v) => Self::Bool(v),
+            serde_json::Value::Number(n) => {
+                // Mirrors what the JSON source produces for the same input
+                if let Some(v) = n.as_u64() {
+                    Self::U64(v)
+                } else if let Some(v) = n.as_i64() {
+                    Self::I64(v)
+                } else {
+                    Self::from_f64(n.as_f64().unwrap_or(f64::NAN))
+                }
+            }
+            serde_json::Value::String(v) => Self::String(v),
+            serde_json::Value::Array(v) => Self::Sequence(v.into_iter().map(From::from).collect()),
+            serde_json::Value::Object(v) => Self::Map(
+                v.into_iter()
+                    .map(|(k, v)| (Self::String(k), Self::from(v)))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl convert::TryFrom<Value> for serde_json::Value {
+    type Error = error::Error;
+
+    fn try_from(v: Value) -> error::Result<Self> {
+        Ok(serde_json::to_value(&v)?)
+    }
+}
+
+impl convert::TryFrom<&Value> for serde_json::Value {
+    type Error = error::Error;
+
+    fn try_from(v: &Value) -> error::Result<Self> {
+        Ok(serde_json::to_value(v)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::convert::TryFrom;
+
+    #[test]
+    fn test_from() {
+        assert_eq!(Value::from(()), Value::Unit);
+        assert_eq!(Value::from(-1i8), Value::I8(-1));
+        assert_eq!(Value::from(1u64), Value::U64(1));
+        assert_eq!(Value::from('c'), Value::Char('c'));
+        assert_eq!(Value::from("s"), Value::String("s".to_owned()));
+        assert_eq!(Value::from(vec![1u8, 2]), Value::Bytes(vec![1, 2]));
+        assert_eq!(Value::from(1.5f32), Value::from_f32(1.5));
+        assert_eq!(Value::from(None::<i32>), Value::Unit);
+        assert_eq!(Value::from(Some(true)), Value::Bool(true));
+    }
+
+    #[test]
+    fn test_json() {
+        let json = serde_json::json!({
+            "u": 1,
+            "i": -1,
+            "f": 0.5,
+            "big": u64::MAX,
+            "s": "x",
+            "n": null,
+            "a": [true],
+        });
+        let v = Value::from(json.clone());
+        // Numbers become the same types that the JSON source reads them as
+        assert_eq!(v.get(".u"), Some(&Value::U64(1)));
+        assert_eq!(v.get(".i"), Some(&Value::I64(-1)));
+        assert_eq!(v.get(".f"), Some(&Value::from_f64(0.5)));
+        assert_eq!(v.get(".big"), Some(&Value::U64(u64::MAX)));
+        assert_eq!(v.get(".n"), Some(&Value::Unit));
+        assert_eq!(serde_json::Value::try_from(&v).unwrap(), json);
+        assert_eq!(serde_json::Value::try_from(v).unwrap(), json);
+
+        // JSON has no keys other than strings
+        let map = Value::Map(vec![(Value::Sequence(Vec::new()), Value::Unit)]);
+        assert!(serde_json::Value::try_from(map).is_err());
+    }
+
+    #[test]
+    fn test_value_macro() {
+        assert_eq!(value!(null), Value::Unit);
+        assert_eq!(value!("a"), Value::String("a".to_owned()));
+        assert_eq!(
+            value!([1, "b"]),
+            Value::Sequence(vec![Value::U64(1), Value::String("b".to_owned())])
+        );
+        // Keys come out sorted, like in the maps of `serde_json`
+        assert_eq!(
+            value!({"z": 1, "a": {}}),
+            Value::Map(vec![
+                (Value::String("a".to_owned()), Value::Map(Vec::new())),
+                (Value::String("z".to_owned()), Value::U64(1)),
+            ])
+        );
+    }
+}
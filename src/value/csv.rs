@@ -9,29 +9,104 @@ pub struct Source<R>(csv::StringRecordsIntoIter<R>)
 where
     R: io::Read;
 
-pub struct Sink<W>(csv::Writer<W>)
+/// A CSV sink, which remembers the header that it wrote for maps with `wrap_scalars`.
+pub struct Sink<W>(csv::Writer<W>, bool, Option<Vec<String>>)
 where
     W: io::Write;
 
+/// The characters that separate and quote the fields of CSV data, for variants like TSV.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Dialect {
+    pub delimiter: u8,
+    pub quote: u8,
+    /// The character that escapes quotes in quoted fields.  By default, quotes are escaped by
+    /// doubling them.
+    pub escape: Option<u8>,
+    /// The character that ends records.  By default, records end with any line break when
+    /// reading, and with `\n` when writing.
+    pub terminator: Option<u8>,
+}
+
 #[inline]
 pub fn source<R>(r: R) -> Source<R>
 where
     R: io::Read,
 {
-    Source(
-        csv::ReaderBuilder::new()
-            .has_headers(false)
-            .from_reader(r)
-            .into_records(),
-    )
+    source_with(r, &Dialect::default())
+}
+
+/// Creates a CSV source for data in a different dialect.
+pub fn source_with<R>(r: R, dialect: &Dialect) -> Source<R>
+where
+    R: io::Read,
+{
+    let mut builder = csv::ReaderBuilder::new();
+    builder
+        .has_headers(false)
+        .delimiter(dialect.delimiter)
+        .quote(dialect.quote)
+        .escape(dialect.escape)
+        .double_quote(dialect.escape.is_none());
+    if let Some(terminator) = dialect.terminator {
+        builder.terminator(csv::Terminator::Any(terminator));
+    }
+    Source(builder.from_reader(r).into_records())
 }
 
+/// Creates a CSV sink.  Each record must be a sequence of fields, unless `wrap_scalars` is set,
+/// in which case maps are written as rows of their values under a header with the keys of the
+/// first map, and other records as rows with a single field.
 #[inline]
-pub fn sink<W>(w: W) -> Sink<W>
+pub fn sink<W>(w: W, wrap_scalars: bool) -> Sink<W>
 where
     W: io::Write,
 {
-    Sink(csv::Writer::from_writer(w))
+    sink_with(w, wrap_scalars, &Dialect::default())
+}
+
+/// Creates a CSV sink that writes data in a different dialect.
+pub fn sink_with<W>(w: W, wrap_scalars: bool, dialect: &Dialect) -> Sink<W>
+where
+    W: io::Write,
+{
+    let mut builder = csv::WriterBuilder::new();
+    builder
+        .delimiter(dialect.delimiter)
+        .quote(dialect.quote)
+        .double_quote(dialect.escape.is_none());
+    if let Some(escape) = dialect.escape {
+        builder.escape(escape);
+    }
+    if let Some(terminator) = dialect.terminator {
+        builder.terminator(csv::Terminator::Any(terminator));
+    }
+    Sink(builder.from_writer(w), wrap_scalars, None)
+}
+
+/// Parses a character of a dialect, which must be ASCII.  Tabs can also be given as `\t` or
+/// `tab`, since they are awkward to type in a shell.
+pub fn parse_char(s: &str) -> error::Result<u8> {
+    match s {
+        "\\t" | "tab" => Ok(b'\t'),
+        "\\n" => Ok(b'\n'),
+        "\\r" => Ok(b'\r'),
+        _ if s.len() == 1 && s.is_ascii() => Ok(s.as_bytes()[0]),
+        _ => Err(error::Error::Message(format!(
+            "CSV delimiters, quotes and escapes must be a single ASCII character, got: {:?}",
+            s
+        ))),
+    }
+}
+
+impl Default for Dialect {
+    fn default() -> Self {
+        Self {
+            delimiter: b',',
+            quote: b'"',
+            escape: None,
+            terminator: None,
+        }
+    }
 }
 
 impl<R> value::Source for Source<R>
@@ -50,6 +125,16 @@ where
             None => Ok(None),
         }
     }
+
+    #[inline]
+    fn position(&self) -> Option<value::Position> {
+        let position = self.0.reader().position();
+        Some(value::Position {
+            record: position.record(),
+            byte: Some(position.byte()),
+            line: Some(position.line()),
+        })
+    }
 }
 
 impl<W> value::Sink for Sink<W>
@@ -67,11 +152,63 @@ where
                 self.0.write_record(record)?;
                 Ok(())
             }
+            // Maps are flattened into a row of their values, in the order of the header
+            value::Value::Map(entries) if self.1 => {
+                let entries: Vec<_> = entries
+                    .into_iter()
+                    .map(|(k, v)| (k.into_key_string(), v))
+                    .collect();
+                let record = match self.2 {
+                    Some(ref header) => row(header, entries)?,
+                    None => {
+                        let header: Vec<_> = entries.iter().map(|(k, _)| k.clone()).collect();
+                        let record = row(&header, entries)?;
+                        self.0.write_record(&header)?;
+                        self.2 = Some(header);
+                        record
+                    }
+                };
+                self.0.write_record(record)?;
+                Ok(())
+            }
+            x if self.1 => {
+                self.0.write_record(&[value_to_csv(x)?])?;
+                Ok(())
+            }
             x => Err(error::Error::Format {
-                msg: format!("csv can only output sequences, got: {:?}", x),
+                msg: format!(
+                    "csv can only output sequences (see --wrap-scalar), got: {}",
+                    x.summary(value::ERROR_SUMMARY_LEN)
+                ),
             }),
         }
     }
+
+    #[inline]
+    fn flush(&mut self) -> error::Result<()> {
+        self.0.flush()?;
+        Ok(())
+    }
+}
+
+/// The fields of a map in the order of the header, leaving out the fields that it doesn't have.
+fn row(header: &[String], mut entries: Vec<(String, value::Value)>) -> error::Result<Vec<String>> {
+    let mut record = Vec::with_capacity(header.len());
+    for column in header {
+        match entries.iter().position(|(k, _)| k == column) {
+            Some(i) => record.push(value_to_csv(entries.remove(i).1)?),
+            None => record.push(String::new()),
+        }
+    }
+    match entries.first() {
+        Some((k, _)) => Err(error::Error::Format {
+            msg: format!(
+                "csv can't output the key {:?}, which isn't in the header",
+                k
+            ),
+        }),
+        None => Ok(record),
+    }
 }
 
 fn value_to_csv(value: value::Value) -> error::Result<String> {
@@ -126,3 +263,75 @@ where
         f.debug_struct("CsvSink").finish()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::value::Sink as _;
+    use crate::value::Source as _;
+
+    #[test]
+    fn test_dialect() {
+        let tsv = Dialect {
+            delimiter: b'\t',
+            ..Dialect::default()
+        };
+        let input = b"a\tb,c\n\"x\ty\"\tz\n";
+        let mut reader = source_with(&input[..], &tsv);
+        let mut output = Vec::new();
+        {
+            let mut writer = sink_with(&mut output, false, &tsv);
+            while let Some(record) = reader.read().unwrap() {
+                writer.write(record).unwrap();
+            }
+            writer.finish().unwrap();
+        }
+        assert_eq!(output, input);
+
+        let dialect = Dialect {
+            delimiter: b';',
+            escape: Some(b'\\'),
+            terminator: Some(b'|'),
+            ..Dialect::default()
+        };
+        let mut reader = source_with(&b"a;\"b\\\"c\"|d;e|"[..], &dialect);
+        assert_eq!(reader.read().unwrap(), Some(value!(["a", "b\"c"])));
+        assert_eq!(reader.read().unwrap(), Some(value!(["d", "e"])));
+        assert_eq!(reader.read().unwrap(), None);
+    }
+
+    #[test]
+    fn test_wrap_scalars() {
+        let input = br#"{"a": 1, "b": "x"} {"b": "y z", "a": 2} {"a": 3} [4, 5]"#;
+        let mut reader = value::json::source(&input[..]);
+        let mut output = Vec::new();
+        {
+            let mut writer = sink(&mut output, true);
+            while let Some(record) = reader.read().unwrap() {
+                writer.write(record).unwrap();
+            }
+            writer.finish().unwrap();
+        }
+        assert_eq!(output, b"a,b\n1,x\n2,y z\n3,\n4,5\n");
+
+        let mut output = Vec::new();
+        sink(&mut output, true).write(value!(6)).unwrap();
+        assert_eq!(output, b"6\n");
+        let mut writer = sink(Vec::new(), true);
+        writer.write(value!({"a": 1})).unwrap();
+        assert!(writer.write(value!({"a": 2, "b": 3})).is_err());
+        let mut writer = sink(Vec::new(), false);
+        assert!(writer.write(value!({"a": 1})).is_err());
+        assert!(writer.write(value!(1)).is_err());
+    }
+
+    #[test]
+    fn test_parse_char() {
+        assert_eq!(parse_char(";").unwrap(), b';');
+        assert_eq!(parse_char("tab").unwrap(), b'\t');
+        assert_eq!(parse_char("\\t").unwrap(), b'\t');
+        assert!(parse_char("''").is_err());
+        assert!(parse_char("é").is_err());
+    }
+}
@@ -0,0 +1,311 @@
+//! Environment files (`.env`), with a `KEY=VALUE` line for each variable.
+//!
+//! The quoting follows the common dotenv parsers: values in single quotes are literal, values in
+//! double quotes can have escapes like `\n` and span lines, and unquoted values end at a comment
+//! that starts with ` #`.  Lines can start with `export`.  Variables like `${HOME}` are not
+//! expanded.  The whole file is read as a single map.
+
+use std::io;
+
+use crate::error;
+use crate::value;
+
+#[derive(Debug)]
+pub struct Source(Option<value::Value>);
+
+#[derive(Debug)]
+pub struct Sink<W>(W)
+where
+    W: io::Write;
+
+/// Creates a source for the variables of an environment file, which is read in full from the
+/// input.
+pub fn source<R>(mut r: R) -> error::Result<Source>
+where
+    R: io::Read,
+{
+    let mut input = String::new();
+    r.read_to_string(&mut input)?;
+    Ok(Source(Some(parse(&input)?)))
+}
+
+/// Creates a sink that writes maps of scalars as environment files.
+#[inline]
+pub fn sink<W>(w: W) -> Sink<W>
+where
+    W: io::Write,
+{
+    Sink(w)
+}
+
+impl value::Source for Source {
+    #[inline]
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        Ok(self.0.take())
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = if self.0.is_some() { 1 } else { 0 };
+        (remaining, Some(remaining))
+    }
+}
+
+impl<W> value::Sink for Sink<W>
+where
+    W: io::Write,
+{
+    fn write(&mut self, value: value::Value) -> error::Result<()> {
+        let entries = match value {
+            value::Value::Map(entries) => entries,
+            value => {
+                return Err(error::Error::Format {
+                    msg: format!(
+                        "dotenv can only output maps, got: {}",
+                        value.summary(value::ERROR_SUMMARY_LEN)
+                    ),
+                })
+            }
+        };
+        // Lines are only written once the whole record is known to be valid
+        let mut output = String::new();
+        for (key, v) in entries {
+            let key = match key.as_str() {
+                Some(key) if is_key(key) => key.to_owned(),
+                _ => {
+                    return Err(error::Error::Format {
+                        msg: format!(
+                            "dotenv keys must be names like MY_VAR, got: {}",
+                            key.summary(value::ERROR_SUMMARY_LEN)
+                        ),
+                    })
+                }
+            };
+            let text = match v {
+                value::Value::Unit => String::new(),
+                value::Value::String(s) => s,
+                value::Value::Map(_) | value::Value::Sequence(_) | value::Value::Bytes(_) => {
+                    return Err(error::Error::Format {
+                        msg: format!(
+                            "dotenv can only output scalar values, but {} is: {}",
+                            key,
+                            v.summary(value::ERROR_SUMMARY_LEN)
+                        ),
+                    })
+                }
+                v => v.to_string(),
+            };
+            output.push_str(&key);
+            output.push('=');
+            output.push_str(&quote(&text));
+            output.push('\n');
+        }
+        self.0.write_all(output.as_bytes())?;
+        Ok(())
+    }
+
+    #[inline]
+    fn flush(&mut self) -> error::Result<()> {
+        self.0.flush()?;
+        Ok(())
+    }
+}
+
+fn parse(input: &str) -> error::Result<value::Value> {
+    let mut entries = Vec::new();
+    let mut lines = input.lines().enumerate();
+    while let Some((number, line)) = lines.next() {
+        let error = |msg: &str| error::Error::Format {
+            msg: format!("dotenv line {}: {}", number + 1, msg),
+        };
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line
+            .strip_prefix("export")
+            .filter(|rest| rest.starts_with([' ', '\t']))
+            .map_or(line, str::trim_start);
+        let (key, rest) = match line.split_once('=') {
+            Some((key, rest)) => (key.trim_end(), rest.trim_start()),
+            // A variable without a value
+            None => (line, ""),
+        };
+        if !is_key(key) {
+            return Err(error(&format!("invalid variable name: {}", key)));
+        }
+        let v = if line.len() == key.len() {
+            value::Value::Unit
+        } else {
+            match rest.chars().next() {
+                Some(q @ ('\'' | '"' | '`')) => {
+                    // Quoted values can continue on the next lines
+                    let mut text = rest[1..].to_owned();
+                    let end = loop {
+                        if let Some(end) = closing_quote(&text, q) {
+                            break end;
+                        }
+                        match lines.next() {
+                            Some((_, line)) => {
+                                text.push('\n');
+                                text.push_str(line);
+                            }
+                            None => return Err(error("unterminated quoted value")),
+                        }
+                    };
+                    let after = text[end + 1..].trim_start();
+                    if !after.is_empty() && !after.starts_with('#') {
+                        return Err(error(&format!("text after the quoted value: {}", after)));
+                    }
+                    text.truncate(end);
+                    match q {
+                        '"' => unescape(&text).into(),
+                        _ => text.into(),
+                    }
+                }
+                _ => {
+                    let end = rest.find(" #").or_else(|| rest.find("\t#"));
+                    rest[..end.unwrap_or(rest.len())].trim_end().into()
+                }
+            }
+        };
+        // Later definitions win, like when the file is sourced by a shell
+        entries.retain(|(k, _): &(value::Value, value::Value)| k.as_str() != Some(key));
+        entries.push((key.into(), v));
+    }
+    Ok(value::Value::Map(entries))
+}
+
+/// Finds the quote that ends a value, which isn't escaped in double quotes.
+fn closing_quote(text: &str, quote: char) -> Option<usize> {
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        match c {
+            '\\' if quote == '"' && !escaped => escaped = true,
+            c if c == quote && !escaped => return Some(i),
+            _ => escaped = false,
+        }
+    }
+    None
+}
+
+fn unescape(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => result.push('\n'),
+            Some('r') => result.push('\r'),
+            Some('t') => result.push('\t'),
+            Some(c @ ('"' | '\\' | '$' | '\'' | '`')) => result.push(c),
+            Some(c) => {
+                result.push('\\');
+                result.push(c);
+            }
+            None => result.push('\\'),
+        }
+    }
+    result
+}
+
+/// Quotes a value for all common dotenv parsers and shells: plain values are left as they are,
+/// most others are put in single quotes, and ones with single quotes or line breaks in double
+/// quotes.
+fn quote(text: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "_-.,/:@%+=".contains(c);
+    if text.chars().all(plain) {
+        text.to_owned()
+    } else if !text.contains(['\'', '\n', '\r']) {
+        format!("'{}'", text)
+    } else {
+        let mut quoted = String::with_capacity(text.len() + 2);
+        quoted.push('"');
+        for c in text.chars() {
+            match c {
+                '\n' => quoted.push_str("\\n"),
+                '\r' => quoted.push_str("\\r"),
+                '"' | '\\' | '$' | '`' => {
+                    quoted.push('\\');
+                    quoted.push(c);
+                }
+                c => quoted.push(c),
+            }
+        }
+        quoted.push('"');
+        quoted
+    }
+}
+
+fn is_key(key: &str) -> bool {
+    !key.is_empty()
+        && !key.starts_with(|c: char| c.is_ascii_digit())
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '-')
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::value::Sink as _;
+    use crate::value::Source as _;
+
+    fn write(value: value::Value) -> error::Result<String> {
+        let mut output = Vec::new();
+        sink(&mut output).write(value)?;
+        Ok(String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn test_source() {
+        let env = r#"# Database
+export DB_HOST=localhost
+DB_PORT = 5432 # the default
+PASSWORD='p@ss#word $HOME'
+GREETING="Hello\n\"World\""
+KEY="-----BEGIN KEY-----
+abc
+-----END KEY-----"
+EMPTY=
+UNSET
+DB_HOST=db.internal
+"#;
+        let mut reader = source(env.as_bytes()).unwrap();
+        assert_eq!(
+            reader.read().unwrap().map(|r| r.to_string()),
+            Some(r#"{"DB_PORT": "5432", "PASSWORD": "p@ss#word $HOME", "GREETING": "Hello\n\"World\"", "KEY": "-----BEGIN KEY-----\nabc\n-----END KEY-----", "EMPTY": "", "UNSET": null, "DB_HOST": "db.internal"}"#.to_owned())
+        );
+        assert_eq!(reader.read().unwrap(), None);
+
+        assert!(source(&b"A=\"unterminated"[..]).is_err());
+        assert!(source(&b"key: value"[..]).is_err());
+    }
+
+    #[test]
+    fn test_sink() {
+        let record = value::json::source(
+            &br#"{"HOST": "db.internal", "PORT": 5432, "DEBUG": false, "NAME": "it's $me", "MOTD": "a b", "NONE": null}"#[..],
+        )
+        .read()
+        .unwrap()
+        .unwrap();
+        let output = write(record).unwrap();
+        assert_eq!(
+            output,
+            "HOST=db.internal\nPORT=5432\nDEBUG=false\nNAME=\"it's \\$me\"\nMOTD='a b'\nNONE=\n"
+        );
+        // Written files read back to the same values
+        let round_trip = source(output.as_bytes()).unwrap().read().unwrap().unwrap();
+        assert_eq!(round_trip.get(".NAME").unwrap().as_str(), Some("it's $me"));
+        assert_eq!(round_trip.get(".MOTD").unwrap().as_str(), Some("a b"));
+
+        assert!(write(value!({"A": {"B": 1}})).is_err());
+        assert!(write(value!({"not a name": 1})).is_err());
+        assert!(write(value!([1])).is_err());
+    }
+}
@@ -0,0 +1,725 @@
+//! EDN, the extensible data notation of Clojure.
+//!
+//! Each top-level value becomes a record.  Vectors become sequences, maps become maps, `nil`
+//! becomes null and characters like `\a` become characters.  Like Ion, EDN types that have no
+//! direct equivalent become maps with a single key: keywords become `{"$keyword": "ns/name"}`,
+//! symbols `{"$symbol": "..."}`, sets `{"$set": [...]}`, lists `{"$list": [...]}`, integers like
+//! `1N` or that don't fit in 64 bits `{"$bigint": "1"}` and decimals like `1.5M` `{"$decimal":
+//! "1.5"}`.  Tagged literals like `#inst "2024-01-01"` become `{"$tag": "inst", "$value":
+//! "2024-01-01"}`.
+//!
+//! Keywords that are map keys become strings with their colon instead, like `":name"`, since
+//! most formats only have string keys, and the sink writes such keys as keywords again.  The
+//! sink turns the other maps back into the EDN types, and writes bytes as vectors of integers.
+
+use std::io;
+
+use crate::error;
+use crate::value;
+
+const KEYWORD: &str = "$keyword";
+const SYMBOL: &str = "$symbol";
+const SET: &str = "$set";
+const LIST: &str = "$list";
+const BIGINT: &str = "$bigint";
+const DECIMAL: &str = "$decimal";
+const TAG: &str = "$tag";
+const VALUE: &str = "$value";
+
+/// Characters that can be in symbols and keywords, besides alphanumeric ones.
+const SYMBOL_CHARS: &str = ".*+!-_?$%&=<>/:#'";
+/// How deeply values are nested at most.
+const MAX_DEPTH: usize = 128;
+
+#[derive(Debug)]
+pub struct Source {
+    parser: Parser,
+    records: u64,
+}
+
+#[derive(Debug)]
+pub struct Sink<W>(W)
+where
+    W: io::Write;
+
+/// Creates a source for the values of an EDN document, which is read in full from the input.
+pub fn source<R>(mut r: R) -> error::Result<Source>
+where
+    R: io::Read,
+{
+    let mut input = String::new();
+    r.read_to_string(&mut input)?;
+    Ok(Source {
+        parser: Parser {
+            chars: input.chars().collect(),
+            pos: 0,
+            line: 1,
+            depth: 0,
+        },
+        records: 0,
+    })
+}
+
+/// Creates a sink that writes each value on its own line.
+#[inline]
+pub fn sink<W>(w: W) -> Sink<W>
+where
+    W: io::Write,
+{
+    Sink(w)
+}
+
+impl value::Source for Source {
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        self.parser.skip_space()?;
+        if self.parser.peek().is_none() {
+            return Ok(None);
+        }
+        let v = self.parser.nested(Parser::value)?;
+        self.records += 1;
+        Ok(Some(v))
+    }
+
+    #[inline]
+    fn position(&self) -> Option<value::Position> {
+        Some(value::Position {
+            record: self.records,
+            byte: None,
+            line: Some(self.parser.line),
+        })
+    }
+}
+
+impl<W> value::Sink for Sink<W>
+where
+    W: io::Write,
+{
+    fn write(&mut self, value: value::Value) -> error::Result<()> {
+        let mut output = String::new();
+        write_value(&mut output, &value)?;
+        output.push('\n');
+        self.0.write_all(output.as_bytes())?;
+        Ok(())
+    }
+
+    #[inline]
+    fn flush(&mut self) -> error::Result<()> {
+        self.0.flush()?;
+        Ok(())
+    }
+}
+
+fn single(key: &str, v: value::Value) -> value::Value {
+    value::Value::Map(vec![(key.into(), v)])
+}
+
+fn is_delimiter(c: char) -> bool {
+    c.is_whitespace() || "()[]{}\",;".contains(c)
+}
+
+/// Whether the text is a valid symbol, or the name of a keyword after its colon.
+fn is_symbol(s: &str) -> bool {
+    if s == "/" {
+        return true;
+    }
+    let mut chars = s.chars();
+    let (first, second) = (chars.next(), chars.next());
+    let starts_like_number = match (first, second) {
+        (Some(c), _) if c.is_ascii_digit() => true,
+        (Some('+' | '-' | '.'), Some(c)) => c.is_ascii_digit(),
+        _ => false,
+    };
+    first.is_some()
+        && !starts_like_number
+        && !s.starts_with([':', '#', '/'])
+        && !s.ends_with('/')
+        && s.chars()
+            .all(|c| c.is_alphanumeric() || SYMBOL_CHARS.contains(c))
+}
+
+#[derive(Debug)]
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    line: u64,
+    /// How many values the parser is inside of.
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    fn error(&self, msg: &str) -> error::Error {
+        error::Error::Format {
+            msg: format!("EDN line {}: {}", self.line, msg),
+        }
+    }
+
+    /// Parses something nested in the current value, failing if that is nested too deeply.
+    fn nested<T, F>(&mut self, parse: F) -> error::Result<T>
+    where
+        F: FnOnce(&mut Self) -> error::Result<T>,
+    {
+        if self.depth >= MAX_DEPTH {
+            return Err(error::Error::Message(format!(
+                "EDN line {}: values are nested more than {} levels deep",
+                self.line, MAX_DEPTH
+            )));
+        }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    /// Skips whitespace, commas, comments and discarded values like `#_foo`.
+    fn skip_space(&mut self) -> error::Result<()> {
+        loop {
+            match self.peek() {
+                Some(c) if c.is_whitespace() || c == ',' => {
+                    self.next();
+                }
+                Some(';') => {
+                    while self.peek().is_some_and(|c| c != '\n') {
+                        self.next();
+                    }
+                }
+                Some('#') if self.chars.get(self.pos + 1) == Some(&'_') => {
+                    self.pos += 2;
+                    self.nested(|parser| {
+                        parser.skip_space()?;
+                        parser.value()
+                    })?;
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    /// Parses a value, after any whitespace.
+    fn value(&mut self) -> error::Result<value::Value> {
+        match self.peek() {
+            None => Err(self.error("unexpected end of input")),
+            Some('"') => {
+                self.next();
+                self.string().map(value::Value::String)
+            }
+            Some('[') => {
+                self.next();
+                self.sequence(']').map(value::Value::Sequence)
+            }
+            Some('(') => {
+                self.next();
+                Ok(single(LIST, value::Value::Sequence(self.sequence(')')?)))
+            }
+            Some('{') => {
+                self.next();
+                self.map(None)
+            }
+            Some('\\') => {
+                self.next();
+                self.character()
+            }
+            Some('#') => {
+                self.next();
+                self.dispatch()
+            }
+            Some(c) if is_delimiter(c) => Err(self.error(&format!("unexpected {:?}", c))),
+            Some(_) => {
+                let token = self.token();
+                self.atom(&token)
+            }
+        }
+    }
+
+    /// Parses what comes after a `#`: a set, a symbolic value, a namespaced map or a tagged
+    /// literal.
+    fn dispatch(&mut self) -> error::Result<value::Value> {
+        match self.peek() {
+            Some('{') => {
+                self.next();
+                Ok(single(SET, value::Value::Sequence(self.sequence('}')?)))
+            }
+            Some('#') => {
+                self.next();
+                match self.token().as_str() {
+                    "Inf" => Ok(value::Value::from_f64(f64::INFINITY)),
+                    "-Inf" => Ok(value::Value::from_f64(f64::NEG_INFINITY)),
+                    "NaN" => Ok(value::Value::from_f64(f64::NAN)),
+                    token => Err(self.error(&format!("unknown symbolic value: ##{}", token))),
+                }
+            }
+            Some(':') => {
+                self.next();
+                let namespace = self.token();
+                if !is_symbol(&namespace) {
+                    return Err(self.error(&format!("invalid map namespace: {}", namespace)));
+                }
+                self.skip_space()?;
+                if self.next() != Some('{') {
+                    return Err(self.error("a map namespace must be followed by a map"));
+                }
+                self.map(Some(&namespace))
+            }
+            Some(c) if c.is_alphabetic() => {
+                let tag = self.token();
+                if !is_symbol(&tag) {
+                    return Err(self.error(&format!("invalid tag: {}", tag)));
+                }
+                self.skip_space()?;
+                let v = self.nested(Self::value)?;
+                Ok(value::Value::Map(vec![
+                    (TAG.into(), tag.into()),
+                    (VALUE.into(), v),
+                ]))
+            }
+            _ => Err(self.error("invalid dispatch character after #")),
+        }
+    }
+
+    /// Reads the characters up to the next delimiter.
+    fn token(&mut self) -> String {
+        let mut token = String::new();
+        while let Some(c) = self.peek() {
+            if is_delimiter(c) {
+                break;
+            }
+            token.push(c);
+            self.next();
+        }
+        token
+    }
+
+    fn atom(&self, token: &str) -> error::Result<value::Value> {
+        let mut chars = token.chars();
+        let first = chars.next().unwrap_or_default();
+        let second = chars.next();
+        if first.is_ascii_digit()
+            || (matches!(first, '+' | '-') && second.is_some_and(|c| c.is_ascii_digit()))
+        {
+            return self.number(token);
+        }
+        match token {
+            "nil" => Ok(value::Value::Unit),
+            "true" => Ok(value::Value::Bool(true)),
+            "false" => Ok(value::Value::Bool(false)),
+            _ => match token.strip_prefix(':') {
+                Some(name) if is_symbol(name) => Ok(single(KEYWORD, name.into())),
+                Some(_) => Err(self.error(&format!("invalid keyword: {}", token))),
+                None if is_symbol(token) => Ok(single(SYMBOL, token.into())),
+                None => Err(self.error(&format!("invalid symbol: {}", token))),
+            },
+        }
+    }
+
+    fn number(&self, token: &str) -> error::Result<value::Value> {
+        let invalid = || self.error(&format!("invalid number: {}", token));
+        let digits = token.strip_prefix('+').unwrap_or(token);
+        if let Some(digits) = digits.strip_suffix('M') {
+            return match digits.parse::<f64>() {
+                Ok(_) => Ok(single(DECIMAL, digits.into())),
+                Err(_) => Err(invalid()),
+            };
+        }
+        if digits.contains(['.', 'e', 'E']) {
+            return digits
+                .parse::<f64>()
+                .ok()
+                .map(value::Value::from_f64)
+                .ok_or_else(invalid);
+        }
+        let (digits, bigint) = match digits.strip_suffix('N') {
+            Some(digits) => (digits, true),
+            None => (digits, false),
+        };
+        let unsigned = digits.strip_prefix('-').unwrap_or(digits);
+        let leading_zero = unsigned.len() > 1 && unsigned.starts_with('0');
+        if unsigned.is_empty() || leading_zero || !unsigned.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(invalid());
+        }
+        match digits.parse::<i64>() {
+            Ok(n) if !bigint => Ok(value::Value::I64(n)),
+            // Integers that don't fit are big integers, like in Clojure
+            _ => Ok(single(BIGINT, digits.into())),
+        }
+    }
+
+    /// Parses a string after its opening quote.
+    fn string(&mut self) -> error::Result<String> {
+        let mut s = String::new();
+        loop {
+            match self.next() {
+                None => return Err(self.error("unterminated string")),
+                Some('"') => return Ok(s),
+                Some('\\') => match self.next() {
+                    Some('n') => s.push('\n'),
+                    Some('t') => s.push('\t'),
+                    Some('r') => s.push('\r'),
+                    Some('b') => s.push('\u{08}'),
+                    Some('f') => s.push('\u{0c}'),
+                    Some(c @ ('"' | '\\')) => s.push(c),
+                    Some('u') => {
+                        let hex: String = (0..4).filter_map(|_| self.next()).collect();
+                        let c = u32::from_str_radix(&hex, 16)
+                            .ok()
+                            .and_then(char::from_u32)
+                            .ok_or_else(|| {
+                                self.error(&format!("invalid unicode escape: \\u{}", hex))
+                            })?;
+                        s.push(c);
+                    }
+                    Some(c) => return Err(self.error(&format!("invalid escape: \\{}", c))),
+                    None => return Err(self.error("unterminated string")),
+                },
+                Some(c) => s.push(c),
+            }
+        }
+    }
+
+    /// Parses a character after its backslash, like `a`, `newline` or `u00e9`.
+    fn character(&mut self) -> error::Result<value::Value> {
+        let first = match self.next() {
+            Some(c) => c,
+            None => return Err(self.error("unterminated character")),
+        };
+        // Named characters are alphanumeric, so `\a\b` is two characters
+        let mut name = first.to_string();
+        if first.is_alphanumeric() {
+            while let Some(c) = self.peek().filter(|c| c.is_alphanumeric()) {
+                name.push(c);
+                self.next();
+            }
+        }
+        let c = match name.as_str() {
+            _ if name.chars().count() == 1 => first,
+            "newline" => '\n',
+            "return" => '\r',
+            "space" => ' ',
+            "tab" => '\t',
+            "formfeed" => '\u{0c}',
+            "backspace" => '\u{08}',
+            _ => name
+                .strip_prefix('u')
+                .filter(|hex| hex.len() == 4)
+                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                .and_then(char::from_u32)
+                .ok_or_else(|| self.error(&format!("invalid character: \\{}", name)))?,
+        };
+        Ok(value::Value::Char(c))
+    }
+
+    /// Parses the values of a collection after its opening bracket.
+    fn sequence(&mut self, end: char) -> error::Result<Vec<value::Value>> {
+        let mut values = Vec::new();
+        loop {
+            self.skip_space()?;
+            match self.peek() {
+                None => return Err(self.error(&format!("missing {}", end))),
+                Some(c) if c == end => {
+                    self.next();
+                    return Ok(values);
+                }
+                Some(_) => values.push(self.nested(Self::value)?),
+            }
+        }
+    }
+
+    /// Parses a map after its opening brace.  Keywords that are keys become strings, which get
+    /// the namespace of a namespaced map like `#:person{:name "Jo"}`.
+    fn map(&mut self, namespace: Option<&str>) -> error::Result<value::Value> {
+        let values = self.sequence('}')?;
+        if values.len() % 2 != 0 {
+            return Err(self.error("a map has a key without a value"));
+        }
+        let mut entries = Vec::with_capacity(values.len() / 2);
+        let mut values = values.into_iter();
+        while let (Some(key), Some(v)) = (values.next(), values.next()) {
+            let key = match key.get(KEYWORD).and_then(value::Value::as_str) {
+                Some(name) if key.as_map().is_some_and(|m| m.len() == 1) => {
+                    let name = match namespace {
+                        Some(_) if name.starts_with("_/") => name[2..].to_owned(),
+                        Some(namespace) if !name.contains('/') => {
+                            format!("{}/{}", namespace, name)
+                        }
+                        _ => name.to_owned(),
+                    };
+                    value::Value::String(format!(":{}", name))
+                }
+                _ => key,
+            };
+            entries.push((key, v));
+        }
+        Ok(value::Value::Map(entries))
+    }
+}
+
+fn write_value(output: &mut String, v: &value::Value) -> error::Result<()> {
+    match *v {
+        value::Value::Unit => output.push_str("nil"),
+        value::Value::Bool(b) => output.push_str(if b { "true" } else { "false" }),
+        value::Value::I8(n) => output.push_str(&n.to_string()),
+        value::Value::I16(n) => output.push_str(&n.to_string()),
+        value::Value::I32(n) => output.push_str(&n.to_string()),
+        value::Value::I64(n) => output.push_str(&n.to_string()),
+        value::Value::U8(n) => output.push_str(&n.to_string()),
+        value::Value::U16(n) => output.push_str(&n.to_string()),
+        value::Value::U32(n) => output.push_str(&n.to_string()),
+        value::Value::U64(n) => {
+            output.push_str(&n.to_string());
+            // EDN integers are 64-bit signed
+            if n > i64::MAX as u64 {
+                output.push('N');
+            }
+        }
+        value::Value::F32(_) | value::Value::F64(_) => {
+            let n = v.as_f64().expect("floats are numbers");
+            if n.is_nan() {
+                output.push_str("##NaN");
+            } else if n.is_infinite() {
+                output.push_str(if n > 0.0 { "##Inf" } else { "##-Inf" });
+            } else {
+                // Debug formatting keeps the decimal point of whole numbers
+                output.push_str(&format!("{:?}", n));
+            }
+        }
+        value::Value::Char(c) => write_char(output, c),
+        value::Value::String(ref s) => write_string(output, s),
+        value::Value::Bytes(ref bytes) => {
+            output.push('[');
+            for (i, b) in bytes.iter().enumerate() {
+                if i > 0 {
+                    output.push(' ');
+                }
+                output.push_str(&b.to_string());
+            }
+            output.push(']');
+        }
+        value::Value::Sequence(ref values) => write_values(output, "[", values, "]")?,
+        value::Value::Map(ref entries) => {
+            if !write_convention(output, entries)? {
+                output.push('{');
+                for (i, (key, v)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        output.push_str(", ");
+                    }
+                    match key.as_str().and_then(|s| s.strip_prefix(':')) {
+                        Some(name) if is_symbol(name) => {
+                            output.push(':');
+                            output.push_str(name);
+                        }
+                        _ => write_value(output, key)?,
+                    }
+                    output.push(' ');
+                    write_value(output, v)?;
+                }
+                output.push('}');
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Writes a map of the convention for EDN types as that type, and returns whether it was one.
+fn write_convention(
+    output: &mut String,
+    entries: &[(value::Value, value::Value)],
+) -> error::Result<bool> {
+    let invalid = |kind: &str, v: &value::Value| error::Error::Format {
+        msg: format!(
+            "invalid EDN {}: {}",
+            kind,
+            v.summary(value::ERROR_SUMMARY_LEN)
+        ),
+    };
+    match entries {
+        [(key, v)] => match (key.as_str(), v) {
+            (Some(KEYWORD), value::Value::String(name)) if is_symbol(name) => {
+                output.push(':');
+                output.push_str(name);
+            }
+            (Some(SYMBOL), value::Value::String(name)) if is_symbol(name) => output.push_str(name),
+            (Some(KEYWORD), v) => return Err(invalid("keyword", v)),
+            (Some(SYMBOL), v) => return Err(invalid("symbol", v)),
+            (Some(SET), value::Value::Sequence(values)) => write_values(output, "#{", values, "}")?,
+            (Some(LIST), value::Value::Sequence(values)) => write_values(output, "(", values, ")")?,
+            (Some(BIGINT), value::Value::String(digits)) => {
+                let unsigned = digits.strip_prefix('-').unwrap_or(digits);
+                if unsigned.is_empty() || !unsigned.bytes().all(|b| b.is_ascii_digit()) {
+                    return Err(invalid("big integer", v));
+                }
+                output.push_str(digits);
+                output.push('N');
+            }
+            (Some(DECIMAL), value::Value::String(digits)) => {
+                if digits.parse::<f64>().map_or(true, |n| !n.is_finite()) {
+                    return Err(invalid("decimal", v));
+                }
+                output.push_str(digits);
+                output.push('M');
+            }
+            _ => return Ok(false),
+        },
+        [(k1, tag), (k2, v)] if k1.as_str() == Some(TAG) && k2.as_str() == Some(VALUE) => {
+            match tag.as_str() {
+                Some(tag) if is_symbol(tag) && tag.starts_with(char::is_alphabetic) => {
+                    output.push('#');
+                    output.push_str(tag);
+                    output.push(' ');
+                    write_value(output, v)?;
+                }
+                _ => return Err(invalid("tag", tag)),
+            }
+        }
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+fn write_values(
+    output: &mut String,
+    start: &str,
+    values: &[value::Value],
+    end: &str,
+) -> error::Result<()> {
+    output.push_str(start);
+    for (i, v) in values.iter().enumerate() {
+        if i > 0 {
+            output.push(' ');
+        }
+        write_value(output, v)?;
+    }
+    output.push_str(end);
+    Ok(())
+}
+
+fn write_char(output: &mut String, c: char) {
+    output.push('\\');
+    match c {
+        '\n' => output.push_str("newline"),
+        '\r' => output.push_str("return"),
+        ' ' => output.push_str("space"),
+        '\t' => output.push_str("tab"),
+        '\u{0c}' => output.push_str("formfeed"),
+        '\u{08}' => output.push_str("backspace"),
+        c if c.is_control() || c.is_whitespace() => output.push_str(&format!("u{:04x}", c as u32)),
+        c => output.push(c),
+    }
+}
+
+fn write_string(output: &mut String, s: &str) {
+    output.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            '\t' => output.push_str("\\t"),
+            '\u{08}' => output.push_str("\\b"),
+            '\u{0c}' => output.push_str("\\f"),
+            c if c.is_control() => output.push_str(&format!("\\u{:04x}", c as u32)),
+            c => output.push(c),
+        }
+    }
+    output.push('"');
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::value::Sink as _;
+    use crate::value::Source as _;
+
+    fn read(input: &[u8]) -> error::Result<Vec<value::Value>> {
+        let mut reader = source(input)?;
+        let mut records = Vec::new();
+        while let Some(record) = reader.read()? {
+            records.push(record);
+        }
+        Ok(records)
+    }
+
+    fn write(records: Vec<value::Value>) -> error::Result<String> {
+        let mut output = Vec::new();
+        {
+            let mut writer = sink(&mut output);
+            for record in records {
+                writer.write(record)?;
+            }
+        }
+        Ok(String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let edn = r#"; Keys that are keywords become strings
+{:db/host "localhost", :tags #{:a b} :args (inc 1) #_ignored
+ :at #inst "2024-01-01T00:00:00Z" :n 1N :big 99999999999999999999 :price 1.50M
+ :chars [\a\b \newline] "s" nil}
+#:person{:name "Jo" :_/id 2}
+"#;
+        let records = read(edn.as_bytes()).unwrap();
+        assert_eq!(
+            records.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            vec![
+                r#"{":db/host": "localhost", ":tags": {"$set": [{"$keyword": "a"}, {"$symbol": "b"}]}, ":args": {"$list": [{"$symbol": "inc"}, 1]}, ":at": {"$tag": "inst", "$value": "2024-01-01T00:00:00Z"}, ":n": {"$bigint": "1"}, ":big": {"$bigint": "99999999999999999999"}, ":price": {"$decimal": "1.50"}, ":chars": ['a', 'b', '\n'], "s": null}"#,
+                r#"{":person/name": "Jo", ":id": 2}"#,
+            ]
+        );
+
+        let output = write(records.clone()).unwrap();
+        assert_eq!(
+            output,
+            r#"{:db/host "localhost", :tags #{:a b}, :args (inc 1), :at #inst "2024-01-01T00:00:00Z", :n 1N, :big 99999999999999999999N, :price 1.50M, :chars [\a \b \newline], "s" nil}
+{:person/name "Jo", :id 2}
+"#
+        );
+        // Written values read back to the same records
+        assert_eq!(read(output.as_bytes()).unwrap(), records);
+
+        assert!(read(b"{:a}").is_err());
+        assert!(read(b"[1 2").is_err());
+        assert!(read(br#"{"a": 1}"#).is_err());
+    }
+
+    #[test]
+    fn test_sink() {
+        let json = value::json::source(&br#"{"a": [1, 2.0, "x\n"]}"#[..])
+            .read()
+            .unwrap()
+            .unwrap();
+        assert_eq!(write(vec![json]).unwrap(), "{\"a\" [1 2.0 \"x\\n\"]}\n");
+        assert!(write(vec![value!({"$keyword": "not a name"})]).is_err());
+    }
+
+    #[test]
+    fn test_depth() {
+        let nested = |depth: usize| format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+        assert!(read(nested(128).as_bytes()).is_ok());
+        assert!(read(nested(129).as_bytes()).is_err());
+
+        // Tags and discarded values nest as well
+        for input in &[
+            nested(100_000),
+            "#a ".repeat(100_000),
+            "#_ ".repeat(100_000),
+        ] {
+            match read(input.as_bytes()) {
+                Err(error::Error::Message(msg)) => assert!(msg.contains("nested"), "{}", msg),
+                other => panic!("expected an error for deeply nested EDN, got {:?}", other),
+            }
+        }
+    }
+}
@@ -0,0 +1,57 @@
+//! A source that reads a single record from the environment variables of the process.
+
+use std::env;
+use std::ffi;
+
+use crate::error;
+use crate::value;
+
+#[derive(Debug)]
+pub struct Source(Option<value::Value>);
+
+/// Creates a source with one record: a map from environment variable names to their values,
+/// sorted by name.  If a `prefix` is given, only variables whose names start with it are
+/// included.  Variables that aren't valid Unicode are skipped.
+#[inline]
+pub fn source(prefix: Option<&str>) -> Source {
+    from_vars(env::vars_os(), prefix)
+}
+
+/// Like `source`, but reads the specified variables instead of the process environment.
+pub fn from_vars<I>(vars: I, prefix: Option<&str>) -> Source
+where
+    I: IntoIterator<Item = (ffi::OsString, ffi::OsString)>,
+{
+    let mut entries = Vec::new();
+    for (name, v) in vars {
+        match (name.into_string(), v.into_string()) {
+            (Ok(name), Ok(v)) => {
+                if prefix.is_none_or(|p| name.starts_with(p)) {
+                    entries.push((name, v));
+                }
+            }
+            (Ok(name), Err(_)) => warn!("Skipping environment variable {} (not Unicode)", name),
+            (Err(name), _) => warn!("Skipping environment variable {:?} (not Unicode)", name),
+        }
+    }
+    entries.sort();
+
+    let map = entries
+        .into_iter()
+        .map(|(name, v)| (value::Value::String(name), value::Value::String(v)))
+        .collect();
+    Source(Some(value::Value::Map(map)))
+}
+
+impl value::Source for Source {
+    #[inline]
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        Ok(self.0.take())
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let n = usize::from(self.0.is_some());
+        (n, Some(n))
+    }
+}
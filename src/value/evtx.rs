@@ -0,0 +1,813 @@
+//! Windows event logs (`.evtx` files), as written by the Windows Event Log service.
+//!
+//! Each event becomes a record with its `record_id`, the `timestamp` it was written, the fields
+//! of its `system` section like `EventID`, `Provider` and `TimeCreated`, and its `event_data` by
+//! the names of the data.  Other sections like `user_data` are there as well.  Elements become
+//! values like with XML input, with attributes prefixed with `@`, except that values keep their
+//! types: event IDs are numbers and times are RFC 3339.
+//!
+//! Events are read chunk by chunk, and the binary XML of each is rendered with the templates in
+//! its chunk.
+
+use std::collections;
+use std::convert::TryFrom;
+use std::io;
+
+use crate::error;
+use crate::value;
+
+const FILE_HEADER_LEN: usize = 4096;
+const CHUNK_LEN: usize = 65536;
+/// The size of the header of a chunk, with its tables of strings and templates.
+const CHUNK_HEADER_LEN: usize = 512;
+const RECORD_HEADER_LEN: usize = 24;
+
+/// How deeply templates, binary XML and elements are nested at most, which guards against cycles.
+const MAX_DEPTH: usize = 64;
+
+const TOKEN_END_OF_STREAM: u8 = 0x00;
+const TOKEN_OPEN_START_ELEMENT: u8 = 0x01;
+const TOKEN_CLOSE_START_ELEMENT: u8 = 0x02;
+const TOKEN_CLOSE_EMPTY_ELEMENT: u8 = 0x03;
+const TOKEN_END_ELEMENT: u8 = 0x04;
+const TOKEN_VALUE: u8 = 0x05;
+const TOKEN_ATTRIBUTE: u8 = 0x06;
+const TOKEN_CDATA: u8 = 0x07;
+const TOKEN_CHAR_REF: u8 = 0x08;
+const TOKEN_ENTITY_REF: u8 = 0x09;
+const TOKEN_PI_TARGET: u8 = 0x0a;
+const TOKEN_PI_DATA: u8 = 0x0b;
+const TOKEN_TEMPLATE_INSTANCE: u8 = 0x0c;
+const TOKEN_NORMAL_SUBSTITUTION: u8 = 0x0d;
+const TOKEN_OPTIONAL_SUBSTITUTION: u8 = 0x0e;
+const TOKEN_FRAGMENT_HEADER: u8 = 0x0f;
+/// The flag of tokens that are followed by more of their kind, like more attributes.
+const TOKEN_MORE: u8 = 0x40;
+
+const TYPE_NULL: u8 = 0x00;
+const TYPE_STRING: u8 = 0x01;
+const TYPE_BINARY_XML: u8 = 0x21;
+/// The flag of types that are arrays of their base type.
+const TYPE_ARRAY: u8 = 0x80;
+
+/// Seconds from the start of 1601, where Windows file times start, to the Unix epoch.
+const FILE_TIME_EPOCH: i64 = 11_644_473_600;
+
+#[derive(Debug)]
+pub struct Source {
+    input: Vec<u8>,
+    next_chunk: usize,
+    pending: collections::VecDeque<value::Value>,
+}
+
+/// Creates a source for the events of an event log, which is read in full from the input.
+pub fn source<R>(mut r: R) -> error::Result<Source>
+where
+    R: io::Read,
+{
+    let mut input = Vec::new();
+    r.read_to_end(&mut input)?;
+    if !input.starts_with(b"ElfFile\0") {
+        return Err(format_error("the input is not a Windows event log"));
+    }
+    if input.len() < FILE_HEADER_LEN {
+        return Err(format_error("the file header is truncated"));
+    }
+    Ok(Source {
+        input,
+        next_chunk: 0,
+        pending: collections::VecDeque::new(),
+    })
+}
+
+impl value::Source for Source {
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        while self.pending.is_empty() {
+            let start = FILE_HEADER_LEN + self.next_chunk * CHUNK_LEN;
+            let chunk = match self.input.get(start..start + CHUNK_LEN) {
+                Some(chunk) => chunk,
+                None if start >= self.input.len() => return Ok(None),
+                None => {
+                    return Err(format_error(&format!(
+                        "chunk {} is truncated",
+                        self.next_chunk
+                    )))
+                }
+            };
+            self.next_chunk += 1;
+            // Chunks that were never written are empty
+            if chunk.starts_with(b"ElfChnk\0") {
+                self.pending = events(chunk).map_err(|msg| {
+                    format_error(&format!("invalid chunk {}: {}", self.next_chunk - 1, msg))
+                })?;
+            }
+        }
+        Ok(self.pending.pop_front())
+    }
+}
+
+/// Reads the events of a chunk.
+fn events(chunk: &[u8]) -> Result<collections::VecDeque<value::Value>, String> {
+    let free_space = (u32_at(chunk, 48) as usize).min(CHUNK_LEN);
+    let mut events = collections::VecDeque::new();
+    let mut position = CHUNK_HEADER_LEN;
+    while position + RECORD_HEADER_LEN <= free_space && chunk[position..].starts_with(b"**\0\0") {
+        let size = u32_at(chunk, position + 4) as usize;
+        let id = u64_at(chunk, position + 8);
+        if size < RECORD_HEADER_LEN + 4 || position + size > free_space {
+            return Err(format!("invalid size of record {}", id));
+        }
+        let mut parser = Parser {
+            chunk,
+            position: position + RECORD_HEADER_LEN,
+            end: position + size - 4,
+        };
+        let nodes = parser
+            .content(&[], false, 0)
+            .map_err(|msg| format!("record {} at byte {}: {}", id, parser.position, msg))?;
+        events.push_back(event(id, u64_at(chunk, position + 16), nodes));
+        position += size;
+    }
+    Ok(events)
+}
+
+/// A node of rendered XML.
+enum Node {
+    Element(Element),
+    Text(value::Value),
+}
+
+struct Element {
+    name: String,
+    attributes: Vec<(String, value::Value)>,
+    children: Vec<Node>,
+}
+
+/// The value of a substitution in a template, by its type and where its data is in the chunk.
+#[derive(Clone, Copy)]
+struct Substitution {
+    kind: u8,
+    start: usize,
+    len: usize,
+}
+
+/// Renders binary XML, whose offsets are from the start of its chunk.
+struct Parser<'a> {
+    chunk: &'a [u8],
+    position: usize,
+    end: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.position + len > self.end {
+            return Err("binary XML is truncated".to_owned());
+        }
+        let bytes = &self.chunk[self.position..self.position + len];
+        self.position += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32_at(self.bytes(4)?, 0))
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.chunk[..self.end].get(self.position).copied()
+    }
+
+    /// Reads a string with its length in characters before it.
+    fn string(&mut self) -> Result<String, String> {
+        let len = usize::from(self.u16()?);
+        Ok(utf16(self.bytes(len * 2)?))
+    }
+
+    /// Reads a name, which is stored once per chunk and referred to by its offset.
+    fn name(&mut self) -> Result<String, String> {
+        let offset = self.u32()? as usize;
+        // A name that is stored here for the first time is skipped
+        if offset == self.position {
+            self.bytes(6)?;
+            self.string()?;
+            self.bytes(2)?;
+        }
+        let len = self
+            .chunk
+            .get(offset + 6..offset + 8)
+            .ok_or("invalid name")?;
+        let len = usize::from(u16::from_le_bytes([len[0], len[1]]));
+        let name = self
+            .chunk
+            .get(offset + 8..offset + 8 + len * 2)
+            .ok_or("invalid name")?;
+        Ok(utf16(name))
+    }
+
+    /// Renders nodes up to the end of an element or of the stream.  Elements that are directly in
+    /// binary XML in a substitution are stored without their dependency identifier.
+    fn content(
+        &mut self,
+        substitutions: &[Substitution],
+        nested: bool,
+        depth: usize,
+    ) -> Result<Vec<Node>, String> {
+        let mut nodes = Vec::new();
+        while self.position < self.end {
+            let token = self.u8()?;
+            match token & !TOKEN_MORE {
+                TOKEN_END_OF_STREAM | TOKEN_END_ELEMENT => break,
+                TOKEN_OPEN_START_ELEMENT => {
+                    let element = self.element(token, substitutions, nested, depth)?;
+                    nodes.push(Node::Element(element));
+                }
+                TOKEN_VALUE
+                | TOKEN_CHAR_REF
+                | TOKEN_ENTITY_REF
+                | TOKEN_NORMAL_SUBSTITUTION
+                | TOKEN_OPTIONAL_SUBSTITUTION => {
+                    nodes.extend(self.value(token, substitutions, depth)?);
+                }
+                TOKEN_CDATA => nodes.push(Node::Text(self.string()?.into())),
+                TOKEN_PI_TARGET => {
+                    self.name()?;
+                }
+                TOKEN_PI_DATA => {
+                    self.string()?;
+                }
+                TOKEN_TEMPLATE_INSTANCE => nodes.extend(self.template(depth)?),
+                TOKEN_FRAGMENT_HEADER => {
+                    self.bytes(3)?;
+                }
+                _ => return Err(format!("unknown token 0x{:02x}", token)),
+            }
+        }
+        Ok(nodes)
+    }
+
+    fn element(
+        &mut self,
+        token: u8,
+        substitutions: &[Substitution],
+        nested: bool,
+        depth: usize,
+    ) -> Result<Element, String> {
+        if depth >= MAX_DEPTH {
+            return Err("elements are nested too deeply".to_owned());
+        }
+        if !nested {
+            self.u16()?;
+        }
+        self.u32()?;
+        let name = self.name()?;
+        let mut attributes = Vec::new();
+        if token & TOKEN_MORE != 0 {
+            self.u32()?;
+            while self.peek().map(|t| t & !TOKEN_MORE) == Some(TOKEN_ATTRIBUTE) {
+                self.u8()?;
+                let name = self.name()?;
+                let mut values = Vec::new();
+                while let Some(token) = self.peek().filter(|&t| is_value_token(t)) {
+                    self.u8()?;
+                    for node in self.value(token, substitutions, depth)? {
+                        if let Node::Text(v) = node {
+                            values.push(v);
+                        }
+                    }
+                }
+                if let Some(v) = join(values) {
+                    attributes.push((name, v));
+                }
+            }
+        }
+        let children = match self.u8()? {
+            TOKEN_CLOSE_START_ELEMENT => self.content(substitutions, nested, depth + 1)?,
+            TOKEN_CLOSE_EMPTY_ELEMENT => Vec::new(),
+            token => {
+                return Err(format!(
+                    "unexpected token 0x{:02x} in element {}",
+                    token, name
+                ))
+            }
+        };
+        Ok(Element {
+            name,
+            attributes,
+            children,
+        })
+    }
+
+    /// Renders a value token or substitution, which is nothing for optional substitutions without
+    /// a value.
+    fn value(
+        &mut self,
+        token: u8,
+        substitutions: &[Substitution],
+        depth: usize,
+    ) -> Result<Vec<Node>, String> {
+        let v = match token & !TOKEN_MORE {
+            TOKEN_VALUE => match self.u8()? {
+                TYPE_STRING => self.string()?.into(),
+                kind => return Err(format!("unsupported value type 0x{:02x}", kind)),
+            },
+            TOKEN_CHAR_REF => {
+                let c = char::from_u32(u32::from(self.u16()?)).unwrap_or('\u{fffd}');
+                value::Value::String(c.to_string())
+            }
+            TOKEN_ENTITY_REF => match self.name()?.as_str() {
+                "amp" => "&".into(),
+                "lt" => "<".into(),
+                "gt" => ">".into(),
+                "quot" => "\"".into(),
+                "apos" => "'".into(),
+                name => format!("&{};", name).into(),
+            },
+            _ => {
+                let id = usize::from(self.u16()?);
+                self.u8()?;
+                let substitution = match substitutions.get(id) {
+                    Some(&substitution) => substitution,
+                    None => return Err(format!("missing substitution {}", id)),
+                };
+                if substitution.kind == TYPE_BINARY_XML {
+                    if depth >= MAX_DEPTH {
+                        return Err("binary XML is nested too deeply".to_owned());
+                    }
+                    let mut parser = Parser {
+                        chunk: self.chunk,
+                        position: substitution.start,
+                        end: substitution.start + substitution.len,
+                    };
+                    return parser.content(&[], true, depth + 1);
+                }
+                match substitution_value(
+                    &self.chunk[substitution.start..][..substitution.len],
+                    substitution.kind,
+                ) {
+                    value::Value::Unit => return Ok(Vec::new()),
+                    value::Value::String(s) if s.is_empty() => return Ok(Vec::new()),
+                    v => v,
+                }
+            }
+        };
+        Ok(vec![Node::Text(v)])
+    }
+
+    /// Renders a template instance, whose definition is either here or earlier in the chunk, with
+    /// the values for its substitutions.
+    fn template(&mut self, depth: usize) -> Result<Vec<Node>, String> {
+        if depth >= MAX_DEPTH {
+            return Err("templates are nested too deeply".to_owned());
+        }
+        self.bytes(5)?;
+        let definition = self.u32()? as usize;
+        let header = self
+            .chunk
+            .get(definition..definition + 24)
+            .ok_or("invalid template offset")?;
+        let len = u32_at(header, 20) as usize;
+        if definition == self.position {
+            self.bytes(24 + len)?;
+        }
+
+        let count = self.u32()? as usize;
+        let descriptors = self.bytes(count.checked_mul(4).ok_or("invalid substitutions")?)?;
+        let mut substitutions = Vec::with_capacity(count);
+        for descriptor in descriptors.chunks_exact(4) {
+            let len = usize::from(u16::from_le_bytes([descriptor[0], descriptor[1]]));
+            substitutions.push(Substitution {
+                kind: descriptor[2],
+                start: self.position,
+                len,
+            });
+            self.bytes(len)?;
+        }
+
+        let mut parser = Parser {
+            chunk: self.chunk,
+            position: definition + 24,
+            end: (definition + 24 + len).min(self.chunk.len()),
+        };
+        parser.content(&substitutions, false, depth + 1)
+    }
+}
+
+fn is_value_token(token: u8) -> bool {
+    matches!(
+        token & !TOKEN_MORE,
+        TOKEN_VALUE
+            | TOKEN_CHAR_REF
+            | TOKEN_ENTITY_REF
+            | TOKEN_NORMAL_SUBSTITUTION
+            | TOKEN_OPTIONAL_SUBSTITUTION
+    )
+}
+
+/// Converts the value of a substitution to its type.
+fn substitution_value(data: &[u8], kind: u8) -> value::Value {
+    if kind & TYPE_ARRAY != 0 {
+        let kind = kind & !TYPE_ARRAY;
+        let elements = match kind {
+            TYPE_STRING => utf16(data)
+                .split('\0')
+                .filter(|s| !s.is_empty())
+                .map(Into::into)
+                .collect(),
+            _ => match type_len(kind) {
+                Some(len) => data
+                    .chunks_exact(len)
+                    .map(|element| substitution_value(element, kind))
+                    .collect(),
+                None => return value::Value::Bytes(data.to_vec()),
+            },
+        };
+        return value::Value::Sequence(elements);
+    }
+    if data.is_empty() || kind == TYPE_NULL {
+        return value::Value::Unit;
+    }
+    let fixed = |len: usize| data.len() == len && type_len(kind) == Some(len);
+    match kind {
+        TYPE_STRING => utf16(data).trim_end_matches('\0').to_owned().into(),
+        0x02 => encoding_rs::WINDOWS_1252
+            .decode(data)
+            .0
+            .trim_end_matches('\0')
+            .to_owned()
+            .into(),
+        0x03 if fixed(1) => value::Value::I8(data[0] as i8),
+        0x04 if fixed(1) => value::Value::U8(data[0]),
+        0x05 if fixed(2) => value::Value::I16(i16::from_le_bytes([data[0], data[1]])),
+        0x06 if fixed(2) => value::Value::U16(u16::from_le_bytes([data[0], data[1]])),
+        0x07 if fixed(4) => value::Value::I32(u32_at(data, 0) as i32),
+        0x08 if fixed(4) => value::Value::U32(u32_at(data, 0)),
+        0x09 if fixed(8) => value::Value::I64(u64_at(data, 0) as i64),
+        0x0a if fixed(8) => value::Value::U64(u64_at(data, 0)),
+        0x0b if fixed(4) => value::Value::from_f32(f32::from_bits(u32_at(data, 0))),
+        0x0c if fixed(8) => value::Value::from_f64(f64::from_bits(u64_at(data, 0))),
+        0x0d if fixed(4) => value::Value::Bool(u32_at(data, 0) != 0),
+        0x0f if fixed(16) => guid(data).into(),
+        0x10 if data.len() == 4 => format!("0x{:x}", u32_at(data, 0)).into(),
+        0x10 if data.len() == 8 => format!("0x{:x}", u64_at(data, 0)).into(),
+        0x11 if fixed(8) => file_time(u64_at(data, 0)).into(),
+        0x12 if fixed(16) => {
+            let field = |i: usize| u16::from_le_bytes([data[i * 2], data[i * 2 + 1]]);
+            format!(
+                "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+                field(0),
+                field(1),
+                field(3),
+                field(4),
+                field(5),
+                field(6),
+                field(7)
+            )
+            .into()
+        }
+        0x13 => sid(data).map_or_else(|| value::Value::Bytes(data.to_vec()), Into::into),
+        0x14 if fixed(4) => format!("0x{:x}", u32_at(data, 0)).into(),
+        0x15 if fixed(8) => format!("0x{:x}", u64_at(data, 0)).into(),
+        _ => value::Value::Bytes(data.to_vec()),
+    }
+}
+
+/// The size of the values of types with a fixed size.
+fn type_len(kind: u8) -> Option<usize> {
+    match kind {
+        0x03 | 0x04 => Some(1),
+        0x05 | 0x06 => Some(2),
+        0x07 | 0x08 | 0x0b | 0x0d | 0x14 => Some(4),
+        0x09 | 0x0a | 0x0c | 0x11 | 0x15 => Some(8),
+        0x0f | 0x12 => Some(16),
+        _ => None,
+    }
+}
+
+/// Formats a GUID like `{54849625-5478-4994-a5ba-3e3b0328c30d}`.
+fn guid(data: &[u8]) -> String {
+    format!(
+        "{{{:08x}-{:04x}-{:04x}-{}-{}}}",
+        u32_at(data, 0),
+        u16::from_le_bytes([data[4], data[5]]),
+        u16::from_le_bytes([data[6], data[7]]),
+        hex(&data[8..10]),
+        hex(&data[10..16])
+    )
+}
+
+/// Formats a security identifier like `S-1-5-18`.
+fn sid(data: &[u8]) -> Option<String> {
+    let count = usize::from(*data.get(1)?);
+    let authority = data
+        .get(2..8)?
+        .iter()
+        .fold(0u64, |authority, &b| authority << 8 | u64::from(b));
+    let mut sid = format!("S-{}-{}", data[0], authority);
+    for i in 0..count {
+        let sub_authority = data.get(8 + i * 4..12 + i * 4)?;
+        sid.push_str(&format!("-{}", u32_at(sub_authority, 0)));
+    }
+    Some(sid)
+}
+
+/// Formats a Windows file time, in 100 nanoseconds since 1601, as RFC 3339 with microseconds.
+fn file_time(ticks: u64) -> String {
+    let seconds = i64::try_from(ticks / 10_000_000).unwrap_or(i64::MAX) - FILE_TIME_EPOCH;
+    let time = value::x509::format_time(seconds);
+    format!(
+        "{}.{:06}Z",
+        time.trim_end_matches('Z'),
+        ticks % 10_000_000 / 10
+    )
+}
+
+/// Turns the rendered XML of an event into a record.
+fn event(id: u64, written: u64, nodes: Vec<Node>) -> value::Value {
+    let mut entries = vec![
+        ("record_id".into(), value::Value::U64(id)),
+        ("timestamp".into(), file_time(written).into()),
+    ];
+    let sections = nodes.into_iter().find_map(|node| match node {
+        Node::Element(event) => Some(event.children),
+        Node::Text(_) => None,
+    });
+    for node in sections.into_iter().flatten() {
+        if let Node::Element(section) = node {
+            let key = match section.name.as_str() {
+                "System" => "system".to_owned(),
+                "EventData" => "event_data".to_owned(),
+                "UserData" => "user_data".to_owned(),
+                "RenderingInfo" => "rendering_info".to_owned(),
+                name => name.to_owned(),
+            };
+            let v = if section.name == "EventData" {
+                event_data(section)
+            } else {
+                element_value(section)
+            };
+            entries.push((key.into(), v));
+        }
+    }
+    value::Value::Map(entries)
+}
+
+/// Converts the event data to a map by the names of the data, or their element names if they
+/// have none.
+fn event_data(element: Element) -> value::Value {
+    let mut entries = Vec::new();
+    for node in element.children {
+        if let Node::Element(child) = node {
+            let name = match child.name.as_str() {
+                "Data" => child
+                    .attributes
+                    .iter()
+                    .find(|(name, _)| name == "Name")
+                    .and_then(|(_, name)| name.as_str().map(str::to_owned)),
+                _ => None,
+            };
+            match name {
+                Some(name) => entries.push((
+                    name,
+                    join(text(child.children)).unwrap_or(value::Value::Unit),
+                )),
+                None => entries.push((child.name.clone(), element_value(child))),
+            }
+        }
+    }
+    group(entries)
+}
+
+/// Converts an element like XML input does, with the values of its text keeping their types.
+fn element_value(element: Element) -> value::Value {
+    let is_text = element.attributes.is_empty()
+        && element
+            .children
+            .iter()
+            .all(|node| matches!(node, Node::Text(_)));
+    if is_text {
+        return join(text(element.children)).unwrap_or(value::Value::Unit);
+    }
+    let mut entries: Vec<_> = element
+        .attributes
+        .into_iter()
+        .filter(|(name, _)| name != "xmlns")
+        .map(|(name, v)| (format!("@{}", name), v))
+        .collect();
+    let mut texts = Vec::new();
+    for node in element.children {
+        match node {
+            Node::Element(child) => entries.push((child.name.clone(), element_value(child))),
+            Node::Text(v) => texts.push(v),
+        }
+    }
+    if let Some(v) = join(texts) {
+        entries.push((value::xml::TEXT_KEY.to_owned(), v));
+    }
+    group(entries)
+}
+
+fn text(nodes: Vec<Node>) -> Vec<value::Value> {
+    nodes
+        .into_iter()
+        .filter_map(|node| match node {
+            Node::Text(v) => Some(v),
+            Node::Element(_) => None,
+        })
+        .collect()
+}
+
+/// Joins values, which keep their type if there is only one.
+fn join(mut values: Vec<value::Value>) -> Option<value::Value> {
+    match values.len() {
+        0 => None,
+        1 => values.pop(),
+        _ => Some(
+            values
+                .into_iter()
+                .map(|v| match v {
+                    value::Value::String(s) => s,
+                    v => v.to_string(),
+                })
+                .collect::<String>()
+                .into(),
+        ),
+    }
+}
+
+/// Makes a map of entries, where the values of repeated keys become a sequence.
+fn group(entries: Vec<(String, value::Value)>) -> value::Value {
+    let mut grouped: Vec<(String, Vec<value::Value>)> = Vec::new();
+    for (key, v) in entries {
+        match grouped.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, values)) => values.push(v),
+            None => grouped.push((key, vec![v])),
+        }
+    }
+    value::Value::Map(
+        grouped
+            .into_iter()
+            .map(|(key, mut values)| {
+                let v = if values.len() == 1 {
+                    values.pop().unwrap()
+                } else {
+                    value::Value::Sequence(values)
+                };
+                (key.into(), v)
+            })
+            .collect(),
+    )
+}
+
+fn utf16(bytes: &[u8]) -> String {
+    let units: Vec<u16> = bytes
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .collect();
+    String::from_utf16_lossy(&units)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+
+fn u64_at(bytes: &[u8], at: usize) -> u64 {
+    u64::from(u32_at(bytes, at)) | u64::from(u32_at(bytes, at + 4)) << 32
+}
+
+fn format_error(msg: &str) -> error::Error {
+    error::Error::Format {
+        msg: format!("invalid event log: {}", msg),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::value::Source as _;
+
+    /// Elements nested in each other in binary XML without dependency identifiers, which are all
+    /// named by the name at the start of the chunk.
+    fn nested(depth: usize) -> Vec<u8> {
+        let mut chunk = b"\0\0\0\0\0\0\x01\0a\0".to_vec();
+        for _ in 1..depth {
+            chunk.extend_from_slice(&[TOKEN_OPEN_START_ELEMENT, 0, 0, 0, 0, 0, 0, 0, 0]);
+            chunk.push(TOKEN_CLOSE_START_ELEMENT);
+        }
+        chunk.extend_from_slice(&[TOKEN_OPEN_START_ELEMENT, 0, 0, 0, 0, 0, 0, 0, 0]);
+        chunk.push(TOKEN_CLOSE_EMPTY_ELEMENT);
+        chunk.resize(chunk.len() + depth - 1, TOKEN_END_ELEMENT);
+        chunk
+    }
+
+    fn parse(chunk: &[u8]) -> Result<Vec<Node>, String> {
+        let mut parser = Parser {
+            chunk,
+            position: 10,
+            end: chunk.len(),
+        };
+        parser.content(&[], true, 0)
+    }
+
+    fn read_all(input: &[u8]) -> error::Result<Vec<value::Value>> {
+        let mut reader = source(input)?;
+        let mut records = Vec::new();
+        while let Some(record) = reader.read()? {
+            records.push(record);
+        }
+        Ok(records)
+    }
+
+    #[test]
+    fn test_log() {
+        // Names and the template are stored inline, at offsets from the start of the chunk
+        fn name(b: &mut Vec<u8>, name: &str) {
+            let offset = 536 + b.len() as u32 + 4;
+            b.extend_from_slice(&offset.to_le_bytes());
+            b.extend_from_slice(&[0; 6]);
+            b.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            b.extend(name.encode_utf16().flat_map(u16::to_le_bytes));
+            b.extend_from_slice(&[0; 2]);
+        }
+        fn open(b: &mut Vec<u8>, token: u8, element: &str) {
+            b.extend_from_slice(&[token, 0, 0, 0, 0, 0, 0]);
+            name(b, element);
+        }
+        let mut b = b"\x0f\x01\x01\0\x0c\x01\0\0\0\0".to_vec();
+        b.extend_from_slice(&(536 + b.len() as u32 + 4).to_le_bytes());
+        b.extend_from_slice(&[0; 24]);
+        let definition = b.len();
+        b.extend_from_slice(b"\x0f\x01\x01\0");
+        open(&mut b, 0x01, "Event");
+        b.push(0x02);
+        open(&mut b, 0x01, "System");
+        b.push(0x02);
+        open(&mut b, 0x01, "EventID");
+        b.extend_from_slice(b"\x02\x0d\0\0\x06\x04");
+        open(&mut b, 0x41, "TimeCreated");
+        b.extend_from_slice(&[0; 4]);
+        b.push(0x06);
+        name(&mut b, "SystemTime");
+        b.extend_from_slice(b"\x0d\x01\0\x11\x03\x04");
+        open(&mut b, 0x01, "EventData");
+        b.push(0x02);
+        open(&mut b, 0x41, "Data");
+        b.extend_from_slice(&[0; 4]);
+        b.push(0x06);
+        name(&mut b, "Name");
+        b.extend_from_slice(b"\x05\x01\x04\0U\0s\0e\0r\0\x02\x0d\x02\0\x01\x04\x04\x04\0");
+        let len = (b.len() - definition) as u32;
+        b[definition - 4..definition].copy_from_slice(&len.to_le_bytes());
+        b.extend_from_slice(b"\x03\0\0\0\x02\0\x06\0\x08\0\x11\0\x0a\0\x01\0\x10\x12");
+        let written = 133_589_544_001_234_567_u64.to_le_bytes();
+        b.extend_from_slice(&written);
+        b.extend_from_slice(b"a\0l\0i\0c\0e\0\0");
+
+        let size = (24 + b.len() + 4) as u32;
+        let mut log = vec![0; 4096 + 65536];
+        log[..8].copy_from_slice(b"ElfFile\0");
+        log[4096..4104].copy_from_slice(b"ElfChnk\0");
+        log[4144..4148].copy_from_slice(&(512 + size).to_le_bytes());
+        let record = [
+            &b"**\0\0"[..],
+            &size.to_le_bytes(),
+            &1_u64.to_le_bytes(),
+            &written,
+            &b,
+            &size.to_le_bytes(),
+        ]
+        .concat();
+        log[4608..4608 + record.len()].copy_from_slice(&record);
+        let records = read_all(&log).unwrap();
+        assert_eq!(
+            records.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            vec![
+                r#"{"record_id": 1, "timestamp": "2024-04-30T12:40:00.123456Z", "system": {"EventID": 4624, "TimeCreated": {"@SystemTime": "2024-04-30T12:40:00.123456Z"}}, "event_data": {"User": "alice"}}"#
+            ]
+        );
+        assert!(read_all(&log[..5000]).is_err());
+        assert!(read_all(&b"ElfFile\0"[..]).is_err());
+    }
+
+    #[test]
+    fn test_depth() {
+        assert_eq!(parse(&nested(MAX_DEPTH)).unwrap().len(), 1);
+        for depth in &[MAX_DEPTH + 1, 200_000] {
+            match parse(&nested(*depth)) {
+                Err(msg) => assert!(msg.contains("nested"), "{}", msg),
+                Ok(_) => panic!("expected an error for elements nested {} deep", depth),
+            }
+        }
+    }
+}
@@ -0,0 +1,963 @@
+//! FlatBuffers, decoded with a binary reflection schema (`.bfbs`, made by `flatc --binary
+//! --schema`).
+//!
+//! Tables become maps with all fields of the schema in declaration order: absent scalars take
+//! their defaults, other absent fields are null, and enums are rendered by name, like `flatc
+//! --json --defaults-json` does.  Unions have their type in the `<field>_type` field, like in
+//! the schema.
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::io;
+
+use crate::error;
+use crate::value;
+
+/// How many elements of a vector are allocated for at most before they are read.
+const MAX_PREALLOCATED: usize = 4096;
+/// How deeply tables and structs are nested at most.
+const MAX_DEPTH: usize = 128;
+
+/// A reflection schema.
+#[derive(Debug)]
+pub struct Schema {
+    objects: Vec<Object>,
+    enums: Vec<Enum>,
+    root_table: Option<usize>,
+}
+
+#[derive(Debug)]
+struct Object {
+    name: String,
+    /// The fields in declaration order, which for structs is also their order in memory.
+    fields: Vec<Field>,
+    is_struct: bool,
+    bytesize: usize,
+}
+
+#[derive(Debug)]
+struct Field {
+    name: String,
+    type_: Type,
+    /// The offset in the vtable for tables, or in the struct for structs.
+    offset: usize,
+    default_integer: i64,
+    default_real: f64,
+    deprecated: bool,
+    optional: bool,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Type {
+    base_type: BaseType,
+    element: BaseType,
+    /// The object or enum that the type refers to, if any.
+    index: Option<usize>,
+    fixed_length: usize,
+    element_size: usize,
+}
+
+#[derive(Debug)]
+struct Enum {
+    name: String,
+    values: Vec<EnumVal>,
+}
+
+#[derive(Debug)]
+struct EnumVal {
+    name: String,
+    value: i64,
+    /// The table of union members.
+    object: Option<usize>,
+}
+
+/// The base types of `reflection.fbs`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum BaseType {
+    None,
+    UType,
+    Bool,
+    Byte,
+    UByte,
+    Short,
+    UShort,
+    Int,
+    UInt,
+    Long,
+    ULong,
+    Float,
+    Double,
+    String,
+    Vector,
+    Obj,
+    Union,
+    Array,
+    Vector64,
+}
+
+/// A source for FlatBuffers from the input, which is either a single buffer or a stream of
+/// buffers that are each prefixed with their size.
+pub struct Source {
+    schema: Schema,
+    root: usize,
+    input: Vec<u8>,
+    size_prefixed: bool,
+    offset: usize,
+}
+
+impl Schema {
+    /// Reads a binary reflection schema.
+    pub fn parse(bfbs: &[u8]) -> error::Result<Schema> {
+        let buffer = Buffer(bfbs);
+        let schema = buffer.root()?;
+        let objects = buffer
+            .tables(schema, 0)?
+            .into_iter()
+            .map(|object| read_object(&buffer, object))
+            .collect::<error::Result<Vec<_>>>()?;
+        let enums = buffer
+            .tables(schema, 1)?
+            .into_iter()
+            .map(|e| read_enum(&buffer, e))
+            .collect::<error::Result<Vec<_>>>()?;
+        let root_table = match buffer.table(schema, 4)? {
+            Some(root) => {
+                let name = buffer.required_string(root, 0)?;
+                objects.iter().position(|object| object.name == name)
+            }
+            None => None,
+        };
+        Ok(Schema {
+            objects,
+            enums,
+            root_table,
+        })
+    }
+
+    fn object_named(&self, name: &str) -> error::Result<usize> {
+        // Objects are fully qualified, like `example.Monster`, but the namespace may be left out
+        self.objects
+            .iter()
+            .position(|object| object.name == name)
+            .or_else(|| {
+                self.objects
+                    .iter()
+                    .position(|object| object.name.rsplit('.').next() == Some(name))
+            })
+            .ok_or_else(|| error::Error::Message(format!("the schema has no table {}", name)))
+    }
+}
+
+/// Creates a source for the FlatBuffers in the input, which is read in full, with the root
+/// table of the schema or the named table.
+pub fn source<R>(
+    schema: Schema,
+    root: Option<&str>,
+    mut r: R,
+    size_prefixed: bool,
+) -> error::Result<Source>
+where
+    R: io::Read,
+{
+    let root = match root {
+        Some(name) => schema.object_named(name)?,
+        None => schema.root_table.ok_or_else(|| {
+            error::Error::Message(
+                "the schema has no root type, so the root table must be named".to_owned(),
+            )
+        })?,
+    };
+    let mut input = Vec::new();
+    r.read_to_end(&mut input)?;
+    Ok(Source {
+        schema,
+        root,
+        input,
+        size_prefixed,
+        offset: 0,
+    })
+}
+
+impl value::Source for Source {
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        let remaining = &self.input[self.offset..];
+        let data = if self.size_prefixed {
+            if remaining.is_empty() {
+                return Ok(None);
+            }
+            let size = Buffer(remaining).u32(0)? as usize;
+            let data = remaining.get(4..4 + size).ok_or_else(|| {
+                invalid(format!("the buffer at byte {} is truncated", self.offset))
+            })?;
+            self.offset += 4 + size;
+            data
+        } else if self.offset == 0 && !remaining.is_empty() {
+            self.offset = self.input.len();
+            remaining
+        } else {
+            return Ok(None);
+        };
+        let buffer = Buffer(data);
+        let root = buffer.root()?;
+        let decoder = Decoder {
+            schema: &self.schema,
+            buffer,
+        };
+        Ok(Some(decoder.table(
+            &self.schema.objects[self.root],
+            root,
+            0,
+        )?))
+    }
+}
+
+/// Decodes the values of a buffer as described by a schema.
+struct Decoder<'a> {
+    schema: &'a Schema,
+    buffer: Buffer<'a>,
+}
+
+impl Decoder<'_> {
+    fn table(&self, object: &Object, table: usize, depth: usize) -> error::Result<value::Value> {
+        if object.is_struct {
+            return self.struct_at(object, table, depth);
+        }
+        check_depth(depth)?;
+        let mut entries = Vec::with_capacity(object.fields.len());
+        for field in object.fields.iter().filter(|field| !field.deprecated) {
+            let position = self.buffer.field(table, field.offset)?;
+            let v = match position {
+                Some(position) => self.field(object, field, table, position, depth)?,
+                None if field.optional => value::Value::Unit,
+                None => default(self.schema, field),
+            };
+            entries.push((value::Value::String(field.name.clone()), v));
+        }
+        Ok(value::Value::Map(entries))
+    }
+
+    fn struct_at(
+        &self,
+        object: &Object,
+        position: usize,
+        depth: usize,
+    ) -> error::Result<value::Value> {
+        // Structs can't contain themselves in a valid schema, but the schema is input, too
+        check_depth(depth)?;
+        let mut entries = Vec::with_capacity(object.fields.len());
+        for field in &object.fields {
+            let position = position + field.offset;
+            let v = match field.type_.base_type {
+                BaseType::Obj => self.struct_at(self.object(field.type_)?, position, depth + 1)?,
+                BaseType::Array => {
+                    let size = element_size(self.schema, field.type_)?;
+                    let mut items = Vec::with_capacity(field.type_.fixed_length);
+                    for i in 0..field.type_.fixed_length {
+                        items.push(self.element(field.type_, position + i * size, depth)?);
+                    }
+                    value::Value::Sequence(items)
+                }
+                base_type => self.scalar(base_type, field.type_.index, position)?,
+            };
+            entries.push((value::Value::String(field.name.clone()), v));
+        }
+        Ok(value::Value::Map(entries))
+    }
+
+    /// Decodes a field of a table that is present at the position.
+    fn field(
+        &self,
+        object: &Object,
+        field: &Field,
+        table: usize,
+        position: usize,
+        depth: usize,
+    ) -> error::Result<value::Value> {
+        let type_ = field.type_;
+        match type_.base_type {
+            BaseType::String => self.string(self.buffer.indirect(position)?),
+            BaseType::Obj => {
+                let target = self.object(type_)?;
+                if target.is_struct {
+                    self.struct_at(target, position, depth + 1)
+                } else {
+                    self.table(target, self.buffer.indirect(position)?, depth + 1)
+                }
+            }
+            BaseType::Union => {
+                let utype = self.union_type(object, field, table)?;
+                let member = self.union_member(type_, utype)?;
+                match member {
+                    Some(member) => self.table(member, self.buffer.indirect(position)?, depth + 1),
+                    None => Ok(value::Value::Unit),
+                }
+            }
+            BaseType::Vector => {
+                let vector = self.buffer.indirect(position)?;
+                let len = self.buffer.u32(vector)? as usize;
+                let start = vector + 4;
+                if type_.element == BaseType::Union {
+                    let types = self.union_types(object, field, table)?;
+                    let mut items = Vec::with_capacity(len.min(MAX_PREALLOCATED));
+                    for i in 0..len {
+                        let utype = types.get(i).copied().unwrap_or(0);
+                        let v = match self.union_member(type_, utype)? {
+                            Some(member) => {
+                                self.table(member, self.buffer.indirect(start + i * 4)?, depth + 1)?
+                            }
+                            None => value::Value::Unit,
+                        };
+                        items.push(v);
+                    }
+                    return Ok(value::Value::Sequence(items));
+                }
+                if type_.element == BaseType::UByte && type_.index.is_none() {
+                    // Byte vectors are usually binary data
+                    return Ok(value::Value::Bytes(self.buffer.slice(start, len)?.to_vec()));
+                }
+                let size = element_size(self.schema, type_)?;
+                let mut items = Vec::with_capacity(len.min(MAX_PREALLOCATED));
+                for i in 0..len {
+                    items.push(self.element(type_, start + i * size, depth)?);
+                }
+                Ok(value::Value::Sequence(items))
+            }
+            BaseType::Vector64 | BaseType::Array | BaseType::None => {
+                Err(error::Error::unimplemented(format!(
+                    "FlatBuffers fields of type {:?}",
+                    type_.base_type
+                )))
+            }
+            base_type => self.scalar(base_type, type_.index, position),
+        }
+    }
+
+    /// Decodes an element of a vector or array at the position.
+    fn element(&self, type_: Type, position: usize, depth: usize) -> error::Result<value::Value> {
+        match type_.element {
+            BaseType::String => self.string(self.buffer.indirect(position)?),
+            BaseType::Obj => {
+                let target = self.object(type_)?;
+                if target.is_struct {
+                    self.struct_at(target, position, depth + 1)
+                } else {
+                    self.table(target, self.buffer.indirect(position)?, depth + 1)
+                }
+            }
+            element => self.scalar(element, type_.index, position),
+        }
+    }
+
+    fn scalar(
+        &self,
+        base_type: BaseType,
+        index: Option<usize>,
+        position: usize,
+    ) -> error::Result<value::Value> {
+        let b = &self.buffer;
+        let v = match base_type {
+            BaseType::Bool => return Ok(value::Value::Bool(b.u8(position)? != 0)),
+            BaseType::Float => return Ok(value::Value::from_f32(f32::from_bits(b.u32(position)?))),
+            BaseType::Double => {
+                return Ok(value::Value::from_f64(f64::from_bits(b.u64(position)?)))
+            }
+            BaseType::Byte => b.u8(position)? as i8 as i64,
+            BaseType::UByte | BaseType::UType => b.u8(position)? as i64,
+            BaseType::Short => b.u16(position)? as i16 as i64,
+            BaseType::UShort => b.u16(position)? as i64,
+            BaseType::Int => b.u32(position)? as i32 as i64,
+            BaseType::UInt => b.u32(position)? as i64,
+            BaseType::Long => b.u64(position)? as i64,
+            BaseType::ULong => return Ok(self.integer(index, b.u64(position)?)),
+            _ => {
+                return Err(invalid(format!(
+                    "unexpected {:?} where a scalar was expected",
+                    base_type
+                )))
+            }
+        };
+        match base_type {
+            BaseType::Byte | BaseType::Short | BaseType::Int | BaseType::Long => {
+                Ok(self.enum_name(index, v).unwrap_or(value::Value::I64(v)))
+            }
+            _ => Ok(self.integer(index, v as u64)),
+        }
+    }
+
+    fn integer(&self, index: Option<usize>, v: u64) -> value::Value {
+        self.enum_name(index, v as i64)
+            .unwrap_or(value::Value::U64(v))
+    }
+
+    fn enum_name(&self, index: Option<usize>, v: i64) -> Option<value::Value> {
+        let e = self.schema.enums.get(index?)?;
+        e.values
+            .iter()
+            .find(|value| value.value == v)
+            .map(|value| value::Value::String(value.name.clone()))
+    }
+
+    fn string(&self, position: usize) -> error::Result<value::Value> {
+        let len = self.buffer.u32(position)? as usize;
+        let bytes = self.buffer.slice(position + 4, len)?;
+        Ok(match String::from_utf8(bytes.to_vec()) {
+            Ok(s) => value::Value::String(s),
+            Err(e) => value::Value::Bytes(e.into_bytes()),
+        })
+    }
+
+    fn object(&self, type_: Type) -> error::Result<&Object> {
+        type_
+            .index
+            .and_then(|index| self.schema.objects.get(index))
+            .ok_or_else(|| invalid("a field refers to a missing object".to_owned()))
+    }
+
+    /// The value of the `<field>_type` field of a union.
+    fn union_type(&self, object: &Object, field: &Field, table: usize) -> error::Result<i64> {
+        let name = format!("{}_type", field.name);
+        match object.fields.iter().find(|f| f.name == name) {
+            Some(type_field) => match self.buffer.field(table, type_field.offset)? {
+                Some(position) => Ok(self.buffer.u8(position)? as i64),
+                None => Ok(0),
+            },
+            None => Ok(0),
+        }
+    }
+
+    /// The values of the `<field>_type` vector of a vector of unions.
+    fn union_types(&self, object: &Object, field: &Field, table: usize) -> error::Result<Vec<i64>> {
+        let name = format!("{}_type", field.name);
+        let type_field = match object.fields.iter().find(|f| f.name == name) {
+            Some(type_field) => type_field,
+            None => return Ok(Vec::new()),
+        };
+        let vector = match self.buffer.field(table, type_field.offset)? {
+            Some(position) => self.buffer.indirect(position)?,
+            None => return Ok(Vec::new()),
+        };
+        let len = self.buffer.u32(vector)? as usize;
+        Ok(self
+            .buffer
+            .slice(vector + 4, len)?
+            .iter()
+            .map(|&t| t as i64)
+            .collect())
+    }
+
+    /// The table of the union member with the type, or `None` for `NONE`.
+    fn union_member(&self, type_: Type, utype: i64) -> error::Result<Option<&Object>> {
+        if utype == 0 {
+            return Ok(None);
+        }
+        let e = type_
+            .index
+            .and_then(|index| self.schema.enums.get(index))
+            .ok_or_else(|| invalid("a union refers to a missing enum".to_owned()))?;
+        let member = e
+            .values
+            .iter()
+            .find(|value| value.value == utype)
+            .and_then(|value| value.object)
+            .and_then(|object| self.schema.objects.get(object))
+            .ok_or_else(|| invalid(format!("union {} has no member {}", e.name, utype)))?;
+        Ok(Some(member))
+    }
+}
+
+fn check_depth(depth: usize) -> error::Result<()> {
+    if depth >= MAX_DEPTH {
+        Err(invalid(format!(
+            "tables are nested more than {} levels deep",
+            MAX_DEPTH
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+/// The value of an absent scalar field.
+fn default(schema: &Schema, field: &Field) -> value::Value {
+    let index = field.type_.index;
+    let integer = field.default_integer;
+    let enum_name = || {
+        let e = schema.enums.get(index?)?;
+        e.values
+            .iter()
+            .find(|value| value.value == integer)
+            .map(|value| value::Value::String(value.name.clone()))
+    };
+    match field.type_.base_type {
+        BaseType::Bool => value::Value::Bool(integer != 0),
+        BaseType::Float | BaseType::Double => value::Value::from_f64(field.default_real),
+        BaseType::ULong => enum_name().unwrap_or(value::Value::U64(integer as u64)),
+        BaseType::Byte
+        | BaseType::UByte
+        | BaseType::UType
+        | BaseType::Short
+        | BaseType::UShort
+        | BaseType::Int
+        | BaseType::UInt
+        | BaseType::Long => enum_name().unwrap_or(value::Value::I64(integer)),
+        _ => value::Value::Unit,
+    }
+}
+
+/// The size of the elements of a vector or array.
+fn element_size(schema: &Schema, type_: Type) -> error::Result<usize> {
+    Ok(match type_.element {
+        BaseType::Bool | BaseType::Byte | BaseType::UByte | BaseType::UType => 1,
+        BaseType::Short | BaseType::UShort => 2,
+        BaseType::Int | BaseType::UInt | BaseType::Float => 4,
+        BaseType::Long | BaseType::ULong | BaseType::Double => 8,
+        BaseType::String | BaseType::Union => 4,
+        BaseType::Obj => match type_.index.and_then(|index| schema.objects.get(index)) {
+            Some(object) if object.is_struct => object.bytesize,
+            Some(_) => 4,
+            None => return Err(invalid("a vector refers to a missing object".to_owned())),
+        },
+        _ if type_.element_size > 0 => type_.element_size,
+        element => return Err(invalid(format!("unexpected vector element {:?}", element))),
+    })
+}
+
+fn read_object(buffer: &Buffer, object: usize) -> error::Result<Object> {
+    let mut fields = buffer
+        .tables(object, 1)?
+        .into_iter()
+        .map(|field| read_field(buffer, field))
+        .collect::<error::Result<Vec<_>>>()?;
+    // The schema sorts fields by name, and their ids give the order of declaration
+    fields.sort_by_key(|(id, _)| *id);
+    Ok(Object {
+        name: buffer.required_string(object, 0)?,
+        fields: fields.into_iter().map(|(_, field)| field).collect(),
+        is_struct: buffer.scalar(object, 2, Buffer::u8)?.unwrap_or(0) != 0,
+        bytesize: buffer.scalar(object, 4, Buffer::u32)?.unwrap_or(0) as usize,
+    })
+}
+
+fn read_field(buffer: &Buffer, field: usize) -> error::Result<(u16, Field)> {
+    let type_ = buffer
+        .table(field, 1)?
+        .ok_or_else(|| invalid("a field has no type".to_owned()))?;
+    let index = buffer
+        .scalar(type_, 2, Buffer::u32)?
+        .map_or(-1, |i| i as i32);
+    let id = buffer.scalar(field, 2, Buffer::u16)?.unwrap_or(0);
+    Ok((
+        id,
+        Field {
+            name: buffer.required_string(field, 0)?,
+            type_: Type {
+                base_type: base_type(buffer.scalar(type_, 0, Buffer::u8)?.unwrap_or(0))?,
+                element: base_type(buffer.scalar(type_, 1, Buffer::u8)?.unwrap_or(0))?,
+                index: usize::try_from(index).ok(),
+                fixed_length: buffer.scalar(type_, 3, Buffer::u16)?.unwrap_or(0) as usize,
+                element_size: buffer.scalar(type_, 5, Buffer::u32)?.unwrap_or(0) as usize,
+            },
+            offset: buffer.scalar(field, 3, Buffer::u16)?.unwrap_or(0) as usize,
+            default_integer: buffer.scalar(field, 4, Buffer::u64)?.unwrap_or(0) as i64,
+            default_real: f64::from_bits(buffer.scalar(field, 5, Buffer::u64)?.unwrap_or(0)),
+            deprecated: buffer.scalar(field, 6, Buffer::u8)?.unwrap_or(0) != 0,
+            optional: buffer.scalar(field, 11, Buffer::u8)?.unwrap_or(0) != 0,
+        },
+    ))
+}
+
+fn read_enum(buffer: &Buffer, e: usize) -> error::Result<Enum> {
+    let values = buffer
+        .tables(e, 1)?
+        .into_iter()
+        .map(|value| {
+            let object = match buffer.table(value, 3)? {
+                Some(union_type) => buffer
+                    .scalar(union_type, 2, Buffer::u32)?
+                    .and_then(|i| usize::try_from(i as i32).ok()),
+                None => None,
+            };
+            Ok(EnumVal {
+                name: buffer.required_string(value, 0)?,
+                value: buffer.scalar(value, 1, Buffer::u64)?.unwrap_or(0) as i64,
+                object,
+            })
+        })
+        .collect::<error::Result<Vec<_>>>()?;
+    Ok(Enum {
+        name: buffer.required_string(e, 0)?,
+        values,
+    })
+}
+
+fn base_type(b: u8) -> error::Result<BaseType> {
+    Ok(match b {
+        0 => BaseType::None,
+        1 => BaseType::UType,
+        2 => BaseType::Bool,
+        3 => BaseType::Byte,
+        4 => BaseType::UByte,
+        5 => BaseType::Short,
+        6 => BaseType::UShort,
+        7 => BaseType::Int,
+        8 => BaseType::UInt,
+        9 => BaseType::Long,
+        10 => BaseType::ULong,
+        11 => BaseType::Float,
+        12 => BaseType::Double,
+        13 => BaseType::String,
+        14 => BaseType::Vector,
+        15 => BaseType::Obj,
+        16 => BaseType::Union,
+        17 => BaseType::Array,
+        18 => BaseType::Vector64,
+        b => return Err(invalid(format!("unknown base type {}", b))),
+    })
+}
+
+fn invalid(msg: String) -> error::Error {
+    error::Error::Format {
+        msg: format!("invalid FlatBuffer: {}", msg),
+    }
+}
+
+/// Bounds-checked little-endian reads from a buffer.
+struct Buffer<'a>(&'a [u8]);
+
+impl Buffer<'_> {
+    fn slice(&self, position: usize, len: usize) -> error::Result<&[u8]> {
+        position
+            .checked_add(len)
+            .and_then(|end| self.0.get(position..end))
+            .ok_or_else(|| invalid(format!("{} bytes at {} are out of bounds", len, position)))
+    }
+
+    fn u8(&self, position: usize) -> error::Result<u8> {
+        Ok(self.slice(position, 1)?[0])
+    }
+
+    fn u16(&self, position: usize) -> error::Result<u16> {
+        let mut bytes = [0; 2];
+        bytes.copy_from_slice(self.slice(position, 2)?);
+        Ok(u16::from_le_bytes(bytes))
+    }
+
+    fn u32(&self, position: usize) -> error::Result<u32> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.slice(position, 4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    fn u64(&self, position: usize) -> error::Result<u64> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.slice(position, 8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    /// The position of the root table.
+    fn root(&self) -> error::Result<usize> {
+        self.indirect(0)
+    }
+
+    /// Follows the offset at the position, which is relative to the position.
+    fn indirect(&self, position: usize) -> error::Result<usize> {
+        position
+            .checked_add(self.u32(position)? as usize)
+            .ok_or_else(|| invalid(format!("the offset at {} overflows", position)))
+    }
+
+    /// The position of a field of a table, given by its offset in the vtable, if it is present.
+    fn field(&self, table: usize, offset: usize) -> error::Result<Option<usize>> {
+        let vtable = (table as i64) - (self.u32(table)? as i32 as i64);
+        let vtable = usize::try_from(vtable).map_err(|_| {
+            invalid(format!(
+                "the vtable of the table at {} is out of bounds",
+                table
+            ))
+        })?;
+        let vtable_len = self.u16(vtable)? as usize;
+        if offset + 2 > vtable_len {
+            return Ok(None);
+        }
+        match self.u16(vtable + offset)? {
+            0 => Ok(None),
+            field => Ok(Some(table + field as usize)),
+        }
+    }
+
+    /// Reads a scalar field of a table by its id, if it is present.
+    fn scalar<T>(
+        &self,
+        table: usize,
+        id: usize,
+        read: fn(&Self, usize) -> error::Result<T>,
+    ) -> error::Result<Option<T>> {
+        match self.field(table, 4 + 2 * id)? {
+            Some(position) => Ok(Some(read(self, position)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn table(&self, table: usize, id: usize) -> error::Result<Option<usize>> {
+        match self.field(table, 4 + 2 * id)? {
+            Some(position) => Ok(Some(self.indirect(position)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// The tables of a vector of tables in a field, by its id.
+    fn tables(&self, table: usize, id: usize) -> error::Result<Vec<usize>> {
+        let vector = match self.table(table, id)? {
+            Some(vector) => vector,
+            None => return Ok(Vec::new()),
+        };
+        let len = self.u32(vector)? as usize;
+        (0..len)
+            .map(|i| self.indirect(vector + 4 + i * 4))
+            .collect()
+    }
+
+    fn required_string(&self, table: usize, id: usize) -> error::Result<String> {
+        let position = self
+            .table(table, id)?
+            .ok_or_else(|| invalid(format!("the table at {} has no name", table)))?;
+        let len = self.u32(position)? as usize;
+        Ok(String::from_utf8_lossy(self.slice(position + 4, len)?).into_owned())
+    }
+}
+
+impl fmt::Debug for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FlatbuffersSource")
+            .field("root", &self.schema.objects[self.root].name)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use ::flatbuffers::{FlatBufferBuilder, WIPOffset};
+
+    use crate::value::Source as _;
+
+    type Table = WIPOffset<::flatbuffers::TableFinishedWIPOffset>;
+
+    // The reflection tables of reflection.fbs, with fields in their slots
+    fn type_(b: &mut FlatBufferBuilder, base: i8, element: i8, index: i32) -> Table {
+        let t = b.start_table();
+        b.push_slot::<i8>(4, base, 0);
+        b.push_slot::<i8>(6, element, 0);
+        b.push_slot::<i32>(8, index, -1);
+        b.end_table(t)
+    }
+
+    fn field(
+        b: &mut FlatBufferBuilder,
+        name: &str,
+        id: u16,
+        offset: u16,
+        (base, element, index): (i8, i8, i32),
+        default: i64,
+    ) -> Table {
+        let name = b.create_string(name);
+        let ty = type_(b, base, element, index);
+        let t = b.start_table();
+        b.push_slot_always(4, name);
+        b.push_slot_always(6, ty);
+        b.push_slot::<u16>(8, id, 0);
+        b.push_slot::<u16>(10, offset, 0);
+        b.push_slot::<i64>(12, default, 0);
+        b.end_table(t)
+    }
+
+    fn object(b: &mut FlatBufferBuilder, name: &str, fields: &[Table], size: i32) -> Table {
+        let name = b.create_string(name);
+        let fields = b.create_vector(fields);
+        let t = b.start_table();
+        b.push_slot_always(4, name);
+        b.push_slot_always(6, fields);
+        b.push_slot::<bool>(8, size > 0, false);
+        b.push_slot::<i32>(12, size, 0);
+        b.end_table(t)
+    }
+
+    fn enum_(b: &mut FlatBufferBuilder, name: &str, values: &[(&str, i64, i32)]) -> Table {
+        let values: Vec<Table> = values
+            .iter()
+            .map(|&(name, value, object)| {
+                let name = b.create_string(name);
+                let ty = type_(b, 15, 0, object);
+                let t = b.start_table();
+                b.push_slot_always(4, name);
+                b.push_slot::<i64>(6, value, 0);
+                b.push_slot_always(10, ty);
+                b.end_table(t)
+            })
+            .collect();
+        let name = b.create_string(name);
+        let values = b.create_vector(&values);
+        let t = b.start_table();
+        b.push_slot_always(4, name);
+        b.push_slot_always(6, values);
+        b.end_table(t)
+    }
+
+    /// Finishes a schema with the objects and enums, and the root table.
+    fn finish(
+        b: &mut FlatBufferBuilder,
+        objects: &[Table],
+        enums: &[Table],
+        root: Table,
+    ) -> Vec<u8> {
+        let objects = b.create_vector(objects);
+        let enums = b.create_vector(enums);
+        let s = b.start_table();
+        b.push_slot_always(4, objects);
+        b.push_slot_always(6, enums);
+        b.push_slot_always(12, root);
+        let schema = b.end_table(s);
+        b.finish(schema, Some("BFBS"));
+        b.finished_data().to_vec()
+    }
+
+    #[test]
+    fn test_monster() {
+        // enum Color : byte { Red, Green, Blue }
+        // union Equipment { Weapon }
+        // struct Vec2 { x: float; y: float; }
+        // table Weapon { name: string; damage: short; }
+        // table Monster { pos: Vec2; hp: short = 100; name: string; color: Color = Blue;
+        //   inventory: [ubyte]; weapons: [Weapon]; equipped: Equipment; tags: [string]; }
+        let mut b = FlatBufferBuilder::new();
+        let fields = [
+            field(&mut b, "x", 0, 0, (11, 0, -1), 0),
+            field(&mut b, "y", 1, 4, (11, 0, -1), 0),
+        ];
+        let vec2 = object(&mut b, "test.Vec2", &fields, 8);
+        let fields = [
+            field(&mut b, "damage", 1, 6, (5, 0, -1), 0),
+            field(&mut b, "name", 0, 4, (13, 0, -1), 0),
+        ];
+        let weapon = object(&mut b, "test.Weapon", &fields, 0);
+        // Sorted by name, like flatc does
+        let fields = [
+            field(&mut b, "color", 3, 10, (3, 0, 0), 2),
+            field(&mut b, "equipped", 7, 18, (16, 0, 1), 0),
+            field(&mut b, "equipped_type", 6, 16, (1, 0, 1), 0),
+            field(&mut b, "hp", 1, 6, (5, 0, -1), 100),
+            field(&mut b, "inventory", 4, 12, (14, 4, -1), 0),
+            field(&mut b, "name", 2, 8, (13, 0, -1), 0),
+            field(&mut b, "pos", 0, 4, (15, 0, 2), 0),
+            field(&mut b, "tags", 8, 20, (14, 13, -1), 0),
+            field(&mut b, "weapons", 5, 14, (14, 15, 1), 0),
+        ];
+        let monster = object(&mut b, "test.Monster", &fields, 0);
+        let color = enum_(
+            &mut b,
+            "test.Color",
+            &[("Red", 0, -1), ("Green", 1, -1), ("Blue", 2, -1)],
+        );
+        let equipment = enum_(
+            &mut b,
+            "test.Equipment",
+            &[("NONE", 0, -1), ("Weapon", 1, 1)],
+        );
+        let bfbs = finish(
+            &mut b,
+            &[monster, weapon, vec2],
+            &[color, equipment],
+            monster,
+        );
+
+        fn orc(size_prefixed: bool) -> Vec<u8> {
+            let mut b = FlatBufferBuilder::new();
+            let name = b.create_string("Sword");
+            let w = b.start_table();
+            b.push_slot_always(4, name);
+            b.push_slot::<i16>(6, 3, 0);
+            let sword = b.end_table(w);
+            let weapons = b.create_vector(&[sword]);
+            let name = b.create_string("Orc");
+            let inventory = b.create_vector(&[1u8, 2]);
+            let tags = [b.create_string("a"), b.create_string("b")];
+            let tags = b.create_vector(&tags);
+            let m = b.start_table();
+            // Vec2 { x: 1.5, y: -2.0 }, as the struct is two floats
+            let pos = u64::from(1.5f32.to_bits()) | u64::from((-2.0f32).to_bits()) << 32;
+            b.push_slot_always::<u64>(4, pos);
+            b.push_slot_always(8, name);
+            b.push_slot::<i8>(10, 1, 2);
+            b.push_slot_always(12, inventory);
+            b.push_slot_always(14, weapons);
+            b.push_slot::<u8>(16, 1, 0);
+            b.push_slot_always(18, sword);
+            b.push_slot_always(20, tags);
+            let monster = b.end_table(m);
+            if size_prefixed {
+                b.finish_size_prefixed(monster, None);
+            } else {
+                b.finish(monster, None);
+            }
+            b.finished_data().to_vec()
+        }
+        let expected = r#"{"pos": {"x": 1.5, "y": -2}, "hp": 100, "name": "Orc", "color": "Green", "inventory": 0x0102, "weapons": [{"name": "Sword", "damage": 3}], "equipped_type": "Weapon", "equipped": {"name": "Sword", "damage": 3}, "tags": ["a", "b"]}"#;
+
+        let schema = || Schema::parse(&bfbs).unwrap();
+        let mut reader = source(schema(), None, &orc(false)[..], false).unwrap();
+        assert_eq!(reader.read().unwrap().unwrap().to_string(), expected);
+        assert_eq!(reader.read().unwrap(), None);
+        let buffers = [orc(true), orc(true)].concat();
+        let mut reader = source(schema(), None, &buffers[..], true).unwrap();
+        assert_eq!(reader.read().unwrap().unwrap().to_string(), expected);
+        assert_eq!(reader.read().unwrap().unwrap().to_string(), expected);
+        assert_eq!(reader.read().unwrap(), None);
+
+        // Absent fields take their defaults
+        let mut b = FlatBufferBuilder::new();
+        let m = b.start_table();
+        let monster = b.end_table(m);
+        b.finish(monster, None);
+        let mut reader = source(schema(), Some("Monster"), b.finished_data(), false).unwrap();
+        assert_eq!(
+            reader.read().unwrap().unwrap().to_string(),
+            r#"{"pos": null, "hp": 100, "name": null, "color": "Blue", "inventory": null, "weapons": null, "equipped_type": "NONE", "equipped": null, "tags": null}"#
+        );
+        assert_eq!(reader.read().unwrap(), None);
+
+        assert!(source(schema(), Some("Dragon"), &[][..], false).is_err());
+        let mut reader = source(schema(), None, &[8, 0, 0, 0, 0][..], false).unwrap();
+        assert!(reader.read().is_err());
+    }
+
+    #[test]
+    fn test_depth() {
+        // table Node { child: Node; }
+        let mut b = FlatBufferBuilder::new();
+        let fields = [field(&mut b, "child", 0, 4, (15, 0, 0), 0)];
+        let node = object(&mut b, "test.Node", &fields, 0);
+        let bfbs = finish(&mut b, &[node], &[], node);
+
+        let nodes = |depth: usize| {
+            let mut b = FlatBufferBuilder::new();
+            let t = b.start_table();
+            let mut child = b.end_table(t);
+            for _ in 1..depth {
+                let t = b.start_table();
+                b.push_slot_always(4, child);
+                child = b.end_table(t);
+            }
+            b.finish(child, None);
+            b.finished_data().to_vec()
+        };
+        let read = |input: &[u8]| source(Schema::parse(&bfbs).unwrap(), None, input, false)?.read();
+        assert!(read(&nodes(MAX_DEPTH)).is_ok());
+        match read(&nodes(100_000)) {
+            Err(error::Error::Format { msg }) => assert!(msg.contains("nested"), "{}", msg),
+            other => panic!(
+                "expected an error for deeply nested tables, got {:?}",
+                other
+            ),
+        }
+    }
+}
@@ -0,0 +1,265 @@
+//! The history of a git repository, as printed by `git log --format=raw`, optionally with
+//! `--numstat`.
+//!
+//! Each commit becomes a record with its `commit` and `tree` hashes, `parents`, `author` and
+//! `committer` (with `name`, `email` and `date` in their time zone), `message`, and the `files`
+//! that `--numstat` lists with the lines `added` and `deleted` (null for binary files).
+
+use std::io;
+
+use crate::error;
+use crate::value;
+
+#[derive(Debug)]
+pub struct Source<R> {
+    lines: io::Lines<R>,
+    /// The `commit` line of the next commit, which ends the one before it.
+    next: Option<String>,
+    line: u64,
+    records: u64,
+}
+
+/// Creates a source for the commits of the log in the input.
+pub fn source<R>(r: R) -> Source<R>
+where
+    R: io::BufRead,
+{
+    Source {
+        lines: r.lines(),
+        next: None,
+        line: 0,
+        records: 0,
+    }
+}
+
+impl<R> value::Source for Source<R>
+where
+    R: io::BufRead,
+{
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        let header = match self.next.take() {
+            Some(header) => header,
+            None => loop {
+                match self.next_line()? {
+                    Some(line) if line.starts_with("commit ") => break line,
+                    Some(line) if line.trim().is_empty() => continue,
+                    Some(line) => {
+                        return Err(error::Error::Format {
+                            msg: format!(
+                                "git log line {}: expected a commit, got: {} (was the log \
+                                 printed with --format=raw?)",
+                                self.line, line
+                            ),
+                        })
+                    }
+                    None => return Ok(None),
+                }
+            },
+        };
+        // Decorations and the commit that a reflog entry came from can follow the hash
+        let hash = header["commit ".len()..]
+            .split_whitespace()
+            .next()
+            .unwrap_or_default();
+
+        let mut tree = value::Value::Unit;
+        let mut parents = Vec::new();
+        let mut author = value::Value::Unit;
+        let mut committer = value::Value::Unit;
+        let mut message = Vec::new();
+        let mut files = Vec::new();
+        // Blank lines of the message, which can lose their indentation when logs are edited
+        let mut blank = 0;
+        while let Some(line) = self.next_line()? {
+            if line.starts_with("commit ") {
+                self.next = Some(line);
+                break;
+            }
+            if let Some(text) = line.strip_prefix("    ") {
+                if !message.is_empty() {
+                    message.extend(std::iter::repeat_n(String::new(), blank));
+                }
+                blank = 0;
+                message.push(text.to_owned());
+            } else if line.is_empty() {
+                blank += 1;
+            } else if let Some((key, rest)) = line.split_once(' ') {
+                match key {
+                    "tree" => tree = rest.into(),
+                    "parent" => parents.push(rest.into()),
+                    "author" => author = signature(rest),
+                    "committer" => committer = signature(rest),
+                    // Signatures and merge tags continue on lines that start with a space
+                    _ => (),
+                }
+            } else if let Some(file) = numstat(&line) {
+                files.push(file);
+            }
+        }
+        self.records += 1;
+
+        let mut entries = vec![
+            ("commit".into(), hash.into()),
+            ("tree".into(), tree),
+            ("parents".into(), value::Value::Sequence(parents)),
+            ("author".into(), author),
+            ("committer".into(), committer),
+            ("message".into(), message.join("\n").into()),
+        ];
+        if !files.is_empty() {
+            entries.push(("files".into(), value::Value::Sequence(files)));
+        }
+        Ok(Some(value::Value::Map(entries)))
+    }
+
+    #[inline]
+    fn position(&self) -> Option<value::Position> {
+        Some(value::Position {
+            record: self.records,
+            byte: None,
+            line: Some(self.line),
+        })
+    }
+}
+
+impl<R> Source<R>
+where
+    R: io::BufRead,
+{
+    fn next_line(&mut self) -> error::Result<Option<String>> {
+        match self.lines.next() {
+            Some(line) => {
+                self.line += 1;
+                Ok(Some(line?))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+/// Parses a signature like `Jo <jo@example.com> 1700000000 +0100`.
+fn signature(s: &str) -> value::Value {
+    let (name, rest) = s.split_once(" <").unwrap_or((s, ""));
+    let (email, time) = rest
+        .split_once("> ")
+        .unwrap_or((rest.trim_end_matches('>'), ""));
+    let mut time = time.split_whitespace();
+    let date = match (
+        time.next().and_then(|t| t.parse().ok()),
+        time.next().and_then(parse_offset),
+    ) {
+        (Some(seconds), Some(offset)) => format_date(seconds, offset).into(),
+        _ => value::Value::Unit,
+    };
+    value::Value::Map(vec![
+        ("name".into(), name.into()),
+        ("email".into(), email.into()),
+        ("date".into(), date),
+    ])
+}
+
+/// Parses a time zone like `+0100` into minutes.
+fn parse_offset(s: &str) -> Option<i64> {
+    let (sign, digits) = match s.split_at(1) {
+        ("+", digits) => (1, digits),
+        ("-", digits) => (-1, digits),
+        _ => return None,
+    };
+    if digits.len() != 4 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let (hours, minutes): (i64, i64) = (digits[..2].parse().ok()?, digits[2..].parse().ok()?);
+    Some(sign * (hours * 60 + minutes))
+}
+
+/// Formats a time as RFC 3339 in the time zone, like `2023-11-14T23:13:20+01:00`.
+fn format_date(seconds: i64, offset: i64) -> String {
+    let local = seconds + offset * 60;
+    let (days, seconds) = (local.div_euclid(86_400), local.rem_euclid(86_400));
+    let (year, month, day) = value::bson::civil_from_days(days);
+    let sign = if offset < 0 { '-' } else { '+' };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}{}{:02}:{:02}",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60,
+        sign,
+        offset.abs() / 60,
+        offset.abs() % 60
+    )
+}
+
+/// Parses a line of `--numstat`, like `10\t2\tsrc/main.rs`, where binary files have `-` for the
+/// counts.
+fn numstat(line: &str) -> Option<value::Value> {
+    let mut fields = line.splitn(3, '\t');
+    let (added, deleted, path) = (fields.next()?, fields.next()?, fields.next()?);
+    let count = |s: &str| match s {
+        "-" => Some(value::Value::Unit),
+        s => s.parse::<u64>().ok().map(value::Value::U64),
+    };
+    Some(value::Value::Map(vec![
+        ("path".into(), path.into()),
+        ("added".into(), count(added)?),
+        ("deleted".into(), count(deleted)?),
+    ]))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::value::Source as _;
+
+    fn read(input: &[u8]) -> error::Result<Vec<value::Value>> {
+        let mut reader = source(input);
+        let mut records = Vec::new();
+        while let Some(record) = reader.read()? {
+            records.push(record);
+        }
+        Ok(records)
+    }
+
+    #[test]
+    fn test_log() {
+        let log = "commit 1111 (HEAD -> main)
+tree aaaa
+parent 2222
+parent 3333
+author Jo Smith <jo@example.com> 1700000000 +0130
+committer Bo <bo@example.com> 1700000060 -0800
+gpgsig -----BEGIN PGP SIGNATURE-----
+ iQEzBAABCAAdFiEE
+ -----END PGP SIGNATURE-----
+
+    Merge branch feature
+
+    Details follow.
+
+10\t2\tsrc/main.rs
+-\t-\tlogo.png
+
+commit 2222
+tree bbbb
+author Jo Smith <jo@example.com> 0 +0000
+committer Jo Smith <jo@example.com> 0 +0000
+
+    Initial commit
+";
+        let records: Vec<String> = read(log.as_bytes())
+            .unwrap()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            records,
+            vec![
+                r#"{"commit": "1111", "tree": "aaaa", "parents": ["2222", "3333"], "author": {"name": "Jo Smith", "email": "jo@example.com", "date": "2023-11-14T23:43:20+01:30"}, "committer": {"name": "Bo", "email": "bo@example.com", "date": "2023-11-14T14:14:20-08:00"}, "message": "Merge branch feature\n\nDetails follow.", "files": [{"path": "src/main.rs", "added": 10, "deleted": 2}, {"path": "logo.png", "added": null, "deleted": null}]}"#,
+                r#"{"commit": "2222", "tree": "bbbb", "parents": [], "author": {"name": "Jo Smith", "email": "jo@example.com", "date": "1970-01-01T00:00:00+00:00"}, "committer": {"name": "Jo Smith", "email": "jo@example.com", "date": "1970-01-01T00:00:00+00:00"}, "message": "Initial commit"}"#,
+            ]
+        );
+        assert!(read(b"1111 Initial commit\n").is_err());
+    }
+}
@@ -0,0 +1,273 @@
+//! HCL, the configuration language of Terraform and other HashiCorp tools.
+//!
+//! Files are read as a single map following the HCL JSON specification: blocks become maps
+//! nested by their type and labels, and repeated blocks become sequences.  Expressions that
+//! aren't literal values, like `var.region`, become template strings like `"${var.region}"`.
+
+use std::io;
+
+use crate::error;
+use crate::value;
+
+/// How deeply expressions are nested at most.  The parser recurses for every bracket, brace,
+/// parenthesis and operator, so this is checked before the input is handed to it.  Brackets count
+/// twice, since the parser needs about twice as much stack for them.
+const MAX_DEPTH: usize = 128;
+
+#[derive(Debug)]
+pub struct Source(Option<String>);
+
+#[inline]
+pub fn source<R>(mut r: R) -> error::Result<Source>
+where
+    R: io::Read,
+{
+    let mut string = String::new();
+    r.read_to_string(&mut string)?;
+    Ok(Source(Some(string)))
+}
+
+impl value::Source for Source {
+    #[inline]
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        match self.0.take() {
+            Some(v) => {
+                check_depth(&v)?;
+                Ok(Some(hcl::from_str(&v)?))
+            }
+            None => Ok(None),
+        }
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = if self.0.is_some() { 1 } else { 0 };
+        (remaining, Some(remaining))
+    }
+}
+
+/// Where the depth check is in the input.
+enum Frame {
+    /// An expression in brackets ending with `close`, or at the top level, with the number of
+    /// operators in its current element.
+    Expression { close: char, operators: usize },
+    /// A quoted string.
+    Quoted,
+    /// A heredoc ending with a line that is only `marker`.
+    Heredoc { marker: String },
+}
+
+/// Fails if expressions are nested too deeply.  Brackets and operators in comments and in the
+/// literal parts of strings don't count, but those in template interpolations like `"${...}"` do.
+fn check_depth(input: &str) -> error::Result<()> {
+    let chars: Vec<char> = input.chars().collect();
+    let starts_with = |i: usize, s: &str| {
+        s.chars()
+            .enumerate()
+            .all(|(j, c)| chars.get(i + j) == Some(&c))
+    };
+    let mut frames = vec![Frame::Expression {
+        close: '\0',
+        operators: 0,
+    }];
+    let mut line = 1;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c == '\n' {
+            line += 1;
+        }
+        match frames.last_mut() {
+            Some(Frame::Expression { close, operators }) => match c {
+                '"' => frames.push(Frame::Quoted),
+                '#' => i = end_of_line(&chars, i) - 1,
+                '/' if starts_with(i, "//") => i = end_of_line(&chars, i) - 1,
+                '/' if starts_with(i, "/*") => {
+                    while i < chars.len() && !starts_with(i, "*/") {
+                        if chars[i] == '\n' {
+                            line += 1;
+                        }
+                        i += 1;
+                    }
+                    i += 1;
+                }
+                '<' if starts_with(i, "<<") => {
+                    let rest: String = chars[i + 2..end_of_line(&chars, i)].iter().collect();
+                    let marker = rest.trim_start_matches('-').trim();
+                    if !marker.is_empty() && marker.chars().all(|c| c.is_alphanumeric() || c == '_')
+                    {
+                        frames.push(Frame::Heredoc {
+                            marker: marker.to_owned(),
+                        });
+                        i = end_of_line(&chars, i) - 1;
+                    } else {
+                        *operators += 1;
+                        i += 1;
+                    }
+                }
+                '(' => frames.push(expression(')')),
+                '[' => frames.push(expression(']')),
+                '{' => frames.push(expression('}')),
+                ')' | ']' | '}' if c == *close => {
+                    frames.pop();
+                }
+                ',' => *operators = 0,
+                '\n' if *close == '}' || *close == '\0' => *operators = 0,
+                // Unary operators can be repeated, like `!!a`
+                '!' | '-' => *operators += 1,
+                '=' if starts_with(i, "==") => *operators += 1,
+                // Other operators count once, like `&&`
+                '+' | '*' | '/' | '%' | '<' | '>' | '&' | '|' | '?' | ':'
+                    if !i
+                        .checked_sub(1)
+                        .is_some_and(|j| "+*/%<>&|?:".contains(chars[j])) =>
+                {
+                    *operators += 1
+                }
+                _ => (),
+            },
+            Some(Frame::Quoted) => match c {
+                '\\' => i += 1,
+                // Quoted strings can't span lines, so one that does is an error anyway
+                '"' | '\n' => {
+                    frames.pop();
+                }
+                '$' | '%' if starts_with(i + 1, "{") => {
+                    frames.push(expression('}'));
+                    i += 1;
+                }
+                '$' | '%' if starts_with(i + 1, &format!("{}{{", c)) => i += 2,
+                _ => (),
+            },
+            Some(Frame::Heredoc { marker }) => match c {
+                '\n' => {
+                    let end = end_of_line(&chars, i + 1);
+                    let next: String = chars[i + 1..end].iter().collect();
+                    if next.trim() == marker {
+                        frames.pop();
+                        i = end - 1;
+                    }
+                }
+                '$' | '%' if starts_with(i + 1, "{") => {
+                    frames.push(expression('}'));
+                    i += 1;
+                }
+                '$' | '%' if starts_with(i + 1, &format!("{}{{", c)) => i += 2,
+                _ => (),
+            },
+            None => break,
+        }
+        // The top-level expression isn't in brackets
+        let depth = frames.iter().fold(0, |depth, frame| match frame {
+            Frame::Expression { operators, .. } => depth + 2 + operators,
+            _ => depth,
+        }) - 2;
+        if depth > MAX_DEPTH {
+            return Err(error::Error::Format {
+                msg: format!("HCL line {}: expressions are nested too deeply", line),
+            });
+        }
+        i += 1;
+    }
+    Ok(())
+}
+
+fn expression(close: char) -> Frame {
+    Frame::Expression {
+        close,
+        operators: 0,
+    }
+}
+
+/// The index of the line break that ends the line at `i`, or the end of the input.
+fn end_of_line(chars: &[char], i: usize) -> usize {
+    chars[i..]
+        .iter()
+        .position(|&c| c == '\n')
+        .map_or(chars.len(), |n| i + n)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::value::Source as _;
+
+    fn read(input: &str) -> error::Result<Option<value::Value>> {
+        source(input.as_bytes())?.read()
+    }
+
+    #[test]
+    fn test_depth() {
+        let nested = |depth: usize| format!("a = {}{}", "[".repeat(depth), "]".repeat(depth));
+        for input in &[
+            nested(64),
+            format!("a = {}1{}", "{b = ".repeat(64), "}".repeat(64)),
+            format!("a = {}1{}", "f(".repeat(64), ")".repeat(64)),
+            format!("a = {}1{}", "\"${".repeat(64), "}\"".repeat(64)),
+            format!("a = {}1", "1 + ".repeat(128)),
+            format!("a = {}true", "!".repeat(128)),
+            // Operators are counted per element and line
+            format!("a = [{}]", "1 + 1,".repeat(1000)),
+            (0..1000).map(|i| format!("a{} = 1 + 1\n", i)).collect(),
+            // Brackets in comments and strings don't count
+            format!("# {}\na = \"{}\"", "[".repeat(1000), "(-:".repeat(1000)),
+            format!("a = <<EOF\n{}\nEOF\nb = 1", "[".repeat(1000)),
+        ] {
+            if let Err(e) = read(input) {
+                panic!("expected {:.40?}... to be read, got {}", input, e);
+            }
+        }
+        for input in &[
+            nested(65),
+            nested(200_000),
+            format!("a = {}", "{b = ".repeat(200_000)),
+            format!("a = {}", "\"${".repeat(200_000)),
+            format!("a = {}1", "1 + ".repeat(200_000)),
+            format!("a = {}true", "!".repeat(200_000)),
+            format!("a = {}1", "true ? 1 : ".repeat(200_000)),
+            format!("a = <<EOF\n{}", "${[".repeat(200_000)),
+        ] {
+            match read(input) {
+                Err(error::Error::Format { msg }) => assert!(msg.contains("nested"), "{}", msg),
+                other => panic!("expected an error for deeply nested HCL, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_config() {
+        let record = |input: &str| read(input).unwrap().unwrap().to_string();
+        let config = r#"
+variable "region" {
+  default = "eu-west-1"
+}
+
+resource "aws_instance" "web" {
+  ami           = "ami-123"
+  instance_type = var.size
+  tags = {
+    Name = "web-${var.env}"
+  }
+  ebs_block_device {
+    device_name = "/dev/sda"
+  }
+  ebs_block_device {
+    device_name = "/dev/sdb"
+  }
+}
+"#;
+        assert_eq!(
+            record(config),
+            r#"{"variable": {"region": {"default": "eu-west-1"}}, "resource": {"aws_instance": {"web": {"ami": "ami-123", "instance_type": "${var.size}", "tags": {"Name": "web-${var.env}"}, "ebs_block_device": [{"device_name": "/dev/sda"}, {"device_name": "/dev/sdb"}]}}}}"#
+        );
+        // A .tfvars file only has attributes
+        assert_eq!(
+            record("count = 2\nenabled = true\nports = [80, 443]\n"),
+            r#"{"count": 2, "enabled": true, "ports": [80, 443]}"#
+        );
+
+        assert!(read("resource \"a\" {").is_err());
+        assert!(read("{\"a\": 1}").is_err());
+    }
+}
@@ -0,0 +1,689 @@
+//! Metadata of image files: EXIF, XMP and the text chunks of PNG.
+//!
+//! Each image becomes a record with its `format` (`JPEG`, `PNG`, `TIFF`, `WebP`, `HEIF` or
+//! `AVIF`), its `width` and `height`, the time it was `taken` (like `2024-05-01T12:34:56+02:00`,
+//! from the EXIF original date and its offset), its `gps` position in decimal degrees (or null),
+//! and maps with all of its `exif` tags, `xmp` properties and `text`.  EXIF tags keep their
+//! numeric values, so `Orientation` is `6` rather than a description, and rationals become
+//! floating point numbers.  XMP properties keep their prefixes, like `dc:creator`, with ordered
+//! and unordered arrays as sequences and alternatives as their default language.  The text of
+//! PNG files is under its keyword, and comments of JPEG files are under `comment`.
+
+use std::io;
+use std::str;
+
+use quick_xml::events;
+
+use crate::error;
+use crate::value;
+
+/// The TIFF tag that has the XMP packet of TIFF files, which the EXIF reader doesn't name.
+const XMP_TAG: u16 = 700;
+
+#[derive(Debug)]
+pub struct Source(Option<value::Value>);
+
+/// Creates a source for the metadata of an image, which is read in full from the input.
+pub fn source<R>(mut r: R) -> error::Result<Source>
+where
+    R: io::Read,
+{
+    let mut input = Vec::new();
+    r.read_to_end(&mut input)?;
+    Ok(Source(Some(parse(input)?)))
+}
+
+impl value::Source for Source {
+    #[inline]
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        Ok(self.0.take())
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = if self.0.is_some() { 1 } else { 0 };
+        (remaining, Some(remaining))
+    }
+}
+
+/// The metadata found in the container of an image, before it is decoded.
+#[derive(Default)]
+struct Metadata {
+    width: Option<u32>,
+    height: Option<u32>,
+    exif: Option<exif::Exif>,
+    xmp: Vec<u8>,
+    text: Vec<(value::Value, value::Value)>,
+}
+
+fn parse(input: Vec<u8>) -> error::Result<value::Value> {
+    let (format, mut metadata) = if input.starts_with(b"\xFF\xD8\xFF") {
+        ("JPEG", jpeg(&input)?)
+    } else if input.starts_with(b"\x89PNG\r\n\x1A\n") {
+        ("PNG", png(&input)?)
+    } else if input.starts_with(b"II*\0") || input.starts_with(b"MM\0*") {
+        let exif = read_exif(input)?;
+        let dimension = |tag| {
+            exif.as_ref()
+                .and_then(|exif| exif.get_field(tag, exif::In::PRIMARY))
+                .and_then(|field| field.value.get_uint(0))
+        };
+        let metadata = Metadata {
+            width: dimension(exif::Tag::ImageWidth),
+            height: dimension(exif::Tag::ImageLength),
+            exif,
+            ..Metadata::default()
+        };
+        ("TIFF", metadata)
+    } else if input.len() >= 12 && &input[..4] == b"RIFF" && &input[8..12] == b"WEBP" {
+        ("WebP", webp(&input)?)
+    } else if input.len() >= 12 && &input[4..8] == b"ftyp" {
+        let format = match &input[8..12] {
+            b"avif" | b"avis" => "AVIF",
+            _ => "HEIF",
+        };
+        let exif = match exif::Reader::new().read_from_container(&mut io::Cursor::new(&input)) {
+            Ok(exif) => Some(exif),
+            Err(exif::Error::NotFound(_)) => None,
+            Err(e) => return Err(format_error(&format!("invalid EXIF data: {}", e))),
+        };
+        let metadata = Metadata {
+            exif,
+            ..Metadata::default()
+        };
+        (format, metadata)
+    } else {
+        return Err(format_error(
+            "the input is not a JPEG, PNG, TIFF, WebP or HEIF image",
+        ));
+    };
+
+    let mut exif_entries = Vec::new();
+    let (mut taken, mut gps) = (value::Value::Unit, value::Value::Unit);
+    if let Some(ref exif) = metadata.exif {
+        for field in exif.fields().filter(|f| f.ifd_num == exif::In::PRIMARY) {
+            if field.tag == exif::Tag(exif::Context::Tiff, XMP_TAG) {
+                if let exif::Value::Byte(ref bytes) | exif::Value::Undefined(ref bytes, _) =
+                    field.value
+                {
+                    metadata.xmp.clone_from(bytes);
+                }
+            } else if field.tag != exif::Tag::MakerNote {
+                if let Some(v) = exif_value(field) {
+                    exif_entries.push((field.tag.to_string().into(), v));
+                }
+            }
+        }
+        let dimension = |tag| {
+            exif.get_field(tag, exif::In::PRIMARY)
+                .and_then(|field| field.value.get_uint(0))
+        };
+        metadata.width = metadata
+            .width
+            .or_else(|| dimension(exif::Tag::PixelXDimension));
+        metadata.height = metadata
+            .height
+            .or_else(|| dimension(exif::Tag::PixelYDimension));
+        taken = taken_time(exif);
+        gps = position(exif);
+    }
+    let xmp = if metadata.xmp.is_empty() {
+        Vec::new()
+    } else {
+        xmp_properties(&metadata.xmp)?
+    };
+
+    let dimension = |d: Option<u32>| d.map_or(value::Value::Unit, value::Value::U32);
+    Ok(value::Value::Map(vec![
+        ("format".into(), format.into()),
+        ("width".into(), dimension(metadata.width)),
+        ("height".into(), dimension(metadata.height)),
+        ("taken".into(), taken),
+        ("gps".into(), gps),
+        ("exif".into(), value::Value::Map(exif_entries)),
+        ("xmp".into(), value::Value::Map(xmp)),
+        ("text".into(), value::Value::Map(metadata.text)),
+    ]))
+}
+
+/// Reads the segments of a JPEG file up to the image data.
+fn jpeg(input: &[u8]) -> error::Result<Metadata> {
+    const EXIF: &[u8] = b"Exif\0\0";
+    const XMP: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+
+    let mut metadata = Metadata::default();
+    let mut i = 2;
+    while i < input.len() {
+        if i + 4 > input.len() || input[i] != 0xFF {
+            return Err(format_error(&format!("invalid JPEG segment at byte {}", i)));
+        }
+        let marker = input[i + 1];
+        // Fill bytes and markers without a segment
+        if marker == 0xFF {
+            i += 1;
+            continue;
+        }
+        if marker == 0x01 || (0xD0..=0xD8).contains(&marker) {
+            i += 2;
+            continue;
+        }
+        let length = usize::from(u16::from_be_bytes([input[i + 2], input[i + 3]]));
+        let end = i + 2 + length;
+        if length < 2 || end > input.len() {
+            return Err(format_error(&format!(
+                "JPEG segment at byte {} is truncated",
+                i
+            )));
+        }
+        let data = &input[i + 4..end];
+        match marker {
+            // Start of scan: the metadata comes before the image data
+            0xDA => break,
+            // Start of frame, which shares its range with three other markers
+            0xC0..=0xCF
+                if ![0xC4, 0xC8, 0xCC].contains(&marker)
+                    && data.len() >= 5
+                    && metadata.width.is_none() =>
+            {
+                metadata.height = Some(u32::from(u16::from_be_bytes([data[1], data[2]])));
+                metadata.width = Some(u32::from(u16::from_be_bytes([data[3], data[4]])));
+            }
+            0xE1 if data.starts_with(EXIF) && metadata.exif.is_none() => {
+                metadata.exif = read_exif(data[EXIF.len()..].to_vec())?;
+            }
+            0xE1 if data.starts_with(XMP) => metadata.xmp = data[XMP.len()..].to_vec(),
+            0xFE => metadata.text.push((
+                "comment".into(),
+                String::from_utf8_lossy(data)
+                    .trim_end_matches('\0')
+                    .to_owned()
+                    .into(),
+            )),
+            _ => (),
+        }
+        i = end;
+    }
+    Ok(metadata)
+}
+
+/// Reads the chunks of a PNG file.
+fn png(input: &[u8]) -> error::Result<Metadata> {
+    let mut metadata = Metadata::default();
+    let mut i = 8;
+    while i < input.len() {
+        if i + 8 > input.len() {
+            return Err(format_error(&format!(
+                "PNG chunk at byte {} is truncated",
+                i
+            )));
+        }
+        let length = u32::from_be_bytes([input[i], input[i + 1], input[i + 2], input[i + 3]]);
+        let kind = &input[i + 4..i + 8];
+        let start = i + 8;
+        let end = start.saturating_add(length as usize);
+        if end > input.len() {
+            return Err(format_error(&format!(
+                "PNG chunk {} at byte {} is truncated",
+                String::from_utf8_lossy(kind),
+                i
+            )));
+        }
+        let data = &input[start..end];
+        match kind {
+            b"IHDR" if data.len() >= 8 => {
+                metadata.width = Some(u32::from_be_bytes([data[0], data[1], data[2], data[3]]));
+                metadata.height = Some(u32::from_be_bytes([data[4], data[5], data[6], data[7]]));
+            }
+            b"eXIf" => metadata.exif = read_exif(data.to_vec())?,
+            b"tEXt" | b"zTXt" | b"iTXt" => {
+                if let Some((keyword, text)) = png_text(kind, data)? {
+                    if keyword == "XML:com.adobe.xmp" {
+                        metadata.xmp = text.into_bytes();
+                    } else {
+                        // Later text replaces earlier text with the same keyword
+                        metadata
+                            .text
+                            .retain(|(k, _)| k.as_str() != Some(keyword.as_str()));
+                        metadata.text.push((keyword.into(), text.into()));
+                    }
+                }
+            }
+            b"IEND" => break,
+            _ => (),
+        }
+        // The chunk is followed by its CRC
+        i = end + 4;
+    }
+    Ok(metadata)
+}
+
+/// The keyword and text of a PNG text chunk.  The text of `tEXt` and `zTXt` is Latin-1, and the
+/// text of `iTXt` is UTF-8.
+fn png_text(kind: &[u8], data: &[u8]) -> error::Result<Option<(String, String)>> {
+    let (keyword, rest) = match data.iter().position(|&b| b == 0) {
+        Some(n) => (latin1(&data[..n]), &data[n + 1..]),
+        None => return Ok(None),
+    };
+    let text = match kind {
+        b"tEXt" => latin1(rest),
+        b"zTXt" => match rest.split_first() {
+            Some((0, compressed)) => latin1(&inflate(compressed)?),
+            _ => return Ok(None),
+        },
+        _ => {
+            // The compression flag and method, then the language and translated keyword
+            let (compressed, rest) = match rest {
+                [flag, 0, rest @ ..] => (*flag == 1, rest),
+                _ => return Ok(None),
+            };
+            let mut fields = rest.splitn(3, |&b| b == 0);
+            let text = match (fields.next(), fields.next(), fields.next()) {
+                (Some(_), Some(_), Some(text)) => text,
+                _ => return Ok(None),
+            };
+            if compressed {
+                String::from_utf8_lossy(&inflate(text)?).into_owned()
+            } else {
+                String::from_utf8_lossy(text).into_owned()
+            }
+        }
+    };
+    Ok(Some((keyword, text)))
+}
+
+/// Reads the chunks of a WebP file.
+fn webp(input: &[u8]) -> error::Result<Metadata> {
+    let mut metadata = Metadata::default();
+    let mut i = 12;
+    while i < input.len() {
+        if i + 8 > input.len() {
+            return Err(format_error(&format!(
+                "WebP chunk at byte {} is truncated",
+                i
+            )));
+        }
+        let kind = &input[i..i + 4];
+        let size = u32::from_le_bytes([input[i + 4], input[i + 5], input[i + 6], input[i + 7]]);
+        let start = i + 8;
+        let end = start.saturating_add(size as usize);
+        if end > input.len() {
+            return Err(format_error(&format!(
+                "WebP chunk {} at byte {} is truncated",
+                String::from_utf8_lossy(kind),
+                i
+            )));
+        }
+        let data = &input[start..end];
+        let le24 = |b: &[u8]| u32::from_le_bytes([b[0], b[1], b[2], 0]);
+        match kind {
+            // The extended format has the size of the canvas
+            b"VP8X" if data.len() >= 10 => {
+                metadata.width = Some(le24(&data[4..7]) + 1);
+                metadata.height = Some(le24(&data[7..10]) + 1);
+            }
+            b"VP8 " if data.len() >= 10 && metadata.width.is_none() => {
+                metadata.width = Some(u32::from(u16::from_le_bytes([data[6], data[7]]) & 0x3FFF));
+                metadata.height = Some(u32::from(u16::from_le_bytes([data[8], data[9]]) & 0x3FFF));
+            }
+            b"VP8L" if data.len() >= 5 && metadata.width.is_none() => {
+                let bits = u32::from_le_bytes([data[1], data[2], data[3], data[4]]);
+                metadata.width = Some((bits & 0x3FFF) + 1);
+                metadata.height = Some((bits >> 14 & 0x3FFF) + 1);
+            }
+            b"EXIF" => {
+                // Some writers keep the header of the JPEG segment
+                let data = data.strip_prefix(b"Exif\0\0").unwrap_or(data);
+                metadata.exif = read_exif(data.to_vec())?;
+            }
+            b"XMP " => metadata.xmp = data.to_vec(),
+            _ => (),
+        }
+        // Chunks are padded to an even size
+        i = end + (end & 1);
+    }
+    Ok(metadata)
+}
+
+/// Reads EXIF data in the TIFF format.
+fn read_exif(data: Vec<u8>) -> error::Result<Option<exif::Exif>> {
+    match exif::Reader::new().read_raw(data) {
+        Ok(exif) => Ok(Some(exif)),
+        Err(exif::Error::NotFound(_)) => Ok(None),
+        Err(e) => Err(format_error(&format!("invalid EXIF data: {}", e))),
+    }
+}
+
+/// The value of an EXIF tag: a string, a number, or a sequence of numbers.  Undefined values are
+/// described, like `2.32` for the EXIF version.
+fn exif_value(field: &exif::Field) -> Option<value::Value> {
+    fn numbers<T, F>(values: &[T], f: F) -> value::Value
+    where
+        T: Copy,
+        F: Fn(T) -> value::Value,
+    {
+        match *values {
+            [v] => f(v),
+            _ => value::Value::Sequence(values.iter().copied().map(f).collect()),
+        }
+    }
+    let ratio = |num: f64, denom: f64| {
+        if denom == 0.0 {
+            value::Value::Unit
+        } else {
+            value::Value::from_f64(num / denom)
+        }
+    };
+
+    Some(match field.value {
+        exif::Value::Ascii(ref strings) => {
+            let mut strings: Vec<value::Value> = strings
+                .iter()
+                .map(|s| String::from_utf8_lossy(s).trim_end().to_owned().into())
+                .collect();
+            if strings.len() == 1 {
+                strings.remove(0)
+            } else {
+                value::Value::Sequence(strings)
+            }
+        }
+        exif::Value::Byte(ref v) => numbers(v, value::Value::U8),
+        exif::Value::Short(ref v) => numbers(v, value::Value::U16),
+        exif::Value::Long(ref v) => numbers(v, value::Value::U32),
+        exif::Value::SByte(ref v) => numbers(v, value::Value::I8),
+        exif::Value::SShort(ref v) => numbers(v, value::Value::I16),
+        exif::Value::SLong(ref v) => numbers(v, value::Value::I32),
+        exif::Value::Rational(ref v) => numbers(v, |r| ratio(r.num.into(), r.denom.into())),
+        exif::Value::SRational(ref v) => numbers(v, |r| ratio(r.num.into(), r.denom.into())),
+        exif::Value::Float(ref v) => numbers(v, |f| value::Value::from_f64(f.into())),
+        exif::Value::Double(ref v) => numbers(v, value::Value::from_f64),
+        exif::Value::Undefined(..) => field.display_value().to_string().into(),
+        exif::Value::Unknown(..) => return None,
+    })
+}
+
+/// The time the image was taken, like `2024-05-01T12:34:56+02:00`, with an offset if it is
+/// known.
+fn taken_time(exif: &exif::Exif) -> value::Value {
+    let times = [
+        (exif::Tag::DateTimeOriginal, exif::Tag::OffsetTimeOriginal),
+        (exif::Tag::DateTimeDigitized, exif::Tag::OffsetTimeDigitized),
+        (exif::Tag::DateTime, exif::Tag::OffsetTime),
+    ];
+    let ascii = |tag| match exif.get_field(tag, exif::In::PRIMARY)?.value {
+        exif::Value::Ascii(ref strings) => strings
+            .first()
+            .and_then(|s| str::from_utf8(s).ok())
+            .map(str::trim),
+        _ => None,
+    };
+    for &(time, offset) in &times {
+        let time = match ascii(time) {
+            Some(time) => time,
+            None => continue,
+        };
+        // EXIF times are like `2024:05:01 12:34:56`, with blanks for unknown parts
+        let bytes = time.as_bytes();
+        let valid = bytes.len() == 19
+            && bytes.iter().enumerate().all(|(i, &b)| match i {
+                4 | 7 | 13 | 16 => b == b':',
+                10 => b == b' ',
+                _ => b.is_ascii_digit(),
+            });
+        if !valid {
+            continue;
+        }
+        let mut taken = format!(
+            "{}-{}-{}T{}",
+            &time[..4],
+            &time[5..7],
+            &time[8..10],
+            &time[11..]
+        );
+        if let Some(offset) = ascii(offset).filter(|o| o.len() == 6) {
+            taken.push_str(offset);
+        }
+        return taken.into();
+    }
+    value::Value::Unit
+}
+
+/// The GPS position as `latitude` and `longitude` in decimal degrees, negative for the south and
+/// the west, and the `altitude` in meters, negative below sea level.
+fn position(exif: &exif::Exif) -> value::Value {
+    let field = |tag| exif.get_field(tag, exif::In::PRIMARY).map(|f| &f.value);
+    let degrees = |tag, reference, negative: &[u8]| {
+        let degrees = match field(tag)? {
+            exif::Value::Rational(ref v) if !v.is_empty() => v
+                .iter()
+                .zip(&[1.0, 60.0, 3600.0])
+                .map(|(r, scale)| r.to_f64() / scale)
+                .sum::<f64>(),
+            _ => return None,
+        };
+        let negative = match field(reference) {
+            Some(exif::Value::Ascii(ref strings)) => {
+                strings.first().map(Vec::as_slice) == Some(negative)
+            }
+            _ => false,
+        };
+        Some(if negative { -degrees } else { degrees })
+    };
+    let latitude = degrees(exif::Tag::GPSLatitude, exif::Tag::GPSLatitudeRef, b"S");
+    let longitude = degrees(exif::Tag::GPSLongitude, exif::Tag::GPSLongitudeRef, b"W");
+    let (latitude, longitude) = match (latitude, longitude) {
+        (Some(latitude), Some(longitude)) if latitude.is_finite() && longitude.is_finite() => {
+            (latitude, longitude)
+        }
+        _ => return value::Value::Unit,
+    };
+    let altitude = match field(exif::Tag::GPSAltitude) {
+        Some(exif::Value::Rational(ref v)) if !v.is_empty() && v[0].denom != 0 => {
+            let below = field(exif::Tag::GPSAltitudeRef).and_then(|v| v.get_uint(0)) == Some(1);
+            let altitude = v[0].to_f64();
+            value::Value::from_f64(if below { -altitude } else { altitude })
+        }
+        _ => value::Value::Unit,
+    };
+    value::Value::Map(vec![
+        ("latitude".into(), value::Value::from_f64(latitude)),
+        ("longitude".into(), value::Value::from_f64(longitude)),
+        ("altitude".into(), altitude),
+    ])
+}
+
+/// An element of an XMP packet.
+#[derive(Debug, Default)]
+struct Element {
+    name: String,
+    attributes: Vec<(String, String)>,
+    children: Vec<Element>,
+    text: String,
+}
+
+impl Element {
+    fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// The attributes that are properties, rather than namespace declarations or RDF syntax.
+    fn properties(&self) -> impl Iterator<Item = &(String, String)> {
+        self.attributes.iter().filter(|(name, _)| {
+            !name.starts_with("xmlns") && !name.starts_with("rdf:") && !name.starts_with("xml:")
+        })
+    }
+}
+
+/// Reads the properties of all `rdf:Description` elements of an XMP packet.
+fn xmp_properties(xmp: &[u8]) -> error::Result<Vec<(value::Value, value::Value)>> {
+    let mut reader = quick_xml::Reader::from_reader(xmp);
+    let mut stack = vec![Element::default()];
+    let mut buf = Vec::new();
+    loop {
+        buf.clear();
+        match reader.read_event_into(&mut buf)? {
+            events::Event::Start(start) => stack.push(element(&start)?),
+            events::Event::Empty(start) => {
+                let element = element(&start)?;
+                if let Some(parent) = stack.last_mut() {
+                    parent.children.push(element);
+                }
+            }
+            events::Event::Text(text) => {
+                if let Some(element) = stack.last_mut() {
+                    element.text.push_str(&text.unescape()?);
+                }
+            }
+            events::Event::CData(text) => {
+                if let Some(element) = stack.last_mut() {
+                    element.text.push_str(&String::from_utf8_lossy(&text));
+                }
+            }
+            events::Event::End(_) if stack.len() > 1 => {
+                let element = stack.pop().unwrap();
+                if let Some(parent) = stack.last_mut() {
+                    parent.children.push(element);
+                }
+            }
+            events::Event::Eof => break,
+            _ => (),
+        }
+    }
+    if stack.len() != 1 {
+        return Err(format_error("the XMP packet ended inside of an element"));
+    }
+
+    let mut properties = Vec::new();
+    let mut pending = stack;
+    while let Some(element) = pending.pop() {
+        if element.name == "rdf:Description" {
+            properties.extend(description(&element));
+        } else {
+            pending.extend(element.children.into_iter().rev());
+        }
+    }
+    Ok(properties)
+}
+
+fn element(start: &events::BytesStart) -> error::Result<Element> {
+    let mut element = Element {
+        name: String::from_utf8_lossy(start.name().as_ref()).into_owned(),
+        ..Element::default()
+    };
+    for attribute in start.attributes() {
+        let attribute = attribute.map_err(quick_xml::Error::from)?;
+        element.attributes.push((
+            String::from_utf8_lossy(attribute.key.as_ref()).into_owned(),
+            attribute.unescape_value()?.into_owned(),
+        ));
+    }
+    Ok(element)
+}
+
+/// The properties of a resource, as attributes or child elements.
+fn description(element: &Element) -> Vec<(value::Value, value::Value)> {
+    let attributes = element
+        .properties()
+        .map(|(name, v)| (name.as_str().into(), v.as_str().into()));
+    let children = element
+        .children
+        .iter()
+        .map(|child| (child.name.as_str().into(), property(child)));
+    attributes.chain(children).collect()
+}
+
+/// The value of a property: text, a resource, a structure, or an array.
+fn property(element: &Element) -> value::Value {
+    if let Some(resource) = element.attribute("rdf:resource") {
+        return resource.into();
+    }
+    if element.attribute("rdf:parseType") == Some("Resource") {
+        return value::Value::Map(description(element));
+    }
+    match element.children.first() {
+        Some(child) if child.name == "rdf:Seq" || child.name == "rdf:Bag" => {
+            value::Value::Sequence(child.children.iter().map(property).collect())
+        }
+        Some(child) if child.name == "rdf:Alt" => child
+            .children
+            .iter()
+            .find(|item| item.attribute("xml:lang") == Some("x-default"))
+            .or_else(|| child.children.first())
+            .map_or(value::Value::Unit, property),
+        Some(child) if child.name == "rdf:Description" => value::Value::Map(description(child)),
+        Some(_) => value::Value::Map(description(element)),
+        // Structures can also be written as attributes
+        None if element.properties().next().is_some() => value::Value::Map(description(element)),
+        None => element.text.trim().into(),
+    }
+}
+
+fn inflate(compressed: &[u8]) -> error::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    io::Read::read_to_end(&mut flate2::read::ZlibDecoder::new(compressed), &mut data)
+        .map_err(|e| format_error(&format!("invalid compressed PNG text: {}", e)))?;
+    Ok(data)
+}
+
+fn latin1(bytes: &[u8]) -> String {
+    bytes.iter().map(|&b| char::from(b)).collect()
+}
+
+fn format_error(msg: &str) -> error::Error {
+    error::Error::Format {
+        msg: msg.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::value::Source as _;
+
+    fn read(input: &[u8]) -> error::Result<Vec<value::Value>> {
+        let mut reader = source(input)?;
+        let mut records = Vec::new();
+        while let Some(record) = reader.read()? {
+            records.push(record);
+        }
+        Ok(records)
+    }
+
+    #[test]
+    fn test_png() {
+        // A big-endian TIFF directory with the orientation and the time the image was changed
+        let mut exif = b"MM\0*\0\0\0\x08\0\x02".to_vec();
+        exif.extend_from_slice(b"\x01\x12\0\x03\0\0\0\x01\0\x06\0\0");
+        exif.extend_from_slice(b"\x01\x32\0\x02\0\0\0\x14\0\0\0\x26\0\0\0\0");
+        exif.extend_from_slice(b"2024:05:01 12:34:56\0");
+        let xmp = br#"<rdf:RDF xmlns:rdf="r"><rdf:Description xmlns:dc="d"><dc:subject><rdf:Bag><rdf:li>city</rdf:li></rdf:Bag></dc:subject></rdf:Description></rdf:RDF>"#;
+        // The chunks are followed by their CRC, which isn't checked
+        let chunk = |kind: &[u8], data: &[u8]| {
+            let mut chunk = (data.len() as u32).to_be_bytes().to_vec();
+            chunk.extend_from_slice(kind);
+            chunk.extend_from_slice(data);
+            chunk.extend_from_slice(&[0; 4]);
+            chunk
+        };
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        png.extend(chunk(b"IHDR", b"\0\0\0\x03\0\0\0\x02\x08\x02\0\0\0"));
+        png.extend(chunk(b"tEXt", b"Title\0Caf\xe9"));
+        png.extend(chunk(
+            b"iTXt",
+            &[&b"XML:com.adobe.xmp\0\0\0\0\0"[..], xmp].concat(),
+        ));
+        png.extend(chunk(b"eXIf", &exif));
+        png.extend(chunk(b"IEND", b""));
+        let records = read(&png).unwrap();
+        assert_eq!(
+            records.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            vec![
+                r#"{"format": "PNG", "width": 3, "height": 2, "taken": "2024-05-01T12:34:56", "gps": null, "exif": {"Orientation": 6, "DateTime": "2024:05:01 12:34:56"}, "xmp": {"dc:subject": ["city"]}, "text": {"Title": "Café"}}"#
+            ]
+        );
+        assert!(read(&png[..40]).is_err());
+        assert!(read(b"GIF89a").is_err());
+    }
+}
@@ -0,0 +1,1745 @@
+//! Amazon Ion, in both the text and the binary encoding.
+//!
+//! The source tells the encodings apart by the binary version marker, and reads the whole input
+//! up front.  Ion types that have no direct equivalent become maps with a single key, like
+//! BSON's Extended JSON: symbols become `{"$symbol": "..."}`, decimals `{"$decimal": "1.50"}`,
+//! timestamps `{"$timestamp": "2024-01-01T00:00Z"}` (in Ion text syntax), S-expressions
+//! `{"$sexp": [...]}` and clobs `{"$clob": ...}`.  Blobs become bytes, and integers that don't
+//! fit in 64 bits become decimals.  Annotated values become `{"$annotations": [...], "$value":
+//! ...}`.  Both sinks turn such maps back into the Ion types.
+
+use std::collections;
+use std::convert::TryFrom;
+use std::fmt;
+use std::io;
+use std::str;
+
+use base64::Engine;
+
+use crate::error;
+use crate::value;
+
+const SYMBOL: &str = "$symbol";
+const DECIMAL: &str = "$decimal";
+const TIMESTAMP: &str = "$timestamp";
+const SEXP: &str = "$sexp";
+const CLOB: &str = "$clob";
+const ANNOTATIONS: &str = "$annotations";
+const VALUE: &str = "$value";
+
+/// The binary version marker, which starts binary Ion and resets the symbol table.
+const VERSION_MARKER: [u8; 4] = [0xe0, 0x01, 0x00, 0xea];
+
+/// The symbols that every symbol table starts with.
+const SYSTEM_SYMBOLS: [&str; 9] = [
+    "$ion",
+    "$ion_1_0",
+    "$ion_symbol_table",
+    "name",
+    "version",
+    "imports",
+    "symbols",
+    "max_id",
+    "$ion_shared_symbol_table",
+];
+const SYMBOL_TABLE_SID: u64 = 3;
+const IMPORTS_SID: u64 = 6;
+const SYMBOLS_SID: u64 = 7;
+/// How deeply containers are nested at most.
+const MAX_DEPTH: usize = 128;
+
+pub struct Source {
+    input: Input,
+    position: usize,
+    /// The texts of the symbols by their id, starting at 1.  Shared symbol tables aren't
+    /// available, so the symbols that they would define have no text.
+    symbols: Vec<Option<String>>,
+}
+
+enum Input {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+/// An Ion text sink, which writes one value per line.
+pub struct Sink<W>(W, Vec<u8>)
+where
+    W: io::Write;
+
+/// An Ion binary sink.  Symbols are added to the local symbol table as they are first used.
+pub struct BinarySink<W>
+where
+    W: io::Write,
+{
+    output: W,
+    buffer: Vec<u8>,
+    symbols: collections::HashMap<String, u64>,
+    started: bool,
+}
+
+/// Creates an Ion source, which reads the whole input up front.
+pub fn source<R>(mut r: R) -> error::Result<Source>
+where
+    R: io::Read,
+{
+    let mut input = Vec::new();
+    r.read_to_end(&mut input)?;
+    let input = if input.starts_with(&VERSION_MARKER) {
+        Input::Binary(input)
+    } else {
+        Input::Text(String::from_utf8(input)?)
+    };
+    Ok(Source {
+        input,
+        position: 0,
+        symbols: system_symbols(),
+    })
+}
+
+#[inline]
+pub fn sink<W>(w: W) -> Sink<W>
+where
+    W: io::Write,
+{
+    Sink(w, Vec::new())
+}
+
+#[inline]
+pub fn binary_sink<W>(w: W) -> BinarySink<W>
+where
+    W: io::Write,
+{
+    BinarySink {
+        output: w,
+        buffer: Vec::new(),
+        symbols: collections::HashMap::new(),
+        started: false,
+    }
+}
+
+fn system_symbols() -> Vec<Option<String>> {
+    SYSTEM_SYMBOLS
+        .iter()
+        .map(|s| Some((*s).to_owned()))
+        .collect()
+}
+
+impl value::Source for Source {
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        loop {
+            let next = match self.input {
+                Input::Text(ref text) => {
+                    let mut parser = TextParser {
+                        input: text,
+                        position: self.position,
+                        symbols: &self.symbols,
+                        depth: 0,
+                    };
+                    let next = parser.top_level();
+                    let position = parser.position;
+                    let next = next.map_err(|msg| error::Error::Format {
+                        msg: format!(
+                            "invalid Ion text on line {}: {}",
+                            text[..position].lines().count().max(1),
+                            msg
+                        ),
+                    })?;
+                    self.position = position;
+                    next
+                }
+                Input::Binary(ref input) => {
+                    if input[self.position..].starts_with(&VERSION_MARKER) {
+                        self.position += VERSION_MARKER.len();
+                        self.symbols = system_symbols();
+                        continue;
+                    }
+                    if self.position == input.len() {
+                        return Ok(None);
+                    }
+                    let mut reader = BinaryReader {
+                        input,
+                        position: self.position,
+                        symbols: &self.symbols,
+                        depth: 0,
+                    };
+                    let next = reader.value();
+                    let position = reader.position;
+                    let next = next.map_err(|msg| error::Error::Format {
+                        msg: format!("invalid Ion binary at byte {}: {}", position, msg),
+                    })?;
+                    self.position = position;
+                    match next {
+                        Some(v) => Some(v),
+                        // Padding
+                        None => continue,
+                    }
+                }
+            };
+
+            match next {
+                Some(v) if is_symbol(&v, "$ion_1_0") => self.symbols = system_symbols(),
+                Some(v) if is_symbol_table(&v) => self.add_symbol_table(v),
+                next => return Ok(next),
+            }
+        }
+    }
+}
+
+impl Source {
+    fn add_symbol_table(&mut self, table: value::Value) {
+        let table = match table.get(VALUE) {
+            Some(table) => table,
+            None => return,
+        };
+        match table.get("imports") {
+            Some(imports) if is_symbol(imports, "$ion_symbol_table") => (),
+            Some(value::Value::Sequence(imports)) => {
+                self.symbols = system_symbols();
+                for import in imports {
+                    let name = import.get("name").and_then(value::Value::as_str);
+                    let max_id = import.get("max_id").and_then(value::Value::as_u64);
+                    warn!(
+                        "Ion input imports the shared symbol table {}, which isn't available; \
+                         its symbols are shown by id",
+                        name.unwrap_or("(unnamed)")
+                    );
+                    let count = usize::try_from(max_id.unwrap_or(0)).unwrap_or(0);
+                    self.symbols.extend(std::iter::repeat_n(None, count));
+                }
+            }
+            _ => self.symbols = system_symbols(),
+        }
+        if let Some(value::Value::Sequence(symbols)) = table.get("symbols") {
+            self.symbols
+                .extend(symbols.iter().map(|s| s.as_str().map(str::to_owned)));
+        }
+    }
+}
+
+fn is_symbol(v: &value::Value, text: &str) -> bool {
+    match *v {
+        value::Value::Map(ref entries) => match entries.as_slice() {
+            [(k, value::Value::String(s))] => k.as_str() == Some(SYMBOL) && s == text,
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// Whether a top-level value is a local symbol table, which isn't a record.
+fn is_symbol_table(v: &value::Value) -> bool {
+    match v.get(ANNOTATIONS) {
+        Some(value::Value::Sequence(annotations)) => {
+            annotations.first().and_then(value::Value::as_str) == Some("$ion_symbol_table")
+                && matches!(v.get(VALUE), Some(value::Value::Map(_)))
+        }
+        _ => false,
+    }
+}
+
+fn single(key: &str, v: value::Value) -> value::Value {
+    value::Value::Map(vec![(key.into(), v)])
+}
+
+fn annotated(annotations: Vec<String>, v: value::Value) -> value::Value {
+    if annotations.is_empty() {
+        v
+    } else {
+        value::Value::Map(vec![
+            (
+                ANNOTATIONS.into(),
+                value::Value::Sequence(annotations.into_iter().map(value::Value::String).collect()),
+            ),
+            (VALUE.into(), v),
+        ])
+    }
+}
+
+fn symbol_text(symbols: &[Option<String>], sid: u64) -> String {
+    usize::try_from(sid)
+        .ok()
+        .and_then(|sid| sid.checked_sub(1))
+        .and_then(|i| symbols.get(i).cloned().flatten())
+        .unwrap_or_else(|| format!("${}", sid))
+}
+
+fn int_value(negative: bool, magnitude: u128) -> value::Value {
+    match (negative, u64::try_from(magnitude)) {
+        (false, Ok(n)) => value::Value::U64(n),
+        (true, _) if magnitude <= 1 << 63 => {
+            value::Value::I64((magnitude as i128).wrapping_neg() as i64)
+        }
+        _ => single(
+            DECIMAL,
+            Decimal {
+                negative,
+                coefficient: magnitude,
+                exponent: 0,
+            }
+            .to_string()
+            .into(),
+        ),
+    }
+}
+
+/// A decimal number, which keeps its precision.
+#[derive(Clone, Debug, PartialEq)]
+struct Decimal {
+    negative: bool,
+    coefficient: u128,
+    exponent: i64,
+}
+
+impl Decimal {
+    /// Parses Ion text like `1.50`, `-2d3` or `0.5e-10`.
+    fn parse(s: &str) -> Option<Self> {
+        let s = s.replace('_', "");
+        let (negative, unsigned) = match s.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, s.as_str()),
+        };
+        let (mantissa, exponent) = match unsigned.find(['d', 'D']) {
+            Some(i) => (&unsigned[..i], unsigned[i + 1..].parse::<i64>().ok()?),
+            None => (unsigned, 0),
+        };
+        let (whole, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+        if whole.is_empty()
+            || !whole
+                .bytes()
+                .chain(fraction.bytes())
+                .all(|b| b.is_ascii_digit())
+        {
+            return None;
+        }
+        let digits = format!("{}{}", whole, fraction);
+        let digits = digits.trim_start_matches('0');
+        let coefficient = if digits.is_empty() {
+            0
+        } else {
+            digits.parse().ok()?
+        };
+        Some(Decimal {
+            negative,
+            coefficient,
+            exponent: exponent.checked_sub(fraction.len() as i64)?,
+        })
+    }
+}
+
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sign = if self.negative { "-" } else { "" };
+        let digits = self.coefficient.to_string();
+        let len = digits.len() as i64;
+        if self.exponent < 0 && -self.exponent <= len + 6 {
+            let point = len + self.exponent;
+            if point <= 0 {
+                write!(f, "{}0.{}{}", sign, "0".repeat(-point as usize), digits)
+            } else {
+                let (whole, fraction) = digits.split_at(point as usize);
+                write!(f, "{}{}.{}", sign, whole, fraction)
+            }
+        } else {
+            write!(f, "{}{}d{}", sign, digits, self.exponent)
+        }
+    }
+}
+
+/// A timestamp in local time, with the precision that it was given in.
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Timestamp {
+    year: i64,
+    month: Option<i64>,
+    day: Option<i64>,
+    hour_minute: Option<(i64, i64)>,
+    second: Option<i64>,
+    /// The digits of the fractional second.
+    fraction: String,
+    /// The offset from UTC in minutes, if it is known.
+    offset: Option<i64>,
+}
+
+impl Timestamp {
+    /// Parses Ion text like `2007T`, `2007-02-23` or `2007-02-23T12:14:33.079-08:00`.
+    fn parse(s: &str) -> Option<Self> {
+        fn number(s: &str, len: usize) -> Option<(i64, &str)> {
+            let digits = s.get(..len)?;
+            if !digits.bytes().all(|b| b.is_ascii_digit()) {
+                return None;
+            }
+            Some((digits.parse().ok()?, &s[len..]))
+        }
+
+        let mut t = Timestamp::default();
+        let (year, rest) = number(s, 4)?;
+        t.year = year;
+        if rest == "T" {
+            return Some(t);
+        }
+        let (month, rest) = number(rest.strip_prefix('-')?, 2)?;
+        t.month = Some(month);
+        if rest == "T" {
+            return Some(t);
+        }
+        let (day, rest) = number(rest.strip_prefix('-')?, 2)?;
+        t.day = Some(day);
+        if rest.is_empty() || rest == "T" {
+            return t.validate();
+        }
+        let (hour, rest) = number(rest.strip_prefix('T')?, 2)?;
+        let (minute, mut rest) = number(rest.strip_prefix(':')?, 2)?;
+        t.hour_minute = Some((hour, minute));
+        if let Some(after) = rest.strip_prefix(':') {
+            let (second, after) = number(after, 2)?;
+            t.second = Some(second);
+            rest = after;
+            if let Some(after) = rest.strip_prefix('.') {
+                let len = after.bytes().take_while(u8::is_ascii_digit).count();
+                if len == 0 {
+                    return None;
+                }
+                t.fraction = after[..len].to_owned();
+                rest = &after[len..];
+            }
+        }
+        t.offset = match rest {
+            "Z" | "z" => Some(0),
+            "-00:00" => None,
+            _ => {
+                let sign = match rest.as_bytes().first()? {
+                    b'+' => 1,
+                    b'-' => -1,
+                    _ => return None,
+                };
+                let (hours, after) = number(&rest[1..], 2)?;
+                let (minutes, after) = number(after.strip_prefix(':')?, 2)?;
+                if !after.is_empty() || hours > 23 || minutes > 59 {
+                    return None;
+                }
+                Some(sign * (hours * 60 + minutes))
+            }
+        };
+        t.validate()
+    }
+
+    fn validate(self) -> Option<Self> {
+        let valid_date = self.month.is_none_or(|m| (1..=12).contains(&m))
+            && self.day.is_none_or(|d| (1..=31).contains(&d));
+        let valid_time = self.hour_minute.is_none_or(|(h, m)| h < 24 && m < 60)
+            && self.second.is_none_or(|s| s < 60);
+        if valid_date && valid_time && self.year >= 1 {
+            Some(self)
+        } else {
+            None
+        }
+    }
+
+    /// Moves the time by some minutes, for converting between local time and UTC.
+    fn shifted(mut self, minutes: i64) -> Self {
+        if let (Some(month), Some(day), Some((hour, minute))) =
+            (self.month, self.day, self.hour_minute)
+        {
+            let days = crate::transform::look_behind::days_from_civil(self.year, month, day);
+            let total = days * 1440 + hour * 60 + minute + minutes;
+            let (year, month, day) = value::bson::civil_from_days(total.div_euclid(1440));
+            let minutes = total.rem_euclid(1440);
+            self.year = year;
+            self.month = Some(month);
+            self.day = Some(day);
+            self.hour_minute = Some((minutes / 60, minutes % 60));
+        }
+        self
+    }
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04}", self.year)?;
+        let month = match self.month {
+            Some(month) => month,
+            None => return write!(f, "T"),
+        };
+        write!(f, "-{:02}", month)?;
+        let day = match self.day {
+            Some(day) => day,
+            None => return write!(f, "T"),
+        };
+        write!(f, "-{:02}", day)?;
+        let (hour, minute) = match self.hour_minute {
+            Some(time) => time,
+            None => return Ok(()),
+        };
+        write!(f, "T{:02}:{:02}", hour, minute)?;
+        if let Some(second) = self.second {
+            write!(f, ":{:02}", second)?;
+            if !self.fraction.is_empty() {
+                write!(f, ".{}", self.fraction)?;
+            }
+        }
+        match self.offset {
+            None => write!(f, "-00:00"),
+            Some(0) => write!(f, "Z"),
+            Some(offset) => {
+                let sign = if offset < 0 { '-' } else { '+' };
+                write!(
+                    f,
+                    "{}{:02}:{:02}",
+                    sign,
+                    offset.abs() / 60,
+                    offset.abs() % 60
+                )
+            }
+        }
+    }
+}
+
+struct TextParser<'a> {
+    input: &'a str,
+    position: usize,
+    symbols: &'a [Option<String>],
+    /// How many containers the parser is inside of.
+    depth: usize,
+}
+
+/// Characters that make up operators, which are symbols in S-expressions.
+const OPERATOR_CHARS: &str = "!#%&*+-./;<=>?@^`|~";
+
+impl<'a> TextParser<'a> {
+    fn top_level(&mut self) -> Result<Option<value::Value>, &'static str> {
+        self.skip_whitespace()?;
+        if self.position == self.input.len() {
+            return Ok(None);
+        }
+        self.value(false).map(Some)
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.input[self.position..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn eat(&mut self, s: &str) -> bool {
+        if self.rest().starts_with(s) {
+            self.position += s.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn skip_whitespace(&mut self) -> Result<(), &'static str> {
+        loop {
+            let rest = self.rest();
+            let trimmed = rest.trim_start();
+            self.position += rest.len() - trimmed.len();
+            if self.eat("//") {
+                let len = self.rest().find('\n').unwrap_or(self.rest().len());
+                self.position += len;
+            } else if self.eat("/*") {
+                let len = self.rest().find("*/").ok_or("unterminated comment")?;
+                self.position += len + 2;
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    fn value(&mut self, in_sexp: bool) -> Result<value::Value, &'static str> {
+        let mut annotations = Vec::new();
+        loop {
+            let start = self.position;
+            let symbol = match self.peek() {
+                Some('\'') if !self.rest().starts_with("'''") => Some(self.quoted('\'')?),
+                Some(c) if c.is_ascii_alphabetic() || c == '_' || c == '$' => {
+                    Some(self.identifier().to_owned())
+                }
+                _ => None,
+            };
+            if let Some(symbol) = symbol {
+                self.skip_whitespace()?;
+                if self.eat("::") {
+                    annotations.push(self.resolve(&symbol));
+                    self.skip_whitespace()?;
+                    continue;
+                }
+            }
+            self.position = start;
+            break;
+        }
+        let v = self.unannotated(in_sexp)?;
+        Ok(annotated(annotations, v))
+    }
+
+    fn unannotated(&mut self, in_sexp: bool) -> Result<value::Value, &'static str> {
+        let rest = self.rest();
+        if self.eat("{{") {
+            return self.lob();
+        }
+        if self.eat("{") {
+            return self.nested(Self::structure);
+        }
+        if self.eat("[") {
+            let values = self.nested(|parser| parser.sequence(']', false))?;
+            return Ok(value::Value::Sequence(values));
+        }
+        if self.eat("(") {
+            let values = self.nested(|parser| parser.sequence(')', true))?;
+            return Ok(single(SEXP, value::Value::Sequence(values)));
+        }
+        if rest.starts_with("'''") {
+            return Ok(value::Value::String(self.long_string()?));
+        }
+        if self.eat("\"") {
+            self.position -= 1;
+            return Ok(value::Value::String(self.quoted('"')?));
+        }
+        if self.eat("'") {
+            self.position -= 1;
+            let text = self.quoted('\'')?;
+            return Ok(single(SYMBOL, text.into()));
+        }
+        if rest.starts_with("+inf") {
+            self.position += 4;
+            return Ok(value::Value::from_f64(f64::INFINITY));
+        }
+        if rest.starts_with("-inf") {
+            self.position += 4;
+            return Ok(value::Value::from_f64(f64::NEG_INFINITY));
+        }
+        let starts_number = matches!(rest.as_bytes(), [b'0'..=b'9', ..] | [b'-', b'0'..=b'9', ..]);
+        if starts_number {
+            return self.number();
+        }
+        match self.peek() {
+            Some(c) if c.is_ascii_alphabetic() || c == '_' || c == '$' => {
+                let identifier = self.identifier();
+                Ok(match identifier {
+                    "true" => value::Value::Bool(true),
+                    "false" => value::Value::Bool(false),
+                    "nan" => value::Value::from_f64(f64::NAN),
+                    "null" => {
+                        // Typed nulls like `null.string`
+                        if self.rest().starts_with('.') {
+                            self.position += 1;
+                            self.identifier();
+                        }
+                        value::Value::Unit
+                    }
+                    symbol => single(SYMBOL, self.resolve(symbol).into()),
+                })
+            }
+            Some(c) if in_sexp && OPERATOR_CHARS.contains(c) => {
+                let len = self
+                    .rest()
+                    .find(|c| !OPERATOR_CHARS.contains(c))
+                    .unwrap_or(self.rest().len());
+                let operator = &self.rest()[..len];
+                self.position += len;
+                Ok(single(SYMBOL, operator.into()))
+            }
+            Some(_) => Err("unexpected character"),
+            None => Err("unexpected end of input"),
+        }
+    }
+
+    /// Parses the contents of a container, failing if it is nested too deeply.
+    fn nested<T, F>(&mut self, parse: F) -> Result<T, &'static str>
+    where
+        F: FnOnce(&mut Self) -> Result<T, &'static str>,
+    {
+        if self.depth >= MAX_DEPTH {
+            return Err("containers are nested too deeply");
+        }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    fn identifier(&mut self) -> &'a str {
+        let rest = self.rest();
+        let len = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '$'))
+            .unwrap_or(rest.len());
+        self.position += len;
+        &rest[..len]
+    }
+
+    /// Resolves symbol ids like `$10` through the symbol table.
+    fn resolve(&self, symbol: &str) -> String {
+        match symbol.strip_prefix('$').map(str::parse::<u64>) {
+            Some(Ok(sid)) => symbol_text(self.symbols, sid),
+            _ => symbol.to_owned(),
+        }
+    }
+
+    fn number(&mut self) -> Result<value::Value, &'static str> {
+        let rest = self.rest();
+        let len = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || "._+-:".contains(c)))
+            .unwrap_or(rest.len());
+        let token = &rest[..len];
+        self.position += len;
+
+        let unsigned = token.strip_prefix('-').unwrap_or(token);
+        let negative = unsigned.len() < token.len();
+        let lower = unsigned.to_ascii_lowercase().replace('_', "");
+        let radix = if let Some(digits) = lower.strip_prefix("0x") {
+            Some((16, digits))
+        } else {
+            lower.strip_prefix("0b").map(|digits| (2, digits))
+        };
+        if let Some((radix, digits)) = radix {
+            let magnitude = u128::from_str_radix(digits, radix).map_err(|_| "invalid integer")?;
+            return Ok(int_value(negative, magnitude));
+        }
+
+        let is_timestamp = token.len() > 4
+            && token.as_bytes()[..4].iter().all(u8::is_ascii_digit)
+            && matches!(token.as_bytes()[4], b'-' | b'T');
+        if is_timestamp {
+            let timestamp = Timestamp::parse(token).ok_or("invalid timestamp")?;
+            Ok(single(TIMESTAMP, timestamp.to_string().into()))
+        } else if lower.contains('e') {
+            let f: f64 = token
+                .replace('_', "")
+                .parse()
+                .map_err(|_| "invalid float")?;
+            Ok(value::Value::from_f64(f))
+        } else if lower.contains('d') || lower.contains('.') {
+            let decimal = Decimal::parse(token).ok_or("invalid decimal")?;
+            Ok(single(DECIMAL, decimal.to_string().into()))
+        } else {
+            if lower.len() > 1 && lower.starts_with('0') {
+                return Err("integers can't have leading zeros");
+            }
+            let magnitude: u128 = lower.parse().map_err(|_| "invalid integer")?;
+            Ok(int_value(negative, magnitude))
+        }
+    }
+
+    /// Reads a string or symbol in the given quotes, with escapes.
+    fn quoted(&mut self, quote: char) -> Result<String, &'static str> {
+        self.position += 1;
+        let mut s = String::new();
+        loop {
+            let c = self.peek().ok_or("unterminated string")?;
+            self.position += c.len_utf8();
+            match c {
+                c if c == quote => return Ok(s),
+                '\\' => self.escape(&mut s)?,
+                '\n' if quote == '"' => return Err("newline in a string"),
+                c => s.push(c),
+            }
+        }
+    }
+
+    /// Reads one or more adjacent long strings, which are concatenated.
+    fn long_string(&mut self) -> Result<String, &'static str> {
+        let mut s = String::new();
+        loop {
+            self.position += 3;
+            loop {
+                if self.eat("'''") {
+                    break;
+                }
+                let c = self.peek().ok_or("unterminated long string")?;
+                self.position += c.len_utf8();
+                match c {
+                    '\\' => self.escape(&mut s)?,
+                    c => s.push(c),
+                }
+            }
+            let end = self.position;
+            self.skip_whitespace()?;
+            if !self.rest().starts_with("'''") {
+                self.position = end;
+                return Ok(s);
+            }
+        }
+    }
+
+    fn escape(&mut self, s: &mut String) -> Result<(), &'static str> {
+        let c = self.peek().ok_or("unterminated escape")?;
+        self.position += c.len_utf8();
+        let unescaped = match c {
+            'a' => '\u{7}',
+            'b' => '\u{8}',
+            't' => '\t',
+            'n' => '\n',
+            'f' => '\u{c}',
+            'r' => '\r',
+            'v' => '\u{b}',
+            '0' => '\0',
+            '?' | '/' | '\'' | '"' | '\\' => c,
+            // Escaped newlines are left out
+            '\n' => return Ok(()),
+            'x' | 'u' | 'U' => {
+                let len = match c {
+                    'x' => 2,
+                    'u' => 4,
+                    _ => 8,
+                };
+                let digits = self.rest().get(..len).ok_or("unterminated escape")?;
+                let code = u32::from_str_radix(digits, 16).map_err(|_| "invalid escape")?;
+                self.position += len;
+                char::from_u32(code).ok_or("invalid escape")?
+            }
+            _ => return Err("invalid escape"),
+        };
+        s.push(unescaped);
+        Ok(())
+    }
+
+    fn lob(&mut self) -> Result<value::Value, &'static str> {
+        self.skip_whitespace()?;
+        let v = if self.rest().starts_with("'''") {
+            let text = self.long_string()?;
+            single(CLOB, clob_bytes(&text)?)
+        } else if self.rest().starts_with('"') {
+            let text = self.quoted('"')?;
+            single(CLOB, clob_bytes(&text)?)
+        } else {
+            let len = self.rest().find('}').ok_or("unterminated blob")?;
+            let encoded: String = self.rest()[..len]
+                .chars()
+                .filter(|c| !c.is_whitespace())
+                .collect();
+            self.position += len;
+            let bytes = base64::engine::general_purpose::STANDARD
+                .decode(encoded)
+                .map_err(|_| "invalid base64 in a blob")?;
+            value::Value::Bytes(bytes)
+        };
+        self.skip_whitespace()?;
+        if self.eat("}}") {
+            Ok(v)
+        } else {
+            Err("expected }}")
+        }
+    }
+
+    fn structure(&mut self) -> Result<value::Value, &'static str> {
+        let mut entries = Vec::new();
+        loop {
+            self.skip_whitespace()?;
+            if self.eat("}") {
+                return Ok(value::Value::Map(entries));
+            }
+            let key = match self.peek() {
+                Some('"') => self.quoted('"')?,
+                Some('\'') if self.rest().starts_with("'''") => self.long_string()?,
+                Some('\'') => self.quoted('\'')?,
+                Some(c) if c.is_ascii_alphabetic() || c == '_' || c == '$' => {
+                    let identifier = self.identifier();
+                    self.resolve(identifier)
+                }
+                _ => return Err("expected a field name"),
+            };
+            self.skip_whitespace()?;
+            if !self.eat(":") {
+                return Err("expected : after a field name");
+            }
+            self.skip_whitespace()?;
+            let v = self.value(false)?;
+            entries.push((value::Value::String(key), v));
+            self.skip_whitespace()?;
+            if !self.eat(",") && !self.rest().starts_with('}') {
+                return Err("expected , or }");
+            }
+        }
+    }
+
+    fn sequence(&mut self, end: char, in_sexp: bool) -> Result<Vec<value::Value>, &'static str> {
+        let mut values = Vec::new();
+        loop {
+            self.skip_whitespace()?;
+            if self.peek() == Some(end) {
+                self.position += 1;
+                return Ok(values);
+            }
+            values.push(self.value(in_sexp)?);
+            self.skip_whitespace()?;
+            if !in_sexp && !self.eat(",") && self.peek() != Some(end) {
+                return Err("expected , or the end of the list");
+            }
+        }
+    }
+}
+
+/// The bytes of a clob, whose text may only contain characters up to `\xff`.
+fn clob_bytes(text: &str) -> Result<value::Value, &'static str> {
+    text.chars()
+        .map(|c| u8::try_from(u32::from(c)).map_err(|_| "clobs can only contain bytes"))
+        .collect::<Result<_, _>>()
+        .map(value::Value::Bytes)
+}
+
+struct BinaryReader<'a> {
+    input: &'a [u8],
+    position: usize,
+    symbols: &'a [Option<String>],
+    /// How many containers the reader is inside of.
+    depth: usize,
+}
+
+impl<'a> BinaryReader<'a> {
+    /// Reads a value, or `None` for padding.
+    fn value(&mut self) -> Result<Option<value::Value>, &'static str> {
+        if self.depth >= MAX_DEPTH {
+            return Err("containers are nested too deeply");
+        }
+        let descriptor = self.byte()?;
+        let (kind, low) = (descriptor >> 4, descriptor & 0x0f);
+        if low == 15 {
+            return match kind {
+                0..=13 => Ok(Some(value::Value::Unit)),
+                _ => Err("invalid type descriptor"),
+            };
+        }
+        if kind == 1 {
+            return match low {
+                0 => Ok(Some(value::Value::Bool(false))),
+                1 => Ok(Some(value::Value::Bool(true))),
+                _ => Err("invalid boolean"),
+            };
+        }
+        let len = if low == 14 || (kind == 13 && low == 1) {
+            usize::try_from(self.var_uint()?).map_err(|_| "length out of range")?
+        } else {
+            usize::from(low)
+        };
+        let body = self.take(len)?;
+        let mut reader = BinaryReader {
+            input: body,
+            position: 0,
+            symbols: self.symbols,
+            depth: self.depth + 1,
+        };
+
+        let v = match kind {
+            0 => return Ok(None),
+            2 | 3 => {
+                if body.len() > 16 {
+                    return Err("integer is too large");
+                }
+                let magnitude = body.iter().fold(0u128, |n, &b| n << 8 | u128::from(b));
+                if kind == 3 && magnitude == 0 {
+                    return Err("negative zero integer");
+                }
+                int_value(kind == 3, magnitude)
+            }
+            4 => match *body {
+                [] => value::Value::from_f64(0.0),
+                [a, b, c, d] => value::Value::from_f32(f32::from_be_bytes([a, b, c, d])),
+                [..] if body.len() == 8 => {
+                    let mut bytes = [0; 8];
+                    bytes.copy_from_slice(body);
+                    value::Value::from_f64(f64::from_be_bytes(bytes))
+                }
+                _ => return Err("invalid float length"),
+            },
+            5 => {
+                let decimal = if body.is_empty() {
+                    Decimal {
+                        negative: false,
+                        coefficient: 0,
+                        exponent: 0,
+                    }
+                } else {
+                    let (exponent, _) = reader.var_int()?;
+                    let (negative, coefficient) = reader.int_rest()?;
+                    Decimal {
+                        negative,
+                        coefficient,
+                        exponent,
+                    }
+                };
+                single(DECIMAL, decimal.to_string().into())
+            }
+            6 => single(TIMESTAMP, reader.timestamp()?.to_string().into()),
+            7 => {
+                let sid = body.iter().fold(0u64, |n, &b| n << 8 | u64::from(b));
+                single(SYMBOL, symbol_text(self.symbols, sid).into())
+            }
+            8 => value::Value::String(
+                str::from_utf8(body)
+                    .map_err(|_| "string is not UTF-8")?
+                    .to_owned(),
+            ),
+            9 => single(CLOB, value::Value::Bytes(body.to_vec())),
+            10 => value::Value::Bytes(body.to_vec()),
+            11 | 12 => {
+                let mut values = Vec::new();
+                while reader.position < body.len() {
+                    values.extend(reader.value()?);
+                }
+                if kind == 11 {
+                    value::Value::Sequence(values)
+                } else {
+                    single(SEXP, value::Value::Sequence(values))
+                }
+            }
+            13 => {
+                let mut entries = Vec::new();
+                while reader.position < body.len() {
+                    let sid = reader.var_uint()?;
+                    if let Some(v) = reader.value()? {
+                        entries.push((symbol_text(self.symbols, sid).into(), v));
+                    }
+                }
+                value::Value::Map(entries)
+            }
+            14 => {
+                let annotations_len =
+                    usize::try_from(reader.var_uint()?).map_err(|_| "length out of range")?;
+                let end = reader.position + annotations_len;
+                let mut annotations = Vec::new();
+                while reader.position < end {
+                    annotations.push(symbol_text(self.symbols, reader.var_uint()?));
+                }
+                let v = reader.value()?.ok_or("annotated padding")?;
+                if reader.position != body.len() {
+                    return Err("annotation wrapper length doesn't match its value");
+                }
+                annotated(annotations, v)
+            }
+            _ => return Err("invalid type descriptor"),
+        };
+        Ok(Some(v))
+    }
+
+    /// Reads a timestamp, which is stored in UTC with the local offset.
+    fn timestamp(&mut self) -> Result<Timestamp, &'static str> {
+        let (offset, unknown_offset) = self.var_int()?;
+        let mut t = Timestamp {
+            year: self.var_uint_i64()?,
+            offset: if unknown_offset { None } else { Some(offset) },
+            ..Timestamp::default()
+        };
+        if self.position < self.input.len() {
+            t.month = Some(self.var_uint_i64()?);
+        }
+        if self.position < self.input.len() {
+            t.day = Some(self.var_uint_i64()?);
+        }
+        if self.position < self.input.len() {
+            t.hour_minute = Some((self.var_uint_i64()?, self.var_uint_i64()?));
+        }
+        if self.position < self.input.len() {
+            t.second = Some(self.var_uint_i64()?);
+        }
+        if self.position < self.input.len() {
+            let (exponent, _) = self.var_int()?;
+            let (negative, coefficient) = self.int_rest()?;
+            if negative || exponent > 0 {
+                return Err("invalid fractional second");
+            }
+            if exponent < 0 {
+                let digits = coefficient.to_string();
+                let len = usize::try_from(-exponent).map_err(|_| "invalid fractional second")?;
+                if digits.len() > len && coefficient != 0 {
+                    return Err("invalid fractional second");
+                }
+                t.fraction = format!("{:0>width$}", digits, width = len);
+            }
+        }
+        let t = t.validate().ok_or("invalid timestamp")?;
+        Ok(match t.offset {
+            Some(offset) => t.shifted(offset),
+            None => t,
+        })
+    }
+
+    fn byte(&mut self) -> Result<u8, &'static str> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], &'static str> {
+        let end = self
+            .position
+            .checked_add(len)
+            .filter(|&end| end <= self.input.len())
+            .ok_or("input ended in a value")?;
+        let bytes = &self.input[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn var_uint(&mut self) -> Result<u64, &'static str> {
+        let mut n = 0u64;
+        loop {
+            let b = self.byte()?;
+            n = n
+                .checked_mul(128)
+                .ok_or("variable-length integer is too large")?
+                | u64::from(b & 0x7f);
+            if b & 0x80 != 0 {
+                return Ok(n);
+            }
+        }
+    }
+
+    fn var_uint_i64(&mut self) -> Result<i64, &'static str> {
+        i64::try_from(self.var_uint()?).map_err(|_| "variable-length integer is too large")
+    }
+
+    /// Reads a signed variable-length integer, and whether it is negative zero.
+    fn var_int(&mut self) -> Result<(i64, bool), &'static str> {
+        let first = self.byte()?;
+        let negative = first & 0x40 != 0;
+        let mut n = i64::from(first & 0x3f);
+        let mut b = first;
+        while b & 0x80 == 0 {
+            b = self.byte()?;
+            n = n
+                .checked_mul(128)
+                .ok_or("variable-length integer is too large")?
+                | i64::from(b & 0x7f);
+        }
+        Ok(if negative { (-n, n == 0) } else { (n, false) })
+    }
+
+    /// Reads a signed integer in sign-and-magnitude form from the rest of the input.
+    fn int_rest(&mut self) -> Result<(bool, u128), &'static str> {
+        let bytes = &self.input[self.position..];
+        self.position = self.input.len();
+        if bytes.len() > 16 {
+            return Err("integer is too large");
+        }
+        let negative = bytes.first().is_some_and(|b| b & 0x80 != 0);
+        let magnitude = bytes.iter().enumerate().fold(0u128, |n, (i, &b)| {
+            n << 8 | u128::from(if i == 0 { b & 0x7f } else { b })
+        });
+        Ok((negative, magnitude))
+    }
+}
+
+/// A value to write, with the conventions for Ion types resolved.
+#[derive(Debug)]
+struct Annotated {
+    annotations: Vec<String>,
+    ion: Ion,
+}
+
+#[derive(Debug)]
+enum Ion {
+    Null,
+    Bool(bool),
+    Int(i128),
+    Float(f64),
+    Decimal(Decimal),
+    Timestamp(Timestamp),
+    Symbol(String),
+    String(String),
+    Blob(Vec<u8>),
+    Clob(Vec<u8>),
+    List(Vec<Annotated>),
+    Sexp(Vec<Annotated>),
+    Struct(Vec<(String, Annotated)>),
+}
+
+impl Annotated {
+    fn from_value(v: value::Value) -> error::Result<Self> {
+        let ion = match v {
+            value::Value::Unit => Ion::Null,
+            value::Value::Bool(v) => Ion::Bool(v),
+            value::Value::I8(v) => Ion::Int(i128::from(v)),
+            value::Value::I16(v) => Ion::Int(i128::from(v)),
+            value::Value::I32(v) => Ion::Int(i128::from(v)),
+            value::Value::I64(v) => Ion::Int(i128::from(v)),
+            value::Value::U8(v) => Ion::Int(i128::from(v)),
+            value::Value::U16(v) => Ion::Int(i128::from(v)),
+            value::Value::U32(v) => Ion::Int(i128::from(v)),
+            value::Value::U64(v) => Ion::Int(i128::from(v)),
+            value::Value::F32(v) => Ion::Float(f64::from(v.into_inner())),
+            value::Value::F64(v) => Ion::Float(v.into_inner()),
+            value::Value::Char(v) => Ion::String(v.to_string()),
+            value::Value::String(v) => Ion::String(v),
+            value::Value::Bytes(v) => Ion::Blob(v),
+            value::Value::Sequence(values) => Ion::List(
+                values
+                    .into_iter()
+                    .map(Annotated::from_value)
+                    .collect::<error::Result<_>>()?,
+            ),
+            value::Value::Map(entries) => match Annotated::from_convention(entries)? {
+                Ok(v) => return Ok(v),
+                Err(entries) => Ion::Struct(
+                    entries
+                        .into_iter()
+                        .map(|(k, v)| Ok((k.into_key_string(), Annotated::from_value(v)?)))
+                        .collect::<error::Result<_>>()?,
+                ),
+            },
+        };
+        Ok(Annotated {
+            annotations: Vec::new(),
+            ion,
+        })
+    }
+
+    /// Resolves a map that stands for an Ion type, or gives back its entries if it doesn't.
+    #[allow(clippy::type_complexity)]
+    fn from_convention(
+        mut entries: Vec<(value::Value, value::Value)>,
+    ) -> error::Result<Result<Self, Vec<(value::Value, value::Value)>>> {
+        let invalid = |what: &str, v: &value::Value| error::Error::Format {
+            msg: format!(
+                "not a valid Ion {}: {}",
+                what,
+                v.summary(value::ERROR_SUMMARY_LEN)
+            ),
+        };
+        let plain = |ion| {
+            Ok(Ok(Annotated {
+                annotations: Vec::new(),
+                ion,
+            }))
+        };
+
+        if entries.len() == 2 {
+            let position = |key| entries.iter().position(|(k, _)| k.as_str() == Some(key));
+            if let (Some(a), Some(v)) = (position(ANNOTATIONS), position(VALUE)) {
+                let annotations = match entries[a].1 {
+                    value::Value::Sequence(ref names) => names
+                        .iter()
+                        .map(|n| n.as_str().map(str::to_owned))
+                        .collect::<Option<Vec<_>>>()
+                        .ok_or_else(|| invalid("annotation", &entries[a].1))?,
+                    ref other => return Err(invalid("annotation", other)),
+                };
+                let v = entries.swap_remove(v).1;
+                let mut inner = Annotated::from_value(v)?;
+                inner.annotations.splice(0..0, annotations);
+                return Ok(Ok(inner));
+            }
+        }
+        if entries.len() != 1 {
+            return Ok(Err(entries));
+        }
+
+        let key = entries[0].0.as_str().map(str::to_owned);
+        let v = &entries[0].1;
+        match (key.as_deref(), v) {
+            (Some(SYMBOL), value::Value::String(s)) => plain(Ion::Symbol(s.clone())),
+            (Some(DECIMAL), value::Value::String(s)) => match Decimal::parse(s) {
+                Some(d) => plain(Ion::Decimal(d)),
+                None => Err(invalid("decimal", v)),
+            },
+            (Some(TIMESTAMP), value::Value::String(s)) => match Timestamp::parse(s) {
+                Some(t) => plain(Ion::Timestamp(t)),
+                None => Err(invalid("timestamp", v)),
+            },
+            (Some(CLOB), value::Value::Bytes(bytes)) => plain(Ion::Clob(bytes.clone())),
+            (Some(CLOB), value::Value::String(s)) => plain(Ion::Clob(s.as_bytes().to_vec())),
+            (Some(SEXP), value::Value::Sequence(_)) => match entries.remove(0).1 {
+                value::Value::Sequence(values) => plain(Ion::Sexp(
+                    values
+                        .into_iter()
+                        .map(Annotated::from_value)
+                        .collect::<error::Result<_>>()?,
+                )),
+                _ => unreachable!(),
+            },
+            _ => Ok(Err(entries)),
+        }
+    }
+}
+
+fn write_text(out: &mut String, v: &Annotated) {
+    for annotation in &v.annotations {
+        write_symbol(out, annotation);
+        out.push_str("::");
+    }
+    match v.ion {
+        Ion::Null => out.push_str("null"),
+        Ion::Bool(b) => out.push_str(if b { "true" } else { "false" }),
+        Ion::Int(n) => out.push_str(&n.to_string()),
+        Ion::Float(f) if f.is_nan() => out.push_str("nan"),
+        Ion::Float(f) if f.is_infinite() => out.push_str(if f > 0.0 { "+inf" } else { "-inf" }),
+        Ion::Float(f) => out.push_str(&format!("{:e}", f)),
+        Ion::Decimal(ref d) => out.push_str(&d.to_string()),
+        Ion::Timestamp(ref t) => out.push_str(&t.to_string()),
+        Ion::Symbol(ref s) => write_symbol(out, s),
+        Ion::String(ref s) => write_quoted(out, s, '"'),
+        Ion::Blob(ref bytes) => {
+            out.push_str("{{");
+            out.push_str(&base64::engine::general_purpose::STANDARD.encode(bytes));
+            out.push_str("}}");
+        }
+        Ion::Clob(ref bytes) => {
+            out.push_str("{{\"");
+            for &b in bytes {
+                match b {
+                    b'"' | b'\\' => {
+                        out.push('\\');
+                        out.push(char::from(b));
+                    }
+                    0x20..=0x7e => out.push(char::from(b)),
+                    _ => out.push_str(&format!("\\x{:02x}", b)),
+                }
+            }
+            out.push_str("\"}}");
+        }
+        Ion::List(ref values) => {
+            out.push('[');
+            for (i, v) in values.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                write_text(out, v);
+            }
+            out.push(']');
+        }
+        Ion::Sexp(ref values) => {
+            out.push('(');
+            for (i, v) in values.iter().enumerate() {
+                if i > 0 {
+                    out.push(' ');
+                }
+                write_text(out, v);
+            }
+            out.push(')');
+        }
+        Ion::Struct(ref entries) => {
+            out.push('{');
+            for (i, (k, v)) in entries.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                write_symbol(out, k);
+                out.push_str(": ");
+                write_text(out, v);
+            }
+            out.push('}');
+        }
+    }
+}
+
+/// Writes a symbol, in quotes unless it is a valid identifier.
+fn write_symbol(out: &mut String, s: &str) {
+    let is_identifier = s
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == '$')
+        && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$')
+        && !matches!(s, "null" | "true" | "false" | "nan")
+        // Identifiers like `$10` would be symbol ids
+        && !(s.starts_with('$') && s[1..].bytes().all(|b| b.is_ascii_digit()));
+    if is_identifier {
+        out.push_str(s);
+    } else {
+        write_quoted(out, s, '\'');
+    }
+}
+
+fn write_quoted(out: &mut String, s: &str, quote: char) {
+    out.push(quote);
+    for c in s.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c == quote => {
+                out.push('\\');
+                out.push(c);
+            }
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", u32::from(c))),
+            c => out.push(c),
+        }
+    }
+    out.push(quote);
+}
+
+impl<W> value::Sink for Sink<W>
+where
+    W: io::Write,
+{
+    fn write(&mut self, v: value::Value) -> error::Result<()> {
+        let v = Annotated::from_value(v)?;
+        let mut text = String::new();
+        write_text(&mut text, &v);
+        text.push('\n');
+        self.1.clear();
+        self.1.extend_from_slice(text.as_bytes());
+        self.0.write_all(&self.1)?;
+        Ok(())
+    }
+
+    #[inline]
+    fn flush(&mut self) -> error::Result<()> {
+        self.0.flush()?;
+        Ok(())
+    }
+}
+
+impl<W> BinarySink<W>
+where
+    W: io::Write,
+{
+    fn sid(&self, symbol: &str) -> u64 {
+        match SYSTEM_SYMBOLS.iter().position(|s| *s == symbol) {
+            Some(i) => i as u64 + 1,
+            None => self.symbols[symbol],
+        }
+    }
+
+    fn add_symbol(&mut self, symbol: &str, new: &mut Vec<String>) {
+        if !SYSTEM_SYMBOLS.contains(&symbol) && !self.symbols.contains_key(symbol) {
+            let sid = (SYSTEM_SYMBOLS.len() + self.symbols.len() + 1) as u64;
+            self.symbols.insert(symbol.to_owned(), sid);
+            new.push(symbol.to_owned());
+        }
+    }
+
+    /// Adds the symbols that aren't in the symbol table yet.
+    fn collect_symbols(&mut self, v: &Annotated, new: &mut Vec<String>) {
+        for annotation in &v.annotations {
+            self.add_symbol(annotation, new);
+        }
+        match v.ion {
+            Ion::Symbol(ref s) => self.add_symbol(s, new),
+            Ion::List(ref values) | Ion::Sexp(ref values) => {
+                for v in values {
+                    self.collect_symbols(v, new);
+                }
+            }
+            Ion::Struct(ref entries) => {
+                for (k, v) in entries {
+                    self.add_symbol(k, new);
+                    self.collect_symbols(v, new);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn encode(&self, out: &mut Vec<u8>, v: &Annotated) {
+        if v.annotations.is_empty() {
+            return self.encode_unannotated(out, &v.ion);
+        }
+        let mut annotations = Vec::new();
+        for annotation in &v.annotations {
+            write_var_uint(&mut annotations, self.sid(annotation));
+        }
+        let mut body = Vec::new();
+        write_var_uint(&mut body, annotations.len() as u64);
+        body.extend_from_slice(&annotations);
+        self.encode_unannotated(&mut body, &v.ion);
+        write_typed(out, 14, &body);
+    }
+
+    fn encode_unannotated(&self, out: &mut Vec<u8>, ion: &Ion) {
+        match *ion {
+            Ion::Null => out.push(0x0f),
+            Ion::Bool(b) => out.push(0x10 | u8::from(b)),
+            Ion::Int(n) => {
+                let kind = if n < 0 { 3 } else { 2 };
+                write_typed(out, kind, &magnitude_bytes(n.unsigned_abs()));
+            }
+            Ion::Float(f) => write_typed(out, 4, &f.to_be_bytes()),
+            Ion::Decimal(ref d) => {
+                let mut body = Vec::new();
+                if d.negative || d.coefficient != 0 || d.exponent != 0 {
+                    write_var_int(&mut body, d.exponent, false);
+                    write_int(&mut body, d.negative, d.coefficient);
+                }
+                write_typed(out, 5, &body);
+            }
+            Ion::Timestamp(ref t) => {
+                let utc = match t.offset {
+                    Some(offset) => t.clone().shifted(-offset),
+                    None => t.clone(),
+                };
+                let mut body = Vec::new();
+                write_var_int(&mut body, t.offset.unwrap_or(0), t.offset.is_none());
+                write_var_uint(&mut body, utc.year as u64);
+                let (hour, minute) = utc.hour_minute.unzip();
+                let fields = [utc.month, utc.day, hour, minute, utc.second];
+                for field in fields.iter().flatten() {
+                    write_var_uint(&mut body, *field as u64);
+                }
+                if !utc.fraction.is_empty() {
+                    write_var_int(&mut body, -(utc.fraction.len() as i64), false);
+                    let coefficient = utc.fraction.parse::<u128>().unwrap_or(0);
+                    if coefficient != 0 {
+                        write_int(&mut body, false, coefficient);
+                    }
+                }
+                write_typed(out, 6, &body);
+            }
+            Ion::Symbol(ref s) => write_typed(out, 7, &magnitude_bytes(u128::from(self.sid(s)))),
+            Ion::String(ref s) => write_typed(out, 8, s.as_bytes()),
+            Ion::Clob(ref bytes) => write_typed(out, 9, bytes),
+            Ion::Blob(ref bytes) => write_typed(out, 10, bytes),
+            Ion::List(ref values) | Ion::Sexp(ref values) => {
+                let mut body = Vec::new();
+                for v in values {
+                    self.encode(&mut body, v);
+                }
+                let kind = if let Ion::List(_) = *ion { 11 } else { 12 };
+                write_typed(out, kind, &body);
+            }
+            Ion::Struct(ref entries) => {
+                let mut body = Vec::new();
+                for (k, v) in entries {
+                    write_var_uint(&mut body, self.sid(k));
+                    self.encode(&mut body, v);
+                }
+                write_typed(out, 13, &body);
+            }
+        }
+    }
+}
+
+/// Writes a type descriptor with the length, followed by the body.
+fn write_typed(out: &mut Vec<u8>, kind: u8, body: &[u8]) {
+    // A struct with length 1 would mean a sorted struct with a separate length
+    if body.len() < 14 && !(kind == 13 && body.len() == 1) {
+        out.push(kind << 4 | body.len() as u8);
+    } else {
+        out.push(kind << 4 | 14);
+        write_var_uint(out, body.len() as u64);
+    }
+    out.extend_from_slice(body);
+}
+
+fn write_var_uint(out: &mut Vec<u8>, n: u64) {
+    let mut groups = vec![(n & 0x7f) as u8 | 0x80];
+    let mut rest = n >> 7;
+    while rest != 0 {
+        groups.push((rest & 0x7f) as u8);
+        rest >>= 7;
+    }
+    out.extend(groups.iter().rev());
+}
+
+fn write_var_int(out: &mut Vec<u8>, n: i64, negative_zero: bool) {
+    let magnitude = n.unsigned_abs();
+    let negative = n < 0 || negative_zero;
+    let mut groups = Vec::new();
+    let mut rest = magnitude;
+    loop {
+        groups.push((rest & 0x7f) as u8);
+        rest >>= 7;
+        if rest == 0 {
+            break;
+        }
+    }
+    // The first byte only has room for six bits of the magnitude next to the sign
+    if groups.last().is_some_and(|&b| b & 0x40 != 0) {
+        groups.push(0);
+    }
+    groups[0] |= 0x80;
+    if negative {
+        if let Some(first) = groups.last_mut() {
+            *first |= 0x40;
+        }
+    }
+    out.extend(groups.iter().rev());
+}
+
+/// Writes a signed integer in sign-and-magnitude form.
+fn write_int(out: &mut Vec<u8>, negative: bool, magnitude: u128) {
+    let mut bytes = magnitude_bytes(magnitude);
+    if bytes.first().is_none_or(|&b| b & 0x80 != 0) {
+        bytes.insert(0, 0);
+    }
+    if negative {
+        bytes[0] |= 0x80;
+    }
+    out.extend_from_slice(&bytes);
+}
+
+/// The big-endian bytes of a number, without leading zeros.
+fn magnitude_bytes(n: u128) -> Vec<u8> {
+    let bytes = n.to_be_bytes();
+    let start = bytes.iter().position(|&b| b != 0).unwrap_or(bytes.len());
+    bytes[start..].to_vec()
+}
+
+impl<W> value::Sink for BinarySink<W>
+where
+    W: io::Write,
+{
+    fn write(&mut self, v: value::Value) -> error::Result<()> {
+        let v = Annotated::from_value(v)?;
+        self.buffer.clear();
+        if !self.started {
+            self.buffer.extend_from_slice(&VERSION_MARKER);
+        }
+
+        let had_symbols = !self.symbols.is_empty();
+        let mut new = Vec::new();
+        self.collect_symbols(&v, &mut new);
+        if !new.is_empty() {
+            // Appends to the symbol table that earlier values used
+            let mut table = Vec::new();
+            if had_symbols {
+                write_var_uint(&mut table, IMPORTS_SID);
+                write_typed(
+                    &mut table,
+                    7,
+                    &magnitude_bytes(u128::from(SYMBOL_TABLE_SID)),
+                );
+            }
+            let mut symbols = Vec::new();
+            for symbol in &new {
+                write_typed(&mut symbols, 8, symbol.as_bytes());
+            }
+            write_var_uint(&mut table, SYMBOLS_SID);
+            write_typed(&mut table, 11, &symbols);
+            let mut wrapper = vec![0x81, SYMBOL_TABLE_SID as u8 | 0x80];
+            write_typed(&mut wrapper, 13, &table);
+            write_typed(&mut self.buffer, 14, &wrapper);
+        }
+
+        let mut buffer = std::mem::take(&mut self.buffer);
+        self.encode(&mut buffer, &v);
+        self.buffer = buffer;
+        self.output.write_all(&self.buffer)?;
+        self.started = true;
+        Ok(())
+    }
+
+    #[inline]
+    fn flush(&mut self) -> error::Result<()> {
+        self.output.flush()?;
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("IonSource").finish()
+    }
+}
+
+impl<W> fmt::Debug for Sink<W>
+where
+    W: io::Write,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("IonSink").finish()
+    }
+}
+
+impl<W> fmt::Debug for BinarySink<W>
+where
+    W: io::Write,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("IonBinarySink").finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::value::Source as _;
+
+    fn read(input: &[u8]) -> error::Result<Option<value::Value>> {
+        source(input)?.read()
+    }
+
+    /// Binary lists nested in each other.
+    fn nested_binary(depth: usize) -> Vec<u8> {
+        let mut list = Vec::new();
+        for _ in 0..depth {
+            let mut outer = Vec::new();
+            if list.len() < 14 {
+                outer.push(0xb0 | list.len() as u8);
+            } else {
+                outer.push(0xbe);
+                write_var_uint(&mut outer, list.len() as u64);
+            }
+            outer.extend_from_slice(&list);
+            list = outer;
+        }
+        let mut input = VERSION_MARKER.to_vec();
+        input.extend_from_slice(&list);
+        input
+    }
+
+    #[test]
+    fn test_depth() {
+        let nested = |depth: usize| format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+        assert!(read(nested(128).as_bytes()).is_ok());
+        assert!(read(&nested_binary(128)).is_ok());
+        for input in &[
+            nested(129).into_bytes(),
+            nested(200_000).into_bytes(),
+            "{a:".repeat(200_000).into_bytes(),
+            "(".repeat(200_000).into_bytes(),
+            nested_binary(129),
+            nested_binary(100_000),
+        ] {
+            match read(input) {
+                Err(error::Error::Format { msg }) => assert!(msg.contains("nested"), "{}", msg),
+                other => panic!("expected an error for deeply nested Ion, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        use crate::value::Sink as _;
+
+        let text = "$ion_1_0 {at: 2024-01-12T09:30-08:00, price: 1.50, tags: [a, 'b c']}\n\
+                    // A comment\n\
+                    unit::2.5e0 (+ 1 2) null.string";
+        let mut reader = source(text.as_bytes()).unwrap();
+        let records = vec![
+            reader.read().unwrap().unwrap(),
+            reader.read().unwrap().unwrap(),
+            reader.read().unwrap().unwrap(),
+            reader.read().unwrap().unwrap(),
+        ];
+        assert_eq!(reader.read().unwrap(), None);
+        assert_eq!(
+            records[0],
+            value!({
+                "price": {"$decimal": "1.50"},
+                "tags": [{"$symbol": "a"}, {"$symbol": "b c"}],
+                "at": {"$timestamp": "2024-01-12T09:30-08:00"},
+            })
+        );
+        assert_eq!(
+            records[1],
+            value!({"$annotations": ["unit"], "$value": 2.5})
+        );
+        assert_eq!(records[3], value::Value::Unit);
+
+        // The binary encoding of the integer 1
+        let mut reader = source(&b"\xe0\x01\x00\xea\x21\x01"[..]).unwrap();
+        assert_eq!(reader.read().unwrap(), Some(value!(1)));
+
+        for binary in &[false, true] {
+            let mut output = Vec::new();
+            {
+                let mut writer: Box<dyn value::Sink> = if *binary {
+                    Box::new(binary_sink(&mut output))
+                } else {
+                    Box::new(sink(&mut output))
+                };
+                for record in records.clone() {
+                    writer.write(record).unwrap();
+                }
+                assert!(writer.write(value!({"$decimal": "x"})).is_err());
+            }
+            let mut reader = source(&output[..]).unwrap();
+            for record in &records {
+                let read = reader.read().unwrap().unwrap();
+                assert_eq!(read.to_string(), record.to_string());
+            }
+            assert_eq!(reader.read().unwrap(), None);
+        }
+        assert!(source(&b"[1, 2"[..]).unwrap().read().is_err());
+    }
+}
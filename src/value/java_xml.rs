@@ -0,0 +1,428 @@
+//! XML files of Java programs: properties files stored with `Properties.storeToXML`, and logs
+//! written by the XML layouts of log4j and logback.
+//!
+//! Each `properties` document becomes a map from the keys of its entries to their values, and
+//! its comment is left out.
+//!
+//! Each event of a log becomes a flat record with its `timestamp` in RFC 3339, `level`,
+//! `logger`, `thread`, `message`, `throwable` and location (`class`, `method`, `file` and
+//! `line`), which are null when they're missing.  The NDC of log4j 1 events and the marker of
+//! log4j 2 events are there as `ndc` and `marker` when events have them, and the properties of
+//! the events (the MDC or context map) follow under their own keys.  Both the `log4j:event`
+//! elements of log4j 1 and logback and the `Event` elements of log4j 2 are read, either on their
+//! own or in a `log4j:eventSet` or `Events` element.
+
+use std::fmt;
+use std::io;
+
+use quick_xml::events;
+
+use crate::error;
+use crate::value;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Kind {
+    Properties,
+    Log4j,
+}
+
+pub struct Source<R> {
+    reader: quick_xml::Reader<R>,
+    kind: Kind,
+}
+
+impl<R> fmt::Debug for Source<R>
+where
+    R: io::BufRead,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("JavaXmlSource")
+            .field("kind", &self.kind)
+            .finish()
+    }
+}
+
+/// Creates a source for properties files in XML, where each document is a record.
+pub fn properties<R>(r: R) -> Source<R>
+where
+    R: io::BufRead,
+{
+    Source {
+        reader: quick_xml::Reader::from_reader(r),
+        kind: Kind::Properties,
+    }
+}
+
+/// Creates a source for the events of a log4j or logback XML log.
+pub fn log4j<R>(r: R) -> Source<R>
+where
+    R: io::BufRead,
+{
+    Source {
+        reader: quick_xml::Reader::from_reader(r),
+        kind: Kind::Log4j,
+    }
+}
+
+impl<R> value::Source for Source<R>
+where
+    R: io::BufRead,
+{
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        let mut buf = Vec::new();
+        loop {
+            buf.clear();
+            let (start, empty) = match self.reader.read_event_into(&mut buf)? {
+                events::Event::Start(start) => (start.into_owned(), false),
+                events::Event::Empty(start) => (start.into_owned(), true),
+                events::Event::Text(text) if !text.iter().all(u8::is_ascii_whitespace) => {
+                    return Err(format_error(&format!(
+                        "XML text outside of an element at byte {}",
+                        self.reader.buffer_position()
+                    )));
+                }
+                events::Event::Eof => return Ok(None),
+                _ => continue,
+            };
+            let name = local_name(&start);
+            match (self.kind, name.as_str()) {
+                (Kind::Properties, "properties") => {
+                    return Ok(Some(properties_record(&mut self.reader, empty)?))
+                }
+                (Kind::Log4j, "event" | "Event") => {
+                    return Ok(Some(event(&mut self.reader, &start, empty)?))
+                }
+                // Events are read from sets of them as if the set wasn't there
+                (Kind::Log4j, "eventSet" | "Events") => (),
+                _ => {
+                    return Err(format_error(&format!(
+                        "unexpected element {} at byte {}",
+                        name,
+                        self.reader.buffer_position()
+                    )))
+                }
+            }
+        }
+    }
+}
+
+fn properties_record<R>(
+    reader: &mut quick_xml::Reader<R>,
+    empty: bool,
+) -> error::Result<value::Value>
+where
+    R: io::BufRead,
+{
+    let mut entries: Vec<(value::Value, value::Value)> = Vec::new();
+    let mut buf = Vec::new();
+    if !empty {
+        loop {
+            buf.clear();
+            let (child, empty) = match reader.read_event_into(&mut buf)? {
+                events::Event::Start(child) => (child.into_owned(), false),
+                events::Event::Empty(child) => (child.into_owned(), true),
+                events::Event::End(_) => break,
+                events::Event::Eof => {
+                    return Err(format_error("XML input ended in the properties"))
+                }
+                _ => continue,
+            };
+            let text = if empty { String::new() } else { text(reader)? };
+            match local_name(&child).as_str() {
+                "entry" => {
+                    let key = attributes(&child)?
+                        .into_iter()
+                        .find(|(name, _)| name == "key")
+                        .map(|(_, key)| key)
+                        .ok_or_else(|| {
+                            format_error(&format!(
+                                "entry without a key at byte {}",
+                                reader.buffer_position()
+                            ))
+                        })?;
+                    // Like when Java loads the file, a repeated key has the last value
+                    match entries.iter_mut().find(|(k, _)| k.as_str() == Some(&key)) {
+                        Some((_, v)) => *v = text.into(),
+                        None => entries.push((key.into(), text.into())),
+                    }
+                }
+                "comment" => (),
+                name => {
+                    return Err(format_error(&format!(
+                        "unexpected element {} in the properties at byte {}",
+                        name,
+                        reader.buffer_position()
+                    )))
+                }
+            }
+        }
+    }
+    Ok(value::Value::Map(entries))
+}
+
+/// Reads an event of log4j 1 or logback (`log4j:event`) or of log4j 2 (`Event`).
+fn event<R>(
+    reader: &mut quick_xml::Reader<R>,
+    start: &events::BytesStart,
+    empty: bool,
+) -> error::Result<value::Value>
+where
+    R: io::BufRead,
+{
+    let mut fields: Vec<(&str, value::Value)> = [
+        "timestamp",
+        "level",
+        "logger",
+        "thread",
+        "message",
+        "throwable",
+        "class",
+        "method",
+        "file",
+        "line",
+    ]
+    .iter()
+    .map(|&field| (field, value::Value::Unit))
+    .collect();
+    let mut properties = Vec::new();
+    let mut set =
+        |field: &'static str, v: value::Value| match fields.iter_mut().find(|(f, _)| *f == field) {
+            Some((_, existing)) => *existing = v,
+            None => fields.push((field, v)),
+        };
+
+    for (name, v) in attributes(start)? {
+        match name.as_str() {
+            "timestamp" | "timeMillis" => {
+                if let Ok(millis) = v.parse::<i64>() {
+                    let nanos = millis.rem_euclid(1000) as u32 * 1_000_000;
+                    set(
+                        "timestamp",
+                        timestamp(millis.div_euclid(1000), nanos).into(),
+                    );
+                }
+            }
+            "level" => set("level", v.into()),
+            "logger" | "loggerName" => set("logger", v.into()),
+            "thread" => set("thread", v.into()),
+            _ => (),
+        }
+    }
+
+    let mut buf = Vec::new();
+    if !empty {
+        loop {
+            buf.clear();
+            let (child, empty) = match reader.read_event_into(&mut buf)? {
+                events::Event::Start(child) => (child.into_owned(), false),
+                events::Event::Empty(child) => (child.into_owned(), true),
+                events::Event::End(_) => break,
+                events::Event::Eof => return Err(format_error("XML input ended in an event")),
+                _ => continue,
+            };
+            let attributes = attributes(&child)?;
+            let attribute = |name: &str| {
+                attributes
+                    .iter()
+                    .find(|(n, _)| n == name)
+                    .map(|(_, v)| v.clone())
+            };
+            let name = local_name(&child);
+            if name == "properties" || name == "ContextMap" {
+                if !empty {
+                    event_properties(reader, &mut properties)?;
+                }
+                continue;
+            }
+            let text = if empty { String::new() } else { text(reader)? };
+            match name.as_str() {
+                "message" | "Message" => set("message", text.into()),
+                "throwable" => set("throwable", text.trim_end().into()),
+                "NDC" => set("ndc", text.into()),
+                "Thrown" => {
+                    let thrown = match (attribute("name"), attribute("message")) {
+                        (Some(name), Some(message)) => format!("{}: {}", name, message),
+                        (Some(name), None) => name,
+                        (None, message) => message.unwrap_or_default(),
+                    };
+                    set("throwable", thrown.into());
+                }
+                "locationInfo" | "Source" => {
+                    for field in ["class", "method", "file"] {
+                        if let Some(v) = attribute(field) {
+                            set(field, v.into());
+                        }
+                    }
+                    if let Some(line) = attribute("line") {
+                        let line = match line.parse() {
+                            Ok(line) => value::Value::U64(line),
+                            Err(_) => line.into(),
+                        };
+                        set("line", line);
+                    }
+                }
+                "Instant" => {
+                    let seconds = attribute("epochSecond").and_then(|s| s.parse().ok());
+                    let nanos = attribute("nanoOfSecond").and_then(|n| n.parse().ok());
+                    if let Some(seconds) = seconds {
+                        set("timestamp", timestamp(seconds, nanos.unwrap_or(0)).into());
+                    }
+                }
+                "Marker" => {
+                    if let Some(name) = attribute("name") {
+                        set("marker", name.into());
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+    Ok(value::Value::Map(
+        fields
+            .into_iter()
+            .map(|(field, v)| (field.into(), v))
+            .chain(properties)
+            .collect(),
+    ))
+}
+
+/// Reads the properties of an event, which are `log4j:data` elements with a `name` and `value`
+/// in log4j 1, and `item` elements with a `key` and `value` in log4j 2.
+fn event_properties<R>(
+    reader: &mut quick_xml::Reader<R>,
+    properties: &mut Vec<(value::Value, value::Value)>,
+) -> error::Result<()>
+where
+    R: io::BufRead,
+{
+    let mut buf = Vec::new();
+    loop {
+        buf.clear();
+        let (child, empty) = match reader.read_event_into(&mut buf)? {
+            events::Event::Start(child) => (child.into_owned(), false),
+            events::Event::Empty(child) => (child.into_owned(), true),
+            events::Event::End(_) => return Ok(()),
+            events::Event::Eof => return Err(format_error("XML input ended in an event")),
+            _ => continue,
+        };
+        let text = if empty { String::new() } else { text(reader)? };
+        let mut key = None;
+        let mut v = None;
+        for (name, attribute) in attributes(&child)? {
+            match name.as_str() {
+                "name" | "key" => key = Some(attribute),
+                "value" => v = Some(attribute),
+                _ => (),
+            }
+        }
+        if let Some(key) = key {
+            properties.push((key.into(), v.unwrap_or(text).into()));
+        }
+    }
+}
+
+/// Reads the text in an element, up to its end, including the text in elements in it.
+fn text<R>(reader: &mut quick_xml::Reader<R>) -> error::Result<String>
+where
+    R: io::BufRead,
+{
+    let mut text = String::new();
+    let mut depth = 0;
+    let mut buf = Vec::new();
+    loop {
+        buf.clear();
+        match reader.read_event_into(&mut buf)? {
+            events::Event::Start(_) => depth += 1,
+            events::Event::End(_) if depth == 0 => return Ok(text),
+            events::Event::End(_) => depth -= 1,
+            events::Event::Text(t) => text.push_str(&t.unescape()?),
+            events::Event::CData(t) => text.push_str(&String::from_utf8_lossy(&t)),
+            events::Event::Eof => return Err(format_error("XML input ended in an element")),
+            _ => (),
+        }
+    }
+}
+
+/// The attributes of an element by their local names, without namespace prefixes.
+fn attributes(start: &events::BytesStart) -> error::Result<Vec<(String, String)>> {
+    let mut attributes = Vec::new();
+    for attribute in start.attributes() {
+        let attribute = attribute.map_err(quick_xml::Error::from)?;
+        let name = String::from_utf8_lossy(attribute.key.local_name().as_ref()).into_owned();
+        attributes.push((name, attribute.unescape_value()?.into_owned()));
+    }
+    Ok(attributes)
+}
+
+/// The name of an element without its namespace prefix, like `event` for `log4j:event`.
+fn local_name(start: &events::BytesStart) -> String {
+    String::from_utf8_lossy(start.local_name().as_ref()).into_owned()
+}
+
+/// Formats a time as RFC 3339 with as many digits of the fraction of a second as needed, in
+/// groups of three.
+fn timestamp(seconds: i64, nanos: u32) -> String {
+    let time = value::x509::format_time(seconds);
+    let time = time.trim_end_matches('Z');
+    match nanos {
+        0 => format!("{}Z", time),
+        _ if nanos.is_multiple_of(1_000_000) => format!("{}.{:03}Z", time, nanos / 1_000_000),
+        _ if nanos.is_multiple_of(1000) => format!("{}.{:06}Z", time, nanos / 1000),
+        _ => format!("{}.{:09}Z", time, nanos),
+    }
+}
+
+fn format_error(msg: &str) -> error::Error {
+    error::Error::Format {
+        msg: msg.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::value::Source as _;
+
+    const PROPERTIES: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+        <!DOCTYPE properties SYSTEM "http://java.sun.com/dtd/properties.dtd">
+        <properties><comment>x</comment><entry key="a">1 &amp; 2</entry><entry key="b"/></properties>"#;
+
+    fn read<R>(mut reader: Source<R>) -> error::Result<Vec<value::Value>>
+    where
+        R: io::BufRead,
+    {
+        let mut records = Vec::new();
+        while let Some(record) = reader.read()? {
+            records.push(record);
+        }
+        Ok(records)
+    }
+
+    #[test]
+    fn test_properties() {
+        let records = read(properties(PROPERTIES.as_bytes())).unwrap();
+        assert_eq!(records[0].to_string(), r#"{"a": "1 & 2", "b": ""}"#);
+    }
+
+    #[test]
+    fn test_log4j() {
+        let log = r#"<log4j:event logger="App" timestamp="1714480800123" level="ERROR" thread="main">
+            <log4j:message><![CDATA[Failed]]></log4j:message>
+            <log4j:locationInfo class="App" method="main" file="App.java" line="12"/>
+            <log4j:properties><log4j:data name="user" value="alice"/></log4j:properties>
+            </log4j:event>
+            <Events><Event timeMillis="1714480800123" thread="main" level="INFO" loggerName="App">
+            <Instant epochSecond="1714480800" nanoOfSecond="123456000"/><Message>Done</Message>
+            <Thrown message="bad" name="java.lang.RuntimeException"/>
+            <ContextMap><item key="user" value="bob"/></ContextMap></Event></Events>"#;
+        let records = read(log4j(log.as_bytes())).unwrap();
+        assert_eq!(
+            records.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            vec![
+                r#"{"timestamp": "2024-04-30T12:40:00.123Z", "level": "ERROR", "logger": "App", "thread": "main", "message": "Failed", "throwable": null, "class": "App", "method": "main", "file": "App.java", "line": 12, "user": "alice"}"#,
+                r#"{"timestamp": "2024-04-30T12:40:00.123456Z", "level": "INFO", "logger": "App", "thread": "main", "message": "Done", "throwable": "java.lang.RuntimeException: bad", "class": null, "method": null, "file": null, "line": null, "user": "bob"}"#,
+            ]
+        );
+        assert!(read(log4j(PROPERTIES.as_bytes())).is_err());
+    }
+}
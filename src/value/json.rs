@@ -12,11 +12,14 @@ use std::str;
 
 pub struct Source<'de, R>(
     serde_json::StreamDeserializer<'de, serde_json::de::IoRead<R>, value::Value>,
+    u64,
 )
 where
     R: io::Read;
 
-pub struct Sink<W, F>(W, F)
+/// A JSON sink.  Each record is serialized into a buffer first, so that a record that fails to
+/// serialize leaves nothing behind in the output.
+pub struct Sink<W, F>(W, F, Vec<u8>)
 where
     W: io::Write,
     F: Clone + serde_json::ser::Formatter;
@@ -47,6 +50,9 @@ pub struct ReadableFormatter {
     object_key_quote_style: ansi_term::Style,
     object_key_char_style: ansi_term::Style,
     object_key_escape_style: ansi_term::Style,
+
+    highlight: Option<regex::Regex>,
+    highlight_style: ansi_term::Style,
 }
 
 #[inline]
@@ -54,7 +60,10 @@ pub fn source<'de, R>(r: R) -> Source<'de, R>
 where
     R: io::Read,
 {
-    Source(serde_json::Deserializer::new(serde_json::de::IoRead::new(r)).into_iter())
+    Source(
+        serde_json::Deserializer::new(serde_json::de::IoRead::new(r)).into_iter(),
+        0,
+    )
 }
 
 #[inline]
@@ -62,7 +71,7 @@ pub fn sink_compact<W>(w: W) -> Sink<W, serde_json::ser::CompactFormatter>
 where
     W: io::Write,
 {
-    Sink(w, serde_json::ser::CompactFormatter)
+    Sink(w, serde_json::ser::CompactFormatter, Vec::new())
 }
 
 #[inline]
@@ -70,7 +79,18 @@ pub fn sink_readable<W>(w: W) -> Sink<W, ReadableFormatter>
 where
     W: io::Write,
 {
-    Sink(w, ReadableFormatter::new())
+    Sink(w, ReadableFormatter::new(), Vec::new())
+}
+
+/// Like `sink_readable`, but also highlights the parts of string values that match the regex.
+#[inline]
+pub fn sink_readable_highlighting<W>(w: W, regex: regex::Regex) -> Sink<W, ReadableFormatter>
+where
+    W: io::Write,
+{
+    let mut formatter = ReadableFormatter::new();
+    formatter.highlight = Some(regex);
+    Sink(w, formatter, Vec::new())
 }
 
 #[inline]
@@ -78,7 +98,7 @@ pub fn sink_indented<'a, W>(w: W) -> Sink<W, serde_json::ser::PrettyFormatter<'a
 where
     W: io::Write,
 {
-    Sink(w, serde_json::ser::PrettyFormatter::new())
+    Sink(w, serde_json::ser::PrettyFormatter::new(), Vec::new())
 }
 
 impl<'de, R> value::Source for Source<'de, R>
@@ -88,11 +108,23 @@ where
     #[inline]
     fn read(&mut self) -> error::Result<Option<value::Value>> {
         match self.0.next() {
-            Some(Ok(v)) => Ok(Some(v)),
+            Some(Ok(v)) => {
+                self.1 += 1;
+                Ok(Some(v))
+            }
             Some(Err(e)) => Err(error::Error::from(e)),
             None => Ok(None),
         }
     }
+
+    #[inline]
+    fn position(&self) -> Option<value::Position> {
+        Some(value::Position {
+            record: self.1,
+            byte: Some(self.0.byte_offset() as u64),
+            line: None,
+        })
+    }
 }
 
 impl<W, F> value::Sink for Sink<W, F>
@@ -102,12 +134,20 @@ where
 {
     #[inline]
     fn write(&mut self, v: value::Value) -> error::Result<()> {
+        self.2.clear();
         {
             let mut serializer =
-                serde_json::ser::Serializer::with_formatter(&mut self.0, self.1.clone());
+                serde_json::ser::Serializer::with_formatter(&mut self.2, self.1.clone());
             serde::Serialize::serialize(&v, &mut serializer)?;
         }
-        self.0.write_all(b"\n")?;
+        self.2.push(b'\n');
+        self.0.write_all(&self.2)?;
+        Ok(())
+    }
+
+    #[inline]
+    fn flush(&mut self) -> error::Result<()> {
+        self.0.flush()?;
         Ok(())
     }
 }
@@ -141,6 +181,9 @@ impl ReadableFormatter {
             object_key_quote_style: Colour::Blue.dimmed(),
             object_key_char_style: Colour::Blue.normal(),
             object_key_escape_style: Colour::Blue.dimmed(),
+
+            highlight: None,
+            highlight_style: Colour::Red.bold().reverse(),
         }
     }
 
@@ -179,7 +222,7 @@ impl serde_json::ser::Formatter for ReadableFormatter {
     where
         W: io::Write + ?Sized,
     {
-        write!(writer, "{}", self.null_style.paint("null")).map_err(From::from)
+        write!(writer, "{}", self.null_style.paint("null"))
     }
 
     /// Writes a `true` or `false` value to the specified writer.
@@ -193,7 +236,7 @@ impl serde_json::ser::Formatter for ReadableFormatter {
         } else {
             self.false_style.paint("false")
         };
-        write!(writer, "{}", s).map_err(From::from)
+        write!(writer, "{}", s)
     }
 
     #[inline]
@@ -289,7 +332,7 @@ impl serde_json::ser::Formatter for ReadableFormatter {
             self.string_quote_style
         };
 
-        write!(writer, "{}", style.paint("\"")).map_err(From::from)
+        write!(writer, "{}", style.paint("\""))
     }
 
     /// Called after each series of `write_string_fragment` and
@@ -305,7 +348,7 @@ impl serde_json::ser::Formatter for ReadableFormatter {
             self.string_quote_style
         };
 
-        write!(writer, "{}", style.paint("\"")).map_err(From::from)
+        write!(writer, "{}", style.paint("\""))
     }
 
     /// Writes a string fragment that doesn't need any escaping to the
@@ -321,7 +364,24 @@ impl serde_json::ser::Formatter for ReadableFormatter {
             self.string_char_style
         };
 
-        write!(writer, "{}", style.paint(fragment)).map_err(From::from)
+        match self.highlight {
+            // Matches are found within each fragment, so they can't span escape codes
+            Some(ref regex) if !self.is_in_object_key => {
+                let mut end = 0;
+                for m in regex.find_iter(fragment).filter(|m| !m.as_str().is_empty()) {
+                    if m.start() > end {
+                        write!(writer, "{}", style.paint(&fragment[end..m.start()]))?;
+                    }
+                    write!(writer, "{}", self.highlight_style.paint(m.as_str()))?;
+                    end = m.end();
+                }
+                if end < fragment.len() || end == 0 {
+                    write!(writer, "{}", style.paint(&fragment[end..]))?;
+                }
+                Ok(())
+            }
+            _ => write!(writer, "{}", style.paint(fragment)),
+        }
     }
 
     /// Writes a character escape code to the specified writer.
@@ -364,11 +424,11 @@ impl serde_json::ser::Formatter for ReadableFormatter {
                 let s = unsafe { str::from_utf8_unchecked(bytes) };
 
                 // Need to return early because of allocated String
-                return write!(writer, "{}", style.paint(s)).map_err(From::from);
+                return write!(writer, "{}", style.paint(s));
             }
         };
 
-        write!(writer, "{}", style.paint(s)).map_err(From::from)
+        write!(writer, "{}", style.paint(s))
     }
 
     /// Called before every array.  Writes a `[` to the specified
@@ -381,7 +441,7 @@ impl serde_json::ser::Formatter for ReadableFormatter {
         self.current_indent += 1;
         self.has_value = false;
 
-        write!(writer, "{}", self.array_bracket_style.paint("[")).map_err(From::from)
+        write!(writer, "{}", self.array_bracket_style.paint("["))
     }
 
     /// Called after every array.  Writes a `]` to the specified
@@ -398,7 +458,7 @@ impl serde_json::ser::Formatter for ReadableFormatter {
             indent(writer, self.current_indent)?;
         }
 
-        write!(writer, "{}", self.array_bracket_style.paint("]")).map_err(From::from)
+        write!(writer, "{}", self.array_bracket_style.paint("]"))
     }
 
     /// Called before every array value.  Writes a `,` if needed to
@@ -437,7 +497,7 @@ impl serde_json::ser::Formatter for ReadableFormatter {
         self.current_indent += 1;
         self.has_value = false;
 
-        write!(writer, "{}", self.object_brace_style.paint("{")).map_err(From::from)
+        write!(writer, "{}", self.object_brace_style.paint("{"))
     }
 
     /// Called after every object.  Writes a `}` to the specified
@@ -454,7 +514,7 @@ impl serde_json::ser::Formatter for ReadableFormatter {
             indent(writer, self.current_indent)?;
         }
 
-        write!(writer, "{}", self.object_brace_style.paint("}")).map_err(From::from)
+        write!(writer, "{}", self.object_brace_style.paint("}"))
     }
 
     /// Called before every object key.
@@ -494,7 +554,7 @@ impl serde_json::ser::Formatter for ReadableFormatter {
     where
         W: io::Write + ?Sized,
     {
-        write!(writer, "{}", self.object_colon_style.paint(": ")).map_err(From::from)
+        write!(writer, "{}", self.object_colon_style.paint(": "))
     }
 
     /// Called after every object value.
@@ -0,0 +1,886 @@
+//! KDL documents, a node-based configuration language.
+//!
+//! Each top-level node becomes a record like `{"name": "pane", "args": [1, "a"], "props": {"size":
+//! 2}, "children": [...]}`, where the children are nodes in the same way.  `args`, `props` and
+//! `children` are left out when a node has none, and a node with a type annotation like
+//! `(tab)pane` has a `type` after its `name`.  Values with a type annotation like
+//! `(date)"2024-01-01"` become `{"type": "date", "value": "2024-01-01"}`, except for
+//! `(base64)` strings, which become bytes.  Writing turns such records back into the same nodes.
+//!
+//! Both versions of KDL are read: version 1, with keywords like `true` and raw strings like
+//! `r"..."`, and version 2, with keywords like `#true`, raw strings like `#"..."#`, multi-line
+//! strings and unquoted strings.  Version 2 is written, with all strings quoted.
+
+use std::collections;
+use std::io;
+
+use base64::Engine;
+
+use crate::error;
+use crate::value;
+
+#[derive(Debug)]
+pub struct Source(collections::VecDeque<value::Value>);
+
+#[derive(Debug)]
+pub struct Sink<W>(W)
+where
+    W: io::Write;
+
+/// Creates a source for the top-level nodes of a KDL document, which is read in full from the
+/// input.
+pub fn source<R>(mut r: R) -> error::Result<Source>
+where
+    R: io::Read,
+{
+    let mut input = String::new();
+    r.read_to_string(&mut input)?;
+    let mut parser = Parser {
+        chars: input.chars().collect(),
+        pos: 0,
+        line: 1,
+        depth: 0,
+    };
+    Ok(Source(parser.nodes(false)?.into()))
+}
+
+/// Creates a sink that writes records as KDL nodes.
+#[inline]
+pub fn sink<W>(w: W) -> Sink<W>
+where
+    W: io::Write,
+{
+    Sink(w)
+}
+
+impl value::Source for Source {
+    #[inline]
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        Ok(self.0.pop_front())
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.0.len(), Some(self.0.len()))
+    }
+}
+
+impl<W> value::Sink for Sink<W>
+where
+    W: io::Write,
+{
+    fn write(&mut self, value: value::Value) -> error::Result<()> {
+        let mut output = String::new();
+        write_node(&mut output, &value, 0)?;
+        self.0.write_all(output.as_bytes())?;
+        Ok(())
+    }
+
+    #[inline]
+    fn flush(&mut self) -> error::Result<()> {
+        self.0.flush()?;
+        Ok(())
+    }
+}
+
+/// Characters that end unquoted identifiers and values.
+const DELIMITERS: &str = "\\/(){};[]=\"#";
+/// How deeply children blocks are nested at most.
+const MAX_DEPTH: usize = 128;
+
+fn is_newline(c: char) -> bool {
+    matches!(
+        c,
+        '\n' | '\r' | '\u{85}' | '\u{0c}' | '\u{2028}' | '\u{2029}'
+    )
+}
+
+fn is_space(c: char) -> bool {
+    c == '\u{feff}' || (c.is_whitespace() && !is_newline(c))
+}
+
+fn ends_token(c: char) -> bool {
+    c.is_whitespace() || c == '\u{feff}' || DELIMITERS.contains(c)
+}
+
+/// A string that can be a node name or property key, or a value.
+enum Token {
+    String(String),
+    Value(value::Value),
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    line: u64,
+    /// How many children blocks the parser is inside of.
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.pos + offset).copied()
+    }
+
+    fn starts_with(&self, s: &str) -> bool {
+        s.chars()
+            .enumerate()
+            .all(|(i, c)| self.peek_at(i) == Some(c))
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        // A CRLF counts as one line break
+        if c == '\n' || (is_newline(c) && !(c == '\r' && self.peek() == Some('\n'))) {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    fn error(&self, msg: &str) -> error::Error {
+        error::Error::Format {
+            msg: format!("KDL line {}: {}", self.line, msg),
+        }
+    }
+
+    /// Parses nodes until the end of the input, or the `}` of a children block.
+    fn nodes(&mut self, children: bool) -> error::Result<Vec<value::Value>> {
+        let mut nodes = Vec::new();
+        loop {
+            self.skip_line_space()?;
+            match self.peek() {
+                None if children => return Err(self.error("unclosed children block")),
+                None => return Ok(nodes),
+                Some('}') if children => {
+                    self.next();
+                    return Ok(nodes);
+                }
+                Some('}') => return Err(self.error("unexpected }")),
+                _ => (),
+            }
+            if self.starts_with("/-") {
+                self.pos += 2;
+                self.skip_line_space()?;
+                self.node()?;
+            } else {
+                nodes.push(self.node()?);
+            }
+        }
+    }
+
+    /// Parses the nodes of a children block after its `{`, failing if it is nested too deeply.
+    fn children(&mut self) -> error::Result<Vec<value::Value>> {
+        if self.depth >= MAX_DEPTH {
+            return Err(self.error(&format!(
+                "children blocks are nested more than {} levels deep",
+                MAX_DEPTH
+            )));
+        }
+        self.depth += 1;
+        let result = self.nodes(true);
+        self.depth -= 1;
+        result
+    }
+
+    fn node(&mut self) -> error::Result<value::Value> {
+        let kind = self.annotation()?;
+        let name = match self.token()? {
+            Token::String(name) => name,
+            Token::Value(v) => {
+                return Err(self.error(&format!("expected a node name, got: {}", v)));
+            }
+        };
+        let mut args = Vec::new();
+        let mut props: Vec<(value::Value, value::Value)> = Vec::new();
+        let mut children = None;
+        loop {
+            self.skip_node_space()?;
+            match self.peek() {
+                None | Some('}') => break,
+                Some(c) if is_newline(c) || c == ';' => {
+                    self.next();
+                    break;
+                }
+                Some('/') if self.starts_with("//") => {
+                    self.skip_line_comment();
+                    break;
+                }
+                Some('{') => {
+                    self.next();
+                    children = Some(self.children()?);
+                }
+                _ if self.starts_with("/-") => {
+                    self.pos += 2;
+                    self.skip_node_space()?;
+                    if self.peek() == Some('{') {
+                        self.next();
+                        self.children()?;
+                    } else {
+                        self.entry()?;
+                    }
+                }
+                _ if children.is_some() => {
+                    return Err(self.error("arguments and properties must come before children"))
+                }
+                _ => match self.entry()? {
+                    (Some(key), v) => {
+                        match props.iter_mut().find(|(k, _)| k.as_str() == Some(&key)) {
+                            // The last value of a property wins
+                            Some((_, existing)) => *existing = v,
+                            None => props.push((key.into(), v)),
+                        }
+                    }
+                    (None, v) => args.push(v),
+                },
+            }
+        }
+
+        let mut entries = vec![("name".into(), name.into())];
+        if let Some(kind) = kind {
+            entries.push(("type".into(), kind.into()));
+        }
+        if !args.is_empty() {
+            entries.push(("args".into(), value::Value::Sequence(args)));
+        }
+        if !props.is_empty() {
+            entries.push(("props".into(), value::Value::Map(props)));
+        }
+        match children {
+            Some(children) if !children.is_empty() => {
+                entries.push(("children".into(), value::Value::Sequence(children)))
+            }
+            _ => (),
+        }
+        Ok(value::Value::Map(entries))
+    }
+
+    /// Parses an argument, or a property with its key.
+    fn entry(&mut self) -> error::Result<(Option<String>, value::Value)> {
+        if self.peek() == Some('(') {
+            return Ok((None, self.value()?));
+        }
+        let token = self.token()?;
+        let start = self.pos;
+        self.skip_node_space()?;
+        if self.peek() != Some('=') {
+            self.pos = start;
+            return Ok((None, self.token_value(token)));
+        }
+        self.next();
+        self.skip_node_space()?;
+        match token {
+            Token::String(key) => Ok((Some(key), self.value()?)),
+            Token::Value(v) => Err(self.error(&format!("invalid property key: {}", v))),
+        }
+    }
+
+    /// Parses a value with its optional type annotation.
+    fn value(&mut self) -> error::Result<value::Value> {
+        let kind = self.annotation()?;
+        let token = self.token()?;
+        let v = self.token_value(token);
+        match (kind, v) {
+            (None, v) => Ok(v),
+            (Some(kind), value::Value::String(ref s)) if kind == "base64" => {
+                let bytes = base64::engine::general_purpose::STANDARD
+                    .decode(s)
+                    .map_err(|e| self.error(&format!("invalid base64: {}", e)))?;
+                Ok(value::Value::Bytes(bytes))
+            }
+            (Some(kind), v) => Ok(value::Value::Map(vec![
+                ("type".into(), kind.into()),
+                ("value".into(), v),
+            ])),
+        }
+    }
+
+    fn token_value(&self, token: Token) -> value::Value {
+        match token {
+            Token::String(s) => value::Value::String(s),
+            Token::Value(v) => v,
+        }
+    }
+
+    /// Parses a type annotation like `(u8)`, if there is one.
+    fn annotation(&mut self) -> error::Result<Option<String>> {
+        if self.peek() != Some('(') {
+            return Ok(None);
+        }
+        self.next();
+        self.skip_node_space()?;
+        let kind = match self.token()? {
+            Token::String(kind) => kind,
+            Token::Value(v) => return Err(self.error(&format!("invalid type annotation: {}", v))),
+        };
+        self.skip_node_space()?;
+        if self.next() != Some(')') {
+            return Err(self.error("unclosed type annotation"));
+        }
+        self.skip_node_space()?;
+        Ok(Some(kind))
+    }
+
+    /// Parses a string, identifier, number or keyword.
+    fn token(&mut self) -> error::Result<Token> {
+        match self.peek() {
+            None => Err(self.error("unexpected end of input")),
+            Some('"') => Ok(Token::String(self.string(0)?)),
+            Some('#') => {
+                let hashes = (0..).take_while(|&i| self.peek_at(i) == Some('#')).count();
+                if self.peek_at(hashes) == Some('"') {
+                    self.pos += hashes;
+                    return Ok(Token::String(self.string(hashes)?));
+                }
+                let word = self.word();
+                let v = match word.as_str() {
+                    "#true" => value::Value::Bool(true),
+                    "#false" => value::Value::Bool(false),
+                    "#null" => value::Value::Unit,
+                    "#inf" => value::Value::from_f64(f64::INFINITY),
+                    "#-inf" => value::Value::from_f64(f64::NEG_INFINITY),
+                    "#nan" => value::Value::from_f64(f64::NAN),
+                    _ => return Err(self.error(&format!("unknown keyword: {}", word))),
+                };
+                Ok(Token::Value(v))
+            }
+            // Raw strings of version 1, like r"C:\" or r#"a "quote""#
+            Some('r')
+                if matches!(self.peek_at(1), Some('"'))
+                    || (self.peek_at(1) == Some('#') && {
+                        let hashes = (1..).take_while(|&i| self.peek_at(i) == Some('#')).count();
+                        self.peek_at(1 + hashes) == Some('"')
+                    }) =>
+            {
+                self.next();
+                let hashes = (0..).take_while(|&i| self.peek_at(i) == Some('#')).count();
+                self.pos += hashes;
+                Ok(Token::String(self.string(hashes)?))
+            }
+            Some(c) if c.is_ascii_digit() => self.number(),
+            Some('+' | '-' | '.')
+                if self.peek_at(1).is_some_and(|c| c.is_ascii_digit())
+                    || (self.peek() != Some('.')
+                        && self.peek_at(1) == Some('.')
+                        && self.peek_at(2).is_some_and(|c| c.is_ascii_digit())) =>
+            {
+                self.number()
+            }
+            Some(c) if ends_token(c) => Err(self.error(&format!("unexpected {:?}", c))),
+            Some(_) => {
+                let word = self.word();
+                Ok(match word.as_str() {
+                    "true" => Token::Value(value::Value::Bool(true)),
+                    "false" => Token::Value(value::Value::Bool(false)),
+                    "null" => Token::Value(value::Value::Unit),
+                    _ => Token::String(word),
+                })
+            }
+        }
+    }
+
+    /// Reads the characters up to the next delimiter, after the first one.
+    fn word(&mut self) -> String {
+        let mut word = String::new();
+        if let Some(c) = self.next() {
+            word.push(c);
+        }
+        while let Some(c) = self.peek() {
+            if ends_token(c) {
+                break;
+            }
+            word.push(c);
+            self.next();
+        }
+        word
+    }
+
+    fn number(&mut self) -> error::Result<Token> {
+        let word = self.word();
+        let digits: String = word.chars().filter(|&c| c != '_').collect();
+        let (negative, unsigned) = match digits.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, digits.strip_prefix('+').unwrap_or(&digits)),
+        };
+        let radix = match unsigned.get(..2) {
+            Some("0x") => Some(16),
+            Some("0o") => Some(8),
+            Some("0b") => Some(2),
+            _ => None,
+        };
+        let v = match radix {
+            Some(radix) => u64::from_str_radix(&unsigned[2..], radix)
+                .ok()
+                .and_then(|n| {
+                    if negative {
+                        0i64.checked_sub_unsigned(n).map(value::Value::I64)
+                    } else {
+                        Some(value::Value::U64(n))
+                    }
+                }),
+            None if unsigned.contains(['.', 'e', 'E']) => {
+                digits.parse::<f64>().ok().map(value::Value::from_f64)
+            }
+            None if negative => digits.parse::<i64>().ok().map(value::Value::I64),
+            None => unsigned.parse::<u64>().ok().map(value::Value::U64),
+        };
+        v.map(Token::Value)
+            .ok_or_else(|| self.error(&format!("invalid number: {}", word)))
+    }
+
+    /// Parses a string after the hashes of a raw string, from its opening quote.
+    fn string(&mut self, hashes: usize) -> error::Result<String> {
+        let raw = hashes > 0 || self.chars.get(self.pos.wrapping_sub(1)) == Some(&'r');
+        let multi_line = self.starts_with("\"\"\"");
+        let quotes = if multi_line { 3 } else { 1 };
+        self.pos += quotes;
+        let close: String = "\"".repeat(quotes) + &"#".repeat(hashes);
+
+        let mut text = String::new();
+        loop {
+            if self.starts_with(&close) {
+                self.pos += close.chars().count();
+                break;
+            }
+            match self.next() {
+                Some('\\') if !raw => {
+                    // Escapes are resolved later, but escaped quotes don't end the string
+                    text.push('\\');
+                    if let Some(c) = self.next() {
+                        text.push(c);
+                    }
+                }
+                Some(c) => text.push(c),
+                None => return Err(self.error("unterminated string")),
+            }
+        }
+        let text = if multi_line {
+            self.dedent(&text)?
+        } else {
+            text
+        };
+        if raw {
+            Ok(text)
+        } else {
+            self.unescape(&text)
+        }
+    }
+
+    /// Removes the first line break and the indentation of the closing quotes from the lines of
+    /// a multi-line string.
+    fn dedent(&self, text: &str) -> error::Result<String> {
+        let text = text
+            .strip_prefix("\r\n")
+            .or_else(|| text.strip_prefix(is_newline))
+            .ok_or_else(|| self.error("multi-line strings must start with a line break"))?;
+        let lines: Vec<_> = text.split(is_newline).collect();
+        let (last, lines) = lines.split_last().expect("split returns a line");
+        if !last.chars().all(is_space) {
+            return Err(
+                self.error("the closing quotes of multi-line strings must be on their own line")
+            );
+        }
+        let lines = lines
+            .iter()
+            .map(|line| {
+                if line.chars().all(is_space) {
+                    Ok("")
+                } else {
+                    line.strip_prefix(last)
+                        .ok_or_else(|| self.error("a line of a multi-line string is not indented"))
+                }
+            })
+            .collect::<error::Result<Vec<_>>>()?;
+        Ok(lines.join("\n"))
+    }
+
+    fn unescape(&self, text: &str) -> error::Result<String> {
+        let mut result = String::with_capacity(text.len());
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                result.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('n') => result.push('\n'),
+                Some('r') => result.push('\r'),
+                Some('t') => result.push('\t'),
+                Some('b') => result.push('\u{08}'),
+                Some('f') => result.push('\u{0c}'),
+                Some('s') => result.push(' '),
+                Some(c @ ('"' | '\\' | '/')) => result.push(c),
+                Some('u') if chars.next_if_eq(&'{').is_some() => {
+                    let hex: String = chars.by_ref().take_while(|&c| c != '}').collect();
+                    let c = u32::from_str_radix(&hex, 16)
+                        .ok()
+                        .and_then(char::from_u32)
+                        .ok_or_else(|| self.error(&format!("invalid unicode escape: {}", hex)))?;
+                    result.push(c);
+                }
+                // Whitespace escapes of version 2 remove the whitespace
+                Some(c) if c.is_whitespace() => {
+                    while chars.next_if(|c| c.is_whitespace()).is_some() {}
+                }
+                Some(c) => return Err(self.error(&format!("invalid escape: \\{}", c))),
+                None => return Err(self.error("unterminated escape")),
+            }
+        }
+        Ok(result)
+    }
+
+    /// Skips whitespace, line breaks, comments and semicolons between nodes.
+    fn skip_line_space(&mut self) -> error::Result<()> {
+        loop {
+            match self.peek() {
+                Some(c) if c.is_whitespace() || c == '\u{feff}' || c == ';' => {
+                    self.next();
+                }
+                Some('/') if self.starts_with("//") => self.skip_line_comment(),
+                Some('/') if self.starts_with("/*") => self.skip_block_comment()?,
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    /// Skips whitespace, block comments and escaped line breaks within a node.
+    fn skip_node_space(&mut self) -> error::Result<()> {
+        loop {
+            match self.peek() {
+                Some(c) if is_space(c) => {
+                    self.next();
+                }
+                Some('/') if self.starts_with("/*") => self.skip_block_comment()?,
+                Some('\\') => {
+                    self.next();
+                    while self.peek().is_some_and(is_space) {
+                        self.next();
+                    }
+                    if self.starts_with("//") {
+                        self.skip_line_comment();
+                    } else if self.peek().is_some_and(is_newline) {
+                        self.next();
+                        if self.chars.get(self.pos - 1) == Some(&'\r') && self.peek() == Some('\n')
+                        {
+                            self.next();
+                        }
+                    } else if self.peek().is_some() {
+                        return Err(self.error("a line continuation must end the line"));
+                    }
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn skip_line_comment(&mut self) {
+        while let Some(c) = self.peek() {
+            if is_newline(c) {
+                break;
+            }
+            self.next();
+        }
+    }
+
+    /// Skips a block comment, which can be nested.
+    fn skip_block_comment(&mut self) -> error::Result<()> {
+        let mut depth = 0;
+        loop {
+            if self.starts_with("/*") {
+                self.pos += 2;
+                depth += 1;
+            } else if self.starts_with("*/") {
+                self.pos += 2;
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(());
+                }
+            } else if self.next().is_none() {
+                return Err(self.error("unterminated block comment"));
+            }
+        }
+    }
+}
+
+fn write_node(output: &mut String, node: &value::Value, indent: usize) -> error::Result<()> {
+    let field = |key: &str| match *node {
+        value::Value::Map(ref entries) => entries
+            .iter()
+            .find(|(k, _)| k.as_str() == Some(key))
+            .map(|(_, v)| v),
+        _ => None,
+    };
+    let name = match field("name").and_then(value::Value::as_str) {
+        Some(name) => name,
+        None => {
+            return Err(error::Error::Format {
+                msg: format!(
+                    "KDL nodes must be maps with a name, got: {}",
+                    node.summary(value::ERROR_SUMMARY_LEN)
+                ),
+            })
+        }
+    };
+
+    output.push_str(&" ".repeat(indent));
+    if let Some(kind) = field("type").filter(|v| !v.is_unit()) {
+        write_annotation(output, kind)?;
+    }
+    write_identifier(output, name);
+    match field("args") {
+        Some(value::Value::Sequence(args)) => {
+            for arg in args {
+                output.push(' ');
+                write_value(output, arg)?;
+            }
+        }
+        None | Some(value::Value::Unit) => (),
+        Some(v) => return Err(invalid_field("args", "a sequence", v)),
+    }
+    match field("props") {
+        Some(value::Value::Map(props)) => {
+            for (key, v) in props {
+                output.push(' ');
+                match key {
+                    value::Value::String(key) => write_identifier(output, key),
+                    key => write_identifier(output, &key.clone().into_key_string()),
+                }
+                output.push('=');
+                write_value(output, v)?;
+            }
+        }
+        None | Some(value::Value::Unit) => (),
+        Some(v) => return Err(invalid_field("props", "a map", v)),
+    }
+    match field("children") {
+        Some(value::Value::Sequence(children)) if !children.is_empty() => {
+            output.push_str(" {\n");
+            for child in children {
+                write_node(output, child, indent + 4)?;
+            }
+            output.push_str(&" ".repeat(indent));
+            output.push('}');
+        }
+        None | Some(value::Value::Unit) | Some(value::Value::Sequence(_)) => (),
+        Some(v) => return Err(invalid_field("children", "a sequence", v)),
+    }
+    output.push('\n');
+    Ok(())
+}
+
+fn write_value(output: &mut String, v: &value::Value) -> error::Result<()> {
+    match *v {
+        value::Value::Unit => output.push_str("#null"),
+        value::Value::Bool(b) => output.push_str(if b { "#true" } else { "#false" }),
+        value::Value::I8(n) => output.push_str(&n.to_string()),
+        value::Value::I16(n) => output.push_str(&n.to_string()),
+        value::Value::I32(n) => output.push_str(&n.to_string()),
+        value::Value::I64(n) => output.push_str(&n.to_string()),
+        value::Value::U8(n) => output.push_str(&n.to_string()),
+        value::Value::U16(n) => output.push_str(&n.to_string()),
+        value::Value::U32(n) => output.push_str(&n.to_string()),
+        value::Value::U64(n) => output.push_str(&n.to_string()),
+        value::Value::F32(_) | value::Value::F64(_) => {
+            let n = v.as_f64().expect("floats are numbers");
+            if n.is_nan() {
+                output.push_str("#nan");
+            } else if n.is_infinite() {
+                output.push_str(if n > 0.0 { "#inf" } else { "#-inf" });
+            } else {
+                // Debug formatting keeps the decimal point of whole numbers
+                output.push_str(&format!("{:?}", n));
+            }
+        }
+        value::Value::Char(c) => write_string(output, &c.to_string()),
+        value::Value::String(ref s) => write_string(output, s),
+        value::Value::Bytes(ref b) => {
+            output.push_str("(base64)");
+            write_string(output, &base64::engine::general_purpose::STANDARD.encode(b));
+        }
+        value::Value::Map(_) => match (v.get(".type"), v.get(".value")) {
+            (Some(kind), Some(inner)) if !matches!(inner, value::Value::Map(_)) => {
+                write_annotation(output, kind)?;
+                write_value(output, inner)?;
+            }
+            _ => return Err(not_a_value(v)),
+        },
+        value::Value::Sequence(_) => return Err(not_a_value(v)),
+    }
+    Ok(())
+}
+
+fn write_annotation(output: &mut String, kind: &value::Value) -> error::Result<()> {
+    match kind.as_str() {
+        Some(kind) => {
+            output.push('(');
+            write_identifier(output, kind);
+            output.push(')');
+            Ok(())
+        }
+        None => Err(invalid_field("type", "a string", kind)),
+    }
+}
+
+/// Writes a name unquoted if it can be, and quoted otherwise.
+fn write_identifier(output: &mut String, s: &str) {
+    let looks_like_number = {
+        let mut chars = s.chars();
+        let first = chars.next();
+        let second = chars.next();
+        match (first, second) {
+            (Some(c), _) if c.is_ascii_digit() => true,
+            (Some('+' | '-' | '.'), Some(c)) if c.is_ascii_digit() => true,
+            (Some('+' | '-'), Some('.')) => chars.next().is_some_and(|c| c.is_ascii_digit()),
+            _ => false,
+        }
+    };
+    let plain = !s.is_empty()
+        && !looks_like_number
+        && !s.chars().any(ends_token)
+        && !s.chars().any(|c| c.is_control())
+        && !matches!(s, "true" | "false" | "null" | "inf" | "-inf" | "nan");
+    if plain {
+        output.push_str(s);
+    } else {
+        write_string(output, s);
+    }
+}
+
+fn write_string(output: &mut String, s: &str) {
+    output.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            '\t' => output.push_str("\\t"),
+            '\u{08}' => output.push_str("\\b"),
+            '\u{0c}' => output.push_str("\\f"),
+            c if c.is_control() => output.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c => output.push(c),
+        }
+    }
+    output.push('"');
+}
+
+fn invalid_field(key: &str, expected: &str, v: &value::Value) -> error::Error {
+    error::Error::Format {
+        msg: format!(
+            "the {} of KDL nodes must be {}, got: {}",
+            key,
+            expected,
+            v.summary(value::ERROR_SUMMARY_LEN)
+        ),
+    }
+}
+
+fn not_a_value(v: &value::Value) -> error::Error {
+    error::Error::Format {
+        msg: format!(
+            "KDL values can't be maps or sequences, except for annotated values like \
+             {{\"type\": \"date\", \"value\": \"2024-01-01\"}}, got: {}",
+            v.summary(value::ERROR_SUMMARY_LEN)
+        ),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::value::Sink as _;
+    use crate::value::Source as _;
+
+    fn read(input: &[u8]) -> error::Result<Vec<value::Value>> {
+        let mut reader = source(input)?;
+        let mut records = Vec::new();
+        while let Some(record) = reader.read()? {
+            records.push(record);
+        }
+        Ok(records)
+    }
+
+    fn write(records: Vec<value::Value>) -> error::Result<Vec<u8>> {
+        let mut output = Vec::new();
+        let mut writer = sink(&mut output);
+        for record in records {
+            writer.write(record)?;
+        }
+        writer.flush()?;
+        Ok(output)
+    }
+
+    #[test]
+    fn test_depth() {
+        let nested = |depth: usize| format!("{}{}", "a {".repeat(depth), "}".repeat(depth));
+        assert!(source(nested(128).as_bytes()).is_ok());
+        for input in &[nested(129), nested(200_000), "a /-{".repeat(200_000)] {
+            match source(input.as_bytes()) {
+                Err(error::Error::Format { msg }) => assert!(msg.contains("nested"), "{}", msg),
+                other => panic!("expected an error for deeply nested KDL, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let kdl = r##"// Version 1, like zellij configuration
+keybinds clear-defaults=true {
+    normal {
+        bind "Ctrl g" { SwitchToMode "locked"; }
+    }
+}
+/-disabled 1
+pane size=1 borderless=true split_direction="vertical" size=2
+(tab)layout r#"C:\path"# 0x1f -1.5e3 null /* comment */ \
+    "a\tb"
+/* Version 2 */
+theme name=dracula #true #null #-inf (date)"2024-01-01" (base64)"aGk="
+text """
+    multi
+      line
+    """
+"##;
+        let records = read(kdl.as_bytes()).unwrap();
+        assert_eq!(
+            records.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            vec![
+                r#"{"name": "keybinds", "props": {"clear-defaults": true}, "children": [{"name": "normal", "children": [{"name": "bind", "args": ["Ctrl g"], "children": [{"name": "SwitchToMode", "args": ["locked"]}]}]}]}"#,
+                r#"{"name": "pane", "props": {"size": 2, "borderless": true, "split_direction": "vertical"}}"#,
+                r#"{"name": "layout", "type": "tab", "args": ["C:\\path", 31, -1500, null, "a\tb"]}"#,
+                r#"{"name": "theme", "args": [true, null, -inf, {"type": "date", "value": "2024-01-01"}, 0x6869], "props": {"name": "dracula"}}"#,
+                r#"{"name": "text", "args": ["multi\n  line"]}"#,
+            ]
+        );
+
+        let output = String::from_utf8(write(records.clone()).unwrap()).unwrap();
+        assert_eq!(
+            output,
+            r#"keybinds clear-defaults=#true {
+    normal {
+        bind "Ctrl g" {
+            SwitchToMode "locked"
+        }
+    }
+}
+pane size=2 borderless=#true split_direction="vertical"
+(tab)layout "C:\\path" 31 -1500.0 #null "a\tb"
+theme #true #null #-inf (date)"2024-01-01" (base64)"aGk=" name="dracula"
+text "multi\n  line"
+"#
+        );
+        // Written documents read back to the same records
+        assert_eq!(read(output.as_bytes()).unwrap(), records);
+
+        assert!(write(vec![value!({"args": [1]})]).is_err());
+        assert!(write(vec![value!({"name": "a", "args": [[1]]})]).is_err());
+        assert!(read(b"node \"unterminated").is_err());
+        assert!(read(b"node {").is_err());
+        assert!(read(br#"{"a": 1}"#).is_err());
+    }
+}
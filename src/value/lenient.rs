@@ -0,0 +1,450 @@
+//! Readers that fix up common deviations from the strict syntax of text formats before they are
+//! parsed, for `--lenient` input.
+//!
+//! Every kind of fix is reported once when the input ends, with how often it was needed and the
+//! first line where it was, so that the input can be cleaned up at the source.
+
+use std::cmp;
+use std::io;
+use std::mem;
+
+const BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// A kind of deviation from the strict syntax that was fixed.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Fix {
+    Bom,
+    Comment,
+    TrailingComma,
+    SingleQuotes,
+    UnquotedKey,
+    Tab,
+}
+
+impl Fix {
+    fn describe(self) -> &'static str {
+        match self {
+            Self::Bom => "removed a byte order mark",
+            Self::Comment => "removed comments",
+            Self::TrailingComma => "removed trailing commas",
+            Self::SingleQuotes => "converted single-quoted strings",
+            Self::UnquotedKey => "quoted unquoted keys",
+            Self::Tab => "expanded tabs in indentation",
+        }
+    }
+}
+
+/// Counts the fixes that were made, and reports them at the end of the input.
+#[derive(Debug, Default)]
+struct Fixes {
+    format: &'static str,
+    /// For each kind of fix: how often it was made, and the first line that needed it.
+    counts: Vec<(Fix, u64, u64)>,
+    reported: bool,
+}
+
+impl Fixes {
+    fn new(format: &'static str) -> Self {
+        Self {
+            format,
+            ..Self::default()
+        }
+    }
+
+    fn record(&mut self, fix: Fix, line: u64) {
+        match self.counts.iter_mut().find(|(f, _, _)| *f == fix) {
+            Some((_, count, _)) => *count += 1,
+            None => self.counts.push((fix, 1, line)),
+        }
+    }
+
+    fn report(&mut self) {
+        if mem::replace(&mut self.reported, true) {
+            return;
+        }
+        for &(fix, count, line) in &self.counts {
+            if count == 1 {
+                warn!(
+                    "Lenient {} input: {} (on line {})",
+                    self.format,
+                    fix.describe(),
+                    line
+                );
+            } else {
+                warn!(
+                    "Lenient {} input: {} ({} times, first on line {})",
+                    self.format,
+                    fix.describe(),
+                    count,
+                    line
+                );
+            }
+        }
+    }
+}
+
+/// Reads YAML, replacing tabs in the indentation of lines with spaces (at tab stops of 8), and
+/// removing a byte order mark.  The whole input is read up front.
+pub fn yaml<R>(mut r: R) -> io::Result<io::Cursor<Vec<u8>>>
+where
+    R: io::Read,
+{
+    let mut input = Vec::new();
+    r.read_to_end(&mut input)?;
+    let mut fixes = Fixes::new("YAML");
+    let mut input = &input[..];
+    if let Some(rest) = input.strip_prefix(BOM) {
+        fixes.record(Fix::Bom, 1);
+        input = rest;
+    }
+
+    let mut output = Vec::with_capacity(input.len());
+    for (i, line) in input.split_inclusive(|&b| b == b'\n').enumerate() {
+        let indent = line
+            .iter()
+            .take_while(|&&b| b == b' ' || b == b'\t')
+            .count();
+        if line[..indent].contains(&b'\t') {
+            fixes.record(Fix::Tab, i as u64 + 1);
+            let mut column = 0;
+            for &b in &line[..indent] {
+                let width = if b == b'\t' { 8 - column % 8 } else { 1 };
+                output.extend(std::iter::repeat_n(b' ', width));
+                column += width;
+            }
+            output.extend_from_slice(&line[indent..]);
+        } else {
+            output.extend_from_slice(line);
+        }
+    }
+    fixes.report();
+    Ok(io::Cursor::new(output))
+}
+
+/// Reads JSON that may have comments, trailing commas, single-quoted strings, unquoted keys and
+/// a byte order mark, and turns it into strict JSON.  Line breaks are kept, so that line numbers
+/// in parse errors still match the input.
+pub fn json<R>(r: R) -> Json<R>
+where
+    R: io::BufRead,
+{
+    Json {
+        inner: r,
+        state: State::Start,
+        output: Vec::new(),
+        position: 0,
+        pending: Pending::None,
+        last_significant: 0,
+        line: 1,
+        fixes: Fixes::new("JSON"),
+        jsonc: false,
+    }
+}
+
+/// Reads JSON with comments (JSONC), like VS Code settings or `tsconfig.json`, which may also
+/// have trailing commas and a byte order mark, and turns it into strict JSON.  Unlike `json`,
+/// this doesn't report the fixes, since they are part of the format, and it doesn't accept
+/// single-quoted strings or unquoted keys.
+pub fn jsonc<R>(r: R) -> Json<R>
+where
+    R: io::BufRead,
+{
+    Json {
+        jsonc: true,
+        ..json(r)
+    }
+}
+
+/// See `json`.
+#[derive(Debug)]
+pub struct Json<R> {
+    inner: R,
+    state: State,
+    /// Fixed-up output that hasn't been read yet, starting at `position`.
+    output: Vec<u8>,
+    position: usize,
+    /// Output that is held back until it's known whether it needs fixing.
+    pending: Pending,
+    /// The last byte outside of strings that wasn't whitespace.
+    last_significant: u8,
+    line: u64,
+    fixes: Fixes,
+    /// Only fix what JSONC allows, and don't report it.
+    jsonc: bool,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum State {
+    Start,
+    Normal,
+    String { quote: u8, escaped: bool },
+    Slash,
+    LineComment,
+    BlockComment { star: bool },
+}
+
+#[derive(Debug)]
+enum Pending {
+    None,
+    /// A comma and the whitespace after it, which are dropped if a `]` or `}` follows.
+    Comma(Vec<u8>),
+    /// A bare identifier and the whitespace after it, which are quoted if a `:` follows.
+    Identifier(Vec<u8>, Vec<u8>),
+}
+
+impl<R> Json<R>
+where
+    R: io::BufRead,
+{
+    /// Writes output, or holds it back with the pending comma or identifier.
+    fn emit(&mut self, bytes: &[u8]) {
+        match self.pending {
+            Pending::None => self.output.extend_from_slice(bytes),
+            Pending::Comma(ref mut held) | Pending::Identifier(_, ref mut held) => {
+                held.extend_from_slice(bytes)
+            }
+        }
+    }
+
+    fn flush_pending(&mut self) {
+        match mem::replace(&mut self.pending, Pending::None) {
+            Pending::None => (),
+            Pending::Comma(held) => self.output.extend_from_slice(&held),
+            Pending::Identifier(identifier, held) => {
+                self.output.extend_from_slice(&identifier);
+                self.output.extend_from_slice(&held);
+            }
+        }
+    }
+
+    fn process(&mut self, b: u8) {
+        if b == b'\n' {
+            self.line += 1;
+        }
+        match self.state {
+            State::Start => {
+                // The byte order mark is checked for before processing starts
+                self.state = State::Normal;
+                self.process_normal(b);
+            }
+            State::Normal => self.process_normal(b),
+            State::String { quote, escaped } => self.process_string(b, quote, escaped),
+            State::Slash => match b {
+                b'/' => {
+                    self.fixes.record(Fix::Comment, self.line);
+                    self.state = State::LineComment;
+                }
+                b'*' => {
+                    self.fixes.record(Fix::Comment, self.line);
+                    self.state = State::BlockComment { star: false };
+                }
+                _ => {
+                    self.emit(b"/");
+                    self.state = State::Normal;
+                    self.process_normal(b);
+                }
+            },
+            State::LineComment => {
+                if b == b'\n' {
+                    self.emit(b"\n");
+                    self.state = State::Normal;
+                }
+            }
+            State::BlockComment { star } => match b {
+                b'/' if star => {
+                    self.emit(b" ");
+                    self.state = State::Normal;
+                }
+                b'\n' => {
+                    self.emit(b"\n");
+                    self.state = State::BlockComment { star: false };
+                }
+                _ => self.state = State::BlockComment { star: b == b'*' },
+            },
+        }
+    }
+
+    fn process_normal(&mut self, b: u8) {
+        let is_whitespace = matches!(b, b' ' | b'\t' | b'\n' | b'\r');
+        let is_identifier = b.is_ascii_alphanumeric() || b == b'_' || b == b'$';
+
+        match self.pending {
+            Pending::Identifier(ref mut identifier, ref held)
+                if held.is_empty() && is_identifier =>
+            {
+                identifier.push(b);
+                return;
+            }
+            Pending::Identifier(_, ref mut held) | Pending::Comma(ref mut held)
+                if is_whitespace =>
+            {
+                held.push(b);
+                return;
+            }
+            Pending::Identifier(..) if b == b':' => {
+                if let Pending::Identifier(identifier, held) =
+                    mem::replace(&mut self.pending, Pending::None)
+                {
+                    self.fixes.record(Fix::UnquotedKey, self.line);
+                    self.output.push(b'"');
+                    self.output.extend_from_slice(&identifier);
+                    self.output.push(b'"');
+                    self.output.extend_from_slice(&held);
+                }
+            }
+            Pending::Comma(_) if b == b']' || b == b'}' => {
+                if let Pending::Comma(held) = mem::replace(&mut self.pending, Pending::None) {
+                    self.fixes.record(Fix::TrailingComma, self.line);
+                    self.output.extend_from_slice(&held[1..]);
+                }
+            }
+            _ if b == b'/' => {
+                // Could be a comment, which may come before the end of the pending output
+                self.state = State::Slash;
+                return;
+            }
+            Pending::None => (),
+            _ => self.flush_pending(),
+        }
+
+        if is_whitespace {
+            self.output.push(b);
+            return;
+        }
+        match b {
+            b'"' => {
+                self.output.push(b'"');
+                self.state = State::String {
+                    quote: b'"',
+                    escaped: false,
+                };
+            }
+            b'\'' if !self.jsonc => {
+                self.fixes.record(Fix::SingleQuotes, self.line);
+                self.output.push(b'"');
+                self.state = State::String {
+                    quote: b'\'',
+                    escaped: false,
+                };
+            }
+            b',' => self.pending = Pending::Comma(vec![b',']),
+            _ if (b.is_ascii_alphabetic() || b == b'_' || b == b'$')
+                && matches!(self.last_significant, b'{' | b',')
+                && !self.jsonc =>
+            {
+                self.pending = Pending::Identifier(vec![b], Vec::new());
+            }
+            _ => self.output.push(b),
+        }
+        self.last_significant = b;
+    }
+
+    fn process_string(&mut self, b: u8, quote: u8, escaped: bool) {
+        let mut escaped_next = false;
+        if escaped {
+            if quote == b'\'' && b == b'\'' {
+                // `\'` isn't a valid escape in JSON, but a plain `'` is fine there
+                self.output.pop();
+            }
+            self.output.push(b);
+        } else if b == quote {
+            self.output.push(b'"');
+            self.state = State::Normal;
+            self.last_significant = b'"';
+            return;
+        } else if b == b'"' {
+            // Only possible in single-quoted strings
+            self.output.extend_from_slice(b"\\\"");
+        } else {
+            escaped_next = b == b'\\';
+            self.output.push(b);
+        }
+        self.state = State::String {
+            quote,
+            escaped: escaped_next,
+        };
+    }
+
+    /// Fixes up more input, returning false if the input has ended.
+    fn fill(&mut self) -> io::Result<bool> {
+        self.output.clear();
+        self.position = 0;
+        loop {
+            let buf = self.inner.fill_buf()?;
+            if buf.is_empty() {
+                if self.state == State::Slash {
+                    self.state = State::Normal;
+                    self.emit(b"/");
+                }
+                self.flush_pending();
+                if !self.jsonc {
+                    self.fixes.report();
+                }
+                return Ok(!self.output.is_empty());
+            }
+            let mut buf = buf.to_vec();
+            let len = buf.len();
+            if self.state == State::Start {
+                if let Some(rest) = buf.strip_prefix(BOM) {
+                    self.fixes.record(Fix::Bom, 1);
+                    buf = rest.to_vec();
+                }
+            }
+            for b in buf {
+                self.process(b);
+            }
+            self.inner.consume(len);
+            if !self.output.is_empty() {
+                return Ok(true);
+            }
+        }
+    }
+}
+
+impl<R> io::Read for Json<R>
+where
+    R: io::BufRead,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position >= self.output.len() && !self.fill()? {
+            return Ok(0);
+        }
+        let available = &self.output[self.position..];
+        let len = cmp::min(available.len(), buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_json() {
+        let input = "\u{feff}{a: 'it\\'s \"x\"', // comment\n 'b': [1, 2, /* c */],}";
+        let mut output = String::new();
+        json(input.as_bytes()).read_to_string(&mut output).unwrap();
+        assert_eq!(output, "{\"a\": \"it's \\\"x\\\"\", \n \"b\": [1, 2  ]}");
+        let value: serde_json::Value = serde_json::from_str(&output).unwrap();
+        assert_eq!(value, serde_json::json!({"a": "it's \"x\"", "b": [1, 2]}));
+    }
+
+    #[test]
+    fn test_jsonc() {
+        use crate::value::Source as _;
+
+        let read = |input: &'static [u8]| {
+            crate::value::json::source(io::BufReader::new(jsonc(input))).read()
+        };
+        let input = b"// settings\n{\"a\": \"http://x\", /* b */ \"b\": [1, 2,],}\n";
+        assert_eq!(
+            read(input).unwrap(),
+            Some(value!({"a": "http://x", "b": [1, 2]}))
+        );
+        assert!(read(b"{'a': 1}").is_err());
+        assert!(read(b"{a: 1}").is_err());
+    }
+}
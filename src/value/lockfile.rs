@@ -0,0 +1,425 @@
+//! Lockfiles of package managers: `Cargo.lock`, npm's `package-lock.json` and Yarn's
+//! `yarn.lock`, both in the classic format and in the YAML format of Yarn 2 and later.
+//!
+//! Each locked package becomes a record with its `name`, `version`, `source` and `checksum`,
+//! for auditing dependencies.  Sources are where the package was resolved from, like a registry
+//! or git URL, and null for local packages.
+
+use std::collections;
+use std::io;
+
+use crate::error;
+use crate::value;
+
+#[derive(Debug)]
+pub struct Source(collections::VecDeque<value::Value>);
+
+/// Creates a source for the packages of a lockfile, which is read in full from the input and
+/// whose kind is detected from its content.
+pub fn source<R>(mut r: R) -> error::Result<Source>
+where
+    R: io::Read,
+{
+    let mut input = String::new();
+    r.read_to_string(&mut input)?;
+
+    let packages = if input.trim_start().starts_with('{') {
+        npm(&serde_json::from_str(&input)?)?
+    } else if input.lines().any(|line| line == "__metadata:") {
+        yarn_berry(&serde_yaml::from_str(&input)?)
+    } else if input
+        .lines()
+        .any(|line| line.starts_with("# yarn lockfile v1"))
+    {
+        yarn_classic(&input)?
+    } else {
+        cargo(&toml::from_str(&input)?)?
+    };
+    Ok(Source(packages.into()))
+}
+
+impl value::Source for Source {
+    #[inline]
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        Ok(self.0.pop_front())
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.0.len(), Some(self.0.len()))
+    }
+}
+
+fn package(
+    name: value::Value,
+    version: value::Value,
+    source: value::Value,
+    checksum: value::Value,
+) -> value::Value {
+    value::Value::Map(vec![
+        ("name".into(), name),
+        ("version".into(), version),
+        ("source".into(), source),
+        ("checksum".into(), checksum),
+    ])
+}
+
+/// The packages of a `Cargo.lock`.  Checksums are in the packages since version 2 of the format,
+/// and in a separate `[metadata]` table before.
+fn cargo(lockfile: &value::Value) -> error::Result<Vec<value::Value>> {
+    let packages = match lockfile.get(".package") {
+        Some(value::Value::Sequence(packages)) => packages,
+        _ => return Err(not_a_lockfile("Cargo.lock", "a [[package]] array")),
+    };
+    Ok(packages
+        .iter()
+        .map(|p| {
+            let (name, version, source) =
+                (text(p, ".name"), text(p, ".version"), text(p, ".source"));
+            let checksum = match p.get(".checksum") {
+                Some(checksum) => text_value(checksum),
+                None => {
+                    let key = format!(
+                        "checksum {} {} ({})",
+                        name.as_str().unwrap_or_default(),
+                        version.as_str().unwrap_or_default(),
+                        source.as_str().unwrap_or_default()
+                    );
+                    entry(lockfile.get(".metadata"), &key).map_or(value::Value::Unit, text_value)
+                }
+            };
+            package(name, version, source, checksum)
+        })
+        .collect())
+}
+
+/// The packages of a `package-lock.json`, which are in `packages` by their path since version 2
+/// of the format, and nested in `dependencies` by their name before.
+fn npm(lockfile: &value::Value) -> error::Result<Vec<value::Value>> {
+    let mut packages = Vec::new();
+    if let Some(value::Value::Map(entries)) = lockfile.get(".packages") {
+        for (path, p) in entries {
+            let path = path.as_str().unwrap_or_default();
+            // The project itself
+            if path.is_empty() {
+                continue;
+            }
+            let name = match p.get(".name") {
+                Some(name) => text_value(name),
+                None => path.rsplit("node_modules/").next().unwrap_or(path).into(),
+            };
+            packages.push(package(
+                name,
+                text(p, ".version"),
+                text(p, ".resolved"),
+                text(p, ".integrity"),
+            ));
+        }
+    } else if let Some(dependencies) = lockfile.get(".dependencies") {
+        npm_dependencies(dependencies, &mut packages);
+    } else {
+        return Err(not_a_lockfile(
+            "package-lock.json",
+            "packages or dependencies",
+        ));
+    }
+    Ok(packages)
+}
+
+fn npm_dependencies(dependencies: &value::Value, packages: &mut Vec<value::Value>) {
+    if let value::Value::Map(entries) = dependencies {
+        for (name, p) in entries {
+            packages.push(package(
+                text_value(name),
+                text(p, ".version"),
+                text(p, ".resolved"),
+                text(p, ".integrity"),
+            ));
+            if let Some(nested) = p.get(".dependencies") {
+                npm_dependencies(nested, packages);
+            }
+        }
+    }
+}
+
+/// The packages of a `yarn.lock` of Yarn 2 and later, which is YAML with an entry for each
+/// package, keyed by the ranges that resolve to it.
+fn yarn_berry(lockfile: &value::Value) -> Vec<value::Value> {
+    let entries = match lockfile {
+        value::Value::Map(entries) => &entries[..],
+        _ => &[],
+    };
+    entries
+        .iter()
+        .filter(|(key, _)| key.as_str() != Some("__metadata"))
+        .map(|(key, p)| {
+            // Resolutions are like `lodash@npm:4.17.21`, or `@types/node@npm:20.1.0`
+            let resolution = p
+                .get(".resolution")
+                .and_then(value::Value::as_str)
+                .or_else(|| key.as_str().and_then(|key| key.split(", ").next()))
+                .unwrap_or_default();
+            let (name, source) = split_descriptor(resolution);
+            package(
+                name.into(),
+                text(p, ".version"),
+                source.map_or(value::Value::Unit, Into::into),
+                text(p, ".checksum"),
+            )
+        })
+        .collect()
+}
+
+/// The packages of a classic `yarn.lock`, which has a block for each package, headed by the
+/// ranges that resolve to it, with indented fields like `version "1.2.3"`.
+fn yarn_classic(lockfile: &str) -> error::Result<Vec<value::Value>> {
+    let mut packages = Vec::new();
+    let mut current: Option<(String, collections::BTreeMap<String, String>)> = None;
+    for (number, line) in lockfile.lines().enumerate() {
+        if line.trim().is_empty() || line.trim_start().starts_with('#') {
+            continue;
+        }
+        if !line.starts_with(' ') {
+            packages.extend(current.take().map(yarn_classic_package));
+            let first = line
+                .trim_end_matches(':')
+                .split(", ")
+                .next()
+                .unwrap_or_default();
+            let first = unquote(first);
+            let (name, _) = split_descriptor(&first);
+            current = Some((name.to_owned(), collections::BTreeMap::new()));
+        } else if let Some(field) = line.strip_prefix("  ").filter(|l| !l.starts_with(' ')) {
+            let fields = match current {
+                Some((_, ref mut fields)) => fields,
+                None => {
+                    return Err(error::Error::Format {
+                        msg: format!("yarn.lock line {}: field outside of a package", number + 1),
+                    })
+                }
+            };
+            if let Some((key, v)) = field.split_once(' ') {
+                fields.insert(unquote(key), unquote(v.trim()));
+            }
+        }
+    }
+    packages.extend(current.map(yarn_classic_package));
+    Ok(packages)
+}
+
+fn yarn_classic_package(
+    (name, mut fields): (String, collections::BTreeMap<String, String>),
+) -> value::Value {
+    let resolved = fields.remove("resolved");
+    // Old lockfiles only have the SHA-1 of the tarball, in the fragment of its URL
+    let checksum = fields.remove("integrity").or_else(|| {
+        resolved
+            .as_deref()
+            .and_then(|url| url.split_once('#'))
+            .map(|(_, hash)| hash.to_owned())
+    });
+    let optional = |v: Option<String>| v.map_or(value::Value::Unit, value::Value::String);
+    package(
+        value::Value::String(name),
+        optional(fields.remove("version")),
+        optional(resolved),
+        optional(checksum),
+    )
+}
+
+/// Splits a descriptor like `@babel/core@^7.0.0` into the name and the range or resolution.
+fn split_descriptor(descriptor: &str) -> (&str, Option<&str>) {
+    match descriptor.get(1..).and_then(|rest| rest.find('@')) {
+        Some(i) => (&descriptor[..=i], Some(&descriptor[i + 2..])),
+        None => (descriptor, None),
+    }
+}
+
+fn unquote(s: &str) -> String {
+    match s.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
+        Some(s) => s.replace("\\\"", "\"").replace("\\\\", "\\"),
+        None => s.to_owned(),
+    }
+}
+
+fn entry<'a>(map: Option<&'a value::Value>, key: &str) -> Option<&'a value::Value> {
+    match map {
+        Some(value::Value::Map(entries)) => entries
+            .iter()
+            .find(|(k, _)| k.as_str() == Some(key))
+            .map(|(_, v)| v),
+        _ => None,
+    }
+}
+
+/// A field as text, or null if it is missing.
+fn text(v: &value::Value, path: &str) -> value::Value {
+    v.get(path).map_or(value::Value::Unit, text_value)
+}
+
+/// Versions like `1.0` can be numbers in YAML, which are turned back into text.
+fn text_value(v: &value::Value) -> value::Value {
+    match *v {
+        value::Value::String(_) | value::Value::Unit => v.clone(),
+        ref v => value::Value::String(v.to_string()),
+    }
+}
+
+fn not_a_lockfile(kind: &str, missing: &str) -> error::Error {
+    error::Error::Format {
+        msg: format!("not a {}: it has no {}", kind, missing),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::value::Source as _;
+
+    fn read(input: &[u8]) -> error::Result<Vec<value::Value>> {
+        let mut reader = source(input)?;
+        let mut records = Vec::new();
+        while let Some(record) = reader.read()? {
+            records.push(record);
+        }
+        Ok(records)
+    }
+
+    fn packages(input: &str) -> Vec<String> {
+        read(input.as_bytes())
+            .unwrap()
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn test_cargo() {
+        let crates_io = "registry+https://github.com/rust-lang/crates.io-index";
+
+        let cargo = format!(
+            r#"# This file is automatically @generated by Cargo.
+version = 3
+
+[[package]]
+name = "app"
+version = "0.1.0"
+dependencies = ["serde"]
+
+[[package]]
+name = "serde"
+version = "1.0.219"
+source = "{}"
+checksum = "5f0e2c6e"
+"#,
+            crates_io
+        );
+        assert_eq!(
+            packages(&cargo),
+            vec![
+                r#"{"name": "app", "version": "0.1.0", "source": null, "checksum": null}"#
+                    .to_owned(),
+                format!(
+                    r#"{{"name": "serde", "version": "1.0.219", "source": "{}", "checksum": "5f0e2c6e"}}"#,
+                    crates_io
+                ),
+            ]
+        );
+        let cargo_v1 = format!(
+            "[[package]]\nname = \"serde\"\nversion = \"1.0.0\"\nsource = \"{0}\"\n\n\
+             [metadata]\n\"checksum serde 1.0.0 ({0})\" = \"9dad3f75\"\n",
+            crates_io
+        );
+        assert!(packages(&cargo_v1)[0].ends_with(r#""checksum": "9dad3f75"}"#));
+    }
+
+    #[test]
+    fn test_npm() {
+        let npm = r#"{
+  "name": "app",
+  "lockfileVersion": 3,
+  "packages": {
+    "": {"name": "app", "version": "1.0.0"},
+    "node_modules/@types/node": {
+      "version": "20.1.0",
+      "resolved": "https://registry.npmjs.org/@types/node/-/node-20.1.0.tgz",
+      "integrity": "sha512-abc"
+    },
+    "node_modules/a/node_modules/b": {"version": "2.0.0"}
+  }
+}"#;
+        assert_eq!(
+            packages(npm),
+            vec![
+                r#"{"name": "@types/node", "version": "20.1.0", "source": "https://registry.npmjs.org/@types/node/-/node-20.1.0.tgz", "checksum": "sha512-abc"}"#,
+                r#"{"name": "b", "version": "2.0.0", "source": null, "checksum": null}"#,
+            ]
+        );
+        let npm_v1 = r#"{"lockfileVersion": 1, "dependencies": {
+            "a": {"version": "1.0.0", "integrity": "sha1-x",
+                  "dependencies": {"b": {"version": "2.0.0"}}}}}"#;
+        assert_eq!(
+            packages(npm_v1),
+            vec![
+                r#"{"name": "a", "version": "1.0.0", "source": null, "checksum": "sha1-x"}"#,
+                r#"{"name": "b", "version": "2.0.0", "source": null, "checksum": null}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_yarn() {
+        let yarn = r#"# THIS IS AN AUTOGENERATED FILE. DO NOT EDIT THIS FILE DIRECTLY.
+# yarn lockfile v1
+
+"@babel/code-frame@^7.0.0", "@babel/code-frame@^7.10.4":
+  version "7.12.13"
+  resolved "https://registry.yarnpkg.com/@babel/code-frame/-/code-frame-7.12.13.tgz#dcfc826b"
+  integrity sha512-HV1Cm0Q3
+  dependencies:
+    "@babel/highlight" "^7.12.13"
+
+left-pad@1.3.0:
+  version "1.3.0"
+  resolved "https://registry.yarnpkg.com/left-pad/-/left-pad-1.3.0.tgz#5b8a3a7765dfe001261dde915589e782f8c94d1e"
+"#;
+        assert_eq!(
+            packages(yarn),
+            vec![
+                r#"{"name": "@babel/code-frame", "version": "7.12.13", "source": "https://registry.yarnpkg.com/@babel/code-frame/-/code-frame-7.12.13.tgz#dcfc826b", "checksum": "sha512-HV1Cm0Q3"}"#,
+                r#"{"name": "left-pad", "version": "1.3.0", "source": "https://registry.yarnpkg.com/left-pad/-/left-pad-1.3.0.tgz#5b8a3a7765dfe001261dde915589e782f8c94d1e", "checksum": "5b8a3a7765dfe001261dde915589e782f8c94d1e"}"#,
+            ]
+        );
+
+        let berry = r#"__metadata:
+  version: 6
+  cacheKey: 8
+
+"@types/node@npm:^20.1.0":
+  version: 20.1.0
+  resolution: "@types/node@npm:20.1.0"
+  checksum: 0a1b2c
+  languageName: node
+  linkType: hard
+
+"app@workspace:.":
+  version: 0.0.0-use.local
+  resolution: "app@workspace:."
+  languageName: unknown
+  linkType: soft
+"#;
+        assert_eq!(
+            packages(berry),
+            vec![
+                r#"{"name": "@types/node", "version": "20.1.0", "source": "npm:20.1.0", "checksum": "0a1b2c"}"#,
+                r#"{"name": "app", "version": "0.0.0-use.local", "source": "workspace:.", "checksum": null}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unknown() {
+        assert!(read(b"[dependencies]\nserde = \"1\"").is_err());
+        assert!(read(b"{\"name\": \"app\"}").is_err());
+    }
+}
@@ -0,0 +1,367 @@
+//! Logs in logfmt, with a line of `key=value` pairs for each event, like
+//! `at=info method=GET path="/" status=200`.
+//!
+//! Each line becomes a flat map.  Values in double quotes are strings and can have escapes like
+//! `\"` and `\n`.  Unquoted numbers, `true`, `false` and `null` become numbers, booleans and null,
+//! and other unquoted values are strings.  A key without a value, like `debug`, is `true`.  Empty
+//! lines are skipped.
+
+use std::char;
+use std::io;
+
+use crate::error;
+use crate::value;
+
+#[derive(Debug)]
+pub struct Source<R>
+where
+    R: io::Read,
+{
+    lines: io::Lines<io::BufReader<R>>,
+    line: u64,
+    records: u64,
+}
+
+#[derive(Debug)]
+pub struct Sink<W>(W)
+where
+    W: io::Write;
+
+/// Creates a source for the lines of a logfmt log, where each line is a record.
+#[inline]
+pub fn source<R>(r: R) -> Source<R>
+where
+    R: io::Read,
+{
+    use std::io::BufRead;
+    Source {
+        lines: io::BufReader::new(r).lines(),
+        line: 0,
+        records: 0,
+    }
+}
+
+/// Creates a sink that writes flat maps as logfmt lines.
+#[inline]
+pub fn sink<W>(w: W) -> Sink<W>
+where
+    W: io::Write,
+{
+    Sink(w)
+}
+
+impl<R> value::Source for Source<R>
+where
+    R: io::Read,
+{
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        loop {
+            let line = match self.lines.next() {
+                Some(line) => line?,
+                None => return Ok(None),
+            };
+            self.line += 1;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record = parse(&line)
+                .map_err(|msg| format_error(&format!("logfmt line {}: {}", self.line, msg)))?;
+            self.records += 1;
+            return Ok(Some(record));
+        }
+    }
+
+    #[inline]
+    fn position(&self) -> Option<value::Position> {
+        Some(value::Position {
+            record: self.records,
+            byte: None,
+            line: Some(self.line),
+        })
+    }
+}
+
+impl<W> value::Sink for Sink<W>
+where
+    W: io::Write,
+{
+    fn write(&mut self, value: value::Value) -> error::Result<()> {
+        let entries = match value {
+            value::Value::Map(entries) => entries,
+            value => {
+                return Err(format_error(&format!(
+                    "logfmt can only output maps, got: {}",
+                    value.summary(value::ERROR_SUMMARY_LEN)
+                )))
+            }
+        };
+        // The line is only written once the whole record is known to be valid
+        let mut output = String::new();
+        for (key, v) in entries {
+            let key = match key.as_str() {
+                Some(key) if is_key(key) => key.to_owned(),
+                _ => {
+                    return Err(format_error(&format!(
+                        "logfmt keys can't be empty or have spaces, quotes or =, got: {}",
+                        key.summary(value::ERROR_SUMMARY_LEN)
+                    )))
+                }
+            };
+            let text = match v {
+                value::Value::Unit => "null".to_owned(),
+                value::Value::String(s) => format_string(s),
+                value::Value::Char(c) => format_string(c.to_string()),
+                value::Value::Map(_) | value::Value::Sequence(_) => {
+                    return Err(format_error(&format!(
+                        "logfmt can only output scalar values, but {} is: {}",
+                        key,
+                        v.summary(value::ERROR_SUMMARY_LEN)
+                    )))
+                }
+                v => v.to_string(),
+            };
+            if !output.is_empty() {
+                output.push(' ');
+            }
+            output.push_str(&key);
+            output.push('=');
+            output.push_str(&text);
+        }
+        output.push('\n');
+        self.0.write_all(output.as_bytes())?;
+        Ok(())
+    }
+
+    #[inline]
+    fn flush(&mut self) -> error::Result<()> {
+        self.0.flush()?;
+        Ok(())
+    }
+}
+
+fn parse(line: &str) -> Result<value::Value, String> {
+    let mut entries = Vec::new();
+    let mut chars = line.chars().peekable();
+    loop {
+        while chars.peek().is_some_and(|c| c.is_whitespace()) {
+            chars.next();
+        }
+        let mut key = String::new();
+        while let Some(&c) = chars.peek() {
+            if c.is_whitespace() || c == '=' || c == '"' {
+                break;
+            }
+            key.push(c);
+            chars.next();
+        }
+        match chars.peek() {
+            None if key.is_empty() => return Ok(value::Value::Map(entries)),
+            Some(&c) if key.is_empty() => return Err(format!("expected a key before {:?}", c)),
+            Some('"') => return Err(format!("unexpected quote after {}", key)),
+            Some('=') => {
+                chars.next();
+            }
+            // A key without a value is a flag that's set
+            _ => {
+                entries.push((key.into(), value::Value::Bool(true)));
+                continue;
+            }
+        }
+
+        let v = if chars.peek() == Some(&'"') {
+            chars.next();
+            let mut text = String::new();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => text.push(unescape(&mut chars)?),
+                    Some(c) => text.push(c),
+                    None => return Err(format!("unterminated quoted value of {}", key)),
+                }
+            }
+            match chars.peek() {
+                Some(c) if !c.is_whitespace() => {
+                    return Err(format!("expected a space after the value of {}", key))
+                }
+                _ => (),
+            }
+            value::Value::String(text)
+        } else {
+            let mut text = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() {
+                    break;
+                }
+                if c == '"' || c == '=' {
+                    return Err(format!("unexpected {:?} in the value of {}", c, key));
+                }
+                text.push(c);
+                chars.next();
+            }
+            scalar(text)
+        };
+        entries.push((key.into(), v));
+    }
+}
+
+/// Reads the escape after a backslash in a quoted value, with the escapes of Go strings that
+/// logfmt writers use.
+fn unescape<I>(chars: &mut I) -> Result<char, String>
+where
+    I: Iterator<Item = char>,
+{
+    Ok(match chars.next() {
+        Some('n') => '\n',
+        Some('r') => '\r',
+        Some('t') => '\t',
+        Some('u') => {
+            let hex: String = chars.take(4).collect();
+            u32::from_str_radix(&hex, 16)
+                .ok()
+                .and_then(char::from_u32)
+                .ok_or_else(|| format!("invalid escape \\u{}", hex))?
+        }
+        Some(c @ ('"' | '\\' | '/')) => c,
+        Some(c) => return Err(format!("invalid escape \\{}", c)),
+        None => return Err("unterminated escape".to_owned()),
+    })
+}
+
+/// The value of an unquoted text, which is a number, boolean or null when it looks like one.
+fn scalar(text: String) -> value::Value {
+    match text.as_str() {
+        "true" => return value::Value::Bool(true),
+        "false" => return value::Value::Bool(false),
+        "null" => return value::Value::Unit,
+        _ => (),
+    }
+    if let Ok(n) = text.parse::<i64>() {
+        value::Value::I64(n)
+    } else if let Ok(n) = text.parse::<u64>() {
+        value::Value::U64(n)
+    } else if is_float(&text) {
+        value::Value::F64(text.parse().unwrap())
+    } else {
+        value::Value::String(text)
+    }
+}
+
+/// Whether a text is a decimal number with a fraction or exponent, but not something like `inf`
+/// or `NaN` that Rust would also parse.
+fn is_float(text: &str) -> bool {
+    text.bytes().any(|b| b.is_ascii_digit())
+        && text
+            .bytes()
+            .all(|b| b.is_ascii_digit() || matches!(b, b'.' | b'e' | b'E' | b'+' | b'-'))
+        && text.parse::<f64>().is_ok()
+}
+
+fn is_key(key: &str) -> bool {
+    !key.is_empty()
+        && key
+            .chars()
+            .all(|c| !c.is_whitespace() && !c.is_control() && c != '=' && c != '"')
+}
+
+/// The text of a string value, which is quoted when it has to be to be read back as the same
+/// string.
+fn format_string(s: String) -> String {
+    let plain = !s.is_empty()
+        && s.chars()
+            .all(|c| !c.is_whitespace() && !c.is_control() && c != '=' && c != '"')
+        && matches!(scalar(s.clone()), value::Value::String(_));
+    if plain {
+        return s;
+    }
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn format_error(msg: &str) -> error::Error {
+    error::Error::Format {
+        msg: msg.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::value::Sink as _;
+    use crate::value::Source as _;
+
+    fn read(input: &[u8]) -> error::Result<Vec<value::Value>> {
+        let mut reader = source(input);
+        let mut records = Vec::new();
+        while let Some(record) = reader.read()? {
+            records.push(record);
+        }
+        Ok(records)
+    }
+
+    fn write(json: &str) -> error::Result<String> {
+        let mut output = Vec::new();
+        {
+            let mut writer = sink(&mut output);
+            let mut reader = value::json::source(json.as_bytes());
+            while let Some(record) = reader.read()? {
+                writer.write(record)?;
+            }
+        }
+        Ok(String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn test_source() {
+        let log = r#"at=info method=GET path="/users?id=1" status=200 bytes=1543 service=12ms
+debug msg="say \"hi\"\n" ratio=0.5 empty= fwd=null
+
+code=H12 ok=false
+"#;
+        let records = read(log.as_bytes()).unwrap();
+        assert_eq!(
+            records.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            vec![
+                r#"{"at": "info", "method": "GET", "path": "/users?id=1", "status": 200, "bytes": 1543, "service": "12ms"}"#,
+                r#"{"debug": true, "msg": "say \"hi\"\n", "ratio": 0.5, "empty": "", "fwd": null}"#,
+                r#"{"code": "H12", "ok": false}"#,
+            ]
+        );
+
+        assert!(read(b"msg=\"unterminated").is_err());
+        assert!(read(b"=value").is_err());
+    }
+
+    #[test]
+    fn test_sink() {
+        let output = write(
+            r#"{"at": "info", "status": 200, "code": "200", "msg": "a \"b\"", "empty": "", "none": null}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            output,
+            "at=info status=200 code=\"200\" msg=\"a \\\"b\\\"\" empty=\"\" none=null\n"
+        );
+        // Written lines read back to the same values
+        let round_trip = read(output.as_bytes()).unwrap();
+        assert_eq!(round_trip[0].get(".code").unwrap().as_str(), Some("200"));
+        assert_eq!(round_trip[0].get(".msg").unwrap().as_str(), Some("a \"b\""));
+
+        assert!(write(r#"{"a": [1]}"#).is_err());
+        assert!(write(r#"{"a b": 1}"#).is_err());
+        assert!(write("1").is_err());
+    }
+}
@@ -58,6 +58,12 @@ where
     fn write(&mut self, v: value::Value) -> error::Result<()> {
         rmpv::encode::write_value(&mut self.0, &value_to_message_pack(v)).map_err(From::from)
     }
+
+    #[inline]
+    fn flush(&mut self) -> error::Result<()> {
+        self.0.flush()?;
+        Ok(())
+    }
 }
 
 fn value_from_message_pack(value: rmpv::Value) -> error::Result<value::Value> {
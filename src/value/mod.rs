@@ -6,18 +6,76 @@ use serde_json;
 use std::fmt;
 use std::io;
 
+/// Builds a `Value` using JSON syntax, with the same rules as `serde_json::json!`.
+///
+/// ```
+/// use record_query::value;
+///
+/// let v = value!({"name": "rq", "tags": ["a", "b"], "stars": 42});
+/// assert_eq!(v.get("tags.1").and_then(value::Value::as_str), Some("b"));
+/// assert_eq!(v.get("stars").and_then(value::Value::as_i64), Some(42));
+/// ```
+#[macro_export]
+macro_rules! value {
+    ($($json:tt)+) => {
+        $crate::value::Value::from($crate::__serde_json::json!($($json)+))
+    };
+}
+
+/// The length that values are summarized to when they are mentioned in error messages.
+pub(crate) const ERROR_SUMMARY_LEN: usize = 64;
+/// How many elements of a sequence are allocated up front when the input says how long it is,
+/// which keeps a bogus length from allocating more than the input can fill.
+const MAX_PREALLOCATED: usize = 4096;
+
+pub mod arrow;
+pub mod asn1;
+pub mod audio_tags;
 pub mod avro;
+pub mod bencode;
+pub mod bson;
 pub mod cbor;
+pub mod chunks;
+mod convert;
 pub mod csv;
+pub mod dotenv;
+pub mod edn;
+pub mod env;
+pub mod evtx;
+pub mod flatbuffers;
+pub mod git_log;
+pub mod hcl;
+pub mod image;
+pub mod ion;
+pub mod java_xml;
 pub mod json;
+pub mod kdl;
+pub mod lenient;
+pub mod lockfile;
+pub mod logfmt;
 pub mod messagepack;
+pub mod oci;
+pub mod parquet;
+pub mod path;
+pub mod pdf;
 pub mod protobuf;
+pub mod protobuf_raw;
 pub mod raw;
+pub mod registry;
 pub mod smile;
+pub mod sqlite;
+pub mod ssh_keys;
+pub mod textproto;
+pub mod thrift;
 pub mod toml;
+pub mod ubjson;
+pub mod x509;
+pub mod xlsx;
+pub mod xml;
 pub mod yaml;
+pub mod zone_file;
 
-#[derive(Debug)]
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub enum Value {
     Unit,
     Bool(bool),
@@ -46,12 +104,91 @@ pub enum Value {
     Map(Vec<(Value, Value)>),
 }
 
+/// How far a `Source` has come in reading its input.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Position {
+    /// The number of records that have been read so far.
+    pub record: u64,
+    /// The byte offset into the input, for sources that keep track of it.
+    pub byte: Option<u64>,
+    /// The (1-based) line number in the input, for line-oriented sources.
+    pub line: Option<u64>,
+}
+
 pub trait Source {
     fn read(&mut self) -> error::Result<Option<Value>>;
+
+    /// The current position in the input, if the source is able to tell.
+    #[inline]
+    fn position(&self) -> Option<Position> {
+        None
+    }
+
+    /// Bounds on the number of records that remain to be read, with the same semantics as
+    /// `Iterator::size_hint`.
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, None)
+    }
 }
 
 pub trait Sink {
     fn write(&mut self, v: Value) -> error::Result<()>;
+
+    /// Writes out any records that the sink might have buffered internally.  The driver calls
+    /// this once all records have been written, so that errors can be reported instead of being
+    /// lost when the sink is dropped.
+    #[inline]
+    fn flush(&mut self) -> error::Result<()> {
+        Ok(())
+    }
+
+    /// Signals that no more records will be written, giving the sink a chance to write any
+    /// trailers that its format requires (container sync blocks, closing brackets, footers...).
+    #[inline]
+    fn finish(&mut self) -> error::Result<()> {
+        Ok(())
+    }
+}
+
+impl<S> Source for Box<S>
+where
+    S: Source + ?Sized,
+{
+    #[inline]
+    fn read(&mut self) -> error::Result<Option<Value>> {
+        (**self).read()
+    }
+
+    #[inline]
+    fn position(&self) -> Option<Position> {
+        (**self).position()
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (**self).size_hint()
+    }
+}
+
+impl<S> Sink for Box<S>
+where
+    S: Sink + ?Sized,
+{
+    #[inline]
+    fn write(&mut self, v: Value) -> error::Result<()> {
+        (**self).write(v)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> error::Result<()> {
+        (**self).flush()
+    }
+
+    #[inline]
+    fn finish(&mut self) -> error::Result<()> {
+        (**self).finish()
+    }
 }
 
 struct ValueVisitor;
@@ -73,12 +210,147 @@ impl Value {
     pub fn from_f64(v: f64) -> Self {
         Self::F64(ordered_float::OrderedFloat(v))
     }
+
+    /// Looks up a nested value by a dotted path like `.items.0.name`; see `path::Path`.
+    pub fn get(&self, path: &str) -> Option<&Self> {
+        path::Path::from(path).get(self)
+    }
+
+    pub fn get_mut(&mut self, path: &str) -> Option<&mut Self> {
+        path::Path::from(path).get_mut(self)
+    }
+
+    pub fn is_unit(&self) -> bool {
+        matches!(*self, Self::Unit)
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match *self {
+            Self::Bool(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match *self {
+            Self::String(ref v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match *self {
+            Self::String(ref v) => Some(v.as_bytes()),
+            Self::Bytes(ref v) => Some(v),
+            _ => None,
+        }
+    }
+
+    /// The value as an `i64`, if it is an integer that fits.
+    pub fn as_i64(&self) -> Option<i64> {
+        use std::convert::TryFrom;
+        match *self {
+            Self::I8(v) => Some(i64::from(v)),
+            Self::I16(v) => Some(i64::from(v)),
+            Self::I32(v) => Some(i64::from(v)),
+            Self::I64(v) => Some(v),
+            Self::U8(v) => Some(i64::from(v)),
+            Self::U16(v) => Some(i64::from(v)),
+            Self::U32(v) => Some(i64::from(v)),
+            Self::U64(v) => i64::try_from(v).ok(),
+            _ => None,
+        }
+    }
+
+    /// The value as a `u64`, if it is an integer that fits.
+    pub fn as_u64(&self) -> Option<u64> {
+        use std::convert::TryFrom;
+        match *self {
+            Self::U8(v) => Some(u64::from(v)),
+            Self::U16(v) => Some(u64::from(v)),
+            Self::U32(v) => Some(u64::from(v)),
+            Self::U64(v) => Some(v),
+            _ => self.as_i64().and_then(|v| u64::try_from(v).ok()),
+        }
+    }
+
+    /// The value as an `f64`, if it is any kind of number.  Large integers lose precision.
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Self::F32(ordered_float::OrderedFloat(v)) => Some(f64::from(v)),
+            Self::F64(ordered_float::OrderedFloat(v)) => Some(v),
+            Self::U64(v) => Some(v as f64),
+            _ => self.as_i64().map(|v| v as f64),
+        }
+    }
+
+    pub fn as_sequence(&self) -> Option<&[Self]> {
+        match *self {
+            Self::Sequence(ref v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn as_map(&self) -> Option<&[(Self, Self)]> {
+        match *self {
+            Self::Map(ref v) => Some(v),
+            _ => None,
+        }
+    }
+
+    /// The text of this value as a map key, for formats whose keys are strings.  Strings and
+    /// chars are used as they are, and other values are rendered without quotes, like `1` or
+    /// `0xff00`.
+    pub fn into_key_string(self) -> String {
+        match self {
+            Self::String(s) => s,
+            Self::Char(c) => c.to_string(),
+            v => v.to_string(),
+        }
+    }
+
+    /// A human readable rendering of this value that is at most `max_len` chars long.  Longer
+    /// renderings are cut off and end with an ellipsis.
+    ///
+    /// Only as much of the value as fits is rendered, so this is cheap even for huge values.
+    pub fn summary(&self, max_len: usize) -> String {
+        use std::fmt::Write;
+
+        let mut out = Truncating {
+            buffer: String::new(),
+            remaining: max_len,
+            truncated: false,
+        };
+        // The only error that can occur is the one that signals truncation
+        let _ = write!(out, "{}", self);
+
+        if out.truncated && max_len > 0 {
+            out.buffer.pop();
+            out.buffer.push('…');
+        }
+        out.buffer
+    }
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "record {}", self.record)?;
+        if let Some(line) = self.line {
+            write!(f, ", line {}", line)?;
+        }
+        if let Some(byte) = self.byte {
+            write!(f, ", byte {}", byte)?;
+        }
+        Ok(())
+    }
 }
 
 impl fmt::Display for Value {
+    /// Renders the value on a single line, in a JSON-like notation that keeps the distinction
+    /// between strings, chars and bytes visible.
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            Self::Unit => write!(f, "()"),
+            Self::Unit => write!(f, "null"),
             Self::Bool(v) => write!(f, "{}", v),
 
             Self::I8(v) => write!(f, "{}", v),
@@ -94,9 +366,10 @@ impl fmt::Display for Value {
             Self::F32(v) => write!(f, "{}", v),
             Self::F64(v) => write!(f, "{}", v),
 
-            Self::Char(v) => write!(f, "{}", v),
-            Self::String(ref v) => write!(f, "{}", v),
+            Self::Char(v) => write!(f, "'{}'", v.escape_debug()),
+            Self::String(ref v) => write!(f, "\"{}\"", v.escape_debug()),
             Self::Bytes(ref v) => {
+                write!(f, "0x")?;
                 for b in v {
                     write!(f, "{:02x}", b)?;
                 }
@@ -133,6 +406,27 @@ impl fmt::Display for Value {
     }
 }
 
+/// A `fmt::Write` that gives up once it has received a certain number of chars.
+struct Truncating {
+    buffer: String,
+    remaining: usize,
+    truncated: bool,
+}
+
+impl fmt::Write for Truncating {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            if self.remaining == 0 {
+                self.truncated = true;
+                return Err(fmt::Error);
+            }
+            self.buffer.push(c);
+            self.remaining -= 1;
+        }
+        Ok(())
+    }
+}
+
 impl serde::ser::Serialize for Value {
     #[inline]
     fn serialize<S>(&self, s: S) -> Result<S::Ok, S::Error>
@@ -348,7 +642,8 @@ impl<'de> serde::de::Visitor<'de> for ValueVisitor {
     where
         V: serde::de::SeqAccess<'de>,
     {
-        let mut values = v.size_hint().map_or(Vec::new(), Vec::with_capacity);
+        // The hint comes from the input, so it is only trusted up to a point
+        let mut values = Vec::with_capacity(v.size_hint().unwrap_or(0).min(MAX_PREALLOCATED));
 
         while let Some(element) = v.next_element()? {
             values.push(element);
@@ -371,3 +666,88 @@ impl<'de> serde::de::Visitor<'de> for ValueVisitor {
         Ok(Value::Map(values))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        assert_eq!(Value::Unit.to_string(), "null");
+        assert_eq!(
+            Value::String("a \"b\"\n".to_owned()).to_string(),
+            r#""a \"b\"\n""#
+        );
+        assert_eq!(Value::Char('\'').to_string(), r"'\''");
+        assert_eq!(Value::Bytes(vec![0, 0xab, 0x0f]).to_string(), "0x00ab0f");
+        assert_eq!(Value::Bytes(Vec::new()).to_string(), "0x");
+        assert_eq!(
+            value!({"a": [1, -2.5, true], "b": {}}).to_string(),
+            r#"{"a": [1, -2.5, true], "b": {}}"#
+        );
+        let map = Value::Map(vec![
+            (Value::U8(1), Value::Char('x')),
+            (Value::Bytes(vec![0xff]), Value::Unit),
+        ]);
+        assert_eq!(map.to_string(), "{1: 'x', 0xff: null}");
+    }
+
+    #[test]
+    fn test_summary() {
+        let v = value!({"message": "hello world"});
+        assert_eq!(v.summary(100), r#"{"message": "hello world"}"#);
+        // Exactly as long as the rendering isn't truncated
+        assert_eq!(v.summary(26), r#"{"message": "hello world"}"#);
+        assert_eq!(v.summary(25), r#"{"message": "hello world…"#);
+        assert_eq!(v.summary(1), "…");
+        assert_eq!(v.summary(0), "");
+        // Lengths are in chars, not bytes
+        assert_eq!(value!("ééé").summary(4), "\"éé…");
+        assert_eq!(Value::Bytes(vec![0xff; 1000]).summary(6), "0xfff…");
+    }
+
+    #[test]
+    fn test_into_key_string() {
+        assert_eq!(value!("a b").into_key_string(), "a b");
+        assert_eq!(Value::Char('c').into_key_string(), "c");
+        assert_eq!(Value::I64(-1).into_key_string(), "-1");
+        assert_eq!(Value::Bool(true).into_key_string(), "true");
+        assert_eq!(Value::Bytes(vec![1, 2]).into_key_string(), "0x0102");
+    }
+
+    #[test]
+    fn test_accessors() {
+        assert!(Value::Unit.is_unit());
+        assert_eq!(Value::Bool(true).as_bool(), Some(true));
+        assert_eq!(value!("a").as_str(), Some("a"));
+        assert_eq!(value!(1).as_str(), None);
+        assert_eq!(value!("a").as_bytes(), Some(&b"a"[..]));
+        assert_eq!(Value::Bytes(vec![1]).as_bytes(), Some(&[1][..]));
+
+        assert_eq!(Value::U8(7).as_i64(), Some(7));
+        assert_eq!(Value::U64(u64::MAX).as_i64(), None);
+        assert_eq!(Value::I8(-1).as_u64(), None);
+        assert_eq!(Value::I64(5).as_u64(), Some(5));
+        assert_eq!(Value::from_f64(1.0).as_i64(), None);
+        assert_eq!(Value::from_f32(0.5).as_f64(), Some(0.5));
+        assert_eq!(Value::I16(-3).as_f64(), Some(-3.0));
+        assert_eq!(value!("1").as_f64(), None);
+
+        let v = value!({"a": [1, {"b": 2}]});
+        assert_eq!(v.as_map().map(<[_]>::len), Some(1));
+        assert_eq!(
+            v.get(".a").and_then(Value::as_sequence).map(<[_]>::len),
+            Some(2)
+        );
+        assert_eq!(v.get(".a.1.b"), Some(&value!(2)));
+        assert_eq!(v.get(".a.2"), None);
+    }
+
+    #[test]
+    fn test_bogus_sequence_length() {
+        // A CBOR array that claims to have 2^64-1 elements
+        let result: Result<Value, _> =
+            serde_cbor::from_slice(b"\x9b\xff\xff\xff\xff\xff\xff\xff\xff\x01");
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,483 @@
+//! Container images, in a tarball of an OCI image layout or as written by `docker save`.
+//!
+//! Each image becomes a `manifest` record, followed by a `config` record and a `layer` record
+//! for each of its layers, which the `type` field tells apart.  All records have the `image` they
+//! belong to, which is the digest of its config, the image ID of Docker.
+//!
+//! * Manifests have the `digest` and `media_type` of the manifest (null for the legacy format of
+//!   `docker save`), the `tags` of the image, its `platform` like `linux/arm64/v8`, its `config`
+//!   digest and the digests of its `layers`.
+//! * Configs are the image configuration, with fields like `architecture`, `created`, `config`
+//!   (with `Env`, `Cmd`, `Labels` and so on), `rootfs` and `history`.
+//! * Layers have their `index` in the image, the `digest`, `media_type` and `size` of the blob,
+//!   the `diff_id` of its uncompressed content and the `created_by` command from the history.
+//!
+//! Images of an OCI layout whose blobs aren't in the archive, like the other platforms of an
+//! image that `docker save` only has one platform of, are left out.
+
+use std::collections;
+use std::io;
+
+use crate::error;
+use crate::value;
+
+/// Files in the archive that are larger than this are not metadata and aren't kept in memory.
+/// Registries don't accept manifests that are larger.
+const MAX_METADATA_SIZE: u64 = 4 << 20;
+
+const INDEX_MEDIA_TYPES: &[&str] = &[
+    "application/vnd.oci.image.index.v1+json",
+    "application/vnd.docker.distribution.manifest.list.v2+json",
+];
+
+#[derive(Debug)]
+pub struct Source(collections::VecDeque<value::Value>);
+
+#[derive(Debug)]
+struct File {
+    size: u64,
+    contents: Option<Vec<u8>>,
+}
+
+/// Creates a source for the images in a tar archive, which is read in full from the input.
+pub fn source<R>(r: R) -> error::Result<Source>
+where
+    R: io::Read,
+{
+    let mut files = collections::HashMap::new();
+    let mut archive = tar::Archive::new(r);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let path = entry.path()?.to_string_lossy().into_owned();
+        let path = path.trim_start_matches("./").to_owned();
+        let size = entry.size();
+        let contents = if size <= MAX_METADATA_SIZE {
+            let mut contents = Vec::with_capacity(size as usize);
+            io::Read::read_to_end(&mut entry, &mut contents)?;
+            Some(contents)
+        } else {
+            None
+        };
+        files.insert(path, File { size, contents });
+    }
+
+    let mut records = Vec::new();
+    if files.contains_key("index.json") {
+        let index = json(&files, "index.json")?;
+        oci_index(&files, &index, &mut records)?;
+    } else if files.contains_key("manifest.json") {
+        docker_manifest(&files, &json(&files, "manifest.json")?, &mut records)?;
+    } else {
+        return Err(error::Error::Format {
+            msg: "not an OCI image layout or docker save archive: it has no index.json or \
+                  manifest.json"
+                .to_owned(),
+        });
+    }
+    Ok(Source(records.into()))
+}
+
+impl value::Source for Source {
+    #[inline]
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        Ok(self.0.pop_front())
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.0.len(), Some(self.0.len()))
+    }
+}
+
+/// The images of an image index, which can refer to nested indexes for multi-platform images.
+fn oci_index(
+    files: &collections::HashMap<String, File>,
+    index: &value::Value,
+    records: &mut Vec<value::Value>,
+) -> error::Result<()> {
+    let manifests = match index.get(".manifests") {
+        Some(value::Value::Sequence(manifests)) => manifests,
+        _ => return Ok(()),
+    };
+    for descriptor in manifests {
+        let digest = descriptor
+            .get(".digest")
+            .and_then(value::Value::as_str)
+            .unwrap_or_default();
+        let path = blob_path(digest);
+        if !files.contains_key(&path) {
+            debug!("Skipping {}, which is not in the archive", digest);
+            continue;
+        }
+        let document = json(files, &path)?;
+        let media_type = descriptor
+            .get(".mediaType")
+            .or_else(|| document.get(".mediaType"))
+            .and_then(value::Value::as_str)
+            .unwrap_or_default();
+        if INDEX_MEDIA_TYPES.contains(&media_type) || document.get(".manifests").is_some() {
+            oci_index(files, &document, records)?;
+            continue;
+        }
+
+        let tags = [
+            // The full name of the image, or only the tag
+            "/annotations/io.containerd.image.name",
+            "/annotations/org.opencontainers.image.ref.name",
+        ]
+        .iter()
+        .filter_map(|path| descriptor.get(path).cloned())
+        .take(1)
+        .collect();
+        let config = document
+            .get(".config")
+            .cloned()
+            .unwrap_or(value::Value::Unit);
+        let layers = match document.get(".layers") {
+            Some(value::Value::Sequence(layers)) => layers.clone(),
+            _ => Vec::new(),
+        };
+        image(
+            files,
+            Image {
+                digest: digest.into(),
+                media_type: media_type.into(),
+                tags,
+                config,
+                layers,
+            },
+            records,
+        )?;
+    }
+    Ok(())
+}
+
+/// The images of the `manifest.json` of `docker save`, which refers to configs and layers by
+/// their path in the archive.
+fn docker_manifest(
+    files: &collections::HashMap<String, File>,
+    manifest: &value::Value,
+    records: &mut Vec<value::Value>,
+) -> error::Result<()> {
+    let images = match manifest {
+        value::Value::Sequence(images) => images,
+        _ => return Err(not_an_image("manifest.json is not an array")),
+    };
+    for entry in images {
+        let config_path = entry
+            .get(".Config")
+            .and_then(value::Value::as_str)
+            .unwrap_or_default();
+        let tags = match entry.get(".RepoTags") {
+            Some(value::Value::Sequence(tags)) => tags.clone(),
+            _ => Vec::new(),
+        };
+        let layer_paths = match entry.get(".Layers") {
+            Some(value::Value::Sequence(layers)) => &layers[..],
+            _ => &[],
+        };
+        let layers = layer_paths
+            .iter()
+            .map(|path| {
+                let path = path.as_str().unwrap_or_default();
+                value::Value::Map(vec![
+                    ("digest".into(), path_digest(path)),
+                    (
+                        "size".into(),
+                        files
+                            .get(path)
+                            .map_or(value::Value::Unit, |f| value::Value::U64(f.size)),
+                    ),
+                ])
+            })
+            .collect();
+        let config = value::Value::Map(vec![
+            ("digest".into(), path_digest(config_path)),
+            ("path".into(), config_path.into()),
+        ]);
+        image(
+            files,
+            Image {
+                digest: value::Value::Unit,
+                media_type: value::Value::Unit,
+                tags,
+                config,
+                layers,
+            },
+            records,
+        )?;
+    }
+    Ok(())
+}
+
+/// An image manifest, with the descriptors of its config and layers.
+struct Image {
+    digest: value::Value,
+    media_type: value::Value,
+    tags: Vec<value::Value>,
+    config: value::Value,
+    layers: Vec<value::Value>,
+}
+
+fn image(
+    files: &collections::HashMap<String, File>,
+    image: Image,
+    records: &mut Vec<value::Value>,
+) -> error::Result<()> {
+    let config_digest = image
+        .config
+        .get(".digest")
+        .cloned()
+        .unwrap_or(value::Value::Unit);
+    let config_path = match image.config.get(".path").and_then(value::Value::as_str) {
+        Some(path) => path.to_owned(),
+        None => blob_path(config_digest.as_str().unwrap_or_default()),
+    };
+    let config = json(files, &config_path)?;
+
+    let platform = [".os", ".architecture", ".variant"]
+        .iter()
+        .filter_map(|path| config.get(path).and_then(value::Value::as_str))
+        .collect::<Vec<_>>()
+        .join("/");
+    records.push(value::Value::Map(vec![
+        ("type".into(), "manifest".into()),
+        ("image".into(), config_digest.clone()),
+        ("digest".into(), image.digest),
+        ("media_type".into(), image.media_type),
+        ("tags".into(), value::Value::Sequence(image.tags)),
+        ("platform".into(), platform.into()),
+        ("config".into(), config_digest.clone()),
+        (
+            "layers".into(),
+            value::Value::Sequence(
+                image
+                    .layers
+                    .iter()
+                    .map(|layer| layer.get(".digest").cloned().unwrap_or(value::Value::Unit))
+                    .collect(),
+            ),
+        ),
+    ]));
+
+    let mut entries = vec![
+        ("type".into(), "config".into()),
+        ("image".into(), config_digest.clone()),
+        ("digest".into(), config_digest.clone()),
+    ];
+    if let value::Value::Map(ref fields) = config {
+        entries.extend(fields.iter().cloned());
+    }
+    records.push(value::Value::Map(entries));
+
+    let diff_ids = match config.get(".rootfs.diff_ids") {
+        Some(value::Value::Sequence(diff_ids)) => &diff_ids[..],
+        _ => &[],
+    };
+    // History entries of instructions like ENV don't have a layer
+    let history = match config.get(".history") {
+        Some(value::Value::Sequence(history)) => &history[..],
+        _ => &[],
+    };
+    let mut created_by = history
+        .iter()
+        .filter(|h| h.get(".empty_layer").and_then(value::Value::as_bool) != Some(true))
+        .map(|h| h.get(".created_by").cloned().unwrap_or(value::Value::Unit));
+    for (index, layer) in image.layers.iter().enumerate() {
+        let field = |path| layer.get(path).cloned().unwrap_or(value::Value::Unit);
+        records.push(value::Value::Map(vec![
+            ("type".into(), "layer".into()),
+            ("image".into(), config_digest.clone()),
+            ("index".into(), value::Value::U64(index as u64)),
+            ("digest".into(), field(".digest")),
+            ("media_type".into(), field(".mediaType")),
+            ("size".into(), field(".size")),
+            (
+                "diff_id".into(),
+                diff_ids.get(index).cloned().unwrap_or(value::Value::Unit),
+            ),
+            (
+                "created_by".into(),
+                created_by.next().unwrap_or(value::Value::Unit),
+            ),
+        ]));
+    }
+    Ok(())
+}
+
+/// The path of a blob in an OCI layout, like `blobs/sha256/<hex>` for `sha256:<hex>`.
+fn blob_path(digest: &str) -> String {
+    match digest.split_once(':') {
+        Some((algorithm, hex)) => format!("blobs/{}/{}", algorithm, hex),
+        None => digest.to_owned(),
+    }
+}
+
+/// The digest of a file in the archive, if its path has one, like `blobs/sha256/<hex>` or
+/// `<hex>.json` does.
+fn path_digest(path: &str) -> value::Value {
+    let hex = |s: &str| s.len() == 64 && s.bytes().all(|b| b.is_ascii_hexdigit());
+    if let Some((algorithm, digest)) = path
+        .strip_prefix("blobs/")
+        .and_then(|rest| rest.split_once('/'))
+    {
+        format!("{}:{}", algorithm, digest).into()
+    } else if let Some(digest) = path.strip_suffix(".json").filter(|s| hex(s)) {
+        format!("sha256:{}", digest).into()
+    } else {
+        value::Value::Unit
+    }
+}
+
+fn json(files: &collections::HashMap<String, File>, path: &str) -> error::Result<value::Value> {
+    match files.get(path) {
+        Some(File {
+            contents: Some(contents),
+            ..
+        }) => Ok(serde_json::from_slice(contents)?),
+        Some(File { size, .. }) => Err(not_an_image(&format!(
+            "{} is too large for metadata ({} bytes)",
+            path, size
+        ))),
+        None => Err(not_an_image(&format!("{} is missing", path))),
+    }
+}
+
+fn not_an_image(reason: &str) -> error::Error {
+    error::Error::Format {
+        msg: format!("not a valid image archive: {}", reason),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::value::Source as _;
+
+    fn read(input: &[u8]) -> error::Result<Vec<value::Value>> {
+        let mut reader = source(input)?;
+        let mut records = Vec::new();
+        while let Some(record) = reader.read()? {
+            records.push(record);
+        }
+        Ok(records)
+    }
+
+    #[test]
+    fn test_archives() {
+        let archive = |files: &[(&str, &str)]| -> Vec<u8> {
+            let mut builder = tar::Builder::new(Vec::new());
+            for (path, contents) in files {
+                let mut header = tar::Header::new_gnu();
+                header.set_size(contents.len() as u64);
+                header.set_mode(0o644);
+                header.set_cksum();
+                builder
+                    .append_data(&mut header, path, contents.as_bytes())
+                    .unwrap();
+            }
+            builder.into_inner().unwrap()
+        };
+        let records = |archive: &[u8]| -> Vec<String> {
+            read(archive)
+                .unwrap()
+                .iter()
+                .map(ToString::to_string)
+                .collect()
+        };
+        let (index, manifest, config, layer) = (
+            "1".repeat(64),
+            "2".repeat(64),
+            "3".repeat(64),
+            "4".repeat(64),
+        );
+        let config_json = r#"{"architecture": "arm64", "variant": "v8", "os": "linux",
+            "config": {"Cmd": ["sh"]},
+            "rootfs": {"type": "layers", "diff_ids": ["sha256:5"]},
+            "history": [{"created_by": "ENV A=1", "empty_layer": true}, {"created_by": "ADD rootfs /"}]}"#;
+
+        let layout = archive(&[
+            ("oci-layout", r#"{"imageLayoutVersion": "1.0.0"}"#),
+            (
+                "index.json",
+                &format!(
+                    r#"{{"schemaVersion": 2, "manifests": [{{"mediaType": "application/vnd.oci.image.index.v1+json", "digest": "sha256:{}", "size": 1,
+                        "annotations": {{"io.containerd.image.name": "docker.io/library/busybox:latest"}}}}]}}"#,
+                    index
+                ),
+            ),
+            (
+                &format!("blobs/sha256/{}", index),
+                &format!(
+                    r#"{{"manifests": [
+                        {{"mediaType": "application/vnd.oci.image.manifest.v1+json", "digest": "sha256:{}", "size": 1,
+                          "annotations": {{"io.containerd.image.name": "docker.io/library/busybox:latest"}}}},
+                        {{"mediaType": "application/vnd.oci.image.manifest.v1+json", "digest": "sha256:{}", "size": 1}}]}}"#,
+                    manifest,
+                    "9".repeat(64)
+                ),
+            ),
+            (
+                &format!("blobs/sha256/{}", manifest),
+                &format!(
+                    r#"{{"config": {{"mediaType": "application/vnd.oci.image.config.v1+json", "digest": "sha256:{}", "size": 1}},
+                        "layers": [{{"mediaType": "application/vnd.oci.image.layer.v1.tar+gzip", "digest": "sha256:{}", "size": 4}}]}}"#,
+                    config, layer
+                ),
+            ),
+            (&format!("blobs/sha256/{}", config), config_json),
+            (&format!("blobs/sha256/{}", layer), "gzip"),
+        ]);
+        assert_eq!(
+            records(&layout),
+            vec![
+                format!(
+                    r#"{{"type": "manifest", "image": "sha256:{0}", "digest": "sha256:{1}", "media_type": "application/vnd.oci.image.manifest.v1+json", "tags": ["docker.io/library/busybox:latest"], "platform": "linux/arm64/v8", "config": "sha256:{0}", "layers": ["sha256:{2}"]}}"#,
+                    config, manifest, layer
+                ),
+                format!(
+                    r#"{{"type": "config", "image": "sha256:{0}", "digest": "sha256:{0}", "architecture": "arm64", "variant": "v8", "os": "linux", "config": {{"Cmd": ["sh"]}}, "rootfs": {{"type": "layers", "diff_ids": ["sha256:5"]}}, "history": [{{"created_by": "ENV A=1", "empty_layer": true}}, {{"created_by": "ADD rootfs /"}}]}}"#,
+                    config
+                ),
+                format!(
+                    r#"{{"type": "layer", "image": "sha256:{0}", "index": 0, "digest": "sha256:{1}", "media_type": "application/vnd.oci.image.layer.v1.tar+gzip", "size": 4, "diff_id": "sha256:5", "created_by": "ADD rootfs /"}}"#,
+                    config, layer
+                ),
+            ]
+        );
+
+        let legacy = archive(&[
+            ("abc/layer.tar", "layer"),
+            (&format!("{}.json", config), config_json),
+            (
+                "manifest.json",
+                &format!(
+                    r#"[{{"Config": "{}.json", "RepoTags": ["busybox:latest"], "Layers": ["abc/layer.tar"]}}]"#,
+                    config
+                ),
+            ),
+        ]);
+        let legacy = records(&legacy);
+        assert_eq!(legacy.len(), 3);
+        assert_eq!(
+            legacy[0],
+            format!(
+                r#"{{"type": "manifest", "image": "sha256:{0}", "digest": null, "media_type": null, "tags": ["busybox:latest"], "platform": "linux/arm64/v8", "config": "sha256:{0}", "layers": [null]}}"#,
+                config
+            )
+        );
+        assert_eq!(
+            legacy[2],
+            format!(
+                r#"{{"type": "layer", "image": "sha256:{}", "index": 0, "digest": null, "media_type": null, "size": 5, "diff_id": "sha256:5", "created_by": "ADD rootfs /"}}"#,
+                config
+            )
+        );
+
+        assert!(read(&archive(&[("a.txt", "a")])).is_err());
+        assert!(read(b"{}").is_err());
+    }
+}
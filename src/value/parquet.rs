@@ -0,0 +1,205 @@
+//! Apache Parquet files.
+//!
+//! The source reads the rows of all row groups as maps.  The sink buffers records into batches,
+//! infers the schema from the first batch, and writes each batch as a row group.  Records after
+//! the first batch must fit that schema; fields that it doesn't have are left out.
+
+use std::fmt;
+use std::io;
+use std::mem;
+
+use parquet::file::reader::FileReader;
+use parquet::record::Field;
+
+use crate::error;
+use crate::value;
+
+pub struct Source(parquet::record::reader::RowIter<'static>);
+
+pub struct Sink<W>
+where
+    W: io::Write,
+{
+    output: W,
+    batcher: value::arrow::Batcher,
+    // The writer only writes to a buffer, because it needs an output that is `Send`
+    writer: Option<parquet::arrow::ArrowWriter<Vec<u8>>>,
+}
+
+/// Creates a Parquet source.  Parquet files can only be read from the end, so the whole input
+/// is read up front.
+pub fn source<R>(mut r: R) -> error::Result<Source>
+where
+    R: io::Read,
+{
+    let mut input = Vec::new();
+    r.read_to_end(&mut input)?;
+    let reader =
+        parquet::file::serialized_reader::SerializedFileReader::new(bytes::Bytes::from(input))?;
+    let reader: Box<dyn FileReader> = Box::new(reader);
+    Ok(Source(parquet::record::reader::RowIter::from_file_into(
+        reader,
+    )))
+}
+
+#[inline]
+pub fn sink<W>(w: W) -> Sink<W>
+where
+    W: io::Write,
+{
+    Sink {
+        output: w,
+        batcher: value::arrow::Batcher::new(),
+        writer: None,
+    }
+}
+
+impl value::Source for Source {
+    #[inline]
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        match self.0.next() {
+            Some(row) => Ok(Some(value_from_row(row?))),
+            None => Ok(None),
+        }
+    }
+}
+
+fn value_from_row(row: parquet::record::Row) -> value::Value {
+    value::Value::Map(
+        row.into_columns()
+            .into_iter()
+            .map(|(name, field)| (value::Value::String(name), value_from_field(field)))
+            .collect(),
+    )
+}
+
+fn value_from_field(field: Field) -> value::Value {
+    match field {
+        Field::Null => value::Value::Unit,
+        Field::Bool(v) => value::Value::Bool(v),
+        Field::Byte(v) => value::Value::I8(v),
+        Field::Short(v) => value::Value::I16(v),
+        Field::Int(v) => value::Value::I32(v),
+        Field::Long(v) => value::Value::I64(v),
+        Field::UByte(v) => value::Value::U8(v),
+        Field::UShort(v) => value::Value::U16(v),
+        Field::UInt(v) => value::Value::U32(v),
+        Field::ULong(v) => value::Value::U64(v),
+        Field::Float16(v) => value::Value::from_f32(v.to_f32()),
+        Field::Float(v) => value::Value::from_f32(v),
+        Field::Double(v) => value::Value::from_f64(v),
+        Field::Str(v) => value::Value::String(v),
+        Field::Bytes(v) => value::Value::Bytes(v.data().to_vec()),
+        // Decimals, dates and timestamps are written like the Parquet tools show them
+        field @ Field::Decimal(_)
+        | field @ Field::Date(_)
+        | field @ Field::TimestampMillis(_)
+        | field @ Field::TimestampMicros(_) => value::Value::String(field.to_string()),
+        Field::Group(row) => value_from_row(row),
+        Field::ListInternal(list) => value::Value::Sequence(
+            list.elements()
+                .iter()
+                .cloned()
+                .map(value_from_field)
+                .collect(),
+        ),
+        Field::MapInternal(map) => value::Value::Map(
+            map.entries()
+                .iter()
+                .cloned()
+                .map(|(k, v)| (value_from_field(k), value_from_field(v)))
+                .collect(),
+        ),
+    }
+}
+
+impl<W> Sink<W>
+where
+    W: io::Write,
+{
+    fn write_batch(&mut self) -> error::Result<()> {
+        if let Some((schema, batch)) = self.batcher.batch()? {
+            if self.writer.is_none() {
+                let writer = parquet::arrow::ArrowWriter::try_new(Vec::new(), schema, None)?;
+                self.writer = Some(writer);
+            }
+            if let Some(ref mut writer) = self.writer {
+                writer.write(&batch)?;
+                writer.flush()?;
+                self.output.write_all(&mem::take(writer.inner_mut()))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<W> value::Sink for Sink<W>
+where
+    W: io::Write,
+{
+    fn write(&mut self, value: value::Value) -> error::Result<()> {
+        if self.batcher.push(value, "Parquet")? {
+            self.write_batch()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> error::Result<()> {
+        self.output.flush()?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> error::Result<()> {
+        self.write_batch()?;
+        if let Some(writer) = self.writer.take() {
+            let output = writer.into_inner()?;
+            self.output.write_all(&output)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ParquetSource").finish()
+    }
+}
+
+impl<W> fmt::Debug for Sink<W>
+where
+    W: io::Write,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ParquetSink").finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::value::Sink as _;
+    use crate::value::Source as _;
+
+    #[test]
+    fn test_round_trip() {
+        let records = vec![
+            value!({"id": 1, "name": "a", "tags": ["x", "y"], "owner": {"admin": true}}),
+            value!({"id": 2, "name": null, "tags": [], "owner": {"admin": false}}),
+        ];
+        let mut output = Vec::new();
+        {
+            let mut writer = sink(&mut output);
+            for record in records.clone() {
+                writer.write(record).unwrap();
+            }
+            writer.finish().unwrap();
+        }
+        let mut reader = source(&output[..]).unwrap();
+        for record in records {
+            let read = reader.read().unwrap().unwrap();
+            assert_eq!(read.to_string(), record.to_string());
+        }
+        assert_eq!(reader.read().unwrap(), None);
+    }
+}
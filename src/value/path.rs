@@ -0,0 +1,237 @@
+use crate::error;
+use crate::value;
+use std::fmt;
+use std::str;
+
+/// A path that addresses a value nested within another value.
+///
+/// Paths are written as dot-separated segments, like `.items.0.name`; the leading dot is
+/// optional.  A segment selects the entry with a matching key in a map, or the element at
+/// that index in a sequence.
+///
+/// Paths starting with a slash are RFC 6901 JSON Pointers instead, like `/items/0/name`, which
+/// can also address keys that contain dots:
+///
+/// ```
+/// use record_query::value;
+/// use record_query::value::path::Path;
+///
+/// let v = value!({"a.b": [1, 2]});
+/// let path = Path::from("/a.b/1");
+/// assert_eq!(path.get(&v).and_then(value::Value::as_i64), Some(2));
+/// assert_eq!(path.to_pointer(), "/a.b/1");
+/// ```
+#[derive(Clone, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Path(Vec<String>);
+
+impl Path {
+    /// The empty path, which addresses the value itself.
+    pub fn root() -> Self {
+        Self(Vec::new())
+    }
+
+    pub fn segments(&self) -> &[String] {
+        &self.0
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn push<S>(&mut self, segment: S)
+    where
+        S: Into<String>,
+    {
+        self.0.push(segment.into())
+    }
+
+    /// Formats this path as an RFC 6901 JSON Pointer, like `/items/0/name`.
+    pub fn to_pointer(&self) -> String {
+        self.0
+            .iter()
+            .map(|segment| format!("/{}", segment.replace('~', "~0").replace('/', "~1")))
+            .collect()
+    }
+
+    pub fn get<'a>(&self, mut value: &'a value::Value) -> Option<&'a value::Value> {
+        for segment in &self.0 {
+            value = child(value, segment)?;
+        }
+        Some(value)
+    }
+
+    pub fn get_mut<'a>(&self, mut value: &'a mut value::Value) -> Option<&'a mut value::Value> {
+        for segment in &self.0 {
+            value = child_mut(value, segment)?;
+        }
+        Some(value)
+    }
+
+    /// Sets the value at this path, creating maps for any missing segments along the way.  Like
+    /// in JSON Pointers, the segment `-` appends to a sequence.  Returns `false` if the path goes
+    /// through something other than a map or sequence, or through a sequence index that is out
+    /// of bounds.
+    pub fn set(&self, mut value: &mut value::Value, v: value::Value) -> bool {
+        for segment in &self.0 {
+            if child(value, segment).is_none() {
+                match *value {
+                    value::Value::Map(ref mut entries) => entries.push((
+                        value::Value::String(segment.clone()),
+                        value::Value::Map(Vec::new()),
+                    )),
+                    value::Value::Sequence(ref mut elements) if segment == "-" => {
+                        elements.push(value::Value::Map(Vec::new()));
+                        let last = elements.len() - 1;
+                        value = &mut elements[last];
+                        continue;
+                    }
+                    _ => return false,
+                }
+            }
+            value = child_mut(value, segment).expect("child was just created");
+        }
+        *value = v;
+        true
+    }
+
+    /// Removes and returns the value at this path from its containing map or sequence.  The root
+    /// path can't be removed.
+    pub fn remove(&self, value: &mut value::Value) -> Option<value::Value> {
+        let (last, parents) = self.0.split_last()?;
+        let mut parent = value;
+        for segment in parents {
+            parent = child_mut(parent, segment)?;
+        }
+        match *parent {
+            value::Value::Map(ref mut entries) => {
+                let i = entries.iter().position(|(k, _)| key_matches(k, last))?;
+                Some(entries.remove(i).1)
+            }
+            value::Value::Sequence(ref mut elements) => {
+                let i = last.parse::<usize>().ok()?;
+                if i < elements.len() {
+                    Some(elements.remove(i))
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Whether a map key is addressed by the specified path segment.
+pub fn key_matches(key: &value::Value, segment: &str) -> bool {
+    match *key {
+        value::Value::String(ref s) => s == segment,
+        value::Value::Char(c) => segment.chars().eq(Some(c)),
+        value::Value::I8(_)
+        | value::Value::I16(_)
+        | value::Value::I32(_)
+        | value::Value::I64(_)
+        | value::Value::U8(_)
+        | value::Value::U16(_)
+        | value::Value::U32(_)
+        | value::Value::U64(_) => key.to_string() == segment,
+        _ => false,
+    }
+}
+
+fn child<'a>(value: &'a value::Value, segment: &str) -> Option<&'a value::Value> {
+    match *value {
+        value::Value::Map(ref entries) => entries
+            .iter()
+            .find(|(k, _)| key_matches(k, segment))
+            .map(|(_, v)| v),
+        value::Value::Sequence(ref elements) => elements.get(segment.parse::<usize>().ok()?),
+        _ => None,
+    }
+}
+
+fn child_mut<'a>(value: &'a mut value::Value, segment: &str) -> Option<&'a mut value::Value> {
+    match *value {
+        value::Value::Map(ref mut entries) => entries
+            .iter_mut()
+            .find(|(k, _)| key_matches(k, segment))
+            .map(|(_, v)| v),
+        value::Value::Sequence(ref mut elements) => {
+            elements.get_mut(segment.parse::<usize>().ok()?)
+        }
+        _ => None,
+    }
+}
+
+impl From<&str> for Path {
+    fn from(s: &str) -> Self {
+        if let Some(pointer) = s.strip_prefix('/') {
+            return Self(
+                pointer
+                    .split('/')
+                    .map(|segment| segment.replace("~1", "/").replace("~0", "~"))
+                    .collect(),
+            );
+        }
+        let s = s.strip_prefix('.').unwrap_or(s);
+        if s.is_empty() {
+            Self::root()
+        } else {
+            Self(s.split('.').map(ToOwned::to_owned).collect())
+        }
+    }
+}
+
+impl str::FromStr for Path {
+    type Err = error::Error;
+
+    fn from_str(s: &str) -> error::Result<Self> {
+        Ok(Self::from(s))
+    }
+}
+
+impl fmt::Display for Path {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0.is_empty() {
+            return write!(f, ".");
+        }
+        for segment in &self.0 {
+            write!(f, ".{}", segment)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pointer_escapes() {
+        let path = Path::from("/a~1b/c~0d/~01");
+        assert_eq!(path.segments(), ["a/b", "c~d", "~1"]);
+        assert_eq!(path.to_pointer(), "/a~1b/c~0d/~01");
+
+        let v = value!({"a/b": {"c~d": {"~1": true}}});
+        assert_eq!(path.get(&v), Some(&value!(true)));
+        // Dots are not separators in pointers
+        assert_eq!(Path::from("/a.b").segments(), ["a.b"]);
+        assert_eq!(Path::from("/").segments(), [""]);
+    }
+
+    #[test]
+    fn test_pointer_indices() {
+        let mut v = value!({"items": [{"name": "a"}, {"name": "b"}]});
+        assert_eq!(Path::from("/items/1/name").get(&v), Some(&value!("b")));
+        assert_eq!(Path::from("/items/2").get(&v), None);
+        assert_eq!(Path::from("/items/-").get(&v), None);
+        assert_eq!(Path::from("/items/x").get(&v), None);
+
+        // `-` appends, and indices past the end can't be set
+        assert!(Path::from("/items/-/name").set(&mut v, value!("c")));
+        assert!(!Path::from("/items/5").set(&mut v, value!("d")));
+        assert_eq!(
+            Path::from("/items/0").remove(&mut v),
+            Some(value!({"name": "a"}))
+        );
+        assert_eq!(v, value!({"items": [{"name": "b"}, {"name": "c"}]}));
+    }
+}
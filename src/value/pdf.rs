@@ -0,0 +1,333 @@
+//! Metadata of PDF documents, without their text.
+//!
+//! Each document becomes a record with its PDF `version`, the number of `pages`, whether it is
+//! `encrypted`, the entries of its document `info` like `Title`, `Author` and `CreationDate`,
+//! and its `outline`: the bookmarks, each with its `title`, the `page` it goes to (counting from
+//! 1, or null for other destinations) and its `children`.  Dates become RFC 3339, like
+//! `2024-05-01T12:34:56+02:00`.  Documents that are encrypted with an empty user password, which
+//! only restrict what readers may do, are decrypted.
+
+use std::collections;
+use std::io;
+
+use crate::error;
+use crate::value;
+
+/// How deeply outlines are read, which guards against cycles of outline items.
+const MAX_OUTLINE_DEPTH: usize = 64;
+
+#[derive(Debug)]
+pub struct Source(Option<value::Value>);
+
+/// Creates a source for the metadata of a PDF document, which is read in full from the input.
+pub fn source<R>(mut r: R) -> error::Result<Source>
+where
+    R: io::Read,
+{
+    let mut input = Vec::new();
+    r.read_to_end(&mut input)?;
+    if !input.starts_with(b"%PDF-") {
+        return Err(format_error("the input is not a PDF document"));
+    }
+    let document = lopdf::Document::load_mem(&input)
+        .map_err(|e| format_error(&format!("invalid PDF document: {}", e)))?;
+    Ok(Source(Some(read(&document)?)))
+}
+
+impl value::Source for Source {
+    #[inline]
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        Ok(self.0.take())
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = if self.0.is_some() { 1 } else { 0 };
+        (remaining, Some(remaining))
+    }
+}
+
+fn read(document: &lopdf::Document) -> error::Result<value::Value> {
+    let catalog = document.catalog().map_err(|e| {
+        if document.is_encrypted() {
+            format_error("the PDF document is encrypted with a password")
+        } else {
+            format_error(&format!("invalid PDF document catalog: {}", e))
+        }
+    })?;
+    let pages = document.get_pages();
+
+    let mut info = Vec::new();
+    if let Ok(object) = document.trailer.get(b"Info") {
+        if let Ok(lopdf::Object::Dictionary(ref dictionary)) =
+            document.dereference(object).map(|(_, o)| o)
+        {
+            for (key, v) in dictionary {
+                let key = String::from_utf8_lossy(key).into_owned();
+                let v = match document.dereference(v).map(|(_, o)| o) {
+                    Ok(v @ lopdf::Object::String(..)) if key.ends_with("Date") => {
+                        let text = text(v);
+                        date(&text).unwrap_or(text).into()
+                    }
+                    Ok(v @ lopdf::Object::String(..)) => text(v).into(),
+                    Ok(lopdf::Object::Name(name)) => {
+                        String::from_utf8_lossy(name).into_owned().into()
+                    }
+                    Ok(lopdf::Object::Boolean(b)) => value::Value::Bool(*b),
+                    Ok(lopdf::Object::Integer(i)) => value::Value::I64(*i),
+                    Ok(lopdf::Object::Real(f)) => value::Value::from_f64(f64::from(*f)),
+                    _ => continue,
+                };
+                info.push((key.into(), v));
+            }
+        }
+    }
+
+    let page_numbers: collections::HashMap<lopdf::ObjectId, u32> =
+        pages.iter().map(|(&number, &id)| (id, number)).collect();
+    let outline = match catalog
+        .get(b"Outlines")
+        .and_then(|o| document.dereference(o))
+        .and_then(|(_, o)| o.as_dict())
+    {
+        Ok(outlines) => {
+            let mut seen = collections::HashSet::new();
+            outline_items(document, catalog, &page_numbers, outlines, 0, &mut seen)
+        }
+        Err(_) => Vec::new(),
+    };
+
+    Ok(value::Value::Map(vec![
+        ("version".into(), document.version.as_str().into()),
+        ("pages".into(), value::Value::U64(pages.len() as u64)),
+        (
+            "encrypted".into(),
+            value::Value::Bool(document.is_encrypted()),
+        ),
+        ("info".into(), value::Value::Map(info)),
+        ("outline".into(), value::Value::Sequence(outline)),
+    ]))
+}
+
+/// Reads the children of an outline item, or of the outline itself.
+fn outline_items(
+    document: &lopdf::Document,
+    catalog: &lopdf::Dictionary,
+    page_numbers: &collections::HashMap<lopdf::ObjectId, u32>,
+    parent: &lopdf::Dictionary,
+    depth: usize,
+    seen: &mut collections::HashSet<lopdf::ObjectId>,
+) -> Vec<value::Value> {
+    let mut items = Vec::new();
+    if depth >= MAX_OUTLINE_DEPTH {
+        return items;
+    }
+    let mut next = parent.get(b"First").ok();
+    while let Some(object) = next {
+        let (id, item) = match document.dereference(object) {
+            Ok((id, lopdf::Object::Dictionary(item))) => (id, item),
+            _ => break,
+        };
+        if let Some(id) = id {
+            if !seen.insert(id) {
+                break;
+            }
+        }
+        let title = item.get(b"Title").map_or_else(|_| String::new(), text);
+        // The destination is either given directly, or by a go-to action
+        let destination = item.get(b"Dest").ok().or_else(|| {
+            let action = document.dereference(item.get(b"A").ok()?).ok()?.1;
+            let action = action.as_dict().ok()?;
+            match action.get(b"S").and_then(lopdf::Object::as_name) {
+                Ok(b"GoTo") => action.get(b"D").ok(),
+                _ => None,
+            }
+        });
+        let page = destination
+            .and_then(|d| destination_page(document, catalog, page_numbers, d))
+            .map_or(value::Value::Unit, value::Value::U32);
+        let children = outline_items(document, catalog, page_numbers, item, depth + 1, seen);
+        items.push(value::Value::Map(vec![
+            ("title".into(), title.into()),
+            ("page".into(), page),
+            ("children".into(), value::Value::Sequence(children)),
+        ]));
+        next = item.get(b"Next").ok();
+    }
+    items
+}
+
+/// Finds the page number of a destination, which is an array that starts with the page, or the
+/// name of a destination in the catalog.
+fn destination_page(
+    document: &lopdf::Document,
+    catalog: &lopdf::Dictionary,
+    page_numbers: &collections::HashMap<lopdf::ObjectId, u32>,
+    destination: &lopdf::Object,
+) -> Option<u32> {
+    let destination = document.dereference(destination).ok()?.1;
+    let destination = match *destination {
+        lopdf::Object::Name(ref name) => {
+            // Named destinations of PDF 1.1 are in a dictionary
+            let dests = document.dereference(catalog.get(b"Dests").ok()?).ok()?.1;
+            document
+                .dereference(dests.as_dict().ok()?.get(name).ok()?)
+                .ok()?
+                .1
+        }
+        lopdf::Object::String(ref name, _) => {
+            let names = document.dereference(catalog.get(b"Names").ok()?).ok()?.1;
+            let tree = document
+                .dereference(names.as_dict().ok()?.get(b"Dests").ok()?)
+                .ok()?
+                .1;
+            name_tree_lookup(document, tree, name, 0)?
+        }
+        _ => destination,
+    };
+    // Named destinations can also be dictionaries with the destination under D
+    let destination = match *destination {
+        lopdf::Object::Dictionary(ref d) => document.dereference(d.get(b"D").ok()?).ok()?.1,
+        _ => destination,
+    };
+    let page = destination.as_array().ok()?.first()?.as_reference().ok()?;
+    page_numbers.get(&page).copied()
+}
+
+/// Looks up a key in a name tree, which has pairs of keys and values in the `Names` of its
+/// leaves.
+fn name_tree_lookup<'a>(
+    document: &'a lopdf::Document,
+    node: &'a lopdf::Object,
+    key: &[u8],
+    depth: usize,
+) -> Option<&'a lopdf::Object> {
+    if depth >= MAX_OUTLINE_DEPTH {
+        return None;
+    }
+    let node = node.as_dict().ok()?;
+    if let Ok(names) = node.get(b"Names").and_then(lopdf::Object::as_array) {
+        for pair in names.chunks(2) {
+            if let [k, v] = pair {
+                if document.dereference(k).ok()?.1.as_str().ok()? == key {
+                    return Some(document.dereference(v).ok()?.1);
+                }
+            }
+        }
+    }
+    let kids = node.get(b"Kids").and_then(lopdf::Object::as_array).ok()?;
+    kids.iter().find_map(|kid| {
+        let kid = document.dereference(kid).ok()?.1;
+        name_tree_lookup(document, kid, key, depth + 1)
+    })
+}
+
+/// Decodes a text string, which is UTF-16 or PDFDocEncoding.
+fn text(object: &lopdf::Object) -> String {
+    match lopdf::decode_text_string(object) {
+        Ok(text) => text.trim_start_matches('\u{feff}').to_owned(),
+        Err(_) => object
+            .as_str()
+            .map(|s| String::from_utf8_lossy(s).into_owned())
+            .unwrap_or_default(),
+    }
+}
+
+/// Converts a date like `D:20240501123456+02'00'` to RFC 3339.  Everything after the year is
+/// optional.
+fn date(text: &str) -> Option<String> {
+    let text = text.strip_prefix("D:").unwrap_or(text);
+    let digits = text.bytes().take_while(u8::is_ascii_digit).count();
+    if digits < 4 || digits % 2 != 0 || digits > 14 {
+        return None;
+    }
+    let field = |start: usize, default: &'static str| {
+        text.get(start..start + 2)
+            .filter(|_| start + 2 <= digits)
+            .unwrap_or(default)
+    };
+    let mut date = format!(
+        "{}-{}-{}T{}:{}:{}",
+        &text[..4],
+        field(4, "01"),
+        field(6, "01"),
+        field(8, "00"),
+        field(10, "00"),
+        field(12, "00")
+    );
+    let zone = &text[digits..];
+    match zone.as_bytes().first() {
+        None => (),
+        Some(b'Z') => date.push('Z'),
+        Some(&sign @ (b'+' | b'-')) => {
+            let offset: String = zone[1..].chars().filter(char::is_ascii_digit).collect();
+            if offset.len() < 2 {
+                return None;
+            }
+            let minutes = offset.get(2..4).unwrap_or("00");
+            date.push_str(&format!("{}{}:{}", char::from(sign), &offset[..2], minutes));
+        }
+        Some(_) => return None,
+    }
+    Some(date)
+}
+
+fn format_error(msg: &str) -> error::Error {
+    error::Error::Format {
+        msg: msg.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::value::Source as _;
+
+    fn read_all(input: &[u8]) -> error::Result<Vec<value::Value>> {
+        let mut reader = source(input)?;
+        let mut records = Vec::new();
+        while let Some(record) = reader.read()? {
+            records.push(record);
+        }
+        Ok(records)
+    }
+
+    #[test]
+    fn test_outline() {
+        let objects = [
+            "<< /Type /Catalog /Pages 2 0 R /Outlines 5 0 R >>",
+            "<< /Type /Pages /Kids [3 0 R 4 0 R] /Count 2 >>",
+            "<< /Type /Page /Parent 2 0 R >>",
+            "<< /Type /Page /Parent 2 0 R >>",
+            "<< /Type /Outlines /First 6 0 R /Last 7 0 R >>",
+            "<< /Title (Intro) /Parent 5 0 R /Next 7 0 R /First 8 0 R /Dest [3 0 R /Fit] >>",
+            "<< /Title (End) /Parent 5 0 R /A << /S /GoTo /D [4 0 R /Fit] >> >>",
+            "<< /Title (Link) /Parent 6 0 R /A << /S /URI /URI (https://example.com) >> >>",
+            "<< /Title (Report) /CreationDate (D:20240501123456+02'00') >>",
+        ];
+        let mut pdf = b"%PDF-1.7\n".to_vec();
+        let mut xref = format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1);
+        for (i, object) in objects.iter().enumerate() {
+            xref.push_str(&format!("{:010} 00000 n \n", pdf.len()));
+            pdf.extend(format!("{} 0 obj\n{}\nendobj\n", i + 1, object).bytes());
+        }
+        let start = pdf.len();
+        pdf.extend(xref.bytes());
+        pdf.extend(
+            format!(
+                "trailer\n<< /Size {} /Root 1 0 R /Info 9 0 R >>\nstartxref\n{}\n%%EOF\n",
+                objects.len() + 1,
+                start
+            )
+            .bytes(),
+        );
+        let records = read_all(&pdf).unwrap();
+        assert_eq!(
+            records.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            vec![
+                r#"{"version": "1.7", "pages": 2, "encrypted": false, "info": {"Title": "Report", "CreationDate": "2024-05-01T12:34:56+02:00"}, "outline": [{"title": "Intro", "page": 1, "children": [{"title": "Link", "page": null, "children": []}]}, {"title": "End", "page": 2, "children": []}]}"#
+            ]
+        );
+        assert!(read_all(b"%!PS-Adobe-3.0").is_err());
+    }
+}
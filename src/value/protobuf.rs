@@ -8,7 +8,14 @@ use crate::value;
 use serde_protobuf;
 use serde_protobuf::descriptor;
 
-pub struct Source<'a>(serde_protobuf::de::Deserializer<'a>, bool);
+pub struct Source<'a>(
+    serde_protobuf::de::Deserializer<'a>,
+    bool,
+    Option<(
+        &'a descriptor::Descriptors,
+        &'a descriptor::MessageDescriptor,
+    )>,
+);
 
 #[inline]
 pub fn source<'a>(
@@ -17,7 +24,24 @@ pub fn source<'a>(
     input: protobuf::CodedInputStream<'a>,
 ) -> error::Result<Source<'a>> {
     let de = serde_protobuf::de::Deserializer::for_named_message(descriptors, message_name, input)?;
-    Ok(Source(de, true))
+    Ok(Source(de, true, None))
+}
+
+/// Like `source`, but renders the message according to the proto3 JSON mapping, like
+/// `protoc` and gRPC gateways do: fields are named in lowerCamelCase and unset fields are left
+/// out, 64-bit integers become strings, bytes become base64 strings and maps become objects.
+/// Well-known types like `google.protobuf.Timestamp` are rendered as ordinary messages.
+pub fn proto3_json_source<'a>(
+    descriptors: &'a descriptor::Descriptors,
+    message_name: &str,
+    input: protobuf::CodedInputStream<'a>,
+) -> error::Result<Source<'a>> {
+    let mut source = source(descriptors, message_name, input)?;
+    // The deserializer has already checked that the message exists
+    source.2 = descriptors
+        .message_by_name(message_name)
+        .map(|message| (descriptors, message));
+    Ok(source)
 }
 
 impl<'a> value::Source for Source<'a> {
@@ -28,7 +52,10 @@ impl<'a> value::Source for Source<'a> {
             match serde::Deserialize::deserialize(&mut self.0)
                 .map_err(serde_protobuf::error::CompatError::into_error)
             {
-                Ok(v) => Ok(Some(v)),
+                Ok(v) => Ok(Some(match self.2 {
+                    Some((descriptors, message)) => proto3_message(v, descriptors, message),
+                    None => v,
+                })),
                 Err(serde_protobuf::error::Error::EndOfStream) => Ok(None),
                 Err(e) => Err(error::Error::from(e)),
             }
@@ -36,6 +63,15 @@ impl<'a> value::Source for Source<'a> {
             Ok(None)
         }
     }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        if self.1 {
+            (0, Some(1))
+        } else {
+            (0, Some(0))
+        }
+    }
 }
 
 impl<'a> fmt::Debug for Source<'a> {
@@ -43,3 +79,182 @@ impl<'a> fmt::Debug for Source<'a> {
         f.debug_struct("ProtobufSource").finish()
     }
 }
+
+fn proto3_message(
+    v: value::Value,
+    descriptors: &descriptor::Descriptors,
+    message: &descriptor::MessageDescriptor,
+) -> value::Value {
+    let entries = match v {
+        value::Value::Map(entries) => entries,
+        v => return v,
+    };
+    let mut result = Vec::with_capacity(entries.len());
+    for (key, v) in entries {
+        let field = match key.as_str().and_then(|name| message.field_by_name(name)) {
+            Some(field) => field,
+            None => {
+                result.push((key, v));
+                continue;
+            }
+        };
+        let v = match v {
+            value::Value::Unit => continue,
+            value::Value::Sequence(ref values) if values.is_empty() => continue,
+            value::Value::Sequence(values) if field.is_repeated() => {
+                proto3_repeated(values, descriptors, field)
+            }
+            v => proto3_value(v, descriptors, field),
+        };
+        result.push((value::Value::String(json_name(field.name())), v));
+    }
+    value::Value::Map(result)
+}
+
+fn proto3_repeated(
+    values: Vec<value::Value>,
+    descriptors: &descriptor::Descriptors,
+    field: &descriptor::FieldDescriptor,
+) -> value::Value {
+    if let descriptor::FieldType::Message(entry) = field.field_type(descriptors) {
+        if let (Some(key_field), Some(value_field)) = map_entry_fields(entry) {
+            let mut result = Vec::with_capacity(values.len());
+            for entry in values {
+                let (mut key, mut v) = (value::Value::Unit, value::Value::Unit);
+                if let value::Value::Map(entry) = entry {
+                    for (k, e) in entry {
+                        match k.as_str() {
+                            Some(name) if name == key_field.name() => key = e,
+                            Some(name) if name == value_field.name() => v = e,
+                            _ => (),
+                        }
+                    }
+                }
+                // Map keys are always strings in JSON, whatever their type
+                let key = match key {
+                    value::Value::String(key) => key,
+                    value::Value::Unit => String::new(),
+                    key => key.to_string(),
+                };
+                let v = proto3_value(v, descriptors, value_field);
+                result.push((value::Value::String(key), v));
+            }
+            return value::Value::Map(result);
+        }
+    }
+    value::Value::Sequence(
+        values
+            .into_iter()
+            .map(|v| proto3_value(v, descriptors, field))
+            .collect(),
+    )
+}
+
+fn proto3_value(
+    v: value::Value,
+    descriptors: &descriptor::Descriptors,
+    field: &descriptor::FieldDescriptor,
+) -> value::Value {
+    use base64::Engine;
+
+    match (field.field_type(descriptors), v) {
+        (descriptor::FieldType::Message(message), v) => proto3_message(v, descriptors, message),
+        (_, value::Value::I64(n)) => value::Value::String(n.to_string()),
+        (_, value::Value::U64(n)) => value::Value::String(n.to_string()),
+        (_, value::Value::Bytes(bytes)) => {
+            value::Value::String(base64::engine::general_purpose::STANDARD.encode(bytes))
+        }
+        (_, v) => match v.as_f64() {
+            Some(f) if f.is_nan() => value::Value::String("NaN".to_owned()),
+            Some(f) if f.is_infinite() && f > 0.0 => value::Value::String("Infinity".to_owned()),
+            Some(f) if f.is_infinite() => value::Value::String("-Infinity".to_owned()),
+            _ => v,
+        },
+    }
+}
+
+/// The key and value fields of a message, if it is the entry type of a map field.  `protoc`
+/// generates these as nested messages named `<Field>Entry` with the fields `key` and `value`.
+fn map_entry_fields(
+    message: &descriptor::MessageDescriptor,
+) -> (
+    Option<&descriptor::FieldDescriptor>,
+    Option<&descriptor::FieldDescriptor>,
+) {
+    if message.name().ends_with("Entry") && message.fields().len() == 2 {
+        (
+            message.field_by_name("key").filter(|f| f.number() == 1),
+            message.field_by_name("value").filter(|f| f.number() == 2),
+        )
+    } else {
+        (None, None)
+    }
+}
+
+/// The JSON name of a field, in lowerCamelCase, like `protoc` derives it.
+pub fn json_name(name: &str) -> String {
+    let mut result = String::with_capacity(name.len());
+    let mut upper = false;
+    for c in name.chars() {
+        if c == '_' {
+            upper = true;
+        } else if upper {
+            result.extend(c.to_uppercase());
+            upper = false;
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::value::Source as _;
+
+    #[test]
+    fn test_json_name() {
+        assert_eq!(json_name("user_id_v2"), "userIdV2");
+    }
+
+    #[test]
+    fn test_proto3_json() {
+        use serde_protobuf::descriptor::{
+            Descriptors, FieldDescriptor, FieldLabel, InternalFieldType, MessageDescriptor,
+        };
+
+        let mut message = MessageDescriptor::new(".test.Msg");
+        let fields = vec![
+            ("user_id", 1, InternalFieldType::Int64),
+            ("raw_data", 2, InternalFieldType::Bytes),
+            ("count", 3, InternalFieldType::Int32),
+            ("note", 4, InternalFieldType::String),
+        ];
+        for (name, number, field_type) in fields {
+            message.add_field(FieldDescriptor::new(
+                name,
+                number,
+                FieldLabel::Optional,
+                field_type,
+                None,
+            ));
+        }
+        let mut descriptors = Descriptors::new();
+        descriptors.add_message(message);
+        descriptors.resolve_refs();
+
+        let input = [0x08, 0x05, 0x12, 0x02, b'h', b'i', 0x18, 0x07];
+        let mut input = &input[..];
+        let stream = protobuf::CodedInputStream::new(&mut input);
+        let mut reader = proto3_json_source(&descriptors, ".test.Msg", stream).unwrap();
+        assert_eq!(
+            reader.read().unwrap(),
+            Some(value::Value::Map(vec![
+                ("userId".into(), "5".into()),
+                ("rawData".into(), "aGk=".into()),
+                ("count".into(), value::Value::I32(7)),
+            ]))
+        );
+    }
+}
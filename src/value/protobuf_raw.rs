@@ -0,0 +1,260 @@
+//! Protobuf messages decoded without a schema, like `protoc --decode_raw` does.
+//!
+//! The input is a single message, which becomes a map from field numbers to values.  Fields
+//! that occur more than once become a sequence.  The wire format only tells apart varints,
+//! fixed-size numbers and length-delimited data, so the types are guessed: varints and fixed
+//! numbers are unsigned integers, and length-delimited data is a string if it is printable
+//! UTF-8, a nested message if it parses as one, and bytes otherwise.
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::io;
+use std::str;
+
+use crate::error;
+use crate::value;
+
+/// How deeply messages and groups are nested at most.
+const MAX_DEPTH: usize = 128;
+const TOO_DEEP: &str = "messages are nested more than 128 levels deep";
+
+pub struct Source(Option<Vec<u8>>);
+
+#[inline]
+pub fn source<R>(mut r: R) -> error::Result<Source>
+where
+    R: io::Read,
+{
+    let mut input = Vec::new();
+    r.read_to_end(&mut input)?;
+    Ok(Source(Some(input)))
+}
+
+impl value::Source for Source {
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        match self.0.take() {
+            Some(input) => {
+                let mut decoder = Decoder {
+                    input: &input,
+                    position: 0,
+                };
+                match decoder.message(None, 0) {
+                    Ok(message) => Ok(Some(message)),
+                    Err(msg) => Err(error::Error::Format {
+                        msg: format!(
+                            "invalid protobuf wire format at byte {}: {}",
+                            decoder.position, msg
+                        ),
+                    }),
+                }
+            }
+            None => Ok(None),
+        }
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = if self.0.is_some() { 1 } else { 0 };
+        (remaining, Some(remaining))
+    }
+}
+
+impl fmt::Debug for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ProtobufRawSource").finish()
+    }
+}
+
+struct Decoder<'a> {
+    input: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Decoder<'a> {
+    /// Decodes fields until the end of the input, or until the end of the group with the given
+    /// field number.
+    fn message(&mut self, group: Option<u64>, depth: usize) -> Result<value::Value, &'static str> {
+        if depth >= MAX_DEPTH {
+            return Err(TOO_DEEP);
+        }
+        let mut fields = Vec::new();
+        loop {
+            if self.position == self.input.len() {
+                return match group {
+                    Some(_) => Err("input ended inside of a group"),
+                    None => Ok(value::Value::Map(fields)),
+                };
+            }
+            let key = self.varint()?;
+            let (number, wire_type) = (key >> 3, key & 7);
+            if number == 0 {
+                return Err("field number 0");
+            }
+            let v = match wire_type {
+                0 => value::Value::U64(self.varint()?),
+                1 => value::Value::U64(u64::from_le_bytes(self.fixed()?)),
+                2 => {
+                    let len = self.varint()?;
+                    let len = usize::try_from(len).map_err(|_| "length out of range")?;
+                    let bytes = self.take(len)?;
+                    length_delimited(bytes, depth + 1)?
+                }
+                3 => self.message(Some(number), depth + 1)?,
+                4 => {
+                    return match group {
+                        Some(n) if n == number => Ok(value::Value::Map(fields)),
+                        _ => Err("unexpected end of group"),
+                    };
+                }
+                5 => value::Value::U32(u32::from_le_bytes(self.fixed()?)),
+                _ => return Err("unknown wire type"),
+            };
+            add_field(&mut fields, number, v);
+        }
+    }
+
+    fn varint(&mut self) -> Result<u64, &'static str> {
+        let mut result = 0u64;
+        for shift in (0..64).step_by(7) {
+            let b = *self
+                .input
+                .get(self.position)
+                .ok_or("input ended in a varint")?;
+            self.position += 1;
+            result |= u64::from(b & 0x7f) << shift;
+            if b & 0x80 == 0 {
+                return Ok(result);
+            }
+        }
+        Err("varint is too long")
+    }
+
+    fn fixed<const N: usize>(&mut self) -> Result<[u8; N], &'static str> {
+        let mut result = [0; N];
+        result.copy_from_slice(self.take(N)?);
+        Ok(result)
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], &'static str> {
+        let end = self
+            .position
+            .checked_add(len)
+            .filter(|&end| end <= self.input.len())
+            .ok_or("input ended in a field")?;
+        let bytes = &self.input[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+}
+
+/// Guesses what length-delimited data is.  Only fails if it is nested too deeply, since the
+/// data is just bytes if it doesn't parse as anything else.
+fn length_delimited(bytes: &[u8], depth: usize) -> Result<value::Value, &'static str> {
+    if let Ok(s) = str::from_utf8(bytes) {
+        if s.chars().all(|c| !c.is_control() || c.is_whitespace()) {
+            return Ok(value::Value::String(s.to_owned()));
+        }
+    }
+    let mut decoder = Decoder {
+        input: bytes,
+        position: 0,
+    };
+    match decoder.message(None, depth) {
+        Ok(message) if !bytes.is_empty() => Ok(message),
+        Err(msg) if msg == TOO_DEEP => Err(msg),
+        _ => Ok(value::Value::Bytes(bytes.to_vec())),
+    }
+}
+
+/// Adds a field, turning repeated fields into a sequence.
+fn add_field(fields: &mut Vec<(value::Value, value::Value)>, number: u64, v: value::Value) {
+    let key = number.to_string();
+    match fields
+        .iter_mut()
+        .find(|(k, _)| k.as_str() == Some(key.as_str()))
+    {
+        Some((_, value::Value::Sequence(ref mut values))) => values.push(v),
+        Some((_, existing)) => {
+            let first = std::mem::replace(existing, value::Value::Unit);
+            *existing = value::Value::Sequence(vec![first, v]);
+        }
+        None => fields.push((value::Value::String(key), v)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::value::Source as _;
+
+    fn read(input: &[u8]) -> error::Result<Option<value::Value>> {
+        source(input).unwrap().read()
+    }
+
+    #[test]
+    fn test_fields() {
+        // 1: 150, 2: "hi", 3: {1: 1}, 3: {1: 2}, 4: fixed32 1, 5: bytes [0, 255]
+        let input = [
+            0x08, 0x96, 0x01, 0x12, 0x02, b'h', b'i', 0x1a, 0x02, 0x08, 0x01, 0x1a, 0x02, 0x08,
+            0x02, 0x25, 0x01, 0x00, 0x00, 0x00, 0x2a, 0x02, 0x00, 0xff,
+        ];
+        let nested = |n| value::Value::Map(vec![("1".into(), value::Value::U64(n))]);
+        assert_eq!(
+            read(&input).unwrap(),
+            Some(value::Value::Map(vec![
+                ("1".into(), value::Value::U64(150)),
+                ("2".into(), "hi".into()),
+                (
+                    "3".into(),
+                    value::Value::Sequence(vec![nested(1), nested(2)])
+                ),
+                ("4".into(), value::Value::U32(1)),
+                ("5".into(), value::Value::Bytes(vec![0, 0xff])),
+            ]))
+        );
+        let mut reader = source(&input[..]).unwrap();
+        reader.read().unwrap();
+        assert_eq!(reader.read().unwrap(), None);
+
+        assert!(read(&[0x12, 0x05, b'h']).is_err());
+    }
+
+    #[test]
+    fn test_depth() {
+        // Field 1 as a group, nested as deeply as allowed
+        let mut input = vec![0x0b; MAX_DEPTH - 1];
+        input.extend(vec![0x0c; MAX_DEPTH - 1]);
+        assert!(read(&input).is_ok());
+
+        let input = vec![0x0b; 2_000_000];
+        match read(&input) {
+            Err(error::Error::Format { msg }) => assert!(msg.contains("nested"), "{}", msg),
+            other => panic!(
+                "expected an error for deeply nested groups, got {:?}",
+                other
+            ),
+        }
+
+        // Field 1 as a message, in a message, and so on
+        let mut input = Vec::new();
+        for _ in 0..1000 {
+            let mut outer = vec![0x0a];
+            let mut len = input.len();
+            while len >= 0x80 {
+                outer.push(len as u8 | 0x80);
+                len >>= 7;
+            }
+            outer.push(len as u8);
+            outer.extend(input);
+            input = outer;
+        }
+        match read(&input) {
+            Err(error::Error::Format { msg }) => assert!(msg.contains("nested"), "{}", msg),
+            other => panic!(
+                "expected an error for deeply nested messages, got {:?}",
+                other
+            ),
+        }
+    }
+}
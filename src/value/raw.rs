@@ -3,7 +3,7 @@ use crate::value;
 use std::io;
 
 #[derive(Debug)]
-pub struct Source<R>(io::Lines<io::BufReader<R>>)
+pub struct Source<R>(io::Lines<io::BufReader<R>>, u64)
 where
     R: io::Read;
 
@@ -18,7 +18,7 @@ where
     R: io::Read,
 {
     use std::io::BufRead;
-    Source(io::BufReader::new(r).lines())
+    Source(io::BufReader::new(r).lines(), 0)
 }
 
 #[inline]
@@ -36,11 +36,23 @@ where
     #[inline]
     fn read(&mut self) -> error::Result<Option<value::Value>> {
         match self.0.next() {
-            Some(Ok(v)) => Ok(Some(value::Value::String(v))),
+            Some(Ok(v)) => {
+                self.1 += 1;
+                Ok(Some(value::Value::String(v)))
+            }
             Some(Err(e)) => Err(error::Error::from(e)),
             None => Ok(None),
         }
     }
+
+    #[inline]
+    fn position(&self) -> Option<value::Position> {
+        Some(value::Position {
+            record: self.1,
+            byte: None,
+            line: Some(self.1 + 1),
+        })
+    }
 }
 
 impl<W> value::Sink for Sink<W>
@@ -66,8 +78,18 @@ where
                 Ok(())
             }
             x => Err(error::Error::Format {
-                msg: format!("raw can only output strings, bytes and chars, got: {:?}", x),
+                msg: format!(
+                    "raw can only output strings, bytes and chars, got: {}",
+                    x.summary(value::ERROR_SUMMARY_LEN)
+                ),
             }),
         }
     }
+
+    #[inline]
+    fn flush(&mut self) -> error::Result<()> {
+        use std::io::Write;
+        self.0.flush()?;
+        Ok(())
+    }
 }
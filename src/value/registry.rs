@@ -0,0 +1,573 @@
+//! The Windows registry, either exported by `regedit` as a `.reg` file or as a raw hive file like
+//! `NTUSER.DAT` or `SYSTEM`, which start with `regf`.
+//!
+//! Each key becomes a record with its `key` path, its `values` by name and their `types`, like
+//! `REG_DWORD`.  The default value of a key is named `@`.  Strings become strings, multi-strings
+//! sequences of strings, DWORDs and QWORDs integers, and binary and all other types bytes.
+//!
+//! Keys of `.reg` files also have whether they are `deleted`, as in `[-HKEY_CURRENT_USER\Foo]`,
+//! and deleted values are null without a type.  Keys of hives have the time they were last
+//! `modified`, and their paths are relative to the root key of the hive, whose path is empty.
+
+use std::collections;
+use std::convert::TryFrom;
+use std::io;
+use std::str;
+
+use crate::error;
+use crate::value;
+
+/// The size of the base block of a hive, after which the cells start.
+const BASE_BLOCK_LEN: usize = 4096;
+
+/// The largest value data that isn't split into segments of a big data cell.
+const MAX_DATA_LEN: usize = 16344;
+
+/// How deeply keys and subkey lists are nested at most, which guards against cycles.
+const MAX_DEPTH: usize = 512;
+
+/// The names of the value types, by their number.
+const TYPES: &[&str] = &[
+    "REG_NONE",
+    "REG_SZ",
+    "REG_EXPAND_SZ",
+    "REG_BINARY",
+    "REG_DWORD",
+    "REG_DWORD_BIG_ENDIAN",
+    "REG_LINK",
+    "REG_MULTI_SZ",
+    "REG_RESOURCE_LIST",
+    "REG_FULL_RESOURCE_DESCRIPTOR",
+    "REG_RESOURCE_REQUIREMENTS_LIST",
+    "REG_QWORD",
+];
+
+const REG_SZ: u32 = 1;
+const REG_EXPAND_SZ: u32 = 2;
+const REG_BINARY: u32 = 3;
+const REG_DWORD: u32 = 4;
+const REG_DWORD_BIG_ENDIAN: u32 = 5;
+const REG_LINK: u32 = 6;
+const REG_MULTI_SZ: u32 = 7;
+const REG_QWORD: u32 = 11;
+
+/// Seconds from the start of 1601, where Windows file times start, to the Unix epoch.
+const FILE_TIME_EPOCH: i64 = 11_644_473_600;
+
+#[derive(Debug)]
+pub struct Source(collections::VecDeque<value::Value>);
+
+/// Creates a source for the keys of a `.reg` file or hive, which is read in full from the input.
+pub fn source<R>(mut r: R) -> error::Result<Source>
+where
+    R: io::Read,
+{
+    let mut input = Vec::new();
+    r.read_to_end(&mut input)?;
+    let keys = if input.starts_with(b"regf") {
+        Hive::new(&input)?.keys()?
+    } else {
+        reg_file(&decode(&input))?
+    };
+    Ok(Source(keys.into()))
+}
+
+impl value::Source for Source {
+    #[inline]
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        Ok(self.0.pop_front())
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.0.len(), Some(self.0.len()))
+    }
+}
+
+/// The values of a key and their types, in the order they were read.
+#[derive(Default)]
+struct Values {
+    values: Vec<(value::Value, value::Value)>,
+    types: Vec<(value::Value, value::Value)>,
+}
+
+impl Values {
+    fn push(&mut self, name: String, kind: u32, data: value::Value) {
+        self.types
+            .push((name.as_str().into(), type_name(kind).into()));
+        self.values.push((name.into(), data));
+    }
+
+    fn into_record(self, key: String, extra: (&str, value::Value)) -> value::Value {
+        value::Value::Map(vec![
+            ("key".into(), key.into()),
+            (extra.0.into(), extra.1),
+            ("values".into(), value::Value::Map(self.values)),
+            ("types".into(), value::Value::Map(self.types)),
+        ])
+    }
+}
+
+/// Decodes the text of a `.reg` file, which is UTF-16 with a byte order mark when written by
+/// `regedit` 5, and in the ANSI code page for `REGEDIT4`.
+fn decode(input: &[u8]) -> String {
+    if let Some((encoding, len)) = encoding_rs::Encoding::for_bom(input) {
+        encoding
+            .decode_without_bom_handling(&input[len..])
+            .0
+            .into_owned()
+    } else {
+        match str::from_utf8(input) {
+            Ok(text) => text.to_owned(),
+            Err(_) => encoding_rs::WINDOWS_1252.decode(input).0.into_owned(),
+        }
+    }
+}
+
+fn reg_file(text: &str) -> error::Result<Vec<value::Value>> {
+    let mut lines = text.lines().enumerate();
+    // The header tells whether strings in hex data are UTF-16 or ANSI
+    let wide = loop {
+        match lines.next() {
+            Some((_, line)) if line.trim().is_empty() => (),
+            Some((_, line)) if line.trim() == "Windows Registry Editor Version 5.00" => break true,
+            Some((_, line)) if line.trim() == "REGEDIT4" => break false,
+            _ => return Err(format_error("missing the header of a .reg file")),
+        }
+    };
+
+    let mut keys = Vec::new();
+    let mut key: Option<(String, bool, Values)> = None;
+    while let Some((number, line)) = lines.next() {
+        let mut line = line.trim().to_owned();
+        // Long hex data continues on the next lines
+        while line.ends_with('\\') {
+            match lines.next() {
+                Some((_, next)) => {
+                    line.pop();
+                    line.push_str(next.trim());
+                }
+                None => break,
+            }
+        }
+        let line_error = |msg: &str| format_error(&format!("{} on line {}", msg, number + 1));
+
+        if line.is_empty() || line.starts_with(';') {
+            continue;
+        }
+        if let Some(path) = line.strip_prefix('[') {
+            let path = path
+                .strip_suffix(']')
+                .ok_or_else(|| line_error("unterminated key"))?;
+            if let Some((path, deleted, values)) = key.take() {
+                keys.push(values.into_record(path, ("deleted", value::Value::Bool(deleted))));
+            }
+            key = Some(match path.strip_prefix('-') {
+                Some(path) => (path.to_owned(), true, Values::default()),
+                None => (path.to_owned(), false, Values::default()),
+            });
+            continue;
+        }
+        let values = match key {
+            Some((_, _, ref mut values)) => values,
+            None => return Err(line_error("value outside of a key")),
+        };
+
+        let (name, rest) = if let Some(rest) = line.strip_prefix('@') {
+            ("@".to_owned(), rest)
+        } else {
+            quoted(&line).ok_or_else(|| line_error("invalid value name"))?
+        };
+        let data = rest
+            .trim_start()
+            .strip_prefix('=')
+            .ok_or_else(|| line_error("missing = after the value name"))?
+            .trim();
+        if data == "-" {
+            values.values.push((name.into(), value::Value::Unit));
+        } else if data.starts_with('"') {
+            match quoted(data) {
+                Some((s, rest)) if rest.trim().is_empty() || rest.trim().starts_with(';') => {
+                    values.push(name, REG_SZ, s.into())
+                }
+                _ => return Err(line_error("invalid string")),
+            }
+        } else if let Some(digits) = data.strip_prefix("dword:") {
+            let dword = u32::from_str_radix(digits, 16)
+                .map_err(|_| line_error(&format!("invalid DWORD {:?}", digits)))?;
+            values.push(name, REG_DWORD, value::Value::U32(dword));
+        } else if let Some(bytes) = data.strip_prefix("hex:") {
+            let bytes = hex(bytes).ok_or_else(|| line_error("invalid hex data"))?;
+            values.push(name, REG_BINARY, value::Value::Bytes(bytes));
+        } else if let Some(rest) = data.strip_prefix("hex(") {
+            let (kind, bytes) = rest
+                .split_once("):")
+                .and_then(|(kind, bytes)| Some((u32::from_str_radix(kind, 16).ok()?, bytes)))
+                .ok_or_else(|| line_error("invalid hex type"))?;
+            let bytes = hex(bytes).ok_or_else(|| line_error("invalid hex data"))?;
+            values.push(name, kind, data_value(kind, &bytes, wide));
+        } else {
+            return Err(line_error(&format!("invalid data {:?}", data)));
+        }
+    }
+    if let Some((path, deleted, values)) = key {
+        keys.push(values.into_record(path, ("deleted", value::Value::Bool(deleted))));
+    }
+    Ok(keys)
+}
+
+/// Reads a string in double quotes, where backslashes escape quotes and backslashes, and returns
+/// it with the rest of the line.
+fn quoted(s: &str) -> Option<(String, &str)> {
+    let mut chars = s.strip_prefix('"')?.char_indices();
+    let mut string = String::new();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Some((string, &s[i + 2..])),
+            '\\' => match chars.next()? {
+                (_, c @ ('"' | '\\')) => string.push(c),
+                (_, c) => {
+                    string.push('\\');
+                    string.push(c);
+                }
+            },
+            c => string.push(c),
+        }
+    }
+    None
+}
+
+/// Reads hex data like `01,ff,`.
+fn hex(s: &str) -> Option<Vec<u8>> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|b| !b.is_empty())
+        .map(|b| u8::from_str_radix(b, 16).ok())
+        .collect()
+}
+
+/// Converts the data of a value to its type, where strings are UTF-16 if `wide`.
+fn data_value(kind: u32, data: &[u8], wide: bool) -> value::Value {
+    let string = |data: &[u8]| {
+        if wide {
+            utf16(data)
+        } else {
+            encoding_rs::WINDOWS_1252.decode(data).0.into_owned()
+        }
+    };
+    match kind {
+        REG_SZ | REG_EXPAND_SZ | REG_LINK => string(data).trim_end_matches('\0').to_owned().into(),
+        REG_MULTI_SZ => value::Value::Sequence(
+            string(data)
+                .split('\0')
+                .filter(|s| !s.is_empty())
+                .map(Into::into)
+                .collect(),
+        ),
+        REG_DWORD => match <[u8; 4]>::try_from(data) {
+            Ok(data) => value::Value::U32(u32::from_le_bytes(data)),
+            Err(_) => value::Value::Bytes(data.to_vec()),
+        },
+        REG_DWORD_BIG_ENDIAN => match <[u8; 4]>::try_from(data) {
+            Ok(data) => value::Value::U32(u32::from_be_bytes(data)),
+            Err(_) => value::Value::Bytes(data.to_vec()),
+        },
+        REG_QWORD => match <[u8; 8]>::try_from(data) {
+            Ok(data) => value::Value::U64(u64::from_le_bytes(data)),
+            Err(_) => value::Value::Bytes(data.to_vec()),
+        },
+        _ => value::Value::Bytes(data.to_vec()),
+    }
+}
+
+fn type_name(kind: u32) -> String {
+    match TYPES.get(kind as usize) {
+        Some(name) => (*name).to_owned(),
+        None => format!("0x{:x}", kind),
+    }
+}
+
+/// The cells of a hive, which are referred to by their offset after the base block.
+struct Hive<'a> {
+    cells: &'a [u8],
+    root: u32,
+}
+
+struct Key {
+    name: String,
+    modified: i64,
+    subkeys: Vec<u32>,
+    values: Values,
+}
+
+impl<'a> Hive<'a> {
+    fn new(input: &'a [u8]) -> error::Result<Self> {
+        if input.len() < BASE_BLOCK_LEN {
+            return Err(hive_error("the base block is truncated".to_owned()));
+        }
+        Ok(Hive {
+            cells: &input[BASE_BLOCK_LEN..],
+            root: u32_at(input, 0x24),
+        })
+    }
+
+    /// Reads all keys, each before its subkeys.
+    fn keys(&self) -> error::Result<Vec<value::Value>> {
+        let mut keys = Vec::new();
+        let mut seen = collections::HashSet::new();
+        let mut pending = vec![(self.root, None::<String>, 0)];
+        while let Some((offset, parent, depth)) = pending.pop() {
+            if depth > MAX_DEPTH || !seen.insert(offset) {
+                return Err(hive_error(format!(
+                    "key at cell 0x{:x} is in a cycle",
+                    offset
+                )));
+            }
+            let key = self.key(offset)?;
+            let path = match parent {
+                None => String::new(),
+                Some(parent) if parent.is_empty() => key.name,
+                Some(parent) => format!("{}\\{}", parent, key.name),
+            };
+            for &subkey in key.subkeys.iter().rev() {
+                pending.push((subkey, Some(path.clone()), depth + 1));
+            }
+            let modified = value::x509::format_time(key.modified).into();
+            keys.push(key.values.into_record(path, ("modified", modified)));
+        }
+        Ok(keys)
+    }
+
+    /// The data of the cell at an offset, without its size.
+    fn cell(&self, offset: u32) -> error::Result<&'a [u8]> {
+        let start = offset as usize;
+        let size = self
+            .cells
+            .get(start..start + 4)
+            .map(|size| i32::from_le_bytes([size[0], size[1], size[2], size[3]]).unsigned_abs());
+        match size {
+            Some(size) if size >= 4 && start + size as usize <= self.cells.len() => {
+                Ok(&self.cells[start + 4..start + size as usize])
+            }
+            _ => Err(hive_error(format!("invalid cell at 0x{:x}", offset))),
+        }
+    }
+
+    fn key(&self, offset: u32) -> error::Result<Key> {
+        let cell = self.cell(offset)?;
+        if cell.len() < 0x4c || &cell[..2] != b"nk" {
+            return Err(hive_error(format!("invalid key at cell 0x{:x}", offset)));
+        }
+        let flags = u16_at(cell, 0x2);
+        let name = cell
+            .get(0x4c..0x4c + usize::from(u16_at(cell, 0x48)))
+            .ok_or_else(|| hive_error(format!("invalid key name at cell 0x{:x}", offset)))?;
+        // Names that are ASCII are stored in single bytes
+        let name = if flags & 0x20 != 0 {
+            encoding_rs::WINDOWS_1252.decode(name).0.into_owned()
+        } else {
+            utf16(name)
+        };
+        let ticks = i64::try_from(u64_at(cell, 0x4)).unwrap_or(i64::MAX);
+        let modified = ticks / 10_000_000 - FILE_TIME_EPOCH;
+
+        let mut subkeys = Vec::new();
+        if u32_at(cell, 0x14) > 0 {
+            self.subkey_list(u32_at(cell, 0x1c), &mut subkeys, 0)?;
+        }
+        let mut values = Values::default();
+        let count = u32_at(cell, 0x24) as usize;
+        if count > 0 {
+            let list = self.cell(u32_at(cell, 0x28))?;
+            if list.len() < count * 4 {
+                return Err(hive_error(format!(
+                    "invalid value list of cell 0x{:x}",
+                    offset
+                )));
+            }
+            for i in 0..count {
+                let (name, kind, data) = self.value(u32_at(list, i * 4))?;
+                values.push(name, kind, data);
+            }
+        }
+        Ok(Key {
+            name,
+            modified,
+            subkeys,
+            values,
+        })
+    }
+
+    /// Collects the offsets of subkeys from a list, which can be a list of lists.
+    fn subkey_list(&self, offset: u32, subkeys: &mut Vec<u32>, depth: usize) -> error::Result<()> {
+        let cell = self.cell(offset)?;
+        if cell.len() < 4 || depth > MAX_DEPTH {
+            return Err(hive_error(format!("invalid subkey list at 0x{:x}", offset)));
+        }
+        let count = usize::from(u16_at(cell, 2));
+        let stride = match &cell[..2] {
+            b"lf" | b"lh" => 8,
+            b"li" | b"ri" => 4,
+            _ => return Err(hive_error(format!("invalid subkey list at 0x{:x}", offset))),
+        };
+        if cell.len() < 4 + count * stride {
+            return Err(hive_error(format!(
+                "truncated subkey list at 0x{:x}",
+                offset
+            )));
+        }
+        for i in 0..count {
+            let entry = u32_at(cell, 4 + i * stride);
+            if &cell[..2] == b"ri" {
+                self.subkey_list(entry, subkeys, depth + 1)?;
+            } else {
+                subkeys.push(entry);
+            }
+        }
+        Ok(())
+    }
+
+    fn value(&self, offset: u32) -> error::Result<(String, u32, value::Value)> {
+        let cell = self.cell(offset)?;
+        if cell.len() < 0x14 || &cell[..2] != b"vk" {
+            return Err(hive_error(format!("invalid value at cell 0x{:x}", offset)));
+        }
+        let name = cell
+            .get(0x14..0x14 + usize::from(u16_at(cell, 0x2)))
+            .ok_or_else(|| hive_error(format!("invalid value name at cell 0x{:x}", offset)))?;
+        let name = match name {
+            [] => "@".to_owned(),
+            name if u16_at(cell, 0x10) & 1 != 0 => {
+                encoding_rs::WINDOWS_1252.decode(name).0.into_owned()
+            }
+            name => utf16(name),
+        };
+        let kind = u32_at(cell, 0xc);
+
+        let size = u32_at(cell, 0x4);
+        let data_error = || hive_error(format!("invalid data of the value at cell 0x{:x}", offset));
+        let data = if size & 0x8000_0000 != 0 {
+            // Data of up to 4 bytes is stored instead of its offset
+            let len = (size & 0x7fff_ffff) as usize;
+            cell.get(0x8..0x8 + len).ok_or_else(data_error)?.to_vec()
+        } else {
+            let len = size as usize;
+            let data = self.cell(u32_at(cell, 0x8))?;
+            if len > MAX_DATA_LEN && data.starts_with(b"db") && data.len() >= 8 {
+                let segments = self.cell(u32_at(data, 0x4))?;
+                let count = usize::from(u16_at(data, 0x2));
+                if segments.len() < count * 4 {
+                    return Err(data_error());
+                }
+                let mut joined = Vec::with_capacity(len);
+                for i in 0..count {
+                    let segment = self.cell(u32_at(segments, i * 4))?;
+                    joined.extend_from_slice(&segment[..segment.len().min(MAX_DATA_LEN)]);
+                }
+                joined.get(..len).ok_or_else(data_error)?.to_vec()
+            } else {
+                data.get(..len).ok_or_else(data_error)?.to_vec()
+            }
+        };
+        Ok((name, kind, data_value(kind, &data, true)))
+    }
+}
+
+fn utf16(bytes: &[u8]) -> String {
+    let units: Vec<u16> = bytes
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .collect();
+    String::from_utf16_lossy(&units)
+}
+
+fn u16_at(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([bytes[at], bytes[at + 1]])
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+
+fn u64_at(bytes: &[u8], at: usize) -> u64 {
+    u64::from(u32_at(bytes, at)) | u64::from(u32_at(bytes, at + 4)) << 32
+}
+
+fn hive_error(msg: String) -> error::Error {
+    format_error(&format!("invalid hive: {}", msg))
+}
+
+fn format_error(msg: &str) -> error::Error {
+    error::Error::Format {
+        msg: msg.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::value::Source as _;
+
+    fn read(input: &[u8]) -> error::Result<Vec<value::Value>> {
+        let mut reader = source(input)?;
+        let mut records = Vec::new();
+        while let Some(record) = reader.read()? {
+            records.push(record);
+        }
+        Ok(records)
+    }
+
+    #[test]
+    fn test_reg() {
+        let reg = "REGEDIT4\r\n\r\n[HKEY_CURRENT_USER\\Software\\Rq]\r\n@=\"C:\\\\rq \\\"x\\\"\"\r\n\
+                   \"Count\"=dword:0000000a\r\n\"Data\"=hex:01,\\\r\n  02\r\n\
+                   \"Path\"=hex(2):25,41,25,00\r\n\"Old\"=-\r\n\r\n[-HKEY_CURRENT_USER\\Software\\Gone]\r\n";
+        let records = read(reg.as_bytes()).unwrap();
+        assert_eq!(
+            records.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            vec![
+                r#"{"key": "HKEY_CURRENT_USER\\Software\\Rq", "deleted": false, "values": {"@": "C:\\rq \"x\"", "Count": 10, "Data": 0x0102, "Path": "%A%", "Old": null}, "types": {"@": "REG_SZ", "Count": "REG_DWORD", "Data": "REG_BINARY", "Path": "REG_EXPAND_SZ"}}"#,
+                r#"{"key": "HKEY_CURRENT_USER\\Software\\Gone", "deleted": true, "values": {}, "types": {}}"#,
+            ]
+        );
+        assert!(read(b"[HKEY_CURRENT_USER]").is_err());
+    }
+
+    #[test]
+    fn test_hive() {
+        // A hive whose root key has a DWORD value and a subkey, with the cells after a bin header
+        let key = |name: &[u8], subkeys: u8, values: u8| {
+            let mut cell = vec![0; 0x50];
+            cell[..8].copy_from_slice(b"\xa8\xff\xff\xffnk\x20\0");
+            cell[0x18] = subkeys;
+            cell[0x20] = 0xd0;
+            cell[0x28] = values;
+            cell[0x2c] = 0xe0;
+            cell[0x4c] = name.len() as u8;
+            cell.extend_from_slice(&[name, &[0; 8][name.len()..]].concat());
+            cell
+        };
+        let mut hive = vec![0; 4096];
+        hive[..4].copy_from_slice(b"regf");
+        hive[0x24] = 0x20;
+        hive.extend_from_slice(b"hbin");
+        hive.extend_from_slice(&[0; 28]);
+        hive.extend(key(b"ROOT", 1, 1));
+        hive.extend(key(b"Child", 0, 0));
+        hive.extend_from_slice(b"\xf0\xff\xff\xffli\x01\0\x78\0\0\0\0\0\0\0");
+        hive.extend_from_slice(b"\xf8\xff\xff\xff\xe8\0\0\0");
+        hive.extend_from_slice(
+            b"\xe0\xff\xff\xffvk\x01\0\x04\0\0\x80\x07\0\0\0\x04\0\0\0\x01\0\0\0N",
+        );
+        hive.extend_from_slice(&[0; 7]);
+        let records = read(&hive).unwrap();
+        assert_eq!(
+            records.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            vec![
+                r#"{"key": "", "modified": "1601-01-01T00:00:00Z", "values": {"N": 7}, "types": {"N": "REG_DWORD"}}"#,
+                r#"{"key": "Child", "modified": "1601-01-01T00:00:00Z", "values": {}, "types": {}}"#,
+            ]
+        );
+        assert!(read(&hive[..4200]).is_err());
+    }
+}
@@ -16,7 +16,11 @@ pub struct Source<R>(
 where
     R: io::Read;
 
-pub struct Sink<W>(serde_smile::ser::Serializer<W>)
+/// A Smile sink.  Each record is serialized into a buffer first, so that a record that fails to
+/// serialize leaves nothing behind in the output.  The serializer remembers strings that it wrote
+/// before, and may have remembered some from a failed record, so no further records can be
+/// written after a failure.
+pub struct Sink<W>(W, serde_smile::ser::Serializer<Vec<u8>>, bool)
 where
     W: io::Write;
 
@@ -37,9 +41,11 @@ where
     W: io::Write,
 {
     Ok(Sink(
+        w,
         serde_smile::ser::Serializer::builder()
             .shared_strings(true)
-            .build(w),
+            .build(Vec::new()),
+        false,
     ))
 }
 
@@ -59,7 +65,24 @@ where
 {
     #[inline]
     fn write(&mut self, v: value::Value) -> error::Result<()> {
-        Ok(serde::Serialize::serialize(&v, &mut self.0)?)
+        if self.2 {
+            return Err(error::Error::Message(
+                "can't write more Smile records after a record failed".to_owned(),
+            ));
+        }
+        self.1.get_mut().clear();
+        if let Err(e) = serde::Serialize::serialize(&v, &mut self.1) {
+            self.2 = true;
+            return Err(e.into());
+        }
+        self.0.write_all(self.1.get_ref())?;
+        Ok(())
+    }
+
+    #[inline]
+    fn flush(&mut self) -> error::Result<()> {
+        self.0.flush()?;
+        Ok(())
     }
 }
 
@@ -0,0 +1,346 @@
+//! SQLite databases.
+//!
+//! The source reads the rows of a query, or of all tables, as maps from the column names to the
+//! values.  The sink inserts maps into a table of a database file, creating the table with
+//! columns for the fields of the first record if it doesn't exist yet.
+
+use std::collections;
+use std::convert::TryFrom;
+use std::fmt;
+use std::io;
+use std::path;
+
+use crate::error;
+use crate::value;
+
+pub struct Source(collections::VecDeque<value::Value>);
+
+pub struct Sink {
+    connection: rusqlite::Connection,
+    table: String,
+    /// The columns of the table, once it has been created or found.
+    columns: Option<Vec<String>>,
+    /// Fields of records that the table has no column for, which have been warned about.
+    dropped: collections::BTreeSet<String>,
+}
+
+/// Creates a source for the rows of a database, read in full from the input.  The query can be
+/// SQL or the name of a table; without one, all tables are read one after the other.
+pub fn source<R>(mut r: R, query: Option<&str>) -> error::Result<Source>
+where
+    R: io::Read,
+{
+    let mut input = Vec::new();
+    r.read_to_end(&mut input)?;
+    let mut connection = rusqlite::Connection::open_in_memory()?;
+    connection.deserialize_read_exact(rusqlite::MAIN_DB, &input[..], input.len(), true)?;
+
+    let queries = match query {
+        Some(query) if is_identifier(query) => vec![format!("SELECT * FROM {}", quote(query))],
+        Some(query) => vec![query.to_owned()],
+        None => {
+            let mut statement = connection.prepare(
+                "SELECT name FROM sqlite_schema WHERE type = 'table' \
+                     AND name NOT LIKE 'sqlite_%' ORDER BY rowid",
+            )?;
+            let tables = statement
+                .query_map([], |row| row.get::<_, String>(0))?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            tables
+                .iter()
+                .map(|table| format!("SELECT * FROM {}", quote(table)))
+                .collect()
+        }
+    };
+
+    let mut records = collections::VecDeque::new();
+    for query in queries {
+        debug!("Running SQLite query {:?}", query);
+        let mut statement = connection.prepare(&query)?;
+        let names: Vec<value::Value> = statement
+            .column_names()
+            .into_iter()
+            .map(|name| value::Value::String(name.to_owned()))
+            .collect();
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            let entries = names
+                .iter()
+                .enumerate()
+                .map(|(i, name)| Ok((name.clone(), value_from_sql(row.get_ref(i)?))))
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            records.push_back(value::Value::Map(entries));
+        }
+    }
+    Ok(Source(records))
+}
+
+/// Creates a sink that inserts records into a table of the database file, which is created if
+/// it doesn't exist.  All records are inserted in one transaction, which `finish` commits.
+pub fn sink(path: &path::Path, table: &str) -> error::Result<Sink> {
+    let connection = rusqlite::Connection::open(path)?;
+    connection.execute_batch("BEGIN")?;
+    Ok(Sink {
+        connection,
+        table: table.to_owned(),
+        columns: None,
+        dropped: collections::BTreeSet::new(),
+    })
+}
+
+impl value::Source for Source {
+    #[inline]
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        Ok(self.0.pop_front())
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.0.len(), Some(self.0.len()))
+    }
+}
+
+impl value::Sink for Sink {
+    fn write(&mut self, v: value::Value) -> error::Result<()> {
+        let entries = match v {
+            value::Value::Map(entries) => entries,
+            v => {
+                return Err(error::Error::Format {
+                    msg: format!(
+                        "SQLite can only output maps, got: {}",
+                        v.summary(value::ERROR_SUMMARY_LEN)
+                    ),
+                })
+            }
+        };
+        let fields: Vec<(String, value::Value)> = entries
+            .into_iter()
+            .map(|(k, v)| match k {
+                value::Value::String(k) => (k, v),
+                k => (k.to_string(), v),
+            })
+            .collect();
+
+        let columns = match self.columns {
+            Some(ref columns) => columns,
+            None => {
+                let columns = self.create_table(&fields)?;
+                self.columns.get_or_insert(columns)
+            }
+        };
+
+        let mut values = vec![rusqlite::types::Value::Null; columns.len()];
+        for (name, v) in fields {
+            match columns.iter().position(|column| *column == name) {
+                Some(i) => values[i] = value_to_sql(v)?,
+                None => {
+                    if self.dropped.insert(name.clone()) {
+                        warn!(
+                            "SQLite table {} has no column {:?}; leaving it out",
+                            self.table, name
+                        );
+                    }
+                }
+            }
+        }
+        let sql = format!(
+            "INSERT INTO {} ({}) VALUES ({})",
+            quote(&self.table),
+            columns
+                .iter()
+                .map(|c| quote(c))
+                .collect::<Vec<_>>()
+                .join(", "),
+            vec!["?"; columns.len()].join(", ")
+        );
+        self.connection
+            .prepare_cached(&sql)
+            .and_then(|mut statement| statement.execute(rusqlite::params_from_iter(values)))?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> error::Result<()> {
+        if self.connection.is_autocommit() {
+            return Ok(());
+        }
+        self.connection.execute_batch("COMMIT")?;
+        Ok(())
+    }
+}
+
+impl Sink {
+    /// Finds the columns of the table, or creates it with columns for the fields.
+    fn create_table(&self, fields: &[(String, value::Value)]) -> error::Result<Vec<String>> {
+        let mut statement = self
+            .connection
+            .prepare(&format!("PRAGMA table_info({})", quote(&self.table)))?;
+        let existing = statement
+            .query_map([], |row| row.get::<_, String>(1))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        if !existing.is_empty() {
+            debug!("Appending to SQLite table {}", self.table);
+            return Ok(existing);
+        }
+
+        let definitions: Vec<String> = fields
+            .iter()
+            .map(|(name, v)| match column_type(v) {
+                Some(t) => format!("{} {}", quote(name), t),
+                None => quote(name),
+            })
+            .collect();
+        let sql = format!(
+            "CREATE TABLE {} ({})",
+            quote(&self.table),
+            definitions.join(", ")
+        );
+        debug!("Creating SQLite table: {}", sql);
+        self.connection.execute(&sql, [])?;
+        Ok(fields.iter().map(|(name, _)| name.clone()).collect())
+    }
+}
+
+/// The type of a column inferred from a value, if the value has one.
+fn column_type(v: &value::Value) -> Option<&'static str> {
+    match *v {
+        value::Value::Unit => None,
+        value::Value::Bool(_)
+        | value::Value::I8(_)
+        | value::Value::I16(_)
+        | value::Value::I32(_)
+        | value::Value::I64(_)
+        | value::Value::U8(_)
+        | value::Value::U16(_)
+        | value::Value::U32(_)
+        | value::Value::U64(_) => Some("INTEGER"),
+        value::Value::F32(_) | value::Value::F64(_) => Some("REAL"),
+        value::Value::Bytes(_) => Some("BLOB"),
+        value::Value::Char(_)
+        | value::Value::String(_)
+        | value::Value::Sequence(_)
+        | value::Value::Map(_) => Some("TEXT"),
+    }
+}
+
+fn value_from_sql(v: rusqlite::types::ValueRef) -> value::Value {
+    match v {
+        rusqlite::types::ValueRef::Null => value::Value::Unit,
+        rusqlite::types::ValueRef::Integer(v) => value::Value::I64(v),
+        rusqlite::types::ValueRef::Real(v) => value::Value::from_f64(v),
+        rusqlite::types::ValueRef::Text(v) => {
+            value::Value::String(String::from_utf8_lossy(v).into_owned())
+        }
+        rusqlite::types::ValueRef::Blob(v) => value::Value::Bytes(v.to_vec()),
+    }
+}
+
+/// Converts a value for a column.  Nested sequences and maps are stored as JSON text.
+fn value_to_sql(v: value::Value) -> error::Result<rusqlite::types::Value> {
+    use rusqlite::types::Value as Sql;
+
+    Ok(match v {
+        value::Value::Unit => Sql::Null,
+        value::Value::Bool(v) => Sql::Integer(v.into()),
+        value::Value::I8(v) => Sql::Integer(v.into()),
+        value::Value::I16(v) => Sql::Integer(v.into()),
+        value::Value::I32(v) => Sql::Integer(v.into()),
+        value::Value::I64(v) => Sql::Integer(v),
+        value::Value::U8(v) => Sql::Integer(v.into()),
+        value::Value::U16(v) => Sql::Integer(v.into()),
+        value::Value::U32(v) => Sql::Integer(v.into()),
+        value::Value::U64(v) => match i64::try_from(v) {
+            Ok(v) => Sql::Integer(v),
+            Err(_) => Sql::Real(v as f64),
+        },
+        value::Value::F32(v) => Sql::Real(v.0.into()),
+        value::Value::F64(v) => Sql::Real(v.0),
+        value::Value::Char(v) => Sql::Text(v.to_string()),
+        value::Value::String(v) => Sql::Text(v),
+        value::Value::Bytes(v) => Sql::Blob(v),
+        v @ value::Value::Sequence(_) | v @ value::Value::Map(_) => {
+            Sql::Text(serde_json::to_string(&v)?)
+        }
+    })
+}
+
+fn is_identifier(s: &str) -> bool {
+    !s.is_empty() && s.chars().all(|c| c.is_alphanumeric() || c == '_')
+}
+
+fn quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+impl fmt::Debug for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SqliteSource")
+            .field("rows", &self.0.len())
+            .finish()
+    }
+}
+
+impl fmt::Debug for Sink {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SqliteSink")
+            .field("table", &self.table)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    use crate::value::Sink as _;
+    use crate::value::Source as _;
+
+    fn write(path: &path::Path, json: &str) -> error::Result<()> {
+        let mut writer = sink(path, "people")?;
+        let mut reader = value::json::source(json.as_bytes());
+        while let Some(record) = reader.read()? {
+            writer.write(record)?;
+        }
+        writer.finish()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let dir = env::temp_dir().join(format!("rq-sqlite-module-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let output = dir.join("people.db");
+        write(
+            &output,
+            r#"{"name": "Jo", "age": 34, "tags": ["a"]} {"name": "Al", "height": 1.8}"#,
+        )
+        .unwrap();
+        // Appends to the existing table
+        write(&output, r#"{"age": 2.5, "name": "Ed"}"#).unwrap();
+
+        let database = fs::read(&output).unwrap();
+        assert!(database.starts_with(b"SQLite format 3\0"));
+        let mut reader = source(&database[..], None).unwrap();
+        assert_eq!(
+            reader.read().unwrap().map(|r| r.to_string()),
+            Some(r#"{"name": "Jo", "age": 34, "tags": "[\"a\"]"}"#.to_owned())
+        );
+        assert_eq!(
+            reader.read().unwrap().map(|r| r.to_string()),
+            Some(r#"{"name": "Al", "age": null, "tags": null}"#.to_owned())
+        );
+        assert_eq!(
+            reader.read().unwrap().map(|r| r.to_string()),
+            Some(r#"{"name": "Ed", "age": 2.5, "tags": null}"#.to_owned())
+        );
+        assert_eq!(reader.read().unwrap(), None);
+
+        let mut reader = source(&database[..], Some("SELECT count(*) AS n FROM people")).unwrap();
+        assert_eq!(
+            reader.read().unwrap().map(|r| r.to_string()),
+            Some(r#"{"n": 3}"#.to_owned())
+        );
+        assert!(source(&b"not a database"[..], None).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
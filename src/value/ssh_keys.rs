@@ -0,0 +1,373 @@
+//! OpenSSH `known_hosts` and `authorized_keys` files.
+//!
+//! Each key becomes a record with its `type`, the `key` in base64, the `fingerprint` that
+//! `ssh-keygen -l` shows, the `bits` of the key, and the `comment`.  Keys of `known_hosts` also
+//! have the `hosts` they are for, which are patterns or hashes as they are written, and the
+//! `marker` (`cert-authority` or `revoked`, or null).  Keys of `authorized_keys` also have their
+//! `options`, as a map with the values of options like `command`, and `true` for options like
+//! `no-pty`.
+
+use std::convert::TryInto;
+use std::io;
+
+use base64::Engine;
+use sha2::Digest;
+
+use crate::error;
+use crate::value;
+
+#[derive(Clone, Copy, Debug)]
+enum Kind {
+    KnownHosts,
+    AuthorizedKeys,
+}
+
+#[derive(Debug)]
+pub struct Source<R> {
+    lines: io::Lines<R>,
+    kind: Kind,
+    line: u64,
+    records: u64,
+}
+
+/// Creates a source for the keys of a `known_hosts` file.
+pub fn known_hosts<R>(r: R) -> Source<R>
+where
+    R: io::BufRead,
+{
+    source(r, Kind::KnownHosts)
+}
+
+/// Creates a source for the keys of an `authorized_keys` file.
+pub fn authorized_keys<R>(r: R) -> Source<R>
+where
+    R: io::BufRead,
+{
+    source(r, Kind::AuthorizedKeys)
+}
+
+fn source<R>(r: R, kind: Kind) -> Source<R>
+where
+    R: io::BufRead,
+{
+    Source {
+        lines: r.lines(),
+        kind,
+        line: 0,
+        records: 0,
+    }
+}
+
+impl<R> value::Source for Source<R>
+where
+    R: io::BufRead,
+{
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        while let Some(line) = self.lines.next() {
+            let line = line?;
+            self.line += 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let record = match self.kind {
+                Kind::KnownHosts => self.known_host(line)?,
+                Kind::AuthorizedKeys => self.authorized_key(line)?,
+            };
+            self.records += 1;
+            return Ok(Some(record));
+        }
+        Ok(None)
+    }
+
+    #[inline]
+    fn position(&self) -> Option<value::Position> {
+        Some(value::Position {
+            record: self.records,
+            byte: None,
+            line: Some(self.line),
+        })
+    }
+}
+
+impl<R> Source<R> {
+    /// Parses a line like `@revoked host1,[host2]:2222 ssh-ed25519 AAAA... comment`.
+    fn known_host(&self, line: &str) -> error::Result<value::Value> {
+        let (marker, line) = match line.strip_prefix('@') {
+            Some(rest) => {
+                let (marker, rest) = split_word(rest);
+                (value::Value::from(marker), rest)
+            }
+            None => (value::Value::Unit, line),
+        };
+        let (hosts, rest) = split_word(line);
+        let hosts = hosts.split(',').map(value::Value::from).collect();
+
+        let mut entries = vec![
+            ("marker".into(), marker),
+            ("hosts".into(), value::Value::Sequence(hosts)),
+        ];
+        entries.extend(self.key(rest)?);
+        Ok(value::Value::Map(entries))
+    }
+
+    /// Parses a line like `no-pty,command="uptime" ssh-ed25519 AAAA... comment`, where the
+    /// options are optional.
+    fn authorized_key(&self, line: &str) -> error::Result<value::Value> {
+        let (options, rest) = match self.key(line) {
+            Ok(key) => (Vec::new(), Ok(key)),
+            Err(_) => {
+                let (options, rest) = self.options(line)?;
+                (options, self.key(rest))
+            }
+        };
+        let mut entries = vec![("options".into(), value::Value::Map(options))];
+        entries.extend(rest?);
+        Ok(value::Value::Map(entries))
+    }
+
+    /// Parses the options before the key, which are separated by commas and can have values
+    /// in double quotes, returning them and the rest of the line.
+    fn options<'a>(
+        &self,
+        line: &'a str,
+    ) -> error::Result<(Vec<(value::Value, value::Value)>, &'a str)> {
+        let mut options = Vec::new();
+        let mut chars = line.char_indices().peekable();
+        loop {
+            let start = chars.peek().map_or(line.len(), |&(i, _)| i);
+            let mut end = line.len();
+            let mut value = None;
+            while let Some(&(i, c)) = chars.peek() {
+                if c == ',' || c == ' ' || c == '\t' {
+                    end = i;
+                    break;
+                }
+                chars.next();
+                if c == '=' {
+                    end = i;
+                    let mut text = String::new();
+                    if chars.next_if(|&(_, c)| c == '"').is_none() {
+                        return Err(self.error("option values must be in double quotes"));
+                    }
+                    loop {
+                        match chars.next() {
+                            Some((_, '"')) => break,
+                            Some((_, '\\')) if chars.peek().map(|&(_, c)| c) == Some('"') => {
+                                text.push('"');
+                                chars.next();
+                            }
+                            Some((_, c)) => text.push(c),
+                            None => return Err(self.error("unterminated option value")),
+                        }
+                    }
+                    value = Some(text);
+                    break;
+                }
+            }
+            let name = &line[start..end];
+            if name.is_empty() {
+                return Err(self.error("empty option"));
+            }
+            options.push((
+                name.into(),
+                value.map_or(value::Value::Bool(true), value::Value::String),
+            ));
+            match chars.next() {
+                Some((_, ',')) => continue,
+                Some((i, _)) => return Ok((options, line[i..].trim_start())),
+                None => return Err(self.error("the options are not followed by a key")),
+            }
+        }
+    }
+
+    /// Parses a key like `ssh-ed25519 AAAA... comment` into its fields.
+    fn key(&self, line: &str) -> error::Result<Vec<(value::Value, value::Value)>> {
+        let (kind, rest) = split_word(line);
+        let (key, comment) = split_word(rest);
+        if kind.is_empty() {
+            return Err(self.error("missing key type"));
+        }
+        if key.is_empty() {
+            return Err(self.error("missing key"));
+        }
+        let blob = base64::engine::general_purpose::STANDARD
+            .decode(key)
+            .map_err(|e| self.error(&format!("invalid key: {}", e)))?;
+        let mut reader = Reader(&blob);
+        let blob_kind = reader
+            .string()
+            .ok_or_else(|| self.error("the key doesn't start with its type"))?;
+        if blob_kind != kind.as_bytes() {
+            return Err(self.error(&format!(
+                "the key is not of type {}: {}",
+                kind,
+                String::from_utf8_lossy(blob_kind)
+            )));
+        }
+
+        let fingerprint = format!(
+            "SHA256:{}",
+            base64::engine::general_purpose::STANDARD_NO_PAD.encode(sha2::Sha256::digest(&blob))
+        );
+        Ok(vec![
+            ("type".into(), kind.into()),
+            ("key".into(), key.into()),
+            ("fingerprint".into(), fingerprint.into()),
+            (
+                "bits".into(),
+                bits(kind, &mut reader).map_or(value::Value::Unit, value::Value::U64),
+            ),
+            (
+                "comment".into(),
+                match comment {
+                    "" => value::Value::Unit,
+                    comment => comment.into(),
+                },
+            ),
+        ])
+    }
+
+    fn error(&self, msg: &str) -> error::Error {
+        error::Error::Format {
+            msg: format!("line {}: {}", self.line, msg),
+        }
+    }
+}
+
+/// The size of a key, from the rest of its blob after the type.
+fn bits(kind: &str, reader: &mut Reader) -> Option<u64> {
+    let kind = kind
+        .strip_prefix("sk-")
+        .map_or(kind, |k| k.trim_end_matches("@openssh.com"));
+    match kind {
+        "ssh-rsa" => {
+            // The exponent comes before the modulus
+            reader.string()?;
+            mpint_bits(reader.string()?)
+        }
+        "ssh-dss" => mpint_bits(reader.string()?),
+        "ssh-ed25519" => Some(256),
+        _ => match reader.string()? {
+            b"nistp256" => Some(256),
+            b"nistp384" => Some(384),
+            b"nistp521" => Some(521),
+            _ => None,
+        },
+    }
+}
+
+fn mpint_bits(mpint: &[u8]) -> Option<u64> {
+    let start = mpint.iter().position(|&b| b != 0)?;
+    let leading = u64::from(mpint[start].leading_zeros());
+    Some((mpint.len() - start) as u64 * 8 - leading)
+}
+
+/// Reads the length-prefixed strings of the SSH wire format.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn string(&mut self) -> Option<&'a [u8]> {
+        let len = u32::from_be_bytes(self.0.get(..4)?.try_into().ok()?) as usize;
+        let s = self.0.get(4..4 + len)?;
+        self.0 = &self.0[4 + len..];
+        Some(s)
+    }
+}
+
+/// Splits the first word of the text from the rest.
+fn split_word(s: &str) -> (&str, &str) {
+    match s.split_once([' ', '\t']) {
+        Some((word, rest)) => (word, rest.trim_start()),
+        None => (s, ""),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::value::Source as _;
+
+    fn read<R>(mut source: Source<R>) -> error::Result<Vec<value::Value>>
+    where
+        R: io::BufRead,
+    {
+        let mut records = Vec::new();
+        while let Some(record) = source.read()? {
+            records.push(record);
+        }
+        Ok(records)
+    }
+
+    #[test]
+    fn test_records() {
+        let records = |source: Source<&[u8]>| -> Vec<String> {
+            read(source)
+                .unwrap()
+                .iter()
+                .map(ToString::to_string)
+                .collect()
+        };
+        let ed25519 = "AAAAC3NzaC1lZDI1NTE5AAAAIF3Pb+YyZw172L5br8kV8FeT/RiDyL/NZW3QtEHQvMsE";
+        let rsa = "AAAAB3NzaC1yc2EAAAADAQABAAAAgQDSj6udupJwN8CZJoS8qHujox+oq2Hd7a7Pn/DuTLFHu4a6a0Sy9dFjdgMPRY6PqLTPVzDSda2TYalk+DK2cUNnXyPtofNT/hfazKQVTIwwsQLREq1gWEIDvIs3DtKAqhoEFh+/MX6FRDV+amn34EW2sNsjnEmYLg25iwLXHpc68w==";
+
+        let hosts = format!(
+            "# comment\n\ngithub.com,[10.0.0.1]:2222 ssh-ed25519 {}\n@revoked |1|c2FsdA==|aGFzaA== ssh-rsa {} old key\n",
+            ed25519, rsa
+        );
+        assert_eq!(
+            records(known_hosts(hosts.as_bytes())),
+            vec![
+                format!(
+                    r#"{{"marker": null, "hosts": ["github.com", "[10.0.0.1]:2222"], "type": "ssh-ed25519", "key": "{}", "fingerprint": "SHA256:2xxD/qgHthKNVYzdeNTJDSmsOhXLJk0wM4/9+hTz9aM", "bits": 256, "comment": null}}"#,
+                    ed25519
+                ),
+                format!(
+                    r#"{{"marker": "revoked", "hosts": ["|1|c2FsdA==|aGFzaA=="], "type": "ssh-rsa", "key": "{}", "fingerprint": "SHA256:IQHdv3eKYHz2cpRY72dWGEBWMGpBJ86OWOviGmWzxb0", "bits": 1024, "comment": "old key"}}"#,
+                    rsa
+                ),
+            ]
+        );
+
+        let keys = format!(
+            "ssh-ed25519 {0} jo@laptop\nno-pty,command=\"echo \\\"hi\\\", bye\",from=\"10.0.0.0/8\" ssh-ed25519 {0}\n",
+            ed25519
+        );
+        assert_eq!(
+            records(authorized_keys(keys.as_bytes())),
+            vec![
+                format!(
+                    r#"{{"options": {{}}, "type": "ssh-ed25519", "key": "{}", "fingerprint": "SHA256:2xxD/qgHthKNVYzdeNTJDSmsOhXLJk0wM4/9+hTz9aM", "bits": 256, "comment": "jo@laptop"}}"#,
+                    ed25519
+                ),
+                format!(
+                    r#"{{"options": {{"no-pty": true, "command": "echo \"hi\", bye", "from": "10.0.0.0/8"}}, "type": "ssh-ed25519", "key": "{}", "fingerprint": "SHA256:2xxD/qgHthKNVYzdeNTJDSmsOhXLJk0wM4/9+hTz9aM", "bits": 256, "comment": null}}"#,
+                    ed25519
+                ),
+            ]
+        );
+
+        // A key of another type than it claims, and a key without hosts
+        let mismatched = format!("host ssh-rsa {}", ed25519);
+        assert!(read(known_hosts(mismatched.as_bytes())).is_err());
+        let no_hosts = format!("ssh-ed25519 {}", ed25519);
+        assert!(read(known_hosts(no_hosts.as_bytes())).is_err());
+        assert!(read(authorized_keys(&b"hello world"[..])).is_err());
+
+        // Lines without a key type or key, and keys whose blob has no type
+        for line in &[
+            "a,b",
+            "a,b ssh-ed25519",
+            "ssh-ed25519",
+            "a,b ssh-ed25519 AAAA",
+        ] {
+            match read(known_hosts(line.as_bytes())) {
+                Err(error::Error::Format { msg }) => assert!(msg.contains("key"), "{}", msg),
+                other => panic!("expected an error for {:?}, got {:?}", line, other),
+            }
+        }
+        assert!(read(authorized_keys(&b"ssh-ed25519"[..])).is_err());
+        assert!(read(authorized_keys(&b"ssh-ed25519 AAAA"[..])).is_err());
+    }
+}
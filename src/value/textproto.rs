@@ -0,0 +1,841 @@
+//! The protobuf text format, as used by `protoc --encode` and many configuration files.
+//!
+//! Both directions need the schema of the message.  Input is parsed into the binary encoding
+//! and then read like binary protobuf input, so that both render messages the same way.
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::io;
+
+use serde_protobuf::descriptor;
+
+use crate::error;
+use crate::value;
+
+/// How deeply messages are nested at most.
+const MAX_DEPTH: usize = 128;
+
+pub struct Source(Option<value::Value>);
+
+pub struct Sink<W> {
+    descriptors: descriptor::Descriptors,
+    message_name: String,
+    writer: W,
+    written: bool,
+}
+
+/// Creates a source for the message in the text format that is read in full from the input.
+pub fn source<R>(
+    descriptors: &descriptor::Descriptors,
+    message_name: &str,
+    mut r: R,
+) -> error::Result<Source>
+where
+    R: io::Read,
+{
+    let mut text = String::new();
+    r.read_to_string(&mut text)?;
+    let message = message_by_name(descriptors, message_name)?;
+    let mut parser = Parser {
+        descriptors,
+        tokens: tokenize(&text)?,
+        next: 0,
+        depth: 0,
+    };
+    let encoded = parser.message(message, None)?;
+
+    let mut input = &encoded[..];
+    let stream = protobuf::CodedInputStream::new(&mut input);
+    let mut source = value::protobuf::source(descriptors, message_name, stream)?;
+    let record = value::Source::read(&mut source)?;
+    Ok(Source(record))
+}
+
+/// Creates a sink that writes a record as a message in the text format.  The fields of the
+/// record may be named like in the schema or in lowerCamelCase, like in the proto3 JSON mapping.
+pub fn sink<W>(
+    descriptors: descriptor::Descriptors,
+    message_name: &str,
+    writer: W,
+) -> error::Result<Sink<W>>
+where
+    W: io::Write,
+{
+    message_by_name(&descriptors, message_name)?;
+    Ok(Sink {
+        descriptors,
+        message_name: message_name.to_owned(),
+        writer,
+        written: false,
+    })
+}
+
+impl value::Source for Source {
+    #[inline]
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        Ok(self.0.take())
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let n = if self.0.is_some() { 1 } else { 0 };
+        (n, Some(n))
+    }
+}
+
+impl<W> value::Sink for Sink<W>
+where
+    W: io::Write,
+{
+    fn write(&mut self, v: value::Value) -> error::Result<()> {
+        if self.written {
+            return Err(error::Error::Format {
+                msg: "the protobuf text format holds a single message, but there is more than \
+                      one record"
+                    .to_owned(),
+            });
+        }
+        self.written = true;
+        let message = message_by_name(&self.descriptors, &self.message_name)?;
+        let mut text = String::new();
+        write_message(&mut text, &self.descriptors, message, &v, 0)?;
+        self.writer.write_all(text.as_bytes())?;
+        Ok(())
+    }
+
+    #[inline]
+    fn flush(&mut self) -> error::Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+fn message_by_name<'a>(
+    descriptors: &'a descriptor::Descriptors,
+    name: &str,
+) -> error::Result<&'a descriptor::MessageDescriptor> {
+    descriptors
+        .message_by_name(name)
+        .ok_or_else(|| error::Error::Message(format!("unknown protobuf message: {}", name)))
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(String),
+    String(Vec<u8>),
+    Punct(char),
+}
+
+/// Splits the text into tokens, each with its line number.  Comments start with `#`.
+fn tokenize(text: &str) -> error::Result<Vec<(Token, usize)>> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    let mut line = 1;
+    while let Some(&c) = chars.peek() {
+        if c == '\n' {
+            line += 1;
+            chars.next();
+        } else if c.is_whitespace() {
+            chars.next();
+        } else if c == '#' {
+            while chars.peek().is_some_and(|&c| c != '\n') {
+                chars.next();
+            }
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let mut ident = String::new();
+            while let Some(&c) = chars
+                .peek()
+                .filter(|c| c.is_ascii_alphanumeric() || **c == '_')
+            {
+                ident.push(c);
+                chars.next();
+            }
+            tokens.push((Token::Ident(ident), line));
+        } else if c.is_ascii_digit() || c == '.' {
+            let mut number = String::new();
+            while let Some(&c) = chars.peek() {
+                let exponent_sign = (c == '+' || c == '-')
+                    && number.ends_with(['e', 'E'])
+                    && !number.starts_with("0x")
+                    && !number.starts_with("0X");
+                if c.is_ascii_alphanumeric() || c == '.' || exponent_sign {
+                    number.push(c);
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            tokens.push((Token::Number(number), line));
+        } else if c == '"' || c == '\'' {
+            chars.next();
+            let bytes = unescape(&mut chars, c, line)?;
+            // Adjacent strings are concatenated, like in C
+            match tokens.last_mut() {
+                Some((Token::String(ref mut previous), _)) => previous.extend(bytes),
+                _ => tokens.push((Token::String(bytes), line)),
+            }
+        } else if "{}<>[]:,;-".contains(c) {
+            chars.next();
+            tokens.push((Token::Punct(c), line));
+        } else {
+            return Err(syntax_error(line, format!("unexpected character {:?}", c)));
+        }
+    }
+    Ok(tokens)
+}
+
+/// Reads the rest of a string literal up to the closing quote, resolving C-style escapes.
+fn unescape<I>(
+    chars: &mut std::iter::Peekable<I>,
+    quote: char,
+    line: usize,
+) -> error::Result<Vec<u8>>
+where
+    I: Iterator<Item = char>,
+{
+    let mut bytes = Vec::new();
+    loop {
+        let c = match chars.next() {
+            Some('\n') | None => return Err(syntax_error(line, "unterminated string".to_owned())),
+            Some(c) if c == quote => return Ok(bytes),
+            Some(c) => c,
+        };
+        if c != '\\' {
+            let mut buffer = [0; 4];
+            bytes.extend(c.encode_utf8(&mut buffer).as_bytes());
+            continue;
+        }
+        let escape = chars
+            .next()
+            .ok_or_else(|| syntax_error(line, "unterminated string".to_owned()))?;
+        match escape {
+            'a' => bytes.push(0x07),
+            'b' => bytes.push(0x08),
+            'f' => bytes.push(0x0c),
+            'n' => bytes.push(b'\n'),
+            'r' => bytes.push(b'\r'),
+            't' => bytes.push(b'\t'),
+            'v' => bytes.push(0x0b),
+            '\\' | '\'' | '"' | '?' => bytes.push(escape as u8),
+            '0'..='7' => {
+                let mut n = escape.to_digit(8).unwrap();
+                for _ in 0..2 {
+                    match chars.peek().and_then(|c| c.to_digit(8)) {
+                        Some(d) => {
+                            n = n * 8 + d;
+                            chars.next();
+                        }
+                        None => break,
+                    }
+                }
+                bytes.push(n as u8);
+            }
+            'x' | 'u' | 'U' => {
+                let max = match escape {
+                    'x' => 2,
+                    'u' => 4,
+                    _ => 8,
+                };
+                let mut n = 0;
+                let mut digits = 0;
+                while digits < max {
+                    match chars.peek().and_then(|c| c.to_digit(16)) {
+                        Some(d) => {
+                            n = n * 16 + d;
+                            digits += 1;
+                            chars.next();
+                        }
+                        None => break,
+                    }
+                }
+                if digits == 0 || (escape != 'x' && digits < max) {
+                    return Err(syntax_error(line, format!("invalid \\{} escape", escape)));
+                }
+                if escape == 'x' {
+                    bytes.push(n as u8);
+                } else {
+                    let c = char::from_u32(n).ok_or_else(|| {
+                        syntax_error(line, format!("invalid code point U+{:X}", n))
+                    })?;
+                    let mut buffer = [0; 4];
+                    bytes.extend(c.encode_utf8(&mut buffer).as_bytes());
+                }
+            }
+            c => return Err(syntax_error(line, format!("invalid escape \\{}", c))),
+        }
+    }
+}
+
+/// Encodes the messages of a token stream in the binary format, as described by the schema.
+struct Parser<'a> {
+    descriptors: &'a descriptor::Descriptors,
+    tokens: Vec<(Token, usize)>,
+    next: usize,
+    depth: usize,
+}
+
+impl Parser<'_> {
+    /// Encodes the fields of a message up to the closing bracket, or up to the end of the
+    /// input for the outermost message.
+    fn message(
+        &mut self,
+        message: &descriptor::MessageDescriptor,
+        end: Option<char>,
+    ) -> error::Result<Vec<u8>> {
+        let mut encoded = Vec::new();
+        {
+            let mut out = protobuf::CodedOutputStream::vec(&mut encoded);
+            loop {
+                let name = match self.take() {
+                    None if end.is_none() => break,
+                    Some(Token::Punct(c)) if Some(c) == end => break,
+                    Some(Token::Ident(name)) => name,
+                    Some(Token::Punct('[')) => {
+                        return Err(
+                            self.error("extensions and Any messages are not supported".to_owned())
+                        )
+                    }
+                    Some(token) => {
+                        return Err(self.error(format!("expected a field name, got {:?}", token)))
+                    }
+                    None => return Err(self.error("unexpected end of input".to_owned())),
+                };
+                let field = message.field_by_name(&name).ok_or_else(|| {
+                    self.error(format!("message {} has no field {}", message.name(), name))
+                })?;
+                let is_message = matches!(
+                    field.field_type(self.descriptors),
+                    descriptor::FieldType::Message(_)
+                );
+                // The colon is optional before messages
+                if self.peek() == Some(&Token::Punct(':')) {
+                    self.next += 1;
+                } else if !is_message {
+                    return Err(self.error(format!("expected ':' after {}", name)));
+                }
+                if self.peek() == Some(&Token::Punct('[')) {
+                    self.next += 1;
+                    if self.peek() == Some(&Token::Punct(']')) {
+                        self.next += 1;
+                    } else {
+                        loop {
+                            self.value(&mut out, field)?;
+                            match self.take() {
+                                Some(Token::Punct(',')) => (),
+                                Some(Token::Punct(']')) => break,
+                                _ => return Err(self.error("expected ',' or ']'".to_owned())),
+                            }
+                        }
+                    }
+                } else {
+                    self.value(&mut out, field)?;
+                }
+                if let Some(Token::Punct(',')) | Some(Token::Punct(';')) = self.peek() {
+                    self.next += 1;
+                }
+            }
+            out.flush()?;
+        }
+        Ok(encoded)
+    }
+
+    fn value(
+        &mut self,
+        out: &mut protobuf::CodedOutputStream,
+        field: &descriptor::FieldDescriptor,
+    ) -> error::Result<()> {
+        use serde_protobuf::descriptor::FieldType;
+
+        let number = field.number() as u32;
+        let field_type = field.field_type(self.descriptors);
+        if let FieldType::Message(message) = field_type {
+            let end = match self.take() {
+                Some(Token::Punct('{')) => '}',
+                Some(Token::Punct('<')) => '>',
+                _ => return Err(self.error(format!("expected a message for {}", field.name()))),
+            };
+            if self.depth >= MAX_DEPTH {
+                return Err(self.error(format!(
+                    "messages are nested more than {} levels deep",
+                    MAX_DEPTH
+                )));
+            }
+            self.depth += 1;
+            let encoded = self.message(message, Some(end))?;
+            self.depth -= 1;
+            out.write_bytes(number, &encoded)?;
+            return Ok(());
+        }
+
+        let negative = self.peek() == Some(&Token::Punct('-'));
+        if negative {
+            self.next += 1;
+        }
+        let token = self
+            .take()
+            .ok_or_else(|| self.error("unexpected end of input".to_owned()))?;
+        let name = field.name();
+        match (field_type, token) {
+            (FieldType::String, Token::String(bytes)) if !negative => {
+                let s = String::from_utf8(bytes)
+                    .map_err(|_| self.error(format!("{} must be valid UTF-8", name)))?;
+                out.write_string(number, &s)?;
+            }
+            (FieldType::Bytes, Token::String(bytes)) if !negative => {
+                out.write_bytes(number, &bytes)?;
+            }
+            (FieldType::Bool, Token::Ident(ref b)) if !negative => match b.as_str() {
+                "true" | "True" | "t" => out.write_bool(number, true)?,
+                "false" | "False" | "f" => out.write_bool(number, false)?,
+                _ => return Err(self.error(format!("expected a bool for {}, got {}", name, b))),
+            },
+            (FieldType::Bool, Token::Number(ref b)) if !negative && (b == "0" || b == "1") => {
+                out.write_bool(number, b == "1")?;
+            }
+            (FieldType::Enum(e), Token::Ident(ref v)) if !negative => {
+                let v = e
+                    .value_by_name(v)
+                    .ok_or_else(|| self.error(format!("enum {} has no value {}", e.name(), v)))?;
+                out.write_enum(number, v.number())?;
+            }
+            (FieldType::Float, token) => {
+                out.write_float(number, self.float(token, negative)? as f32)?
+            }
+            (FieldType::Double, token) => out.write_double(number, self.float(token, negative)?)?,
+            (field_type, Token::Number(ref n)) => {
+                let v = parse_integer(n, negative).ok_or_else(|| {
+                    self.error(format!("expected an integer for {}, got {}", name, n))
+                })?;
+                let range = || self.error(format!("{} is out of range for {}", v, name));
+                match field_type {
+                    FieldType::Int32 => {
+                        out.write_int32(number, i32::try_from(v).map_err(|_| range())?)?
+                    }
+                    FieldType::SInt32 => {
+                        out.write_sint32(number, i32::try_from(v).map_err(|_| range())?)?
+                    }
+                    FieldType::SFixed32 => {
+                        out.write_sfixed32(number, i32::try_from(v).map_err(|_| range())?)?
+                    }
+                    FieldType::Enum(_) => {
+                        out.write_enum(number, i32::try_from(v).map_err(|_| range())?)?
+                    }
+                    FieldType::Int64 => {
+                        out.write_int64(number, i64::try_from(v).map_err(|_| range())?)?
+                    }
+                    FieldType::SInt64 => {
+                        out.write_sint64(number, i64::try_from(v).map_err(|_| range())?)?
+                    }
+                    FieldType::SFixed64 => {
+                        out.write_sfixed64(number, i64::try_from(v).map_err(|_| range())?)?
+                    }
+                    FieldType::UInt32 => {
+                        out.write_uint32(number, u32::try_from(v).map_err(|_| range())?)?
+                    }
+                    FieldType::Fixed32 => {
+                        out.write_fixed32(number, u32::try_from(v).map_err(|_| range())?)?
+                    }
+                    FieldType::UInt64 => {
+                        out.write_uint64(number, u64::try_from(v).map_err(|_| range())?)?
+                    }
+                    FieldType::Fixed64 => {
+                        out.write_fixed64(number, u64::try_from(v).map_err(|_| range())?)?
+                    }
+                    _ => return Err(self.error(format!("unexpected number for {}", name))),
+                }
+            }
+            (_, token) => {
+                return Err(self.error(format!("unexpected {:?} for {}", token, name)));
+            }
+        }
+        Ok(())
+    }
+
+    fn float(&self, token: Token, negative: bool) -> error::Result<f64> {
+        let v = match token {
+            Token::Ident(ref s) => match s.to_ascii_lowercase().as_str() {
+                "inf" | "infinity" => Some(f64::INFINITY),
+                "nan" => Some(f64::NAN),
+                _ => None,
+            },
+            Token::Number(ref s) => {
+                let s = s.trim_end_matches(['f', 'F']);
+                s.parse::<f64>()
+                    .ok()
+                    .or_else(|| parse_integer(s, false).map(|v| v as f64))
+            }
+            _ => None,
+        };
+        match v {
+            Some(v) if negative => Ok(-v),
+            Some(v) => Ok(v),
+            None => Err(self.error(format!("expected a number, got {:?}", token))),
+        }
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next).map(|(token, _)| token)
+    }
+
+    fn take(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.next).map(|(token, _)| token.clone());
+        self.next += 1;
+        token
+    }
+
+    /// An error at the last token that was looked at.
+    fn error(&self, msg: String) -> error::Error {
+        let line = self
+            .tokens
+            .get(self.next.saturating_sub(1))
+            .or_else(|| self.tokens.last())
+            .map_or(1, |&(_, line)| line);
+        syntax_error(line, msg)
+    }
+}
+
+fn syntax_error(line: usize, msg: String) -> error::Error {
+    error::Error::Format {
+        msg: format!("protobuf text format, line {}: {}", line, msg),
+    }
+}
+
+/// Parses a decimal, hexadecimal (`0x`) or octal (leading `0`) integer.
+fn parse_integer(s: &str, negative: bool) -> Option<i128> {
+    let v = if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        i128::from_str_radix(hex, 16).ok()?
+    } else if s.len() > 1 && s.starts_with('0') {
+        i128::from_str_radix(&s[1..], 8).ok()?
+    } else {
+        s.parse().ok()?
+    };
+    Some(if negative { -v } else { v })
+}
+
+fn write_message(
+    out: &mut String,
+    descriptors: &descriptor::Descriptors,
+    message: &descriptor::MessageDescriptor,
+    v: &value::Value,
+    indent: usize,
+) -> error::Result<()> {
+    let entries = match *v {
+        value::Value::Map(ref entries) => entries,
+        ref v => {
+            return Err(error::Error::Format {
+                msg: format!(
+                    "protobuf message {} must be a map, got: {}",
+                    message.name(),
+                    v.summary(value::ERROR_SUMMARY_LEN)
+                ),
+            })
+        }
+    };
+    for (key, v) in entries {
+        let name = key.as_str().unwrap_or_default();
+        let field = message
+            .field_by_name(name)
+            .or_else(|| {
+                message
+                    .fields()
+                    .iter()
+                    .find(|f| value::protobuf::json_name(f.name()) == name)
+            })
+            .ok_or_else(|| error::Error::Format {
+                msg: format!("protobuf message {} has no field {}", message.name(), key),
+            })?;
+        match *v {
+            value::Value::Unit => (),
+            value::Value::Sequence(ref values) if field.is_repeated() => {
+                for v in values {
+                    write_field(out, descriptors, field, v, indent)?;
+                }
+            }
+            // Maps like in the proto3 JSON mapping, written as repeated entry messages
+            value::Value::Map(ref map) if field.is_repeated() => {
+                for (k, v) in map {
+                    let entry = value::Value::Map(vec![
+                        ("key".into(), k.clone()),
+                        ("value".into(), v.clone()),
+                    ]);
+                    write_field(out, descriptors, field, &entry, indent)?;
+                }
+            }
+            ref v => write_field(out, descriptors, field, v, indent)?,
+        }
+    }
+    Ok(())
+}
+
+fn write_field(
+    out: &mut String,
+    descriptors: &descriptor::Descriptors,
+    field: &descriptor::FieldDescriptor,
+    v: &value::Value,
+    indent: usize,
+) -> error::Result<()> {
+    use serde_protobuf::descriptor::FieldType;
+
+    let invalid = || error::Error::Format {
+        msg: format!(
+            "invalid value for protobuf field {}: {}",
+            field.name(),
+            v.summary(value::ERROR_SUMMARY_LEN)
+        ),
+    };
+    out.extend(std::iter::repeat_n(' ', indent));
+    out.push_str(field.name());
+    match field.field_type(descriptors) {
+        FieldType::Message(message) => {
+            out.push_str(" {\n");
+            write_message(out, descriptors, message, v, indent + 2)?;
+            out.extend(std::iter::repeat_n(' ', indent));
+            out.push('}');
+        }
+        FieldType::String | FieldType::Bytes => {
+            out.push_str(": ");
+            match *v {
+                value::Value::String(ref s) => escape(out, s.as_bytes()),
+                value::Value::Bytes(ref b) => escape(out, b),
+                value::Value::Char(c) => escape(out, c.to_string().as_bytes()),
+                _ => return Err(invalid()),
+            }
+        }
+        FieldType::Enum(_) => {
+            out.push_str(": ");
+            match *v {
+                value::Value::String(ref s) => out.push_str(s),
+                ref v if is_integer(v) => out.push_str(&v.to_string()),
+                _ => return Err(invalid()),
+            }
+        }
+        FieldType::Bool => {
+            out.push_str(": ");
+            match *v {
+                value::Value::Bool(b) => out.push_str(if b { "true" } else { "false" }),
+                _ => return Err(invalid()),
+            }
+        }
+        FieldType::Float | FieldType::Double => {
+            out.push_str(": ");
+            let f = match *v {
+                // Like in the proto3 JSON mapping
+                value::Value::String(ref s) => match s.as_str() {
+                    "NaN" => f64::NAN,
+                    "Infinity" => f64::INFINITY,
+                    "-Infinity" => f64::NEG_INFINITY,
+                    s => s.parse().map_err(|_| invalid())?,
+                },
+                ref v => v.as_f64().ok_or_else(invalid)?,
+            };
+            if f.is_nan() {
+                out.push_str("nan");
+            } else if f.is_infinite() {
+                out.push_str(if f > 0.0 { "inf" } else { "-inf" });
+            } else {
+                out.push_str(&f.to_string());
+            }
+        }
+        _ => {
+            out.push_str(": ");
+            match *v {
+                // 64-bit integers are strings in the proto3 JSON mapping
+                value::Value::String(ref s) if s.parse::<i128>().is_ok() => out.push_str(s),
+                ref v if is_integer(v) => out.push_str(&v.to_string()),
+                _ => return Err(invalid()),
+            }
+        }
+    }
+    out.push('\n');
+    Ok(())
+}
+
+fn is_integer(v: &value::Value) -> bool {
+    matches!(
+        *v,
+        value::Value::I8(_)
+            | value::Value::I16(_)
+            | value::Value::I32(_)
+            | value::Value::I64(_)
+            | value::Value::U8(_)
+            | value::Value::U16(_)
+            | value::Value::U32(_)
+            | value::Value::U64(_)
+    )
+}
+
+/// Writes bytes as a double-quoted string, escaping quotes, control characters and invalid
+/// UTF-8 like `protoc` does.
+fn escape(out: &mut String, bytes: &[u8]) {
+    out.push('"');
+    for chunk in bytes.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '"' => out.push_str("\\\""),
+                '\\' => out.push_str("\\\\"),
+                '\n' => out.push_str("\\n"),
+                '\r' => out.push_str("\\r"),
+                '\t' => out.push_str("\\t"),
+                c if c.is_control() => out.push_str(&format!("\\{:03o}", c as u32)),
+                c => out.push(c),
+            }
+        }
+        for b in chunk.invalid() {
+            out.push_str(&format!("\\{:03o}", b));
+        }
+    }
+    out.push('"');
+}
+
+impl fmt::Debug for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TextprotoSource").finish()
+    }
+}
+
+impl<W> fmt::Debug for Sink<W> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TextprotoSink")
+            .field("message_name", &self.message_name)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use serde_protobuf::descriptor::{
+        Descriptors, EnumDescriptor, EnumValueDescriptor, FieldDescriptor, FieldLabel,
+        InternalFieldType, MessageDescriptor,
+    };
+
+    use crate::value::Sink as _;
+    use crate::value::Source as _;
+
+    fn schema() -> Descriptors {
+        let mut descriptors = Descriptors::new();
+        let mut color = EnumDescriptor::new(".test.Color");
+        color.add_value(EnumValueDescriptor::new("RED", 0));
+        color.add_value(EnumValueDescriptor::new("GREEN", 1));
+        descriptors.add_enum(color);
+        let mut server = MessageDescriptor::new(".test.Server");
+        server.add_field(FieldDescriptor::new(
+            "host",
+            1,
+            FieldLabel::Optional,
+            InternalFieldType::String,
+            None,
+        ));
+        server.add_field(FieldDescriptor::new(
+            "port",
+            2,
+            FieldLabel::Optional,
+            InternalFieldType::UInt32,
+            None,
+        ));
+        descriptors.add_message(server);
+        let mut config = MessageDescriptor::new(".test.Config");
+        let fields = vec![
+            ("name", 1, FieldLabel::Optional, InternalFieldType::String),
+            (
+                "retries",
+                2,
+                FieldLabel::Optional,
+                InternalFieldType::SInt64,
+            ),
+            ("ratio", 3, FieldLabel::Optional, InternalFieldType::Double),
+            (
+                "color",
+                4,
+                FieldLabel::Optional,
+                InternalFieldType::UnresolvedEnum(".test.Color".to_owned()),
+            ),
+            (
+                "servers",
+                5,
+                FieldLabel::Repeated,
+                InternalFieldType::UnresolvedMessage(".test.Server".to_owned()),
+            ),
+            ("tags", 6, FieldLabel::Repeated, InternalFieldType::String),
+            ("secret", 7, FieldLabel::Optional, InternalFieldType::Bytes),
+            ("debug", 8, FieldLabel::Optional, InternalFieldType::Bool),
+        ];
+        for (name, number, label, field_type) in fields {
+            config.add_field(FieldDescriptor::new(name, number, label, field_type, None));
+        }
+        descriptors.add_message(config);
+        let mut node = MessageDescriptor::new(".test.Node");
+        node.add_field(FieldDescriptor::new(
+            "child",
+            1,
+            FieldLabel::Optional,
+            InternalFieldType::UnresolvedMessage(".test.Node".to_owned()),
+            None,
+        ));
+        descriptors.add_message(node);
+        descriptors.resolve_refs();
+        descriptors
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let descriptors = schema();
+
+        let text = r#"
+# The production config
+name: "api" ' "v2"'
+retries: -3
+ratio: 0.5
+color: GREEN
+servers { host: "a.example" port: 80 }
+servers < host: 'b.example', port: 0x1bb >;
+tags: ["x", "y\n"]
+secret: "\001\xff"
+"#;
+        let mut reader = source(&descriptors, ".test.Config", text.as_bytes()).unwrap();
+        let record = reader.read().unwrap().unwrap();
+        assert_eq!(reader.read().unwrap(), None);
+        assert_eq!(
+            record.to_string(),
+            r#"{"name": "api \"v2\"", "retries": -3, "ratio": 0.5, "color": "GREEN", "servers": [{"host": "a.example", "port": 80}, {"host": "b.example", "port": 443}], "tags": ["x", "y\n"], "secret": 0x01ff, "debug": null}"#
+        );
+
+        let mut output = Vec::new();
+        {
+            let mut writer = sink(schema(), ".test.Config", &mut output).unwrap();
+            writer.write(record.clone()).unwrap();
+            assert!(writer.write(record.clone()).is_err());
+        }
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(
+            output,
+            "name: \"api \\\"v2\\\"\"\nretries: -3\nratio: 0.5\ncolor: GREEN\n\
+             servers {\n  host: \"a.example\"\n  port: 80\n}\n\
+             servers {\n  host: \"b.example\"\n  port: 443\n}\n\
+             tags: \"x\"\ntags: \"y\\n\"\nsecret: \"\\001\\377\"\n"
+        );
+        let mut reader = source(&descriptors, ".test.Config", output.as_bytes()).unwrap();
+        assert_eq!(reader.read().unwrap(), Some(record));
+
+        for invalid in &["nope: 1", "retries: 1.5", "color: BLUE", "name: 'open"] {
+            assert!(source(&descriptors, ".test.Config", invalid.as_bytes()).is_err());
+        }
+    }
+
+    #[test]
+    fn test_depth() {
+        let descriptors = schema();
+        let nested = |depth: usize| format!("{}{}", "child { ".repeat(depth), "}".repeat(depth));
+        let read = |text: &str| source(&descriptors, ".test.Node", text.as_bytes());
+        assert!(read(&nested(MAX_DEPTH)).is_ok());
+        match read(&nested(100_000)) {
+            Err(error::Error::Format { msg }) => assert!(msg.contains("nested"), "{}", msg),
+            other => panic!(
+                "expected an error for deeply nested messages, got {:?}",
+                other
+            ),
+        }
+    }
+}
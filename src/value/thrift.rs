@@ -0,0 +1,1534 @@
+//! Apache Thrift structs in the binary or the compact protocol, described by a `.thrift` IDL.
+//!
+//! Structs are decoded into maps from field names to values, in the order of the IDL, leaving
+//! out fields that aren't set.  Enums are rendered by name, `binary` fields as bytes and UUIDs as
+//! strings.  Input is a series of structs, without the message envelope of RPC calls.
+
+use std::collections;
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path;
+use std::str;
+
+use crate::error;
+use crate::value;
+
+/// The protocol that structs are encoded with.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Protocol {
+    /// `TBinaryProtocol`, with big-endian fixed-width integers.
+    Binary,
+    /// `TCompactProtocol`, with variable-length integers.
+    Compact,
+}
+
+/// The types that are declared in a Thrift IDL and the files it includes.  The types of included
+/// files are named after the file, like `shared.Address` for a type of `shared.thrift`.
+#[derive(Debug, Default)]
+pub struct Idl {
+    structs: collections::HashMap<String, Struct>,
+    enums: collections::HashMap<String, Enum>,
+    typedefs: collections::HashMap<String, Type>,
+}
+
+#[derive(Debug)]
+struct Struct {
+    name: String,
+    fields: Vec<Field>,
+}
+
+#[derive(Debug)]
+struct Field {
+    id: i16,
+    name: String,
+    type_: Type,
+    required: bool,
+}
+
+#[derive(Debug)]
+struct Enum {
+    name: String,
+    values: Vec<(String, i32)>,
+}
+
+#[derive(Clone, Debug)]
+enum Type {
+    Bool,
+    Byte,
+    I16,
+    I32,
+    I64,
+    Double,
+    String,
+    Binary,
+    Uuid,
+    List(Box<Type>),
+    Set(Box<Type>),
+    Map(Box<Type>, Box<Type>),
+    /// A struct, enum or typedef, by its qualified name.
+    Named(String),
+}
+
+/// A type with names resolved.
+enum Kind<'a> {
+    Bool,
+    Byte,
+    I16,
+    I32,
+    I64,
+    Double,
+    String,
+    Binary,
+    Uuid,
+    List(&'a Type),
+    Set(&'a Type),
+    Map(&'a Type, &'a Type),
+    Enum(&'a Enum),
+    Struct(&'a Struct),
+}
+
+/// A value as it is encoded, without the names and types of the schema.
+#[derive(Debug)]
+enum Wire {
+    Bool(bool),
+    Byte(i8),
+    I16(i16),
+    I32(i32),
+    I64(i64),
+    Double(f64),
+    Binary(Vec<u8>),
+    Uuid([u8; 16]),
+    Struct(Vec<(i16, Wire)>),
+    /// The elements of a list or set, with their type.
+    List(u8, Vec<Wire>),
+    Set(u8, Vec<Wire>),
+    /// The entries of a map, with the types of keys and values.
+    Map(u8, u8, Vec<(Wire, Wire)>),
+}
+
+// The type ids of the binary protocol, which the compact protocol maps to its own
+const T_STOP: u8 = 0;
+const T_BOOL: u8 = 2;
+const T_BYTE: u8 = 3;
+const T_DOUBLE: u8 = 4;
+const T_I16: u8 = 6;
+const T_I32: u8 = 8;
+const T_I64: u8 = 10;
+const T_STRING: u8 = 11;
+const T_STRUCT: u8 = 12;
+const T_MAP: u8 = 13;
+const T_SET: u8 = 14;
+const T_LIST: u8 = 15;
+const T_UUID: u8 = 16;
+
+/// Collections can't be nested deeper than this, so that malicious input can't overflow the
+/// stack.
+const MAX_DEPTH: usize = 64;
+
+pub struct Source {
+    idl: Idl,
+    struct_name: String,
+    protocol: Protocol,
+    input: Vec<u8>,
+    offset: usize,
+    records: u64,
+}
+
+pub struct Sink<W> {
+    idl: Idl,
+    struct_name: String,
+    protocol: Protocol,
+    writer: W,
+}
+
+impl Idl {
+    /// Reads an IDL file and the files it includes, which are looked up relative to it.
+    pub fn load(path: &path::Path) -> error::Result<Idl> {
+        let mut idl = Idl::default();
+        idl.load_into(path, "", &mut Vec::new())?;
+        Ok(idl)
+    }
+
+    /// Parses an IDL.  Includes are ignored, since there is no file to resolve them against.
+    pub fn parse(text: &str) -> error::Result<Idl> {
+        let mut idl = Idl::default();
+        idl.parse_into(text, "", None, &mut Vec::new())?;
+        Ok(idl)
+    }
+
+    fn load_into(
+        &mut self,
+        path: &path::Path,
+        prefix: &str,
+        loading: &mut Vec<path::PathBuf>,
+    ) -> error::Result<()> {
+        let canonical = fs::canonicalize(path)?;
+        if loading.contains(&canonical) {
+            return Ok(());
+        }
+        loading.push(canonical);
+        let text = fs::read_to_string(path)?;
+        self.parse_into(&text, prefix, path.parent(), loading)
+    }
+
+    fn parse_into(
+        &mut self,
+        text: &str,
+        prefix: &str,
+        dir: Option<&path::Path>,
+        loading: &mut Vec<path::PathBuf>,
+    ) -> error::Result<()> {
+        let mut parser = Parser {
+            tokens: tokenize(text)?,
+            next: 0,
+            prefix,
+        };
+        while let Some(keyword) = parser.next_token() {
+            let keyword = match keyword {
+                Token::Ident(keyword) => keyword,
+                token => return Err(parser.error(format!("unexpected {}", token))),
+            };
+            match keyword.as_str() {
+                "namespace" => {
+                    parser.next_token();
+                    parser.next_token();
+                }
+                "cpp_include" => {
+                    parser.string()?;
+                }
+                "include" => {
+                    let file = parser.string()?;
+                    match dir {
+                        Some(dir) => {
+                            let path = dir.join(&file);
+                            let name = path
+                                .file_stem()
+                                .and_then(|stem| stem.to_str())
+                                .unwrap_or_default()
+                                .to_owned();
+                            self.load_into(&path, &format!("{}.", name), loading)?;
+                        }
+                        None => debug!("Ignoring Thrift include {:?}", file),
+                    }
+                }
+                "typedef" => {
+                    let type_ = parser.type_()?;
+                    let name = parser.ident()?;
+                    parser.annotations()?;
+                    parser.separator();
+                    self.typedefs.insert(format!("{}{}", prefix, name), type_);
+                }
+                "const" => {
+                    parser.type_()?;
+                    parser.ident()?;
+                    parser.expect('=')?;
+                    parser.skip_value()?;
+                    parser.separator();
+                }
+                "enum" => {
+                    let name = format!("{}{}", prefix, parser.ident()?);
+                    let values = parser.enum_values()?;
+                    parser.annotations()?;
+                    self.enums.insert(name.clone(), Enum { name, values });
+                }
+                "struct" | "union" | "exception" => {
+                    let name = format!("{}{}", prefix, parser.ident()?);
+                    if parser.peek_ident() == Some("xsd_all") {
+                        parser.next_token();
+                    }
+                    let fields = parser.fields()?;
+                    parser.annotations()?;
+                    self.structs.insert(name.clone(), Struct { name, fields });
+                }
+                "service" | "senum" => {
+                    // Services only describe RPC calls, which aren't decoded
+                    while !parser.at('{') {
+                        if parser.next_token().is_none() {
+                            return Err(parser.error(format!("{} without a body", keyword)));
+                        }
+                    }
+                    parser.skip_value()?;
+                    parser.annotations()?;
+                }
+                _ => return Err(parser.error(format!("unexpected {:?}", keyword))),
+            }
+        }
+        Ok(())
+    }
+
+    /// Finds a struct by its qualified name, or by its name if that is unambiguous.
+    fn struct_named(&self, name: &str) -> error::Result<&Struct> {
+        if let Some(s) = self.structs.get(name) {
+            return Ok(s);
+        }
+        let mut matches = self
+            .structs
+            .values()
+            .filter(|s| s.name.rsplit('.').next() == Some(name));
+        match (matches.next(), matches.next()) {
+            (Some(s), None) => Ok(s),
+            _ => Err(error::Error::Message(format!(
+                "the Thrift IDL has no struct {}",
+                name
+            ))),
+        }
+    }
+
+    fn resolve<'a>(&'a self, type_: &'a Type) -> error::Result<Kind<'a>> {
+        let mut type_ = type_;
+        // Typedefs can refer to other typedefs, but not in a cycle
+        for _ in 0..MAX_DEPTH {
+            return Ok(match *type_ {
+                Type::Bool => Kind::Bool,
+                Type::Byte => Kind::Byte,
+                Type::I16 => Kind::I16,
+                Type::I32 => Kind::I32,
+                Type::I64 => Kind::I64,
+                Type::Double => Kind::Double,
+                Type::String => Kind::String,
+                Type::Binary => Kind::Binary,
+                Type::Uuid => Kind::Uuid,
+                Type::List(ref element) => Kind::List(element),
+                Type::Set(ref element) => Kind::Set(element),
+                Type::Map(ref key, ref value) => Kind::Map(key, value),
+                Type::Named(ref name) => {
+                    if let Some(s) = self.structs.get(name) {
+                        Kind::Struct(s)
+                    } else if let Some(e) = self.enums.get(name) {
+                        Kind::Enum(e)
+                    } else if let Some(t) = self.typedefs.get(name) {
+                        type_ = t;
+                        continue;
+                    } else {
+                        return Err(error::Error::Message(format!(
+                            "the Thrift IDL has no type {}",
+                            name
+                        )));
+                    }
+                }
+            });
+        }
+        Err(error::Error::Message(
+            "the Thrift IDL has a cycle of typedefs".to_owned(),
+        ))
+    }
+}
+
+/// Creates a source for the structs in the input, which is read in full.
+pub fn source<R>(idl: Idl, struct_name: &str, protocol: Protocol, mut r: R) -> error::Result<Source>
+where
+    R: io::Read,
+{
+    idl.struct_named(struct_name)?;
+    let mut input = Vec::new();
+    r.read_to_end(&mut input)?;
+    Ok(Source {
+        idl,
+        struct_name: struct_name.to_owned(),
+        protocol,
+        input,
+        offset: 0,
+        records: 0,
+    })
+}
+
+/// Creates a sink that writes records as structs, one after the other.  Fields are looked up by
+/// name, and enums can be given by name or by value.
+pub fn sink<W>(idl: Idl, struct_name: &str, protocol: Protocol, writer: W) -> error::Result<Sink<W>>
+where
+    W: io::Write,
+{
+    idl.struct_named(struct_name)?;
+    Ok(Sink {
+        idl,
+        struct_name: struct_name.to_owned(),
+        protocol,
+        writer,
+    })
+}
+
+impl value::Source for Source {
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        if self.offset >= self.input.len() {
+            return Ok(None);
+        }
+        let mut reader = Reader {
+            input: &self.input,
+            offset: self.offset,
+            protocol: self.protocol,
+        };
+        let wire = reader.struct_(0)?;
+        self.offset = reader.offset;
+        self.records += 1;
+        let s = self.idl.struct_named(&self.struct_name)?;
+        Ok(Some(to_value(&self.idl, wire, &Kind::Struct(s))?))
+    }
+
+    #[inline]
+    fn position(&self) -> Option<value::Position> {
+        Some(value::Position {
+            record: self.records,
+            byte: Some(self.offset as u64),
+            line: None,
+        })
+    }
+}
+
+impl<W> value::Sink for Sink<W>
+where
+    W: io::Write,
+{
+    fn write(&mut self, v: value::Value) -> error::Result<()> {
+        let s = self.idl.struct_named(&self.struct_name)?;
+        let wire = from_value(&self.idl, &v, &Kind::Struct(s))?;
+        let mut writer = Writer {
+            output: Vec::new(),
+            protocol: self.protocol,
+        };
+        writer.value(&wire)?;
+        self.writer.write_all(&writer.output)?;
+        Ok(())
+    }
+
+    #[inline]
+    fn flush(&mut self) -> error::Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Converts a decoded value according to its type in the schema.
+fn to_value(idl: &Idl, wire: Wire, kind: &Kind) -> error::Result<value::Value> {
+    Ok(match (kind, wire) {
+        (Kind::Bool, Wire::Bool(v)) => value::Value::Bool(v),
+        (Kind::Byte, Wire::Byte(v)) => value::Value::I8(v),
+        (Kind::I16, Wire::I16(v)) => value::Value::I16(v),
+        (Kind::I32, Wire::I32(v)) => value::Value::I32(v),
+        (Kind::I64, Wire::I64(v)) => value::Value::I64(v),
+        (Kind::Double, Wire::Double(v)) => value::Value::from_f64(v),
+        (Kind::String, Wire::Binary(v)) => match String::from_utf8(v) {
+            Ok(v) => value::Value::String(v),
+            Err(e) => value::Value::Bytes(e.into_bytes()),
+        },
+        (Kind::Binary, Wire::Binary(v)) => value::Value::Bytes(v),
+        (Kind::Uuid, Wire::Uuid(v)) => value::Value::String(format_uuid(&v)),
+        (Kind::Enum(e), Wire::I32(v)) => match e.values.iter().find(|(_, n)| *n == v) {
+            Some((name, _)) => value::Value::String(name.clone()),
+            None => value::Value::I32(v),
+        },
+        (Kind::Struct(s), Wire::Struct(mut fields)) => {
+            let mut entries = Vec::with_capacity(fields.len());
+            for field in &s.fields {
+                if let Some(i) = fields.iter().position(|(id, _)| *id == field.id) {
+                    let (_, wire) = fields.remove(i);
+                    let v = to_value(idl, wire, &idl.resolve(&field.type_)?).map_err(|e| {
+                        error::Error::Format {
+                            msg: format!("{}.{}: {}", s.name, field.name, e),
+                        }
+                    })?;
+                    entries.push((value::Value::String(field.name.clone()), v));
+                }
+            }
+            for (id, _) in fields {
+                debug!("Skipping unknown field {} of Thrift struct {}", id, s.name);
+            }
+            value::Value::Map(entries)
+        }
+        (Kind::List(element), Wire::List(_, items))
+        | (Kind::Set(element), Wire::Set(_, items))
+        // Sets and lists are told apart by the schema, so accept either
+        | (Kind::List(element), Wire::Set(_, items))
+        | (Kind::Set(element), Wire::List(_, items)) => {
+            let element = idl.resolve(element)?;
+            value::Value::Sequence(
+                items
+                    .into_iter()
+                    .map(|item| to_value(idl, item, &element))
+                    .collect::<error::Result<_>>()?,
+            )
+        }
+        (Kind::Map(key, v), Wire::Map(_, _, entries)) => {
+            let (key, v) = (idl.resolve(key)?, idl.resolve(v)?);
+            value::Value::Map(
+                entries
+                    .into_iter()
+                    .map(|(k, item)| Ok((to_value(idl, k, &key)?, to_value(idl, item, &v)?)))
+                    .collect::<error::Result<_>>()?,
+            )
+        }
+        (kind, wire) => {
+            return Err(error::Error::Format {
+                msg: format!("expected {}, got {}", kind.name(), wire.name()),
+            })
+        }
+    })
+}
+
+/// Converts a value for encoding according to its type in the schema.
+fn from_value(idl: &Idl, v: &value::Value, kind: &Kind) -> error::Result<Wire> {
+    let mismatch = || error::Error::Format {
+        msg: format!(
+            "expected {}, got: {}",
+            kind.name(),
+            v.summary(value::ERROR_SUMMARY_LEN)
+        ),
+    };
+    let integer = |min: i64, max: i64| {
+        v.as_i64()
+            .filter(|n| (min..=max).contains(n))
+            .ok_or_else(mismatch)
+    };
+    Ok(match *kind {
+        Kind::Bool => Wire::Bool(v.as_bool().ok_or_else(mismatch)?),
+        Kind::Byte => Wire::Byte(integer(i8::MIN.into(), i8::MAX.into())? as i8),
+        Kind::I16 => Wire::I16(integer(i16::MIN.into(), i16::MAX.into())? as i16),
+        Kind::I32 => Wire::I32(integer(i32::MIN.into(), i32::MAX.into())? as i32),
+        Kind::I64 => Wire::I64(integer(i64::MIN, i64::MAX)?),
+        Kind::Double => Wire::Double(v.as_f64().ok_or_else(mismatch)?),
+        Kind::String | Kind::Binary => Wire::Binary(v.as_bytes().ok_or_else(mismatch)?.to_vec()),
+        Kind::Uuid => Wire::Uuid(v.as_str().and_then(parse_uuid).ok_or_else(mismatch)?),
+        Kind::Enum(e) => match v.as_str() {
+            Some(name) => Wire::I32(
+                e.values
+                    .iter()
+                    .find(|(n, _)| n == name)
+                    .map(|(_, n)| *n)
+                    .ok_or_else(mismatch)?,
+            ),
+            None => Wire::I32(integer(i32::MIN.into(), i32::MAX.into())? as i32),
+        },
+        Kind::Struct(s) => {
+            let entries = v.as_map().ok_or_else(mismatch)?;
+            for (k, _) in entries {
+                if !s.fields.iter().any(|f| k.as_str() == Some(&f.name)) {
+                    return Err(error::Error::Format {
+                        msg: format!("Thrift struct {} has no field {}", s.name, k),
+                    });
+                }
+            }
+            let mut fields = Vec::new();
+            for field in &s.fields {
+                let field_value = entries
+                    .iter()
+                    .find(|(k, _)| k.as_str() == Some(&field.name))
+                    .map(|(_, v)| v)
+                    .filter(|v| !v.is_unit());
+                match field_value {
+                    Some(field_value) => {
+                        let wire = from_value(idl, field_value, &idl.resolve(&field.type_)?)
+                            .map_err(|e| error::Error::Format {
+                                msg: format!("{}.{}: {}", s.name, field.name, e),
+                            })?;
+                        fields.push((field.id, wire));
+                    }
+                    None if field.required => {
+                        return Err(error::Error::Format {
+                            msg: format!(
+                                "{}.{} is required, but missing in: {}",
+                                s.name,
+                                field.name,
+                                v.summary(value::ERROR_SUMMARY_LEN)
+                            ),
+                        })
+                    }
+                    None => (),
+                }
+            }
+            Wire::Struct(fields)
+        }
+        Kind::List(element) | Kind::Set(element) => {
+            let items = v.as_sequence().ok_or_else(mismatch)?;
+            let element = idl.resolve(element)?;
+            let items = items
+                .iter()
+                .map(|item| from_value(idl, item, &element))
+                .collect::<error::Result<_>>()?;
+            match kind {
+                Kind::Set(_) => Wire::Set(element.type_id(), items),
+                _ => Wire::List(element.type_id(), items),
+            }
+        }
+        Kind::Map(key, item) => {
+            let entries = v.as_map().ok_or_else(mismatch)?;
+            let (key, item) = (idl.resolve(key)?, idl.resolve(item)?);
+            Wire::Map(
+                key.type_id(),
+                item.type_id(),
+                entries
+                    .iter()
+                    .map(|(k, v)| Ok((from_value(idl, k, &key)?, from_value(idl, v, &item)?)))
+                    .collect::<error::Result<_>>()?,
+            )
+        }
+    })
+}
+
+impl Kind<'_> {
+    /// The type id of the binary protocol.
+    fn type_id(&self) -> u8 {
+        match *self {
+            Kind::Bool => T_BOOL,
+            Kind::Byte => T_BYTE,
+            Kind::I16 => T_I16,
+            Kind::I32 | Kind::Enum(_) => T_I32,
+            Kind::I64 => T_I64,
+            Kind::Double => T_DOUBLE,
+            Kind::String | Kind::Binary => T_STRING,
+            Kind::Uuid => T_UUID,
+            Kind::List(_) => T_LIST,
+            Kind::Set(_) => T_SET,
+            Kind::Map(_, _) => T_MAP,
+            Kind::Struct(_) => T_STRUCT,
+        }
+    }
+
+    fn name(&self) -> String {
+        match *self {
+            Kind::Bool => "bool".to_owned(),
+            Kind::Byte => "byte".to_owned(),
+            Kind::I16 => "i16".to_owned(),
+            Kind::I32 => "i32".to_owned(),
+            Kind::I64 => "i64".to_owned(),
+            Kind::Double => "double".to_owned(),
+            Kind::String => "string".to_owned(),
+            Kind::Binary => "binary".to_owned(),
+            Kind::Uuid => "uuid".to_owned(),
+            Kind::List(_) => "list".to_owned(),
+            Kind::Set(_) => "set".to_owned(),
+            Kind::Map(_, _) => "map".to_owned(),
+            Kind::Enum(e) => format!("enum {}", e.name),
+            Kind::Struct(s) => format!("struct {}", s.name),
+        }
+    }
+}
+
+impl Wire {
+    fn name(&self) -> &'static str {
+        match *self {
+            Wire::Bool(_) => "bool",
+            Wire::Byte(_) => "byte",
+            Wire::I16(_) => "i16",
+            Wire::I32(_) => "i32",
+            Wire::I64(_) => "i64",
+            Wire::Double(_) => "double",
+            Wire::Binary(_) => "string",
+            Wire::Uuid(_) => "uuid",
+            Wire::Struct(_) => "struct",
+            Wire::List(_, _) => "list",
+            Wire::Set(_, _) => "set",
+            Wire::Map(_, _, _) => "map",
+        }
+    }
+}
+
+/// Decodes values of either protocol.
+struct Reader<'a> {
+    input: &'a [u8],
+    offset: usize,
+    protocol: Protocol,
+}
+
+impl Reader<'_> {
+    fn bytes(&mut self, len: usize) -> error::Result<&[u8]> {
+        let start = self.offset;
+        let bytes = start
+            .checked_add(len)
+            .and_then(|end| self.input.get(start..end))
+            .ok_or_else(|| invalid(start, "the input ends in the middle of a struct"))?;
+        self.offset += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> error::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn fixed<const N: usize>(&mut self) -> error::Result<[u8; N]> {
+        let mut bytes = [0; N];
+        bytes.copy_from_slice(self.bytes(N)?);
+        Ok(bytes)
+    }
+
+    fn varint(&mut self) -> error::Result<u64> {
+        let start = self.offset;
+        let mut v = 0u64;
+        for shift in (0..64).step_by(7) {
+            let b = self.u8()?;
+            v |= u64::from(b & 0x7f) << shift;
+            if b & 0x80 == 0 {
+                return Ok(v);
+            }
+        }
+        Err(invalid(start, "a variable-length integer is too long"))
+    }
+
+    fn zigzag(&mut self) -> error::Result<i64> {
+        let v = self.varint()?;
+        Ok((v >> 1) as i64 ^ -((v & 1) as i64))
+    }
+
+    fn i16(&mut self) -> error::Result<i16> {
+        Ok(match self.protocol {
+            Protocol::Binary => i16::from_be_bytes(self.fixed()?),
+            Protocol::Compact => self.zigzag()? as i16,
+        })
+    }
+
+    fn i32(&mut self) -> error::Result<i32> {
+        Ok(match self.protocol {
+            Protocol::Binary => i32::from_be_bytes(self.fixed()?),
+            Protocol::Compact => self.zigzag()? as i32,
+        })
+    }
+
+    fn size(&mut self) -> error::Result<usize> {
+        let start = self.offset;
+        let size = match self.protocol {
+            Protocol::Binary => i64::from(i32::from_be_bytes(self.fixed()?)),
+            Protocol::Compact => self.varint()? as i64,
+        };
+        // Every element takes at least a byte, so larger sizes must be corrupt
+        usize::try_from(size)
+            .ok()
+            .filter(|&size| size <= self.input.len() - self.offset)
+            .ok_or_else(|| invalid(start, &format!("invalid size {}", size)))
+    }
+
+    fn struct_(&mut self, depth: usize) -> error::Result<Wire> {
+        if depth > MAX_DEPTH {
+            return Err(invalid(self.offset, "structs are nested too deeply"));
+        }
+        let mut fields = Vec::new();
+        let mut last_id = 0i16;
+        loop {
+            let start = self.offset;
+            let header = self.u8()?;
+            let (type_id, id, bool_value) = match self.protocol {
+                Protocol::Binary => {
+                    if header == T_STOP {
+                        break;
+                    }
+                    (header, self.i16()?, None)
+                }
+                Protocol::Compact => {
+                    if header == 0 {
+                        break;
+                    }
+                    let delta = header >> 4;
+                    let id = if delta == 0 {
+                        self.i16()?
+                    } else {
+                        last_id.wrapping_add(i16::from(delta))
+                    };
+                    last_id = id;
+                    let compact = header & 0x0f;
+                    let bool_value = match compact {
+                        1 => Some(true),
+                        2 => Some(false),
+                        _ => None,
+                    };
+                    (from_compact(compact, start)?, id, bool_value)
+                }
+            };
+            let v = match bool_value {
+                Some(v) => Wire::Bool(v),
+                None => self.value(type_id, depth)?,
+            };
+            fields.push((id, v));
+        }
+        Ok(Wire::Struct(fields))
+    }
+
+    fn value(&mut self, type_id: u8, depth: usize) -> error::Result<Wire> {
+        let start = self.offset;
+        Ok(match type_id {
+            T_BOOL => match self.protocol {
+                Protocol::Binary => Wire::Bool(self.u8()? != 0),
+                // In collections, booleans are a byte of their compact type
+                Protocol::Compact => Wire::Bool(self.u8()? == 1),
+            },
+            T_BYTE => Wire::Byte(self.u8()? as i8),
+            T_I16 => Wire::I16(self.i16()?),
+            T_I32 => Wire::I32(self.i32()?),
+            T_I64 => Wire::I64(match self.protocol {
+                Protocol::Binary => i64::from_be_bytes(self.fixed()?),
+                Protocol::Compact => self.zigzag()?,
+            }),
+            T_DOUBLE => Wire::Double(match self.protocol {
+                Protocol::Binary => f64::from_be_bytes(self.fixed()?),
+                Protocol::Compact => f64::from_le_bytes(self.fixed()?),
+            }),
+            T_STRING => {
+                let len = self.size()?;
+                Wire::Binary(self.bytes(len)?.to_vec())
+            }
+            T_UUID => Wire::Uuid(self.fixed()?),
+            T_STRUCT => self.struct_(depth + 1)?,
+            T_LIST | T_SET => {
+                if depth > MAX_DEPTH {
+                    return Err(invalid(start, "collections are nested too deeply"));
+                }
+                let (element, size) = match self.protocol {
+                    Protocol::Binary => (self.u8()?, self.size()?),
+                    Protocol::Compact => {
+                        let header = self.u8()?;
+                        let size = match header >> 4 {
+                            15 => self.size()?,
+                            size => usize::from(size),
+                        };
+                        (from_compact(header & 0x0f, start)?, size)
+                    }
+                };
+                let items = (0..size)
+                    .map(|_| self.value(element, depth + 1))
+                    .collect::<error::Result<_>>()?;
+                if type_id == T_SET {
+                    Wire::Set(element, items)
+                } else {
+                    Wire::List(element, items)
+                }
+            }
+            T_MAP => {
+                if depth > MAX_DEPTH {
+                    return Err(invalid(start, "collections are nested too deeply"));
+                }
+                let (key, item, size) = match self.protocol {
+                    Protocol::Binary => (self.u8()?, self.u8()?, self.size()?),
+                    Protocol::Compact => {
+                        let size = self.size()?;
+                        if size == 0 {
+                            (T_STOP, T_STOP, 0)
+                        } else {
+                            let types = self.u8()?;
+                            (
+                                from_compact(types >> 4, start)?,
+                                from_compact(types & 0x0f, start)?,
+                                size,
+                            )
+                        }
+                    }
+                };
+                let mut entries = Vec::with_capacity(size);
+                for _ in 0..size {
+                    let k = self.value(key, depth + 1)?;
+                    entries.push((k, self.value(item, depth + 1)?));
+                }
+                Wire::Map(key, item, entries)
+            }
+            _ => return Err(invalid(start, &format!("unknown type {}", type_id))),
+        })
+    }
+}
+
+/// Encodes values in either protocol.
+struct Writer {
+    output: Vec<u8>,
+    protocol: Protocol,
+}
+
+impl Writer {
+    fn varint(&mut self, mut v: u64) {
+        while v >= 0x80 {
+            self.output.push(v as u8 | 0x80);
+            v >>= 7;
+        }
+        self.output.push(v as u8);
+    }
+
+    fn zigzag(&mut self, v: i64) {
+        self.varint(((v << 1) ^ (v >> 63)) as u64);
+    }
+
+    fn size(&mut self, size: usize) -> error::Result<()> {
+        match self.protocol {
+            Protocol::Binary => {
+                let size = i32::try_from(size).map_err(|_| error::Error::Format {
+                    msg: format!("{} elements are too many for Thrift", size),
+                })?;
+                self.output.extend(size.to_be_bytes());
+            }
+            Protocol::Compact => self.varint(size as u64),
+        }
+        Ok(())
+    }
+
+    fn value(&mut self, wire: &Wire) -> error::Result<()> {
+        match *wire {
+            Wire::Bool(v) => match self.protocol {
+                Protocol::Binary => self.output.push(v as u8),
+                Protocol::Compact => self.output.push(if v { 1 } else { 2 }),
+            },
+            Wire::Byte(v) => self.output.push(v as u8),
+            Wire::I16(v) => match self.protocol {
+                Protocol::Binary => self.output.extend(v.to_be_bytes()),
+                Protocol::Compact => self.zigzag(v.into()),
+            },
+            Wire::I32(v) => match self.protocol {
+                Protocol::Binary => self.output.extend(v.to_be_bytes()),
+                Protocol::Compact => self.zigzag(v.into()),
+            },
+            Wire::I64(v) => match self.protocol {
+                Protocol::Binary => self.output.extend(v.to_be_bytes()),
+                Protocol::Compact => self.zigzag(v),
+            },
+            Wire::Double(v) => match self.protocol {
+                Protocol::Binary => self.output.extend(v.to_be_bytes()),
+                Protocol::Compact => self.output.extend(v.to_le_bytes()),
+            },
+            Wire::Binary(ref v) => {
+                self.size(v.len())?;
+                self.output.extend(v);
+            }
+            Wire::Uuid(ref v) => self.output.extend(v),
+            Wire::Struct(ref fields) => {
+                let mut last_id = 0i16;
+                for (id, v) in fields {
+                    let type_id = v.type_id();
+                    match self.protocol {
+                        Protocol::Binary => {
+                            self.output.push(type_id);
+                            self.output.extend(id.to_be_bytes());
+                        }
+                        Protocol::Compact => {
+                            // Booleans are encoded in the type of their field
+                            let compact = match *v {
+                                Wire::Bool(b) => {
+                                    if b {
+                                        1
+                                    } else {
+                                        2
+                                    }
+                                }
+                                _ => to_compact(type_id),
+                            };
+                            let delta = i32::from(*id) - i32::from(last_id);
+                            if (1..=15).contains(&delta) {
+                                self.output.push((delta as u8) << 4 | compact);
+                            } else {
+                                self.output.push(compact);
+                                self.zigzag((*id).into());
+                            }
+                            last_id = *id;
+                        }
+                    }
+                    if let (Protocol::Compact, Wire::Bool(_)) = (self.protocol, v) {
+                        continue;
+                    }
+                    self.value(v)?;
+                }
+                self.output.push(T_STOP);
+            }
+            Wire::List(element, ref items) | Wire::Set(element, ref items) => {
+                match self.protocol {
+                    Protocol::Binary => {
+                        self.output.push(element);
+                        self.size(items.len())?;
+                    }
+                    Protocol::Compact => {
+                        let compact = to_compact(element);
+                        if items.len() < 15 {
+                            self.output.push((items.len() as u8) << 4 | compact);
+                        } else {
+                            self.output.push(0xf0 | compact);
+                            self.size(items.len())?;
+                        }
+                    }
+                }
+                for item in items {
+                    self.value(item)?;
+                }
+            }
+            Wire::Map(key, item, ref entries) => {
+                match self.protocol {
+                    Protocol::Binary => {
+                        self.output.push(key);
+                        self.output.push(item);
+                        self.size(entries.len())?;
+                    }
+                    Protocol::Compact => {
+                        self.size(entries.len())?;
+                        if !entries.is_empty() {
+                            self.output.push(to_compact(key) << 4 | to_compact(item));
+                        }
+                    }
+                }
+                for (k, v) in entries {
+                    self.value(k)?;
+                    self.value(v)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Wire {
+    fn type_id(&self) -> u8 {
+        match *self {
+            Wire::Bool(_) => T_BOOL,
+            Wire::Byte(_) => T_BYTE,
+            Wire::I16(_) => T_I16,
+            Wire::I32(_) => T_I32,
+            Wire::I64(_) => T_I64,
+            Wire::Double(_) => T_DOUBLE,
+            Wire::Binary(_) => T_STRING,
+            Wire::Uuid(_) => T_UUID,
+            Wire::Struct(_) => T_STRUCT,
+            Wire::List(_, _) => T_LIST,
+            Wire::Set(_, _) => T_SET,
+            Wire::Map(_, _, _) => T_MAP,
+        }
+    }
+}
+
+/// The type id of the binary protocol for a type of the compact protocol.
+fn from_compact(compact: u8, offset: usize) -> error::Result<u8> {
+    Ok(match compact {
+        1 | 2 => T_BOOL,
+        3 => T_BYTE,
+        4 => T_I16,
+        5 => T_I32,
+        6 => T_I64,
+        7 => T_DOUBLE,
+        8 => T_STRING,
+        9 => T_LIST,
+        10 => T_SET,
+        11 => T_MAP,
+        12 => T_STRUCT,
+        13 => T_UUID,
+        _ => {
+            return Err(invalid(
+                offset,
+                &format!("unknown compact type {}", compact),
+            ))
+        }
+    })
+}
+
+fn to_compact(type_id: u8) -> u8 {
+    match type_id {
+        T_BOOL => 1,
+        T_BYTE => 3,
+        T_I16 => 4,
+        T_I32 => 5,
+        T_I64 => 6,
+        T_DOUBLE => 7,
+        T_STRING => 8,
+        T_LIST => 9,
+        T_SET => 10,
+        T_MAP => 11,
+        T_STRUCT => 12,
+        T_UUID => 13,
+        _ => 0,
+    }
+}
+
+fn format_uuid(bytes: &[u8; 16]) -> String {
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+fn parse_uuid(s: &str) -> Option<[u8; 16]> {
+    let hex: String = s.chars().filter(|&c| c != '-').collect();
+    if hex.len() != 32 {
+        return None;
+    }
+    let mut bytes = [0; 16];
+    for (i, b) in bytes.iter_mut().enumerate() {
+        *b = u8::from_str_radix(hex.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+    Some(bytes)
+}
+
+fn invalid(offset: usize, msg: &str) -> error::Error {
+    error::Error::Format {
+        msg: format!("invalid Thrift struct at byte {}: {}", offset, msg),
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(String),
+    String(String),
+    Punct(char),
+}
+
+/// Splits an IDL into tokens, each with its line number.  Comments are like in C, or start with
+/// `#`.
+fn tokenize(text: &str) -> error::Result<Vec<(Token, usize)>> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    let mut line = 1;
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => line += 1,
+            c if c.is_whitespace() => (),
+            '#' => {
+                while chars.peek().is_some_and(|&c| c != '\n') {
+                    chars.next();
+                }
+            }
+            '/' if chars.peek() == Some(&'/') => {
+                while chars.peek().is_some_and(|&c| c != '\n') {
+                    chars.next();
+                }
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut previous = ' ';
+                loop {
+                    match chars.next() {
+                        Some('/') if previous == '*' => break,
+                        Some(c) => {
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            previous = c;
+                        }
+                        None => return Err(syntax_error(line, "unterminated comment".to_owned())),
+                    }
+                }
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let mut ident = c.to_string();
+                while let Some(&c) = chars
+                    .peek()
+                    .filter(|c| c.is_ascii_alphanumeric() || **c == '_' || **c == '.')
+                {
+                    ident.push(c);
+                    chars.next();
+                }
+                tokens.push((Token::Ident(ident), line));
+            }
+            c if c.is_ascii_digit() || c == '-' || c == '+' => {
+                let mut number = c.to_string();
+                while let Some(&c) = chars
+                    .peek()
+                    .filter(|c| c.is_ascii_alphanumeric() || **c == '.' || **c == '-' || **c == '+')
+                {
+                    number.push(c);
+                    chars.next();
+                }
+                tokens.push((Token::Number(number), line));
+            }
+            '"' | '\'' => {
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        Some(q) if q == c => break,
+                        Some('\\') => s.extend(chars.next()),
+                        Some(c) => {
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            s.push(c);
+                        }
+                        None => return Err(syntax_error(line, "unterminated string".to_owned())),
+                    }
+                }
+                tokens.push((Token::String(s), line));
+            }
+            c if "{}<>()[],;:=*".contains(c) => tokens.push((Token::Punct(c), line)),
+            c => return Err(syntax_error(line, format!("unexpected character {:?}", c))),
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: Vec<(Token, usize)>,
+    next: usize,
+    /// The prefix of the names that are declared, for included files.
+    prefix: &'a str,
+}
+
+impl Parser<'_> {
+    fn next_token(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.next).map(|(token, _)| token.clone());
+        self.next += 1;
+        token
+    }
+
+    fn at(&self, c: char) -> bool {
+        matches!(self.tokens.get(self.next), Some((Token::Punct(p), _)) if *p == c)
+    }
+
+    fn peek_ident(&self) -> Option<&str> {
+        match self.tokens.get(self.next) {
+            Some((Token::Ident(ident), _)) => Some(ident),
+            _ => None,
+        }
+    }
+
+    fn error(&self, msg: String) -> error::Error {
+        let line = self
+            .tokens
+            .get(
+                self.next
+                    .saturating_sub(1)
+                    .min(self.tokens.len().saturating_sub(1)),
+            )
+            .map_or(1, |&(_, line)| line);
+        syntax_error(line, msg)
+    }
+
+    fn expect(&mut self, c: char) -> error::Result<()> {
+        match self.next_token() {
+            Some(Token::Punct(p)) if p == c => Ok(()),
+            Some(token) => Err(self.error(format!("expected '{}', got {}", c, token))),
+            None => Err(self.error(format!("expected '{}' at the end", c))),
+        }
+    }
+
+    fn ident(&mut self) -> error::Result<String> {
+        match self.next_token() {
+            Some(Token::Ident(ident)) => Ok(ident),
+            Some(token) => Err(self.error(format!("expected a name, got {}", token))),
+            None => Err(self.error("expected a name at the end".to_owned())),
+        }
+    }
+
+    fn string(&mut self) -> error::Result<String> {
+        match self.next_token() {
+            Some(Token::String(s)) => Ok(s),
+            Some(token) => Err(self.error(format!("expected a string, got {}", token))),
+            None => Err(self.error("expected a string at the end".to_owned())),
+        }
+    }
+
+    /// Skips an optional `,` or `;` after a declaration.
+    fn separator(&mut self) {
+        if self.at(',') || self.at(';') {
+            self.next += 1;
+        }
+    }
+
+    /// Skips annotations, like `(python.immutable = "")`.
+    fn annotations(&mut self) -> error::Result<()> {
+        if self.at('(') {
+            self.skip_value()?;
+        }
+        Ok(())
+    }
+
+    /// Skips a token, or a bracketed group of tokens.
+    fn skip_value(&mut self) -> error::Result<()> {
+        let mut depth = 0usize;
+        loop {
+            match self.next_token() {
+                Some(Token::Punct('{')) | Some(Token::Punct('[')) | Some(Token::Punct('(')) => {
+                    depth += 1
+                }
+                Some(Token::Punct('}')) | Some(Token::Punct(']')) | Some(Token::Punct(')')) => {
+                    depth = depth
+                        .checked_sub(1)
+                        .ok_or_else(|| self.error("unbalanced brackets".to_owned()))?;
+                }
+                Some(_) => (),
+                None => return Err(self.error("unbalanced brackets".to_owned())),
+            }
+            if depth == 0 {
+                return Ok(());
+            }
+        }
+    }
+
+    fn type_(&mut self) -> error::Result<Type> {
+        let name = self.ident()?;
+        let type_ = match name.as_str() {
+            "bool" => Type::Bool,
+            "byte" | "i8" => Type::Byte,
+            "i16" => Type::I16,
+            "i32" => Type::I32,
+            "i64" => Type::I64,
+            "double" => Type::Double,
+            "string" => Type::String,
+            "binary" => Type::Binary,
+            "uuid" => Type::Uuid,
+            "list" | "set" => {
+                self.expect('<')?;
+                let element = Box::new(self.type_()?);
+                self.expect('>')?;
+                if name == "list" {
+                    Type::List(element)
+                } else {
+                    Type::Set(element)
+                }
+            }
+            "map" => {
+                self.expect('<')?;
+                let key = Box::new(self.type_()?);
+                self.expect(',')?;
+                let v = Box::new(self.type_()?);
+                self.expect('>')?;
+                Type::Map(key, v)
+            }
+            // Types of included files are already qualified by the name of the file
+            name if name.contains('.') => Type::Named(name.to_owned()),
+            name => Type::Named(format!("{}{}", self.prefix, name)),
+        };
+        self.annotations()?;
+        Ok(type_)
+    }
+
+    fn enum_values(&mut self) -> error::Result<Vec<(String, i32)>> {
+        self.expect('{')?;
+        let mut values = Vec::new();
+        let mut next = 0i32;
+        while !self.at('}') {
+            let name = self.ident()?;
+            if self.at('=') {
+                self.next += 1;
+                next = match self.next_token() {
+                    Some(Token::Number(n)) => parse_integer(&n)
+                        .and_then(|n| i32::try_from(n).ok())
+                        .ok_or_else(|| self.error(format!("invalid enum value {}", n)))?,
+                    _ => return Err(self.error(format!("{} has no value", name))),
+                };
+            }
+            values.push((name, next));
+            next = next.wrapping_add(1);
+            self.annotations()?;
+            self.separator();
+        }
+        self.next += 1;
+        Ok(values)
+    }
+
+    fn fields(&mut self) -> error::Result<Vec<Field>> {
+        self.expect('{')?;
+        let mut fields = Vec::new();
+        // Fields without an id get negative ones, like the Thrift compiler assigns
+        let mut implicit = 0i16;
+        while !self.at('}') {
+            let id = match self.tokens.get(self.next) {
+                Some((Token::Number(n), _)) => {
+                    let id = parse_integer(n)
+                        .and_then(|n| i16::try_from(n).ok())
+                        .ok_or_else(|| self.error(format!("invalid field id {}", n)))?;
+                    self.next += 1;
+                    self.expect(':')?;
+                    id
+                }
+                _ => {
+                    implicit -= 1;
+                    implicit
+                }
+            };
+            let required = match self.peek_ident() {
+                Some("required") => {
+                    self.next += 1;
+                    true
+                }
+                Some("optional") => {
+                    self.next += 1;
+                    false
+                }
+                _ => false,
+            };
+            let type_ = self.type_()?;
+            let name = self.ident()?;
+            if self.at('=') {
+                self.next += 1;
+                self.skip_value()?;
+            }
+            self.annotations()?;
+            self.separator();
+            fields.push(Field {
+                id,
+                name,
+                type_,
+                required,
+            });
+        }
+        self.next += 1;
+        Ok(fields)
+    }
+}
+
+fn parse_integer(s: &str) -> Option<i64> {
+    let (negative, digits) = match s.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, s.strip_prefix('+').unwrap_or(s)),
+    };
+    let n = match digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        Some(hex) => i64::from_str_radix(hex, 16).ok()?,
+        None => digits.parse().ok()?,
+    };
+    Some(if negative { -n } else { n })
+}
+
+fn syntax_error(line: usize, msg: String) -> error::Error {
+    error::Error::Format {
+        msg: format!("Thrift IDL line {}: {}", line, msg),
+    }
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Token::Ident(ref ident) => write!(f, "{:?}", ident),
+            Token::Number(ref n) => f.write_str(n),
+            Token::String(ref s) => write!(f, "{:?}", s),
+            Token::Punct(c) => write!(f, "'{}'", c),
+        }
+    }
+}
+
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Protocol::Binary => "binary",
+            Protocol::Compact => "compact",
+        })
+    }
+}
+
+impl str::FromStr for Protocol {
+    type Err = error::Error;
+
+    fn from_str(s: &str) -> error::Result<Self> {
+        match s {
+            "binary" => Ok(Protocol::Binary),
+            "compact" => Ok(Protocol::Compact),
+            _ => Err(error::Error::Message(format!(
+                "Thrift protocols are 'binary' and 'compact', got: {}",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Debug for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ThriftSource")
+            .field("struct_name", &self.struct_name)
+            .field("protocol", &self.protocol)
+            .finish()
+    }
+}
+
+impl<W> fmt::Debug for Sink<W> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ThriftSink")
+            .field("struct_name", &self.struct_name)
+            .field("protocol", &self.protocol)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::env;
+
+    #[test]
+    fn test_round_trip() {
+        use crate::value::Sink as _;
+        use crate::value::Source as _;
+
+        let dir = env::temp_dir().join(format!("rq-thrift-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("shared.thrift"),
+            "struct Address { 1: string city }",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("user.thrift"),
+            r#"
+namespace java com.example // Comments like in C
+include "shared.thrift"
+
+/* Statuses */
+enum Status { ACTIVE = 1, DISABLED }
+typedef i64 Timestamp
+const i32 MAX = 10
+const list<string> NAMES = ["a", "b"]
+
+struct User {
+  1: required i32 id,
+  2: string name (go.tag = "json");
+  3: optional bool ok = true
+  4: Status status
+  5: list<Timestamp> logins
+  6: map<string, shared.Address> addresses
+  7: set<i16> codes
+  8: binary avatar
+  9: double score
+  10: uuid key
+  20: shared.Address home
+}
+
+service Users {
+  User get(1: i32 id)
+}
+"#,
+        )
+        .unwrap();
+        let idl = || Idl::load(&dir.join("user.thrift")).unwrap();
+        let read = |protocol, input: &[u8]| -> error::Result<Vec<String>> {
+            let mut reader = source(idl(), "User", protocol, input)?;
+            let mut records = Vec::new();
+            while let Some(record) = reader.read()? {
+                records.push(record.to_string());
+            }
+            Ok(records)
+        };
+
+        // {1: 5, 2: "hi", 3: true}, followed by {1: -1}
+        let binary = [
+            8, 0, 1, 0, 0, 0, 5, 11, 0, 2, 0, 0, 0, 2, b'h', b'i', 2, 0, 3, 1, 0, 8, 0, 1, 255,
+            255, 255, 255, 0,
+        ];
+        let compact = [0x15, 10, 0x18, 2, b'h', b'i', 0x11, 0, 0x15, 1, 0];
+        let expected = vec![
+            r#"{"id": 5, "name": "hi", "ok": true}"#.to_owned(),
+            r#"{"id": -1}"#.to_owned(),
+        ];
+        assert_eq!(read(Protocol::Binary, &binary).unwrap(), expected);
+        assert_eq!(read(Protocol::Compact, &compact).unwrap(), expected);
+
+        let record = r#"{"id": 1, "name": "Jo", "ok": false, "status": "DISABLED", "logins": [1700000000, -2], "addresses": {"work": {"city": "Oslo"}}, "codes": [200, 404], "avatar": 0x00ff, "score": 0.5, "key": "123e4567-e89b-12d3-a456-426614174000", "home": {"city": "Bergen"}}"#;
+        let json = record.replace(", \"avatar\": 0x00ff", "");
+        let mut value =
+            value::Value::from(serde_json::from_str::<serde_json::Value>(&json).unwrap());
+        if let value::Value::Map(ref mut entries) = value {
+            entries.insert(7, ("avatar".into(), value::Value::Bytes(vec![0, 255])));
+        }
+        for protocol in &[Protocol::Binary, Protocol::Compact] {
+            let mut output = Vec::new();
+            {
+                let mut writer = sink(idl(), "User", *protocol, &mut output).unwrap();
+                writer.write(value.clone()).unwrap();
+                writer.write(value.clone()).unwrap();
+            }
+            let records = read(*protocol, &output).unwrap();
+            assert_eq!(records.len(), 2);
+            assert_eq!(records[0], record, "{}", protocol);
+            assert!(read(*protocol, &output[..output.len() - 1]).is_err());
+        }
+
+        let mut writer = sink(idl(), "User", Protocol::Binary, io::sink()).unwrap();
+        assert!(writer.write(value!({"name": "x"})).is_err());
+        assert!(writer.write(value!({"id": 1, "nope": 2})).is_err());
+        assert!(writer.write(value!({"id": 1, "status": "GONE"})).is_err());
+        assert!(sink(idl(), "Nope", Protocol::Binary, io::sink()).is_err());
+        assert!(Idl::parse("struct A { 1: i32 }").is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,4 +1,5 @@
 use std::io;
+use std::str;
 
 use serde;
 use toml;
@@ -10,7 +11,18 @@ use crate::value;
 pub struct Source(Option<String>);
 
 #[derive(Debug)]
-pub struct Sink<W: io::Write>(W);
+pub struct Sink<W: io::Write>(W, NullPolicy, Option<String>);
+
+/// What to do with null (unit) values, which TOML has no way of representing.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum NullPolicy {
+    /// Leave out map entries and sequence elements that are null, and skip null records.
+    Omit,
+    /// Replace nulls with empty strings.
+    EmptyString,
+    /// Fail with an error when encountering a null.
+    Error,
+}
 
 #[inline]
 pub fn source<R>(mut r: R) -> error::Result<Source>
@@ -22,12 +34,14 @@ where
     Ok(Source(Some(string)))
 }
 
+/// Creates a TOML sink.  TOML documents must be tables, so if `wrap_key` is specified, records
+/// that aren't maps are written as a table with the record stored under that key.
 #[inline]
-pub fn sink<W>(w: W) -> Sink<W>
+pub fn sink<W>(w: W, nulls: NullPolicy, wrap_key: Option<String>) -> Sink<W>
 where
     W: io::Write,
 {
-    Sink(w)
+    Sink(w, nulls, wrap_key)
 }
 
 impl value::Source for Source {
@@ -44,6 +58,12 @@ impl value::Source for Source {
             None => Ok(None),
         }
     }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = if self.0.is_some() { 1 } else { 0 };
+        (remaining, Some(remaining))
+    }
 }
 
 impl<W> value::Sink for Sink<W>
@@ -52,6 +72,25 @@ where
 {
     #[inline]
     fn write(&mut self, value: value::Value) -> error::Result<()> {
+        let value = match replace_nulls(value, self.1)? {
+            Some(value) => value,
+            None => return Ok(()),
+        };
+        let value = match (value, &self.2) {
+            (value @ value::Value::Map(_), _) => value,
+            (value, Some(key)) => {
+                value::Value::Map(vec![(value::Value::String(key.clone()), value)])
+            }
+            (value, None) => {
+                return Err(error::Error::Format {
+                    msg: format!(
+                        "TOML can only output maps (see --wrap-scalar), got: {}",
+                        value.summary(value::ERROR_SUMMARY_LEN)
+                    ),
+                })
+            }
+        };
+
         let mut string = String::new();
         {
             let ser = toml::ser::Serializer::new(&mut string);
@@ -62,4 +101,58 @@ where
         self.0.write_all(b"\n")?;
         Ok(())
     }
+
+    #[inline]
+    fn flush(&mut self) -> error::Result<()> {
+        self.0.flush()?;
+        Ok(())
+    }
+}
+
+/// Applies the null policy to the value, returning `None` if the value itself should be omitted.
+fn replace_nulls(value: value::Value, policy: NullPolicy) -> error::Result<Option<value::Value>> {
+    match value {
+        value::Value::Unit => match policy {
+            NullPolicy::Omit => Ok(None),
+            NullPolicy::EmptyString => Ok(Some(value::Value::String(String::new()))),
+            NullPolicy::Error => Err(error::Error::Format {
+                msg: "TOML cannot represent null values (see --toml-nulls)".to_owned(),
+            }),
+        },
+        value::Value::Sequence(seq) => {
+            let mut result = Vec::with_capacity(seq.len());
+            for v in seq {
+                if let Some(v) = replace_nulls(v, policy)? {
+                    result.push(v);
+                }
+            }
+            Ok(Some(value::Value::Sequence(result)))
+        }
+        value::Value::Map(map) => {
+            let mut result = Vec::with_capacity(map.len());
+            for (k, v) in map {
+                if let Some(v) = replace_nulls(v, policy)? {
+                    result.push((k, v));
+                }
+            }
+            Ok(Some(value::Value::Map(result)))
+        }
+        v => Ok(Some(v)),
+    }
+}
+
+impl str::FromStr for NullPolicy {
+    type Err = error::Error;
+
+    fn from_str(s: &str) -> error::Result<Self> {
+        match s {
+            "omit" => Ok(Self::Omit),
+            "empty-string" => Ok(Self::EmptyString),
+            "error" => Ok(Self::Error),
+            _ => Err(error::Error::Message(format!(
+                "unrecognized TOML null policy: {}",
+                s
+            ))),
+        }
+    }
 }
@@ -0,0 +1,518 @@
+//! Universal Binary JSON (UBJSON), as of draft 12 of the specification.
+//!
+//! Each top-level value is a record.  Both the plain and the optimized containers (with a `$`
+//! type and `#` count) are read, and strongly typed arrays of `uint8` become bytes.  High-precision
+//! numbers become integers if they fit in 64 bits, and strings otherwise.  The sink writes
+//! integers in the smallest type that fits them, bytes as optimized `uint8` arrays, and keys that
+//! aren't strings as their text, like `1`.
+
+use std::convert::TryFrom;
+use std::io;
+use std::str;
+
+use crate::error;
+use crate::value;
+
+const NULL: u8 = b'Z';
+const NO_OP: u8 = b'N';
+const TRUE: u8 = b'T';
+const FALSE: u8 = b'F';
+const INT8: u8 = b'i';
+const UINT8: u8 = b'U';
+const INT16: u8 = b'I';
+const INT32: u8 = b'l';
+const INT64: u8 = b'L';
+const FLOAT32: u8 = b'd';
+const FLOAT64: u8 = b'D';
+const HIGH_PRECISION: u8 = b'H';
+const CHAR: u8 = b'C';
+const STRING: u8 = b'S';
+const ARRAY_START: u8 = b'[';
+const ARRAY_END: u8 = b']';
+const OBJECT_START: u8 = b'{';
+const OBJECT_END: u8 = b'}';
+const TYPE: u8 = b'$';
+const COUNT: u8 = b'#';
+
+/// How many elements of an optimized container are allocated up front, which keeps a bogus count
+/// from allocating more than the input can fill.
+const MAX_PREALLOCATED: usize = 4096;
+/// How deeply arrays and objects are nested at most.
+const MAX_DEPTH: usize = 128;
+
+#[derive(Debug)]
+pub struct Source<R>
+where
+    R: io::Read,
+{
+    input: io::BufReader<R>,
+    position: u64,
+    /// How many containers the source is inside of.
+    depth: usize,
+}
+
+/// A UBJSON sink.  Each value is serialized into a buffer first, so that a record that fails to
+/// serialize leaves nothing behind in the output.
+#[derive(Debug)]
+pub struct Sink<W>(W, Vec<u8>)
+where
+    W: io::Write;
+
+#[inline]
+pub fn source<R>(r: R) -> Source<R>
+where
+    R: io::Read,
+{
+    Source {
+        input: io::BufReader::new(r),
+        position: 0,
+        depth: 0,
+    }
+}
+
+#[inline]
+pub fn sink<W>(w: W) -> Sink<W>
+where
+    W: io::Write,
+{
+    Sink(w, Vec::new())
+}
+
+impl<R> value::Source for Source<R>
+where
+    R: io::Read,
+{
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        // The input may only end between values, or after no-ops
+        loop {
+            match self.peek()? {
+                None => return Ok(None),
+                Some(NO_OP) => self.skip(),
+                Some(_) => break,
+            }
+        }
+        let start = self.position;
+        let marker = self.byte()?;
+        let v = self.typed(marker).map_err(|e| match e {
+            error::Error::Format { msg } => error::Error::Format {
+                msg: format!(
+                    "invalid UBJSON at byte {} of a value starting at byte {}: {}",
+                    self.position, start, msg
+                ),
+            },
+            e => e,
+        })?;
+        Ok(Some(v))
+    }
+}
+
+impl<R> Source<R>
+where
+    R: io::Read,
+{
+    fn peek(&mut self) -> error::Result<Option<u8>> {
+        use std::io::BufRead;
+
+        Ok(self.input.fill_buf()?.first().copied())
+    }
+
+    fn skip(&mut self) {
+        use std::io::BufRead;
+
+        self.input.consume(1);
+        self.position += 1;
+    }
+
+    fn byte(&mut self) -> error::Result<u8> {
+        let b = self.peek()?.ok_or_else(|| format_error("input ended"))?;
+        self.skip();
+        Ok(b)
+    }
+
+    fn bytes<const N: usize>(&mut self) -> error::Result<[u8; N]> {
+        use std::io::Read;
+
+        let mut bytes = [0; N];
+        match self.input.read_exact(&mut bytes) {
+            Ok(()) => (),
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                return Err(format_error("input ended"))
+            }
+            Err(e) => return Err(e.into()),
+        }
+        self.position += N as u64;
+        Ok(bytes)
+    }
+
+    fn data(&mut self, len: usize) -> error::Result<Vec<u8>> {
+        use std::io::Read;
+
+        let mut data = Vec::with_capacity(len.min(MAX_PREALLOCATED));
+        (&mut self.input).take(len as u64).read_to_end(&mut data)?;
+        self.position += data.len() as u64;
+        if data.len() != len {
+            return Err(format_error("input ended"));
+        }
+        Ok(data)
+    }
+
+    /// Reads the next value, after any no-ops.
+    fn value(&mut self) -> error::Result<value::Value> {
+        let marker = self.marker()?;
+        self.typed(marker)
+    }
+
+    fn marker(&mut self) -> error::Result<u8> {
+        loop {
+            match self.byte()? {
+                NO_OP => (),
+                marker => return Ok(marker),
+            }
+        }
+    }
+
+    /// Reads the rest of a value with the given type marker.
+    fn typed(&mut self, marker: u8) -> error::Result<value::Value> {
+        Ok(match marker {
+            NULL => value::Value::Unit,
+            TRUE => value::Value::Bool(true),
+            FALSE => value::Value::Bool(false),
+            INT8 => value::Value::I8(i8::from_be_bytes(self.bytes()?)),
+            UINT8 => value::Value::U8(self.byte()?),
+            INT16 => value::Value::I16(i16::from_be_bytes(self.bytes()?)),
+            INT32 => value::Value::I32(i32::from_be_bytes(self.bytes()?)),
+            INT64 => value::Value::I64(i64::from_be_bytes(self.bytes()?)),
+            FLOAT32 => value::Value::from_f32(f32::from_be_bytes(self.bytes()?)),
+            FLOAT64 => value::Value::from_f64(f64::from_be_bytes(self.bytes()?)),
+            HIGH_PRECISION => {
+                let number = self.string()?;
+                if let Ok(v) = number.parse() {
+                    value::Value::U64(v)
+                } else if let Ok(v) = number.parse() {
+                    value::Value::I64(v)
+                } else {
+                    value::Value::String(number)
+                }
+            }
+            CHAR => match self.byte()? {
+                b if b.is_ascii() => value::Value::Char(char::from(b)),
+                b => return Err(format_error(&format!("invalid char 0x{:02x}", b))),
+            },
+            STRING => value::Value::String(self.string()?),
+            ARRAY_START => self.nested(Self::array)?,
+            OBJECT_START => self.nested(Self::object)?,
+            marker => return Err(format_error(&format!("unknown marker 0x{:02x}", marker))),
+        })
+    }
+
+    /// Reads a container, failing if it is nested too deeply.
+    fn nested<F>(&mut self, read: F) -> error::Result<value::Value>
+    where
+        F: FnOnce(&mut Self) -> error::Result<value::Value>,
+    {
+        if self.depth >= MAX_DEPTH {
+            return Err(format_error(&format!(
+                "containers are nested more than {} levels deep",
+                MAX_DEPTH
+            )));
+        }
+        self.depth += 1;
+        let result = read(self);
+        self.depth -= 1;
+        result
+    }
+
+    /// Reads a length, which is an integer of any type.
+    fn length(&mut self) -> error::Result<usize> {
+        let len = match self.byte()? {
+            INT8 => i64::from(i8::from_be_bytes(self.bytes()?)),
+            UINT8 => i64::from(self.byte()?),
+            INT16 => i64::from(i16::from_be_bytes(self.bytes()?)),
+            INT32 => i64::from(i32::from_be_bytes(self.bytes()?)),
+            INT64 => i64::from_be_bytes(self.bytes()?),
+            marker => {
+                return Err(format_error(&format!(
+                    "expected an integer length, got marker 0x{:02x}",
+                    marker
+                )))
+            }
+        };
+        usize::try_from(len).map_err(|_| format_error(&format!("invalid length {}", len)))
+    }
+
+    /// Reads a string without its marker, which is its length and then its UTF-8 bytes.
+    fn string(&mut self) -> error::Result<String> {
+        let len = self.length()?;
+        String::from_utf8(self.data(len)?)
+            .map_err(|e| format_error(&format!("invalid UTF-8 in a string: {}", e)))
+    }
+
+    /// Reads the type and count of an optimized container, if it has them.
+    fn container_header(&mut self) -> error::Result<(Option<u8>, Option<usize>)> {
+        let element_type = if self.peek()? == Some(TYPE) {
+            self.skip();
+            match self.byte()? {
+                NO_OP | ARRAY_END | OBJECT_END | TYPE | COUNT => {
+                    return Err(format_error("invalid container type"))
+                }
+                marker => Some(marker),
+            }
+        } else {
+            None
+        };
+        let count = if self.peek()? == Some(COUNT) {
+            self.skip();
+            Some(self.length()?)
+        } else if element_type.is_some() {
+            return Err(format_error("a container type must be followed by a count"));
+        } else {
+            None
+        };
+        Ok((element_type, count))
+    }
+
+    fn array(&mut self) -> error::Result<value::Value> {
+        let mut elements = Vec::new();
+        match self.container_header()? {
+            (Some(UINT8), Some(count)) => return Ok(value::Value::Bytes(self.data(count)?)),
+            (Some(element_type), Some(count)) => {
+                elements.reserve(count.min(MAX_PREALLOCATED));
+                for _ in 0..count {
+                    elements.push(self.typed(element_type)?);
+                }
+            }
+            (_, Some(count)) => {
+                elements.reserve(count.min(MAX_PREALLOCATED));
+                for _ in 0..count {
+                    elements.push(self.value()?);
+                }
+            }
+            (_, None) => loop {
+                match self.marker()? {
+                    ARRAY_END => break,
+                    marker => elements.push(self.typed(marker)?),
+                }
+            },
+        }
+        Ok(value::Value::Sequence(elements))
+    }
+
+    fn object(&mut self) -> error::Result<value::Value> {
+        let mut entries = Vec::new();
+        match self.container_header()? {
+            (element_type, Some(count)) => {
+                entries.reserve(count.min(MAX_PREALLOCATED));
+                for _ in 0..count {
+                    let key = self.string()?;
+                    let v = match element_type {
+                        Some(element_type) => self.typed(element_type)?,
+                        None => self.value()?,
+                    };
+                    entries.push((value::Value::String(key), v));
+                }
+            }
+            (_, None) => loop {
+                while self.peek()? == Some(NO_OP) {
+                    self.skip();
+                }
+                if self.peek()? == Some(OBJECT_END) {
+                    self.skip();
+                    break;
+                }
+                let key = self.string()?;
+                let v = self.value()?;
+                entries.push((value::Value::String(key), v));
+            },
+        }
+        Ok(value::Value::Map(entries))
+    }
+}
+
+impl<W> value::Sink for Sink<W>
+where
+    W: io::Write,
+{
+    fn write(&mut self, v: value::Value) -> error::Result<()> {
+        self.1.clear();
+        Encoder(&mut self.1).value(v);
+        self.0.write_all(&self.1)?;
+        Ok(())
+    }
+
+    #[inline]
+    fn flush(&mut self) -> error::Result<()> {
+        self.0.flush()?;
+        Ok(())
+    }
+}
+
+/// Encodes records into a buffer.
+struct Encoder<'a>(&'a mut Vec<u8>);
+
+impl<'a> Encoder<'a> {
+    fn value(&mut self, v: value::Value) {
+        match v {
+            value::Value::Unit => self.0.push(NULL),
+            value::Value::Bool(true) => self.0.push(TRUE),
+            value::Value::Bool(false) => self.0.push(FALSE),
+            value::Value::I8(v) => self.integer(i64::from(v)),
+            value::Value::I16(v) => self.integer(i64::from(v)),
+            value::Value::I32(v) => self.integer(i64::from(v)),
+            value::Value::I64(v) => self.integer(v),
+            value::Value::U8(v) => self.integer(i64::from(v)),
+            value::Value::U16(v) => self.integer(i64::from(v)),
+            value::Value::U32(v) => self.integer(i64::from(v)),
+            value::Value::U64(v) => match i64::try_from(v) {
+                Ok(v) => self.integer(v),
+                // Too large for any integer type
+                Err(_) => {
+                    self.0.push(HIGH_PRECISION);
+                    self.string(&v.to_string());
+                }
+            },
+            value::Value::F32(v) => {
+                self.0.push(FLOAT32);
+                self.0.extend_from_slice(&v.0.to_be_bytes());
+            }
+            value::Value::F64(v) => {
+                self.0.push(FLOAT64);
+                self.0.extend_from_slice(&v.0.to_be_bytes());
+            }
+            value::Value::Char(c) if c.is_ascii() => {
+                self.0.push(CHAR);
+                self.0.push(c as u8);
+            }
+            value::Value::Char(c) => {
+                self.0.push(STRING);
+                self.string(c.encode_utf8(&mut [0; 4]));
+            }
+            value::Value::String(s) => {
+                self.0.push(STRING);
+                self.string(&s);
+            }
+            value::Value::Bytes(bytes) => {
+                self.0.extend_from_slice(&[ARRAY_START, TYPE, UINT8, COUNT]);
+                self.integer(bytes.len() as i64);
+                self.0.extend_from_slice(&bytes);
+            }
+            value::Value::Sequence(elements) => {
+                self.0.push(ARRAY_START);
+                for element in elements {
+                    self.value(element);
+                }
+                self.0.push(ARRAY_END);
+            }
+            value::Value::Map(entries) => {
+                self.0.push(OBJECT_START);
+                for (key, v) in entries {
+                    match key {
+                        value::Value::String(key) => self.string(&key),
+                        key => self.string(&key.into_key_string()),
+                    }
+                    self.value(v);
+                }
+                self.0.push(OBJECT_END);
+            }
+        }
+    }
+
+    /// Writes an integer with its marker, in the smallest type that fits it.
+    fn integer(&mut self, v: i64) {
+        if let Ok(v) = u8::try_from(v) {
+            self.0.extend_from_slice(&[UINT8, v]);
+        } else if let Ok(v) = i8::try_from(v) {
+            self.0.push(INT8);
+            self.0.extend_from_slice(&v.to_be_bytes());
+        } else if let Ok(v) = i16::try_from(v) {
+            self.0.push(INT16);
+            self.0.extend_from_slice(&v.to_be_bytes());
+        } else if let Ok(v) = i32::try_from(v) {
+            self.0.push(INT32);
+            self.0.extend_from_slice(&v.to_be_bytes());
+        } else {
+            self.0.push(INT64);
+            self.0.extend_from_slice(&v.to_be_bytes());
+        }
+    }
+
+    /// Writes a string without its marker.
+    fn string(&mut self, s: &str) {
+        self.integer(s.len() as i64);
+        self.0.extend_from_slice(s.as_bytes());
+    }
+}
+
+fn format_error(msg: &str) -> error::Error {
+    error::Error::Format {
+        msg: msg.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::value::Sink as _;
+    use crate::value::Source as _;
+
+    fn read(input: &[u8]) -> error::Result<Vec<value::Value>> {
+        let mut reader = source(input);
+        let mut records = Vec::new();
+        while let Some(record) = reader.read()? {
+            records.push(record);
+        }
+        Ok(records)
+    }
+
+    #[test]
+    fn test_depth() {
+        let nested = |depth: usize| format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+        assert!(source(nested(128).as_bytes()).read().is_ok());
+        for input in &[nested(129), nested(200_000), "{i\x01a".repeat(200_000)] {
+            match source(input.as_bytes()).read() {
+                Err(error::Error::Format { msg }) => assert!(msg.contains("nested"), "{}", msg),
+                other => panic!(
+                    "expected an error for deeply nested UBJSON, got {:?}",
+                    other
+                ),
+            }
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        // Optimized containers with a type and count, and no-ops between values
+        let input = b"N{$i#U\x02U\x01a\x05U\x01b\xfbN[$U#U\x03abc[#U\x02ZC!NSU\x02hiHU\x031.5";
+        let records = read(input).unwrap();
+        assert_eq!(
+            records.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            vec![
+                r#"{"a": 5, "b": -5}"#,
+                "0x616263",
+                "[null, '!']",
+                r#""hi""#,
+                r#""1.5""#
+            ]
+        );
+        assert!(read(b"[U\x01").is_err());
+        assert!(read(b"[$U]").is_err());
+
+        let mut output = Vec::new();
+        {
+            let mut writer = sink(&mut output);
+            writer
+                .write(value!({"n": [1, -300, 70000, 2.5], "s": "hé"}))
+                .unwrap();
+            writer.write(value::Value::Bytes(vec![1, 2])).unwrap();
+        }
+        assert_eq!(
+            output,
+            b"{U\x01n[U\x01I\xfe\xd4l\x00\x01\x11\x70D\x40\x04\0\0\0\0\0\0]U\x01sSU\x03h\xc3\xa9}[$U#U\x02\x01\x02"
+        );
+        let records = read(&output).unwrap();
+        assert_eq!(
+            records[0].to_string(),
+            r#"{"n": [1, -300, 70000, 2.5], "s": "hé"}"#
+        );
+    }
+}
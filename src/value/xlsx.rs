@@ -0,0 +1,261 @@
+//! Excel workbooks (`.xlsx`).
+//!
+//! The first row of each sheet is its header, and every other row becomes a map from the header
+//! cells to the cells of the row.  Dates and times become ISO 8601 strings, error cells strings
+//! like `#DIV/0!`, and empty cells null; rows that are entirely empty are skipped.
+
+use std::collections;
+use std::fmt;
+use std::io;
+
+use calamine::Reader;
+
+use crate::error;
+use crate::transform;
+use crate::value;
+
+pub struct Source {
+    workbook: calamine::Xlsx<io::Cursor<Vec<u8>>>,
+    sheets: collections::VecDeque<String>,
+    current: Option<Sheet>,
+    records: u64,
+}
+
+struct Sheet {
+    range: calamine::Range<calamine::Data>,
+    header: Vec<value::Value>,
+    row: usize,
+}
+
+/// Creates a source for the rows of a workbook, of all sheets one after the other or only of
+/// the named sheet.  The whole input is read up front, since workbooks are zip archives.
+pub fn source<R>(mut r: R, sheet: Option<&str>) -> error::Result<Source>
+where
+    R: io::Read,
+{
+    let mut input = Vec::new();
+    r.read_to_end(&mut input)?;
+    let workbook = calamine::Xlsx::new(io::Cursor::new(input))?;
+    let names = workbook.sheet_names();
+    let sheets = match sheet {
+        Some(sheet) if names.iter().any(|name| name == sheet) => vec![sheet.to_owned()],
+        Some(sheet) => {
+            return Err(error::Error::Message(format!(
+                "the workbook has no sheet {:?}, only: {}",
+                sheet,
+                names.join(", ")
+            )))
+        }
+        None => names,
+    };
+    Ok(Source {
+        workbook,
+        sheets: sheets.into(),
+        current: None,
+        records: 0,
+    })
+}
+
+impl value::Source for Source {
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        loop {
+            if let Some(ref mut sheet) = self.current {
+                if let Some(record) = sheet.next_record() {
+                    self.records += 1;
+                    return Ok(Some(record));
+                }
+                self.current = None;
+            }
+
+            match self.sheets.pop_front() {
+                Some(name) => {
+                    debug!("Reading sheet {:?}", name);
+                    let range = self.workbook.worksheet_range(&name)?;
+                    self.current = Some(Sheet::new(range));
+                }
+                None => return Ok(None),
+            }
+        }
+    }
+
+    #[inline]
+    fn position(&self) -> Option<value::Position> {
+        Some(value::Position {
+            record: self.records,
+            byte: None,
+            line: None,
+        })
+    }
+}
+
+impl Sheet {
+    fn new(range: calamine::Range<calamine::Data>) -> Self {
+        let first_column = range.start().map_or(0, |(_, column)| column);
+        let header = match range.rows().next() {
+            Some(cells) => cells
+                .iter()
+                .enumerate()
+                .map(|(i, cell)| match value_from_cell(cell) {
+                    // Blank headers are named like their column
+                    value::Value::Unit => {
+                        value::Value::String(column_name(first_column + i as u32))
+                    }
+                    value::Value::String(name) => value::Value::String(name),
+                    name => value::Value::String(name.to_string()),
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+        Self {
+            range,
+            header,
+            row: 1,
+        }
+    }
+
+    fn next_record(&mut self) -> Option<value::Value> {
+        loop {
+            let cells = self.range.rows().nth(self.row)?;
+            self.row += 1;
+            if cells.iter().all(|cell| *cell == calamine::Data::Empty) {
+                continue;
+            }
+            return Some(value::Value::Map(
+                self.header
+                    .iter()
+                    .cloned()
+                    .zip(cells.iter().map(value_from_cell))
+                    .collect(),
+            ));
+        }
+    }
+}
+
+fn value_from_cell(cell: &calamine::Data) -> value::Value {
+    match *cell {
+        calamine::Data::Int(v) => value::Value::I64(v),
+        // Excel stores all numbers as floats, so whole numbers are shown as integers
+        calamine::Data::Float(v) => transform::number(v),
+        calamine::Data::String(ref s) => value::Value::String(s.clone()),
+        calamine::Data::Bool(v) => value::Value::Bool(v),
+        calamine::Data::DateTime(ref v) if v.is_duration() => {
+            value::Value::String(format!("PT{}S", transform::number(v.as_f64() * 86400.0)))
+        }
+        calamine::Data::DateTime(ref v) => {
+            let (year, month, day, hour, minute, second, milli) = v.to_ymd_hms_milli();
+            let mut s = format!(
+                "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+                year, month, day, hour, minute, second
+            );
+            if milli > 0 {
+                s.push_str(&format!(".{:03}", milli));
+            }
+            value::Value::String(s)
+        }
+        calamine::Data::DateTimeIso(ref s) | calamine::Data::DurationIso(ref s) => {
+            value::Value::String(s.clone())
+        }
+        calamine::Data::Error(ref e) => value::Value::String(e.to_string()),
+        calamine::Data::Empty => value::Value::Unit,
+    }
+}
+
+/// The letters of a zero-based column index, like `A` or `AB`.
+fn column_name(mut column: u32) -> String {
+    let mut name = Vec::new();
+    loop {
+        name.push(b'A' + (column % 26) as u8);
+        if column < 26 {
+            break;
+        }
+        column = column / 26 - 1;
+    }
+    name.reverse();
+    String::from_utf8(name).unwrap()
+}
+
+impl fmt::Debug for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("XlsxSource")
+            .field("sheets", &self.sheets)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::value::Source as _;
+
+    /// A workbook with a sheet of people, which has a gap in its rows and columns, and an empty
+    /// sheet.
+    fn workbook() -> Vec<u8> {
+        use std::io::Write;
+
+        let sheet = |rows: &str| {
+            format!(
+                r#"<worksheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main"><sheetData>{}</sheetData></worksheet>"#,
+                rows
+            )
+        };
+        let files = [
+            (
+                "[Content_Types].xml",
+                r#"<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types"/>"#
+                    .to_owned(),
+            ),
+            (
+                "xl/workbook.xml",
+                r#"<workbook xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main" xmlns:r="http://schemas.openxmlformats.org/officeDocument/2006/relationships"><sheets><sheet name="People" sheetId="1" r:id="rId1"/><sheet name="Empty" sheetId="2" r:id="rId2"/></sheets></workbook>"#
+                    .to_owned(),
+            ),
+            (
+                "xl/_rels/workbook.xml.rels",
+                r#"<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/worksheet" Target="worksheets/sheet1.xml"/><Relationship Id="rId2" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/worksheet" Target="worksheets/sheet2.xml"/></Relationships>"#
+                    .to_owned(),
+            ),
+            (
+                "xl/worksheets/sheet1.xml",
+                sheet(concat!(
+                    r#"<row r="1"><c r="A1" t="inlineStr"><is><t>name</t></is></c><c r="C1" t="inlineStr"><is><t>age</t></is></c></row>"#,
+                    r#"<row r="2"><c r="A2" t="inlineStr"><is><t>Jo</t></is></c><c r="B2" t="b"><v>1</v></c><c r="C2"><v>34</v></c></row>"#,
+                    r#"<row r="4"><c r="A4" t="inlineStr"><is><t>Al</t></is></c><c r="C4"><v>2.5</v></c></row>"#,
+                )),
+            ),
+            ("xl/worksheets/sheet2.xml", sheet("")),
+        ];
+        let mut workbook = zip::ZipWriter::new(io::Cursor::new(Vec::new()));
+        for (name, contents) in &files {
+            workbook
+                .start_file(*name, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            workbook.write_all(contents.as_bytes()).unwrap();
+        }
+        workbook.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn test_sheet() {
+        let mut reader = source(&workbook()[..], Some("People")).unwrap();
+        assert_eq!(
+            reader.read().unwrap().map(|r| r.to_string()),
+            Some(r#"{"name": "Jo", "B": true, "age": 34}"#.to_owned())
+        );
+        assert_eq!(
+            reader.read().unwrap().map(|r| r.to_string()),
+            Some(r#"{"name": "Al", "B": null, "age": 2.5}"#.to_owned())
+        );
+        assert_eq!(reader.read().unwrap(), None);
+    }
+
+    #[test]
+    fn test_all_sheets() {
+        let mut reader = source(&workbook()[..], None).unwrap();
+        let mut records = 0;
+        while reader.read().unwrap().is_some() {
+            records += 1;
+        }
+        assert_eq!(records, 2);
+        assert!(source(&workbook()[..], Some("Nope")).is_err());
+    }
+}
@@ -0,0 +1,383 @@
+//! XML documents as records.
+//!
+//! Each document is a record: a map from the name of the root element to its contents.  An
+//! element with only text becomes a string, and an empty element becomes null.  Other elements
+//! become maps, with attributes under their name with a prefix (`@` by default), child elements
+//! under their name, and the text under `#text`.  Child elements that are repeated become a
+//! sequence.  All values are strings, since XML has no types.
+
+use std::fmt;
+use std::io;
+use std::str;
+
+use quick_xml::events;
+
+use crate::error;
+use crate::value;
+
+/// The key of the text of elements that also have attributes or child elements.
+pub const TEXT_KEY: &str = "#text";
+/// How deeply elements are nested at most.
+const MAX_DEPTH: usize = 128;
+
+pub struct Source<R>(quick_xml::Reader<R>, String);
+
+#[derive(Debug)]
+pub struct Sink<W: io::Write>(W, Vec<u8>, String, Option<String>);
+
+#[inline]
+pub fn source<R>(r: R, attribute_prefix: String) -> Source<R>
+where
+    R: io::BufRead,
+{
+    Source(quick_xml::Reader::from_reader(r), attribute_prefix)
+}
+
+/// Creates an XML sink.  Records must be maps with a single entry for the root element, so if
+/// `wrap_key` is specified, other records are written as a root element with that name.
+#[inline]
+pub fn sink<W>(w: W, attribute_prefix: String, wrap_key: Option<String>) -> Sink<W>
+where
+    W: io::Write,
+{
+    Sink(w, Vec::new(), attribute_prefix, wrap_key)
+}
+
+impl<R> value::Source for Source<R>
+where
+    R: io::BufRead,
+{
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        let mut buf = Vec::new();
+        loop {
+            buf.clear();
+            match self.0.read_event_into(&mut buf)? {
+                events::Event::Start(start) => {
+                    let start = start.into_owned();
+                    let name = value::Value::String(decode_name(start.name().as_ref())?);
+                    let content = read_element(&mut self.0, &start, false, &self.1, 0)?;
+                    return Ok(Some(value::Value::Map(vec![(name, content)])));
+                }
+                events::Event::Empty(start) => {
+                    let start = start.into_owned();
+                    let name = value::Value::String(decode_name(start.name().as_ref())?);
+                    let content = read_element(&mut self.0, &start, true, &self.1, 0)?;
+                    return Ok(Some(value::Value::Map(vec![(name, content)])));
+                }
+                events::Event::Text(text) if !text.iter().all(u8::is_ascii_whitespace) => {
+                    return Err(error::Error::Format {
+                        msg: format!(
+                            "XML text outside of the root element at byte {}",
+                            self.0.buffer_position()
+                        ),
+                    });
+                }
+                events::Event::Eof => return Ok(None),
+                _ => (),
+            }
+        }
+    }
+}
+
+fn read_element<R>(
+    reader: &mut quick_xml::Reader<R>,
+    start: &events::BytesStart,
+    empty: bool,
+    attribute_prefix: &str,
+    depth: usize,
+) -> error::Result<value::Value>
+where
+    R: io::BufRead,
+{
+    if depth >= MAX_DEPTH {
+        return Err(error::Error::Message(format!(
+            "XML elements are nested more than {} levels deep",
+            MAX_DEPTH
+        )));
+    }
+    let mut entries = Vec::new();
+    for attribute in start.attributes() {
+        let attribute = attribute.map_err(quick_xml::Error::from)?;
+        let key = format!(
+            "{}{}",
+            attribute_prefix,
+            decode_name(attribute.key.as_ref())?
+        );
+        let v = attribute.unescape_value()?.into_owned();
+        entries.push((value::Value::String(key), value::Value::String(v)));
+    }
+
+    let mut text = String::new();
+    if !empty {
+        let mut buf = Vec::new();
+        loop {
+            buf.clear();
+            match reader.read_event_into(&mut buf)? {
+                events::Event::Start(child) => {
+                    let child = child.into_owned();
+                    let name = decode_name(child.name().as_ref())?;
+                    let v = read_element(reader, &child, false, attribute_prefix, depth + 1)?;
+                    add_child(&mut entries, name, v);
+                }
+                events::Event::Empty(child) => {
+                    let child = child.into_owned();
+                    let name = decode_name(child.name().as_ref())?;
+                    let v = read_element(reader, &child, true, attribute_prefix, depth + 1)?;
+                    add_child(&mut entries, name, v);
+                }
+                events::Event::Text(t) => text.push_str(&t.unescape()?),
+                events::Event::CData(t) => text.push_str(&String::from_utf8_lossy(&t)),
+                events::Event::End(_) => break,
+                events::Event::Eof => {
+                    return Err(error::Error::Format {
+                        msg: format!(
+                            "XML input ended inside of the element {}",
+                            decode_name(start.name().as_ref())?
+                        ),
+                    })
+                }
+                _ => (),
+            }
+        }
+    }
+
+    let text = text.trim();
+    if entries.is_empty() {
+        if text.is_empty() {
+            Ok(value::Value::Unit)
+        } else {
+            Ok(value::Value::String(text.to_owned()))
+        }
+    } else {
+        if !text.is_empty() {
+            entries.push((
+                value::Value::String(TEXT_KEY.to_owned()),
+                value::Value::String(text.to_owned()),
+            ));
+        }
+        Ok(value::Value::Map(entries))
+    }
+}
+
+/// Adds a child element, turning repeated elements into a sequence.
+fn add_child(entries: &mut Vec<(value::Value, value::Value)>, name: String, v: value::Value) {
+    match entries
+        .iter_mut()
+        .find(|(k, _)| k.as_str() == Some(name.as_str()))
+    {
+        // Elements are never sequences by themselves, so this must be a repeated element
+        Some((_, value::Value::Sequence(ref mut values))) => values.push(v),
+        Some((_, existing)) => {
+            let first = std::mem::replace(existing, value::Value::Unit);
+            *existing = value::Value::Sequence(vec![first, v]);
+        }
+        None => entries.push((value::Value::String(name), v)),
+    }
+}
+
+fn decode_name(name: &[u8]) -> error::Result<String> {
+    match str::from_utf8(name) {
+        Ok(name) => Ok(name.to_owned()),
+        Err(_) => Err(error::Error::Format {
+            msg: format!("XML name is not UTF-8: {}", String::from_utf8_lossy(name)),
+        }),
+    }
+}
+
+impl<W> value::Sink for Sink<W>
+where
+    W: io::Write,
+{
+    fn write(&mut self, value: value::Value) -> error::Result<()> {
+        let (name, content) = match (value, &self.3) {
+            (value::Value::Map(mut entries), _)
+                if entries.len() == 1 && !is_attribute(&entries[0].0, &self.2) =>
+            {
+                let (name, content) = entries.remove(0);
+                (name.into_key_string(), content)
+            }
+            (value, Some(key)) => (key.clone(), value),
+            (value, None) => {
+                return Err(error::Error::Format {
+                    msg: format!(
+                        "XML can only output maps with a single root element (see \
+                         --wrap-scalar), got: {}",
+                        value.summary(value::ERROR_SUMMARY_LEN)
+                    ),
+                })
+            }
+        };
+
+        self.1.clear();
+        {
+            let mut writer = quick_xml::Writer::new_with_indent(&mut self.1, b' ', 2);
+            write_element(&mut writer, &name, content, &self.2)?;
+        }
+        self.1.push(b'\n');
+        self.0.write_all(&self.1)?;
+        Ok(())
+    }
+
+    #[inline]
+    fn flush(&mut self) -> error::Result<()> {
+        self.0.flush()?;
+        Ok(())
+    }
+}
+
+fn write_element<W>(
+    writer: &mut quick_xml::Writer<W>,
+    name: &str,
+    content: value::Value,
+    attribute_prefix: &str,
+) -> error::Result<()>
+where
+    W: io::Write,
+{
+    check_name(name)?;
+    match content {
+        value::Value::Sequence(values) => {
+            for v in values {
+                write_element(writer, name, v, attribute_prefix)?;
+            }
+        }
+        value::Value::Map(entries) => {
+            let mut start = events::BytesStart::new(name);
+            let mut text = None;
+            let mut children = Vec::new();
+            for (k, v) in entries {
+                if is_attribute(&k, attribute_prefix) && is_scalar(&v) {
+                    let key = k.into_key_string();
+                    let key = &key[attribute_prefix.len()..];
+                    check_name(key)?;
+                    start.push_attribute((key, scalar_text(v).as_str()));
+                } else if k.as_str() == Some(TEXT_KEY) && is_scalar(&v) {
+                    text = Some(scalar_text(v));
+                } else {
+                    children.push((k.into_key_string(), v));
+                }
+            }
+            if text.is_none() && children.is_empty() {
+                writer.write_event(events::Event::Empty(start))?;
+            } else {
+                writer.write_event(events::Event::Start(start))?;
+                if let Some(text) = text {
+                    writer.write_event(events::Event::Text(events::BytesText::new(&text)))?;
+                }
+                for (k, v) in children {
+                    write_element(writer, &k, v, attribute_prefix)?;
+                }
+                writer.write_event(events::Event::End(events::BytesEnd::new(name)))?;
+            }
+        }
+        value::Value::Unit => {
+            writer.write_event(events::Event::Empty(events::BytesStart::new(name)))?;
+        }
+        v => {
+            let text = scalar_text(v);
+            writer.write_event(events::Event::Start(events::BytesStart::new(name)))?;
+            writer.write_event(events::Event::Text(events::BytesText::new(&text)))?;
+            writer.write_event(events::Event::End(events::BytesEnd::new(name)))?;
+        }
+    }
+    Ok(())
+}
+
+fn is_attribute(key: &value::Value, attribute_prefix: &str) -> bool {
+    !attribute_prefix.is_empty()
+        && key
+            .as_str()
+            .is_some_and(|k| k.len() > attribute_prefix.len() && k.starts_with(attribute_prefix))
+}
+
+fn is_scalar(v: &value::Value) -> bool {
+    !matches!(v, value::Value::Sequence(_) | value::Value::Map(_))
+}
+
+fn scalar_text(v: value::Value) -> String {
+    match v {
+        value::Value::String(s) => s,
+        value::Value::Unit => String::new(),
+        v => v.to_string(),
+    }
+}
+
+impl<R> fmt::Debug for Source<R>
+where
+    R: io::BufRead,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("XmlSource").finish()
+    }
+}
+
+/// Checks that the name can be used for an element or attribute.
+fn check_name(name: &str) -> error::Result<()> {
+    let valid = name
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_alphabetic() || c == '_' || c == ':')
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '_' | ':' | '-' | '.'));
+    if valid {
+        Ok(())
+    } else {
+        Err(error::Error::Format {
+            msg: format!("not a valid XML name: {:?}", name),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::value::Sink as _;
+    use crate::value::Source as _;
+
+    #[test]
+    fn test_round_trip() {
+        let input = r#"<?xml version="1.0"?>
+<order id="7"><item>a &amp; b</item><item>c</item><note/></order>"#;
+        let record = value!({"order": {"@id": "7", "item": ["a & b", "c"], "note": null}});
+
+        let mut reader = source(input.as_bytes(), "@".to_owned());
+        assert_eq!(reader.read().unwrap(), Some(record.clone()));
+        assert_eq!(reader.read().unwrap(), None);
+
+        let mut output = Vec::new();
+        sink(&mut output, "@".to_owned(), None)
+            .write(record.clone())
+            .unwrap();
+        let mut reader = source(&output[..], "@".to_owned());
+        assert_eq!(reader.read().unwrap(), Some(record));
+
+        // Keys that aren't strings name elements without quotes
+        let record = value::Value::Map(vec![(
+            value::Value::Char('a'),
+            value::Value::Map(vec![(value::Value::Char('b'), value!(1))]),
+        )]);
+        let mut output = Vec::new();
+        sink(&mut output, "@".to_owned(), None)
+            .write(record)
+            .unwrap();
+        let mut reader = source(&output[..], "@".to_owned());
+        assert_eq!(reader.read().unwrap(), Some(value!({"a": {"b": "1"}})));
+    }
+
+    #[test]
+    fn test_depth() {
+        let nested = |depth: usize| format!("{}{}", "<a>".repeat(depth), "</a>".repeat(depth));
+        let input = nested(128);
+        let mut reader = source(input.as_bytes(), "@".to_owned());
+        assert!(reader.read().is_ok());
+
+        let input = nested(100_000);
+        let mut reader = source(input.as_bytes(), "@".to_owned());
+        match reader.read() {
+            Err(error::Error::Message(msg)) => assert!(msg.contains("nested"), "{}", msg),
+            other => panic!("expected an error for deeply nested XML, got {:?}", other),
+        }
+    }
+}
@@ -1,22 +1,27 @@
 use crate::error;
 use crate::value;
+use serde::Deserialize;
 use serde_yaml;
+use std::fmt;
 use std::io;
 
-#[derive(Debug)]
-pub struct Source<R>(Option<R>);
+/// A YAML source, with a record for each document of a multi-document stream.
+pub struct Source<'de>(serde_yaml::Deserializer<'de>);
 
+/// A YAML sink.  Each record is serialized into a buffer first, so that a record that fails to
+/// serialize leaves nothing behind in the output.  Records after the first are separated by
+/// `---`, so that the output is a multi-document stream.
 #[derive(Debug)]
-pub struct Sink<W>(W)
+pub struct Sink<W>(W, Vec<u8>, bool)
 where
     W: io::Write;
 
 #[inline]
-pub fn source<R>(r: R) -> Source<R>
+pub fn source<'de, R>(r: R) -> Source<'de>
 where
-    R: io::Read,
+    R: io::Read + 'de,
 {
-    Source(Some(r))
+    Source(serde_yaml::Deserializer::from_reader(r))
 }
 
 #[inline]
@@ -24,22 +29,15 @@ pub fn sink<W>(w: W) -> Sink<W>
 where
     W: io::Write,
 {
-    Sink(w)
+    Sink(w, Vec::new(), false)
 }
 
-impl<R> value::Source for Source<R>
-where
-    R: io::Read,
-{
+impl value::Source for Source<'_> {
     #[inline]
     fn read(&mut self) -> error::Result<Option<value::Value>> {
-        if let Some(r) = self.0.take() {
-            match serde_yaml::from_reader(r) {
-                Ok(v) => Ok(Some(v)),
-                Err(e) => Err(error::Error::from(e)),
-            }
-        } else {
-            Ok(None)
+        match self.0.next() {
+            Some(document) => Ok(Some(value::Value::deserialize(document)?)),
+            None => Ok(None),
         }
     }
 }
@@ -50,8 +48,26 @@ where
 {
     #[inline]
     fn write(&mut self, value: value::Value) -> error::Result<()> {
-        serde_yaml::to_writer(&mut self.0, &value)?;
-        self.0.write_all(b"\n")?;
+        self.1.clear();
+        if self.2 {
+            self.1.extend_from_slice(b"---\n");
+        }
+        serde_yaml::to_writer(&mut self.1, &value)?;
+        self.1.push(b'\n');
+        self.0.write_all(&self.1)?;
+        self.2 = true;
         Ok(())
     }
+
+    #[inline]
+    fn flush(&mut self) -> error::Result<()> {
+        self.0.flush()?;
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Source<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("YamlSource").finish()
+    }
 }
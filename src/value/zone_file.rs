@@ -0,0 +1,546 @@
+//! DNS zone files, in the master file format of RFC 1035 that BIND and most other name servers
+//! use.
+//!
+//! Each resource record becomes a record with its `name`, `ttl`, `class` and `type`, and the
+//! `rdata` as a map with fields for the type, like `preference` and `exchange` for MX records.
+//! Names are made absolute with the origin, and records without a TTL or class get them from the
+//! `$TTL` directive or the record before them.  The data of types without fields here are in
+//! `data` as it is written.
+
+use std::io;
+use std::net;
+
+use crate::error;
+use crate::value;
+
+/// The fields of the data of record types, which are names if they end with `.`, and numbers if
+/// they end with `#`.  The last field has the rest of the data.
+const TYPES: &[(&str, &[&str])] = &[
+    ("A", &["address"]),
+    ("AAAA", &["address"]),
+    ("AFSDB", &["subtype#", "hostname."]),
+    ("CAA", &["flags#", "tag", "value"]),
+    (
+        "CDNSKEY",
+        &["flags#", "protocol#", "algorithm#", "public_key"],
+    ),
+    ("CDS", &["key_tag#", "algorithm#", "digest_type#", "digest"]),
+    ("CERT", &[]),
+    ("CNAME", &["target."]),
+    ("DHCID", &[]),
+    ("DNAME", &["target."]),
+    (
+        "DNSKEY",
+        &["flags#", "protocol#", "algorithm#", "public_key"],
+    ),
+    ("DS", &["key_tag#", "algorithm#", "digest_type#", "digest"]),
+    ("HINFO", &["cpu", "os"]),
+    ("HTTPS", &[]),
+    ("IPSECKEY", &[]),
+    ("KEY", &[]),
+    ("LOC", &[]),
+    ("MX", &["preference#", "exchange."]),
+    (
+        "NAPTR",
+        &[
+            "order#",
+            "preference#",
+            "flags",
+            "services",
+            "regexp",
+            "replacement.",
+        ],
+    ),
+    ("NS", &["host."]),
+    ("NSEC", &[]),
+    ("NSEC3", &[]),
+    ("NSEC3PARAM", &[]),
+    ("OPENPGPKEY", &[]),
+    ("PTR", &["target."]),
+    ("RP", &["mailbox.", "text."]),
+    ("RRSIG", &[]),
+    ("SIG", &[]),
+    ("SMIMEA", &["usage#", "selector#", "matching_type#", "data"]),
+    (
+        "SOA",
+        &[
+            "mname.", "rname.", "serial#", "refresh#", "retry#", "expire#", "minimum#",
+        ],
+    ),
+    ("SPF", &["text"]),
+    ("SRV", &["priority#", "weight#", "port#", "target."]),
+    ("SSHFP", &["algorithm#", "fingerprint_type#", "fingerprint"]),
+    ("SVCB", &[]),
+    ("TLSA", &["usage#", "selector#", "matching_type#", "data"]),
+    ("TXT", &["text"]),
+    ("URI", &["priority#", "weight#", "target"]),
+    ("ZONEMD", &[]),
+];
+
+const CLASSES: &[&str] = &["IN", "CH", "HS", "CS"];
+
+#[derive(Debug)]
+pub struct Source<R> {
+    lines: io::Lines<R>,
+    origin: Option<String>,
+    /// The TTL of `$TTL`, for records without one.
+    default_ttl: Option<u64>,
+    last_name: Option<String>,
+    last_ttl: Option<u64>,
+    last_class: Option<String>,
+    line: u64,
+    records: u64,
+}
+
+#[derive(Debug)]
+struct Token {
+    text: String,
+    quoted: bool,
+}
+
+/// Creates a source for the resource records of the zone file in the input.
+pub fn source<R>(r: R) -> Source<R>
+where
+    R: io::BufRead,
+{
+    Source {
+        lines: r.lines(),
+        origin: None,
+        default_ttl: None,
+        last_name: None,
+        last_ttl: None,
+        last_class: None,
+        line: 0,
+        records: 0,
+    }
+}
+
+impl<R> value::Source for Source<R>
+where
+    R: io::BufRead,
+{
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        loop {
+            let (tokens, indented) = match self.entry()? {
+                Some(entry) => entry,
+                None => return Ok(None),
+            };
+            if tokens.is_empty() {
+                continue;
+            }
+            if !indented && tokens[0].text.starts_with('$') {
+                self.directive(&tokens)?;
+                continue;
+            }
+            let record = self.record(tokens, indented)?;
+            self.records += 1;
+            return Ok(Some(record));
+        }
+    }
+
+    #[inline]
+    fn position(&self) -> Option<value::Position> {
+        Some(value::Position {
+            record: self.records,
+            byte: None,
+            line: Some(self.line),
+        })
+    }
+}
+
+impl<R> Source<R>
+where
+    R: io::BufRead,
+{
+    /// Reads the tokens of the next entry, which continues on later lines within parentheses,
+    /// and whether it starts with whitespace, which leaves out the name.
+    fn entry(&mut self) -> error::Result<Option<(Vec<Token>, bool)>> {
+        let mut tokens = Vec::new();
+        let mut indented = None;
+        let mut depth = 0;
+        loop {
+            let line = match self.lines.next() {
+                Some(line) => line?,
+                None if depth > 0 => return Err(self.error("unclosed parenthesis")),
+                None if indented.is_none() => return Ok(None),
+                None => break,
+            };
+            self.line += 1;
+            if indented.is_none() {
+                indented = Some(line.starts_with([' ', '\t']));
+            }
+
+            let mut chars = line.chars().peekable();
+            while let Some(c) = chars.next() {
+                match c {
+                    ' ' | '\t' | '\r' => (),
+                    ';' => break,
+                    '(' => depth += 1,
+                    ')' if depth > 0 => depth -= 1,
+                    ')' => return Err(self.error("unbalanced parenthesis")),
+                    '"' => {
+                        let mut text = String::new();
+                        loop {
+                            match chars.next() {
+                                Some('"') => break,
+                                Some('\\') => text.push_str(&unescape(&mut chars)),
+                                Some(c) => text.push(c),
+                                None => return Err(self.error("unterminated quoted string")),
+                            }
+                        }
+                        tokens.push(Token { text, quoted: true });
+                    }
+                    c => {
+                        let mut text = c.to_string();
+                        // Escapes like `\.` are kept in names, but can quote delimiters
+                        let mut escaped = c == '\\';
+                        while let Some(&c) = chars.peek() {
+                            if !escaped && " \t\r;()\"".contains(c) {
+                                break;
+                            }
+                            escaped = !escaped && c == '\\';
+                            text.push(c);
+                            chars.next();
+                        }
+                        tokens.push(Token {
+                            text,
+                            quoted: false,
+                        });
+                    }
+                }
+            }
+            if depth == 0 {
+                break;
+            }
+        }
+        Ok(Some((tokens, indented.unwrap_or_default())))
+    }
+
+    fn directive(&mut self, tokens: &[Token]) -> error::Result<()> {
+        let argument = tokens.get(1).map(|t| t.text.as_str());
+        match (tokens[0].text.to_uppercase().as_str(), argument) {
+            ("$ORIGIN", Some(origin)) => self.origin = Some(self.absolute(origin)),
+            ("$TTL", Some(ttl)) => match parse_ttl(ttl) {
+                Some(ttl) => self.default_ttl = Some(ttl),
+                None => return Err(self.error(&format!("invalid TTL: {}", ttl))),
+            },
+            ("$INCLUDE", _) => {
+                return Err(self
+                    .error("$INCLUDE is not supported, the included file must be read separately"))
+            }
+            (directive, _) => {
+                return Err(self.error(&format!("unsupported directive: {}", directive)))
+            }
+        }
+        Ok(())
+    }
+
+    fn record(&mut self, tokens: Vec<Token>, indented: bool) -> error::Result<value::Value> {
+        let mut tokens = tokens.into_iter().peekable();
+        let name = if indented {
+            match self.last_name {
+                Some(ref name) => name.clone(),
+                None => return Err(self.error("the first record has no name")),
+            }
+        } else {
+            let name = tokens.next().expect("entries have tokens").text;
+            self.absolute(&name)
+        };
+
+        // The TTL and class can be in either order, and both are optional
+        let (mut ttl, mut class) = (None, None);
+        while let Some(token) = tokens.peek().filter(|t| !t.quoted) {
+            let upper = token.text.to_uppercase();
+            if class.is_none() && is_class(&upper) {
+                class = Some(upper);
+            } else if let (None, Some(t)) = (ttl, parse_ttl(&token.text)) {
+                ttl = Some(t);
+            } else {
+                break;
+            }
+            tokens.next();
+        }
+        let kind = match tokens.next() {
+            Some(token) if !token.quoted => token.text.to_uppercase(),
+            Some(token) => {
+                return Err(self.error(&format!("invalid record type: {:?}", token.text)))
+            }
+            None => return Err(self.error("the record has no type")),
+        };
+        let fields = match TYPES.iter().find(|&&(t, _)| t == kind) {
+            Some(&(_, fields)) => fields,
+            None if kind.starts_with("TYPE") && kind[4..].parse::<u16>().is_ok() => &[],
+            None => return Err(self.error(&format!("unknown record type: {}", kind))),
+        };
+        let rdata = self.rdata(&kind, fields, tokens.collect())?;
+
+        let ttl = ttl.or(self.last_ttl).or(self.default_ttl);
+        let class = class
+            .or_else(|| self.last_class.clone())
+            .unwrap_or_else(|| "IN".to_owned());
+        self.last_name = Some(name.clone());
+        if ttl.is_some() {
+            self.last_ttl = ttl;
+        }
+        self.last_class = Some(class.clone());
+
+        Ok(value::Value::Map(vec![
+            ("name".into(), name.into()),
+            (
+                "ttl".into(),
+                ttl.map_or(value::Value::Unit, value::Value::U64),
+            ),
+            ("class".into(), class.into()),
+            ("type".into(), kind.into()),
+            ("rdata".into(), rdata),
+        ]))
+    }
+
+    fn rdata(
+        &self,
+        kind: &str,
+        fields: &[&str],
+        tokens: Vec<Token>,
+    ) -> error::Result<value::Value> {
+        if fields.is_empty() {
+            let data: Vec<_> = tokens.into_iter().map(|t| t.text).collect();
+            return Ok(value::Value::Map(vec![(
+                "data".into(),
+                data.join(" ").into(),
+            )]));
+        }
+        if kind == "A" || kind == "AAAA" {
+            let address = match tokens.as_slice() {
+                [token] if !token.quoted => token.text.as_str(),
+                _ => return Err(self.error(&format!("{} record must have a single address", kind))),
+            };
+            let valid = if kind == "A" {
+                address.parse::<net::Ipv4Addr>().is_ok()
+            } else {
+                address.parse::<net::Ipv6Addr>().is_ok()
+            };
+            if !valid {
+                return Err(self.error(&format!("invalid address of {} record: {}", kind, address)));
+            }
+            return Ok(value::Value::Map(vec![("address".into(), address.into())]));
+        }
+        if tokens.len() < fields.len() {
+            return Err(self.error(&format!(
+                "{} record has {} fields instead of {}",
+                kind,
+                tokens.len(),
+                fields.len()
+            )));
+        }
+        let mut tokens = tokens.into_iter();
+        let mut entries = Vec::with_capacity(fields.len());
+        for (i, field) in fields.iter().enumerate() {
+            let (key, v) = if let Some(key) = field.strip_suffix('#') {
+                let token = tokens.next().expect("tokens were counted");
+                let number = match (kind, key) {
+                    _ if token.quoted => None,
+                    ("SOA", "serial") => token.text.parse::<u32>().ok().map(u64::from),
+                    // SOA timers can have units like TTLs
+                    ("SOA", _) => parse_ttl(&token.text),
+                    _ => token.text.parse::<u16>().ok().map(u64::from),
+                };
+                match number {
+                    Some(n) => (key, value::Value::U64(n)),
+                    None => {
+                        return Err(self.error(&format!(
+                            "invalid {} of {} record: {}",
+                            key, kind, token.text
+                        )))
+                    }
+                }
+            } else if let Some(key) = field.strip_suffix('.') {
+                let token = tokens.next().expect("tokens were counted");
+                (key, self.absolute(&token.text).into())
+            } else if i + 1 == fields.len() {
+                // Character strings like those of TXT records are joined, base64 and hex data
+                // is split by whitespace
+                let rest: Vec<_> = tokens.by_ref().collect();
+                let separator = if rest.iter().any(|t| t.quoted) {
+                    ""
+                } else {
+                    " "
+                };
+                let rest: Vec<_> = rest.into_iter().map(|t| t.text).collect();
+                let rest = match kind {
+                    "CDNSKEY" | "CDS" | "DNSKEY" | "DS" | "SMIMEA" | "SSHFP" | "TLSA" => {
+                        rest.concat()
+                    }
+                    _ => rest.join(separator),
+                };
+                (*field, rest.into())
+            } else {
+                (
+                    *field,
+                    tokens.next().expect("tokens were counted").text.into(),
+                )
+            };
+            entries.push((key.into(), v));
+        }
+        Ok(value::Value::Map(entries))
+    }
+
+    /// Makes a name absolute with the origin, unless it ends with a dot already.
+    fn absolute(&self, name: &str) -> String {
+        let origin = self.origin.as_deref();
+        if name == "@" {
+            return origin.unwrap_or("@").to_owned();
+        }
+        if name.ends_with('.') && !name.ends_with("\\.") {
+            return name.to_owned();
+        }
+        match origin {
+            Some(".") => format!("{}.", name),
+            Some(origin) => format!("{}.{}", name, origin),
+            None => name.to_owned(),
+        }
+    }
+
+    fn error(&self, msg: &str) -> error::Error {
+        error::Error::Format {
+            msg: format!("zone file line {}: {}", self.line, msg),
+        }
+    }
+}
+
+fn is_class(s: &str) -> bool {
+    CLASSES.contains(&s) || (s.starts_with("CLASS") && s[5..].parse::<u16>().is_ok())
+}
+
+/// Parses a TTL in seconds, or with units like `1h30m`.
+fn parse_ttl(s: &str) -> Option<u64> {
+    if let Ok(seconds) = s.parse() {
+        return Some(seconds);
+    }
+    let mut total = 0u64;
+    let mut number = None;
+    for c in s.chars() {
+        match c.to_ascii_lowercase() {
+            c @ '0'..='9' => {
+                number = Some(number.unwrap_or(0u64).checked_mul(10)? + u64::from(c as u8 - b'0'))
+            }
+            unit => {
+                let scale = match unit {
+                    's' => 1,
+                    'm' => 60,
+                    'h' => 3600,
+                    'd' => 86_400,
+                    'w' => 604_800,
+                    _ => return None,
+                };
+                total = total.checked_add(number.take()?.checked_mul(scale)?)?;
+            }
+        }
+    }
+    match number {
+        None if !s.is_empty() => Some(total),
+        _ => None,
+    }
+}
+
+/// Resolves the escape after a backslash in a quoted string, which is a character or a decimal
+/// byte like `\032`.
+fn unescape<I>(chars: &mut std::iter::Peekable<I>) -> String
+where
+    I: Iterator<Item = char>,
+{
+    let mut digits = String::new();
+    while digits.len() < 3 {
+        match chars.peek() {
+            Some(c) if c.is_ascii_digit() => digits.push(*c),
+            _ => break,
+        }
+        chars.next();
+    }
+    match digits.parse::<u8>() {
+        Ok(byte) if digits.len() == 3 => char::from(byte).to_string(),
+        _ if !digits.is_empty() => digits,
+        _ => chars.next().map(String::from).unwrap_or_default(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::value::Source as _;
+
+    fn read(input: &[u8]) -> error::Result<Vec<value::Value>> {
+        let mut reader = source(input);
+        let mut records = Vec::new();
+        while let Some(record) = reader.read()? {
+            records.push(record);
+        }
+        Ok(records)
+    }
+
+    #[test]
+    fn test_records() {
+        let records = |input: &str| -> Vec<String> {
+            read(input.as_bytes())
+                .unwrap()
+                .iter()
+                .map(ToString::to_string)
+                .collect()
+        };
+        let zone = r#"$ORIGIN example.com.
+$TTL 1h
+@   IN  SOA ns1 hostmaster (
+            2024010101 ; serial
+            1d 2h 4w 1h )
+    IN  NS  ns1
+        NS  ns2.example.net.
+    300 MX  10 mail
+ns1     A   192.0.2.1
+mail 60 in AAAA 2001:db8::1
+www CNAME @
+_sip._tcp SRV 0 5 5060 sip
+@ TXT "v=spf1 -all" ; comment
+    TXT "a \"quoted\"" " string"
+@ CAA 0 issue "letsencrypt.org"
+@ TYPE65534 \# 4 0a000001
+"#;
+        assert_eq!(
+            records(zone),
+            vec![
+                r#"{"name": "example.com.", "ttl": 3600, "class": "IN", "type": "SOA", "rdata": {"mname": "ns1.example.com.", "rname": "hostmaster.example.com.", "serial": 2024010101, "refresh": 86400, "retry": 7200, "expire": 2419200, "minimum": 3600}}"#,
+                r#"{"name": "example.com.", "ttl": 3600, "class": "IN", "type": "NS", "rdata": {"host": "ns1.example.com."}}"#,
+                r#"{"name": "example.com.", "ttl": 3600, "class": "IN", "type": "NS", "rdata": {"host": "ns2.example.net."}}"#,
+                r#"{"name": "example.com.", "ttl": 300, "class": "IN", "type": "MX", "rdata": {"preference": 10, "exchange": "mail.example.com."}}"#,
+                r#"{"name": "ns1.example.com.", "ttl": 300, "class": "IN", "type": "A", "rdata": {"address": "192.0.2.1"}}"#,
+                r#"{"name": "mail.example.com.", "ttl": 60, "class": "IN", "type": "AAAA", "rdata": {"address": "2001:db8::1"}}"#,
+                r#"{"name": "www.example.com.", "ttl": 60, "class": "IN", "type": "CNAME", "rdata": {"target": "example.com."}}"#,
+                r#"{"name": "_sip._tcp.example.com.", "ttl": 60, "class": "IN", "type": "SRV", "rdata": {"priority": 0, "weight": 5, "port": 5060, "target": "sip.example.com."}}"#,
+                r#"{"name": "example.com.", "ttl": 60, "class": "IN", "type": "TXT", "rdata": {"text": "v=spf1 -all"}}"#,
+                r#"{"name": "example.com.", "ttl": 60, "class": "IN", "type": "TXT", "rdata": {"text": "a \"quoted\" string"}}"#,
+                r#"{"name": "example.com.", "ttl": 60, "class": "IN", "type": "CAA", "rdata": {"flags": 0, "tag": "issue", "value": "letsencrypt.org"}}"#,
+                r#"{"name": "example.com.", "ttl": 60, "class": "IN", "type": "TYPE65534", "rdata": {"data": "\\# 4 0a000001"}}"#,
+            ]
+        );
+
+        assert!(read(b"www IN BOGUS data").is_err());
+        assert!(read(b"@ MX mail").is_err());
+        assert!(read(b"@ SOA ns1 ( 1").is_err());
+        assert!(read(b"$INCLUDE other.zone").is_err());
+        assert!(read(b"hello world").is_err());
+
+        // Quoted types, addresses that aren't IP addresses, and units outside of SOA timers
+        for input in &[
+            r#"{"a": 1}"#,
+            r#"www "A" 192.0.2.1"#,
+            "www A 192.0.2",
+            "www A 2001:db8::1",
+            "www AAAA 192.0.2.1",
+            "www A 192.0.2.1 192.0.2.2",
+            "@ MX 1w mail",
+            "@ MX 65536 mail",
+            "@ SOA ns1 hostmaster 1d 1 1 1 1",
+        ] {
+            assert!(read(input.as_bytes()).is_err(), "{}", input);
+        }
+    }
+}